@@ -1,11 +1,16 @@
+// pyo3 0.22's `#[pymethods]` expansion trips this lint on newer toolchains
+#![allow(clippy::useless_conversion)]
+
 use std::{fs::File, path::PathBuf};
 
 use codecov_rs::{parsers, report};
-use pyo3::prelude::*;
+use pyo3::{exceptions::PyValueError, prelude::*};
 
-use crate::error::PyCodecovError;
+use crate::error::{PyCodecovError, RsCodecovError};
 
 mod error;
+#[cfg(feature = "totals-buffer")]
+mod totals_buffer;
 
 #[pyclass]
 pub struct SqliteReportBuilder(report::SqliteReportBuilder);
@@ -34,8 +39,52 @@ impl SqliteReportBuilder {
     }
 }
 
+/// Collects the totals of each `(commitish, report path)` pair, in order, and
+/// returns them as a JSON-encoded `TotalsTimeSeries`.
+#[pyfunction]
+pub fn totals_time_series(reports: Vec<(String, String)>) -> PyResult<String> {
+    let series = report::timeseries::totals_time_series(reports).map_err(PyCodecovError::from)?;
+    Ok(serde_json::to_string(&series)
+        .map_err(RsCodecovError::from)
+        .map_err(PyCodecovError::from)?)
+}
+
+/// Merges the SQLite reports at `paths` into a new report at `out_path`, using
+/// up to `threads` threads. `policy` is the snake_case name of a
+/// `MergePolicy`. The GIL is released while merging.
+#[pyfunction]
+#[pyo3(signature = (paths, out_path, threads = 1, policy = "keep_both"))]
+pub fn merge_reports(
+    py: Python,
+    paths: Vec<PathBuf>,
+    out_path: PathBuf,
+    threads: usize,
+    policy: &str,
+) -> PyResult<()> {
+    let policy = match policy {
+        "keep_both" => report::MergePolicy::KeepBoth,
+        "sum_hits" => report::MergePolicy::SumHits,
+        "max_hits" => report::MergePolicy::MaxHits,
+        "prefer_newest" => report::MergePolicy::PreferNewest,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "unknown merge policy '{policy}'"
+            )))
+        }
+    };
+    py.allow_threads(|| {
+        report::SqliteReport::merge_many(&paths, out_path, threads, policy).map(drop)
+    })
+    .map_err(PyCodecovError::from)?;
+    Ok(())
+}
+
 #[pymodule]
 fn _bindings(_py: Python, m: &Bound<PyModule>) -> PyResult<()> {
     m.add_class::<SqliteReportBuilder>()?;
+    #[cfg(feature = "totals-buffer")]
+    m.add_class::<totals_buffer::FileTotalsBuffer>()?;
+    m.add_function(wrap_pyfunction!(totals_time_series, m)?)?;
+    m.add_function(wrap_pyfunction!(merge_reports, m)?)?;
     Ok(())
 }
@@ -0,0 +1,142 @@
+//! Per-file totals laid out as a 2D array of `u64`s that Python can read
+//! through the buffer protocol, e.g. with `numpy.asarray()` or
+//! `pyarrow.py_buffer()`, instead of converting a dict for every file.
+
+use std::{
+    ffi::{c_int, c_void, CStr},
+    mem::size_of,
+    ptr,
+};
+
+use codecov_rs::report::{self, models, Report};
+use pyo3::{exceptions::PyBufferError, ffi, prelude::*};
+
+use crate::error::PyCodecovError;
+
+/// The column for each field of `CoverageTotals`, in order.
+const COLUMNS: [&str; 9] = [
+    "hit_lines",
+    "total_lines",
+    "hit_branches",
+    "total_branches",
+    "total_branch_roots",
+    "hit_methods",
+    "total_methods",
+    "hit_complexity_paths",
+    "total_complexity",
+];
+
+/// The `struct` module's code for a native-endian `unsigned long long`.
+const FORMAT: &CStr = c"Q";
+
+/// One row per file, in the order of `paths()`, and one column per name in
+/// `columns()`. The buffer is read-only and the data is never modified, so
+/// views of it stay valid for as long as they're held.
+#[pyclass(frozen)]
+pub struct FileTotalsBuffer {
+    paths: Vec<String>,
+    data: Vec<u64>,
+    shape: [ffi::Py_ssize_t; 2],
+    strides: [ffi::Py_ssize_t; 2],
+}
+
+impl FileTotalsBuffer {
+    fn new(file_totals: Vec<(models::SourceFile, models::CoverageTotals)>) -> FileTotalsBuffer {
+        let mut paths = Vec::with_capacity(file_totals.len());
+        let mut data = Vec::with_capacity(file_totals.len() * COLUMNS.len());
+        for (file, totals) in file_totals {
+            paths.push(file.path);
+            data.extend([
+                totals.hit_lines,
+                totals.total_lines,
+                totals.hit_branches,
+                totals.total_branches,
+                totals.total_branch_roots,
+                totals.hit_methods,
+                totals.total_methods,
+                totals.hit_complexity_paths,
+                totals.total_complexity,
+            ]);
+        }
+
+        let item_size = size_of::<u64>() as ffi::Py_ssize_t;
+        let columns = COLUMNS.len() as ffi::Py_ssize_t;
+        FileTotalsBuffer {
+            shape: [paths.len() as ffi::Py_ssize_t, columns],
+            strides: [columns * item_size, item_size],
+            paths,
+            data,
+        }
+    }
+}
+
+#[pymethods]
+impl FileTotalsBuffer {
+    /// Computes the totals of every file in the SQLite report at
+    /// `report_filepath`.
+    #[staticmethod]
+    pub fn from_sqlite(report_filepath: &str) -> PyResult<FileTotalsBuffer> {
+        let report = report::SqliteReport::open_readonly(report_filepath.into())
+            .map_err(PyCodecovError::from)?;
+        let file_totals = report.list_file_totals().map_err(PyCodecovError::from)?;
+        Ok(FileTotalsBuffer::new(file_totals))
+    }
+
+    /// The name of each column.
+    #[staticmethod]
+    pub fn columns() -> Vec<&'static str> {
+        COLUMNS.to_vec()
+    }
+
+    /// The path of the file in each row.
+    pub fn paths(&self) -> Vec<String> {
+        self.paths.clone()
+    }
+
+    pub fn __len__(&self) -> usize {
+        self.paths.len()
+    }
+
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        if view.is_null() {
+            return Err(PyBufferError::new_err("View is null"));
+        }
+        if flags & ffi::PyBUF_WRITABLE == ffi::PyBUF_WRITABLE {
+            return Err(PyBufferError::new_err("Object is not writable"));
+        }
+
+        let this = slf.get();
+        let view = &mut *view;
+        view.buf = this.data.as_ptr() as *mut c_void;
+        view.len = (this.data.len() * size_of::<u64>()) as ffi::Py_ssize_t;
+        view.readonly = 1;
+        view.itemsize = size_of::<u64>() as ffi::Py_ssize_t;
+        view.format = if flags & ffi::PyBUF_FORMAT == ffi::PyBUF_FORMAT {
+            FORMAT.as_ptr() as *mut _
+        } else {
+            ptr::null_mut()
+        };
+        // Consumers that don't ask for a shape see a flat run of bytes
+        if flags & ffi::PyBUF_ND == ffi::PyBUF_ND {
+            view.ndim = 2;
+            view.shape = this.shape.as_ptr() as *mut _;
+        } else {
+            view.ndim = 1;
+            view.shape = ptr::null_mut();
+        }
+        view.strides = if flags & ffi::PyBUF_STRIDES == ffi::PyBUF_STRIDES {
+            this.strides.as_ptr() as *mut _
+        } else {
+            ptr::null_mut()
+        };
+        view.suboffsets = ptr::null_mut();
+        view.internal = ptr::null_mut();
+        // The view holds a reference to us, which keeps `data` alive
+        view.obj = slf.into_any().into_ptr();
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use codecov_rs::report::{models, sqlite::BulkInsertMode, ReportBuilder, SqliteReportBuilder};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tempfile::TempDir;
+
+criterion_group!(benches, multi_insert);
+criterion_main!(benches);
+
+const FILES: i64 = 100;
+const LINES_PER_FILE: i64 = 1000;
+
+/// Inserts `FILES * LINES_PER_FILE` samples in one `multi_insert` call per
+/// file, binding rows with `mode`.
+fn insert_samples(out_dir: &TempDir, mode: BulkInsertMode) {
+    let mut builder = SqliteReportBuilder::open(out_dir.path().join("report.sqlite")).unwrap();
+    builder.set_bulk_insert_mode(mode);
+    builder.begin().unwrap();
+    let upload = builder.insert_raw_upload(Default::default()).unwrap();
+    for file in 0..FILES {
+        let file = builder.insert_file(&format!("src/file_{file}.rs")).unwrap();
+        let mut samples: Vec<_> = (0..LINES_PER_FILE)
+            .map(|line_no| models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no,
+                hits: Some(line_no % 3),
+                ..Default::default()
+            })
+            .collect();
+        builder
+            .multi_insert_coverage_sample(samples.iter_mut().collect())
+            .unwrap();
+    }
+    builder.commit().unwrap();
+}
+
+fn multi_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("multi_insert_coverage_sample");
+    group.sample_size(10);
+
+    for mode in [BulkInsertMode::Placeholders, BulkInsertMode::JsonEach] {
+        group.bench_function(format!("{mode:?}"), |b| {
+            b.iter_batched(
+                || TempDir::new().unwrap(),
+                |out_dir| insert_samples(&out_dir, mode),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use codecov_rs::{
+    report::{MergePolicy, Report, SqliteReport},
+    test_utils::generator::{generate_sqlite_report, GeneratorConfig},
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tempfile::TempDir;
+
+criterion_group!(benches, merge_shards);
+criterion_main!(benches);
+
+const SHARDS: usize = 64;
+
+/// Generates `SHARDS` small reports, like a test suite split across CI jobs.
+fn generate_shards(temp_dir: &TempDir) -> Vec<PathBuf> {
+    (0..SHARDS)
+        .map(|i| {
+            let path = temp_dir.path().join(format!("shard-{i}.sqlite"));
+            let config = GeneratorConfig {
+                files: 20,
+                lines_per_file: 100,
+                sessions: 1,
+                labels: 10,
+                seed: i as u64,
+                ..Default::default()
+            };
+            generate_sqlite_report(&config, path.clone()).unwrap();
+            path
+        })
+        .collect()
+}
+
+fn merge_shards(c: &mut Criterion) {
+    let shards_dir = TempDir::new().unwrap();
+    let shards = generate_shards(&shards_dir);
+
+    let mut group = c.benchmark_group("merge_shards");
+    group.sample_size(10);
+
+    group.bench_function("sequential", |b| {
+        b.iter_batched(
+            || TempDir::new().unwrap(),
+            |out_dir| {
+                let mut report = SqliteReport::open(out_dir.path().join("merged.sqlite")).unwrap();
+                for shard in &shards {
+                    report
+                        .merge(
+                            &SqliteReport::open(shard.clone()).unwrap(),
+                            MergePolicy::SumHits,
+                        )
+                        .unwrap();
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    for threads in [1, 4, 8] {
+        group.bench_function(format!("merge_many_{threads}_threads"), |b| {
+            b.iter_batched(
+                || TempDir::new().unwrap(),
+                |out_dir| {
+                    SqliteReport::merge_many(
+                        &shards,
+                        out_dir.path().join("merged.sqlite"),
+                        threads,
+                        MergePolicy::SumHits,
+                    )
+                    .unwrap();
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+
+    group.finish();
+}
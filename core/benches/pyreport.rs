@@ -1,10 +1,15 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, num::NonZeroUsize};
 
 use codecov_rs::{
     parsers::pyreport::{chunks, report_json},
-    test_utils::test_report::{TestReport, TestReportBuilder},
+    report::pyreport::{PyreportOptions, ToPyreport},
+    test_utils::{
+        generator::{self, GeneratorConfig},
+        test_report::{TestReport, TestReportBuilder},
+    },
 };
 use criterion::{criterion_group, criterion_main, Criterion};
+use tempfile::TempDir;
 use test_utils::fixtures::{read_fixture, FixtureFormat::Pyreport, FixtureSize::Large};
 use winnow::Parser as _;
 
@@ -14,6 +19,9 @@ criterion_group!(
     complex_report_json,
     simple_chunks,
     complex_chunks,
+    generated_chunks,
+    generated_legacy_chunks,
+    sql_to_pyreport,
 );
 criterion_main!(benches);
 
@@ -96,13 +104,57 @@ fn complex_chunks(c: &mut Criterion) {
         "worker-c71ddfd4cb1753c7a540e5248c2beaa079fc3341-report_json.json",
     )
     .unwrap();
-    let report_json::ParsedReportJson { files, sessions } = parse_report_json(&report);
+    let report_json::ParsedReportJson {
+        files, sessions, ..
+    } = parse_report_json(&report);
 
     c.bench_function("complex_chunks", |b| {
         b.iter(|| parse_chunks_file(chunks, files.clone(), sessions.clone()))
     });
 }
 
+fn generated_chunks(c: &mut Criterion) {
+    let config = GeneratorConfig {
+        files: 100,
+        lines_per_file: 500,
+        sessions: 5,
+        labels: 50,
+        ..Default::default()
+    };
+    bench_generated_chunks(c, "generated_chunks", &config);
+}
+
+// Older chunks files repeat full label names in every datapoint and list missed
+// branches, which is where the parser used to copy the most strings
+fn generated_legacy_chunks(c: &mut Criterion) {
+    let config = GeneratorConfig {
+        files: 100,
+        lines_per_file: 500,
+        sessions: 5,
+        labels: 50,
+        inline_labels: true,
+        missing_branches: true,
+        ..Default::default()
+    };
+    bench_generated_chunks(c, "generated_legacy_chunks", &config);
+}
+
+fn bench_generated_chunks(c: &mut Criterion, name: &str, config: &GeneratorConfig) {
+    let mut report = Vec::new();
+    generator::write_report_json(config, &mut report).unwrap();
+    let mut chunks = Vec::new();
+    generator::write_chunks(config, &mut chunks).unwrap();
+    let chunks = std::str::from_utf8(&chunks).unwrap();
+
+    let report_json::ParsedReportJson {
+        files, sessions, ..
+    } = parse_report_json(&report);
+
+    c.bench_function(name, |b| {
+        b.iter(|| parse_chunks_file(chunks, files.clone(), sessions.clone()))
+    });
+}
+
 fn parse_chunks_file(input: &str, files: HashMap<usize, i64>, sessions: HashMap<usize, i64>) {
     let report_builder = TestReportBuilder::default();
 
@@ -116,3 +168,36 @@ fn parse_chunks_file(input: &str, files: HashMap<usize, i64>, sessions: HashMap<
         .parse_next(&mut chunks_stream)
         .unwrap();
 }
+
+// One thread is the old single-threaded path
+fn sql_to_pyreport(c: &mut Criterion) {
+    let config = GeneratorConfig {
+        files: 2000,
+        lines_per_file: 100,
+        sessions: 5,
+        labels: 50,
+        ..Default::default()
+    };
+    let temp_dir = TempDir::new().unwrap();
+    let report =
+        generator::generate_sqlite_report(&config, temp_dir.path().join("db.sqlite")).unwrap();
+
+    let mut group = c.benchmark_group("sql_to_pyreport");
+    group.sample_size(10);
+    for threads in [1, 2, 4] {
+        let options = PyreportOptions {
+            threads: NonZeroUsize::new(threads).unwrap(),
+            ..Default::default()
+        };
+        group.bench_function(format!("{threads}_threads"), |b| {
+            b.iter(|| {
+                let mut report_json = tempfile::tempfile().unwrap();
+                let mut chunks = tempfile::tempfile().unwrap();
+                report
+                    .to_pyreport_with_options(&mut report_json, &mut chunks, &options)
+                    .unwrap();
+            })
+        });
+    }
+    group.finish();
+}
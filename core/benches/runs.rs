@@ -0,0 +1,100 @@
+use std::fs;
+
+use codecov_rs::report::{
+    models, sqlite::CompactOptions, Report, ReportBuilder, SqliteReport, SqliteReportBuilder,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tempfile::TempDir;
+
+criterion_group!(benches, pack, read);
+criterion_main!(benches);
+
+const FILES: i64 = 100;
+const LINES_PER_FILE: i64 = 1000;
+
+/// How many consecutive lines share a hit count.
+const RUN_LENGTH: i64 = 20;
+
+/// Builds a report of `FILES * LINES_PER_FILE` line samples whose hit count
+/// changes every `RUN_LENGTH` lines, then compacts it, packing runs if
+/// `packed` is set.
+fn build_report(out_dir: &TempDir, packed: bool) -> SqliteReport {
+    let mut builder = SqliteReportBuilder::open(out_dir.path().join("report.sqlite")).unwrap();
+    builder.begin().unwrap();
+    let upload = builder.insert_raw_upload(Default::default()).unwrap();
+    for file in 0..FILES {
+        let file = builder.insert_file(&format!("src/file_{file}.rs")).unwrap();
+        let mut samples: Vec<_> = (0..LINES_PER_FILE)
+            .map(|line_no| models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no,
+                hits: Some(line_no / RUN_LENGTH % 3),
+                ..Default::default()
+            })
+            .collect();
+        builder
+            .multi_insert_coverage_sample(samples.iter_mut().collect())
+            .unwrap();
+    }
+    builder.commit().unwrap();
+
+    let mut report = builder.build().unwrap();
+    report
+        .compact(&CompactOptions {
+            pack_coverage_runs: packed,
+            ..Default::default()
+        })
+        .unwrap();
+    report
+}
+
+/// Times packing a freshly built report, and prints the database size with
+/// and without runs.
+fn pack(c: &mut Criterion) {
+    for packed in [false, true] {
+        let out_dir = TempDir::new().unwrap();
+        build_report(&out_dir, packed);
+        let size = fs::metadata(out_dir.path().join("report.sqlite"))
+            .unwrap()
+            .len();
+        println!("database size (packed: {packed}): {} KiB", size / 1024);
+    }
+
+    let mut group = c.benchmark_group("pack_coverage_runs");
+    group.sample_size(10);
+    group.bench_function("pack", |b| {
+        b.iter_batched(
+            || {
+                let out_dir = TempDir::new().unwrap();
+                let report = build_report(&out_dir, false);
+                (out_dir, report)
+            },
+            |(_out_dir, mut report)| report.pack_coverage_runs().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+/// Compares reads through the view that expands runs with reads of plain
+/// samples.
+fn read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_coverage_runs");
+    group.sample_size(10);
+
+    for packed in [false, true] {
+        let out_dir = TempDir::new().unwrap();
+        let report = build_report(&out_dir, packed);
+        let file = report.list_files().unwrap().swap_remove(0);
+
+        let name = if packed { "packed" } else { "unpacked" };
+        group.bench_function(format!("totals/{name}"), |b| {
+            b.iter(|| report.totals().unwrap())
+        });
+        group.bench_function(format!("list_samples_for_file/{name}"), |b| {
+            b.iter(|| report.list_samples_for_file(&file).unwrap())
+        });
+    }
+    group.finish();
+}
@@ -1,13 +1,15 @@
 use std::{env, fs::File, path::PathBuf};
 
-use codecov_rs::{error::Result, parsers::pyreport::parse_pyreport, report::SqliteReportBuilder};
+use codecov_rs::prelude::*;
 
 fn usage_error() -> ! {
     println!("Usage:");
-    println!("  cargo run --example parse_pyreport -- [REPORT_JSON_PATH] [CHUNKS_PATH] [OUT_PATH]");
+    println!("  cargo run --example parse_pyreport -- [REPORT_JSON_PATH] [CHUNKS_PATH] [OUT_PATH] [FILE_PATH...]");
+    println!();
+    println!("Prints each upload, and every sample in each FILE_PATH given.");
     println!();
     println!("Example:");
-    println!("  cargo run --example parse_pyreport -- tests/common/sample_data/codecov-rs-reports-json-d2a9ba1.txt tests/common/sample_data/codecov-rs-chunks-d2a9ba1.txt d2a9ba1.sqlite");
+    println!("  cargo run --example parse_pyreport -- ../test_utils/fixtures/pyreport/codecov-rs-reports-json-d2a9ba1.txt ../test_utils/fixtures/pyreport/codecov-rs-chunks-d2a9ba1.txt d2a9ba1.sqlite src/report.rs");
 
     std::process::exit(1);
 }
@@ -15,7 +17,7 @@ fn usage_error() -> ! {
 pub fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 4 {
+    if args.len() < 4 {
         usage_error();
     }
 
@@ -24,7 +26,31 @@ pub fn main() -> Result<()> {
     let out_path = PathBuf::from(&args[3]);
 
     let mut report_builder = SqliteReportBuilder::open(out_path)?;
-    parse_pyreport(&report_json_file, &chunks_file, &mut report_builder)?;
+    let result = parse_pyreport(&report_json_file, &chunks_file, &mut report_builder)?;
+    println!(
+        "Parsed {} files and {} samples in {:?}",
+        result.files_touched, result.samples_inserted, result.duration
+    );
+    for warning in result.warnings {
+        println!("Warning: {warning}");
+    }
+
+    let report = report_builder.build()?;
+    for (upload, totals) in report.list_upload_totals()? {
+        println!(
+            "{upload}: {}/{} lines hit",
+            totals.hit_lines, totals.total_lines
+        );
+    }
+    for path in &args[4..] {
+        let Some(file) = report.list_files()?.into_iter().find(|f| &f.path == path) else {
+            println!("No file {path:?} in the report");
+            continue;
+        };
+        for sample in report.list_samples_for_file(&file)? {
+            println!("{}", sample.in_file(&file));
+        }
+    }
 
     Ok(())
 }
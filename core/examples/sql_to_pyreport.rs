@@ -1,9 +1,6 @@
 use std::{env, fs::File};
 
-use codecov_rs::{
-    error::Result,
-    report::{pyreport::ToPyreport, SqliteReport},
-};
+use codecov_rs::prelude::*;
 
 fn usage_error() -> ! {
     println!("Usage:");
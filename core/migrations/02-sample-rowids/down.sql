@@ -0,0 +1,66 @@
+CREATE TABLE coverage_sample_old (
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    local_sample_id INTEGER NOT NULL,
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+    line_no INTEGER NOT NULL,
+    coverage_type VARCHAR NOT NULL,
+    hits INTEGER,
+    hit_branches INTEGER,
+    total_branches INTEGER,
+    PRIMARY KEY (raw_upload_id, local_sample_id)
+);
+
+CREATE TABLE branches_data_old (
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    local_sample_id INTEGER NOT NULL,
+    local_branch_id INTEGER NOT NULL,
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+    hits INTEGER NOT NULL,
+    branch_format VARCHAR NOT NULL,
+    branch VARCHAR NOT NULL,
+    FOREIGN KEY (raw_upload_id, local_sample_id) REFERENCES coverage_sample(raw_upload_id, local_sample_id),
+    PRIMARY KEY (raw_upload_id, local_branch_id)
+);
+
+CREATE TABLE method_data_old (
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    local_sample_id INTEGER NOT NULL,
+    local_method_id INTEGER NOT NULL,
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+    line_no INTEGER,
+    hit_branches INTEGER,
+    total_branches INTEGER,
+    hit_complexity_paths INTEGER,
+    total_complexity INTEGER,
+    FOREIGN KEY (raw_upload_id, local_sample_id) REFERENCES coverage_sample(raw_upload_id, local_sample_id),
+    PRIMARY KEY (raw_upload_id, local_method_id)
+);
+
+CREATE TABLE span_data_old (
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    local_sample_id INTEGER,
+    local_span_id INTEGER NOT NULL,
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+    hits INTEGER NOT NULL,
+    start_line INTEGER,
+    start_col INTEGER,
+    end_line INTEGER,
+    end_col INTEGER,
+    FOREIGN KEY (raw_upload_id, local_sample_id) REFERENCES coverage_sample(raw_upload_id, local_sample_id),
+    PRIMARY KEY (raw_upload_id, local_span_id)
+);
+
+INSERT INTO coverage_sample_old SELECT raw_upload_id, local_sample_id, source_file_id, line_no, coverage_type, hits, hit_branches, total_branches FROM coverage_sample;
+INSERT INTO branches_data_old SELECT raw_upload_id, local_sample_id, local_branch_id, source_file_id, hits, branch_format, branch FROM branches_data;
+INSERT INTO method_data_old SELECT raw_upload_id, local_sample_id, local_method_id, source_file_id, line_no, hit_branches, total_branches, hit_complexity_paths, total_complexity FROM method_data;
+INSERT INTO span_data_old SELECT raw_upload_id, local_sample_id, local_span_id, source_file_id, hits, start_line, start_col, end_line, end_col FROM span_data;
+
+DROP TABLE branches_data;
+DROP TABLE method_data;
+DROP TABLE span_data;
+DROP TABLE coverage_sample;
+
+ALTER TABLE coverage_sample_old RENAME TO coverage_sample;
+ALTER TABLE branches_data_old RENAME TO branches_data;
+ALTER TABLE method_data_old RENAME TO method_data;
+ALTER TABLE span_data_old RENAME TO span_data;
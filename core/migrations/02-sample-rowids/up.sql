@@ -0,0 +1,112 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Sample tables previously used `(raw_upload_id, local_*_id)` as a joint
+-- primary key. SQLite stores such tables as a rowid b-tree plus a separate
+-- index for the joint key, so every insert paid for two b-tree writes anyway.
+-- Giving each table an explicit `INTEGER PRIMARY KEY` makes the rowid ours to
+-- use in joins while the joint key keeps its uniqueness guarantee via a
+-- `UNIQUE` index.
+--
+-- SQLite can't alter a table's primary key in place, so each table is rebuilt.
+
+CREATE TABLE coverage_sample_new (
+    id INTEGER PRIMARY KEY,
+
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+
+    -- This should be an application-managed auto-incremented integer.
+    local_sample_id INTEGER NOT NULL,
+
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+    line_no INTEGER NOT NULL,
+
+    coverage_type VARCHAR NOT NULL,
+    hits INTEGER,
+    hit_branches INTEGER,
+    total_branches INTEGER
+);
+
+CREATE TABLE branches_data_new (
+    id INTEGER PRIMARY KEY,
+
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    local_sample_id INTEGER NOT NULL,
+
+    -- This should be an application-managed auto-incremented integer.
+    local_branch_id INTEGER NOT NULL,
+
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+
+    hits INTEGER NOT NULL,
+    branch_format VARCHAR NOT NULL,
+    branch VARCHAR NOT NULL,
+
+    FOREIGN KEY (raw_upload_id, local_sample_id) REFERENCES coverage_sample(raw_upload_id, local_sample_id)
+);
+
+CREATE TABLE method_data_new (
+    id INTEGER PRIMARY KEY,
+
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    local_sample_id INTEGER NOT NULL,
+
+    -- This should be an application-managed auto-incremented integer.
+    local_method_id INTEGER NOT NULL,
+
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+    line_no INTEGER,
+
+    hit_branches INTEGER,
+    total_branches INTEGER,
+    hit_complexity_paths INTEGER,
+    total_complexity INTEGER,
+
+    FOREIGN KEY (raw_upload_id, local_sample_id) REFERENCES coverage_sample(raw_upload_id, local_sample_id)
+);
+
+CREATE TABLE span_data_new (
+    id INTEGER PRIMARY KEY,
+
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    local_sample_id INTEGER,
+
+    -- This should be an application-managed auto-incremented integer.
+    local_span_id INTEGER NOT NULL,
+
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+
+    hits INTEGER NOT NULL,
+    start_line INTEGER,
+    start_col INTEGER,
+    end_line INTEGER,
+    end_col INTEGER,
+
+    FOREIGN KEY (raw_upload_id, local_sample_id) REFERENCES coverage_sample(raw_upload_id, local_sample_id)
+);
+
+INSERT INTO coverage_sample_new (raw_upload_id, local_sample_id, source_file_id, line_no, coverage_type, hits, hit_branches, total_branches)
+SELECT raw_upload_id, local_sample_id, source_file_id, line_no, coverage_type, hits, hit_branches, total_branches FROM coverage_sample;
+
+INSERT INTO branches_data_new (raw_upload_id, local_sample_id, local_branch_id, source_file_id, hits, branch_format, branch)
+SELECT raw_upload_id, local_sample_id, local_branch_id, source_file_id, hits, branch_format, branch FROM branches_data;
+
+INSERT INTO method_data_new (raw_upload_id, local_sample_id, local_method_id, source_file_id, line_no, hit_branches, total_branches, hit_complexity_paths, total_complexity)
+SELECT raw_upload_id, local_sample_id, local_method_id, source_file_id, line_no, hit_branches, total_branches, hit_complexity_paths, total_complexity FROM method_data;
+
+INSERT INTO span_data_new (raw_upload_id, local_sample_id, local_span_id, source_file_id, hits, start_line, start_col, end_line, end_col)
+SELECT raw_upload_id, local_sample_id, local_span_id, source_file_id, hits, start_line, start_col, end_line, end_col FROM span_data;
+
+DROP TABLE branches_data;
+DROP TABLE method_data;
+DROP TABLE span_data;
+DROP TABLE coverage_sample;
+
+ALTER TABLE coverage_sample_new RENAME TO coverage_sample;
+ALTER TABLE branches_data_new RENAME TO branches_data;
+ALTER TABLE method_data_new RENAME TO method_data;
+ALTER TABLE span_data_new RENAME TO span_data;
+
+CREATE UNIQUE INDEX coverage_sample_local_id ON coverage_sample (raw_upload_id, local_sample_id);
+CREATE UNIQUE INDEX branches_data_local_id ON branches_data (raw_upload_id, local_branch_id);
+CREATE UNIQUE INDEX method_data_local_id ON method_data (raw_upload_id, local_method_id);
+CREATE UNIQUE INDEX span_data_local_id ON span_data (raw_upload_id, local_span_id);
@@ -0,0 +1 @@
+DROP INDEX context_assoc_sample;
@@ -0,0 +1,6 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- `context_assoc`'s primary key leads with `context_id`, which serves "which
+-- samples does this context cover" lookups. This index serves the reverse,
+-- "which contexts cover this sample", and joins from the sample tables.
+CREATE INDEX context_assoc_sample ON context_assoc (raw_upload_id, local_sample_id);
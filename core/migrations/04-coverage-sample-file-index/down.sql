@@ -0,0 +1 @@
+DROP INDEX coverage_sample_source_file;
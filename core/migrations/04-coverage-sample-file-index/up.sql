@@ -0,0 +1,5 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Lets queries that only want some files' samples, like serializing a range
+-- of chunks, avoid scanning the whole table.
+CREATE INDEX coverage_sample_source_file ON coverage_sample (source_file_id);
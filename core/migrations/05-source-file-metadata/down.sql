@@ -0,0 +1,3 @@
+ALTER TABLE source_file DROP COLUMN line_count;
+ALTER TABLE source_file DROP COLUMN content_hash;
+ALTER TABLE source_file DROP COLUMN language;
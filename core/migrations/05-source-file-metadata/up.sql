@@ -0,0 +1,5 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+ALTER TABLE source_file ADD COLUMN language VARCHAR;
+ALTER TABLE source_file ADD COLUMN content_hash VARCHAR;
+ALTER TABLE source_file ADD COLUMN line_count INTEGER;
@@ -0,0 +1,2 @@
+ALTER TABLE method_data DROP COLUMN signature;
+ALTER TABLE method_data DROP COLUMN name;
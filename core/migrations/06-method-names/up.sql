@@ -0,0 +1,4 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+ALTER TABLE method_data ADD COLUMN name VARCHAR;
+ALTER TABLE method_data ADD COLUMN signature VARCHAR;
@@ -0,0 +1 @@
+DROP INDEX raw_upload_url;
@@ -0,0 +1,5 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Lets the worker find the uploads for an archive storage entry when it
+-- reconciles the two during reprocessing.
+CREATE INDEX raw_upload_url ON raw_upload (raw_upload_url);
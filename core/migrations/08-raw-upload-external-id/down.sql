@@ -0,0 +1,2 @@
+DROP INDEX raw_upload_external_id;
+ALTER TABLE raw_upload DROP COLUMN external_id;
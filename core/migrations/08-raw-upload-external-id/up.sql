@@ -0,0 +1,7 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+ALTER TABLE raw_upload ADD COLUMN external_id VARCHAR;
+
+-- SQLite treats `NULL`s as distinct, so only uploads that have an
+-- `external_id` are constrained.
+CREATE UNIQUE INDEX raw_upload_external_id ON raw_upload (external_id);
@@ -0,0 +1,2 @@
+DROP INDEX source_file_chunk_index;
+ALTER TABLE source_file DROP COLUMN chunk_index;
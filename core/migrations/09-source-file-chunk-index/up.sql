@@ -0,0 +1,5 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+ALTER TABLE source_file ADD COLUMN chunk_index INTEGER;
+
+CREATE INDEX source_file_chunk_index ON source_file (chunk_index);
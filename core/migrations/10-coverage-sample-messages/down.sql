@@ -0,0 +1 @@
+ALTER TABLE coverage_sample DROP COLUMN messages;
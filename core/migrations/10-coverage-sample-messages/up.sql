@@ -0,0 +1,3 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+ALTER TABLE coverage_sample ADD COLUMN messages VARCHAR; -- JSON
@@ -0,0 +1,2 @@
+DROP INDEX context_assoc_file;
+ALTER TABLE context_assoc DROP COLUMN source_file_id;
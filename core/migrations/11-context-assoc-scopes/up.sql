@@ -0,0 +1,7 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- A `context_assoc` with neither `local_sample_id` nor `local_span_id` applies
+-- to a whole file if `source_file_id` is set, or else to a whole upload.
+ALTER TABLE context_assoc ADD COLUMN source_file_id INTEGER;
+
+CREATE INDEX context_assoc_file ON context_assoc (source_file_id);
@@ -0,0 +1,2 @@
+DROP INDEX upload_tag_key_value;
+DROP TABLE upload_tag;
@@ -0,0 +1,11 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+CREATE TABLE upload_tag (
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    key VARCHAR NOT NULL,
+    value VARCHAR NOT NULL,
+
+    PRIMARY KEY (raw_upload_id, key)
+);
+
+CREATE INDEX upload_tag_key_value ON upload_tag (key, value);
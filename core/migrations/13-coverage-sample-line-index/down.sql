@@ -0,0 +1,2 @@
+CREATE INDEX coverage_sample_source_file ON coverage_sample (source_file_id);
+DROP INDEX coverage_sample_source_file_line;
@@ -0,0 +1,7 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Lets queries look up the samples for a single line, e.g. to show which
+-- uploads covered it. Queries that only filter by file can use its prefix, so
+-- it replaces `coverage_sample_source_file`.
+CREATE INDEX coverage_sample_source_file_line ON coverage_sample (source_file_id, line_no);
+DROP INDEX coverage_sample_source_file;
@@ -0,0 +1,2 @@
+DROP INDEX session_file_totals_raw_upload;
+DROP TABLE session_file_totals;
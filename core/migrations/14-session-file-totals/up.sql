@@ -0,0 +1,23 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+CREATE TABLE session_file_totals (
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    files INTEGER NOT NULL,
+    lines INTEGER NOT NULL,
+    hits INTEGER NOT NULL,
+    misses INTEGER NOT NULL,
+    partials INTEGER NOT NULL,
+    coverage VARCHAR,
+    branches INTEGER NOT NULL,
+    methods INTEGER NOT NULL,
+    messages INTEGER NOT NULL,
+    sessions INTEGER NOT NULL,
+    complexity INTEGER NOT NULL,
+    complexity_total INTEGER NOT NULL,
+    diff INTEGER NOT NULL,
+
+    PRIMARY KEY (source_file_id, raw_upload_id)
+);
+
+CREATE INDEX session_file_totals_raw_upload ON session_file_totals (raw_upload_id);
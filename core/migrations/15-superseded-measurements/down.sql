@@ -0,0 +1,7 @@
+ALTER TABLE context_assoc DROP COLUMN superseded;
+ALTER TABLE span_data DROP COLUMN superseded;
+ALTER TABLE method_data DROP COLUMN superseded;
+ALTER TABLE branches_data DROP COLUMN superseded;
+ALTER TABLE coverage_sample DROP COLUMN superseded;
+
+ALTER TABLE raw_upload DROP COLUMN generation;
@@ -0,0 +1,9 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+ALTER TABLE raw_upload ADD COLUMN generation INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE coverage_sample ADD COLUMN superseded INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE branches_data ADD COLUMN superseded INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE method_data ADD COLUMN superseded INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE span_data ADD COLUMN superseded INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE context_assoc ADD COLUMN superseded INTEGER NOT NULL DEFAULT 0;
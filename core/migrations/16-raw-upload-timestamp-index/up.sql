@@ -0,0 +1,4 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Lets `list_uploads_in_range` find uploads by time without a full scan.
+CREATE INDEX raw_upload_timestamp ON raw_upload (timestamp);
@@ -0,0 +1,8 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Facts about the report file itself rather than its coverage data, like the
+-- newest version of codecov-rs that has written to it.
+CREATE TABLE report_metadata (
+    key VARCHAR PRIMARY KEY,
+    value VARCHAR NOT NULL
+);
@@ -0,0 +1,17 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Runs of consecutive lines that share the same plain line coverage, stored in
+-- place of one `coverage_sample` row per line. Each line in a run gets the
+-- next local sample ID after the previous one's.
+CREATE TABLE coverage_run (
+    raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL,
+    first_local_sample_id INTEGER NOT NULL,
+
+    source_file_id INTEGER REFERENCES source_file(id) NOT NULL,
+    first_line_no INTEGER NOT NULL,
+    last_line_no INTEGER NOT NULL,
+
+    hits INTEGER,
+
+    PRIMARY KEY (raw_upload_id, first_local_sample_id)
+);
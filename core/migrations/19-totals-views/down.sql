@@ -0,0 +1,3 @@
+DROP VIEW v_upload_totals;
+DROP VIEW v_file_totals;
+DROP VIEW v_coverage_sample;
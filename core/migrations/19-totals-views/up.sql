@@ -0,0 +1,66 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Read-only views so tools that only speak SQL can get the same aggregates
+-- `Report::list_file_totals` and `Report::list_upload_totals` return, which
+-- are computed from these views.
+
+-- Every current (not superseded) coverage sample, with the lines in
+-- `coverage_run` expanded to one row each.
+CREATE VIEW v_coverage_sample AS
+WITH RECURSIVE expanded (raw_upload_id, local_sample_id, source_file_id, line_no, last_line_no, hits) AS (
+    SELECT raw_upload_id, first_local_sample_id, source_file_id, first_line_no, last_line_no, hits FROM coverage_run
+    UNION ALL
+    SELECT raw_upload_id, local_sample_id + 1, source_file_id, line_no + 1, last_line_no, hits FROM expanded WHERE line_no < last_line_no
+)
+SELECT raw_upload_id, local_sample_id, source_file_id, line_no, coverage_type, hits, hit_branches, total_branches
+FROM coverage_sample
+WHERE superseded = 0
+UNION ALL
+SELECT raw_upload_id, local_sample_id, source_file_id, line_no, 'l', hits, NULL, NULL
+FROM expanded;
+
+-- Coverage totals for each file. Files without samples have all-zero totals.
+-- Complexity counts wherever a method was declared, even if the sample there
+-- is a branch.
+CREATE VIEW v_file_totals AS
+SELECT
+    source_file.id AS source_file_id,
+    source_file.path,
+    coalesce(sum(iif(sample.coverage_type = 'l' AND sample.hits > 0, 1, 0)), 0) AS hit_lines,
+    coalesce(sum(iif(sample.coverage_type = 'l', 1, 0)), 0) AS total_lines,
+    coalesce(sum(iif(sample.coverage_type = 'b', sample.hit_branches, 0)), 0) AS hit_branches,
+    coalesce(sum(iif(sample.coverage_type = 'b', sample.total_branches, 0)), 0) AS total_branches,
+    coalesce(sum(iif(sample.coverage_type = 'b', 1, 0)), 0) AS total_branch_roots,
+    coalesce(sum(iif(sample.coverage_type = 'm' AND sample.hits > 0, 1, 0)), 0) AS hit_methods,
+    coalesce(sum(iif(sample.coverage_type = 'm', 1, 0)), 0) AS total_methods,
+    coalesce(sum(method_data.hit_complexity_paths), 0) AS hit_complexity_paths,
+    coalesce(sum(method_data.total_complexity), 0) AS total_complexity
+FROM source_file
+LEFT JOIN v_coverage_sample sample
+    ON sample.source_file_id = source_file.id
+LEFT JOIN method_data
+    ON method_data.raw_upload_id = sample.raw_upload_id
+    AND method_data.local_sample_id = sample.local_sample_id
+GROUP BY source_file.id;
+
+-- Coverage totals for each upload, counted the same way as `v_file_totals`.
+-- Uploads without samples have all-zero totals.
+CREATE VIEW v_upload_totals AS
+SELECT
+    raw_upload.id AS raw_upload_id,
+    coalesce(sum(iif(sample.coverage_type = 'l' AND sample.hits > 0, 1, 0)), 0) AS hit_lines,
+    coalesce(sum(iif(sample.coverage_type = 'l', 1, 0)), 0) AS total_lines,
+    coalesce(sum(iif(sample.coverage_type = 'b', sample.hit_branches, 0)), 0) AS hit_branches,
+    coalesce(sum(iif(sample.coverage_type = 'b', sample.total_branches, 0)), 0) AS total_branches,
+    coalesce(sum(iif(sample.coverage_type = 'b', 1, 0)), 0) AS total_branch_roots,
+    coalesce(sum(iif(sample.coverage_type = 'm' AND sample.hits > 0, 1, 0)), 0) AS hit_methods,
+    coalesce(sum(iif(sample.coverage_type = 'm', 1, 0)), 0) AS total_methods,
+    coalesce(sum(method_data.hit_complexity_paths), 0) AS hit_complexity_paths,
+    coalesce(sum(method_data.total_complexity), 0) AS total_complexity
+FROM raw_upload
+LEFT JOIN v_coverage_sample sample
+    ON sample.raw_upload_id = raw_upload.id
+LEFT JOIN method_data
+    ON method_data.raw_upload_id = sample.raw_upload_id
+    AND method_data.local_sample_id = sample.local_sample_id
+GROUP BY raw_upload.id;
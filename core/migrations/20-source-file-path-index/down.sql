@@ -0,0 +1 @@
+DROP INDEX source_file_path;
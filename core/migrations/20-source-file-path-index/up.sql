@@ -0,0 +1,5 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Lets queries look up files by path, e.g. to find the tests covering the
+-- lines a diff changed, without scanning every file.
+CREATE INDEX source_file_path ON source_file (path);
@@ -0,0 +1,2 @@
+DROP INDEX collapsed_upload_canonical;
+DROP TABLE collapsed_upload;
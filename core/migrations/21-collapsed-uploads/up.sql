@@ -0,0 +1,10 @@
+-- See `src/report/models.rs` for complete, up-to-date schema documentation.
+
+-- Uploads that recorded exactly the same coverage as another upload, whose
+-- data is only stored under that other ("canonical") upload.
+CREATE TABLE collapsed_upload (
+    raw_upload_id INTEGER PRIMARY KEY REFERENCES raw_upload(id),
+    canonical_raw_upload_id INTEGER REFERENCES raw_upload(id) NOT NULL
+);
+
+CREATE INDEX collapsed_upload_canonical ON collapsed_upload (canonical_raw_upload_id);
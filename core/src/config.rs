@@ -0,0 +1,225 @@
+//! Reads the parts of a `codecov.yml` that affect how uploads are ingested:
+//! path `fixes`, `ignore`d paths, `flags` and `component_management`.
+//! Everything else in the file is ignored.
+//!
+//! ```
+//! # use codecov_rs::config::CodecovConfig;
+//! let config = CodecovConfig::from_yaml(
+//!     r#"
+//! fixes:
+//!   - "/home/runner/work/project/::"
+//! ignore:
+//!   - "vendor/**"
+//! "#,
+//! )
+//! .unwrap();
+//! let path = config.fix_path("/home/runner/work/project/src/lib.rs");
+//! assert_eq!(path, "src/lib.rs");
+//! assert!(config.is_ignored("vendor/dep/lib.rs"));
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::{
+    error::Result,
+    report::components::{glob_match, Component, ComponentManagement},
+};
+
+/// A `before::after` rewrite from the `fixes` list, which replaces a leading
+/// `before` in a path with `after`. Either side may be empty, so `::src/`
+/// adds a prefix and `/tmp/build/::` strips one.
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PathFix {
+    pub before: String,
+    pub after: String,
+}
+
+impl PathFix {
+    /// Rewrites `path` if it starts with `before`.
+    pub fn apply(&self, path: &str) -> Option<String> {
+        path.strip_prefix(&self.before)
+            .map(|rest| format!("{}{rest}", self.after))
+    }
+}
+
+impl TryFrom<String> for PathFix {
+    type Error = String;
+
+    fn try_from(fix: String) -> Result<Self, Self::Error> {
+        let Some((before, after)) = fix.split_once("::") else {
+            return Err(format!("path fix '{fix}' is missing '::'"));
+        };
+        Ok(PathFix {
+            before: before.to_string(),
+            after: after.to_string(),
+        })
+    }
+}
+
+/// An entry under `flags`.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Deserialize)]
+pub struct FlagConfig {
+    /// Globs for the paths uploads with this flag are expected to cover. If
+    /// empty, the flag covers every path.
+    #[serde(default)]
+    pub paths: Vec<String>,
+
+    /// Whether the flag's coverage is carried forward from the last commit
+    /// that uploaded it when a commit doesn't.
+    #[serde(default)]
+    pub carryforward: bool,
+}
+
+impl FlagConfig {
+    /// Whether `path` is one of the paths the flag covers.
+    pub fn covers(&self, path: &str) -> bool {
+        self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|glob| glob_match(glob.as_bytes(), path.as_bytes()))
+    }
+}
+
+/// The ingestion-related parts of a `codecov.yml`.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Deserialize)]
+pub struct CodecovConfig {
+    /// Path rewrites, tried in order. Only the first matching one is applied.
+    #[serde(default)]
+    pub fixes: Vec<PathFix>,
+
+    /// Globs for paths that shouldn't be included in reports.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+
+    /// Flag definitions, keyed by flag name.
+    #[serde(default)]
+    pub flags: BTreeMap<String, FlagConfig>,
+
+    #[serde(default)]
+    pub component_management: ComponentManagement,
+}
+
+impl CodecovConfig {
+    pub fn from_yaml(yaml: &str) -> Result<CodecovConfig> {
+        // An empty file parses as YAML's `null`
+        if yaml.trim().is_empty() {
+            return Ok(CodecovConfig::default());
+        }
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// Applies the first of [`CodecovConfig::fixes`] that matches `path`.
+    pub fn fix_path(&self, path: &str) -> String {
+        self.fixes
+            .iter()
+            .find_map(|fix| fix.apply(path))
+            .unwrap_or_else(|| path.to_string())
+    }
+
+    /// Whether `path` matches any of the [`CodecovConfig::ignore`] globs.
+    pub fn is_ignored(&self, path: &str) -> bool {
+        self.ignore
+            .iter()
+            .any(|glob| glob_match(glob.as_bytes(), path.as_bytes()))
+    }
+
+    /// The components defined under `component_management`.
+    pub fn components(&self) -> &[Component] {
+        &self.component_management.individual_components
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CodecovError;
+
+    #[test]
+    fn test_from_yaml() {
+        let config = CodecovConfig::from_yaml(
+            r#"
+codecov:
+  require_ci_to_pass: true
+coverage:
+  status:
+    project: off
+fixes:
+  - "before/::after/"
+  - "::prefix/"
+ignore:
+  - "vendor/**"
+  - "**/*_pb2.py"
+flags:
+  unit:
+    paths:
+      - "src/**"
+    carryforward: true
+  integration: {}
+component_management:
+  individual_components:
+    - component_id: parsers
+      name: Parsers
+      paths:
+        - "src/parsers/**"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.fixes,
+            [
+                PathFix {
+                    before: "before/".to_string(),
+                    after: "after/".to_string(),
+                },
+                PathFix {
+                    before: "".to_string(),
+                    after: "prefix/".to_string(),
+                },
+            ]
+        );
+        assert_eq!(config.fix_path("before/lib.rs"), "after/lib.rs");
+        assert_eq!(config.fix_path("lib.rs"), "prefix/lib.rs");
+
+        assert!(config.is_ignored("vendor/dep/lib.rs"));
+        assert!(config.is_ignored("proto/api_pb2.py"));
+        assert!(!config.is_ignored("src/lib.rs"));
+
+        assert_eq!(
+            config.flags["unit"],
+            FlagConfig {
+                paths: vec!["src/**".to_string()],
+                carryforward: true,
+            }
+        );
+        assert!(config.flags["unit"].covers("src/lib.rs"));
+        assert!(!config.flags["unit"].covers("tests/lib.rs"));
+        assert!(config.flags["integration"].covers("tests/lib.rs"));
+
+        assert_eq!(config.components().len(), 1);
+        assert_eq!(config.components()[0].display_name(), "Parsers");
+        assert!(config.components()[0].matches("src/parsers/json.rs"));
+    }
+
+    #[test]
+    fn test_from_yaml_empty() {
+        assert_eq!(
+            CodecovConfig::from_yaml("").unwrap(),
+            CodecovConfig::default()
+        );
+        assert_eq!(
+            CodecovConfig::from_yaml("codecov:\n  bot: codecov\n").unwrap(),
+            CodecovConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_from_yaml_malformed_fix() {
+        let result = CodecovConfig::from_yaml("fixes:\n  - \"no separator\"\n");
+        assert!(matches!(result, Err(CodecovError::ConfigError(_))));
+    }
+}
@@ -1,29 +1,217 @@
 use thiserror::Error;
 
+use crate::report::limits::Limit;
+
 pub type Result<T, E = CodecovError> = std::result::Result<T, E>;
 
 #[derive(Error, Debug)]
 pub enum CodecovError {
+    // Converted manually so constraint violations get their own variant
+    #[cfg(feature = "sqlite")]
     #[error("sqlite failure: '{0}'")]
-    SqliteError(#[from] rusqlite::Error),
+    SqliteError(rusqlite::Error),
+
+    /// An insert or update violated a constraint in the schema. `columns` holds
+    /// the `table.column` names SQLite reported, if any.
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite failure: '{source}'")]
+    SqliteConstraintViolation {
+        kind: ConstraintKind,
+        columns: Vec<String>,
+        source: rusqlite::Error,
+    },
 
+    #[cfg(feature = "sqlite")]
     #[error("sqlite migration failure: '{0}'")]
     SqliteMigrationError(#[from] rusqlite_migration::Error),
 
+    /// The database's schema is one we can't use. Either it was created by a
+    /// newer version of this library and has migrations applied that we don't
+    /// know about, or it was opened read-only and is too old to use without
+    /// migrating it.
+    #[error("database schema version {found} is not supported (expected {latest})")]
+    SchemaVersionMismatch { found: usize, latest: usize },
+
+    /// The database was last written by a newer version of this library, so
+    /// it may hold data we'd misread even if its schema looks familiar.
+    #[error(
+        "report was written by codecov-rs {found}, which is newer than this version ({current})"
+    )]
+    NewerCrateVersion { found: String, current: String },
+
+    /// A database failed SQLite's `integrity_check` or `foreign_key_check`.
+    /// `problems` holds what they reported.
+    #[cfg(feature = "sqlite")]
+    #[error("database is corrupt: {}", .problems.join("; "))]
+    CorruptDatabase { problems: Vec<String> },
+
     #[error("report builder error: '{0}'")]
     ReportBuilderError(String),
 
-    // Can't use #[from]
-    #[error("parser error: '{0}'")]
-    ParserError(winnow::error::ContextError),
+    /// The input went over one of the
+    /// [`Limits`](crate::report::limits::Limits) it was parsed with. `found`
+    /// is how many there were, or for [`Limit::LinesPerFile`] the line number.
+    #[error("input has too many {limit}: {found} (the limit is {max})")]
+    LimitExceeded { limit: Limit, max: u64, found: u64 },
+
+    /// A winnow parser failed. `offset`, `line` and `column` (both 1-based)
+    /// locate the failure in the input.
+    #[error("parser error at {line}:{column}: '{context}'")]
+    ParserError {
+        offset: usize,
+        line: usize,
+        column: usize,
+        context: winnow::error::ContextError,
+    },
 
     #[error("parser error: '{0}'")]
     Json(#[from] serde_json::Error),
 
+    #[cfg(any(feature = "opencover", feature = "scoverage"))]
+    #[error("parser error: '{0}'")]
+    Xml(#[from] roxmltree::Error),
+
+    /// The input is recognizably a coverage format, or a version of one, that
+    /// we don't handle.
+    #[error("unsupported format '{format}': {reason}")]
+    UnsupportedFormat { format: String, reason: String },
+
     #[error("io error: '{0}'")]
     IOError(#[from] std::io::Error),
 
     #[cfg(feature = "pyreport")]
     #[error("failed to convert sqlite to pyreport: '{0}'")]
     PyreportConversionError(String),
+
+    #[cfg(feature = "config")]
+    #[error("config error: '{0}'")]
+    ConfigError(#[from] serde_yaml::Error),
+}
+
+/// The kind of constraint behind a [`CodecovError::SqliteConstraintViolation`].
+#[cfg(feature = "sqlite")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ConstraintKind {
+    PrimaryKey,
+    Unique,
+    ForeignKey,
+    NotNull,
+    Check,
+    Other,
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for CodecovError {
+    fn from(error: rusqlite::Error) -> Self {
+        use rusqlite::ffi;
+
+        let (extended_code, message) = match &error {
+            rusqlite::Error::SqliteFailure(e, message)
+                if e.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                (e.extended_code, message.as_deref().unwrap_or_default())
+            }
+            _ => return CodecovError::SqliteError(error),
+        };
+
+        let kind = match extended_code {
+            ffi::SQLITE_CONSTRAINT_PRIMARYKEY => ConstraintKind::PrimaryKey,
+            ffi::SQLITE_CONSTRAINT_UNIQUE => ConstraintKind::Unique,
+            ffi::SQLITE_CONSTRAINT_FOREIGNKEY => ConstraintKind::ForeignKey,
+            ffi::SQLITE_CONSTRAINT_NOTNULL => ConstraintKind::NotNull,
+            ffi::SQLITE_CONSTRAINT_CHECK => ConstraintKind::Check,
+            _ => ConstraintKind::Other,
+        };
+
+        // Messages look like "UNIQUE constraint failed: table.a, table.b"
+        let columns = message
+            .split_once(": ")
+            .map(|(_, columns)| columns.split(", ").map(str::to_string).collect())
+            .unwrap_or_default();
+
+        CodecovError::SqliteConstraintViolation {
+            kind,
+            columns,
+            source: error,
+        }
+    }
+}
+
+impl CodecovError {
+    /// Build a [`CodecovError::ParserError`] for a failure `remaining.len()`
+    /// bytes before the end of `input`.
+    pub fn parser_error(
+        input: &str,
+        remaining: &str,
+        context: winnow::error::ContextError,
+    ) -> CodecovError {
+        let offset = input.len().saturating_sub(remaining.len());
+        let consumed = &input.as_bytes()[..offset];
+        let line = consumed.iter().filter(|b| **b == b'\n').count() + 1;
+        let line_start = consumed
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+
+        CodecovError::ParserError {
+            offset,
+            line,
+            column: offset - line_start + 1,
+            context,
+        }
+    }
+
+    /// Whether this failed because another connection to the same SQLite
+    /// database held a lock for longer than we were willing to wait. See
+    /// [`BusyPolicy`](crate::report::sqlite::BusyPolicy).
+    #[cfg(feature = "sqlite")]
+    pub fn is_busy(&self) -> bool {
+        match self {
+            CodecovError::SqliteError(e) => crate::report::sqlite::is_busy(e),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parser_error_location() {
+        let input = "abc\ndef\nghi";
+
+        let error = CodecovError::parser_error(input, &input[5..], Default::default());
+        assert!(matches!(
+            error,
+            CodecovError::ParserError {
+                offset: 5,
+                line: 2,
+                column: 2,
+                ..
+            }
+        ));
+
+        let error = CodecovError::parser_error(input, input, Default::default());
+        assert!(matches!(
+            error,
+            CodecovError::ParserError {
+                offset: 0,
+                line: 1,
+                column: 1,
+                ..
+            }
+        ));
+
+        let error = CodecovError::parser_error(input, "", Default::default());
+        assert!(matches!(
+            error,
+            CodecovError::ParserError {
+                offset: 11,
+                line: 3,
+                column: 4,
+                ..
+            }
+        ));
+    }
 }
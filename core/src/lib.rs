@@ -6,5 +6,10 @@ pub mod parsers;
 
 pub mod error;
 
+#[cfg(feature = "config")]
+pub mod config;
+
+pub mod prelude;
+
 #[cfg(any(test, feature = "testing"))]
 pub mod test_utils;
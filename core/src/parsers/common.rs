@@ -1,7 +1,10 @@
-use std::{fmt, fmt::Debug, marker::PhantomData};
+use std::{collections::BTreeMap, fmt, fmt::Debug, marker::PhantomData, time::Duration};
 
-use crate::report::{Report, ReportBuilder};
+use ::winnow::Stateful;
 
+use crate::report::{components::glob_match, models, Report, ReportBuilder};
+
+/// Parser state that holds the [`ReportBuilder`] parsed data is written to.
 #[derive(PartialEq)]
 pub struct ReportBuilderCtx<R: Report, B: ReportBuilder<R>> {
     pub report_builder: B,
@@ -25,6 +28,211 @@ impl<R: Report, B: ReportBuilder<R>> Debug for ReportBuilderCtx<R, B> {
     }
 }
 
+/// An input stream for parsers that insert what they parse into a
+/// [`ReportBuilder`] as they go, reachable as `buf.state.report_builder`.
+pub type ReportBuilderStream<S, R, B> = Stateful<S, ReportBuilderCtx<R, B>>;
+
+/// What a parser inserted into its [`ReportBuilder`], returned by every
+/// parser so callers can log and measure ingestion the same way regardless of
+/// format.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IngestResult {
+    /// The upload everything was inserted under, for formats that make up a
+    /// single upload. A pyreport inserts one upload per session, so this is
+    /// `None` for it.
+    pub raw_upload: Option<models::RawUpload>,
+
+    /// How many files coverage was recorded for.
+    pub files_touched: usize,
+
+    /// How many [`models::CoverageSample`]s were inserted.
+    pub samples_inserted: usize,
+
+    /// How many files were left out because they matched the parser's
+    /// [`IgnoreGlobs`].
+    pub files_ignored: usize,
+
+    /// How many malformed measurements each of a pyreport's `QuirksProfile`s
+    /// corrected, keyed by the profile's name. Always empty for other formats.
+    pub quirks_applied: BTreeMap<String, usize>,
+
+    /// How many labels were left off of samples by a pyreport's
+    /// [`LabelPruning`](crate::report::LabelPruning). Always zero for other
+    /// formats.
+    pub labels_pruned: usize,
+
+    /// Problems with the input that didn't stop it from being parsed, such as
+    /// skipped chunks or sessions.
+    pub warnings: Vec<String>,
+
+    /// How long parsing took. Always zero on `wasm32`, which has no clock.
+    pub duration: Duration,
+}
+
+/// Globs for paths a parser leaves out of the report entirely, like
+/// `vendor/**`, `**/node_modules/**` or `**/*_pb2.py`. They match the same way
+/// as a `codecov.yml`'s `ignore` list (see [`crate::report::components`]), but
+/// nothing for an ignored file is ever inserted, which for some repositories
+/// is most of what there is to ingest.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct IgnoreGlobs(pub Vec<String>);
+
+impl IgnoreGlobs {
+    /// Whether `path` matches any of the globs.
+    pub fn is_ignored(&self, path: &str) -> bool {
+        self.0
+            .iter()
+            .any(|glob| glob_match(glob.as_bytes(), path.as_bytes()))
+    }
+}
+
+/// Times a parse for [`IngestResult::duration`].
+#[cfg(any(
+    feature = "pyreport",
+    feature = "coverlet",
+    feature = "coveragepy",
+    feature = "gcov",
+    feature = "lcov",
+    feature = "opencover",
+    feature = "scoverage"
+))]
+pub(crate) struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+#[cfg(any(
+    feature = "pyreport",
+    feature = "coverlet",
+    feature = "coveragepy",
+    feature = "gcov",
+    feature = "lcov",
+    feature = "opencover",
+    feature = "scoverage"
+))]
+impl Stopwatch {
+    pub(crate) fn start() -> Stopwatch {
+        Stopwatch {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        return Duration::ZERO;
+    }
+}
+
+/// What a parser does with a sample whose line number is past the end of its
+/// file. Some formats report bogus line numbers, but we can only tell when the
+/// file's [`line_count`](crate::report::models::SourceFile::line_count) is
+/// known.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum LineBoundsPolicy {
+    /// Record the sample as reported.
+    #[default]
+    Keep,
+    /// Record the sample on the file's last line instead.
+    Clamp,
+    /// Don't record the sample.
+    Drop,
+}
+
+impl LineBoundsPolicy {
+    /// Returns the line number to record a sample on line `line_no` at, or
+    /// `None` if it should be dropped. Lines are always kept if `line_count`
+    /// is `None`.
+    pub fn apply(self, line_no: i64, line_count: Option<i64>) -> Option<i64> {
+        match (self, line_count) {
+            (_, None) | (LineBoundsPolicy::Keep, _) => Some(line_no),
+            (_, Some(line_count)) if line_no <= line_count => Some(line_no),
+            (LineBoundsPolicy::Clamp, Some(line_count)) => Some(line_count),
+            (LineBoundsPolicy::Drop, _) => None,
+        }
+    }
+}
+
+/// Strips the return type and parameter list from a .NET method signature as
+/// Coverlet and OpenCover write it:
+/// `System.Int32 MyLibrary.Calculator::Add(System.Int32,System.Int32)` becomes
+/// `MyLibrary.Calculator::Add`.
+#[cfg(any(feature = "coverlet", feature = "opencover"))]
+pub(crate) fn dotnet_method_name(signature: &str) -> &str {
+    let name = signature
+        .split_once('(')
+        .map_or(signature, |(name, _)| name);
+    name.rsplit_once(' ').map_or(name, |(_, name)| name)
+}
+
+/// Helpers for parsers of XML formats, which read the whole document with
+/// [`roxmltree`] before inserting anything.
+#[cfg(any(feature = "opencover", feature = "scoverage"))]
+pub(crate) mod xml {
+    use std::str::FromStr;
+
+    use roxmltree::Node;
+    use winnow::error::{AddContext, ContextError, StrContext};
+
+    use crate::error::{CodecovError, Result};
+
+    /// The text a [`roxmltree::Document`] was parsed from, to locate errors
+    /// in.
+    pub struct XmlInput<'i>(pub &'i str);
+
+    impl XmlInput<'_> {
+        /// A [`CodecovError::ParserError`] at the start of `node`.
+        pub fn error(&self, node: Node, label: &'static str) -> CodecovError {
+            let remaining = &self.0[node.range().start..];
+            CodecovError::parser_error(
+                self.0,
+                remaining,
+                ContextError::new().add_context(&remaining, StrContext::Label(label)),
+            )
+        }
+
+        /// Parses `node`'s attribute `name` if it has one.
+        pub fn opt_attr<T: FromStr>(&self, node: Node, name: &'static str) -> Result<Option<T>> {
+            node.attribute(name)
+                .map(|value| value.trim().parse().map_err(|_| self.error(node, name)))
+                .transpose()
+        }
+
+        /// Parses `node`'s attribute `name`, which must be present.
+        pub fn attr<T: FromStr>(&self, node: Node, name: &'static str) -> Result<T> {
+            self.opt_attr(node, name)?
+                .ok_or_else(|| self.error(node, name))
+        }
+    }
+
+    /// The child elements of `node` named `tag`.
+    pub fn children<'a, 'i>(
+        node: Node<'a, 'i>,
+        tag: &'static str,
+    ) -> impl Iterator<Item = Node<'a, 'i>> {
+        node.children().filter(move |child| child.has_tag_name(tag))
+    }
+
+    /// The first child element of `node` named `tag`.
+    pub fn child<'a, 'i>(node: Node<'a, 'i>, tag: &'static str) -> Option<Node<'a, 'i>> {
+        children(node, tag).next()
+    }
+
+    /// The `item` elements in `node`'s `list` element, like the `<Module>`s
+    /// in `<Modules>`.
+    pub fn list<'a, 'i>(
+        node: Node<'a, 'i>,
+        list: &'static str,
+        item: &'static str,
+    ) -> impl Iterator<Item = Node<'a, 'i>> {
+        child(node, list)
+            .into_iter()
+            .flat_map(move |list| children(list, item))
+    }
+}
+
 pub mod winnow {
     use winnow::{
         ascii::float,
@@ -177,3 +385,23 @@ pub mod winnow {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_bounds_policy() {
+        for policy in [
+            LineBoundsPolicy::Keep,
+            LineBoundsPolicy::Clamp,
+            LineBoundsPolicy::Drop,
+        ] {
+            assert_eq!(policy.apply(12, None), Some(12));
+            assert_eq!(policy.apply(10, Some(10)), Some(10));
+        }
+        assert_eq!(LineBoundsPolicy::Keep.apply(12, Some(10)), Some(12));
+        assert_eq!(LineBoundsPolicy::Clamp.apply(12, Some(10)), Some(10));
+        assert_eq!(LineBoundsPolicy::Drop.apply(12, Some(10)), None);
+    }
+}
@@ -0,0 +1,421 @@
+//! Parses the JSON report written by [coverage.py](https://coverage.readthedocs.io/)'s
+//! `coverage json` command.
+//!
+//! The parts of the format we use look like:
+//! ```json
+//! {
+//!     "meta": {"branch_coverage": true, "show_contexts": true, ...},
+//!     "files": {
+//!         "src/foo.py": {
+//!             "executed_lines": [1, 2, 4],
+//!             "missing_lines": [3],
+//!             "excluded_lines": [5],
+//!             "executed_branches": [[2, 4]],
+//!             "missing_branches": [[2, 3]],
+//!             "contexts": {
+//!                 "1": [""],
+//!                 "2": ["tests/test_foo.py::test_bar|run"],
+//!                 "4": ["tests/test_foo.py::test_bar|run"]
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! coverage.py doesn't count hits, so executed lines have 1 hit and missing
+//! lines have 0. A line that is the source of any branch arc is recorded as a
+//! branch sample with a [`models::BranchesData`] for each arc, identified by
+//! its destination line in [`models::BranchFormat::Line`] format. Negative
+//! destinations are coverage.py's way of saying "exits the code object".
+//! Lines excluded with `# pragma: no cover` or the `exclude_lines` setting get
+//! a [`models::LineAttribute`] with [`models::LineAttributes::EXCLUDED`].
+//!
+//! `contexts` is only present when the report was generated with
+//! `--show-contexts`. When measured with dynamic contexts (e.g. pytest-cov's
+//! `--cov-context=test`), each context string identifies a test. If
+//! [`CoveragePyOptions::ingest_contexts`] is set, each distinct context string
+//! becomes a [`models::Context`] and each (line, context) pair becomes a
+//! [`models::ContextAssoc`]. The empty string is coverage.py's default
+//! context and is skipped.
+//!
+//! Every [`models::SourceFile`] gets `"python"` as its language, and its
+//! `line_count` if one is given in [`CoveragePyOptions::line_counts`]. Lines
+//! past the end of a file with a known line count are handled according to
+//! [`CoveragePyOptions::line_bounds`], except that excluded lines are never
+//! clamped, since that would exclude the last line instead.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+
+use super::common::{IgnoreGlobs, IngestResult, LineBoundsPolicy, Stopwatch};
+use crate::{
+    error::Result,
+    report::{models, Report, ReportBuilder},
+};
+
+#[derive(Debug, Clone)]
+pub struct CoveragePyOptions {
+    /// Whether to create [`models::Context`]s and [`models::ContextAssoc`]s
+    /// from each file's `contexts`.
+    pub ingest_contexts: bool,
+
+    /// Known line counts for files in the report, keyed by path.
+    pub line_counts: HashMap<String, i64>,
+
+    /// What to do with lines past the end of a file in `line_counts`.
+    pub line_bounds: LineBoundsPolicy,
+
+    /// Paths of files to leave out of the report.
+    pub ignore_globs: IgnoreGlobs,
+}
+
+impl Default for CoveragePyOptions {
+    fn default() -> Self {
+        Self {
+            ingest_contexts: true,
+            line_counts: HashMap::new(),
+            line_bounds: LineBoundsPolicy::default(),
+            ignore_globs: IgnoreGlobs::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CoveragePyJson {
+    // NOTE: this is a `BTreeMap` only to have stable iteration order in tests
+    files: BTreeMap<String, File>,
+}
+
+#[derive(Debug, Deserialize)]
+struct File {
+    executed_lines: Vec<i64>,
+    missing_lines: Vec<i64>,
+    #[serde(default)]
+    excluded_lines: Vec<i64>,
+    #[serde(default)]
+    executed_branches: Vec<(i64, i64)>,
+    #[serde(default)]
+    missing_branches: Vec<(i64, i64)>,
+    #[serde(default)]
+    contexts: HashMap<i64, Vec<String>>,
+}
+
+/// Maps context strings to the IDs of [`models::Context`]s we've already
+/// inserted so each is only inserted once per parse.
+#[derive(Debug, Default)]
+struct ContextCache(HashMap<String, i64>);
+
+impl ContextCache {
+    fn get_or_insert<B, R>(&mut self, name: &str, builder: &mut B) -> Result<i64>
+    where
+        B: ReportBuilder<R>,
+        R: Report,
+    {
+        if let Some(id) = self.0.get(name) {
+            return Ok(*id);
+        }
+        let context = builder.insert_context(name)?;
+        self.0.insert(context.name, context.id);
+        Ok(context.id)
+    }
+}
+
+/// Parses a coverage.py JSON report into `builder` as a single
+/// [`models::RawUpload`]. Lines dropped by
+/// [`line_bounds`](CoveragePyOptions::line_bounds) are reported as warnings.
+pub fn parse_coveragepy_json<B, R>(
+    input: &[u8],
+    builder: &mut B,
+    options: &CoveragePyOptions,
+) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    let stopwatch = Stopwatch::start();
+    let report: CoveragePyJson = serde_json::from_slice(input)?;
+
+    let raw_upload = builder.insert_raw_upload(Default::default())?;
+    let mut result = IngestResult::default();
+    let mut context_cache = ContextCache::default();
+
+    for (path, file) in report.files {
+        if options.ignore_globs.is_ignored(&path) {
+            result.files_ignored += 1;
+            continue;
+        }
+        let mut source_file = builder.insert_file(&path)?;
+        result.files_touched += 1;
+        source_file.language = Some("python".to_string());
+        source_file.line_count = options.line_counts.get(&path).copied();
+        builder.update_file_metadata(&source_file)?;
+
+        // Branch arcs keyed by their source line
+        let mut arcs: BTreeMap<i64, Vec<(i64, bool)>> = BTreeMap::new();
+        for (from, to) in &file.executed_branches {
+            arcs.entry(*from).or_default().push((*to, true));
+        }
+        for (from, to) in &file.missing_branches {
+            arcs.entry(*from).or_default().push((*to, false));
+        }
+
+        let lines = file
+            .executed_lines
+            .iter()
+            .map(|line| (*line, 1))
+            .chain(file.missing_lines.iter().map(|line| (*line, 0)));
+        // Each sample is paired with the line coverage.py reported it on, which
+        // may differ from its `line_no` if it was clamped
+        let mut samples: Vec<(i64, models::CoverageSample)> = lines
+            .filter_map(|(reported_line, hits)| {
+                let line_no = options
+                    .line_bounds
+                    .apply(reported_line, source_file.line_count)?;
+                let mut sample = models::CoverageSample {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: source_file.id,
+                    line_no,
+                    ..Default::default()
+                };
+                match arcs.get(&reported_line) {
+                    Some(arcs) => {
+                        sample.coverage_type = models::CoverageType::Branch;
+                        sample.hit_branches =
+                            Some(arcs.iter().filter(|(_, hit)| *hit).count() as i64);
+                        sample.total_branches = Some(arcs.len() as i64);
+                    }
+                    None => sample.hits = Some(hits),
+                }
+                Some((reported_line, sample))
+            })
+            .collect();
+        let dropped = file.executed_lines.len() + file.missing_lines.len() - samples.len();
+        if dropped > 0 {
+            result
+                .warnings
+                .push(format!("dropped {dropped} line(s) past the end of {path}"));
+        }
+        samples.sort_by_key(|(_, sample)| sample.line_no);
+        builder.multi_insert_coverage_sample(samples.iter_mut().map(|(_, s)| s).collect())?;
+        result.samples_inserted += samples.len();
+
+        let mut branches = vec![];
+        let mut assocs = vec![];
+        for (reported_line, sample) in &samples {
+            for (to, hit) in arcs.get(reported_line).into_iter().flatten() {
+                branches.push(models::BranchesData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: source_file.id,
+                    local_sample_id: sample.local_sample_id,
+                    hits: *hit as i64,
+                    branch_format: models::BranchFormat::Line,
+                    branch: to.to_string(),
+                    ..Default::default()
+                });
+            }
+
+            if !options.ingest_contexts {
+                continue;
+            }
+            let contexts = file.contexts.get(reported_line).into_iter().flatten();
+            for context in contexts.filter(|c| !c.is_empty()) {
+                assocs.push(models::ContextAssoc {
+                    context_id: context_cache.get_or_insert(context, builder)?,
+                    raw_upload_id: raw_upload.id,
+                    local_sample_id: Some(sample.local_sample_id),
+                    ..Default::default()
+                });
+            }
+        }
+        builder.multi_insert_branches_data(branches.iter_mut().collect())?;
+        builder.multi_associate_context(assocs.iter_mut().collect())?;
+
+        let excluded_bounds = match options.line_bounds {
+            LineBoundsPolicy::Clamp => LineBoundsPolicy::Drop,
+            line_bounds => line_bounds,
+        };
+        let excluded: Vec<_> = file
+            .excluded_lines
+            .iter()
+            .filter_map(|line| excluded_bounds.apply(*line, source_file.line_count))
+            .map(|line_no| models::LineAttribute {
+                raw_upload_id: raw_upload.id,
+                source_file_id: source_file.id,
+                line_no,
+                attributes: models::LineAttributes::EXCLUDED,
+            })
+            .collect();
+        builder.multi_insert_line_attributes(&excluded)?;
+    }
+
+    result.raw_upload = Some(raw_upload);
+    result.duration = stopwatch.elapsed();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_report::TestReportBuilder;
+
+    const INPUT: &[u8] = br#"{
+        "meta": {"format": 3, "version": "7.4.0", "branch_coverage": true, "show_contexts": true},
+        "files": {
+            "src/foo.py": {
+                "executed_lines": [1, 2, 4],
+                "missing_lines": [3],
+                "excluded_lines": [5],
+                "executed_branches": [[2, 4]],
+                "missing_branches": [[2, 3]],
+                "contexts": {
+                    "1": [""],
+                    "2": ["test_a|run", "test_b|run"],
+                    "4": ["test_a|run"]
+                }
+            }
+        },
+        "totals": {}
+    }"#;
+
+    #[test]
+    fn test_parse_coveragepy_json() {
+        let mut report_builder = TestReportBuilder::default();
+        let result =
+            parse_coveragepy_json(INPUT, &mut report_builder, &Default::default()).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.samples_inserted), (1, 4));
+        assert!(result.warnings.is_empty());
+        let raw_upload = result.raw_upload.unwrap();
+
+        let file = models::SourceFile {
+            language: Some("python".to_string()),
+            ..models::SourceFile::new("src/foo.py")
+        };
+        assert_eq!(report.files, std::slice::from_ref(&file));
+
+        let samples: Vec<_> = report
+            .samples
+            .iter()
+            .map(|s| (s.line_no, s.coverage_type, s.hits, s.hit_branches))
+            .collect();
+        assert_eq!(
+            samples,
+            &[
+                (1, models::CoverageType::Line, Some(1), None),
+                (2, models::CoverageType::Branch, None, Some(1)),
+                (3, models::CoverageType::Line, Some(0), None),
+                (4, models::CoverageType::Line, Some(1), None),
+            ]
+        );
+
+        let branches: Vec<_> = report
+            .branches
+            .iter()
+            .map(|b| (b.local_sample_id, b.branch.as_str(), b.hits))
+            .collect();
+        assert_eq!(branches, &[(1, "4", 1), (1, "3", 0)]);
+
+        assert_eq!(
+            report.line_attributes,
+            &[models::LineAttribute {
+                raw_upload_id: raw_upload.id,
+                source_file_id: file.id,
+                line_no: 5,
+                attributes: models::LineAttributes::EXCLUDED,
+            }]
+        );
+
+        // Each context is only inserted once, and the default context is skipped
+        assert_eq!(
+            report.contexts,
+            &[
+                models::Context::new("test_a|run"),
+                models::Context::new("test_b|run")
+            ]
+        );
+        let test_a = models::Context::new("test_a|run").id;
+        let test_b = models::Context::new("test_b|run").id;
+        let assocs: Vec<_> = report
+            .assocs
+            .iter()
+            .map(|a| (a.context_id, a.raw_upload_id, a.local_sample_id))
+            .collect();
+        assert_eq!(
+            assocs,
+            &[
+                (test_a, raw_upload.id, Some(1)),
+                (test_b, raw_upload.id, Some(1)),
+                (test_a, raw_upload.id, Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_coveragepy_json_without_contexts() {
+        let mut report_builder = TestReportBuilder::default();
+        let options = CoveragePyOptions {
+            ingest_contexts: false,
+            ..Default::default()
+        };
+        parse_coveragepy_json(INPUT, &mut report_builder, &options).unwrap();
+        let report = report_builder.build().unwrap();
+
+        assert_eq!(report.samples.len(), 4);
+        assert!(report.contexts.is_empty());
+        assert!(report.assocs.is_empty());
+    }
+
+    #[test]
+    fn test_parse_coveragepy_json_line_bounds() {
+        let line_counts = HashMap::from([("src/foo.py".to_string(), 3)]);
+        let parse = |line_bounds| {
+            let mut report_builder = TestReportBuilder::default();
+            let options = CoveragePyOptions {
+                line_counts: line_counts.clone(),
+                line_bounds,
+                ..Default::default()
+            };
+            let result = parse_coveragepy_json(INPUT, &mut report_builder, &options).unwrap();
+            (report_builder.build().unwrap(), result.warnings)
+        };
+        let line_nos = |report: &crate::test_utils::test_report::TestReport| {
+            report.samples.iter().map(|s| s.line_no).collect::<Vec<_>>()
+        };
+
+        let (report, warnings) = parse(LineBoundsPolicy::Keep);
+        assert!(warnings.is_empty());
+        assert_eq!(report.files[0].line_count, Some(3));
+        assert_eq!(line_nos(&report), &[1, 2, 3, 4]);
+        assert_eq!(report.line_attributes[0].line_no, 5);
+
+        let (report, warnings) = parse(LineBoundsPolicy::Drop);
+        assert_eq!(line_nos(&report), &[1, 2, 3]);
+        assert_eq!(warnings, &["dropped 1 line(s) past the end of src/foo.py"]);
+        // Line 4's context goes with it
+        assert_eq!(report.assocs.len(), 2);
+
+        let (report, warnings) = parse(LineBoundsPolicy::Clamp);
+        assert!(warnings.is_empty());
+        assert_eq!(line_nos(&report), &[1, 2, 3, 3]);
+        assert_eq!(report.assocs.len(), 3);
+        // Clamping line 5's exclusion would exclude line 3
+        assert!(report.line_attributes.is_empty());
+    }
+
+    #[test]
+    fn test_parse_coveragepy_json_ignore_globs() {
+        let mut report_builder = TestReportBuilder::default();
+        let options = CoveragePyOptions {
+            ignore_globs: IgnoreGlobs(vec!["src/**".to_string()]),
+            ..Default::default()
+        };
+        let result = parse_coveragepy_json(INPUT, &mut report_builder, &options).unwrap();
+        let report = report_builder.build().unwrap();
+
+        assert_eq!((result.files_touched, result.files_ignored), (0, 1));
+        assert!(report.files.is_empty());
+        assert!(report.samples.is_empty());
+        assert!(report.line_attributes.is_empty());
+    }
+}
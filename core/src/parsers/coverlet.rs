@@ -0,0 +1,381 @@
+//! Parses the JSON format written by [Coverlet](https://github.com/coverlet-coverage/coverlet),
+//! the most common coverage tool for .NET projects.
+//!
+//! The format nests modules, documents (source files), classes and methods:
+//! ```json
+//! {
+//!     "MyLibrary.dll": {
+//!         "/src/MyLibrary/Calculator.cs": {
+//!             "MyLibrary.Calculator": {
+//!                 "System.Int32 MyLibrary.Calculator::Add(System.Int32,System.Int32)": {
+//!                     "Lines": {
+//!                         "10": 1,
+//!                         "11": 0
+//!                     },
+//!                     "Branches": [
+//!                         {
+//!                             "Line": 11,
+//!                             "Offset": 7,
+//!                             "EndOffset": 9,
+//!                             "Path": 0,
+//!                             "Ordinal": 0,
+//!                             "Hits": 1
+//!                         }
+//!                     ]
+//!                 }
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Each parsed file produces a single [`models::RawUpload`] and a
+//! [`models::SourceFile`] for each document. Every line in `Lines` becomes a
+//! [`models::CoverageSample`]. Lines with entries in `Branches` are branch
+//! samples with a [`models::BranchesData`] for each branch, identified by
+//! `"{Offset}:{Path}"` in [`models::BranchFormat::BlockAndBranch`] format.
+//! The first line of each method gets a [`models::MethodData`] record and, if
+//! it isn't a branch, is recorded as a method sample. Method keys are stored
+//! as the `signature` and, without the return type and parameters, as the
+//! `name` (`MyLibrary.Calculator::Add`).
+//!
+//! Compiler-generated methods (lambdas, async state machines) often report
+//! lines that overlap with their parent method. Lines are aggregated per
+//! document so each line is only counted once: hits are summed and branches
+//! are combined.
+//!
+//! Documents matching [`CoverletOptions::ignore_globs`] are skipped before
+//! any of their lines are aggregated.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+
+use super::common::{dotnet_method_name, IgnoreGlobs, IngestResult, Stopwatch};
+use crate::{
+    error::Result,
+    report::{models, Report, ReportBuilder},
+};
+
+// NOTE: these are `BTreeMap` only to have stable iteration order in tests
+type CoverletJson = BTreeMap<String, Module>;
+type Module = BTreeMap<String, Document>;
+type Document = BTreeMap<String, Class>;
+type Class = BTreeMap<String, Method>;
+
+#[derive(Debug, Clone, Default)]
+pub struct CoverletOptions {
+    /// Paths of documents to leave out of the report.
+    pub ignore_globs: IgnoreGlobs,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Method {
+    lines: BTreeMap<i64, i64>,
+    #[serde(default)]
+    branches: Vec<Branch>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Branch {
+    line: i64,
+    offset: i64,
+    path: i64,
+    hits: i64,
+}
+
+#[derive(Debug, Default)]
+struct LineTotals {
+    hits: i64,
+    // Keyed by (offset, path) to combine branches reported by multiple methods
+    branches: BTreeMap<(i64, i64), i64>,
+}
+
+#[derive(Debug)]
+struct MethodTotals {
+    signature: String,
+    line_no: i64,
+    hit_branches: i64,
+    total_branches: i64,
+}
+
+/// Parses a Coverlet JSON report into `builder` as a single
+/// [`models::RawUpload`]. Documents that appear in multiple modules are merged.
+pub fn parse_coverlet_json<B, R>(input: &[u8], builder: &mut B) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    parse_coverlet_json_with_options(input, builder, &CoverletOptions::default())
+}
+
+/// Like [`parse_coverlet_json`], but with non-default [`CoverletOptions`].
+pub fn parse_coverlet_json_with_options<B, R>(
+    input: &[u8],
+    builder: &mut B,
+    options: &CoverletOptions,
+) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    let stopwatch = Stopwatch::start();
+    let coverlet: CoverletJson = serde_json::from_slice(input)?;
+
+    let mut documents: BTreeMap<String, (BTreeMap<i64, LineTotals>, Vec<MethodTotals>)> =
+        BTreeMap::new();
+    let mut ignored = BTreeSet::new();
+    for (path, classes) in coverlet.into_values().flatten() {
+        if options.ignore_globs.is_ignored(&path) {
+            ignored.insert(path);
+            continue;
+        }
+        let (lines, methods) = documents.entry(path).or_default();
+        for (signature, method) in classes.into_values().flatten() {
+            let Some(&first_line) = method.lines.keys().next() else {
+                continue;
+            };
+
+            for (line_no, hits) in method.lines {
+                lines.entry(line_no).or_default().hits += hits;
+            }
+            for branch in &method.branches {
+                *lines
+                    .entry(branch.line)
+                    .or_default()
+                    .branches
+                    .entry((branch.offset, branch.path))
+                    .or_default() += branch.hits;
+            }
+
+            methods.push(MethodTotals {
+                signature,
+                line_no: first_line,
+                hit_branches: method.branches.iter().filter(|b| b.hits > 0).count() as i64,
+                total_branches: method.branches.len() as i64,
+            });
+        }
+    }
+
+    let raw_upload = builder.insert_raw_upload(Default::default())?;
+    let mut result = IngestResult {
+        files_ignored: ignored.len(),
+        ..Default::default()
+    };
+
+    for (path, (lines, methods)) in documents {
+        let file = builder.insert_file(&path)?;
+        result.files_touched += 1;
+        let method_lines: BTreeMap<i64, &MethodTotals> =
+            methods.iter().map(|m| (m.line_no, m)).collect();
+
+        let mut samples: Vec<models::CoverageSample> = lines
+            .iter()
+            .map(|(&line_no, totals)| {
+                let mut sample = models::CoverageSample {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    ..Default::default()
+                };
+                if totals.branches.is_empty() {
+                    sample.hits = Some(totals.hits);
+                    if method_lines.contains_key(&line_no) {
+                        sample.coverage_type = models::CoverageType::Method;
+                    }
+                } else {
+                    sample.coverage_type = models::CoverageType::Branch;
+                    sample.hit_branches =
+                        Some(totals.branches.values().filter(|h| **h > 0).count() as i64);
+                    sample.total_branches = Some(totals.branches.len() as i64);
+                }
+                sample
+            })
+            .collect();
+        builder.multi_insert_coverage_sample(samples.iter_mut().collect())?;
+        result.samples_inserted += samples.len();
+
+        let mut branches = vec![];
+        let mut method_data = vec![];
+        for (sample, totals) in samples.iter().zip(lines.values()) {
+            for (&(offset, path), &hits) in &totals.branches {
+                branches.push(models::BranchesData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    hits,
+                    branch_format: models::BranchFormat::BlockAndBranch,
+                    branch: format!("{offset}:{path}"),
+                    ..Default::default()
+                });
+            }
+            if let Some(method) = method_lines.get(&sample.line_no) {
+                method_data.push(models::MethodData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    line_no: Some(method.line_no),
+                    hit_branches: Some(method.hit_branches),
+                    total_branches: Some(method.total_branches),
+                    name: Some(dotnet_method_name(&method.signature).to_string()),
+                    signature: Some(method.signature.clone()),
+                    ..Default::default()
+                });
+            }
+        }
+        builder.multi_insert_branches_data(branches.iter_mut().collect())?;
+        builder.multi_insert_method_data(method_data.iter_mut().collect())?;
+    }
+
+    result.raw_upload = Some(raw_upload);
+    result.duration = stopwatch.elapsed();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_report::TestReportBuilder;
+
+    #[test]
+    fn test_parse_coverlet_json() {
+        let input = br#"{
+            "MyLibrary.dll": {
+                "/src/Calculator.cs": {
+                    "MyLibrary.Calculator": {
+                        "System.Int32 MyLibrary.Calculator::Add(System.Int32,System.Int32)": {
+                            "Lines": {"10": 1, "11": 1, "12": 0},
+                            "Branches": [
+                                {"Line": 11, "Offset": 7, "EndOffset": 9, "Path": 0, "Ordinal": 0, "Hits": 1},
+                                {"Line": 11, "Offset": 7, "EndOffset": 12, "Path": 1, "Ordinal": 1, "Hits": 0}
+                            ]
+                        },
+                        "System.Void MyLibrary.Calculator/<>c::<Add>b__0_0()": {
+                            "Lines": {"12": 2},
+                            "Branches": []
+                        }
+                    }
+                }
+            }
+        }"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let result = parse_coverlet_json(input, &mut report_builder).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.samples_inserted), (1, 3));
+        let raw_upload = result.raw_upload.unwrap();
+
+        let file = models::SourceFile::new("/src/Calculator.cs");
+        assert_eq!(report.files, std::slice::from_ref(&file));
+        assert_eq!(report.uploads.len(), 1);
+
+        let sample =
+            |line_no, coverage_type, hits, hit_branches, total_branches| models::CoverageSample {
+                raw_upload_id: raw_upload.id,
+                source_file_id: file.id,
+                line_no,
+                coverage_type,
+                hits,
+                hit_branches,
+                total_branches,
+                ..Default::default()
+            };
+        let samples: Vec<_> = report
+            .samples
+            .iter()
+            .map(|s| models::CoverageSample {
+                local_sample_id: 0,
+                ..s.clone()
+            })
+            .collect();
+        assert_eq!(
+            samples,
+            &[
+                sample(10, models::CoverageType::Method, Some(1), None, None),
+                sample(11, models::CoverageType::Branch, None, Some(1), Some(2)),
+                // Overlapping lines from the lambda are summed, and it gets a method record
+                sample(12, models::CoverageType::Method, Some(2), None, None),
+            ]
+        );
+
+        let branches: Vec<_> = report
+            .branches
+            .iter()
+            .map(|b| (b.local_sample_id, b.branch.as_str(), b.hits))
+            .collect();
+        assert_eq!(branches, &[(1, "7:0", 1), (1, "7:1", 0)]);
+
+        let methods: Vec<_> = report
+            .methods
+            .iter()
+            .map(|m| {
+                (
+                    m.local_sample_id,
+                    m.hit_branches,
+                    m.total_branches,
+                    m.name.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            methods,
+            &[
+                (0, Some(1), Some(2), Some("MyLibrary.Calculator::Add")),
+                (
+                    2,
+                    Some(0),
+                    Some(0),
+                    Some("MyLibrary.Calculator/<>c::<Add>b__0_0")
+                ),
+            ]
+        );
+        assert_eq!(
+            report.methods[0].signature.as_deref(),
+            Some("System.Int32 MyLibrary.Calculator::Add(System.Int32,System.Int32)")
+        );
+    }
+
+    #[test]
+    fn test_parse_coverlet_json_invalid() {
+        let mut report_builder = TestReportBuilder::default();
+        parse_coverlet_json(br#"{"MyLibrary.dll": []}"#, &mut report_builder).unwrap_err();
+
+        let mut report_builder = TestReportBuilder::default();
+        parse_coverlet_json(
+            br#"{"m": {"f": {"c": {"m": {"Lines": {"x": 1}}}}}}"#,
+            &mut report_builder,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_coverlet_json_ignore_globs() {
+        let input = br#"{
+            "MyLibrary.dll": {
+                "/src/Calculator.cs": {"C": {"M": {"Lines": {"10": 1}}}},
+                "/src/Generated/Api.cs": {"C": {"M": {"Lines": {"10": 1}}}}
+            },
+            "MyLibrary.Tests.dll": {
+                "/src/Generated/Api.cs": {"C": {"M": {"Lines": {"11": 1}}}}
+            }
+        }"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let options = CoverletOptions {
+            ignore_globs: IgnoreGlobs(vec!["/src/Generated/**".to_string()]),
+        };
+        let result =
+            parse_coverlet_json_with_options(input, &mut report_builder, &options).unwrap();
+        let report = report_builder.build().unwrap();
+
+        // A document in several modules is only ignored once
+        assert_eq!((result.files_touched, result.files_ignored), (1, 1));
+        assert_eq!(
+            report.files,
+            &[models::SourceFile::new("/src/Calculator.cs")]
+        );
+        assert_eq!(report.samples.len(), 1);
+    }
+}
@@ -0,0 +1,556 @@
+//! Parses the text files written by [gcov](https://gcc.gnu.org/onlinedocs/gcc/Gcov.html),
+//! GCC's coverage tool, which C and C++ projects often upload as-is.
+//!
+//! Each `.gcov` file annotates one source file with an execution count per
+//! line. With `-b`, lines are followed by a summary of their branches and
+//! calls, and functions are preceded by a summary of their own:
+//! ```text
+//!         -:    0:Source:src/main.c
+//!         -:    0:Graph:main.gcno
+//! function main called 1 returned 100% blocks executed 80%
+//!         1:    3:int main(int argc, char **argv) {
+//!         1:    4:    if (argc > 1)
+//! branch  0 taken 0% (fallthrough)
+//! branch  1 taken 100%
+//!     #####:    5:        usage();
+//! call    0 never executed
+//!         1:    6:    return 0;
+//!         -:    7:}
+//! ```
+//!
+//! Lines with a count of `-` aren't executable and are skipped. `#####` and
+//! `=====` mean the line was never executed, and a trailing `*` (some of the
+//! line's blocks were never executed) is ignored. Counts abbreviated by
+//! `--human-readable`, like `1.2k`, are expanded.
+//!
+//! Lines with branches are branch samples with a [`models::BranchesData`] for
+//! each branch, identified by its number in [`models::BranchFormat::Condition`]
+//! format. gcov reports how often a branch was taken as a percentage of its
+//! line's count unless run with `-c`, so a branch's hits are that percentage
+//! of the line's hits, rounded, but at least 1 if it was taken at all. The
+//! first line of each summarized function gets a [`models::MethodData`] record
+//! and, if it isn't a branch, is recorded as a method sample.
+//!
+//! Output for several source files can be concatenated, as `gcov -t` does, and
+//! a source file that appears more than once (like a header included by
+//! several translation units) has its counts summed. The per-instantiation
+//! listings gcov writes for templates and inline functions repeat lines that
+//! were already counted and are skipped. Source files matching
+//! [`GcovOptions::ignore_globs`] are left out.
+
+use std::collections::BTreeMap;
+
+use winnow::error::{AddContext, ContextError, StrContext};
+
+use super::common::{IgnoreGlobs, IngestResult, Stopwatch};
+use crate::{
+    error::{CodecovError, Result},
+    report::{models, Report, ReportBuilder},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct GcovOptions {
+    /// Paths of source files to leave out of the report.
+    pub ignore_globs: IgnoreGlobs,
+}
+
+#[derive(Debug, Default)]
+struct LineTotals {
+    hits: i64,
+    // Keyed by branch number to combine branches from repeated source files
+    branches: BTreeMap<i64, i64>,
+}
+
+#[derive(Debug, Default)]
+struct FileTotals {
+    lines: BTreeMap<i64, LineTotals>,
+    // Function names keyed by their first line
+    functions: BTreeMap<i64, String>,
+}
+
+/// How often a branch was taken, as written by gcov.
+#[derive(Debug, PartialEq)]
+enum Taken {
+    Count(i64),
+    Percent(i64),
+}
+
+/// Parses an execution count like `12`, `3*`, `#####` or `1.2k`.
+fn parse_count(count: &str) -> Option<i64> {
+    let count = count.strip_suffix('*').unwrap_or(count);
+    if !count.is_empty() && (count.bytes().all(|b| b == b'#') || count.bytes().all(|b| b == b'=')) {
+        return Some(0);
+    }
+    if let Ok(count) = count.parse() {
+        return Some(count);
+    }
+
+    let (number, multiplier) = match count.char_indices().last()? {
+        (i, 'k') => (&count[..i], 1e3),
+        (i, 'M') => (&count[..i], 1e6),
+        (i, 'G') => (&count[..i], 1e9),
+        (i, 'T') => (&count[..i], 1e12),
+        (i, 'P') => (&count[..i], 1e15),
+        _ => return None,
+    };
+    let number: f64 = number.parse().ok()?;
+    (number >= 0.0).then(|| (number * multiplier).round() as i64)
+}
+
+/// Splits a line record like `    1:    4:    if (argc > 1)` into its count,
+/// line number and source text.
+fn split_line_record(line: &str) -> Option<(&str, i64, &str)> {
+    let (count, rest) = line.split_once(':')?;
+    let (line_no, source) = rest.split_once(':').unwrap_or((rest, ""));
+    Some((count.trim(), line_no.trim().parse().ok()?, source))
+}
+
+/// Parses what follows `branch N ` in a branch summary.
+fn parse_taken(rest: &str) -> Option<Taken> {
+    if rest.starts_with("never executed") {
+        return Some(Taken::Count(0));
+    }
+    let taken = rest.strip_prefix("taken ")?.split_whitespace().next()?;
+    match taken.strip_suffix('%') {
+        Some(percent) => Some(Taken::Percent(percent.parse::<f64>().ok()?.round() as i64)),
+        None => parse_count(taken).map(Taken::Count),
+    }
+}
+
+/// Parses the function name from a summary like `function main called 1
+/// returned 100% blocks executed 80%`. Demangled names may contain spaces.
+fn parse_function_name(line: &str) -> Option<&str> {
+    let (name, _) = line.strip_prefix("function ")?.rsplit_once(" called ")?;
+    Some(name)
+}
+
+fn gcov_error(input: &str, remaining: &str, label: &'static str) -> CodecovError {
+    CodecovError::parser_error(
+        input,
+        remaining,
+        ContextError::new().add_context(&remaining, StrContext::Label(label)),
+    )
+}
+
+/// Sums the counts for each source file in gcov's text output.
+fn parse_files(input: &str) -> Result<BTreeMap<String, FileTotals>> {
+    let mut files: BTreeMap<String, FileTotals> = BTreeMap::new();
+    let mut current: Option<&mut FileTotals> = None;
+    // The last line recorded and its count, which branch summaries refer to
+    let mut last_line: Option<(i64, i64)> = None;
+    // A function summary waiting for the function's first line
+    let mut pending_function: Option<String> = None;
+    let mut after_separator = false;
+    let mut in_instantiation = false;
+
+    let mut offset = 0;
+    for raw_line in input.split_inclusive('\n') {
+        let remaining = &input[offset..];
+        offset += raw_line.len();
+        let line = raw_line.trim_end_matches(['\n', '\r']);
+        let separator = line.starts_with("------------------");
+        let was_after_separator = std::mem::replace(&mut after_separator, separator);
+        if separator {
+            in_instantiation = false;
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(branch) = line.strip_prefix("branch ") {
+            if in_instantiation {
+                continue;
+            }
+            let (number, rest) = branch
+                .trim_start()
+                .split_once(' ')
+                .ok_or_else(|| gcov_error(input, remaining, "branch summary"))?;
+            let number: i64 = number
+                .parse()
+                .map_err(|_| gcov_error(input, remaining, "branch number"))?;
+            let taken =
+                parse_taken(rest).ok_or_else(|| gcov_error(input, remaining, "branch summary"))?;
+            let (Some(file), Some((line_no, line_hits))) = (current.as_deref_mut(), last_line)
+            else {
+                return Err(gcov_error(input, remaining, "line before branch summary"));
+            };
+            let hits = match taken {
+                Taken::Count(hits) => hits,
+                Taken::Percent(0) => 0,
+                Taken::Percent(percent) => {
+                    ((line_hits as f64 * percent as f64 / 100.0).round() as i64).max(1)
+                }
+            };
+            *file
+                .lines
+                .entry(line_no)
+                .or_default()
+                .branches
+                .entry(number)
+                .or_default() += hits;
+            continue;
+        }
+        if line.starts_with("call ") || line.starts_with("unconditional ") {
+            continue;
+        }
+        if line.starts_with("function ") {
+            if !in_instantiation {
+                let name = parse_function_name(line)
+                    .ok_or_else(|| gcov_error(input, remaining, "function summary"))?;
+                pending_function.get_or_insert_with(|| name.to_string());
+            }
+            continue;
+        }
+
+        let Some((count, line_no, source)) = split_line_record(line) else {
+            // An instantiation's listing starts with its name, like `_Z3maxIiET_S0_S0_:`
+            if was_after_separator && line.ends_with(':') {
+                in_instantiation = true;
+                continue;
+            }
+            return Err(gcov_error(input, remaining, "gcov line"));
+        };
+        if in_instantiation {
+            continue;
+        }
+
+        if line_no == 0 {
+            if let Some(path) = source.strip_prefix("Source:") {
+                current = Some(files.entry(path.to_string()).or_default());
+                last_line = None;
+                pending_function = None;
+            }
+            continue;
+        }
+        let Some(file) = current.as_deref_mut() else {
+            return Err(gcov_error(input, remaining, "Source header"));
+        };
+        if count == "-" {
+            last_line = None;
+            continue;
+        }
+        let hits = parse_count(count).ok_or_else(|| gcov_error(input, remaining, "count"))?;
+        file.lines.entry(line_no).or_default().hits += hits;
+        last_line = Some((line_no, hits));
+        if let Some(name) = pending_function.take() {
+            file.functions.entry(line_no).or_insert(name);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Parses gcov's text output into `builder` as a single
+/// [`models::RawUpload`].
+pub fn parse_gcov<B, R>(input: &[u8], builder: &mut B) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    parse_gcov_with_options(input, builder, &GcovOptions::default())
+}
+
+/// Like [`parse_gcov`], but with non-default [`GcovOptions`].
+pub fn parse_gcov_with_options<B, R>(
+    input: &[u8],
+    builder: &mut B,
+    options: &GcovOptions,
+) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    let stopwatch = Stopwatch::start();
+    let input = String::from_utf8_lossy(input);
+    let mut files = parse_files(&input)?;
+    let files_before = files.len();
+    files.retain(|path, _| !options.ignore_globs.is_ignored(path));
+
+    let raw_upload = builder.insert_raw_upload(Default::default())?;
+    let mut result = IngestResult {
+        files_ignored: files_before - files.len(),
+        ..Default::default()
+    };
+
+    for (path, totals) in files {
+        let file = builder.insert_file(&path)?;
+        result.files_touched += 1;
+
+        let mut samples: Vec<models::CoverageSample> = totals
+            .lines
+            .iter()
+            .map(|(&line_no, line)| {
+                let mut sample = models::CoverageSample {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    ..Default::default()
+                };
+                if line.branches.is_empty() {
+                    sample.hits = Some(line.hits);
+                    if totals.functions.contains_key(&line_no) {
+                        sample.coverage_type = models::CoverageType::Method;
+                    }
+                } else {
+                    sample.coverage_type = models::CoverageType::Branch;
+                    sample.hit_branches =
+                        Some(line.branches.values().filter(|h| **h > 0).count() as i64);
+                    sample.total_branches = Some(line.branches.len() as i64);
+                }
+                sample
+            })
+            .collect();
+        builder.multi_insert_coverage_sample(samples.iter_mut().collect())?;
+        result.samples_inserted += samples.len();
+
+        let mut branches = vec![];
+        let mut methods = vec![];
+        for (sample, line) in samples.iter().zip(totals.lines.values()) {
+            for (number, &hits) in &line.branches {
+                branches.push(models::BranchesData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    hits,
+                    branch_format: models::BranchFormat::Condition,
+                    branch: number.to_string(),
+                    ..Default::default()
+                });
+            }
+            if let Some(name) = totals.functions.get(&sample.line_no) {
+                methods.push(models::MethodData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    line_no: Some(sample.line_no),
+                    name: Some(name.clone()),
+                    ..Default::default()
+                });
+            }
+        }
+        builder.multi_insert_branches_data(branches.iter_mut().collect())?;
+        builder.multi_insert_method_data(methods.iter_mut().collect())?;
+    }
+
+    result.raw_upload = Some(raw_upload);
+    result.duration = stopwatch.elapsed();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_report::TestReportBuilder;
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("12"), Some(12));
+        assert_eq!(parse_count("3*"), Some(3));
+        assert_eq!(parse_count("#####"), Some(0));
+        assert_eq!(parse_count("====="), Some(0));
+        assert_eq!(parse_count("1.2k"), Some(1200));
+        assert_eq!(parse_count("5M*"), Some(5_000_000));
+        assert_eq!(parse_count("-"), None);
+        assert_eq!(parse_count("*"), None);
+        assert_eq!(parse_count("12x"), None);
+        assert_eq!(parse_count("-1k"), None);
+    }
+
+    #[test]
+    fn test_parse_taken() {
+        assert_eq!(
+            parse_taken("taken 50% (fallthrough)"),
+            Some(Taken::Percent(50))
+        );
+        assert_eq!(parse_taken("taken 0% (throw)"), Some(Taken::Percent(0)));
+        assert_eq!(parse_taken("taken 7"), Some(Taken::Count(7)));
+        assert_eq!(parse_taken("never executed"), Some(Taken::Count(0)));
+        assert_eq!(parse_taken("returned 100%"), None);
+    }
+
+    #[test]
+    fn test_parse_gcov() {
+        let input = b"        -:    0:Source:src/main.c
+        -:    0:Graph:main.gcno
+        -:    0:Data:main.gcda
+        -:    0:Runs:1
+        -:    1:#include <stdio.h>
+        -:    2:
+function main called 4 returned 100% blocks executed 80%
+        4:    3:int main(int argc, char **argv) {
+        4:    4:    if (argc > 1)
+branch  0 taken 25% (fallthrough)
+branch  1 taken 75%
+    #####:    5:        usage();
+call    0 never executed
+       4*:    6:    return argc > 2 ? 1 : 0;
+branch  0 taken 0%
+branch  1 never executed
+        -:    7:}
+";
+
+        let mut report_builder = TestReportBuilder::default();
+        let result = parse_gcov(input, &mut report_builder).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.samples_inserted), (1, 4));
+        let raw_upload = result.raw_upload.unwrap();
+
+        let file = models::SourceFile::new("src/main.c");
+        assert_eq!(report.files, std::slice::from_ref(&file));
+        assert_eq!(report.uploads.len(), 1);
+
+        let sample =
+            |line_no, coverage_type, hits, hit_branches, total_branches| models::CoverageSample {
+                raw_upload_id: raw_upload.id,
+                source_file_id: file.id,
+                line_no,
+                coverage_type,
+                hits,
+                hit_branches,
+                total_branches,
+                ..Default::default()
+            };
+        let samples: Vec<_> = report
+            .samples
+            .iter()
+            .map(|s| models::CoverageSample {
+                local_sample_id: 0,
+                ..s.clone()
+            })
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                sample(3, models::CoverageType::Method, Some(4), None, None),
+                sample(4, models::CoverageType::Branch, None, Some(2), Some(2)),
+                sample(5, models::CoverageType::Line, Some(0), None, None),
+                sample(6, models::CoverageType::Branch, None, Some(0), Some(2)),
+            ]
+        );
+
+        let branches: Vec<_> = report
+            .branches
+            .iter()
+            .map(|b| (b.local_sample_id, b.branch.as_str(), b.hits))
+            .collect();
+        let line_4 = report.samples[1].local_sample_id;
+        let line_6 = report.samples[3].local_sample_id;
+        assert_eq!(
+            branches,
+            vec![
+                (line_4, "0", 1),
+                (line_4, "1", 3),
+                (line_6, "0", 0),
+                (line_6, "1", 0)
+            ]
+        );
+        assert!(report
+            .branches
+            .iter()
+            .all(|b| b.branch_format == models::BranchFormat::Condition));
+
+        assert_eq!(report.methods.len(), 1);
+        assert_eq!(
+            report.methods[0].local_sample_id,
+            report.samples[0].local_sample_id
+        );
+        assert_eq!(report.methods[0].line_no, Some(3));
+        assert_eq!(report.methods[0].name.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn test_parse_gcov_ignore_globs() {
+        let input = b"        -:    0:Source:src/main.c
+        1:    1:int main() { return 0; }
+        -:    0:Source:/usr/include/stdio.h
+        1:    1:extern int printf(const char *, ...);
+";
+
+        let mut report_builder = TestReportBuilder::default();
+        let options = GcovOptions {
+            ignore_globs: IgnoreGlobs(vec!["/usr/**".to_string()]),
+        };
+        let result = parse_gcov_with_options(input, &mut report_builder, &options).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.files_ignored), (1, 1));
+        assert_eq!(report.files, &[models::SourceFile::new("src/main.c")]);
+        assert_eq!(report.samples.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_gcov_concatenated_and_instantiations() {
+        let input = b"        -:    0:Source:include/max.h
+        -:    1:template <typename T>
+        3:    2:T max(T a, T b) { return a > b ? a : b; }
+branch  0 taken 2
+branch  1 taken 1
+------------------
+_Z3maxIiET_S0_S0_:
+        2:    2:T max(T a, T b) { return a > b ? a : b; }
+branch  0 taken 2
+branch  1 taken 0
+------------------
+double max<double>(double, double):
+        1:    2:T max(T a, T b) { return a > b ? a : b; }
+branch  0 taken 0
+branch  1 taken 1
+------------------
+        -:    3:
+        -:    0:Source:src/b.cc
+        1:    1:int b() { return max(1, 2); }
+        -:    0:Source:include/max.h
+        2:    2:T max(T a, T b) { return a > b ? a : b; }
+branch  0 taken 0
+branch  1 taken 2
+";
+
+        let mut report_builder = TestReportBuilder::default();
+        parse_gcov(input, &mut report_builder).unwrap();
+        let report = report_builder.build().unwrap();
+
+        assert_eq!(
+            report.files,
+            vec![
+                models::SourceFile::new("include/max.h"),
+                models::SourceFile::new("src/b.cc"),
+            ]
+        );
+        assert_eq!(report.samples.len(), 2);
+        assert_eq!(report.samples[0].line_no, 2);
+        assert_eq!(report.samples[0].hit_branches, Some(2));
+        assert_eq!(report.samples[0].total_branches, Some(2));
+        let hits: Vec<_> = report.branches.iter().map(|b| b.hits).collect();
+        assert_eq!(hits, vec![2, 3]);
+        assert_eq!(report.samples[1].hits, Some(1));
+    }
+
+    #[test]
+    fn test_parse_gcov_errors() {
+        let mut report_builder = TestReportBuilder::default();
+        let error = parse_gcov(b"        1:    3:int main() {\n", &mut report_builder).unwrap_err();
+        assert!(matches!(
+            error,
+            CodecovError::ParserError {
+                line: 1,
+                column: 1,
+                ..
+            }
+        ));
+
+        let input = b"        -:    0:Source:a.c\n        1:    1:int x;\n     oops:    2:int y;\n";
+        let error = parse_gcov(input, &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::ParserError { line: 3, .. }));
+
+        let input = b"        -:    0:Source:a.c\nbranch  0 taken 50%\n";
+        let error = parse_gcov(input, &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::ParserError { line: 2, .. }));
+
+        let input = b"        -:    0:Source:a.c\nnot gcov output\n";
+        let error = parse_gcov(input, &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::ParserError { line: 2, .. }));
+
+        // Nothing is inserted for a report that fails to parse
+        assert!(report_builder.build().unwrap().uploads.is_empty());
+    }
+}
@@ -36,7 +36,10 @@ pub fn parse_num<S: StrStream>(buf: &mut S) -> PResult<JsonNumber> {
 
 /// Parses a single character (which may be escaped), returning a `char`.
 ///
-/// ```
+/// This function is internal to the crate, so the example isn't run as a
+/// doctest.
+///
+/// ```ignore
 /// # use codecov_rs::parsers::json::parse_char;
 /// # use winnow::Parser;
 /// assert_eq!(parse_char.parse_peek("a"), Ok(("", 'a')));
@@ -141,7 +144,7 @@ pub fn json_value<S: StrStream>(buf: &mut S) -> PResult<JsonVal> {
 /// passed-in value. To get the corresponding value, parse with something like:
 ///
 /// ```
-/// # use codecov_rs::parsers::json::{specific_key, json_value, JsonVal};
+/// # use codecov_rs::parsers::prelude::{specific_key, json_value, JsonVal};
 /// # use winnow::combinator::preceded;
 /// # use winnow::Parser;
 /// let expected = Ok(("", JsonVal::Array(vec![])));
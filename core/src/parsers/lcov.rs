@@ -0,0 +1,483 @@
+//! Parses the `.info` tracefiles written by [lcov](https://github.com/linux-test-project/lcov)
+//! and the many tools that imitate it (Jest/Istanbul, c8, Dart's
+//! `format_coverage`, Elm's `elm-coverage`, `cargo llvm-cov`, ...).
+//!
+//! A tracefile is a series of records, one per source file:
+//! ```text
+//! TN:
+//! SF:src/lib.rs
+//! FN:3,parse
+//! FNDA:2,parse
+//! DA:3,2
+//! DA:4,2
+//! BRDA:4,0,0,1
+//! BRDA:4,0,1,-
+//! DA:5,0
+//! end_of_record
+//! ```
+//!
+//! Every `DA` line becomes a [`models::CoverageSample`]. Lines with `BRDA`
+//! entries are branch samples with a [`models::BranchesData`] for each
+//! branch, identified by `"{block}:{branch}"` in
+//! [`models::BranchFormat::BlockAndBranch`] format; a `-` for the number of
+//! times a branch was taken means its block never ran. Each `FN` gets a
+//! [`models::MethodData`] on its line, which is recorded as a method sample if
+//! it isn't a branch, with the hits from its `FNDA`. Summary records (`LF`,
+//! `LH`, `BRF`, ...), test names and anything else we don't recognize are
+//! skipped.
+//!
+//! Tools outside of lcov itself often write slightly-off tracefiles, which
+//! [`LcovOptions`] decides how much to tolerate. By default everything below
+//! is accepted:
+//! - Records without `end_of_record`, which end at the next `SF` or at the end
+//!   of the input instead.
+//! - Several records for the same file, which are merged by summing their
+//!   counts.
+//! - Function names containing commas, like demangled C++ signatures. These are
+//!   ambiguous with lcov 2's `FN:<start>,<end>,<name>`, so the second field is
+//!   only taken to be an end line if it's a number.
+//!
+//! Files matching [`LcovOptions::ignore_globs`] are left out, whether or not
+//! the options are strict.
+
+use std::collections::BTreeMap;
+
+use winnow::error::{AddContext, ContextError, StrContext};
+
+use super::common::{IgnoreGlobs, IngestResult, Stopwatch};
+use crate::{
+    error::{CodecovError, Result},
+    report::{models, Report, ReportBuilder},
+};
+
+#[derive(Debug, Clone)]
+pub struct LcovOptions {
+    /// Whether a record can end without `end_of_record`.
+    pub allow_missing_end_of_record: bool,
+
+    /// Whether a file can have more than one record.
+    pub allow_duplicate_files: bool,
+
+    /// Whether function names in `FN` and `FNDA` can contain commas.
+    pub allow_commas_in_function_names: bool,
+
+    /// Paths of files to leave out of the report.
+    pub ignore_globs: IgnoreGlobs,
+}
+
+impl Default for LcovOptions {
+    fn default() -> Self {
+        Self {
+            allow_missing_end_of_record: true,
+            allow_duplicate_files: true,
+            allow_commas_in_function_names: true,
+            ignore_globs: IgnoreGlobs::default(),
+        }
+    }
+}
+
+impl LcovOptions {
+    /// Options that only accept tracefiles the way lcov writes them.
+    pub fn strict() -> Self {
+        Self {
+            allow_missing_end_of_record: false,
+            allow_duplicate_files: false,
+            allow_commas_in_function_names: false,
+            ignore_globs: IgnoreGlobs::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LineTotals {
+    hits: i64,
+    // Keyed by (block, branch) to combine duplicate records
+    branches: BTreeMap<(String, String), i64>,
+}
+
+#[derive(Debug, Default)]
+struct FileTotals {
+    lines: BTreeMap<i64, LineTotals>,
+    // The first line of each function, keyed by name
+    functions: BTreeMap<String, i64>,
+    function_hits: BTreeMap<String, i64>,
+}
+
+fn lcov_error(input: &str, remaining: &str, label: &'static str) -> CodecovError {
+    CodecovError::parser_error(
+        input,
+        remaining,
+        ContextError::new().add_context(&remaining, StrContext::Label(label)),
+    )
+}
+
+/// Splits `FN` or `FNDA` data into its leading number fields and the function
+/// name, which may contain commas if `allow_commas` is set.
+fn split_function(data: &str, numbers: usize, allow_commas: bool) -> Option<(Vec<i64>, &str)> {
+    let mut fields = data.splitn(numbers + 1, ',');
+    let mut parsed = vec![];
+    for _ in 0..numbers {
+        parsed.push(fields.next()?.trim().parse().ok()?);
+    }
+    let name = fields.next()?;
+    (allow_commas || !name.contains(',')).then_some((parsed, name))
+}
+
+/// Parses `FN` data, which is `<line>,<name>` or, since lcov 2,
+/// `<line>,<end line>,<name>`.
+fn parse_function(data: &str, allow_commas: bool) -> Option<(i64, &str)> {
+    if let Some((numbers, name)) = split_function(data, 2, allow_commas) {
+        return Some((numbers[0], name));
+    }
+    let (numbers, name) = split_function(data, 1, allow_commas)?;
+    Some((numbers[0], name))
+}
+
+/// Sums the counts for each source file in a tracefile.
+fn parse_files(input: &str, options: &LcovOptions) -> Result<BTreeMap<String, FileTotals>> {
+    let mut files: BTreeMap<String, FileTotals> = BTreeMap::new();
+    let mut current: Option<String> = None;
+
+    let mut offset = 0;
+    for raw_line in input.split_inclusive('\n') {
+        let remaining = &input[offset..];
+        offset += raw_line.len();
+        let line = raw_line
+            .trim_end_matches(['\n', '\r'])
+            .trim_start_matches('\u{feff}');
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.trim() == "end_of_record" {
+            current = None;
+            continue;
+        }
+        let Some((key, data)) = line.split_once(':') else {
+            return Err(lcov_error(input, remaining, "lcov record"));
+        };
+
+        if key == "SF" {
+            if current.is_some() && !options.allow_missing_end_of_record {
+                return Err(lcov_error(input, remaining, "end_of_record"));
+            }
+            if files.contains_key(data) && !options.allow_duplicate_files {
+                return Err(lcov_error(input, remaining, "unique SF"));
+            }
+            files.entry(data.to_string()).or_default();
+            current = Some(data.to_string());
+            continue;
+        }
+        if !matches!(key, "DA" | "BRDA" | "FN" | "FNDA") {
+            continue;
+        }
+        let Some(file) = current.as_ref().and_then(|path| files.get_mut(path)) else {
+            return Err(lcov_error(input, remaining, "SF"));
+        };
+
+        match key {
+            "DA" => {
+                let mut fields = data.split(',');
+                let line_no = fields.next().and_then(|f| f.trim().parse().ok());
+                let hits: Option<i64> = fields.next().and_then(|f| f.trim().parse().ok());
+                let (Some(line_no), Some(hits)) = (line_no, hits) else {
+                    return Err(lcov_error(input, remaining, "DA"));
+                };
+                file.lines.entry(line_no).or_default().hits += hits;
+            }
+            "BRDA" => {
+                let fields: Vec<&str> = data.split(',').collect();
+                let [line_no, block, branch @ .., taken] = fields.as_slice() else {
+                    return Err(lcov_error(input, remaining, "BRDA"));
+                };
+                let line_no: Option<i64> = line_no.trim().parse().ok();
+                let taken = match taken.trim() {
+                    "-" => Some(0),
+                    taken => taken.parse().ok(),
+                };
+                let (Some(line_no), Some(taken), false) = (line_no, taken, branch.is_empty())
+                else {
+                    return Err(lcov_error(input, remaining, "BRDA"));
+                };
+                let key = (block.trim().to_string(), branch.join(","));
+                *file
+                    .lines
+                    .entry(line_no)
+                    .or_default()
+                    .branches
+                    .entry(key)
+                    .or_default() += taken;
+            }
+            "FN" => {
+                let Some((line_no, name)) =
+                    parse_function(data, options.allow_commas_in_function_names)
+                else {
+                    return Err(lcov_error(input, remaining, "FN"));
+                };
+                file.functions.entry(name.to_string()).or_insert(line_no);
+            }
+            "FNDA" => {
+                let Some((numbers, name)) =
+                    split_function(data, 1, options.allow_commas_in_function_names)
+                else {
+                    return Err(lcov_error(input, remaining, "FNDA"));
+                };
+                *file.function_hits.entry(name.to_string()).or_default() += numbers[0];
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if current.is_some() && !options.allow_missing_end_of_record {
+        return Err(lcov_error(input, "", "end_of_record"));
+    }
+    Ok(files)
+}
+
+/// Parses an lcov tracefile into `builder` as a single [`models::RawUpload`].
+pub fn parse_lcov<B, R>(
+    input: &[u8],
+    builder: &mut B,
+    options: &LcovOptions,
+) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    let stopwatch = Stopwatch::start();
+    let input = String::from_utf8_lossy(input);
+    let mut files = parse_files(&input, options)?;
+    let files_before = files.len();
+    files.retain(|path, _| !options.ignore_globs.is_ignored(path));
+
+    let raw_upload = builder.insert_raw_upload(Default::default())?;
+    let mut result = IngestResult {
+        files_ignored: files_before - files.len(),
+        ..Default::default()
+    };
+
+    for (path, mut totals) in files {
+        let file = builder.insert_file(&path)?;
+        result.files_touched += 1;
+
+        // Functions are recorded on their first line even if it has no `DA`
+        let mut function_lines: BTreeMap<i64, &str> = BTreeMap::new();
+        for (name, &line_no) in &totals.functions {
+            function_lines.entry(line_no).or_insert(name);
+            totals.lines.entry(line_no).or_insert_with(|| LineTotals {
+                hits: totals.function_hits.get(name).copied().unwrap_or(0),
+                ..Default::default()
+            });
+        }
+
+        let mut samples: Vec<models::CoverageSample> = totals
+            .lines
+            .iter()
+            .map(|(&line_no, line)| {
+                let mut sample = models::CoverageSample {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    ..Default::default()
+                };
+                if line.branches.is_empty() {
+                    sample.hits = Some(line.hits);
+                    if function_lines.contains_key(&line_no) {
+                        sample.coverage_type = models::CoverageType::Method;
+                    }
+                } else {
+                    sample.coverage_type = models::CoverageType::Branch;
+                    sample.hit_branches =
+                        Some(line.branches.values().filter(|h| **h > 0).count() as i64);
+                    sample.total_branches = Some(line.branches.len() as i64);
+                }
+                sample
+            })
+            .collect();
+        builder.multi_insert_coverage_sample(samples.iter_mut().collect())?;
+        result.samples_inserted += samples.len();
+
+        let mut branches = vec![];
+        let mut methods = vec![];
+        for (sample, line) in samples.iter().zip(totals.lines.values()) {
+            for ((block, branch), &hits) in &line.branches {
+                branches.push(models::BranchesData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    hits,
+                    branch_format: models::BranchFormat::BlockAndBranch,
+                    branch: format!("{block}:{branch}"),
+                    ..Default::default()
+                });
+            }
+            if let Some(name) = function_lines.get(&sample.line_no) {
+                methods.push(models::MethodData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    line_no: Some(sample.line_no),
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                });
+            }
+        }
+        builder.multi_insert_branches_data(branches.iter_mut().collect())?;
+        builder.multi_insert_method_data(methods.iter_mut().collect())?;
+    }
+
+    result.raw_upload = Some(raw_upload);
+    result.duration = stopwatch.elapsed();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_report::TestReportBuilder;
+
+    #[test]
+    fn test_parse_function() {
+        assert_eq!(parse_function("3,parse", false), Some((3, "parse")));
+        assert_eq!(parse_function("3,9,parse", false), Some((3, "parse")));
+        assert_eq!(
+            parse_function("3,max(int, int)", true),
+            Some((3, "max(int, int)"))
+        );
+        assert_eq!(
+            parse_function("3,9,max(int, int)", true),
+            Some((3, "max(int, int)"))
+        );
+        assert_eq!(parse_function("3,max(int, int)", false), None);
+        assert_eq!(parse_function("x,parse", true), None);
+        assert_eq!(parse_function("3", true), None);
+    }
+
+    #[test]
+    fn test_parse_lcov() {
+        let input = b"TN:
+SF:src/lib.rs
+FN:3,parse
+FN:12,unused
+FNDA:2,parse
+FNDA:0,unused
+FNF:2
+FNH:1
+DA:3,2
+DA:4,2
+BRDA:4,0,0,1
+BRDA:4,0,1,-
+DA:5,0
+LF:3
+LH:2
+end_of_record
+";
+
+        let mut report_builder = TestReportBuilder::default();
+        let result = parse_lcov(input, &mut report_builder, &LcovOptions::strict()).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.samples_inserted), (1, 4));
+        let raw_upload = result.raw_upload.unwrap();
+
+        let file = models::SourceFile::new("src/lib.rs");
+        assert_eq!(report.files, std::slice::from_ref(&file));
+        assert_eq!(report.uploads.len(), 1);
+
+        let sample =
+            |line_no, coverage_type, hits, hit_branches, total_branches| models::CoverageSample {
+                raw_upload_id: raw_upload.id,
+                source_file_id: file.id,
+                line_no,
+                coverage_type,
+                hits,
+                hit_branches,
+                total_branches,
+                ..Default::default()
+            };
+        let samples: Vec<_> = report
+            .samples
+            .iter()
+            .map(|s| models::CoverageSample {
+                local_sample_id: 0,
+                ..s.clone()
+            })
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                sample(3, models::CoverageType::Method, Some(2), None, None),
+                sample(4, models::CoverageType::Branch, None, Some(1), Some(2)),
+                sample(5, models::CoverageType::Line, Some(0), None, None),
+                sample(12, models::CoverageType::Method, Some(0), None, None),
+            ]
+        );
+
+        let branches: Vec<_> = report
+            .branches
+            .iter()
+            .map(|b| (b.branch.as_str(), b.hits))
+            .collect();
+        assert_eq!(branches, vec![("0:0", 1), ("0:1", 0)]);
+
+        let methods: Vec<_> = report
+            .methods
+            .iter()
+            .map(|m| (m.line_no, m.name.as_deref()))
+            .collect();
+        assert_eq!(
+            methods,
+            vec![(Some(3), Some("parse")), (Some(12), Some("unused"))]
+        );
+    }
+
+    #[test]
+    fn test_parse_lcov_ignore_globs() {
+        let input = b"SF:src/lib.rs
+DA:1,1
+end_of_record
+SF:node_modules/dep/index.js
+DA:1,0
+end_of_record
+";
+
+        let mut report_builder = TestReportBuilder::default();
+        let options = LcovOptions {
+            ignore_globs: IgnoreGlobs(vec!["**/node_modules/**".to_string()]),
+            ..LcovOptions::strict()
+        };
+        let result = parse_lcov(input, &mut report_builder, &options).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.files_ignored), (1, 1));
+        assert_eq!(report.files, &[models::SourceFile::new("src/lib.rs")]);
+        assert_eq!(report.samples.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_lcov_errors() {
+        let mut report_builder = TestReportBuilder::default();
+        let parse = |input: &[u8], builder: &mut TestReportBuilder| {
+            parse_lcov(input, builder, &LcovOptions::default()).unwrap_err()
+        };
+
+        let error = parse(b"DA:1,1\n", &mut report_builder);
+        assert!(matches!(error, CodecovError::ParserError { line: 1, .. }));
+
+        let error = parse(b"SF:a.c\nDA:1\n", &mut report_builder);
+        assert!(matches!(error, CodecovError::ParserError { line: 2, .. }));
+
+        let error = parse(b"SF:a.c\nBRDA:1,0,1\n", &mut report_builder);
+        assert!(matches!(error, CodecovError::ParserError { line: 2, .. }));
+
+        let error = parse(b"SF:a.c\nDA:1,1\nnot lcov\n", &mut report_builder);
+        assert!(matches!(
+            error,
+            CodecovError::ParserError {
+                line: 3,
+                column: 1,
+                ..
+            }
+        ));
+
+        // Nothing is inserted for a report that fails to parse
+        assert!(report_builder.build().unwrap().uploads.is_empty());
+    }
+}
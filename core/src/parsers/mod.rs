@@ -1,6 +1,28 @@
-pub mod json;
+pub(crate) mod json;
 
 #[cfg(feature = "pyreport")]
 pub mod pyreport;
 
+#[cfg(feature = "coverlet")]
+pub mod coverlet;
+
+#[cfg(feature = "coveragepy")]
+pub mod coveragepy;
+
+#[cfg(feature = "gcov")]
+pub mod gcov;
+
+#[cfg(feature = "lcov")]
+pub mod lcov;
+
+#[cfg(feature = "opencover")]
+pub mod opencover;
+
+#[cfg(feature = "scoverage")]
+pub mod scoverage;
+
+// Reachable for benches and tests. Use `crate::prelude` instead.
+#[doc(hidden)]
 pub mod common;
+
+pub mod prelude;
@@ -0,0 +1,841 @@
+//! Parses the XML reports written by [OpenCover](https://github.com/OpenCover/opencover)
+//! and by Visual Studio's `CodeCoverage.exe analyze`, which older .NET
+//! projects use instead of Coverlet.
+//!
+//! OpenCover reports list each module's files and the sequence points
+//! (statements) and branch points of each method:
+//! ```xml
+//! <CoverageSession>
+//!   <Modules>
+//!     <Module>
+//!       <Files>
+//!         <File uid="1" fullPath="C:\src\Calculator.cs" />
+//!       </Files>
+//!       <Classes>
+//!         <Class>
+//!           <Methods>
+//!             <Method cyclomaticComplexity="2">
+//!               <Summary numBranchPoints="2" visitedBranchPoints="1" />
+//!               <Name>System.Int32 MyLibrary.Calculator::Add(System.Int32,System.Int32)</Name>
+//!               <FileRef uid="1" />
+//!               <SequencePoints>
+//!                 <SequencePoint vc="1" sl="10" sc="5" el="10" ec="6" fileid="1" />
+//!               </SequencePoints>
+//!               <BranchPoints>
+//!                 <BranchPoint vc="1" sl="11" offset="7" path="0" fileid="1" />
+//!               </BranchPoints>
+//!             </Method>
+//! ...
+//! ```
+//!
+//! Visual Studio reports only say whether each range of a function was
+//! covered:
+//! ```xml
+//! <results>
+//!   <modules>
+//!     <module>
+//!       <functions>
+//!         <function name="Add(int, int)" namespace="MyLibrary" type_name="Calculator">
+//!           <ranges>
+//!             <range source_id="0" covered="yes" start_line="10" start_column="5" end_line="10" end_column="6" />
+//!           </ranges>
+//!         </function>
+//!       </functions>
+//!       <source_files>
+//!         <source_file id="0" path="C:\src\Calculator.cs" />
+//!       </source_files>
+//! ...
+//! ```
+//!
+//! Each parsed file produces a single [`models::RawUpload`] and a
+//! [`models::SourceFile`] for each source file, merged across modules. Every
+//! sequence point or range becomes a [`models::SpanData`] and marks each line
+//! it covers. A line's hits are the most of any span on it, and a line with
+//! both covered and uncovered spans is partial, which is recorded as 1 of 2
+//! branches hit like pyreport's partial lines. Visual Studio's `partial`
+//! ranges are treated the same way, and its covered ranges count as 1 hit.
+//! OpenCover's hidden sequence points, on line 16707566 (`0xFEEFEE`), are
+//! skipped.
+//!
+//! Lines with OpenCover branch points are branch samples with a
+//! [`models::BranchesData`] for each branch point, identified by
+//! `"{offset}:{path}"` in [`models::BranchFormat::BlockAndBranch`] format.
+//! The first line of each method gets a [`models::MethodData`] record and, if
+//! it isn't a branch, is recorded as a method sample. Methods are named like
+//! Coverlet's, and OpenCover's visited and total branch points and cyclomatic
+//! complexity are kept as the method's branch and complexity totals. Modules,
+//! classes and methods OpenCover skipped (with `skippedDueTo`) are ignored,
+//! as are source files matching [`OpenCoverOptions::ignore_globs`].
+
+use std::collections::BTreeMap;
+
+use roxmltree::{Document, Node};
+
+use super::common::{
+    dotnet_method_name,
+    xml::{child, list, XmlInput},
+    IgnoreGlobs, IngestResult, Stopwatch,
+};
+use crate::{
+    error::{CodecovError, Result},
+    report::{models, Report, ReportBuilder},
+};
+
+/// OpenCover's line number for compiler-generated code with no source.
+const HIDDEN_LINE: i64 = 0xFEEFEE;
+
+#[derive(Debug, Clone, Default)]
+pub struct OpenCoverOptions {
+    /// Paths of source files to leave out of the report.
+    pub ignore_globs: IgnoreGlobs,
+}
+
+#[derive(Debug, Default)]
+struct LineTotals {
+    hits: i64,
+    missed: bool,
+    partial: bool,
+    // Keyed by (offset, path)
+    branches: BTreeMap<(i64, i64), i64>,
+}
+
+impl LineTotals {
+    fn is_partial(&self) -> bool {
+        self.partial || (self.hits > 0 && self.missed)
+    }
+}
+
+#[derive(Debug)]
+struct Span {
+    hits: i64,
+    partial: bool,
+    start_line: i64,
+    start_col: Option<i64>,
+    end_line: i64,
+    end_col: Option<i64>,
+}
+
+#[derive(Debug)]
+struct MethodTotals {
+    signature: String,
+    line_no: i64,
+    hit_branches: Option<i64>,
+    total_branches: Option<i64>,
+    total_complexity: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+struct DocumentTotals {
+    lines: BTreeMap<i64, LineTotals>,
+    spans: Vec<Span>,
+    methods: Vec<MethodTotals>,
+}
+
+impl DocumentTotals {
+    fn add_span(&mut self, span: Span) {
+        for line_no in span.start_line..=span.end_line.max(span.start_line) {
+            let line = self.lines.entry(line_no).or_default();
+            line.hits = line.hits.max(span.hits);
+            line.missed |= span.hits == 0 && !span.partial;
+            line.partial |= span.partial;
+        }
+        self.spans.push(span);
+    }
+}
+
+/// Parser state shared by both formats.
+struct XmlParser<'i> {
+    input: XmlInput<'i>,
+    // NOTE: this is a `BTreeMap` only to have stable iteration order in tests
+    documents: BTreeMap<String, DocumentTotals>,
+}
+
+impl XmlParser<'_> {
+    fn document<'a>(
+        &'a mut self,
+        files: &BTreeMap<i64, String>,
+        id: i64,
+    ) -> Option<&'a mut DocumentTotals> {
+        let path = files.get(&id)?;
+        Some(self.documents.entry(path.clone()).or_default())
+    }
+
+    fn parse_opencover(&mut self, root: Node) -> Result<()> {
+        for module in list(root, "Modules", "Module").filter(not_skipped) {
+            let mut files = BTreeMap::new();
+            for file in list(module, "Files", "File") {
+                let path: String = self.input.attr(file, "fullPath")?;
+                files.insert(self.input.attr(file, "uid")?, path);
+            }
+
+            let methods = list(module, "Classes", "Class")
+                .filter(not_skipped)
+                .flat_map(|class| list(class, "Methods", "Method"))
+                .filter(not_skipped);
+            for method in methods {
+                self.parse_opencover_method(method, &files)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_opencover_method(
+        &mut self,
+        method: Node,
+        files: &BTreeMap<i64, String>,
+    ) -> Result<()> {
+        let signature = child(method, "Name")
+            .and_then(|name| name.text())
+            .ok_or_else(|| self.input.error(method, "Name"))?;
+        let default_file = match child(method, "FileRef") {
+            Some(file_ref) => Some(self.input.attr::<i64>(file_ref, "uid")?),
+            None => None,
+        };
+
+        // The first line of the method in each file it has code in
+        let mut first_lines: BTreeMap<i64, i64> = BTreeMap::new();
+        for point in list(method, "SequencePoints", "SequencePoint") {
+            let start_line = self.input.attr(point, "sl")?;
+            let Some(file_id) = self.input.opt_attr(point, "fileid")?.or(default_file) else {
+                return Err(self.input.error(point, "fileid"));
+            };
+            if start_line == HIDDEN_LINE {
+                continue;
+            }
+            let span = Span {
+                hits: self.input.attr(point, "vc")?,
+                partial: false,
+                start_line,
+                start_col: self.input.opt_attr(point, "sc")?,
+                end_line: self.input.opt_attr(point, "el")?.unwrap_or(start_line),
+                end_col: self.input.opt_attr(point, "ec")?,
+            };
+            let Some(document) = self.document(files, file_id) else {
+                return Err(self.input.error(point, "fileid"));
+            };
+            document.add_span(span);
+            let first_line = first_lines.entry(file_id).or_insert(start_line);
+            *first_line = (*first_line).min(start_line);
+        }
+
+        for point in list(method, "BranchPoints", "BranchPoint") {
+            let line_no = self.input.attr(point, "sl")?;
+            let Some(file_id) = self.input.opt_attr(point, "fileid")?.or(default_file) else {
+                return Err(self.input.error(point, "fileid"));
+            };
+            if line_no == HIDDEN_LINE {
+                continue;
+            }
+            let key = (
+                self.input.attr(point, "offset")?,
+                self.input.attr(point, "path")?,
+            );
+            let hits: i64 = self.input.attr(point, "vc")?;
+            let Some(document) = self.document(files, file_id) else {
+                return Err(self.input.error(point, "fileid"));
+            };
+            let branch = document
+                .lines
+                .entry(line_no)
+                .or_default()
+                .branches
+                .entry(key)
+                .or_default();
+            *branch = (*branch).max(hits);
+        }
+
+        let summary = child(method, "Summary");
+        let summary_attr = |name| match summary {
+            Some(summary) => self.input.opt_attr(summary, name),
+            None => Ok(None),
+        };
+        let hit_branches = summary_attr("visitedBranchPoints")?;
+        let total_branches = summary_attr("numBranchPoints")?;
+        let total_complexity = self.input.opt_attr(method, "cyclomaticComplexity")?;
+        for (file_id, line_no) in first_lines {
+            let document = self.document(files, file_id).unwrap();
+            document.methods.push(MethodTotals {
+                signature: signature.to_string(),
+                line_no,
+                hit_branches,
+                total_branches,
+                total_complexity,
+            });
+        }
+        Ok(())
+    }
+
+    fn parse_visual_studio(&mut self, root: Node) -> Result<()> {
+        for module in list(root, "modules", "module") {
+            let mut files = BTreeMap::new();
+            for file in list(module, "source_files", "source_file") {
+                let path: String = self.input.attr(file, "path")?;
+                files.insert(self.input.attr(file, "id")?, path);
+            }
+
+            for function in list(module, "functions", "function") {
+                self.parse_visual_studio_function(function, &files)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_visual_studio_function(
+        &mut self,
+        function: Node,
+        files: &BTreeMap<i64, String>,
+    ) -> Result<()> {
+        let name: String = self.input.attr(function, "name")?;
+        let type_name = [
+            function.attribute("namespace"),
+            function.attribute("type_name"),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(".");
+        let signature = match type_name.as_str() {
+            "" => name,
+            type_name => format!("{type_name}::{name}"),
+        };
+
+        let mut first_lines: BTreeMap<i64, i64> = BTreeMap::new();
+        for range in list(function, "ranges", "range") {
+            let start_line = self.input.attr(range, "start_line")?;
+            let file_id = self.input.attr(range, "source_id")?;
+            let covered: String = self.input.attr(range, "covered")?;
+            let (hits, partial) = match covered.as_str() {
+                "yes" => (1, false),
+                "partial" => (1, true),
+                "no" => (0, false),
+                _ => return Err(self.input.error(range, "covered")),
+            };
+            let span = Span {
+                hits,
+                partial,
+                start_line,
+                start_col: self.input.opt_attr(range, "start_column")?,
+                end_line: self
+                    .input
+                    .opt_attr(range, "end_line")?
+                    .unwrap_or(start_line),
+                end_col: self.input.opt_attr(range, "end_column")?,
+            };
+            let Some(document) = self.document(files, file_id) else {
+                return Err(self.input.error(range, "source_id"));
+            };
+            document.add_span(span);
+            let first_line = first_lines.entry(file_id).or_insert(start_line);
+            *first_line = (*first_line).min(start_line);
+        }
+
+        for (file_id, line_no) in first_lines {
+            let document = self.document(files, file_id).unwrap();
+            document.methods.push(MethodTotals {
+                signature: signature.clone(),
+                line_no,
+                hit_branches: None,
+                total_branches: None,
+                total_complexity: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn not_skipped(node: &Node) -> bool {
+    !node.has_attribute("skippedDueTo")
+}
+
+/// Parses an OpenCover or Visual Studio XML coverage report into `builder`
+/// as a single [`models::RawUpload`].
+pub fn parse_opencover_xml<B, R>(input: &[u8], builder: &mut B) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    parse_opencover_xml_with_options(input, builder, &OpenCoverOptions::default())
+}
+
+/// Like [`parse_opencover_xml`], but with non-default [`OpenCoverOptions`].
+pub fn parse_opencover_xml_with_options<B, R>(
+    input: &[u8],
+    builder: &mut B,
+    options: &OpenCoverOptions,
+) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    let stopwatch = Stopwatch::start();
+    let input = String::from_utf8_lossy(input);
+    let xml = Document::parse(&input)?;
+    let root = xml.root_element();
+
+    let mut parser = XmlParser {
+        input: XmlInput(&input),
+        documents: BTreeMap::new(),
+    };
+    match root.tag_name().name() {
+        "CoverageSession" => parser.parse_opencover(root)?,
+        "results" => parser.parse_visual_studio(root)?,
+        other => {
+            return Err(CodecovError::UnsupportedFormat {
+                format: "xml".to_string(),
+                reason: format!("expected an OpenCover or Visual Studio report, found <{other}>"),
+            })
+        }
+    }
+
+    let files_before = parser.documents.len();
+    parser
+        .documents
+        .retain(|path, _| !options.ignore_globs.is_ignored(path));
+
+    let raw_upload = builder.insert_raw_upload(Default::default())?;
+    let mut result = IngestResult {
+        files_ignored: files_before - parser.documents.len(),
+        ..Default::default()
+    };
+
+    for (path, document) in parser.documents {
+        let file = builder.insert_file(&path)?;
+        result.files_touched += 1;
+        let method_lines: BTreeMap<i64, &MethodTotals> =
+            document.methods.iter().map(|m| (m.line_no, m)).collect();
+
+        let mut samples: Vec<models::CoverageSample> = document
+            .lines
+            .iter()
+            .map(|(&line_no, totals)| {
+                let mut sample = models::CoverageSample {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    ..Default::default()
+                };
+                if !totals.branches.is_empty() {
+                    sample.coverage_type = models::CoverageType::Branch;
+                    sample.hit_branches =
+                        Some(totals.branches.values().filter(|h| **h > 0).count() as i64);
+                    sample.total_branches = Some(totals.branches.len() as i64);
+                } else if totals.is_partial() {
+                    sample.coverage_type = models::CoverageType::Branch;
+                    sample.hit_branches = Some(1);
+                    sample.total_branches = Some(2);
+                } else {
+                    sample.hits = Some(totals.hits);
+                    if method_lines.contains_key(&line_no) {
+                        sample.coverage_type = models::CoverageType::Method;
+                    }
+                }
+                sample
+            })
+            .collect();
+        builder.multi_insert_coverage_sample(samples.iter_mut().collect())?;
+        result.samples_inserted += samples.len();
+        let sample_ids: BTreeMap<i64, i64> = samples
+            .iter()
+            .map(|sample| (sample.line_no, sample.local_sample_id))
+            .collect();
+
+        let mut branches = vec![];
+        let mut method_data = vec![];
+        for (sample, totals) in samples.iter().zip(document.lines.values()) {
+            for (&(offset, path), &hits) in &totals.branches {
+                branches.push(models::BranchesData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    hits,
+                    branch_format: models::BranchFormat::BlockAndBranch,
+                    branch: format!("{offset}:{path}"),
+                    ..Default::default()
+                });
+            }
+            if let Some(method) = method_lines.get(&sample.line_no) {
+                method_data.push(models::MethodData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    line_no: Some(method.line_no),
+                    hit_branches: method.hit_branches,
+                    total_branches: method.total_branches,
+                    total_complexity: method.total_complexity,
+                    name: Some(dotnet_method_name(&method.signature).to_string()),
+                    signature: Some(method.signature.clone()),
+                    ..Default::default()
+                });
+            }
+        }
+        builder.multi_insert_branches_data(branches.iter_mut().collect())?;
+        builder.multi_insert_method_data(method_data.iter_mut().collect())?;
+
+        let mut spans: Vec<models::SpanData> = document
+            .spans
+            .iter()
+            .map(|span| models::SpanData {
+                raw_upload_id: raw_upload.id,
+                source_file_id: file.id,
+                local_sample_id: sample_ids.get(&span.start_line).copied(),
+                hits: span.hits,
+                start_line: Some(span.start_line),
+                start_col: span.start_col,
+                end_line: Some(span.end_line),
+                end_col: span.end_col,
+                ..Default::default()
+            })
+            .collect();
+        builder.multi_insert_span_data(spans.iter_mut().collect())?;
+    }
+
+    result.raw_upload = Some(raw_upload);
+    result.duration = stopwatch.elapsed();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_report::TestReportBuilder;
+
+    fn strip_ids(samples: &[models::CoverageSample]) -> Vec<models::CoverageSample> {
+        samples
+            .iter()
+            .map(|s| models::CoverageSample {
+                local_sample_id: 0,
+                ..s.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_opencover() {
+        let input = br#"<?xml version="1.0" encoding="utf-8"?>
+<CoverageSession xmlns:xsd="http://www.w3.org/2001/XMLSchema" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+  <Modules>
+    <Module hash="AB-CD">
+      <ModuleName>MyLibrary</ModuleName>
+      <Files>
+        <File uid="1" fullPath="C:\src\Calculator.cs" />
+      </Files>
+      <Classes>
+        <Class>
+          <FullName>MyLibrary.Calculator</FullName>
+          <Methods>
+            <Method visited="true" cyclomaticComplexity="2">
+              <Summary numSequencePoints="3" visitedSequencePoints="2" numBranchPoints="2" visitedBranchPoints="1" />
+              <Name>System.Int32 MyLibrary.Calculator::Add(System.Int32,System.Int32)</Name>
+              <FileRef uid="1" />
+              <SequencePoints>
+                <SequencePoint vc="3" uspid="1" ordinal="0" offset="0" sl="10" sc="5" el="10" ec="6" bec="0" bev="0" fileid="1" />
+                <SequencePoint vc="3" uspid="2" ordinal="1" offset="1" sl="11" sc="9" el="11" ec="20" bec="2" bev="1" fileid="1" />
+                <SequencePoint vc="0" uspid="3" ordinal="2" offset="9" sl="12" sc="13" el="13" ec="14" bec="0" bev="0" fileid="1" />
+                <SequencePoint vc="3" uspid="4" ordinal="3" offset="12" sl="16707566" sc="1" el="16707566" ec="1" bec="0" bev="0" fileid="1" />
+              </SequencePoints>
+              <BranchPoints>
+                <BranchPoint vc="3" uspid="5" ordinal="0" offset="7" sl="11" path="0" offsetend="9" fileid="1" />
+                <BranchPoint vc="0" uspid="6" ordinal="1" offset="7" sl="11" path="1" offsetend="12" fileid="1" />
+              </BranchPoints>
+              <MethodPoint vc="3" uspid="1" ordinal="0" offset="0" sl="10" sc="5" el="10" ec="6" fileid="1" />
+            </Method>
+            <Method visited="true" cyclomaticComplexity="1">
+              <Name>System.Void MyLibrary.Calculator::Reset()</Name>
+              <FileRef uid="1" />
+              <SequencePoints>
+                <SequencePoint vc="1" sl="20" sc="5" el="20" ec="12" />
+                <SequencePoint vc="0" sl="20" sc="13" el="20" ec="30" />
+              </SequencePoints>
+            </Method>
+            <Method skippedDueTo="Filter">
+              <Name>System.Void MyLibrary.Calculator::Skipped()</Name>
+            </Method>
+          </Methods>
+        </Class>
+      </Classes>
+    </Module>
+    <Module skippedDueTo="MissingPdb">
+      <ModuleName>Other</ModuleName>
+    </Module>
+  </Modules>
+</CoverageSession>"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let result = parse_opencover_xml(input, &mut report_builder).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.samples_inserted), (1, 5));
+        let raw_upload = result.raw_upload.unwrap();
+
+        let file = models::SourceFile::new(r"C:\src\Calculator.cs");
+        assert_eq!(report.files, std::slice::from_ref(&file));
+        assert_eq!(report.uploads.len(), 1);
+
+        let sample =
+            |line_no, coverage_type, hits, hit_branches, total_branches| models::CoverageSample {
+                raw_upload_id: raw_upload.id,
+                source_file_id: file.id,
+                line_no,
+                coverage_type,
+                hits,
+                hit_branches,
+                total_branches,
+                ..Default::default()
+            };
+        assert_eq!(
+            strip_ids(&report.samples),
+            vec![
+                sample(10, models::CoverageType::Method, Some(3), None, None),
+                sample(11, models::CoverageType::Branch, None, Some(1), Some(2)),
+                sample(12, models::CoverageType::Line, Some(0), None, None),
+                sample(13, models::CoverageType::Line, Some(0), None, None),
+                sample(20, models::CoverageType::Branch, None, Some(1), Some(2)),
+            ]
+        );
+
+        let branches: Vec<_> = report
+            .branches
+            .iter()
+            .map(|b| (b.local_sample_id, b.branch.as_str(), b.hits))
+            .collect();
+        let line_11 = report.samples[1].local_sample_id;
+        assert_eq!(branches, vec![(line_11, "7:0", 3), (line_11, "7:1", 0)]);
+
+        assert_eq!(report.methods.len(), 2);
+        let add = &report.methods[0];
+        assert_eq!(add.local_sample_id, report.samples[0].local_sample_id);
+        assert_eq!(add.name.as_deref(), Some("MyLibrary.Calculator::Add"));
+        assert_eq!(
+            add.signature.as_deref(),
+            Some("System.Int32 MyLibrary.Calculator::Add(System.Int32,System.Int32)")
+        );
+        assert_eq!(add.hit_branches, Some(1));
+        assert_eq!(add.total_branches, Some(2));
+        assert_eq!(add.total_complexity, Some(2));
+        let reset = &report.methods[1];
+        assert_eq!(reset.line_no, Some(20));
+        assert_eq!(reset.local_sample_id, report.samples[4].local_sample_id);
+        assert_eq!(reset.total_branches, None);
+
+        let spans: Vec<_> = report
+            .spans
+            .iter()
+            .map(|s| (s.hits, s.start_line, s.start_col, s.end_line, s.end_col))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (3, Some(10), Some(5), Some(10), Some(6)),
+                (3, Some(11), Some(9), Some(11), Some(20)),
+                (0, Some(12), Some(13), Some(13), Some(14)),
+                (1, Some(20), Some(5), Some(20), Some(12)),
+                (0, Some(20), Some(13), Some(20), Some(30)),
+            ]
+        );
+        assert_eq!(
+            report.spans[2].local_sample_id,
+            Some(report.samples[2].local_sample_id)
+        );
+    }
+
+    #[test]
+    fn test_parse_visual_studio() {
+        let input = br#"<?xml version="1.0" encoding="utf-8"?>
+<results>
+  <modules>
+    <module name="mylibrary.dll" path="mylibrary.dll" id="AB" block_coverage="80.00" line_coverage="75.00">
+      <functions>
+        <function id="1" token="0x6000001" name="Add(int, int)" namespace="MyLibrary" type_name="Calculator" block_coverage="80.00" line_coverage="75.00">
+          <ranges>
+            <range source_id="0" covered="yes" start_line="10" start_column="5" end_line="10" end_column="6" />
+            <range source_id="0" covered="partial" start_line="11" start_column="9" end_line="11" end_column="20" />
+            <range source_id="0" covered="no" start_line="12" start_column="13" end_line="13" end_column="14" />
+          </ranges>
+        </function>
+        <function id="2" token="0x6000002" name="Main()" namespace="" type_name="">
+          <ranges>
+            <range source_id="1" covered="yes" start_line="3" start_column="1" end_line="3" end_column="2" />
+          </ranges>
+        </function>
+      </functions>
+      <source_files>
+        <source_file id="0" path="C:\src\Calculator.cs" checksum_type="SHA256" checksum="00" />
+        <source_file id="1" path="C:\src\Program.cs" checksum_type="SHA256" checksum="00" />
+      </source_files>
+    </module>
+  </modules>
+</results>"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let result = parse_opencover_xml(input, &mut report_builder).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.samples_inserted), (2, 5));
+        let raw_upload = result.raw_upload.unwrap();
+
+        let calculator = models::SourceFile::new(r"C:\src\Calculator.cs");
+        let program = models::SourceFile::new(r"C:\src\Program.cs");
+        assert_eq!(report.files, vec![calculator.clone(), program.clone()]);
+
+        let sample = |file: &models::SourceFile,
+                      line_no,
+                      coverage_type,
+                      hits,
+                      hit_branches,
+                      total_branches| models::CoverageSample {
+            raw_upload_id: raw_upload.id,
+            source_file_id: file.id,
+            line_no,
+            coverage_type,
+            hits,
+            hit_branches,
+            total_branches,
+            ..Default::default()
+        };
+        assert_eq!(
+            strip_ids(&report.samples),
+            vec![
+                sample(
+                    &calculator,
+                    10,
+                    models::CoverageType::Method,
+                    Some(1),
+                    None,
+                    None
+                ),
+                sample(
+                    &calculator,
+                    11,
+                    models::CoverageType::Branch,
+                    None,
+                    Some(1),
+                    Some(2)
+                ),
+                sample(
+                    &calculator,
+                    12,
+                    models::CoverageType::Line,
+                    Some(0),
+                    None,
+                    None
+                ),
+                sample(
+                    &calculator,
+                    13,
+                    models::CoverageType::Line,
+                    Some(0),
+                    None,
+                    None
+                ),
+                sample(
+                    &program,
+                    3,
+                    models::CoverageType::Method,
+                    Some(1),
+                    None,
+                    None
+                ),
+            ]
+        );
+        assert!(report.branches.is_empty());
+
+        let names: Vec<_> = report
+            .methods
+            .iter()
+            .map(|m| (m.name.as_deref(), m.signature.as_deref()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (
+                    Some("MyLibrary.Calculator::Add"),
+                    Some("MyLibrary.Calculator::Add(int, int)")
+                ),
+                (Some("Main"), Some("Main()")),
+            ]
+        );
+        assert_eq!(report.spans.len(), 4);
+        assert_eq!(report.spans[1].hits, 1);
+    }
+
+    #[test]
+    fn test_parse_opencover_ignore_globs() {
+        let input = br#"<results>
+  <modules>
+    <module name="app.dll">
+      <functions>
+        <function name="Main()" namespace="" type_name="">
+          <ranges>
+            <range source_id="0" covered="yes" start_line="3" start_column="1" end_line="3" end_column="2" />
+            <range source_id="1" covered="no" start_line="5" start_column="1" end_line="5" end_column="2" />
+          </ranges>
+        </function>
+      </functions>
+      <source_files>
+        <source_file id="0" path="src/Program.cs" />
+        <source_file id="1" path="obj/Generated.cs" />
+      </source_files>
+    </module>
+  </modules>
+</results>"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let options = OpenCoverOptions {
+            ignore_globs: IgnoreGlobs(vec!["obj/**".to_string()]),
+        };
+        let result =
+            parse_opencover_xml_with_options(input, &mut report_builder, &options).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.files_ignored), (1, 1));
+        assert_eq!(report.files, &[models::SourceFile::new("src/Program.cs")]);
+        assert_eq!(report.samples.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let mut report_builder = TestReportBuilder::default();
+
+        let error = parse_opencover_xml(b"<CoverageSession>", &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::Xml(_)));
+
+        let error = parse_opencover_xml(b"<coverage />", &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::UnsupportedFormat { .. }));
+
+        let input = br#"<CoverageSession><Modules><Module>
+<Files><File uid="1" fullPath="a.cs" /></Files>
+<Classes><Class><Methods><Method>
+<Name>System.Void A::B()</Name>
+<SequencePoints>
+<SequencePoint vc="x" sl="1" fileid="1" />
+</SequencePoints>
+</Method></Methods></Class></Classes>
+</Module></Modules></CoverageSession>"#;
+        let error = parse_opencover_xml(input, &mut report_builder).unwrap_err();
+        assert!(matches!(
+            error,
+            CodecovError::ParserError {
+                line: 6,
+                column: 1,
+                ..
+            }
+        ));
+
+        // A point in a file the module doesn't list
+        let input = std::str::from_utf8(input)
+            .unwrap()
+            .replace(r#"vc="x" sl="1" fileid="1""#, r#"vc="1" sl="1" fileid="2""#);
+        let error = parse_opencover_xml(input.as_bytes(), &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::ParserError { line: 6, .. }));
+
+        let input = br#"<results><modules><module>
+<functions><function name="A()"><ranges>
+<range source_id="0" covered="maybe" start_line="1" />
+</ranges></function></functions>
+<source_files><source_file id="0" path="a.cs" /></source_files>
+</module></modules></results>"#;
+        let error = parse_opencover_xml(input, &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::ParserError { line: 3, .. }));
+
+        // Nothing is inserted for a report that fails to parse
+        assert!(report_builder.build().unwrap().uploads.is_empty());
+    }
+}
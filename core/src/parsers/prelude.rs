@@ -0,0 +1,45 @@
+//! Everything a parser for a new coverage format needs in order to write
+//! what it parses to a [`ReportBuilder`]. Items are only removed from this
+//! module in breaking releases.
+//!
+//! Parsers are [`winnow`](https://docs.rs/winnow) parsers over a
+//! [`ReportBuilderStream`], which carries the [`ReportBuilder`] along as
+//! parser state:
+//! ```
+//! use codecov_rs::parsers::prelude::*;
+//! use winnow::combinator::{delimited, separated};
+//!
+//! /// Parses a JSON array of paths, inserting a file for each one.
+//! fn file_list<S: StrStream, R: Report, B: ReportBuilder<R>>(
+//!     buf: &mut ReportBuilderStream<S, R, B>,
+//! ) -> PResult<Vec<models::SourceFile>> {
+//!     let paths: Vec<String> =
+//!         delimited(('[', ws), separated(0.., parse_str, (ws, ',', ws)), (ws, ']'))
+//!             .parse_next(buf)?;
+//!     paths
+//!         .iter()
+//!         .map(|path| buf.state.report_builder.insert_file(path))
+//!         .collect::<Result<_>>()
+//!         .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e))
+//! }
+//! ```
+
+pub use winnow::{
+    error::{ContextError, ErrMode, ErrorKind, FromExternalError, StrContext},
+    PResult, Parser, Stateful,
+};
+
+pub use super::{
+    common::{
+        winnow::{nullable, parse_u32, ws, CharStream, StrStream},
+        LineBoundsPolicy, ReportBuilderCtx, ReportBuilderStream,
+    },
+    json::{
+        json_value, parse_array, parse_bool, parse_null, parse_num, parse_object, parse_str,
+        specific_key, JsonMap, JsonNumber, JsonVal,
+    },
+};
+pub use crate::{
+    error::{CodecovError, Result},
+    report::{models, Report, ReportBuilder},
+};
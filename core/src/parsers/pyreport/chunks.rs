@@ -1,12 +1,23 @@
-use std::{collections::HashMap, fmt, fmt::Debug};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    fmt::Debug,
+    io::Write,
+};
 
 use winnow::{
+    ascii::line_ending,
     combinator::{
-        alt, cut_err, delimited, empty, eof, opt, peek, preceded, separated, separated_pair, seq,
-        terminated,
+        alt, cut_err, delimited, empty, eof, not, opt, peek, preceded, repeat, separated,
+        separated_pair, seq, terminated,
+    },
+    error::{
+        AddContext, ContextError, ErrMode, ErrorKind, FromExternalError, StrContext,
+        StrContextValue,
     },
-    error::{ContextError, ErrMode, ErrorKind, FromExternalError, StrContext},
     stream::Stream,
+    token::{any, take_till},
     PResult, Parser, Stateful,
 };
 
@@ -18,14 +29,29 @@ use super::{
         },
         json::{json_value, parse_object, parse_str, JsonMap, JsonVal},
     },
+    quirks::Quirks,
     utils,
 };
 #[cfg(doc)]
-use crate::report::models;
 use crate::report::{
-    pyreport::{types::*, CHUNKS_FILE_END_OF_CHUNK, CHUNKS_FILE_HEADER_TERMINATOR},
-    Report, ReportBuilder,
+    models,
+    pyreport::{CHUNKS_FILE_END_OF_CHUNK, CHUNKS_FILE_HEADER_TERMINATOR},
 };
+use crate::report::{pyreport::types::*, LabelPruning, Report, ReportBuilder};
+
+/// What to do when the chunks file and the report JSON disagree about which
+/// chunks there are: a chunk has lines but the report JSON has no file for its
+/// index, or the report JSON lists a file whose chunk isn't in the chunks file.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ChunkMismatchPolicy {
+    /// Fail the parse.
+    Error,
+
+    /// Skip the chunk's lines, or keep the file with no coverage for it, and
+    /// record a warning.
+    #[default]
+    Skip,
+}
 
 #[derive(PartialEq, Debug)]
 pub struct ChunkCtx {
@@ -65,6 +91,52 @@ pub struct ParseCtx<R: Report, B: ReportBuilder<R>> {
     /// the ID of the [`Context`](models::Context) that the session
     /// corresponds to.
     pub report_json_sessions: HashMap<usize, i64>,
+
+    /// The indices of chunks whose files were left out of the report JSON
+    /// because they were ignored. See [`chunk_or_skip`].
+    pub ignored_chunks: HashSet<usize>,
+
+    /// Whether to skip chunks that fail to parse instead of failing the whole
+    /// chunks file. See [`chunk_or_skip`].
+    pub skip_malformed_chunks: bool,
+
+    /// The indices of chunks that were skipped because they were malformed.
+    pub skipped_chunks: Vec<usize>,
+
+    /// How many [`CoverageSample`](models::CoverageSample)s have been
+    /// inserted, not counting those in skipped chunks.
+    pub samples_inserted: usize,
+
+    /// Whether to parse labels without inserting them. See [`label`].
+    pub drop_labels: bool,
+
+    /// Corrections applied to each coverage measurement. See [`report_line`].
+    pub quirks: Quirks,
+
+    /// How many measurements each of `quirks`' profiles corrected, keyed by
+    /// the profile's name, not counting those in skipped chunks.
+    pub quirks_applied: BTreeMap<String, usize>,
+
+    /// Measurements one of `quirks`' profiles recognized but couldn't correct,
+    /// not counting those in skipped chunks. See
+    /// [`QuirksProfile::warning`](super::quirks::QuirksProfile::warning).
+    pub quirk_warnings: Vec<String>,
+
+    /// What to do with a chunk that has lines but no file in the report JSON.
+    /// See [`chunk`].
+    pub chunk_mismatch: ChunkMismatchPolicy,
+
+    /// The indices of chunks that were skipped because they had lines but no
+    /// file in the report JSON.
+    pub orphan_chunks: Vec<usize>,
+
+    /// Which labels to leave off of each sample. See
+    /// [`ParseOptions::label_pruning`](super::ParseOptions::label_pruning).
+    pub label_pruning: LabelPruning,
+
+    /// How many labels `label_pruning` left off, not counting those in
+    /// skipped chunks.
+    pub labels_pruned: usize,
 }
 
 pub type ReportOutputStream<S, R, B> = Stateful<S, ParseCtx<R, B>>;
@@ -84,6 +156,18 @@ impl<R: Report, B: ReportBuilder<R>> ParseCtx<R, B> {
             },
             report_json_files,
             report_json_sessions,
+            ignored_chunks: HashSet::new(),
+            skip_malformed_chunks: false,
+            skipped_chunks: Vec::new(),
+            samples_inserted: 0,
+            drop_labels: false,
+            quirks: Quirks::default(),
+            quirks_applied: BTreeMap::new(),
+            quirk_warnings: Vec::new(),
+            chunk_mismatch: ChunkMismatchPolicy::default(),
+            orphan_chunks: Vec::new(),
+            label_pruning: LabelPruning::default(),
+            labels_pruned: 0,
         }
     }
 }
@@ -185,7 +269,7 @@ pub fn complexity<S: StrStream, R: Report, B: ReportBuilder<R>>(
 /// We'll try our best, and that'll have to do.
 pub fn missing_branches<'a, S, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
-) -> PResult<Vec<MissingBranch>>
+) -> PResult<Vec<MissingBranch<'a>>>
 where
     S: StrStream,
     S: Stream<Slice = &'a str>,
@@ -199,9 +283,8 @@ where
 
     let condition = (parse_u32, condition_type);
     let condition = delimited('"', condition, '"');
-    let condition = condition.map(move |(cond, cond_type)| {
-        MissingBranch::Condition(cond, cond_type.map(move |s: &str| s.to_string()))
-    });
+    let condition =
+        condition.map(move |(cond, cond_type)| MissingBranch::Condition(cond, cond_type));
 
     let line = delimited('"', parse_u32, '"').map(MissingBranch::Line);
 
@@ -266,7 +349,7 @@ pub fn partial_spans<S: StrStream, R: Report, B: ReportBuilder<R>>(
 /// Trailing null fields may be omitted.
 pub fn line_session<'a, S, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
-) -> PResult<LineSession>
+) -> PResult<LineSession<'a>>
 where
     S: StrStream,
     S: Stream<Slice = &'a str>,
@@ -310,37 +393,59 @@ where
 /// - `1`
 /// - `5`
 ///
-/// If the label is already in `buf.state.labels_index`, return it as a string.
-/// If it's not, insert it into the database, insert a mapping from the label to
-/// the DB PK, and then return it as a string.
-pub fn label<S: StrStream, R: Report, B: ReportBuilder<R>>(
+/// Returns the ID of the [`Context`](models::Context) for the label, inserting
+/// one and adding it to `buf.state.labels_index` if this is the first time
+/// we've seen the label. Returning the ID rather than the label itself means
+/// datapoints don't each need their own copies of label names.
+///
+/// If `buf.state.drop_labels` is set, the label is consumed without being
+/// inserted and the returned ID is meaningless. [`coverage_datapoint`] throws
+/// those IDs away.
+pub fn label<'a, S, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
-) -> PResult<String> {
+) -> PResult<i64>
+where
+    S: StrStream,
+    S: Stream<Slice = &'a str>,
+{
+    // Most label names have no escape sequences, so we can look them up
+    // without copying them out of the input
+    let borrowed_name = delimited('"', take_till(0.., ['"', '\\']), '"');
     let raw_label = alt((
         parse_u32.map(RawLabel::LabelId),
-        parse_str.map(RawLabel::LabelName),
+        borrowed_name.map(|name| RawLabel::LabelName(Cow::Borrowed(name))),
+        parse_str.map(|name| RawLabel::LabelName(Cow::Owned(name))),
     ))
     .context(StrContext::Label("label"))
     .parse_next(buf)?;
+    if buf.state.drop_labels {
+        return Ok(0);
+    }
 
-    let labels_index_key = match raw_label {
-        RawLabel::LabelId(id) => id.to_string(),
+    // Numeric labels are keyed by their decimal representation. Format it on
+    // the stack since most labels will already be in the index.
+    let mut id_buf = [0u8; 10];
+    let labels_index_key = match &raw_label {
+        RawLabel::LabelId(id) => {
+            let mut cursor = &mut id_buf[..];
+            let _ = write!(cursor, "{id}");
+            let len = 10 - cursor.len();
+            std::str::from_utf8(&id_buf[..len]).unwrap_or_default()
+        }
         RawLabel::LabelName(name) => name,
     };
 
-    match buf.state.labels_index.get(&labels_index_key) {
-        Some(_) => Ok(labels_index_key),
-        None => {
-            let context = buf
-                .state
-                .db
-                .report_builder
-                .insert_context(&labels_index_key)
-                .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e))?;
-            buf.state.labels_index.insert(context.name, context.id);
-            Ok(labels_index_key)
-        }
+    if let Some(&context_id) = buf.state.labels_index.get(labels_index_key) {
+        return Ok(context_id);
     }
+    let context = buf
+        .state
+        .db
+        .report_builder
+        .insert_context(labels_index_key)
+        .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e))?;
+    buf.state.labels_index.insert(context.name, context.id);
+    Ok(context.id)
 }
 
 /// Parses the (largely redundant) [`CoverageDatapoint`]. Most of its fields are
@@ -351,10 +456,14 @@ pub fn label<S: StrStream, R: Report, B: ReportBuilder<R>>(
 /// when it's missing is identical to the way we serialize
 /// [`CoverageType::Line`] so there's no way to tell
 /// which it is when deserializing.
-pub fn coverage_datapoint<S: StrStream, R: Report, B: ReportBuilder<R>>(
+pub fn coverage_datapoint<'a, S, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
-) -> PResult<(u32, CoverageDatapoint)> {
-    let datapoint = seq! {CoverageDatapoint {
+) -> PResult<(u32, CoverageDatapoint)>
+where
+    S: StrStream,
+    S: Stream<Slice = &'a str>,
+{
+    let mut datapoint = seq! {CoverageDatapoint {
         _: '[',
         session_id: parse_u32,
         _: (ws, ',', ws),
@@ -367,6 +476,9 @@ pub fn coverage_datapoint<S: StrStream, R: Report, B: ReportBuilder<R>>(
     }}
     .context(StrContext::Label("coverage_datapoint"))
     .parse_next(buf)?;
+    if buf.state.drop_labels {
+        datapoint.labels.clear();
+    }
     Ok((datapoint.session_id, datapoint))
 }
 
@@ -378,9 +490,14 @@ pub fn coverage_datapoint<S: StrStream, R: Report, B: ReportBuilder<R>>(
 /// stream and only returns a [`ReportLine`] for tests. The
 /// `report_line_or_empty` parser which wraps this and supports empty lines
 /// returns `Ok(())`.
+///
+/// Coverage measurements are corrected with `buf.state.quirks` before they're
+/// returned, and each correction is counted in `buf.state.quirks_applied`.
+/// Measurements that a profile recognizes but can't correct are left alone
+/// and recorded in `buf.state.quirk_warnings`, once per line.
 pub fn report_line<'a, S, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
-) -> PResult<ReportLine>
+) -> PResult<ReportLine<'a>>
 where
     S: StrStream,
     S: Stream<Slice = &'a str>,
@@ -395,7 +512,7 @@ where
         _: (ws, ',', ws),
         sessions: delimited('[', separated(0.., line_session, (ws, ',', ws)), ']'),
 //        _: (ws, ',', ws),
-        _messages: opt(preceded((ws, ',', ws), nullable(messages))),
+        messages: opt(preceded((ws, ',', ws), nullable(messages))),
 //        _: (ws, ',', ws),
         _complexity: opt(preceded((ws, ',', ws), nullable(complexity))),
 //        _: (ws, ',', ws),
@@ -406,16 +523,36 @@ where
     .parse_next(buf)?;
 
     // Fix issues like recording branch coverage with `CoverageType::Method`
-    let (correct_coverage, correct_type) =
-        normalize_coverage_measurement(&report_line.coverage, &report_line.coverage_type);
-    report_line.coverage = correct_coverage;
-    report_line.coverage_type = correct_type;
+    let quirks = &buf.state.quirks;
+    let quirks_applied = &mut buf.state.quirks_applied;
+    if let Some((name, (correct_coverage, correct_type))) =
+        quirks.correct(&report_line.coverage, report_line.coverage_type)
+    {
+        *quirks_applied.entry(name.to_string()).or_default() += 1;
+        report_line.coverage = correct_coverage;
+        report_line.coverage_type = correct_type;
+    }
 
-    // Fix the `coverage` values in each `LineSession` as well
+    // Fix the `coverage` values in each `LineSession` as well. Sessions have
+    // no coverage type of their own, so only count corrections to `coverage`.
     for line_session in report_line.sessions.iter_mut() {
-        let (correct_coverage, _) =
-            normalize_coverage_measurement(&line_session.coverage, &report_line.coverage_type);
-        line_session.coverage = correct_coverage;
+        if let Some((name, (correct_coverage, _))) = quirks
+            .correct(&line_session.coverage, report_line.coverage_type)
+            .filter(|(_, (coverage, _))| *coverage != line_session.coverage)
+        {
+            *quirks_applied.entry(name.to_string()).or_default() += 1;
+            line_session.coverage = correct_coverage;
+        }
+    }
+
+    let warning = std::iter::once(&report_line.coverage)
+        .chain(report_line.sessions.iter().map(|session| &session.coverage))
+        .find_map(|coverage| quirks.warning(coverage, report_line.coverage_type));
+    if let Some(warning) = warning {
+        buf.state.quirk_warnings.push(format!(
+            "{warning} on line {line_no} of chunk {index}",
+            index = buf.state.chunk.index
+        ));
     }
 
     Ok(report_line)
@@ -429,17 +566,17 @@ where
 /// stream so we don't actually need to return anything to our caller.
 pub fn report_line_or_empty<'a, S, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
-) -> PResult<Option<ReportLine>>
+) -> PResult<Option<ReportLine<'a>>>
 where
     S: StrStream,
     S: Stream<Slice = &'a str>,
 {
     buf.state.chunk.current_line += 1;
 
-    // A line is empty if the next character is `\n` or EOF. We don't consume that
-    // next character from the stream though - we leave it there as either the
+    // A line is empty if the next thing is a line ending or EOF. We don't consume
+    // the line ending from the stream though - we leave it there as either the
     // delimeter between lines or part of `CHUNKS_FILE_END_OF_CHUNK`.
-    let empty_line = peek(alt((eof, "\n"))).map(|_| None);
+    let empty_line = peek(alt((eof, line_ending))).map(|_| None);
     let populated_line = report_line.map(Some);
     alt((populated_line, empty_line))
         .context(StrContext::Label("report_line_or_empty"))
@@ -453,7 +590,7 @@ where
 pub fn chunk_header<S: StrStream, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
 ) -> PResult<JsonMap<String, JsonVal>> {
-    terminated(parse_object, '\n')
+    terminated(parse_object, line_ending)
         .context(StrContext::Label("chunk_header"))
         .parse_next(buf)
 }
@@ -465,6 +602,10 @@ pub fn chunk_header<S: StrStream, R: Report, B: ReportBuilder<R>>(
 /// Each new chunk will reset `buf.state.chunk.current_line` to 0 when it starts
 /// and increment `buf.state.chunk.index` when it ends so that the next chunk
 /// can associate its data with the correct file.
+///
+/// If no file has N in its `chunks_index` field and the chunk isn't empty,
+/// `buf.state.chunk_mismatch` decides whether to fail or to discard the chunk's
+/// lines and record its index in `buf.state.orphan_chunks`.
 pub fn chunk<'a, S, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
 ) -> PResult<()>
@@ -475,20 +616,66 @@ where
     // New chunk, start back at line 0.
     buf.state.chunk.current_line = 0;
 
-    let empty_chunk = terminated("null", peek(alt((eof, "\n")))).map(|_| Vec::new());
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!(
+        "chunk",
+        index = buf.state.chunk.index,
+        lines = tracing::field::Empty
+    )
+    .entered();
+
+    let empty_chunk = terminated("null", peek(alt((eof, line_ending)))).map(|_| Vec::new());
     let report_lines = preceded(
         cut_err(chunk_header),
-        cut_err(separated(1.., report_line_or_empty, '\n')),
+        cut_err(separated(1.., report_line_or_empty, line_ending)),
     );
 
-    let parsed_lines: Vec<_> = alt((empty_chunk, report_lines))
+    // A chunk with no file in the report JSON has nowhere to put its lines. If
+    // they're going to be skipped, don't insert its labels either.
+    let index = buf.state.chunk.index;
+    let orphan = !buf.state.report_json_files.contains_key(&index);
+    let skip_orphan = orphan && buf.state.chunk_mismatch == ChunkMismatchPolicy::Skip;
+    let drop_labels = buf.state.drop_labels;
+    let quirks_applied = skip_orphan.then(|| buf.state.quirks_applied.clone());
+    let quirk_warnings = buf.state.quirk_warnings.len();
+    buf.state.drop_labels |= skip_orphan;
+
+    let parsed_lines = alt((empty_chunk, report_lines))
         .context(StrContext::Label("chunk"))
-        .parse_next(buf)?;
-
-    let parsed_lines: Vec<ReportLine> = parsed_lines.into_iter().flatten().collect();
-
-    utils::save_report_lines(parsed_lines.as_slice(), &mut buf.state)
-        .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e))?;
+        .parse_next(buf);
+    buf.state.drop_labels = drop_labels;
+
+    let parsed_lines: Vec<ReportLine> = parsed_lines?.into_iter().flatten().collect();
+    #[cfg(feature = "tracing")]
+    span.record("lines", parsed_lines.len());
+
+    if orphan && !parsed_lines.is_empty() {
+        match buf.state.chunk_mismatch {
+            ChunkMismatchPolicy::Error => {
+                let error = ContextError::new()
+                    .add_context(buf, StrContext::Label("chunk"))
+                    .add_context(
+                        buf,
+                        StrContext::Expected(StrContextValue::Description(
+                            "a file in the report JSON for this chunk",
+                        )),
+                    );
+                return Err(ErrMode::Cut(error));
+            }
+            ChunkMismatchPolicy::Skip => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(index, "skipping chunk with no file in the report JSON");
+                if let Some(quirks_applied) = quirks_applied {
+                    buf.state.quirks_applied = quirks_applied;
+                }
+                buf.state.quirk_warnings.truncate(quirk_warnings);
+                buf.state.orphan_chunks.push(index);
+            }
+        }
+    } else {
+        utils::save_report_lines(parsed_lines.as_slice(), &mut buf.state)
+            .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e))?;
+    }
 
     // Advance our chunk index so we can associate the data from the next chunk with
     // the correct file from the report JSON.
@@ -497,6 +684,87 @@ where
     Ok(())
 }
 
+/// Parses a [`chunk`], or skips it without parsing if its index is in
+/// `buf.state.ignored_chunks`. If `buf.state.skip_malformed_chunks` is set, the
+/// chunk is parsed inside a savepoint, and if it fails to parse, anything it
+/// inserted is rolled back,
+/// its index is recorded in `buf.state.skipped_chunks`, and the input is
+/// skipped up to the next chunk.
+pub fn chunk_or_skip<'a, S, R: Report, B: ReportBuilder<R>>(
+    buf: &mut ReportOutputStream<S, R, B>,
+) -> PResult<()>
+where
+    S: StrStream,
+    S: Stream<Slice = &'a str>,
+{
+    if buf.state.ignored_chunks.contains(&buf.state.chunk.index) {
+        skip_chunk.parse_next(buf)?;
+        buf.state.chunk.index += 1;
+        return Ok(());
+    }
+    if !buf.state.skip_malformed_chunks {
+        return chunk.parse_next(buf);
+    }
+
+    let start = buf.checkpoint();
+    let index = buf.state.chunk.index;
+    // Labels are inserted as they're encountered, so they may be rolled back too
+    let labels_index = buf.state.labels_index.clone();
+    let samples_inserted = buf.state.samples_inserted;
+    let quirks_applied = buf.state.quirks_applied.clone();
+    let quirk_warnings = buf.state.quirk_warnings.len();
+    let orphan_chunks = buf.state.orphan_chunks.len();
+    let labels_pruned = buf.state.labels_pruned;
+    buf.state
+        .db
+        .report_builder
+        .savepoint()
+        .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e))?;
+
+    // A malformed line after the first one just ends the chunk early, so make
+    // sure the whole chunk was consumed
+    let parsed = chunk
+        .parse_next(buf)
+        .and_then(|()| peek(alt((end_of_chunk, eof.void()))).parse_next(buf));
+    match parsed {
+        Ok(()) => buf
+            .state
+            .db
+            .report_builder
+            .release_savepoint()
+            .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e)),
+        Err(ErrMode::Incomplete(needed)) => Err(ErrMode::Incomplete(needed)),
+        Err(_) => {
+            buf.state
+                .db
+                .report_builder
+                .rollback_to_savepoint()
+                .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e))?;
+            buf.state.labels_index = labels_index;
+            buf.state.samples_inserted = samples_inserted;
+            buf.state.quirks_applied = quirks_applied;
+            buf.state.quirk_warnings.truncate(quirk_warnings);
+            buf.state.orphan_chunks.truncate(orphan_chunks);
+            buf.state.labels_pruned = labels_pruned;
+
+            #[cfg(feature = "tracing")]
+            tracing::warn!(index, "skipping malformed chunk");
+            buf.reset(start);
+            skip_chunk.parse_next(buf)?;
+            buf.state.skipped_chunks.push(index);
+            buf.state.chunk.index = index + 1;
+            Ok(())
+        }
+    }
+}
+
+/// Consumes the rest of the current chunk, up to the next terminator.
+fn skip_chunk<S: StrStream, R: Report, B: ReportBuilder<R>>(
+    buf: &mut ReportOutputStream<S, R, B>,
+) -> PResult<()> {
+    repeat(0.., preceded(not(end_of_chunk), any)).parse_next(buf)
+}
+
 /// Chunks files sometimes begin with a JSON object followed by a terminator
 /// string. The JSON object contains:
 /// - `"labels_index"`: assigns a numeric ID to each label to save space
@@ -506,37 +774,67 @@ where
 /// in `buf.state.labels_index` from numeric ID in the header to the
 /// new `Context`'s ID in the output report. If the `"labels_index"` key is
 /// _not_ present, we will populate `buf.state.labels_index` gradually as we
-/// encounter new labels during parsing.
+/// encounter new labels during parsing. If `buf.state.drop_labels` is set, the
+/// labels index is ignored.
 pub fn chunks_file_header<S: StrStream, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
 ) -> PResult<()> {
-    let header = terminated(parse_object, CHUNKS_FILE_HEADER_TERMINATOR)
+    let header = terminated(parse_object, end_of_header)
         .context(StrContext::Label("chunks_file_header"))
         .parse_next(buf)?;
+    if buf.state.drop_labels {
+        return Ok(());
+    }
 
     let labels_iter = header
         .get("labels_index")
         .and_then(JsonVal::as_object)
         .into_iter()
         .flatten();
+    let mut indexes = vec![];
+    let mut names = vec![];
     for (index, name) in labels_iter {
         let Some(name) = name.as_str() else {
             return Err(ErrMode::Cut(ContextError::new()));
         };
-        let context = buf
-            .state
-            .db
-            .report_builder
-            .insert_context(name)
-            .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e))?;
+        indexes.push(index);
+        names.push(name);
+    }
+
+    // Label-heavy reports can have tens of thousands of labels, so insert
+    // them all at once
+    let contexts = buf
+        .state
+        .db
+        .report_builder
+        .multi_insert_context(&names)
+        .map_err(|e| ErrMode::from_external_error(buf, ErrorKind::Fail, e))?;
+    for (index, context) in indexes.into_iter().zip(contexts) {
         buf.state.labels_index.insert(index.clone(), context.id);
     }
 
     Ok(())
 }
 
+/// Parses [`CHUNKS_FILE_HEADER_TERMINATOR`], with either kind of line ending.
+fn end_of_header<S: StrStream>(buf: &mut S) -> PResult<()> {
+    (line_ending, "<<<<< end_of_header >>>>>", line_ending)
+        .void()
+        .parse_next(buf)
+}
+
+/// Parses [`CHUNKS_FILE_END_OF_CHUNK`], with either kind of line ending.
+pub(crate) fn end_of_chunk<S: StrStream>(buf: &mut S) -> PResult<()> {
+    (line_ending, "<<<<< end_of_chunk >>>>>", line_ending)
+        .void()
+        .parse_next(buf)
+}
+
 /// Parses a chunks file. A chunks file contains an optional header and a series
 /// of 1 or more "chunks" separated by an `CHUNKS_FILE_END_OF_CHUNK` terminator.
+///
+/// Files written on Windows may use `\r\n` line endings and begin with a
+/// UTF-8 byte order mark, both of which are accepted.
 pub fn parse_chunks_file<'a, S, R: Report, B: ReportBuilder<R>>(
     buf: &mut ReportOutputStream<S, R, B>,
 ) -> PResult<()>
@@ -544,9 +842,12 @@ where
     S: StrStream,
     S: Stream<Slice = &'a str>,
 {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("parse_chunks_file").entered();
+
     let _: Vec<_> = preceded(
-        opt(chunks_file_header),
-        separated(1.., chunk, CHUNKS_FILE_END_OF_CHUNK),
+        (opt('\u{feff}'), opt(chunks_file_header)),
+        separated(1.., chunk_or_skip, end_of_chunk),
     )
     .context(StrContext::Label("parse_chunks_file"))
     .parse_next(buf)?;
@@ -843,12 +1144,12 @@ mod tests {
             ("[]", Ok(vec![])),
             (
                 "[\"0:jump\"]",
-                Ok(vec![MissingBranch::Condition(0, Some("jump".to_string()))]),
+                Ok(vec![MissingBranch::Condition(0, Some("jump"))]),
             ),
             (
                 "[\"0:jump\", \"1\", \"2\"]",
                 Ok(vec![
-                    MissingBranch::Condition(0, Some("jump".to_string())),
+                    MissingBranch::Condition(0, Some("jump")),
                     MissingBranch::Condition(1, None),
                     MissingBranch::Condition(2, None),
                 ]),
@@ -1103,10 +1404,7 @@ mod tests {
                 Ok(LineSession {
                     session_id: 0,
                     coverage: PyreportCoverage::HitCount(1),
-                    branches: Some(Some(vec![MissingBranch::Condition(
-                        0,
-                        Some("jump".to_string()),
-                    )])),
+                    branches: Some(Some(vec![MissingBranch::Condition(0, Some("jump"))])),
                     partials: None,
                     complexity: None,
                 }),
@@ -1256,26 +1554,42 @@ mod tests {
             ("1".to_string(), 101),
         ]);
 
-        // Parsing a label that is already in `labels_index` should just return it
+        // Parsing a label that is already in `labels_index` should just return its ID
         buf.input = "\"already_inserted\"";
-        assert_eq!(
-            label.parse_next(&mut buf),
-            Ok("already_inserted".to_string())
-        );
+        assert_eq!(label.parse_next(&mut buf), Ok(100));
 
         // If we parse a number like `1`, we should look for `"1"` in the labels index.
         buf.input = "1";
-        assert_eq!(label.parse_next(&mut buf), Ok("1".to_string()));
+        assert_eq!(label.parse_next(&mut buf), Ok(101));
+        buf.input = "1.0";
+        assert_eq!(label.parse_next(&mut buf), Ok(101));
 
         // Parsing a label that is not already in `labels_index` should insert it
+        let new_context = Context::new("not_already_inserted");
         buf.input = "\"not_already_inserted\"";
+        assert_eq!(label.parse_next(&mut buf), Ok(new_context.id));
+        assert_eq!(
+            buf.state.db.report_builder.report.contexts,
+            std::slice::from_ref(&new_context)
+        );
+        assert_eq!(
+            buf.state.labels_index.get("not_already_inserted"),
+            Some(&new_context.id)
+        );
+
+        // Names with escape sequences are unescaped before being looked up
+        buf.input = "\"escaped \\\"name\\\"\"";
         assert_eq!(
             label.parse_next(&mut buf),
-            Ok("not_already_inserted".to_string())
+            Ok(Context::new("escaped \"name\"").id)
         );
+        assert!(buf.state.labels_index.contains_key("escaped \"name\""));
+
+        // Numeric labels that aren't in the index are inserted too
+        buf.input = "4294967295";
         assert_eq!(
-            buf.state.db.report_builder.report.contexts,
-            &[Context::new("not_already_inserted")]
+            label.parse_next(&mut buf),
+            Ok(Context::new("4294967295").id)
         );
 
         // Malformed labels should never get to inserting
@@ -1318,7 +1632,7 @@ mod tests {
                             total: 2,
                         },
                         _coverage_type: Some(CoverageType::Branch),
-                        labels: vec!["test_case".to_string()],
+                        labels: vec![Context::new("test_case").id],
                     },
                 )),
             ),
@@ -1342,7 +1656,7 @@ mod tests {
                         session_id: 3,
                         _coverage: PyreportCoverage::Partial(),
                         _coverage_type: Some(CoverageType::Line),
-                        labels: vec!["1".to_string(), "2".to_string(), "3".to_string()],
+                        labels: ["1", "2", "3"].map(|l| Context::new(l).id).to_vec(),
                     },
                 )),
             ),
@@ -1438,7 +1752,7 @@ mod tests {
                         partials: None,
                         complexity: None,
                     }],
-                    _messages: None,
+                    messages: None,
                     _complexity: None,
                     datapoints: None,
                 }),
@@ -1465,7 +1779,7 @@ mod tests {
                             complexity: None,
                         },
                     ],
-                    _messages: None,
+                    messages: None,
                     _complexity: None,
                     datapoints: None,
                 }),
@@ -1483,7 +1797,7 @@ mod tests {
                         partials: None,
                         complexity: None,
                     }],
-                    _messages: Some(Some(JsonVal::Null)),
+                    messages: Some(Some(JsonVal::Null)),
                     _complexity: Some(Some(Complexity::Total(3))),
                     datapoints: None,
                 }),
@@ -1501,7 +1815,7 @@ mod tests {
                         partials: None,
                         complexity: None,
                     }],
-                    _messages: Some(Some(JsonVal::Null)),
+                    messages: Some(Some(JsonVal::Null)),
                     _complexity: Some(None),
                     datapoints: Some(Some(HashMap::new())),
                 }),
@@ -1519,7 +1833,7 @@ mod tests {
                         partials: None,
                         complexity: None,
                     }],
-                    _messages: Some(Some(JsonVal::Null)),
+                    messages: Some(Some(JsonVal::Null)),
                     _complexity: Some(None),
                     datapoints: Some(Some(HashMap::from([(
                         0,
@@ -1527,7 +1841,7 @@ mod tests {
                             session_id: 0,
                             _coverage: PyreportCoverage::HitCount(1),
                             _coverage_type: Some(CoverageType::Line),
-                            labels: vec!["test_case".to_string()],
+                            labels: vec![100],
                         },
                     )]))),
                 }),
@@ -1545,7 +1859,7 @@ mod tests {
                         partials: None,
                         complexity: None,
                     }],
-                    _messages: Some(Some(JsonVal::Null)),
+                    messages: Some(Some(JsonVal::Null)),
                     _complexity: Some(None),
                     datapoints: Some(Some(HashMap::from([(
                         0,
@@ -1553,7 +1867,7 @@ mod tests {
                             session_id: 0,
                             _coverage: PyreportCoverage::BranchesTaken{covered: 2, total: 2},
                             _coverage_type: Some(CoverageType::Branch),
-                            labels: vec!["test_case".to_string()],
+                            labels: vec![100],
                         },
                     )]))),
                 }),
@@ -1571,7 +1885,7 @@ mod tests {
                         partials: None,
                         complexity: None,
                     }],
-                    _messages: Some(Some(JsonVal::Null)),
+                    messages: Some(Some(JsonVal::Null)),
                     _complexity: Some(None),
                     datapoints: Some(Some(HashMap::from([(
                         0,
@@ -1579,7 +1893,7 @@ mod tests {
                             session_id: 0,
                             _coverage: PyreportCoverage::HitCount(1),
                             _coverage_type: Some(CoverageType::Method),
-                            labels: vec!["test_case".to_string()],
+                            labels: vec![100],
                         },
                     )]))),
                 }),
@@ -1619,6 +1933,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_report_line_quirks() {
+        let test_ctx = setup();
+        let mut buf = TestStream {
+            input: "",
+            state: test_ctx.parse_ctx,
+        };
+        let input = "[true, null, [[0, true], [1, \"1/2\"]]]";
+
+        // Cloverage's `true` and the Go parser's missing coverage type are
+        // corrected by default
+        buf.input = input;
+        let line = report_line.parse_next(&mut buf).unwrap();
+        let half = PyreportCoverage::BranchesTaken {
+            covered: 1,
+            total: 2,
+        };
+        assert_eq!(line.coverage, half);
+        assert_eq!(line.coverage_type, CoverageType::Line);
+        assert_eq!(line.sessions[0].coverage, half);
+        assert_eq!(line.sessions[1].coverage, half);
+        // The line and its first session were corrected
+        assert_eq!(
+            buf.state.quirks_applied,
+            BTreeMap::from([("cloverage".to_string(), 2)])
+        );
+
+        buf.state.quirks = Quirks::none().with(super::super::quirks::GoQuirks);
+        buf.input = input;
+        let line = report_line.parse_next(&mut buf).unwrap();
+        assert_eq!(line.coverage, PyreportCoverage::Partial());
+        assert_eq!(line.coverage_type, CoverageType::Line);
+        assert_eq!(line.sessions[0].coverage, PyreportCoverage::Partial());
+        assert_eq!(line.sessions[1].coverage, half);
+
+        buf.state.quirks = Quirks::none();
+        buf.input = "[\"1/2\", null, [[0, \"1/2\"]]]";
+        let line = report_line.parse_next(&mut buf).unwrap();
+        assert_eq!(line.coverage_type, CoverageType::Line);
+    }
+
     /* TODO
     #[test]
     fn test_report_line_or_empty() {
@@ -1648,7 +2003,7 @@ mod tests {
                         partials: None,
                         complexity: None,
                     }],
-                    _messages: None,
+                    messages: None,
                     _complexity: None,
                     datapoints: None,
                 })),
@@ -1665,7 +2020,7 @@ mod tests {
                         partials: None,
                         complexity: None,
                     }],
-                    _messages: Some(Some(JsonVal::Null)),
+                    messages: Some(Some(JsonVal::Null)),
                     _complexity: Some(Some(Complexity::Total(3))),
                     datapoints: None,
                 })),
@@ -1682,7 +2037,7 @@ mod tests {
                         partials: None,
                         complexity: None,
                     }],
-                    _messages: Some(Some(JsonVal::Null)),
+                    messages: Some(Some(JsonVal::Null)),
                     _complexity: Some(None),
                     datapoints: Some(Some(HashMap::from([(
                         0,
@@ -1690,7 +2045,7 @@ mod tests {
                             session_id: 0,
                             _coverage: PyreportCoverage::BranchesTaken{covered: 2, total: 2},
                             _coverage_type: Some(CoverageType::Branch),
-                            labels: vec!["test_case".to_string()],
+                            labels: vec![100],
                         },
                     )]))),
                 })),
@@ -1990,4 +2345,118 @@ mod tests {
             assert_eq!(buf.state.chunk.current_line, expected_result.2);
         }
     }
+
+    #[test]
+    fn test_parse_chunks_file_skip_malformed_chunks() {
+        let test_ctx = setup();
+        let mut buf = TestStream {
+            input: concat!(
+                "{}\n[1, null, [[0, 1]]]\n",
+                "<<<<< end_of_chunk >>>>>\n",
+                // A new label is inserted before the malformed line is reached
+                "{}\n[1, null, [[1, 1]], null, null, [[1, 1, null, [\"new_label\"]]]]\n[1, null, [[1, 1]]\n",
+                "<<<<< end_of_chunk >>>>>\n",
+                "{}\n[0, null, [[2, 0]]]\n",
+            ),
+            state: test_ctx.parse_ctx,
+        };
+        buf.state.skip_malformed_chunks = true;
+
+        assert_eq!(parse_chunks_file.parse_next(&mut buf), Ok(()));
+        assert_eq!(buf.input, "");
+        assert_eq!(buf.state.chunk.index, 3);
+        assert_eq!(buf.state.skipped_chunks, &[1]);
+
+        // Only the first and last chunks' data was kept
+        let report = &buf.state.db.report_builder.report;
+        let samples: Vec<_> = report
+            .samples
+            .iter()
+            .map(|s| (s.source_file_id, s.raw_upload_id, s.hits))
+            .collect();
+        assert_eq!(samples, &[(0, 0, Some(1)), (2, 2, Some(0))]);
+        assert!(report.contexts.is_empty());
+        assert!(report.assocs.is_empty());
+        assert!(!buf.state.labels_index.contains_key("new_label"));
+    }
+
+    #[test]
+    fn test_parse_chunks_file_drop_labels() {
+        let test_ctx = setup();
+        let mut buf = TestStream {
+            input: concat!(
+                "{\"labels_index\": {\"0\": \"test_a\", \"1\": \"test_b\"}}\n",
+                "<<<<< end_of_header >>>>>\n",
+                "{}\n[1, null, [[0, 1]], null, null, [[0, 1, null, [0, 1]]]]\n",
+                "<<<<< end_of_chunk >>>>>\n",
+                "{}\n[1, null, [[1, 1]], null, null, [[1, 1, null, [\"test_c\"]]]]\n",
+            ),
+            state: test_ctx.parse_ctx,
+        };
+        buf.state.drop_labels = true;
+
+        assert_eq!(parse_chunks_file.parse_next(&mut buf), Ok(()));
+        assert_eq!(buf.input, "");
+
+        let report = &buf.state.db.report_builder.report;
+        let samples: Vec<_> = report
+            .samples
+            .iter()
+            .map(|s| (s.source_file_id, s.raw_upload_id, s.hits))
+            .collect();
+        assert_eq!(samples, &[(0, 0, Some(1)), (1, 1, Some(1))]);
+        assert!(report.contexts.is_empty());
+        assert!(report.assocs.is_empty());
+        assert!(buf.state.labels_index.is_empty());
+    }
+
+    #[test]
+    fn test_parse_chunks_file_crlf_and_bom() {
+        // (input, expected_chunk_index, expected_line_count)
+        let test_cases = [
+            ("{}\n<<<<< end_of_header >>>>>\n{}\n", 1, 1),
+            ("{}\n[1, null, [[0, 1]]]\n", 1, 2),
+            (
+                "{\"labels_index\": {\"0\": \"test_case\"}}\n<<<<< end_of_header >>>>>\n{}\n[1, null, [[0, 1]]]\n\n<<<<< end_of_chunk >>>>>\nnull\n<<<<< end_of_chunk >>>>>\n{}\n[1, null, [[0, 1], [1, 2]], null, null, [[0, 1, null, [0]]]]\n[1, null, [[0, 1]]]\n",
+                3,
+                3,
+            ),
+        ];
+
+        for (input, expected_index, expected_line) in test_cases {
+            let crlf = input.replace('\n', "\r\n");
+            let variants = [
+                crlf.clone(),
+                format!("\u{feff}{input}"),
+                format!("\u{feff}{crlf}"),
+            ];
+            for variant in &variants {
+                let test_ctx = setup();
+                let mut buf = TestStream {
+                    input: variant,
+                    state: test_ctx.parse_ctx,
+                };
+                assert_eq!(
+                    parse_chunks_file.parse_next(&mut buf),
+                    Ok(()),
+                    "{variant:?}"
+                );
+                assert_eq!(buf.input, "");
+                assert_eq!(buf.state.chunk.index, expected_index);
+                assert_eq!(buf.state.chunk.current_line, expected_line);
+            }
+        }
+
+        // Malformed chunks are skipped up to a CRLF terminator
+        let test_ctx = setup();
+        let mut buf = TestStream {
+            input: "{}\r\n[1, null, [[0, 1]]\r\n<<<<< end_of_chunk >>>>>\r\n{}\r\n[0, null, [[2, 0]]]\r\n",
+            state: test_ctx.parse_ctx,
+        };
+        buf.state.skip_malformed_chunks = true;
+        assert_eq!(parse_chunks_file.parse_next(&mut buf), Ok(()));
+        assert_eq!(buf.input, "");
+        assert_eq!(buf.state.skipped_chunks, &[0]);
+        assert_eq!(buf.state.db.report_builder.report.samples.len(), 1);
+    }
 }
@@ -1,19 +1,77 @@
+#[cfg(feature = "sqlite")]
 use std::fs::File;
+use std::{collections::HashMap, io::Read};
 
+#[cfg(feature = "sqlite")]
 use memmap2::Mmap;
 use winnow::Parser;
 
+use super::common::{IgnoreGlobs, IngestResult, Stopwatch};
+#[cfg(feature = "sqlite")]
+use crate::report::SqliteReportBuilder;
 use crate::{
     error::{CodecovError, Result},
-    report::{SqliteReport, SqliteReportBuilder, SqliteReportBuilderTx},
+    report::{LabelPruning, Report, ReportBuilder},
 };
 
+// Reachable for benches and tests. Use `crate::prelude` instead.
+#[doc(hidden)]
 pub mod report_json;
 
+#[doc(hidden)]
 pub mod chunks;
 
+pub mod streaming;
+
+#[cfg(feature = "sqlite")]
+pub mod resumable;
+
+pub mod quirks;
+
 mod utils;
 
+/// Options controlling how lenient pyreport parsing is.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// How to handle repeated or missing session indices in the report JSON.
+    pub session_keys: report_json::SessionKeyPolicy,
+
+    /// Whether to skip chunks that fail to parse, rolling back whatever they
+    /// inserted, instead of failing the whole report.
+    pub skip_malformed_chunks: bool,
+
+    /// Whether to leave out the labels attached to each line in the chunks
+    /// file. Coverage is parsed as usual, but no
+    /// [`Context`](crate::report::models::Context)s or
+    /// [`ContextAssoc`](crate::report::models::ContextAssoc)s are created for
+    /// labels. Labels are only used for test analytics and can make up most
+    /// of a report, so this is worth setting when they won't be used.
+    pub drop_labels: bool,
+
+    /// Corrections for tools that write malformed coverage measurements.
+    /// Defaults to all of the ones we know about.
+    pub quirks: quirks::Quirks,
+
+    /// Paths of files to leave out of the report. Their chunks are skipped
+    /// without being parsed.
+    pub ignore_globs: IgnoreGlobs,
+
+    /// How to handle a chunk with lines but no file in the report JSON, or a
+    /// file in the report JSON whose chunk is missing from the chunks file.
+    /// By default the chunk's lines are skipped, the file is kept with no
+    /// coverage, and either way a warning is recorded.
+    pub chunk_mismatch: chunks::ChunkMismatchPolicy,
+
+    /// Which labels to leave off of each line. When a line has more than
+    /// [`LabelPruning::max_labels_per_sample`] labels, the ones listed first
+    /// in the chunks file are kept. The labels themselves are still inserted
+    /// as [`Context`](crate::report::models::Context)s, only the
+    /// [`ContextAssoc`](crate::report::models::ContextAssoc)s are left out.
+    /// How many were left out is recorded in
+    /// [`IngestResult::labels_pruned`].
+    pub label_pruning: LabelPruning,
+}
+
 /// Parses the two parts of our Python report class and reshapes the data into a
 /// `SqliteReport`.
 ///
@@ -34,40 +92,242 @@ mod utils;
 /// results of the report JSON parser to figure out the appropriate FKs to
 /// associate a measurement with its `SourceFile` and `Context`(s).
 ///
+/// Files left out by [`ParseOptions::ignore_globs`] are counted in
+/// [`IngestResult::files_ignored`]. Sessions dropped by
+/// [`ParseOptions::session_keys`], chunks skipped by
+/// [`ParseOptions::skip_malformed_chunks`], session keys that aren't part of
+/// the format, and chunks and files skipped by [`ParseOptions::chunk_mismatch`]
+/// are reported as warnings in the returned [`IngestResult`].
+///
 /// TODO: Make this unit testable (currently relying on integration tests)
+#[cfg(feature = "sqlite")]
 pub fn parse_pyreport(
     report_json_file: &File,
     chunks_file: &File,
     report_builder: &mut SqliteReportBuilder,
-) -> Result<()> {
-    // Encapsulate all of this in a block so that `report_builder_tx` gets torn down
-    // at the end. Otherwise, it'll hold onto a reference to `report_builder`
-    // and prevent us from consuming `report_builder` to actually build a
-    // `SqliteReport`.
-    {
-        let mut report_builder_tx = report_builder.transaction()?;
-
-        // Memory-map the input file so we don't have to read the whole thing into RAM
-        let mmap_handle = unsafe { Mmap::map(report_json_file)? };
-        let report_json::ParsedReportJson { files, sessions } =
-            report_json::parse_report_json(&mmap_handle, &mut report_builder_tx)?;
-
-        // Replace our mmap handle so the first one can be unmapped
-        let mmap_handle = unsafe { Mmap::map(chunks_file)? };
-        let buf = unsafe { std::str::from_utf8_unchecked(&mmap_handle[..]) };
-
-        // Move `report_builder` from the report JSON's parse context to this one
-        let chunks_ctx = chunks::ParseCtx::new(report_builder_tx, files, sessions);
-        let mut chunks_stream =
-            chunks::ReportOutputStream::<&str, SqliteReport, SqliteReportBuilderTx> {
-                input: buf,
-                state: chunks_ctx,
-            };
-        chunks::parse_chunks_file
-            .parse_next(&mut chunks_stream)
-            .map_err(|e| e.into_inner().unwrap_or_default())
-            .map_err(CodecovError::ParserError)?;
+) -> Result<IngestResult> {
+    parse_pyreport_with_options(
+        report_json_file,
+        chunks_file,
+        report_builder,
+        &ParseOptions::default(),
+    )
+}
+
+/// Like [`parse_pyreport`], but with non-default [`ParseOptions`].
+#[cfg(feature = "sqlite")]
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub fn parse_pyreport_with_options(
+    report_json_file: &File,
+    chunks_file: &File,
+    report_builder: &mut SqliteReportBuilder,
+    options: &ParseOptions,
+) -> Result<IngestResult> {
+    // Memory-map the input files so we don't have to read them into RAM
+    let report_json_mmap = unsafe { Mmap::map(report_json_file)? };
+    let chunks_mmap = unsafe { Mmap::map(chunks_file)? };
+    let chunks = unsafe { std::str::from_utf8_unchecked(&chunks_mmap[..]) };
+
+    // The transaction is committed when the builder we get back is dropped.
+    // Dropping it here also releases its borrow of `report_builder` so it can
+    // be consumed to actually build a `SqliteReport`.
+    let report_builder_tx = report_builder.transaction()?;
+    let (_, result) =
+        parse_pyreport_buffers(&report_json_mmap, chunks, report_builder_tx, options)?;
+
+    Ok(result)
+}
+
+/// Parses a report JSON and chunks file that are already in memory into any
+/// [`ReportBuilder`]. This is what `parse_pyreport_with_options` does after
+/// memory-mapping its files, and what to use where there's no file IO or
+/// SQLite, e.g. with a `MemoryReportBuilder`. Returns `report_builder` so it
+/// can be built, along with what was inserted into it.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub fn parse_pyreport_buffers<R: Report, B: ReportBuilder<R>>(
+    report_json: &[u8],
+    chunks: &str,
+    mut report_builder: B,
+    options: &ParseOptions,
+) -> Result<(B, IngestResult)> {
+    let stopwatch = Stopwatch::start();
+    let mut result = IngestResult::default();
+    let parsed = parse_report_json(report_json, &mut report_builder, options, &mut result)?;
+
+    let mut chunks_ctx = chunks_parse_ctx(report_builder, parsed.files, parsed.sessions, options);
+    chunks_ctx.ignored_chunks = parsed.ignored_chunks;
+    let mut chunks_stream = chunks::ReportOutputStream::<&str, R, B> {
+        input: chunks,
+        state: chunks_ctx,
+    };
+    chunks::parse_chunks_file
+        .parse_next(&mut chunks_stream)
+        .map_err(|e| {
+            CodecovError::parser_error(
+                chunks,
+                chunks_stream.input,
+                e.into_inner().unwrap_or_default(),
+            )
+        })?;
+
+    let report_builder = finish_ingest(chunks_stream.state, &mut result, &stopwatch)?;
+    Ok((report_builder, result))
+}
+
+/// Like [`parse_pyreport_buffers`], but reads the chunks file from `chunks`
+/// as it's parsed instead of needing all of it in memory, so it can come
+/// straight from a decompression stream or a network body. See
+/// [`streaming::parse_chunks_reader`]. The report JSON is small next to the
+/// chunks file and is read into memory first.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub fn parse_pyreport_readers<R: Report, B: ReportBuilder<R>>(
+    mut report_json: impl Read,
+    chunks: impl Read,
+    mut report_builder: B,
+    options: &ParseOptions,
+) -> Result<(B, IngestResult)> {
+    let stopwatch = Stopwatch::start();
+    let mut result = IngestResult::default();
+    let mut report_json_buf = Vec::new();
+    report_json.read_to_end(&mut report_json_buf)?;
+    let parsed = parse_report_json(&report_json_buf, &mut report_builder, options, &mut result)?;
+
+    let mut chunks_ctx = chunks_parse_ctx(report_builder, parsed.files, parsed.sessions, options);
+    chunks_ctx.ignored_chunks = parsed.ignored_chunks;
+    let chunks_ctx =
+        streaming::parse_chunks_reader(chunks, chunks_ctx, streaming::DEFAULT_WINDOW_SIZE)?;
+
+    let report_builder = finish_ingest(chunks_ctx, &mut result, &stopwatch)?;
+    Ok((report_builder, result))
+}
+
+/// Parses the report JSON, returning the maps from chunk index to file ID and
+/// from session ID to context ID that the chunks parser needs, along with the
+/// chunk indices of ignored files. Files and dropped sessions are recorded in
+/// `result`.
+fn parse_report_json<R: Report, B: ReportBuilder<R>>(
+    report_json: &[u8],
+    report_builder: &mut B,
+    options: &ParseOptions,
+    result: &mut IngestResult,
+) -> Result<report_json::ParsedReportJson> {
+    let parsed = report_json::parse_report_json_with_options(report_json, report_builder, options)?;
+    #[cfg(feature = "tracing")]
+    if !parsed.dropped_sessions.is_empty() {
+        tracing::warn!(dropped_sessions = ?parsed.dropped_sessions, "dropped duplicate sessions");
+    }
+    result.files_touched = parsed.files.len();
+    result.files_ignored = parsed.ignored_chunks.len();
+    result.warnings.extend(
+        parsed
+            .dropped_sessions
+            .iter()
+            .map(|index| format!("dropped duplicate session {index}")),
+    );
+    #[cfg(feature = "tracing")]
+    if !parsed.unknown_session_keys.is_empty() {
+        tracing::warn!(keys = ?parsed.unknown_session_keys, "unknown session keys");
     }
+    result.warnings.extend(
+        parsed
+            .unknown_session_keys
+            .iter()
+            .map(|key| format!("unknown session key {key:?}")),
+    );
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        files = parsed.files.len(),
+        sessions = parsed.sessions.len(),
+        "parsed report JSON"
+    );
+    Ok(parsed)
+}
+
+/// Records what the chunks parser inserted in `result` and takes the report
+/// builder back out of its parse context. Fails if files in the report JSON
+/// have no chunk and [`ParseOptions::chunk_mismatch`] says to.
+fn finish_ingest<R: Report, B: ReportBuilder<R>>(
+    chunks_ctx: chunks::ParseCtx<R, B>,
+    result: &mut IngestResult,
+    stopwatch: &Stopwatch,
+) -> Result<B> {
+    let missing_chunks = missing_chunks(
+        &chunks_ctx.report_json_files,
+        chunks_ctx.chunk.index,
+        chunks_ctx.chunk_mismatch,
+    )?;
+    result.samples_inserted = chunks_ctx.samples_inserted;
+    result.quirks_applied = chunks_ctx.quirks_applied;
+    result.labels_pruned = chunks_ctx.labels_pruned;
+    result.warnings.extend(chunks_ctx.quirk_warnings);
+    result.warnings.extend(chunk_warnings(
+        &chunks_ctx.skipped_chunks,
+        &chunks_ctx.orphan_chunks,
+        &missing_chunks,
+    ));
+    result.duration = stopwatch.elapsed();
+    Ok(chunks_ctx.db.report_builder)
+}
+
+/// Finds the chunk indices of files in the report JSON that the chunks file
+/// ended before reaching, given how many chunks it had. Their files are left
+/// in the report with no coverage, unless `policy` is
+/// [`chunks::ChunkMismatchPolicy::Error`], in which case this fails.
+fn missing_chunks(
+    files: &HashMap<usize, i64>,
+    chunks_parsed: usize,
+    policy: chunks::ChunkMismatchPolicy,
+) -> Result<Vec<usize>> {
+    let mut missing: Vec<usize> = files
+        .keys()
+        .copied()
+        .filter(|&index| index >= chunks_parsed)
+        .collect();
+    missing.sort_unstable();
+    if !missing.is_empty() && policy == chunks::ChunkMismatchPolicy::Error {
+        return Err(<serde_json::Error as serde::de::Error>::custom(format!(
+            "missing chunks for chunk indices {missing:?}"
+        ))
+        .into());
+    }
+    #[cfg(feature = "tracing")]
+    if !missing.is_empty() {
+        tracing::warn!(?missing, "no chunks for files in the report JSON");
+    }
+    Ok(missing)
+}
+
+/// The warnings for chunks that were skipped and files that had no chunk.
+fn chunk_warnings<'a>(
+    skipped_chunks: &'a [usize],
+    orphan_chunks: &'a [usize],
+    missing_chunks: &'a [usize],
+) -> impl Iterator<Item = String> + 'a {
+    let skipped = skipped_chunks
+        .iter()
+        .map(|index| format!("skipped malformed chunk {index}"));
+    let orphans = orphan_chunks
+        .iter()
+        .map(|index| format!("skipped chunk {index} with no file in the report JSON"));
+    let missing = missing_chunks
+        .iter()
+        .map(|index| format!("no chunk for file with chunk index {index}"));
+    skipped.chain(orphans).chain(missing)
+}
 
-    Ok(())
+/// Moves `report_builder` from the report JSON's parse context to the chunks
+/// file's.
+fn chunks_parse_ctx<R: Report, B: ReportBuilder<R>>(
+    report_builder: B,
+    files: HashMap<usize, i64>,
+    sessions: HashMap<usize, i64>,
+    options: &ParseOptions,
+) -> chunks::ParseCtx<R, B> {
+    let mut chunks_ctx = chunks::ParseCtx::new(report_builder, files, sessions);
+    chunks_ctx.skip_malformed_chunks = options.skip_malformed_chunks;
+    chunks_ctx.drop_labels = options.drop_labels;
+    chunks_ctx.quirks = options.quirks.clone();
+    chunks_ctx.chunk_mismatch = options.chunk_mismatch;
+    chunks_ctx.label_pruning = options.label_pruning;
+    chunks_ctx
 }
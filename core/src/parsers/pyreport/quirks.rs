@@ -0,0 +1,452 @@
+//! Corrections for coverage tools (or our own parsers for their formats) that
+//! write coverage measurements into chunks files incorrectly.
+//!
+//! Each tool's quirks are a separate [`QuirksProfile`] so they can be tested
+//! on their own. [`Quirks`] is the set of profiles applied while parsing,
+//! chosen with [`ParseOptions::quirks`](super::ParseOptions::quirks). By
+//! default, every profile in this module is applied.
+
+use std::{fmt, sync::Arc};
+
+use crate::report::pyreport::types::{CoverageType, PyreportCoverage};
+
+/// Corrects measurements from a particular tool that are malformed in a way
+/// we can recognize.
+pub trait QuirksProfile: Send + Sync {
+    /// A short name for the profile, used to tell profiles apart in debug
+    /// output and when comparing [`Quirks`].
+    fn name(&self) -> &'static str;
+
+    /// Returns the corrected coverage and coverage type for a measurement, or
+    /// `None` if this profile doesn't recognize anything wrong with it.
+    fn normalize(
+        &self,
+        coverage: &PyreportCoverage,
+        coverage_type: CoverageType,
+    ) -> Option<(PyreportCoverage, CoverageType)>;
+
+    /// Describes what's wrong with a measurement this profile recognizes but
+    /// can't correct, or returns `None`. Such measurements are left as they
+    /// were written.
+    fn warning(
+        &self,
+        _coverage: &PyreportCoverage,
+        _coverage_type: CoverageType,
+    ) -> Option<String> {
+        None
+    }
+}
+
+/// Clojure's Cloverage tool uses `true` to indicate partial coverage without
+/// giving any specific information about the missed/covered branches. Our
+/// parsers for other formats just make up "1/2" and move on, so we do that
+/// here as well.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CloverageQuirks;
+
+impl QuirksProfile for CloverageQuirks {
+    fn name(&self) -> &'static str {
+        "cloverage"
+    }
+
+    fn normalize(
+        &self,
+        coverage: &PyreportCoverage,
+        coverage_type: CoverageType,
+    ) -> Option<(PyreportCoverage, CoverageType)> {
+        match coverage {
+            PyreportCoverage::Partial() => Some((
+                PyreportCoverage::BranchesTaken {
+                    covered: 1,
+                    total: 2,
+                },
+                coverage_type,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// For method coverage, JaCoCo contains aggregated instruction coverage,
+/// branch coverage, and cyclomatic complexity metrics. If the branch coverage
+/// fields are present and non-zero, our parser will prefer them even though
+/// method coverage should be an int. We normalize by treating the numerator of
+/// the branch coverage as a hit count, so "0/2" is a miss but "3/4" is 3 hits.
+/// Not great, but the data is bugged to begin with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JacocoQuirks;
+
+impl QuirksProfile for JacocoQuirks {
+    fn name(&self) -> &'static str {
+        "jacoco"
+    }
+
+    fn normalize(
+        &self,
+        coverage: &PyreportCoverage,
+        coverage_type: CoverageType,
+    ) -> Option<(PyreportCoverage, CoverageType)> {
+        match (coverage, coverage_type) {
+            (PyreportCoverage::BranchesTaken { covered, .. }, CoverageType::Method) => {
+                Some((PyreportCoverage::HitCount(*covered), CoverageType::Method))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Our Go parser does not properly fill out the `coverage_type` field. If the
+/// `coverage` field has branch data in it, override the coverage type.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GoQuirks;
+
+impl QuirksProfile for GoQuirks {
+    fn name(&self) -> &'static str {
+        "go"
+    }
+
+    fn normalize(
+        &self,
+        coverage: &PyreportCoverage,
+        coverage_type: CoverageType,
+    ) -> Option<(PyreportCoverage, CoverageType)> {
+        match (coverage, coverage_type) {
+            (PyreportCoverage::BranchesTaken { .. }, CoverageType::Line) => {
+                Some((coverage.clone(), CoverageType::Branch))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// We see some instances of Scala Scoverage reports being incorrectly
+/// translated into Cobertura reports. In Scoverage, 0 for branch coverage
+/// means miss, 1 means partial, and 2 means hit. It seems when converting to
+/// Cobertura, the value is taken as a raw hit count and not converted to
+/// `branch-rate` or something, and our Cobertura parser doesn't handle it. So,
+/// we handle it here. Any other hit count can't have come from Scoverage, so
+/// it's left alone with a warning.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScoverageQuirks;
+
+impl QuirksProfile for ScoverageQuirks {
+    fn name(&self) -> &'static str {
+        "scoverage"
+    }
+
+    fn normalize(
+        &self,
+        coverage: &PyreportCoverage,
+        coverage_type: CoverageType,
+    ) -> Option<(PyreportCoverage, CoverageType)> {
+        match (coverage, coverage_type) {
+            (PyreportCoverage::HitCount(n @ 0..=2), CoverageType::Branch) => Some((
+                PyreportCoverage::BranchesTaken {
+                    covered: *n,
+                    total: 2,
+                },
+                CoverageType::Branch,
+            )),
+            _ => None,
+        }
+    }
+
+    fn warning(&self, coverage: &PyreportCoverage, coverage_type: CoverageType) -> Option<String> {
+        match (coverage, coverage_type) {
+            (PyreportCoverage::HitCount(n @ 3..), CoverageType::Branch) => Some(format!(
+                "branch hit count {n} is not a Scoverage branch status (0, 1 or 2)"
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// The [`QuirksProfile`]s to apply to each measurement in a chunks file. The
+/// first profile that recognizes a measurement corrects it and the rest are
+/// skipped.
+#[derive(Clone)]
+pub struct Quirks {
+    profiles: Vec<Arc<dyn QuirksProfile>>,
+}
+
+impl Quirks {
+    /// Applies `profiles`, in order.
+    pub fn new(profiles: Vec<Arc<dyn QuirksProfile>>) -> Quirks {
+        Quirks { profiles }
+    }
+
+    /// Applies no profiles, leaving every measurement as it was written.
+    pub fn none() -> Quirks {
+        Quirks::new(vec![])
+    }
+
+    /// Adds `profile` after the ones already applied.
+    pub fn with(mut self, profile: impl QuirksProfile + 'static) -> Quirks {
+        self.profiles.push(Arc::new(profile));
+        self
+    }
+
+    /// Account for some quirks and malformed data. See each
+    /// [`QuirksProfile`] for details.
+    pub fn normalize(
+        &self,
+        coverage: &PyreportCoverage,
+        coverage_type: CoverageType,
+    ) -> (PyreportCoverage, CoverageType) {
+        self.correct(coverage, coverage_type)
+            .map(|(_, corrected)| corrected)
+            .unwrap_or_else(|| (coverage.clone(), coverage_type))
+    }
+
+    /// Like [`Quirks::normalize`], but returns `None` if no profile recognized
+    /// anything wrong, and otherwise the name of the profile that corrected
+    /// the measurement along with the correction.
+    pub fn correct(
+        &self,
+        coverage: &PyreportCoverage,
+        coverage_type: CoverageType,
+    ) -> Option<(&'static str, (PyreportCoverage, CoverageType))> {
+        self.profiles.iter().find_map(|profile| {
+            profile
+                .normalize(coverage, coverage_type)
+                .map(|corrected| (profile.name(), corrected))
+        })
+    }
+
+    /// The first warning any profile has about a measurement. See
+    /// [`QuirksProfile::warning`].
+    pub fn warning(
+        &self,
+        coverage: &PyreportCoverage,
+        coverage_type: CoverageType,
+    ) -> Option<String> {
+        self.profiles
+            .iter()
+            .find_map(|profile| profile.warning(coverage, coverage_type))
+    }
+}
+
+impl Default for Quirks {
+    /// Every profile in this module.
+    fn default() -> Quirks {
+        Quirks::none()
+            .with(CloverageQuirks)
+            .with(JacocoQuirks)
+            .with(GoQuirks)
+            .with(ScoverageQuirks)
+    }
+}
+
+impl fmt::Debug for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.profiles.iter().map(|profile| profile.name()))
+            .finish()
+    }
+}
+
+impl PartialEq for Quirks {
+    fn eq(&self, other: &Quirks) -> bool {
+        self.profiles
+            .iter()
+            .map(|profile| profile.name())
+            .eq(other.profiles.iter().map(|profile| profile.name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARTIAL: PyreportCoverage = PyreportCoverage::Partial();
+    const HALF: PyreportCoverage = PyreportCoverage::BranchesTaken {
+        covered: 1,
+        total: 2,
+    };
+
+    #[test]
+    fn test_cloverage_quirks() {
+        assert_eq!(
+            CloverageQuirks.normalize(&PARTIAL, CoverageType::Branch),
+            Some((HALF, CoverageType::Branch))
+        );
+        assert_eq!(
+            CloverageQuirks.normalize(&PARTIAL, CoverageType::Line),
+            Some((HALF, CoverageType::Line))
+        );
+        assert_eq!(CloverageQuirks.normalize(&HALF, CoverageType::Branch), None);
+    }
+
+    #[test]
+    fn test_jacoco_quirks() {
+        assert_eq!(
+            JacocoQuirks.normalize(&HALF, CoverageType::Method),
+            Some((PyreportCoverage::HitCount(1), CoverageType::Method))
+        );
+        assert_eq!(
+            JacocoQuirks.normalize(&PyreportCoverage::HitCount(3), CoverageType::Method),
+            None
+        );
+        assert_eq!(JacocoQuirks.normalize(&HALF, CoverageType::Line), None);
+    }
+
+    #[test]
+    fn test_go_quirks() {
+        assert_eq!(
+            GoQuirks.normalize(&HALF, CoverageType::Line),
+            Some((HALF, CoverageType::Branch))
+        );
+        assert_eq!(
+            GoQuirks.normalize(&PyreportCoverage::HitCount(3), CoverageType::Line),
+            None
+        );
+        assert_eq!(GoQuirks.normalize(&HALF, CoverageType::Branch), None);
+    }
+
+    #[test]
+    fn test_scoverage_quirks() {
+        for n in 0..=2 {
+            assert_eq!(
+                ScoverageQuirks.normalize(&PyreportCoverage::HitCount(n), CoverageType::Branch),
+                Some((
+                    PyreportCoverage::BranchesTaken {
+                        covered: n,
+                        total: 2
+                    },
+                    CoverageType::Branch
+                ))
+            );
+        }
+        assert_eq!(
+            ScoverageQuirks.normalize(&PyreportCoverage::HitCount(3), CoverageType::Line),
+            None
+        );
+
+        // Counts Scoverage can't have written are left alone with a warning
+        assert_eq!(
+            ScoverageQuirks.normalize(&PyreportCoverage::HitCount(3), CoverageType::Branch),
+            None
+        );
+        assert!(ScoverageQuirks
+            .warning(&PyreportCoverage::HitCount(3), CoverageType::Branch)
+            .is_some());
+        assert_eq!(
+            ScoverageQuirks.warning(&PyreportCoverage::HitCount(2), CoverageType::Branch),
+            None
+        );
+        assert_eq!(
+            ScoverageQuirks.warning(&PyreportCoverage::HitCount(3), CoverageType::Line),
+            None
+        );
+    }
+
+    #[test]
+    fn test_default_quirks() {
+        let quirks = Quirks::default();
+
+        // Cases that don't need adjustment
+        assert_eq!(
+            quirks.normalize(&PyreportCoverage::HitCount(3), CoverageType::Line),
+            (PyreportCoverage::HitCount(3), CoverageType::Line)
+        );
+        assert_eq!(
+            quirks.normalize(&PyreportCoverage::HitCount(3), CoverageType::Method),
+            (PyreportCoverage::HitCount(3), CoverageType::Method)
+        );
+        assert_eq!(
+            quirks.normalize(
+                &PyreportCoverage::BranchesTaken {
+                    covered: 2,
+                    total: 4
+                },
+                CoverageType::Branch
+            ),
+            (
+                PyreportCoverage::BranchesTaken {
+                    covered: 2,
+                    total: 4
+                },
+                CoverageType::Branch
+            )
+        );
+
+        assert_eq!(
+            quirks.normalize(&PyreportCoverage::HitCount(3), CoverageType::Branch),
+            (PyreportCoverage::HitCount(3), CoverageType::Branch)
+        );
+
+        // Cases that need adjustment
+        assert_eq!(
+            quirks.normalize(&PARTIAL, CoverageType::Branch),
+            (HALF, CoverageType::Branch)
+        );
+        assert_eq!(
+            quirks.normalize(&PyreportCoverage::HitCount(1), CoverageType::Branch),
+            (HALF, CoverageType::Branch)
+        );
+        assert_eq!(
+            quirks.normalize(&HALF, CoverageType::Line),
+            (HALF, CoverageType::Branch)
+        );
+        assert_eq!(
+            quirks.normalize(&HALF, CoverageType::Method),
+            (PyreportCoverage::HitCount(1), CoverageType::Method)
+        );
+    }
+
+    #[test]
+    fn test_correct_names_profile() {
+        let quirks = Quirks::default();
+        assert_eq!(
+            quirks.correct(&PARTIAL, CoverageType::Line),
+            Some(("cloverage", (HALF, CoverageType::Line)))
+        );
+        assert_eq!(
+            quirks.correct(&HALF, CoverageType::Line),
+            Some(("go", (HALF, CoverageType::Branch)))
+        );
+        assert_eq!(
+            quirks.correct(&PyreportCoverage::HitCount(3), CoverageType::Line),
+            None
+        );
+    }
+
+    #[test]
+    fn test_custom_quirks() {
+        struct ZeroIsPartial;
+        impl QuirksProfile for ZeroIsPartial {
+            fn name(&self) -> &'static str {
+                "zero_is_partial"
+            }
+
+            fn normalize(
+                &self,
+                coverage: &PyreportCoverage,
+                coverage_type: CoverageType,
+            ) -> Option<(PyreportCoverage, CoverageType)> {
+                (*coverage == PyreportCoverage::HitCount(0)).then_some((PARTIAL, coverage_type))
+            }
+        }
+
+        let quirks = Quirks::none();
+        assert_eq!(
+            quirks.normalize(&PARTIAL, CoverageType::Line),
+            (PARTIAL, CoverageType::Line)
+        );
+
+        // Profiles are tried in order and only the first match applies
+        let quirks = Quirks::none().with(ZeroIsPartial).with(CloverageQuirks);
+        assert_eq!(format!("{quirks:?}"), r#"["zero_is_partial", "cloverage"]"#);
+        assert_eq!(
+            quirks.normalize(&PyreportCoverage::HitCount(0), CoverageType::Line),
+            (PARTIAL, CoverageType::Line)
+        );
+        assert_eq!(
+            quirks.normalize(&PARTIAL, CoverageType::Line),
+            (HALF, CoverageType::Line)
+        );
+
+        assert_eq!(quirks, quirks.clone());
+        assert_ne!(quirks, Quirks::default());
+    }
+}
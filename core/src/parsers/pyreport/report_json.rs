@@ -25,7 +25,8 @@
 //!
 //! The `files` are key-value pairs where the key is a filename and the value is
 //! a `ReportFileSummary`. We primarily care about the chunks_index field and
-//! can compute the totals on-demand later.
+//! can compute the file totals on-demand later. Per-session totals are kept as
+//! [`models::SessionFileTotals`] where a report still has them.
 //!
 //! The format is messy and can only be fully understood by reading the Python
 //! source in our `shared` repository's
@@ -38,7 +39,7 @@
 //! "filename.rs": [
 //!     chunks_index: int,
 //!     file_totals: ReportTotals,
-//!     session_totals: null, // (formerly SessionTotalsArray, but ignored now)
+//!     session_totals: SessionTotalsArray, // (usually null nowadays)
 //!     diff_totals: ReportTotals (probably),
 //! ]
 //! ```
@@ -51,8 +52,11 @@
 //! It's a dict mapping a session ID to a `SessionTotals` (which is just a type
 //! alias for `ReportTotals` and a "meta" key with extra information including
 //! how many sessions there are in the map, and old reports may still have it.
-//! There's an even older format which is just a flat list. In any case, we
-//! ignore the field now.
+//! There's an even older format which is just a flat list indexed by session
+//! ID. Either way, each session's totals are inserted as a
+//! [`models::SessionFileTotals`] for the upload the session became. Totals for
+//! sessions that aren't in `sessions` are dropped, and anything that isn't a
+//! list of totals is ignored rather than failing the report.
 //!
 //! Input example:
 //! ```json
@@ -160,36 +164,302 @@
 //!      "se": {}               # session extras
 //!    }
 //! ```
+//!
+//! Keys a session has that aren't listed above, e.g. from a newer version of
+//! our Python code, are kept in its `session_extras` unless that already has
+//! a key of the same name, and are returned in
+//! [`ParsedReportJson::unknown_session_keys`].
+//!
+//! Session indices are usually contiguous, but a session index may be missing
+//! (e.g. if an upload was removed from the report) or, in malformed reports,
+//! appear more than once. [`SessionKeyPolicy`] controls how those are
+//! handled.
+//!
+//! ## Variations
+//!
+//! Reports written by different versions of our Python code don't agree on
+//! every detail, so we also accept:
+//! - Chunk indices written as strings (`"0"`) as well as ints (`0`)
+//! - Files with fewer than four slots, as long as the chunk index is there, or
+//!   with extra slots after the diff totals
+//! - `sessions` written as a list, in which case each session's index is its
+//!   position in the list
+//! - Session timestamps written as floats, which are truncated to whole seconds
 
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+};
 
-use serde::{de::IgnoredAny, Deserialize};
-use serde_json::Value;
+use serde::{
+    de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::{Map, Value};
 
+use super::ParseOptions;
 use crate::{
     error::CodecovError,
     report::{models, Report, ReportBuilder},
 };
 
+/// How [`parse_report_json_with_options`] treats session indices that are
+/// repeated or missing.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum SessionKeyPolicy {
+    /// Fail on any repeated or missing session index.
+    Error,
+
+    /// Keep the last session for a repeated index and drop the rest, the way
+    /// a JSON object's last duplicate key usually wins. Missing indices are
+    /// tolerated.
+    #[default]
+    KeepLast,
+
+    /// Keep the first session for a repeated index and drop the rest. Missing
+    /// indices are tolerated.
+    Skip,
+
+    /// Combine sessions with a repeated index into a single upload, keeping
+    /// the first non-null value for each field. Missing indices are
+    /// tolerated.
+    Merge,
+}
+
+/// An index that may be written as an int or as a string holding one, like a
+/// chunk index or a session index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Index(usize);
+
+impl<'de> Deserialize<'de> for Index {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct IndexVisitor;
+
+        impl Visitor<'_> for IndexVisitor {
+            type Value = Index;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a non-negative integer or a string holding one")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                usize::try_from(v)
+                    .map(Index)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                usize::try_from(v)
+                    .map(Index)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse()
+                    .map(Index)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_any(IndexVisitor)
+    }
+}
+
+/// A Unix timestamp in seconds, written as an int or, by Python code that
+/// stored `time.time()` as-is, a float. Fractions of a second are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Seconds(i64);
+
+impl<'de> Deserialize<'de> for Seconds {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SecondsVisitor;
+
+        impl Visitor<'_> for SecondsVisitor {
+            type Value = Seconds;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a number of seconds")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                i64::try_from(v)
+                    .map(Seconds)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(Seconds(v))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                if v.is_finite() && v >= i64::MIN as f64 && v < i64::MAX as f64 {
+                    Ok(Seconds(v.trunc() as i64))
+                } else {
+                    Err(E::invalid_value(de::Unexpected::Float(v), &self))
+                }
+            }
+        }
+
+        deserializer.deserialize_any(SecondsVisitor)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct ReportJson {
-    // NOTE: these two are `BTreeMap` only to have stable iteration order in tests
+    // NOTE: this is a `BTreeMap` only to have stable iteration order in tests
     files: BTreeMap<String, File>,
-    sessions: BTreeMap<usize, Session>,
+    sessions: SessionEntries,
 }
 
-#[derive(Debug, Deserialize)]
-// this really is:
-// - index in chunks
-// - file totals
-// - session totals
-// - diff totals
-struct File(usize, IgnoredAny, IgnoredAny, IgnoredAny);
+/// Every `(index, session)` entry in the order they appear, duplicates
+/// included. Deserializing straight into a map would silently keep only the
+/// last session for a repeated index. A list of sessions is indexed by
+/// position.
+#[derive(Debug)]
+struct SessionEntries(Vec<(usize, Session)>);
+
+impl<'de> Deserialize<'de> for SessionEntries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = SessionEntries;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of session indices to sessions or a list of sessions")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or_default());
+                while let Some((Index(index), session)) = map.next_entry()? {
+                    entries.push((index, session));
+                }
+                Ok(SessionEntries(entries))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+                while let Some(session) = seq.next_element()? {
+                    entries.push((entries.len(), session));
+                }
+                Ok(SessionEntries(entries))
+            }
+        }
+
+        deserializer.deserialize_any(EntriesVisitor)
+    }
+}
+
+/// A file's entry in `files`, which really is:
+/// - index in chunks
+/// - file totals
+/// - session totals
+/// - diff totals
+///
+/// Only the chunk index is required, and we don't use the file or diff
+/// totals.
+#[derive(Debug)]
+struct File {
+    chunk_index: usize,
+    session_totals: Option<Value>,
+}
+
+impl<'de> Deserialize<'de> for File {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FileVisitor;
+
+        impl<'de> Visitor<'de> for FileVisitor {
+            type Value = File;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a list starting with a chunk index")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let Some(Index(chunk_index)) = seq.next_element()? else {
+                    return Err(de::Error::invalid_length(0, &self));
+                };
+                let _file_totals = seq.next_element::<IgnoredAny>()?;
+                let session_totals = seq.next_element::<Option<Value>>()?.flatten();
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
+                Ok(File {
+                    chunk_index,
+                    session_totals,
+                })
+            }
+        }
+
+        deserializer.deserialize_seq(FileVisitor)
+    }
+}
+
+/// Each `(session index, totals)` in a `SessionTotalsArray`, in either of its
+/// formats. The `"meta"` key and anything else that isn't a list of totals is
+/// skipped.
+fn session_totals(session_totals: &Value) -> Vec<(usize, &[Value])> {
+    match session_totals {
+        Value::Object(map) => map
+            .iter()
+            .filter_map(|(index, totals)| {
+                Some((index.parse().ok()?, totals.as_array()?.as_slice()))
+            })
+            .collect(),
+        Value::Array(list) => list
+            .iter()
+            .enumerate()
+            .filter_map(|(index, totals)| Some((index, totals.as_array()?.as_slice())))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Builds a [`models::SessionFileTotals`] from a `ReportTotals` list. Trailing
+/// zeros are stripped when these are written, so missing fields are 0.
+fn session_file_totals(
+    source_file_id: i64,
+    raw_upload_id: i64,
+    totals: &[Value],
+) -> models::SessionFileTotals {
+    let int = |i: usize| {
+        match totals.get(i) {
+            Some(Value::Number(n)) => n.as_i64().or_else(|| n.as_f64().map(|f| f as i64)),
+            Some(Value::String(s)) => s.parse().ok(),
+            _ => None,
+        }
+        .unwrap_or_default()
+    };
+    let coverage = match totals.get(5) {
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(Value::Number(n)) => Some(n.to_string()),
+        _ => None,
+    };
+
+    models::SessionFileTotals {
+        source_file_id,
+        raw_upload_id,
+        files: int(0),
+        lines: int(1),
+        hits: int(2),
+        misses: int(3),
+        partials: int(4),
+        coverage,
+        branches: int(6),
+        methods: int(7),
+        messages: int(8),
+        sessions: int(9),
+        complexity: int(10),
+        complexity_total: int(11),
+        diff: int(12),
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct Session {
+    // Recomputed from the chunks, so never kept
+    #[serde(rename = "t", default)]
+    _totals: Option<IgnoredAny>,
     #[serde(rename = "d")]
-    timestamp: Option<i64>,
+    timestamp: Option<Seconds>,
     #[serde(rename = "a")]
     raw_upload_url: Option<String>,
     #[serde(rename = "f")]
@@ -212,37 +482,164 @@ struct Session {
     session_type: Option<String>,
     #[serde(rename = "se")]
     session_extras: Option<Value>,
+    #[serde(flatten)]
+    unknown: Map<String, Value>,
+}
+
+impl Session {
+    /// Fill in any fields `self` is missing with the values from `other`.
+    fn merge(&mut self, other: Session) {
+        fn fill<T>(field: &mut Option<T>, other: Option<T>) {
+            if field.is_none() {
+                *field = other;
+            }
+        }
+        fill(&mut self.timestamp, other.timestamp);
+        fill(&mut self.raw_upload_url, other.raw_upload_url);
+        fill(&mut self.flags, other.flags);
+        fill(&mut self.provider, other.provider);
+        fill(&mut self.build, other.build);
+        fill(&mut self.name, other.name);
+        fill(&mut self.job_name, other.job_name);
+        fill(&mut self.ci_run_url, other.ci_run_url);
+        fill(&mut self.state, other.state);
+        fill(&mut self.env, other.env);
+        fill(&mut self.session_type, other.session_type);
+        fill(&mut self.session_extras, other.session_extras);
+        for (key, value) in other.unknown {
+            self.unknown.entry(key).or_insert(value);
+        }
+    }
+
+    /// Moves any keys that aren't part of the format into `session_extras`,
+    /// without replacing keys it already has.
+    fn keep_unknown_keys(&mut self) {
+        if self.unknown.is_empty() {
+            return;
+        }
+        let extras = self
+            .session_extras
+            .get_or_insert_with(|| Value::Object(Map::new()));
+        if extras.is_null() {
+            *extras = Value::Object(Map::new());
+        }
+        if let Value::Object(extras) = extras {
+            for (key, value) in std::mem::take(&mut self.unknown) {
+                extras.entry(key).or_insert(value);
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ParsedReportJson {
     pub files: HashMap<usize, i64>,
     pub sessions: HashMap<usize, i64>,
+
+    /// Session indices that appeared more than once and had all but one of
+    /// their occurrences dropped per [`SessionKeyPolicy::KeepLast`] or
+    /// [`SessionKeyPolicy::Skip`].
+    pub dropped_sessions: Vec<usize>,
+
+    /// Session indices missing between 0 and the highest index present.
+    pub missing_sessions: Vec<usize>,
+
+    /// Chunk indices of files left out per [`ParseOptions::ignore_globs`].
+    pub ignored_chunks: HashSet<usize>,
+
+    /// Keys sessions had that aren't part of the format. Each is kept in its
+    /// session's `session_extras` if that's an object without the same key.
+    pub unknown_session_keys: BTreeSet<String>,
 }
 
+/// Like [`parse_report_json_with_options`] with the default [`ParseOptions`].
 pub fn parse_report_json<B, R>(
     input: &[u8],
     builder: &mut B,
 ) -> Result<ParsedReportJson, CodecovError>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    parse_report_json_with_options(input, builder, &ParseOptions::default())
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(bytes = input.len())))]
+pub fn parse_report_json_with_options<B, R>(
+    input: &[u8],
+    builder: &mut B,
+    options: &ParseOptions,
+) -> Result<ParsedReportJson, CodecovError>
 where
     B: ReportBuilder<R>,
     R: Report,
 {
     let report: ReportJson = serde_json::from_slice(input)?;
+    let policy = options.session_keys;
+
+    let mut deduped_sessions: BTreeMap<usize, Session> = BTreeMap::new();
+    let mut dropped_sessions = vec![];
+    for (session_index, session) in report.sessions.0 {
+        let Some(existing) = deduped_sessions.get_mut(&session_index) else {
+            deduped_sessions.insert(session_index, session);
+            continue;
+        };
+        match policy {
+            SessionKeyPolicy::Error => {
+                let error: serde_json::Error =
+                    de::Error::custom(format!("duplicate session index {session_index}"));
+                return Err(error.into());
+            }
+            SessionKeyPolicy::KeepLast => {
+                *existing = session;
+                dropped_sessions.push(session_index);
+            }
+            SessionKeyPolicy::Skip => dropped_sessions.push(session_index),
+            SessionKeyPolicy::Merge => existing.merge(session),
+        }
+    }
+
+    let missing_sessions: Vec<usize> = match deduped_sessions.last_key_value() {
+        Some((&max_index, _)) => (0..max_index)
+            .filter(|i| !deduped_sessions.contains_key(i))
+            .collect(),
+        None => vec![],
+    };
+    if policy == SessionKeyPolicy::Error && !missing_sessions.is_empty() {
+        let error: serde_json::Error =
+            de::Error::custom(format!("missing session indices {missing_sessions:?}"));
+        return Err(error.into());
+    }
 
     let mut files = HashMap::with_capacity(report.files.len());
+    let mut file_session_totals = vec![];
+    let mut ignored_chunks = HashSet::new();
     for (filename, file) in report.files {
-        let chunk_index = file.0;
+        let chunk_index = file.chunk_index;
+        if options.ignore_globs.is_ignored(&filename) {
+            ignored_chunks.insert(chunk_index);
+            continue;
+        }
 
-        let file = builder.insert_file(&filename)?;
-        files.insert(chunk_index, file.id);
+        let inserted = builder.insert_file(&filename)?;
+        builder.update_file_metadata(&models::SourceFile {
+            chunk_index: Some(chunk_index as i64),
+            ..inserted.clone()
+        })?;
+        files.insert(chunk_index, inserted.id);
+        if let Some(session_totals) = file.session_totals {
+            file_session_totals.push((inserted.id, session_totals));
+        }
     }
 
-    let mut sessions = HashMap::with_capacity(report.sessions.len());
-    for (session_index, session) in report.sessions {
+    let mut sessions = HashMap::with_capacity(deduped_sessions.len());
+    let mut unknown_session_keys = BTreeSet::new();
+    for (session_index, mut session) in deduped_sessions {
+        unknown_session_keys.extend(session.unknown.keys().cloned());
+        session.keep_unknown_keys();
         let raw_upload = models::RawUpload {
             id: 0,
-            timestamp: session.timestamp,
+            timestamp: session.timestamp.map(|Seconds(seconds)| seconds),
             raw_upload_url: session.raw_upload_url,
             flags: session.flags,
             provider: session.provider,
@@ -254,6 +651,7 @@ where
             env: session.env,
             session_type: session.session_type,
             session_extras: session.session_extras,
+            external_id: None,
         };
 
         let raw_upload = builder.insert_raw_upload(raw_upload)?;
@@ -261,7 +659,30 @@ where
         sessions.insert(session_index, raw_upload.id);
     }
 
-    Ok(ParsedReportJson { files, sessions })
+    let mut totals = vec![];
+    for (source_file_id, file_session_totals) in &file_session_totals {
+        for (session_index, session_totals) in session_totals(file_session_totals) {
+            if let Some(&raw_upload_id) = sessions.get(&session_index) {
+                totals.push(session_file_totals(
+                    *source_file_id,
+                    raw_upload_id,
+                    session_totals,
+                ));
+            }
+        }
+    }
+    if !totals.is_empty() {
+        builder.multi_insert_session_file_totals(&totals)?;
+    }
+
+    Ok(ParsedReportJson {
+        files,
+        sessions,
+        dropped_sessions,
+        missing_sessions,
+        ignored_chunks,
+        unknown_session_keys,
+    })
 }
 
 #[cfg(test)]
@@ -269,6 +690,13 @@ mod tests {
     use super::*;
     use crate::test_utils::test_report::TestReportBuilder;
 
+    fn chunk_file(path: &str, chunk_index: i64) -> models::SourceFile {
+        models::SourceFile {
+            chunk_index: Some(chunk_index),
+            ..models::SourceFile::new(path)
+        }
+    }
+
     #[test]
     fn test_report_json_simple_valid_case() {
         let input = br#"{"files": {"src/report.rs": [0, {}, [], null]}, "sessions": {"0": {"j": "codecov-rs CI"}}}"#;
@@ -277,7 +705,7 @@ mod tests {
         let _parsed = parse_report_json(input, &mut report_builder).unwrap();
 
         let report = report_builder.build().unwrap();
-        assert_eq!(report.files, &[models::SourceFile::new("src/report.rs")]);
+        assert_eq!(report.files, &[chunk_file("src/report.rs", 0)]);
         assert_eq!(
             report.uploads,
             &[models::RawUpload {
@@ -299,8 +727,8 @@ mod tests {
         assert_eq!(
             report.files,
             &[
-                models::SourceFile::new("src/report.rs"),
-                models::SourceFile::new("src/report/models.rs")
+                chunk_file("src/report.rs", 0),
+                chunk_file("src/report/models.rs", 1)
             ]
         );
         assert_eq!(
@@ -357,8 +785,8 @@ mod tests {
         assert_eq!(
             report.files,
             &[
-                models::SourceFile::new("src/report.rs"),
-                models::SourceFile::new("src/report/models.rs")
+                chunk_file("src/report.rs", 0),
+                chunk_file("src/report/models.rs", 1)
             ]
         );
         assert_eq!(report.uploads, &[]);
@@ -408,4 +836,282 @@ mod tests {
         let mut report_builder = TestReportBuilder::default();
         parse_report_json(input, &mut report_builder).unwrap_err();
     }
+
+    #[test]
+    fn test_report_json_session_totals() {
+        let input = br#"{
+            "files": {
+                "a.rs": [0, [0, 5, 4], {"0": [0, 5, 4, 1, 0, "80.00000", 0, 0, 0, 0, 0, 0, 0], "2": [0, 5], "3": [1, 1, 1, 0, 0, "100"], "meta": {"session_count": 4}}, null],
+                "b.rs": [1, [0, 2, 1], [[0, 2, 1, 1, 0, 50], null, [0, 0, 0, 0, 0, "0", 0, 0, 0, 0, 0, 0, 0]]],
+                "c.rs": [2, [0, 1, 1], null, null],
+                "d.rs": [3, [0, 1, 1], {"0": "bogus", "x": [1]}, null]
+            },
+            "sessions": {"0": {"j": "first"}, "1": {"j": "second"}, "2": {"j": "third"}}
+        }"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let parsed = parse_report_json(input, &mut report_builder).unwrap();
+        let report = report_builder.build().unwrap();
+
+        let a = parsed.files[&0];
+        let b = parsed.files[&1];
+        let upload = |session_index| parsed.sessions[&session_index];
+        let mut totals = report.session_file_totals.clone();
+        totals.sort_by_key(|totals| (totals.source_file_id != a, totals.raw_upload_id));
+        assert_eq!(
+            totals,
+            &[
+                models::SessionFileTotals {
+                    source_file_id: a,
+                    raw_upload_id: upload(0),
+                    lines: 5,
+                    hits: 4,
+                    misses: 1,
+                    coverage: Some("80.00000".into()),
+                    ..Default::default()
+                },
+                // Trailing zeros were stripped
+                models::SessionFileTotals {
+                    source_file_id: a,
+                    raw_upload_id: upload(2),
+                    lines: 5,
+                    ..Default::default()
+                },
+                // Session 3 doesn't exist, so its totals were dropped
+                models::SessionFileTotals {
+                    source_file_id: b,
+                    raw_upload_id: upload(0),
+                    lines: 2,
+                    hits: 1,
+                    misses: 1,
+                    coverage: Some("50".into()),
+                    ..Default::default()
+                },
+                // An old-style list is indexed by position
+                models::SessionFileTotals {
+                    source_file_id: b,
+                    raw_upload_id: upload(2),
+                    coverage: Some("0".into()),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report_json_duplicate_sessions() {
+        let input = br#"{"files": {}, "sessions": {"0": {"j": "first"}, "1": {"j": "second"}, "0": {"j": "duplicate", "n": "build"}}}"#;
+
+        let options = ParseOptions {
+            session_keys: SessionKeyPolicy::Error,
+            ..Default::default()
+        };
+        let mut report_builder = TestReportBuilder::default();
+        let error =
+            parse_report_json_with_options(input, &mut report_builder, &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "parser error: 'duplicate session index 0'"
+        );
+
+        // By default the last session with an index wins
+        let mut report_builder = TestReportBuilder::default();
+        let parsed = parse_report_json(input, &mut report_builder).unwrap();
+        assert_eq!(parsed.dropped_sessions, &[0]);
+        assert_eq!(parsed.sessions, HashMap::from([(0, 0), (1, 1)]));
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.uploads,
+            &[
+                models::RawUpload {
+                    id: 0,
+                    job_name: Some("duplicate".into()),
+                    build: Some("build".into()),
+                    ..Default::default()
+                },
+                models::RawUpload {
+                    id: 1,
+                    job_name: Some("second".into()),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let options = ParseOptions {
+            session_keys: SessionKeyPolicy::Skip,
+            ..Default::default()
+        };
+        let mut report_builder = TestReportBuilder::default();
+        let parsed = parse_report_json_with_options(input, &mut report_builder, &options).unwrap();
+        assert_eq!(parsed.dropped_sessions, &[0]);
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.uploads,
+            &[
+                models::RawUpload {
+                    id: 0,
+                    job_name: Some("first".into()),
+                    ..Default::default()
+                },
+                models::RawUpload {
+                    id: 1,
+                    job_name: Some("second".into()),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let options = ParseOptions {
+            session_keys: SessionKeyPolicy::Merge,
+            ..Default::default()
+        };
+        let mut report_builder = TestReportBuilder::default();
+        let parsed = parse_report_json_with_options(input, &mut report_builder, &options).unwrap();
+        assert!(parsed.dropped_sessions.is_empty());
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.uploads[0],
+            models::RawUpload {
+                id: 0,
+                job_name: Some("first".into()),
+                build: Some("build".into()),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn test_report_json_unknown_session_keys() {
+        let input = br#"{"files": {}, "sessions": {"0": {"t": [1, 2], "j": "first", "x": 1}, "1": {"j": "second", "x": 2, "y": [3], "se": {"y": "kept"}}, "2": {"z": null, "se": "not an object"}}}"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let parsed = parse_report_json(input, &mut report_builder).unwrap();
+        assert_eq!(
+            parsed.unknown_session_keys,
+            BTreeSet::from(["x".to_string(), "y".to_string(), "z".to_string()])
+        );
+        let report = report_builder.build().unwrap();
+        let extras: Vec<_> = report
+            .uploads
+            .iter()
+            .map(|upload| upload.session_extras.clone())
+            .collect();
+        assert_eq!(
+            extras,
+            [
+                Some(serde_json::json!({"x": 1})),
+                Some(serde_json::json!({"x": 2, "y": "kept"})),
+                Some(serde_json::json!("not an object")),
+            ]
+        );
+        assert_eq!(report.uploads[0].job_name.as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn test_report_json_missing_session_indices() {
+        let input = br#"{"files": {}, "sessions": {"1": {"j": "first"}, "3": {"j": "second"}}}"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let parsed = parse_report_json(input, &mut report_builder).unwrap();
+        assert_eq!(parsed.missing_sessions, &[0, 2]);
+        assert_eq!(parsed.sessions, HashMap::from([(1, 0), (3, 1)]));
+
+        let options = ParseOptions {
+            session_keys: SessionKeyPolicy::Error,
+            ..Default::default()
+        };
+        let mut report_builder = TestReportBuilder::default();
+        let error =
+            parse_report_json_with_options(input, &mut report_builder, &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "parser error: 'missing session indices [0, 2]'"
+        );
+    }
+
+    #[test]
+    fn test_report_json_variants() {
+        // Shapes of `files` and `sessions` we've seen in production, each of
+        // which should parse the same
+        let file_variants = [
+            // All four slots
+            r#"{"a.rs": [0, [0, 2, 1, 1, 0, "50.00000"], null, null], "b.rs": [1, {}, [], null]}"#,
+            // String chunk indexes
+            r#"{"a.rs": ["0", [0, 2, 1, 1, 0, "50.00000"], null, null], "b.rs": ["1", {}, [], null]}"#,
+            // Missing optional slots
+            r#"{"a.rs": [0, [0, 2, 1, 1, 0, "50.00000"]], "b.rs": [1]}"#,
+            // Extra trailing slots
+            r#"{"a.rs": [0, {}, [], null, null], "b.rs": [1, {}, [], null, {}]}"#,
+        ];
+        let session_variants = [
+            r#"{"0": {"j": "first"}, "1": {"j": "second"}}"#,
+            r#"[{"j": "first"}, {"j": "second"}]"#,
+        ];
+
+        for files in file_variants {
+            for sessions in session_variants {
+                let input = format!(r#"{{"files": {files}, "sessions": {sessions}}}"#);
+                let mut report_builder = TestReportBuilder::default();
+                let parsed = parse_report_json(input.as_bytes(), &mut report_builder)
+                    .unwrap_or_else(|e| panic!("{input}: {e}"));
+                assert_eq!(parsed.sessions, HashMap::from([(0, 0), (1, 1)]), "{input}");
+                assert!(parsed.missing_sessions.is_empty());
+
+                let report = report_builder.build().unwrap();
+                assert_eq!(
+                    report.files,
+                    &[chunk_file("a.rs", 0), chunk_file("b.rs", 1)],
+                    "{input}"
+                );
+                let job_names: Vec<_> = report
+                    .uploads
+                    .iter()
+                    .map(|upload| upload.job_name.as_deref().unwrap())
+                    .collect();
+                assert_eq!(job_names, ["first", "second"], "{input}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_report_json_timestamps() {
+        let input = br#"{"files": {}, "sessions": {"0": {"d": 1704827412}, "1": {"d": 1704827412.873}, "2": {"d": null}, "3": {}}}"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        parse_report_json(input, &mut report_builder).unwrap();
+
+        let report = report_builder.build().unwrap();
+        let timestamps: Vec<_> = report
+            .uploads
+            .iter()
+            .map(|upload| upload.timestamp)
+            .collect();
+        assert_eq!(timestamps, [Some(1704827412), Some(1704827412), None, None]);
+
+        for input in [
+            br#"{"files": {}, "sessions": {"0": {"d": "1704827412"}}}"#.as_slice(),
+            br#"{"files": {}, "sessions": {"0": {"d": 1e300}}}"#,
+        ] {
+            let mut report_builder = TestReportBuilder::default();
+            assert!(parse_report_json(input, &mut report_builder).is_err());
+        }
+    }
+
+    #[test]
+    fn test_report_json_invalid_indexes() {
+        for input in [
+            br#"{"files": {"a.rs": []}, "sessions": {}}"#.as_slice(),
+            br#"{"files": {"a.rs": [-1]}, "sessions": {}}"#,
+            br#"{"files": {"a.rs": ["zero"]}, "sessions": {}}"#,
+            br#"{"files": {"a.rs": [0.5]}, "sessions": {}}"#,
+            br#"{"files": {}, "sessions": {"-1": {}}}"#,
+        ] {
+            let mut report_builder = TestReportBuilder::default();
+            assert!(
+                parse_report_json(input, &mut report_builder).is_err(),
+                "{}",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
 }
@@ -0,0 +1,356 @@
+//! Parsing a pyreport in checkpointed steps, so that if it fails partway
+//! through a big chunks file, e.g. because the disk filled up, it can pick up
+//! where it left off instead of starting over.
+//!
+//! Each step is its own transaction, and commits a [`Checkpoint`] in the
+//! report's `report_metadata` table along with what it inserted. Parsing the
+//! same inputs into the same report again resumes after the last checkpoint.
+//! The checkpoint is deleted in the step that parses the last chunk.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    mem,
+};
+
+use memmap2::Mmap;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use winnow::{
+    combinator::opt,
+    error::{ContextError, ErrMode},
+    PResult, Parser,
+};
+
+use super::{
+    chunk_warnings,
+    chunks::{chunk_or_skip, chunks_file_header, end_of_chunk, ParseCtx, ReportOutputStream},
+    chunks_parse_ctx, missing_chunks, parse_report_json, ParseOptions,
+};
+use crate::{
+    error::{CodecovError, Result},
+    parsers::common::{IngestResult, Stopwatch},
+    report::{SqliteReport, SqliteReportBuilder, SqliteReportBuilderTx},
+};
+
+/// The `report_metadata` key the checkpoint is stored under.
+const CHECKPOINT_KEY: &str = "pyreport_checkpoint";
+
+/// How far a [`parse_pyreport_resumable`] got, as of its last committed step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Hashes of the report JSON and chunks file being parsed.
+    pub inputs: [u64; 2],
+
+    /// How many chunks have been committed, whether they were parsed or
+    /// skipped.
+    pub chunks_committed: usize,
+
+    /// Where the next chunk starts in the chunks file, in bytes.
+    pub offset: usize,
+
+    /// See [`ParseCtx::report_json_files`].
+    pub files: HashMap<usize, i64>,
+
+    /// See [`ParseCtx::report_json_sessions`].
+    pub sessions: HashMap<usize, i64>,
+
+    /// See [`ParseCtx::labels_index`].
+    pub labels_index: HashMap<String, i64>,
+
+    /// See [`ParseCtx::skipped_chunks`].
+    pub skipped_chunks: Vec<usize>,
+
+    /// See [`ParseCtx::ignored_chunks`].
+    #[serde(default)]
+    pub ignored_chunks: HashSet<usize>,
+
+    /// See [`ParseCtx::orphan_chunks`].
+    #[serde(default)]
+    pub orphan_chunks: Vec<usize>,
+
+    /// See [`IngestResult::files_touched`].
+    pub files_touched: usize,
+
+    /// See [`IngestResult::files_ignored`].
+    #[serde(default)]
+    pub files_ignored: usize,
+
+    /// See [`IngestResult::samples_inserted`].
+    pub samples_inserted: usize,
+
+    /// See [`IngestResult::quirks_applied`].
+    #[serde(default)]
+    pub quirks_applied: BTreeMap<String, usize>,
+
+    /// See [`IngestResult::labels_pruned`].
+    #[serde(default)]
+    pub labels_pruned: usize,
+
+    /// Warnings from the report JSON and the quirks of the chunks committed so
+    /// far. Skipped chunks and files with no chunk are added when the parse
+    /// finishes.
+    pub warnings: Vec<String>,
+}
+
+/// What a step of [`parse_pyreport_resumable`] leaves for the next one.
+enum Step {
+    More(Checkpoint),
+    Done(Checkpoint),
+}
+
+/// Reads the checkpoint a previous [`parse_pyreport_resumable`] left in the
+/// database at `conn`, if it didn't finish.
+pub fn load_checkpoint(conn: &Connection) -> Result<Option<Checkpoint>> {
+    let value: Option<String> = conn
+        .query_row(
+            "SELECT value FROM report_metadata WHERE key = ?1",
+            [CHECKPOINT_KEY],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(value
+        .map(|value| serde_json::from_str(&value))
+        .transpose()?)
+}
+
+/// Like [`super::parse_pyreport_with_options`], but commits after every
+/// `chunks_per_checkpoint` chunks and resumes after the last commit if a
+/// previous call with the same inputs failed. The first commit holds
+/// everything from the report JSON and the chunks file's header.
+///
+/// Fails without inserting anything if the report has a checkpoint from
+/// parsing different inputs.
+///
+/// The returned [`IngestResult`] covers the whole parse, including steps a
+/// previous call committed, except for its `duration`, which is only this
+/// call's.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub fn parse_pyreport_resumable(
+    report_json_file: &File,
+    chunks_file: &File,
+    report_builder: &mut SqliteReportBuilder,
+    options: &ParseOptions,
+    chunks_per_checkpoint: usize,
+) -> Result<IngestResult> {
+    let stopwatch = Stopwatch::start();
+    let report_json = unsafe { Mmap::map(report_json_file)? };
+    let chunks_mmap = unsafe { Mmap::map(chunks_file)? };
+    let chunks = unsafe { std::str::from_utf8_unchecked(&chunks_mmap[..]) };
+    let inputs = [
+        seahash::hash(&report_json),
+        seahash::hash(chunks.as_bytes()),
+    ];
+
+    let mut checkpoint = load_checkpoint(&report_builder.conn)?;
+    if let Some(checkpoint) = &checkpoint {
+        if checkpoint.inputs != inputs {
+            return Err(CodecovError::ReportBuilderError(
+                "report has a checkpoint from parsing different inputs".to_string(),
+            ));
+        }
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            chunks_committed = checkpoint.chunks_committed,
+            "resuming from checkpoint"
+        );
+        for &raw_upload_id in checkpoint.sessions.values() {
+            report_builder.continue_upload(raw_upload_id)?;
+        }
+    }
+
+    loop {
+        let tx = report_builder.transaction()?;
+        let (tx, step) = match checkpoint.take() {
+            None => start(tx, &report_json, chunks, options, inputs),
+            Some(checkpoint) => {
+                parse_chunks(tx, chunks, checkpoint, options, chunks_per_checkpoint)
+            }
+        };
+        // Commit ourselves rather than on drop, where an error committing
+        // would be lost
+        let step = step.and_then(|step| {
+            match &step {
+                Step::More(checkpoint) => tx.conn.execute(
+                    "INSERT INTO report_metadata (key, value) VALUES (?1, ?2)
+                     ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                    (CHECKPOINT_KEY, serde_json::to_string(checkpoint)?),
+                )?,
+                Step::Done(_) => tx.conn.execute(
+                    "DELETE FROM report_metadata WHERE key = ?1",
+                    [CHECKPOINT_KEY],
+                )?,
+            };
+            tx.conn.execute_batch("COMMIT")?;
+            Ok(step)
+        });
+
+        match step {
+            Ok(Step::More(next)) => checkpoint = Some(next),
+            Ok(Step::Done(done)) => {
+                // Already checked against `options.chunk_mismatch` before
+                // the last step committed
+                let missing_chunks =
+                    missing_chunks(&done.files, done.chunks_committed, options.chunk_mismatch)?;
+                let mut warnings = done.warnings;
+                warnings.extend(chunk_warnings(
+                    &done.skipped_chunks,
+                    &done.orphan_chunks,
+                    &missing_chunks,
+                ));
+                return Ok(IngestResult {
+                    raw_upload: None,
+                    files_touched: done.files_touched,
+                    files_ignored: done.files_ignored,
+                    samples_inserted: done.samples_inserted,
+                    quirks_applied: done.quirks_applied,
+                    labels_pruned: done.labels_pruned,
+                    warnings,
+                    duration: stopwatch.elapsed(),
+                });
+            }
+            Err(e) => {
+                if !tx.conn.is_autocommit() {
+                    tx.rollback()?;
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// The first step: parses the report JSON and the chunks file's header.
+fn start<'a>(
+    mut tx: SqliteReportBuilderTx<'a>,
+    report_json: &[u8],
+    chunks: &str,
+    options: &ParseOptions,
+    inputs: [u64; 2],
+) -> (SqliteReportBuilderTx<'a>, Result<Step>) {
+    let mut result = IngestResult::default();
+    let parsed = match parse_report_json(report_json, &mut tx, options, &mut result) {
+        Ok(parsed) => parsed,
+        Err(e) => return (tx, Err(e)),
+    };
+
+    let mut state = chunks_parse_ctx(tx, parsed.files, parsed.sessions, options);
+    state.ignored_chunks = parsed.ignored_chunks;
+    let mut buf = ReportOutputStream {
+        input: chunks,
+        state,
+    };
+    let parsed = (opt('\u{feff}'), opt(chunks_file_header))
+        .void()
+        .parse_next(&mut buf);
+    let remaining = buf.input;
+    let ParseCtx {
+        db,
+        labels_index,
+        report_json_files,
+        report_json_sessions,
+        ignored_chunks,
+        ..
+    } = buf.state;
+
+    let step = parsed
+        .map(|()| {
+            Step::More(Checkpoint {
+                inputs,
+                chunks_committed: 0,
+                offset: chunks.len() - remaining.len(),
+                files: report_json_files,
+                sessions: report_json_sessions,
+                labels_index,
+                skipped_chunks: Vec::new(),
+                ignored_chunks,
+                orphan_chunks: Vec::new(),
+                files_touched: result.files_touched,
+                files_ignored: result.files_ignored,
+                samples_inserted: 0,
+                quirks_applied: BTreeMap::new(),
+                labels_pruned: 0,
+                warnings: result.warnings,
+            })
+        })
+        .map_err(|e| parser_error(chunks, remaining, e));
+    (db.report_builder, step)
+}
+
+/// Every step after the first: parses up to `count` chunks starting where
+/// `checkpoint` left off.
+fn parse_chunks<'a>(
+    tx: SqliteReportBuilderTx<'a>,
+    chunks: &str,
+    mut checkpoint: Checkpoint,
+    options: &ParseOptions,
+    count: usize,
+) -> (SqliteReportBuilderTx<'a>, Result<Step>) {
+    let mut ctx = chunks_parse_ctx(
+        tx,
+        mem::take(&mut checkpoint.files),
+        mem::take(&mut checkpoint.sessions),
+        options,
+    );
+    ctx.labels_index = mem::take(&mut checkpoint.labels_index);
+    ctx.skipped_chunks = mem::take(&mut checkpoint.skipped_chunks);
+    ctx.ignored_chunks = mem::take(&mut checkpoint.ignored_chunks);
+    ctx.orphan_chunks = mem::take(&mut checkpoint.orphan_chunks);
+    ctx.samples_inserted = checkpoint.samples_inserted;
+    ctx.quirks_applied = mem::take(&mut checkpoint.quirks_applied);
+    ctx.labels_pruned = checkpoint.labels_pruned;
+    ctx.chunk.index = checkpoint.chunks_committed;
+
+    let mut buf = ReportOutputStream {
+        input: &chunks[checkpoint.offset..],
+        state: ctx,
+    };
+    let parsed = parse_some_chunks(&mut buf, count);
+    let remaining = buf.input;
+    let ctx = buf.state;
+
+    checkpoint.chunks_committed = ctx.chunk.index;
+    checkpoint.offset = chunks.len() - remaining.len();
+    checkpoint.files = ctx.report_json_files;
+    checkpoint.sessions = ctx.report_json_sessions;
+    checkpoint.labels_index = ctx.labels_index;
+    checkpoint.skipped_chunks = ctx.skipped_chunks;
+    checkpoint.ignored_chunks = ctx.ignored_chunks;
+    checkpoint.orphan_chunks = ctx.orphan_chunks;
+    checkpoint.samples_inserted = ctx.samples_inserted;
+    checkpoint.quirks_applied = ctx.quirks_applied;
+    checkpoint.labels_pruned = ctx.labels_pruned;
+    checkpoint.warnings.extend(ctx.quirk_warnings);
+
+    let step = match parsed {
+        Ok(true) => Ok(Step::More(checkpoint)),
+        // Files the chunks file never got to may fail the parse, so check
+        // before the last step commits
+        Ok(false) => missing_chunks(
+            &checkpoint.files,
+            checkpoint.chunks_committed,
+            options.chunk_mismatch,
+        )
+        .map(|_| Step::Done(checkpoint)),
+        Err(e) => Err(parser_error(chunks, remaining, e)),
+    };
+    (ctx.db.report_builder, step)
+}
+
+/// Parses up to `count` chunks and the terminators after them. Returns whether
+/// there are more chunks after those.
+fn parse_some_chunks(
+    buf: &mut ReportOutputStream<&str, SqliteReport, SqliteReportBuilderTx<'_>>,
+    count: usize,
+) -> PResult<bool> {
+    for _ in 0..count.max(1) {
+        chunk_or_skip.parse_next(buf)?;
+        if opt(end_of_chunk).parse_next(buf)?.is_none() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn parser_error(chunks: &str, remaining: &str, e: ErrMode<ContextError>) -> CodecovError {
+    CodecovError::parser_error(chunks, remaining, e.into_inner().unwrap_or_default())
+}
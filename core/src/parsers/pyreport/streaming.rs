@@ -0,0 +1,564 @@
+//! Parses a chunks file from an [`io::Read`] a window at a time, so it never
+//! has to be in memory or on disk all at once.
+//!
+//! The parsers in [`super::chunks`] work on a [`Partial`] stream of whatever
+//! text has been read so far. When one runs off the end of it, more is read and
+//! the parser is retried from where it started. Parsing a chunk inserts its
+//! data as it goes, so chunks aren't retried like that: the whole chunk, up to
+//! and including the end-of-chunk marker after it, is read first and parsed as
+//! complete input. Only one chunk is held in memory at a time.
+
+use std::io::{self, Read};
+
+use winnow::{
+    combinator::{eof, opt, terminated},
+    error::{ContextError, ErrMode},
+    stream::{Partial, StreamIsPartial},
+    PResult, Parser, Stateful,
+};
+
+use super::chunks::{
+    chunk_or_skip, chunks_file_header, end_of_chunk, ParseCtx, ReportOutputStream,
+};
+use crate::{
+    error::{CodecovError, Result},
+    report::{pyreport::CHUNKS_FILE_END_OF_CHUNK, Report, ReportBuilder},
+};
+
+/// How many bytes [`parse_chunks_reader`] reads at a time unless told
+/// otherwise.
+pub const DEFAULT_WINDOW_SIZE: usize = 64 * 1024;
+
+type WindowStream<'s, R, B> = ReportOutputStream<Partial<&'s str>, R, B>;
+
+/// Parses a chunks file from `reader`, reading `window_size` bytes at a time,
+/// and returns `ctx` with everything inserted into its report builder. The
+/// result is the same as parsing the whole file with
+/// [`super::chunks::parse_chunks_file`], except that anything after the last
+/// chunk has to be line endings.
+///
+/// Parser errors locate the failure in the whole file, not the window it
+/// happened in.
+pub fn parse_chunks_reader<R: Report, B: ReportBuilder<R>>(
+    reader: impl Read,
+    ctx: ParseCtx<R, B>,
+    window_size: usize,
+) -> Result<ParseCtx<R, B>> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("parse_chunks_reader").entered();
+
+    let mut window = Window {
+        reader,
+        text: String::new(),
+        partial_char: Vec::new(),
+        eof: false,
+        window_size: window_size.max(1),
+        offset: 0,
+        line: 0,
+    };
+
+    let (mut ctx, ()) = window.parse(ctx, None, |buf| {
+        (opt('\u{feff}'), opt(chunks_file_header))
+            .void()
+            .parse_next(buf)
+    })?;
+
+    loop {
+        let (end, more_chunks) = window.find_chunk_end()?;
+        (ctx, ()) = window.parse(ctx, Some(end), |buf| {
+            if more_chunks {
+                terminated(chunk_or_skip, end_of_chunk).parse_next(buf)
+            } else {
+                terminated(chunk_or_skip, eof.void()).parse_next(buf)
+            }
+        })?;
+        if !more_chunks {
+            return Ok(ctx);
+        }
+    }
+}
+
+/// The part of a chunks file that has been read but not parsed yet.
+struct Window<Rd> {
+    reader: Rd,
+    text: String,
+
+    /// The bytes at the end of the last read that aren't a whole UTF-8
+    /// character yet.
+    partial_char: Vec<u8>,
+
+    eof: bool,
+    window_size: usize,
+
+    /// How many bytes and lines have been parsed and dropped from `text`.
+    offset: usize,
+    line: usize,
+}
+
+impl<Rd: Read> Window<Rd> {
+    /// Reads at least `len` more bytes onto the end of `text`, or up to the end
+    /// of the file if there aren't that many.
+    fn fill(&mut self, len: usize) -> Result<()> {
+        let mut bytes = std::mem::take(&mut self.partial_char);
+        let start = bytes.len();
+        bytes.resize(start + len, 0);
+        let mut filled = start;
+        while filled < bytes.len() {
+            match self.reader.read(&mut bytes[filled..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        bytes.truncate(filled);
+
+        match std::str::from_utf8(&bytes) {
+            Ok(text) => self.text.push_str(text),
+            // The read stopped partway through a character
+            Err(e) if e.error_len().is_none() && !self.eof => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                self.text.push_str(std::str::from_utf8(valid).unwrap());
+                self.partial_char = rest.to_vec();
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e).into()),
+        }
+        Ok(())
+    }
+
+    /// Reads until `text` holds a whole chunk and returns its length, including
+    /// the end-of-chunk marker and line ending after it if there is one, and
+    /// whether there was.
+    fn find_chunk_end(&mut self) -> Result<(usize, bool)> {
+        let marker = CHUNKS_FILE_END_OF_CHUNK.trim();
+        let mut search_from = 0;
+        loop {
+            match self.text[search_from..].find(marker) {
+                Some(start) => {
+                    let end = search_from + start + marker.len();
+                    let after = &self.text[end..];
+                    if after.starts_with("\r\n") {
+                        return Ok((end + 2, true));
+                    } else if after.starts_with('\n') {
+                        return Ok((end + 1, true));
+                    } else if self.eof || !matches!(after, "" | "\r") {
+                        return Ok((end, true));
+                    }
+                    // Wait for the line ending after the marker too
+                    search_from += start;
+                }
+                None if self.eof => return Ok((self.text.len(), false)),
+                None => {
+                    // The marker may have been cut off by the end of the window
+                    search_from = search_from.max(self.text.len().saturating_sub(marker.len()));
+                    while !self.text.is_char_boundary(search_from) {
+                        search_from -= 1;
+                    }
+                }
+            }
+            self.fill(self.window_size)?;
+        }
+    }
+
+    /// Runs `parser` on the start of `text` and drops whatever it consumed.
+    /// With `limit`, only the first `limit` bytes are parsed and they're
+    /// treated as the whole input. Otherwise, whenever `parser` runs out of
+    /// input, more is read and it's run again.
+    fn parse<R: Report, B: ReportBuilder<R>, O>(
+        &mut self,
+        mut ctx: ParseCtx<R, B>,
+        limit: Option<usize>,
+        mut parser: impl for<'s> FnMut(&mut WindowStream<'s, R, B>) -> PResult<O>,
+    ) -> Result<(ParseCtx<R, B>, O)> {
+        loop {
+            let input = &self.text[..limit.unwrap_or(self.text.len())];
+            let input_len = input.len();
+            let mut input = Partial::new(input);
+            if limit.is_some() || self.eof {
+                let _ = input.complete();
+            }
+            let mut stream = Stateful { input, state: ctx };
+            let result = parser(&mut stream);
+            ctx = stream.state;
+            let consumed = input_len - stream.input.into_inner().len();
+
+            match result {
+                Ok(output) => {
+                    self.consume(consumed);
+                    return Ok((ctx, output));
+                }
+                // Read as much again as we have so that retrying a long parse
+                // doesn't take quadratic time
+                Err(ErrMode::Incomplete(_)) if limit.is_none() && !self.eof => {
+                    self.fill(self.text.len().max(self.window_size))?
+                }
+                Err(e) => {
+                    return Err(self.parser_error(consumed, e.into_inner().unwrap_or_default()))
+                }
+            }
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.offset += len;
+        self.line += self.text[..len].matches('\n').count();
+        self.text.drain(..len);
+    }
+
+    /// Builds a [`CodecovError::ParserError`] for a failure `consumed` bytes
+    /// into `text`. `text` always starts at the start of a line, so only the
+    /// offset and line need adjusting.
+    fn parser_error(&self, consumed: usize, context: ContextError) -> CodecovError {
+        let mut error = CodecovError::parser_error(&self.text, &self.text[consumed..], context);
+        if let CodecovError::ParserError { offset, line, .. } = &mut error {
+            *offset += self.offset;
+            *line += self.line;
+        }
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        path::Path,
+    };
+
+    use super::*;
+    use crate::{
+        parsers::{
+            common::IgnoreGlobs,
+            pyreport::{
+                chunks::{parse_chunks_file, ChunkMismatchPolicy},
+                parse_pyreport_buffers, parse_pyreport_readers, ParseOptions,
+            },
+        },
+        test_utils::test_report::{TestReport, TestReportBuilder},
+    };
+
+    const WINDOW_SIZES: [usize; 5] = [1, 2, 5, 64, DEFAULT_WINDOW_SIZE];
+
+    fn setup(skip_malformed_chunks: bool) -> ParseCtx<TestReport, TestReportBuilder> {
+        let ids = HashMap::from_iter((0..8).map(|i| (i, i as i64)));
+        let mut ctx = ParseCtx::new(TestReportBuilder::default(), ids.clone(), ids);
+        ctx.skip_malformed_chunks = skip_malformed_chunks;
+        ctx
+    }
+
+    fn parse_whole(
+        input: &str,
+        skip_malformed_chunks: bool,
+    ) -> Result<ParseCtx<TestReport, TestReportBuilder>> {
+        let mut buf = ReportOutputStream::<&str, _, _> {
+            input,
+            state: setup(skip_malformed_chunks),
+        };
+        parse_chunks_file.parse_next(&mut buf).map_err(|e| {
+            CodecovError::parser_error(input, buf.input, e.into_inner().unwrap_or_default())
+        })?;
+        Ok(buf.state)
+    }
+
+    fn assert_same_report(
+        streamed: &ParseCtx<TestReport, TestReportBuilder>,
+        whole: &ParseCtx<TestReport, TestReportBuilder>,
+    ) {
+        assert_eq!(streamed.chunk, whole.chunk);
+        assert_eq!(streamed.labels_index, whole.labels_index);
+        assert_eq!(streamed.skipped_chunks, whole.skipped_chunks);
+        assert_eq!(streamed.samples_inserted, whole.samples_inserted);
+        assert_eq!(streamed.quirks_applied, whole.quirks_applied);
+
+        let whole_samples_inserted = whole.samples_inserted;
+        let streamed = &streamed.db.report_builder.report;
+        let whole = &whole.db.report_builder.report;
+        assert_eq!(streamed.contexts, whole.contexts);
+        assert_eq!(streamed.samples, whole.samples);
+        assert_eq!(streamed.samples.len(), whole_samples_inserted);
+        assert_eq!(streamed.assocs, whole.assocs);
+        assert_eq!(streamed.branches, whole.branches);
+        assert_eq!(streamed.methods, whole.methods);
+        assert_eq!(streamed.spans, whole.spans);
+    }
+
+    #[test]
+    fn test_parse_chunks_reader_matches_parse_chunks_file() {
+        let fixtures =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../test_utils/fixtures/pyreport");
+        let mut inputs: Vec<String> = [
+            "codecov-rs-chunks-d2a9ba1.txt",
+            "codecov-rs-chunks-d2a9ba1-crlf.txt",
+            "codecov-rs-chunks-d2a9ba1-bom.txt",
+        ]
+        .iter()
+        .map(|name| std::fs::read_to_string(fixtures.join(name)).unwrap())
+        .collect();
+        for input in [
+            // No header, an empty chunk and labels that aren't ASCII
+            "{}\n[1, null, [[0, 1]], null, null, [[0, 1, null, [\"tést_çase\"]]]]\n\n<<<<< end_of_chunk >>>>>\nnull\n<<<<< end_of_chunk >>>>>\n{}\n[1, null, [[0, 1], [1, 2]], null, null, [[0, 1, null, [\"tést_çase\", \"🦀\"]]]]\n",
+            // A labels index
+            "{\"labels_index\": {\"0\": \"tést_çase\"}}\n<<<<< end_of_header >>>>>\n{}\n[1, null, [[0, 1]]]\n\n<<<<< end_of_chunk >>>>>\n{}\n[1, null, [[0, 1], [1, 2]], null, null, [[0, 1, null, [0]]]]\n[1, null, [[0, 1]]]\n",
+        ] {
+            inputs.push(input.to_string());
+            inputs.push(input.replace('\n', "\r\n"));
+        }
+
+        for input in &inputs {
+            let whole = parse_whole(input, false).unwrap();
+            for window_size in WINDOW_SIZES {
+                let streamed =
+                    parse_chunks_reader(input.as_bytes(), setup(false), window_size).unwrap();
+                assert_same_report(&streamed, &whole);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_chunks_reader_skip_malformed_chunks() {
+        let input = concat!(
+            "{}\n[1, null, [[0, 1]]]\n",
+            "<<<<< end_of_chunk >>>>>\n",
+            "{}\n[true, null, [[1, true]], null, null, [[1, 1, null, [\"new_label\"]]]]\n[1, null, [[1, 1]]\n",
+            "<<<<< end_of_chunk >>>>>\n",
+            "{}\n[true, null, [[2, \"1/2\"]]]\n",
+        );
+        let whole = parse_whole(input, true).unwrap();
+        assert_eq!(whole.skipped_chunks, &[1]);
+        // The skipped chunk's sample and corrections were rolled back
+        assert_eq!(whole.samples_inserted, 2);
+        assert_eq!(
+            whole.quirks_applied,
+            BTreeMap::from([("cloverage".to_string(), 1)])
+        );
+        for window_size in WINDOW_SIZES {
+            let streamed = parse_chunks_reader(input.as_bytes(), setup(true), window_size).unwrap();
+            assert_same_report(&streamed, &whole);
+        }
+    }
+
+    #[test]
+    fn test_parse_chunks_reader_errors() {
+        let location = |error| match error {
+            CodecovError::ParserError {
+                offset,
+                line,
+                column,
+                ..
+            } => (offset, line, column),
+            error => panic!("unexpected error {error:?}"),
+        };
+
+        let inputs = [
+            "{}\n<<<<< end_of_header >>>>>\n\n",
+            "{}\n[1, null, [[0, 1]]]\n<<<<< end_of_chunk >>>>>\n{}\n[1, null, [[0, 1]]\n",
+            "{}\n[1, null, [[0, 1]]]\n<<<<< end_of_chunk >>>>>\nnull\n<<<<< end_of_chunk >>>>>\n{\"a\": [}\n",
+        ];
+        for input in inputs {
+            let expected = location(parse_whole(input, false).unwrap_err());
+            for window_size in WINDOW_SIZES {
+                let error =
+                    parse_chunks_reader(input.as_bytes(), setup(false), window_size).unwrap_err();
+                assert_eq!(location(error), expected, "{input:?} {window_size}");
+            }
+        }
+
+        let error =
+            parse_chunks_reader(&b"{}\n[1, null, [[0, \xff]]]\n"[..], setup(false), 4).unwrap_err();
+        assert!(
+            matches!(error, CodecovError::IOError(e) if e.kind() == io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn test_parse_pyreport_readers() {
+        let fixtures =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../test_utils/fixtures/pyreport");
+        let report_json =
+            std::fs::read(fixtures.join("codecov-rs-reports-json-d2a9ba1.txt")).unwrap();
+        let chunks =
+            std::fs::read_to_string(fixtures.join("codecov-rs-chunks-d2a9ba1.txt")).unwrap();
+        let options = ParseOptions::default();
+
+        let (whole, whole_result) = parse_pyreport_buffers(
+            &report_json,
+            &chunks,
+            TestReportBuilder::default(),
+            &options,
+        )
+        .unwrap();
+        let (streamed, streamed_result) = parse_pyreport_readers(
+            report_json.as_slice(),
+            chunks.as_bytes(),
+            TestReportBuilder::default(),
+            &options,
+        )
+        .unwrap();
+        let (whole, streamed) = (whole.report, streamed.report);
+
+        assert_eq!(streamed.files, whole.files);
+        assert_eq!(streamed.uploads, whole.uploads);
+        assert_eq!(streamed.contexts, whole.contexts);
+        assert_eq!(streamed.samples, whole.samples);
+        assert!(!streamed.samples.is_empty());
+
+        assert_eq!(streamed_result.files_touched, whole.files.len());
+        assert_eq!(streamed_result.samples_inserted, whole.samples.len());
+        assert_eq!(
+            (
+                streamed_result.files_touched,
+                streamed_result.samples_inserted
+            ),
+            (whole_result.files_touched, whole_result.samples_inserted)
+        );
+        assert!(streamed_result.raw_upload.is_none());
+        assert!(streamed_result.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_pyreport_ignore_globs() {
+        let report_json = br#"{"files": {"src/a.py": [0, {}], "vendor/b.py": [1, {}], "src/c.py": [2, {}]}, "sessions": {"0": {}}}"#;
+        let chunks = concat!(
+            "{}\n[1, null, [[0, 1]]]\n",
+            "<<<<< end_of_chunk >>>>>\n",
+            "{}\n[1, null, [[0, 1]]]\n[\"this chunk is never parsed\"\n",
+            "<<<<< end_of_chunk >>>>>\n",
+            "{}\n[0, null, [[0, 0]]]\n",
+        );
+        let options = ParseOptions {
+            ignore_globs: IgnoreGlobs(vec!["vendor/**".to_string()]),
+            ..Default::default()
+        };
+
+        let (whole, whole_result) =
+            parse_pyreport_buffers(report_json, chunks, TestReportBuilder::default(), &options)
+                .unwrap();
+        let (streamed, streamed_result) = parse_pyreport_readers(
+            report_json.as_slice(),
+            chunks.as_bytes(),
+            TestReportBuilder::default(),
+            &options,
+        )
+        .unwrap();
+        for (builder, result) in [(whole, whole_result), (streamed, streamed_result)] {
+            let report = builder.report;
+            let paths: Vec<&str> = report.files.iter().map(|f| f.path.as_str()).collect();
+            assert_eq!(paths, ["src/a.py", "src/c.py"]);
+            let sample_files: Vec<i64> = report.samples.iter().map(|s| s.source_file_id).collect();
+            assert_eq!(sample_files, [report.files[0].id, report.files[1].id]);
+            assert_eq!(
+                (
+                    result.files_touched,
+                    result.files_ignored,
+                    result.samples_inserted
+                ),
+                (2, 1, 2)
+            );
+            assert!(result.warnings.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_parse_pyreport_chunk_mismatch() {
+        // Chunk 1 has no file, and the chunks file ends before chunk 3's
+        let report_json = br#"{"files": {"src/a.py": [0, {}], "src/c.py": [2, {}], "src/d.py": [3, {}]}, "sessions": {"0": {}}}"#;
+        let chunks = concat!(
+            "{}\n[1, null, [[0, 1]]]\n",
+            "<<<<< end_of_chunk >>>>>\n",
+            "{}\n[1, null, [[0, 1]], null, null, [[0, 1, null, [\"orphan\"]]]]\n",
+            "<<<<< end_of_chunk >>>>>\n",
+            "{}\n[0, null, [[0, 0]]]\n",
+        );
+
+        let options = ParseOptions::default();
+        let (whole, whole_result) =
+            parse_pyreport_buffers(report_json, chunks, TestReportBuilder::default(), &options)
+                .unwrap();
+        let (streamed, streamed_result) = parse_pyreport_readers(
+            report_json.as_slice(),
+            chunks.as_bytes(),
+            TestReportBuilder::default(),
+            &options,
+        )
+        .unwrap();
+        for (builder, result) in [(whole, whole_result), (streamed, streamed_result)] {
+            let report = builder.report;
+            let paths: Vec<&str> = report.files.iter().map(|f| f.path.as_str()).collect();
+            assert_eq!(paths, ["src/a.py", "src/c.py", "src/d.py"]);
+            let sample_files: Vec<i64> = report.samples.iter().map(|s| s.source_file_id).collect();
+            assert_eq!(sample_files, [report.files[0].id, report.files[1].id]);
+            assert!(report.contexts.iter().all(|c| c.name != "orphan"));
+            assert_eq!(result.samples_inserted, 2);
+            assert_eq!(
+                result.warnings,
+                [
+                    "skipped chunk 1 with no file in the report JSON",
+                    "no chunk for file with chunk index 3",
+                ]
+            );
+        }
+
+        let options = ParseOptions {
+            chunk_mismatch: ChunkMismatchPolicy::Error,
+            ..Default::default()
+        };
+        assert!(parse_pyreport_buffers(
+            report_json,
+            chunks,
+            TestReportBuilder::default(),
+            &options
+        )
+        .is_err());
+        assert!(parse_pyreport_readers(
+            report_json.as_slice(),
+            chunks.as_bytes(),
+            TestReportBuilder::default(),
+            &options,
+        )
+        .is_err());
+
+        // Without the orphan chunk, the missing one still fails
+        let report_json = br#"{"files": {"src/a.py": [0, {}], "src/b.py": [1, {}], "src/c.py": [2, {}], "src/d.py": [3, {}]}, "sessions": {"0": {}}}"#;
+        let Err(err) =
+            parse_pyreport_buffers(report_json, chunks, TestReportBuilder::default(), &options)
+        else {
+            panic!("expected chunk 3 to be missing");
+        };
+        assert!(err
+            .to_string()
+            .contains("missing chunks for chunk indices [3]"));
+    }
+
+    #[test]
+    fn test_parse_pyreport_scoverage_out_of_range() {
+        // Line 2 has a branch "hit count" Scoverage can't have written
+        let report_json = br#"{"files": {"src/a.scala": [0, {}]}, "sessions": {"0": {}}}"#;
+        let chunks = "{}\n[1, \"b\", [[0, 1]]]\n[3, \"b\", [[0, 3]]]\n";
+
+        let options = ParseOptions::default();
+        let (whole, whole_result) =
+            parse_pyreport_buffers(report_json, chunks, TestReportBuilder::default(), &options)
+                .unwrap();
+        let (streamed, streamed_result) = parse_pyreport_readers(
+            report_json.as_slice(),
+            chunks.as_bytes(),
+            TestReportBuilder::default(),
+            &options,
+        )
+        .unwrap();
+        for (builder, result) in [(whole, whole_result), (streamed, streamed_result)] {
+            let report = builder.report;
+            assert_eq!(report.samples.len(), 2);
+            assert_eq!(report.samples[0].total_branches, Some(2));
+            assert_eq!(report.samples[1].hits, Some(3));
+            assert_eq!(result.quirks_applied["scoverage"], 2);
+            assert_eq!(
+                result.warnings,
+                ["branch hit count 3 is not a Scoverage branch status (0, 1 or 2) on line 2 of chunk 0"]
+            );
+        }
+    }
+}
@@ -96,17 +96,17 @@ fn create_model_sets_for_line_session<R: Report, B: ReportBuilder<R>>(
         ..Default::default()
     };
 
-    // Read the labels index to populate `assocs`
-    let assocs: Vec<_> = datapoint
-        .map_or(&vec![], |datapoint| &datapoint.labels)
+    // Read the labels index to populate `assocs`, leaving off any labels
+    // `ctx.label_pruning` says to
+    let labels = datapoint.map_or(&[][..], |datapoint| &datapoint.labels);
+    let keep = ctx.label_pruning.labels_to_keep(&sample, labels.len());
+    ctx.labels_pruned += labels.len() - keep;
+    let assocs: Vec<_> = labels[..keep]
         .iter()
-        .map(|label| {
-            let label_context_id = ctx.labels_index[label];
-            models::ContextAssoc {
-                context_id: label_context_id,
-                raw_upload_id,
-                ..Default::default()
-            }
+        .map(|&context_id| models::ContextAssoc {
+            context_id,
+            raw_upload_id,
+            ..Default::default()
         })
         .collect();
 
@@ -201,13 +201,21 @@ fn create_model_sets_for_report_line<R: Report, B: ReportBuilder<R>>(
         } else {
             None
         };
-        line_session_models.push(create_model_sets_for_line_session(
+        let mut models = create_model_sets_for_line_session(
             line_session,
             &report_line.coverage_type,
             report_line.line_no,
             datapoint,
             ctx,
-        ));
+        );
+        // We don't know what `messages` means, so we just hold onto it for
+        // whoever writes the report back out
+        if let Some(Some(messages)) = &report_line.messages {
+            if !messages.is_null() {
+                models.sample.messages = Some(messages.clone());
+            }
+        }
+        line_session_models.push(models);
     }
     line_session_models
 }
@@ -237,6 +245,7 @@ pub fn save_report_lines<R: Report, B: ReportBuilder<R>>(
             .map(|LineSessionModels { sample, .. }| sample)
             .collect(),
     )?;
+    ctx.samples_inserted += models.len();
 
     // Populate `local_sample_id` and insert all of the context assocs for each
     // `LineSession` (if there are any)
@@ -313,7 +322,10 @@ mod tests {
     use std::collections::HashMap;
 
     use super::*;
-    use crate::test_utils::test_report::{TestReport, TestReportBuilder};
+    use crate::{
+        report::LabelPruning,
+        test_utils::test_report::{TestReport, TestReportBuilder},
+    };
 
     struct Ctx {
         parse_ctx: ParseCtx<TestReport, TestReportBuilder>,
@@ -335,7 +347,7 @@ mod tests {
         let expected = (models::BranchFormat::BlockAndBranch, "0:1".to_string());
         assert_eq!(format_pyreport_branch(&input), expected);
 
-        let input = MissingBranch::Condition(0, Some("jump".to_string()));
+        let input = MissingBranch::Condition(0, Some("jump"));
         let expected = (models::BranchFormat::Condition, "0:jump".to_string());
         assert_eq!(format_pyreport_branch(&input), expected);
 
@@ -431,16 +443,11 @@ mod tests {
         };
         let input_type = models::CoverageType::Line;
 
-        parse_ctx.labels_index = HashMap::from([
-            ("test_label".to_string(), 50),
-            ("test_label_2".to_string(), 51),
-        ]);
-
         let datapoint = CoverageDatapoint {
             session_id: 0,
             _coverage: PyreportCoverage::HitCount(4),
             _coverage_type: None,
-            labels: vec!["test_label".to_string(), "test_label_2".to_string()],
+            labels: vec![50, 51],
         };
 
         let line_session_models = create_model_sets_for_line_session(
@@ -479,6 +486,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_create_model_sets_for_line_session_label_pruning() {
+        let mut test_ctx = setup();
+        let parse_ctx = &mut test_ctx.parse_ctx;
+        parse_ctx.chunk.index = 0;
+        parse_ctx.chunk.current_line = 1;
+        parse_ctx.label_pruning = LabelPruning {
+            max_labels_per_sample: Some(2),
+            drop_fully_covered: true,
+        };
+
+        let datapoint = CoverageDatapoint {
+            session_id: 0,
+            _coverage: PyreportCoverage::HitCount(0),
+            _coverage_type: None,
+            labels: vec![50, 51, 52],
+        };
+        let mut session = LineSession {
+            session_id: 0,
+            coverage: PyreportCoverage::HitCount(0),
+            branches: None,
+            partials: None,
+            complexity: None,
+        };
+
+        // A missed line keeps the first two labels
+        let models = create_model_sets_for_line_session(
+            &session,
+            &models::CoverageType::Line,
+            5,
+            Some(&datapoint),
+            parse_ctx,
+        );
+        let context_ids: Vec<i64> = models.assocs.iter().map(|a| a.context_id).collect();
+        assert_eq!(context_ids, [50, 51]);
+        assert_eq!(parse_ctx.labels_pruned, 1);
+
+        // A hit line keeps none of them
+        session.coverage = PyreportCoverage::HitCount(4);
+        let models = create_model_sets_for_line_session(
+            &session,
+            &models::CoverageType::Line,
+            6,
+            Some(&datapoint),
+            parse_ctx,
+        );
+        assert!(models.assocs.is_empty());
+        assert_eq!(parse_ctx.labels_pruned, 4);
+    }
+
     #[test]
     fn test_create_model_sets_for_line_session_line_with_partials() {
         let mut test_ctx = setup();
@@ -789,7 +846,7 @@ mod tests {
                 total: 4,
             },
             branches: Some(Some(vec![
-                MissingBranch::Condition(0, Some("jump".to_string())),
+                MissingBranch::Condition(0, Some("jump")),
                 MissingBranch::Condition(1, None),
             ])),
             partials: None,
@@ -916,7 +973,7 @@ mod tests {
             coverage,
             sessions,
             coverage_type,
-            _messages: None,
+            messages: None,
             _complexity: None,
             datapoints: None,
         };
@@ -971,11 +1028,6 @@ mod tests {
         let coverage_type = models::CoverageType::Line;
         let coverage = PyreportCoverage::HitCount(10);
 
-        parse_ctx.labels_index = HashMap::from([
-            ("test_label".to_string(), 50),
-            ("test_label_2".to_string(), 51),
-        ]);
-
         let sessions: Vec<_> = [0, 1, 2]
             .iter()
             .map(|i| LineSession {
@@ -994,7 +1046,7 @@ mod tests {
                     session_id: 0,
                     _coverage: coverage.clone(),
                     _coverage_type: Some(coverage_type),
-                    labels: vec!["test_label".to_string(), "test_label_2".to_string()],
+                    labels: vec![50, 51],
                 },
             ),
             (
@@ -1003,7 +1055,7 @@ mod tests {
                     session_id: 2,
                     _coverage: coverage.clone(),
                     _coverage_type: Some(coverage_type),
-                    labels: vec!["test_label_2".to_string()],
+                    labels: vec![51],
                 },
             ),
         ]);
@@ -1013,7 +1065,7 @@ mod tests {
             coverage,
             sessions,
             coverage_type,
-            _messages: None,
+            messages: None,
             _complexity: None,
             datapoints: Some(Some(datapoints)),
         };
@@ -1079,10 +1131,6 @@ mod tests {
     #[test]
     fn test_save_report_lines() {
         let mut test_ctx = setup();
-        test_ctx.parse_ctx.labels_index = HashMap::from([
-            ("test_label".to_string(), 50),
-            ("test_label_2".to_string(), 51),
-        ]);
         test_ctx.parse_ctx.chunk.current_line = 1;
         test_ctx.parse_ctx.chunk.index = 0;
 
@@ -1114,7 +1162,7 @@ mod tests {
                         complexity: None,
                     },
                 ],
-                _messages: None,
+                messages: None,
                 _complexity: None,
                 datapoints: Some(Some(HashMap::from([(
                     0,
@@ -1122,7 +1170,7 @@ mod tests {
                         session_id: 0,
                         _coverage: PyreportCoverage::HitCount(10),
                         _coverage_type: None,
-                        labels: vec!["test_label".to_string()],
+                        labels: vec![50],
                     },
                 )]))),
             },
@@ -1147,7 +1195,7 @@ mod tests {
                     partials: None,
                     complexity: None,
                 }],
-                _messages: None,
+                messages: None,
                 _complexity: None,
                 datapoints: Some(Some(HashMap::from([(
                     0,
@@ -1158,7 +1206,7 @@ mod tests {
                             total: 4,
                         },
                         _coverage_type: None,
-                        labels: vec!["test_label".to_string()],
+                        labels: vec![50],
                     },
                 )]))),
             },
@@ -1174,7 +1222,7 @@ mod tests {
                     partials: None,
                     complexity: Some(Some(Complexity::Total(4))),
                 }],
-                _messages: None,
+                messages: None,
                 _complexity: None,
                 datapoints: Some(Some(HashMap::from([(
                     2,
@@ -1182,7 +1230,7 @@ mod tests {
                         session_id: 2,
                         _coverage: PyreportCoverage::HitCount(3),
                         _coverage_type: None,
-                        labels: vec!["test_label_2".to_string()],
+                        labels: vec![51],
                     },
                 )]))),
             },
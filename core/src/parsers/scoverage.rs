@@ -0,0 +1,439 @@
+//! Parses the `scoverage.xml` reports written by [scoverage](https://github.com/scoverage),
+//! the coverage tool for Scala.
+//!
+//! scoverage measures statements rather than lines. Each statement knows its
+//! line, how often it ran, and whether it is one side of a branch (`if`,
+//! `match` cases and the like):
+//! ```xml
+//! <scoverage statement-count="3" statements-invoked="2" version="1.0">
+//!   <packages>
+//!     <package name="com.example">
+//!       <classes>
+//!         <class name="com.example.Greeter" filename="com/example/Greeter.scala">
+//!           <methods>
+//!             <method name="com.example/Greeter/greet">
+//!               <statements>
+//!                 <statement source="/repo/src/main/scala/com/example/Greeter.scala"
+//!                   method="greet" start="112" end="140" line="6" branch="false"
+//!                   invocation-count="2" ignored="false" />
+//!                 <statement source="/repo/src/main/scala/com/example/Greeter.scala"
+//!                   method="greet" start="150" end="161" line="7" branch="true"
+//!                   invocation-count="0" ignored="false" />
+//! ...
+//! ```
+//!
+//! Each parsed file produces a single [`models::RawUpload`] and a
+//! [`models::SourceFile`] for each statement `source`, or for its class's
+//! `filename` if it has none. Every statement becomes a
+//! [`models::SpanData`] on its line; its `start` and `end` are character
+//! offsets into the file, not columns, so they aren't kept. Ignored
+//! statements are skipped, as are source files matching
+//! [`ScoverageOptions::ignore_globs`].
+//!
+//! Statements are then aggregated per line. A line with branch statements is
+//! a branch sample with a [`models::BranchesData`] for each of them, numbered
+//! in the order they appear on the line in [`models::BranchFormat::Condition`]
+//! format. Other lines get the most hits of any of their statements, but a
+//! line where some statements ran and others didn't is partial, which is
+//! recorded as 1 of 2 branches hit like pyreport's partial lines. Reading
+//! scoverage's own format this way avoids the mangled branch counts seen when
+//! it's converted to Cobertura first, which the pyreport parser's
+//! `ScoverageQuirks` has to undo.
+//!
+//! The first line of each method gets a [`models::MethodData`] record named
+//! after the method, and if it isn't a branch, is recorded as a method sample.
+
+use std::collections::BTreeMap;
+
+use roxmltree::{Document, Node};
+
+use super::common::{
+    xml::{list, XmlInput},
+    IgnoreGlobs, IngestResult, Stopwatch,
+};
+use crate::{
+    error::{CodecovError, Result},
+    report::{models, Report, ReportBuilder},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct ScoverageOptions {
+    /// Paths of source files to leave out of the report.
+    pub ignore_globs: IgnoreGlobs,
+}
+
+#[derive(Debug, Default)]
+struct LineTotals {
+    hits: i64,
+    missed: bool,
+    // The hits of each branch statement, keyed by its start offset
+    branches: BTreeMap<i64, i64>,
+}
+
+#[derive(Debug, Default)]
+struct FileTotals {
+    lines: BTreeMap<i64, LineTotals>,
+    // The hits and line of each statement
+    spans: Vec<(i64, i64)>,
+    // Method names keyed by their first line
+    methods: BTreeMap<i64, String>,
+}
+
+fn parse_files(input: &XmlInput, root: Node) -> Result<BTreeMap<String, FileTotals>> {
+    let mut files: BTreeMap<String, FileTotals> = BTreeMap::new();
+    let classes =
+        list(root, "packages", "package").flat_map(|package| list(package, "classes", "class"));
+    for class in classes {
+        let class_filename = class.attribute("filename");
+        for method in list(class, "methods", "method") {
+            let method_name = method.attribute("name");
+            // The first line of the method in each file it has statements in
+            let mut first_lines: BTreeMap<&str, i64> = BTreeMap::new();
+            for statement in list(method, "statements", "statement") {
+                if input.opt_attr(statement, "ignored")? == Some(true) {
+                    continue;
+                }
+                let Some(path) = statement.attribute("source").or(class_filename) else {
+                    return Err(input.error(statement, "source"));
+                };
+                let line_no: i64 = input.attr(statement, "line")?;
+                let hits: i64 = input.attr(statement, "invocation-count")?;
+
+                let file = files.entry(path.to_string()).or_default();
+                let line = file.lines.entry(line_no).or_default();
+                if input.opt_attr(statement, "branch")? == Some(true) {
+                    let start = input.attr(statement, "start")?;
+                    *line.branches.entry(start).or_default() += hits;
+                } else {
+                    line.hits = line.hits.max(hits);
+                    line.missed |= hits == 0;
+                }
+                file.spans.push((hits, line_no));
+
+                let first_line = first_lines.entry(path).or_insert(line_no);
+                *first_line = (*first_line).min(line_no);
+            }
+
+            if let Some(name) = method_name {
+                for (path, line_no) in first_lines {
+                    let file = files.get_mut(path).unwrap();
+                    file.methods
+                        .entry(line_no)
+                        .or_insert_with(|| name.to_string());
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Parses a scoverage XML report into `builder` as a single
+/// [`models::RawUpload`].
+pub fn parse_scoverage_xml<B, R>(input: &[u8], builder: &mut B) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    parse_scoverage_xml_with_options(input, builder, &ScoverageOptions::default())
+}
+
+/// Like [`parse_scoverage_xml`], but with non-default [`ScoverageOptions`].
+pub fn parse_scoverage_xml_with_options<B, R>(
+    input: &[u8],
+    builder: &mut B,
+    options: &ScoverageOptions,
+) -> Result<IngestResult>
+where
+    B: ReportBuilder<R>,
+    R: Report,
+{
+    let stopwatch = Stopwatch::start();
+    let input = String::from_utf8_lossy(input);
+    let xml = Document::parse(&input)?;
+    let root = xml.root_element();
+    if root.tag_name().name() != "scoverage" {
+        return Err(CodecovError::UnsupportedFormat {
+            format: "xml".to_string(),
+            reason: format!(
+                "expected a scoverage report, found <{}>",
+                root.tag_name().name()
+            ),
+        });
+    }
+    let mut files = parse_files(&XmlInput(&input), root)?;
+    let files_before = files.len();
+    files.retain(|path, _| !options.ignore_globs.is_ignored(path));
+
+    let raw_upload = builder.insert_raw_upload(Default::default())?;
+    let mut result = IngestResult {
+        files_ignored: files_before - files.len(),
+        ..Default::default()
+    };
+
+    for (path, totals) in files {
+        let file = builder.insert_file(&path)?;
+        result.files_touched += 1;
+
+        let mut samples: Vec<models::CoverageSample> = totals
+            .lines
+            .iter()
+            .map(|(&line_no, line)| {
+                let mut sample = models::CoverageSample {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    ..Default::default()
+                };
+                if !line.branches.is_empty() {
+                    sample.coverage_type = models::CoverageType::Branch;
+                    sample.hit_branches =
+                        Some(line.branches.values().filter(|h| **h > 0).count() as i64);
+                    sample.total_branches = Some(line.branches.len() as i64);
+                } else if line.hits > 0 && line.missed {
+                    sample.coverage_type = models::CoverageType::Branch;
+                    sample.hit_branches = Some(1);
+                    sample.total_branches = Some(2);
+                } else {
+                    sample.hits = Some(line.hits);
+                    if totals.methods.contains_key(&line_no) {
+                        sample.coverage_type = models::CoverageType::Method;
+                    }
+                }
+                sample
+            })
+            .collect();
+        builder.multi_insert_coverage_sample(samples.iter_mut().collect())?;
+        result.samples_inserted += samples.len();
+        let sample_ids: BTreeMap<i64, i64> = samples
+            .iter()
+            .map(|sample| (sample.line_no, sample.local_sample_id))
+            .collect();
+
+        let mut branches = vec![];
+        let mut methods = vec![];
+        for (sample, line) in samples.iter().zip(totals.lines.values()) {
+            for (index, &hits) in line.branches.values().enumerate() {
+                branches.push(models::BranchesData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    hits,
+                    branch_format: models::BranchFormat::Condition,
+                    branch: index.to_string(),
+                    ..Default::default()
+                });
+            }
+            if let Some(name) = totals.methods.get(&sample.line_no) {
+                methods.push(models::MethodData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    line_no: Some(sample.line_no),
+                    name: Some(name.clone()),
+                    ..Default::default()
+                });
+            }
+        }
+        builder.multi_insert_branches_data(branches.iter_mut().collect())?;
+        builder.multi_insert_method_data(methods.iter_mut().collect())?;
+
+        let mut spans: Vec<models::SpanData> = totals
+            .spans
+            .iter()
+            .map(|&(hits, line_no)| models::SpanData {
+                raw_upload_id: raw_upload.id,
+                source_file_id: file.id,
+                local_sample_id: sample_ids.get(&line_no).copied(),
+                hits,
+                start_line: Some(line_no),
+                end_line: Some(line_no),
+                ..Default::default()
+            })
+            .collect();
+        builder.multi_insert_span_data(spans.iter_mut().collect())?;
+    }
+
+    result.raw_upload = Some(raw_upload);
+    result.duration = stopwatch.elapsed();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_report::TestReportBuilder;
+
+    #[test]
+    fn test_parse_scoverage_xml() {
+        let input = br#"<?xml version="1.0" encoding="utf-8"?>
+<scoverage statement-count="7" statements-invoked="4" statement-rate="57.14" branch-rate="50.00" version="1.0" timestamp="1700000000000">
+  <packages>
+    <package name="com.example" statement-count="7" statements-invoked="4" statement-rate="57.14">
+      <classes>
+        <class name="com.example.Greeter" filename="com/example/Greeter.scala" statement-count="7" statements-invoked="4" statement-rate="57.14" branch-rate="50.00">
+          <methods>
+            <method name="com/example/Greeter/greet" statement-count="6" statements-invoked="4" statement-rate="66.67" branch-rate="50.00">
+              <statements>
+                <statement package="com.example" class="Greeter" class-type="Class" full-class-name="com.example.Greeter" source="/repo/Greeter.scala" method="greet" start="112" end="140" line="6" branch="false" invocation-count="2" ignored="false"></statement>
+                <statement package="com.example" class="Greeter" class-type="Class" full-class-name="com.example.Greeter" source="/repo/Greeter.scala" method="greet" start="170" end="180" line="7" branch="true" invocation-count="0" ignored="false"></statement>
+                <statement package="com.example" class="Greeter" class-type="Class" full-class-name="com.example.Greeter" source="/repo/Greeter.scala" method="greet" start="150" end="161" line="7" branch="true" invocation-count="2" ignored="false"></statement>
+                <statement package="com.example" class="Greeter" class-type="Class" full-class-name="com.example.Greeter" source="/repo/Greeter.scala" method="greet" start="190" end="200" line="8" branch="false" invocation-count="2" ignored="false"></statement>
+                <statement package="com.example" class="Greeter" class-type="Class" full-class-name="com.example.Greeter" source="/repo/Greeter.scala" method="greet" start="201" end="220" line="8" branch="false" invocation-count="0" ignored="false"></statement>
+                <statement package="com.example" class="Greeter" class-type="Class" full-class-name="com.example.Greeter" source="/repo/Greeter.scala" method="greet" start="230" end="240" line="9" branch="false" invocation-count="0" ignored="false"></statement>
+              </statements>
+            </method>
+            <method name="com/example/Greeter/debug">
+              <statements>
+                <statement source="/repo/Greeter.scala" start="250" end="260" line="12" branch="false" invocation-count="0" ignored="true"></statement>
+              </statements>
+            </method>
+          </methods>
+        </class>
+      </classes>
+    </package>
+  </packages>
+</scoverage>"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let result = parse_scoverage_xml(input, &mut report_builder).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.samples_inserted), (1, 4));
+        let raw_upload = result.raw_upload.unwrap();
+
+        let file = models::SourceFile::new("/repo/Greeter.scala");
+        assert_eq!(report.files, std::slice::from_ref(&file));
+        assert_eq!(report.uploads.len(), 1);
+
+        let sample =
+            |line_no, coverage_type, hits, hit_branches, total_branches| models::CoverageSample {
+                raw_upload_id: raw_upload.id,
+                source_file_id: file.id,
+                line_no,
+                coverage_type,
+                hits,
+                hit_branches,
+                total_branches,
+                ..Default::default()
+            };
+        let samples: Vec<_> = report
+            .samples
+            .iter()
+            .map(|s| models::CoverageSample {
+                local_sample_id: 0,
+                ..s.clone()
+            })
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                sample(6, models::CoverageType::Method, Some(2), None, None),
+                sample(7, models::CoverageType::Branch, None, Some(1), Some(2)),
+                sample(8, models::CoverageType::Branch, None, Some(1), Some(2)),
+                sample(9, models::CoverageType::Line, Some(0), None, None),
+            ]
+        );
+
+        // Branches are numbered by where they start on the line
+        let line_7 = report.samples[1].local_sample_id;
+        let branches: Vec<_> = report
+            .branches
+            .iter()
+            .map(|b| (b.local_sample_id, b.branch.as_str(), b.hits))
+            .collect();
+        assert_eq!(branches, vec![(line_7, "0", 2), (line_7, "1", 0)]);
+
+        assert_eq!(report.methods.len(), 1);
+        assert_eq!(
+            report.methods[0].name.as_deref(),
+            Some("com/example/Greeter/greet")
+        );
+        assert_eq!(
+            report.methods[0].local_sample_id,
+            report.samples[0].local_sample_id
+        );
+
+        let spans: Vec<_> = report
+            .spans
+            .iter()
+            .map(|s| (s.hits, s.start_line, s.local_sample_id))
+            .collect();
+        assert_eq!(spans.len(), 6);
+        assert_eq!(
+            spans[4],
+            (0, Some(8), Some(report.samples[2].local_sample_id))
+        );
+    }
+
+    #[test]
+    fn test_parse_scoverage_xml_ignore_globs() {
+        let input = br#"<scoverage>
+  <packages>
+    <package name="com.example">
+      <classes>
+        <class name="com.example.Greeter" filename="src/main/scala/Greeter.scala">
+          <methods>
+            <method name="greet">
+              <statements>
+                <statement line="6" invocation-count="1" />
+                <statement source="target/scala/BuildInfo.scala" line="3" invocation-count="0" />
+              </statements>
+            </method>
+          </methods>
+        </class>
+      </classes>
+    </package>
+  </packages>
+</scoverage>"#;
+
+        let mut report_builder = TestReportBuilder::default();
+        let options = ScoverageOptions {
+            ignore_globs: IgnoreGlobs(vec!["target/**".to_string()]),
+        };
+        let result =
+            parse_scoverage_xml_with_options(input, &mut report_builder, &options).unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!((result.files_touched, result.files_ignored), (1, 1));
+        assert_eq!(
+            report.files,
+            &[models::SourceFile::new("src/main/scala/Greeter.scala")]
+        );
+        assert_eq!(report.samples.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_scoverage_xml_errors() {
+        let mut report_builder = TestReportBuilder::default();
+
+        let error = parse_scoverage_xml(b"<scoverage>", &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::Xml(_)));
+
+        let error = parse_scoverage_xml(b"<coverage />", &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::UnsupportedFormat { .. }));
+
+        let input = br#"<scoverage><packages><package><classes><class>
+<methods><method name="a"><statements>
+<statement source="a.scala" line="1" invocation-count="lots" />
+</statements></method></methods>
+</class></classes></package></packages></scoverage>"#;
+        let error = parse_scoverage_xml(input, &mut report_builder).unwrap_err();
+        assert!(matches!(
+            error,
+            CodecovError::ParserError {
+                line: 3,
+                column: 1,
+                ..
+            }
+        ));
+
+        let input = br#"<scoverage><packages><package><classes><class>
+<methods><method name="a"><statements>
+<statement line="1" invocation-count="1" />
+</statements></method></methods>
+</class></classes></package></packages></scoverage>"#;
+        let error = parse_scoverage_xml(input, &mut report_builder).unwrap_err();
+        assert!(matches!(error, CodecovError::ParserError { line: 3, .. }));
+
+        // Nothing is inserted for a report that fails to parse
+        assert!(report_builder.build().unwrap().uploads.is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+//! The supported way to use `codecov-rs` from other crates. Everything here is
+//! covered by semver: it won't change or go away outside of a breaking
+//! release, unlike the modules it is re-exported from, which are organized
+//! for our own convenience.
+//!
+//! ```
+//! use codecov_rs::prelude::*;
+//!
+//! # #[cfg(feature = "sqlite")]
+//! # fn main() -> Result<()> {
+//! # let temp_dir = tempfile::TempDir::new()?;
+//! let mut builder = SqliteReportBuilder::open(temp_dir.path().join("report.sqlite"))?;
+//! let upload = builder.insert_raw_upload(models::RawUpload::default())?;
+//! let file = builder.insert_file("src/lib.rs")?;
+//! builder.insert_coverage_sample(models::CoverageSample {
+//!     raw_upload_id: upload.id,
+//!     source_file_id: file.id,
+//!     line_no: 1,
+//!     hits: Some(1),
+//!     ..Default::default()
+//! })?;
+//! let report = builder.build()?;
+//!
+//! assert_eq!(report.summary()?.totals.hits, 1);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "sqlite"))]
+//! # fn main() {}
+//! ```
+
+// Building, querying and merging reports
+// Parsing coverage formats
+#[cfg(feature = "coveragepy")]
+pub use crate::parsers::coveragepy::{parse_coveragepy_json, CoveragePyOptions};
+#[cfg(feature = "coverlet")]
+pub use crate::parsers::coverlet::{
+    parse_coverlet_json, parse_coverlet_json_with_options, CoverletOptions,
+};
+#[cfg(feature = "gcov")]
+pub use crate::parsers::gcov::{parse_gcov, parse_gcov_with_options, GcovOptions};
+#[cfg(feature = "lcov")]
+pub use crate::parsers::lcov::{parse_lcov, LcovOptions};
+#[cfg(feature = "opencover")]
+pub use crate::parsers::opencover::{
+    parse_opencover_xml, parse_opencover_xml_with_options, OpenCoverOptions,
+};
+#[cfg(feature = "pyreport")]
+pub use crate::parsers::pyreport::{
+    chunks::ChunkMismatchPolicy, parse_pyreport_buffers, parse_pyreport_readers,
+    report_json::SessionKeyPolicy, ParseOptions,
+};
+#[cfg(all(feature = "pyreport", feature = "sqlite"))]
+pub use crate::parsers::pyreport::{parse_pyreport, parse_pyreport_with_options};
+#[cfg(feature = "scoverage")]
+pub use crate::parsers::scoverage::{
+    parse_scoverage_xml, parse_scoverage_xml_with_options, ScoverageOptions,
+};
+// Exporting reports
+#[cfg(all(feature = "pyreport", feature = "sqlite"))]
+pub use crate::report::pyreport::{PyreportOptions, ToPyreport};
+#[cfg(feature = "sqlite")]
+pub use crate::report::{
+    from_samples,
+    sqlite::{
+        BatchPolicy, BuilderInstrumentation, BuilderStats, BulkInsertMode, BusyPolicy,
+        ChecksumStatus, IntegrityMode, ReportStats, StatementCacheStats,
+    },
+    SqliteReport, SqliteReportBuilder, SqliteReportBuilderTx,
+};
+#[cfg(feature = "wasm")]
+pub use crate::report::{MemoryReport, MemoryReportBuilder};
+pub use crate::{
+    error::{CodecovError, Result},
+    parsers::common::{IgnoreGlobs, IngestResult, LineBoundsPolicy},
+    report::{
+        insert_samples, models,
+        summary::{ReportSummary, SummaryCounts},
+        DuplicateUploadPolicy, LabelPruning, MergePolicy, Report, ReportBuilder, SampleSpec,
+    },
+};
@@ -0,0 +1,240 @@
+//! Line-by-line notes on how a change affected coverage, for posting as review
+//! comments on a pull request.
+//!
+//! [`annotate`] compares the coverage of a base and head commit over the lines
+//! a diff touches. It flags added lines that aren't fully covered and
+//! unchanged lines that lost coverage. Review comments can only go on lines in
+//! the diff's hunks, so callers posting to GitHub may need to drop
+//! [`AnnotationKind::LostCoverage`] annotations outside of them.
+
+use std::{collections::BTreeSet, fmt};
+
+use super::{
+    line_coverage::{line_coverage, LineCoverage, LineStatus},
+    Report,
+};
+use crate::error::Result;
+
+/// How one file changed between the base and head commits.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct FileDiff {
+    /// The file's path in the head commit.
+    pub path: String,
+
+    /// The file's path in the base commit, or `None` if the diff adds the
+    /// file.
+    pub base_path: Option<String>,
+
+    /// The lines in the head commit that the diff adds.
+    pub added_lines: BTreeSet<i64>,
+
+    /// The lines in the base commit that the diff removes.
+    pub removed_lines: BTreeSet<i64>,
+}
+
+impl FileDiff {
+    /// The line in the base commit that `line_no` in the head commit was,
+    /// or `None` if the diff added it.
+    pub fn base_line(&self, line_no: i64) -> Option<i64> {
+        if self.added_lines.contains(&line_no) {
+            return None;
+        }
+        // Unchanged lines are in the same order on both sides, so skip past
+        // the additions before this line and then the removals
+        let mut base_line = line_no - self.added_lines.range(..line_no).count() as i64;
+        for removed in &self.removed_lines {
+            if *removed > base_line {
+                break;
+            }
+            base_line += 1;
+        }
+        Some(base_line)
+    }
+}
+
+/// What an [`Annotation`] says about its line.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum AnnotationKind {
+    /// An added line that no test covered.
+    UncoveredAddition,
+
+    /// An added line where only some branches were taken.
+    PartialAddition,
+
+    /// A line the diff didn't change that's less covered than it was in the
+    /// base commit.
+    LostCoverage,
+}
+
+impl fmt::Display for AnnotationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AnnotationKind::UncoveredAddition => "added line not covered",
+            AnnotationKind::PartialAddition => "added line only partially covered",
+            AnnotationKind::LostCoverage => "line lost coverage",
+        })
+    }
+}
+
+/// A note about one line of the head commit. Its message is the `Display` of
+/// its [`AnnotationKind`].
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct Annotation {
+    /// The file's path in the head commit.
+    pub path: String,
+    pub line_no: i64,
+    pub kind: AnnotationKind,
+}
+
+/// Annotates the files in `diff` with how their coverage in `head` differs
+/// from `base`. Annotations are in the order of `diff`, then by line.
+///
+/// Lines count as covered the way [`LineCoverage`] counts them, merging every
+/// upload. A file missing from either report has no coverage there, so an
+/// unchanged line in a file `head` has no coverage for isn't annotated.
+pub fn annotate<B: Report, H: Report>(
+    base: &B,
+    head: &H,
+    diff: &[FileDiff],
+) -> Result<Vec<Annotation>> {
+    let mut annotations = vec![];
+    for file in diff {
+        let head_coverage = coverage_for_path(head, &file.path)?;
+        let base_coverage = match &file.base_path {
+            Some(base_path) => coverage_for_path(base, base_path)?,
+            None => LineCoverage::default(),
+        };
+
+        for (line_no, status) in (1..).zip(head_coverage.statuses()) {
+            let kind = match (file.base_line(line_no), status) {
+                (None, LineStatus::Miss) => Some(AnnotationKind::UncoveredAddition),
+                (None, LineStatus::Partial) => Some(AnnotationKind::PartialAddition),
+                (None, _) | (_, LineStatus::Untracked) => None,
+                (Some(base_line), status) => (*status < base_coverage.status(base_line))
+                    .then_some(AnnotationKind::LostCoverage),
+            };
+            if let Some(kind) = kind {
+                annotations.push(Annotation {
+                    path: file.path.clone(),
+                    line_no,
+                    kind,
+                });
+            }
+        }
+    }
+    Ok(annotations)
+}
+
+fn coverage_for_path<R: Report>(report: &R, path: &str) -> Result<LineCoverage> {
+    match report.get_file_metadata(path)? {
+        Some(file) => line_coverage(report, &file),
+        None => Ok(LineCoverage::default()),
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{from_samples, models, SampleSpec, SqliteReport};
+
+    fn report(temp_dir: &TempDir, name: &str, path: &str, lines: &[(i64, i64)]) -> SqliteReport {
+        let samples: Vec<_> = lines
+            .iter()
+            .map(|&(line_no, hits)| SampleSpec::line(0, 0, line_no, hits))
+            .collect();
+        from_samples(
+            temp_dir.path().join(name),
+            &[path],
+            &[models::RawUpload::default()],
+            &samples,
+        )
+        .unwrap()
+    }
+
+    fn annotation(path: &str, line_no: i64, kind: AnnotationKind) -> Annotation {
+        Annotation {
+            path: path.to_string(),
+            line_no,
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_base_line() {
+        let diff = FileDiff {
+            path: "src/lib.rs".to_string(),
+            base_path: Some("src/lib.rs".to_string()),
+            added_lines: BTreeSet::from([2, 3, 7]),
+            removed_lines: BTreeSet::from([4, 5]),
+        };
+        // Base lines 1, 2, 3, 6, 7 and 8 are head lines 1, 4, 5, 6, 8, 9
+        let expected = [
+            Some(1),
+            None,
+            None,
+            Some(2),
+            Some(3),
+            Some(6),
+            None,
+            Some(7),
+            Some(8),
+        ];
+        for (line_no, expected) in (1..).zip(expected) {
+            assert_eq!(diff.base_line(line_no), expected, "line {line_no}");
+        }
+    }
+
+    #[test]
+    fn test_annotate() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = report(
+            &temp_dir,
+            "base.sqlite",
+            "src/old.rs",
+            &[(1, 1), (2, 1), (3, 0), (4, 1)],
+        );
+        // The file was renamed, line 2 was added, and the old line 3 was
+        // removed. Old line 4 is now line 4 and lost its coverage.
+        let head = report(
+            &temp_dir,
+            "head.sqlite",
+            "src/new.rs",
+            &[(1, 1), (2, 0), (3, 1), (4, 0), (5, 0)],
+        );
+        let diff = [FileDiff {
+            path: "src/new.rs".to_string(),
+            base_path: Some("src/old.rs".to_string()),
+            added_lines: BTreeSet::from([2, 5]),
+            removed_lines: BTreeSet::from([3]),
+        }];
+
+        let annotations = annotate(&base, &head, &diff).unwrap();
+        assert_eq!(
+            annotations,
+            [
+                annotation("src/new.rs", 2, AnnotationKind::UncoveredAddition),
+                annotation("src/new.rs", 4, AnnotationKind::LostCoverage),
+                annotation("src/new.rs", 5, AnnotationKind::UncoveredAddition),
+            ]
+        );
+        assert_eq!(annotations[0].kind.to_string(), "added line not covered");
+
+        // A new file has nothing to lose coverage from
+        let diff = [FileDiff {
+            path: "src/new.rs".to_string(),
+            base_path: None,
+            added_lines: BTreeSet::from([1, 2]),
+            ..Default::default()
+        }];
+        assert_eq!(
+            annotate(&base, &head, &diff).unwrap(),
+            [annotation(
+                "src/new.rs",
+                2,
+                AnnotationKind::UncoveredAddition
+            )]
+        );
+    }
+}
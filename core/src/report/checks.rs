@@ -0,0 +1,435 @@
+//! Pass/fail checks comparing a head commit's coverage to its base, like the
+//! project, patch and component statuses Codecov posts on pull requests.
+//!
+//! Each [`Check`] measures the coverage of one [`Scope`] in the head report
+//! and compares it to a target: a fixed percentage, or the same scope's
+//! coverage in the base report. [`evaluate_checks`] returns a
+//! [`CheckResult`] for each, with a reason that can be shown to users as-is.
+//!
+//! Coverage is the percentage of tracked lines that were fully hit, counted
+//! the way [`LineCoverage`] counts them, so partially covered lines count
+//! against it, the same as in [`SummaryCounts::coverage_pct`].
+//!
+//! [`SummaryCounts::coverage_pct`]: super::summary::SummaryCounts::coverage_pct
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    annotate::FileDiff,
+    components::Component,
+    line_coverage::{line_coverage_by_path, LineCoverage, LineStatus},
+    Report,
+};
+use crate::error::Result;
+
+/// Which lines a [`Check`] measures.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Every line in the report.
+    Project,
+    /// The lines the diff adds.
+    Patch,
+    /// Every line in the component's files.
+    Component(Component),
+}
+
+/// A condition for the coverage of one [`Scope`].
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Check {
+    /// A name for the check, e.g. `codecov/project`.
+    pub name: String,
+
+    pub scope: Scope,
+
+    /// The coverage percentage the scope has to reach. If `None`, the target
+    /// is the coverage of the project (for [`Scope::Patch`]) or of the same
+    /// scope (otherwise) in the base report, like `target: auto` in a
+    /// `codecov.yml`.
+    #[serde(default)]
+    pub target: Option<f64>,
+
+    /// How many percentage points below the target coverage may fall before
+    /// the check fails.
+    #[serde(default)]
+    pub threshold: f64,
+}
+
+/// Whether a [`Check`] passed.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckState {
+    Success,
+    Failure,
+}
+
+/// The outcome of a [`Check`].
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct CheckResult {
+    /// The [`Check::name`] of the check.
+    pub name: String,
+
+    pub state: CheckState,
+
+    /// The scope's coverage in the head report, or `None` if it has no
+    /// tracked lines.
+    pub coverage: Option<f64>,
+
+    /// The coverage the scope had to reach, or `None` if there was nothing to
+    /// compare against.
+    pub target: Option<f64>,
+
+    /// Why the check passed or failed.
+    pub reason: String,
+}
+
+/// Tracked and fully hit line counts.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+struct LineCounts {
+    lines: u64,
+    hits: u64,
+}
+
+impl LineCounts {
+    fn add(&mut self, status: LineStatus) {
+        if status != LineStatus::Untracked {
+            self.lines += 1;
+        }
+        if status == LineStatus::Hit {
+            self.hits += 1;
+        }
+    }
+
+    fn coverage_pct(self) -> Option<f64> {
+        (self.lines > 0).then(|| self.hits as f64 * 100.0 / self.lines as f64)
+    }
+}
+
+/// Counts the lines in the files whose paths `include`.
+fn count_files(
+    coverage: &BTreeMap<String, LineCoverage>,
+    include: impl Fn(&str) -> bool,
+) -> LineCounts {
+    let mut counts = LineCounts::default();
+    for (_, file_coverage) in coverage.iter().filter(|(path, _)| include(path)) {
+        for status in file_coverage.statuses() {
+            counts.add(*status);
+        }
+    }
+    counts
+}
+
+/// Counts the lines `diff` adds.
+fn count_patch(coverage: &BTreeMap<String, LineCoverage>, diff: &[FileDiff]) -> LineCounts {
+    let mut counts = LineCounts::default();
+    for file in diff {
+        if let Some(file_coverage) = coverage.get(&file.path) {
+            for line_no in &file.added_lines {
+                counts.add(file_coverage.status(*line_no));
+            }
+        }
+    }
+    counts
+}
+
+/// A percentage for a [`CheckResult::reason`].
+struct Pct(f64);
+
+impl fmt::Display for Pct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2}%", self.0)
+    }
+}
+
+/// Evaluates each of `checks` against `head`, comparing to `base` where a
+/// check has no fixed target. `diff` is only used by [`Scope::Patch`]
+/// checks. Results are in the order of `checks`.
+///
+/// A check passes if its scope has no tracked lines in `head`, since nothing
+/// was measured, or if it compares to `base` and the scope has no tracked
+/// lines there.
+pub fn evaluate_checks<B: Report, H: Report>(
+    base: &B,
+    head: &H,
+    diff: &[FileDiff],
+    checks: &[Check],
+) -> Result<Vec<CheckResult>> {
+    let base_coverage = line_coverage_by_path(base)?;
+    let head_coverage = line_coverage_by_path(head)?;
+
+    let mut results = vec![];
+    for check in checks {
+        let (head_counts, base_counts) = match &check.scope {
+            Scope::Project => (
+                count_files(&head_coverage, |_| true),
+                count_files(&base_coverage, |_| true),
+            ),
+            Scope::Patch => (
+                count_patch(&head_coverage, diff),
+                count_files(&base_coverage, |_| true),
+            ),
+            Scope::Component(component) => (
+                count_files(&head_coverage, |path| component.matches(path)),
+                count_files(&base_coverage, |path| component.matches(path)),
+            ),
+        };
+        let coverage = head_counts.coverage_pct();
+        let target = check.target.or_else(|| base_counts.coverage_pct());
+
+        let (state, reason) = match (coverage, target) {
+            (None, _) => (
+                CheckState::Success,
+                "no tracked lines to measure".to_string(),
+            ),
+            (Some(coverage), None) => (
+                CheckState::Success,
+                format!("{} coverage, no base to compare against", Pct(coverage)),
+            ),
+            (Some(coverage), Some(target)) => {
+                let state = if coverage >= target - check.threshold {
+                    CheckState::Success
+                } else {
+                    CheckState::Failure
+                };
+                let reason = match (state, check.target) {
+                    (CheckState::Success, Some(_)) if coverage >= target => {
+                        format!(
+                            "{} coverage reached the target of {}",
+                            Pct(coverage),
+                            Pct(target)
+                        )
+                    }
+                    (CheckState::Success, Some(_)) => {
+                        format!(
+                            "{} coverage is within {:.2} points of the target of {}",
+                            Pct(coverage),
+                            check.threshold,
+                            Pct(target)
+                        )
+                    }
+                    (CheckState::Failure, Some(_)) => {
+                        format!(
+                            "{} coverage is below the target of {}",
+                            Pct(coverage),
+                            Pct(target)
+                        )
+                    }
+                    (state, None) => {
+                        let change = coverage - target;
+                        let verb = match state {
+                            CheckState::Success => "is within",
+                            CheckState::Failure => "exceeds",
+                        };
+                        format!(
+                            "{} coverage ({}{:.2} points from base {}), {verb} the threshold of {:.2} points",
+                            Pct(coverage),
+                            if change >= 0.0 { "+" } else { "" },
+                            change,
+                            Pct(target),
+                            check.threshold,
+                        )
+                    }
+                };
+                (state, reason)
+            }
+        };
+
+        results.push(CheckResult {
+            name: check.name.clone(),
+            state,
+            coverage,
+            target,
+            reason,
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{from_samples, models, SampleSpec, SqliteReport};
+
+    /// Builds a report with `lines` of hits in each of `paths`.
+    fn report(temp_dir: &TempDir, name: &str, files: &[(&str, &[i64])]) -> SqliteReport {
+        let paths: Vec<_> = files.iter().map(|(path, _)| *path).collect();
+        let samples: Vec<_> = files
+            .iter()
+            .enumerate()
+            .flat_map(|(file, (_, hits))| {
+                (1..)
+                    .zip(hits.iter())
+                    .map(move |(line_no, hits)| SampleSpec::line(0, file, line_no, *hits))
+            })
+            .collect();
+        from_samples(
+            temp_dir.path().join(name),
+            &paths,
+            &[models::RawUpload::default()],
+            &samples,
+        )
+        .unwrap()
+    }
+
+    fn check(name: &str, scope: Scope, target: Option<f64>, threshold: f64) -> Check {
+        Check {
+            name: name.to_string(),
+            scope,
+            target,
+            threshold,
+        }
+    }
+
+    #[test]
+    fn test_evaluate_checks() {
+        let temp_dir = TempDir::new().unwrap();
+        // 75% overall, 100% in src/parsers
+        let base = report(
+            &temp_dir,
+            "base.sqlite",
+            &[
+                ("src/lib.rs", &[1, 1, 0, 0]),
+                ("src/parsers/a.rs", &[1, 1, 1, 1]),
+            ],
+        );
+        // Lines 5 and 6 of src/lib.rs were added, and src/parsers/a.rs lost
+        // a line of coverage: 70% overall, 75% in src/parsers
+        let head = report(
+            &temp_dir,
+            "head.sqlite",
+            &[
+                ("src/lib.rs", &[1, 1, 0, 0, 1, 1]),
+                ("src/parsers/a.rs", &[1, 1, 1, 0]),
+            ],
+        );
+        let diff = [FileDiff {
+            path: "src/lib.rs".to_string(),
+            base_path: Some("src/lib.rs".to_string()),
+            added_lines: BTreeSet::from([5, 6]),
+            ..Default::default()
+        }];
+        let parsers = Component {
+            component_id: "parsers".to_string(),
+            paths: vec!["src/parsers/**".to_string()],
+            ..Default::default()
+        };
+
+        let checks = [
+            check("project", Scope::Project, None, 0.0),
+            check("project-lenient", Scope::Project, None, 5.0),
+            check("project-target", Scope::Project, Some(70.0), 0.0),
+            check("patch", Scope::Patch, Some(90.0), 0.0),
+            check(
+                "parsers",
+                Scope::Component(parsers.clone()),
+                Some(80.0),
+                0.0,
+            ),
+            check("parsers-auto", Scope::Component(parsers), None, 30.0),
+        ];
+        let results = evaluate_checks(&base, &head, &diff, &checks).unwrap();
+
+        let states: Vec<_> = results
+            .iter()
+            .map(|result| (result.name.as_str(), result.state))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                ("project", CheckState::Failure),
+                ("project-lenient", CheckState::Success),
+                ("project-target", CheckState::Success),
+                ("patch", CheckState::Success),
+                ("parsers", CheckState::Failure),
+                ("parsers-auto", CheckState::Success),
+            ]
+        );
+
+        assert_eq!(results[0].coverage, Some(70.0));
+        assert_eq!(results[0].target, Some(75.0));
+        assert_eq!(
+            results[0].reason,
+            "70.00% coverage (-5.00 points from base 75.00%), exceeds the threshold of 0.00 points"
+        );
+        assert_eq!(
+            results[1].reason,
+            "70.00% coverage (-5.00 points from base 75.00%), is within the threshold of 5.00 points"
+        );
+        assert_eq!(
+            results[2].reason,
+            "70.00% coverage reached the target of 70.00%"
+        );
+        assert_eq!(results[3].coverage, Some(100.0));
+        assert_eq!(
+            results[4].reason,
+            "75.00% coverage is below the target of 80.00%"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_checks_threshold() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = report(&temp_dir, "base.sqlite", &[]);
+        let head = report(&temp_dir, "head.sqlite", &[("src/lib.rs", &[1, 1, 1, 0])]);
+        let checks = [check("project", Scope::Project, Some(80.0), 5.0)];
+
+        let results = evaluate_checks(&base, &head, &[], &checks).unwrap();
+        assert_eq!(results[0].state, CheckState::Success);
+        assert_eq!(
+            results[0].reason,
+            "75.00% coverage is within 5.00 points of the target of 80.00%"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_checks_without_data() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = report(&temp_dir, "base.sqlite", &[]);
+        let head = report(&temp_dir, "head.sqlite", &[("src/lib.rs", &[1, 0])]);
+        let checks = [
+            check("project", Scope::Project, None, 0.0),
+            check("patch", Scope::Patch, Some(100.0), 0.0),
+        ];
+
+        let results = evaluate_checks(&base, &head, &[], &checks).unwrap();
+        assert_eq!(
+            results,
+            [
+                CheckResult {
+                    name: "project".to_string(),
+                    state: CheckState::Success,
+                    coverage: Some(50.0),
+                    target: None,
+                    reason: "50.00% coverage, no base to compare against".to_string(),
+                },
+                CheckResult {
+                    name: "patch".to_string(),
+                    state: CheckState::Success,
+                    coverage: None,
+                    target: Some(100.0),
+                    reason: "no tracked lines to measure".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_checks() {
+        let checks: Vec<Check> = serde_json::from_str(
+            r#"[
+                {"name": "project", "scope": "project", "threshold": 1.5},
+                {"name": "parsers", "scope": {"component": {"component_id": "parsers", "paths": ["src/parsers/**"]}}, "target": 80}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(checks[0], check("project", Scope::Project, None, 1.5));
+        assert_eq!(checks[1].target, Some(80.0));
+        assert!(matches!(&checks[1].scope, Scope::Component(c) if c.matches("src/parsers/a.rs")));
+    }
+}
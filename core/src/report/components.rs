@@ -0,0 +1,217 @@
+//! Components: named groups of files in a report picked out by path globs,
+//! like the `component_management` section of a `codecov.yml`.
+//!
+//! Globs support `*` (anything but `/`), `?` (any one character but `/`) and
+//! `**` (anything, including `/`). `**/` may also match nothing, so
+//! `**/models.rs` matches `models.rs` at the root as well.
+
+use std::collections::BTreeMap;
+#[cfg(feature = "sqlite")]
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "sqlite")]
+use super::SqliteReport;
+use super::{models, Report};
+use crate::error::Result;
+
+/// A named group of files.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Component {
+    /// A unique, stable ID for the component.
+    pub component_id: String,
+
+    /// A display name for the component. Falls back to `component_id`.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Globs matching the paths of the files in the component.
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+/// The shape of `component_management` in a `codecov.yml`.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Deserialize)]
+pub struct ComponentManagement {
+    #[serde(default)]
+    pub individual_components: Vec<Component>,
+}
+
+/// Parses a list of components from the JSON form of a `codecov.yml`'s
+/// `component_management` section.
+pub fn parse_components(json: &str) -> Result<Vec<Component>> {
+    let component_management: ComponentManagement = serde_json::from_str(json)?;
+    Ok(component_management.individual_components)
+}
+
+impl Component {
+    /// The display name for the component.
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.component_id)
+    }
+
+    /// Whether `path` matches any of the component's globs.
+    pub fn matches(&self, path: &str) -> bool {
+        self.paths
+            .iter()
+            .any(|glob| glob_match(glob.as_bytes(), path.as_bytes()))
+    }
+
+    /// Lists the files in `report` that belong to the component.
+    pub fn list_files(&self, report: &impl Report) -> Result<Vec<models::SourceFile>> {
+        let mut files = report.list_files()?;
+        files.retain(|file| self.matches(&file.path));
+        Ok(files)
+    }
+
+    /// Sums the totals of every file in `report` that belongs to the
+    /// component.
+    pub fn totals(&self, report: &impl Report) -> Result<models::CoverageTotals> {
+        let mut totals = models::CoverageTotals::default();
+        for file in self.list_files(report)? {
+            totals += report.file_totals(&file)?;
+        }
+        Ok(totals)
+    }
+
+    /// Creates a new report at `filename` with only the component's files.
+    /// See [`SqliteReport::subset`].
+    #[cfg(feature = "sqlite")]
+    pub fn filtered_report(
+        &self,
+        report: &SqliteReport,
+        filename: PathBuf,
+    ) -> Result<SqliteReport> {
+        report.subset(filename, &self.list_files(report)?)
+    }
+}
+
+/// Computes [`Component::totals`] for each of `components`, keyed by
+/// `component_id`. Files may belong to any number of components.
+pub fn totals_by_component(
+    components: &[Component],
+    report: &impl Report,
+) -> Result<BTreeMap<String, models::CoverageTotals>> {
+    components
+        .iter()
+        .map(|component| Ok((component.component_id.clone(), component.totals(report)?)))
+        .collect()
+}
+
+pub(crate) fn glob_match(glob: &[u8], path: &[u8]) -> bool {
+    match glob {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob_match(rest, path)
+                || (0..path.len()).any(|i| path[i] == b'/' && glob_match(rest, &path[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        [b'*', rest @ ..] => {
+            let segment_len = path.iter().position(|c| *c == b'/').unwrap_or(path.len());
+            (0..=segment_len).any(|i| glob_match(rest, &path[i..]))
+        }
+        [b'?', rest @ ..] => {
+            matches!(path.first(), Some(c) if *c != b'/') && glob_match(rest, &path[1..])
+        }
+        [c, rest @ ..] => path.first() == Some(c) && glob_match(rest, &path[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "sqlite")]
+    use tempfile::TempDir;
+
+    use super::*;
+    #[cfg(feature = "sqlite")]
+    use crate::test_utils::sqlite_report::build_sample_report;
+
+    fn component(component_id: &str, paths: &[&str]) -> Component {
+        Component {
+            component_id: component_id.to_string(),
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        let cases = [
+            ("src/*.rs", "src/lib.rs", true),
+            ("src/*.rs", "src/report/models.rs", false),
+            ("src/**", "src/report/models.rs", true),
+            ("src/**/*.rs", "src/lib.rs", true),
+            ("src/**/*.rs", "src/report/sqlite/models.rs", true),
+            ("**/models.rs", "models.rs", true),
+            ("**/models.rs", "src/report/models.rs", true),
+            ("**/models.rs", "src/report/models.rs.bak", false),
+            ("src/?.rs", "src/a.rs", true),
+            ("src/?.rs", "src/ab.rs", false),
+            ("src/lib.rs", "src/lib.rs", true),
+            ("src/lib.rs", "src/lib.rsx", false),
+        ];
+        for (glob, path, expected) in cases {
+            assert_eq!(
+                glob_match(glob.as_bytes(), path.as_bytes()),
+                expected,
+                "{glob} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_components() {
+        let components = parse_components(
+            r#"{"individual_components": [
+                {"component_id": "models", "name": "Models", "paths": ["**/models.rs"]},
+                {"component_id": "everything"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            components,
+            [
+                Component {
+                    name: Some("Models".to_string()),
+                    ..component("models", &["**/models.rs"])
+                },
+                component("everything", &[]),
+            ]
+        );
+        assert_eq!(components[0].display_name(), "Models");
+        assert_eq!(components[1].display_name(), "everything");
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_component_totals_and_filtered_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let report = build_sample_report(temp_dir.path().join("db.sqlite")).unwrap();
+        let files = report.list_files().unwrap();
+        let models_file = files
+            .iter()
+            .find(|file| file.path == "src/report/models.rs")
+            .unwrap();
+
+        let components = [
+            component("models", &["**/models.rs"]),
+            component("report", &["src/report/*"]),
+            component("nothing", &["tests/**"]),
+        ];
+        let totals = totals_by_component(&components, &report).unwrap();
+        assert_eq!(totals["models"], report.file_totals(models_file).unwrap());
+        assert_eq!(totals["nothing"], models::CoverageTotals::default());
+        let report_totals = report.totals().unwrap().coverage;
+        assert_eq!(totals["report"], report_totals);
+
+        let filtered = components[0]
+            .filtered_report(&report, temp_dir.path().join("models.sqlite"))
+            .unwrap();
+        assert_eq!(
+            filtered.list_files().unwrap(),
+            std::slice::from_ref(models_file)
+        );
+        assert_eq!(filtered.file_totals(models_file).unwrap(), totals["models"]);
+    }
+}
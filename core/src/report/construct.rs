@@ -0,0 +1,211 @@
+//! Builds a report from plain structs in one call, for small tools and tests
+//! that would otherwise write a [`ReportBuilder`] loop and keep track of the
+//! IDs it hands out.
+//!
+//! ```
+//! # #[cfg(feature = "sqlite")]
+//! # fn main() {
+//! # use codecov_rs::report::{from_samples, models, Report, SampleSpec};
+//! # let temp_dir = tempfile::TempDir::new().unwrap();
+//! let report = from_samples(
+//!     temp_dir.path().join("report.sqlite"),
+//!     &["src/lib.rs"],
+//!     &[models::RawUpload::default()],
+//!     &[
+//!         SampleSpec::line(0, 0, 1, 1),
+//!         SampleSpec::line(0, 0, 2, 0),
+//!     ],
+//! )
+//! .unwrap();
+//! assert_eq!(report.totals().unwrap().coverage.hit_lines, 1);
+//! # }
+//! # #[cfg(not(feature = "sqlite"))]
+//! # fn main() {}
+//! ```
+
+#[cfg(feature = "sqlite")]
+use std::path::PathBuf;
+
+use super::{models, Report, ReportBuilder};
+#[cfg(feature = "sqlite")]
+use super::{SqliteReport, SqliteReportBuilder};
+use crate::error::{CodecovError, Result};
+
+/// A [`models::CoverageSample`] that refers to its file and upload by their
+/// positions in the slices passed to [`from_samples`] or [`insert_samples`]
+/// rather than by ID.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct SampleSpec {
+    /// The index of the sample's upload.
+    pub upload: usize,
+    /// The index of the sample's file.
+    pub file: usize,
+    pub line_no: i64,
+    pub coverage_type: models::CoverageType,
+    pub hits: Option<i64>,
+    pub hit_branches: Option<i64>,
+    pub total_branches: Option<i64>,
+}
+
+impl SampleSpec {
+    /// A [`models::CoverageType::Line`] sample hit `hits` times.
+    pub fn line(upload: usize, file: usize, line_no: i64, hits: i64) -> SampleSpec {
+        SampleSpec {
+            upload,
+            file,
+            line_no,
+            coverage_type: models::CoverageType::Line,
+            hits: Some(hits),
+            ..Default::default()
+        }
+    }
+
+    /// A [`models::CoverageType::Branch`] sample with `hit_branches` of
+    /// `total_branches` branches hit.
+    pub fn branch(
+        upload: usize,
+        file: usize,
+        line_no: i64,
+        hit_branches: i64,
+        total_branches: i64,
+    ) -> SampleSpec {
+        SampleSpec {
+            upload,
+            file,
+            line_no,
+            coverage_type: models::CoverageType::Branch,
+            hit_branches: Some(hit_branches),
+            total_branches: Some(total_branches),
+            ..Default::default()
+        }
+    }
+}
+
+/// Inserts `files`, `uploads` and `samples` with `builder` and returns the
+/// inserted samples, whose IDs were assigned by `builder`. The IDs of
+/// `uploads` are ignored. Fails without inserting anything if a sample refers
+/// to a file or upload that isn't there.
+pub fn insert_samples<R: Report, B: ReportBuilder<R>>(
+    builder: &mut B,
+    files: &[&str],
+    uploads: &[models::RawUpload],
+    samples: &[SampleSpec],
+) -> Result<Vec<models::CoverageSample>> {
+    if let Some((i, sample)) = samples
+        .iter()
+        .enumerate()
+        .find(|(_, sample)| sample.file >= files.len() || sample.upload >= uploads.len())
+    {
+        return Err(CodecovError::ReportBuilderError(format!(
+            "sample {i} refers to file {} and upload {}, but there are {} files and {} uploads",
+            sample.file,
+            sample.upload,
+            files.len(),
+            uploads.len()
+        )));
+    }
+
+    let files = files
+        .iter()
+        .map(|path| builder.insert_file(path))
+        .collect::<Result<Vec<_>>>()?;
+    let uploads = uploads
+        .iter()
+        .map(|upload| builder.insert_raw_upload(upload.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut samples: Vec<_> = samples
+        .iter()
+        .map(|sample| models::CoverageSample {
+            raw_upload_id: uploads[sample.upload].id,
+            source_file_id: files[sample.file].id,
+            line_no: sample.line_no,
+            coverage_type: sample.coverage_type,
+            hits: sample.hits,
+            hit_branches: sample.hit_branches,
+            total_branches: sample.total_branches,
+            ..Default::default()
+        })
+        .collect();
+    builder.multi_insert_coverage_sample(samples.iter_mut().collect())?;
+    Ok(samples)
+}
+
+/// Creates a [`SqliteReport`] at `filename` with `files`, `uploads` and
+/// `samples` in one transaction. See [`insert_samples`].
+#[cfg(feature = "sqlite")]
+pub fn from_samples(
+    filename: PathBuf,
+    files: &[&str],
+    uploads: &[models::RawUpload],
+    samples: &[SampleSpec],
+) -> Result<SqliteReport> {
+    let mut builder = SqliteReportBuilder::open(filename)?;
+    {
+        let mut tx = builder.transaction()?;
+        let _ = insert_samples(&mut tx, files, uploads, samples)?;
+    }
+    builder.build()
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_from_samples() {
+        let temp_dir = TempDir::new().unwrap();
+        let uploads = [
+            models::RawUpload {
+                name: Some("unit".to_string()),
+                ..Default::default()
+            },
+            models::RawUpload {
+                name: Some("integration".to_string()),
+                ..Default::default()
+            },
+        ];
+        let report = from_samples(
+            temp_dir.path().join("db.sqlite"),
+            &["src/a.rs", "src/b.rs"],
+            &uploads,
+            &[
+                SampleSpec::line(0, 0, 1, 1),
+                SampleSpec::line(1, 0, 1, 0),
+                SampleSpec::branch(1, 1, 4, 1, 2),
+            ],
+        )
+        .unwrap();
+
+        let files = report.list_files().unwrap();
+        assert_eq!(files.len(), 2);
+        let samples = report.samples_for_line(&files[0], 1).unwrap();
+        let names: Vec<_> = samples
+            .iter()
+            .map(|(sample, upload)| (sample.hits, upload.name.as_deref()))
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&(Some(1), Some("unit"))));
+        assert!(names.contains(&(Some(0), Some("integration"))));
+
+        let totals = report.totals().unwrap();
+        assert_eq!(totals.uploads, 2);
+        assert_eq!(totals.coverage.total_lines, 2);
+        assert_eq!(totals.coverage.hit_branches, 1);
+        assert_eq!(totals.coverage.total_branches, 2);
+    }
+
+    #[test]
+    fn test_from_samples_bad_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let result = from_samples(
+            temp_dir.path().join("db.sqlite"),
+            &["src/a.rs"],
+            &[models::RawUpload::default()],
+            &[SampleSpec::line(0, 1, 1, 1)],
+        );
+        assert!(matches!(result, Err(CodecovError::ReportBuilderError(_))));
+    }
+}
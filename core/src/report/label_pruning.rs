@@ -0,0 +1,84 @@
+//! Keeping the labels attached to each line in check. Test analytics reports
+//! can attach hundreds of labels to a line that every test runs, and for a
+//! big enough test suite they make up most of the report.
+//!
+//! [`LabelPruning`] can be applied while parsing, with
+//! [`ParseOptions::label_pruning`](crate::parsers::pyreport::ParseOptions::label_pruning),
+//! or to a report that's already been built, with
+//! [`SqliteReport::prune_labels`](crate::report::SqliteReport::prune_labels).
+
+use super::{line_coverage::LineStatus, models};
+
+/// Which labels to leave off of each
+/// [`CoverageSample`](models::CoverageSample). Labels attached to whole uploads
+/// or files, like flags, are never pruned. The default keeps every label.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct LabelPruning {
+    /// The most labels to keep on any one sample. Which ones are kept depends
+    /// on where the pruning is applied.
+    pub max_labels_per_sample: Option<usize>,
+
+    /// Whether to drop every label on samples that are fully covered, i.e.
+    /// [`LineStatus::Hit`]. Those are the lines that tend to be run by every
+    /// test, so their labels are the least useful for picking tests to run.
+    pub drop_fully_covered: bool,
+}
+
+impl LabelPruning {
+    /// Whether any labels are pruned at all.
+    pub fn is_enabled(&self) -> bool {
+        self.max_labels_per_sample.is_some() || self.drop_fully_covered
+    }
+
+    /// How many of the `labels` attached to `sample` to keep.
+    pub fn labels_to_keep(&self, sample: &models::CoverageSample, labels: usize) -> usize {
+        if self.drop_fully_covered && LineStatus::of_sample(sample) == LineStatus::Hit {
+            return 0;
+        }
+        self.max_labels_per_sample
+            .map_or(labels, |max| labels.min(max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels_to_keep() {
+        let hit = models::CoverageSample {
+            hits: Some(3),
+            ..Default::default()
+        };
+        let partial = models::CoverageSample {
+            hit_branches: Some(1),
+            total_branches: Some(2),
+            ..Default::default()
+        };
+        let all_branches = models::CoverageSample {
+            hit_branches: Some(2),
+            total_branches: Some(2),
+            ..Default::default()
+        };
+
+        let pruning = LabelPruning::default();
+        assert!(!pruning.is_enabled());
+        assert_eq!(pruning.labels_to_keep(&hit, 500), 500);
+
+        let pruning = LabelPruning {
+            max_labels_per_sample: Some(10),
+            drop_fully_covered: false,
+        };
+        assert!(pruning.is_enabled());
+        assert_eq!(pruning.labels_to_keep(&hit, 500), 10);
+        assert_eq!(pruning.labels_to_keep(&partial, 3), 3);
+
+        let pruning = LabelPruning {
+            max_labels_per_sample: Some(10),
+            drop_fully_covered: true,
+        };
+        assert_eq!(pruning.labels_to_keep(&hit, 500), 0);
+        assert_eq!(pruning.labels_to_keep(&all_branches, 5), 0);
+        assert_eq!(pruning.labels_to_keep(&partial, 500), 10);
+    }
+}
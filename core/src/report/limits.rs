@@ -0,0 +1,430 @@
+//! Guardrails against pathological uploads. Wrap a [`ReportBuilder`] in a
+//! [`LimitedReportBuilder`] before handing it to a parser and the parser will
+//! fail with [`CodecovError::LimitExceeded`] as soon as the input goes over
+//! one of its [`Limits`], instead of after filling up the report.
+
+use std::{collections::HashSet, fmt};
+
+use super::{models, DuplicateUploadPolicy, Report, ReportBuilder};
+use crate::error::{CodecovError, Result};
+
+/// Which of the [`Limits`] an input went over.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Limit {
+    Files,
+    LinesPerFile,
+    Labels,
+    Sessions,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Files => "files",
+            Limit::LinesPerFile => "lines per file",
+            Limit::Labels => "labels",
+            Limit::Sessions => "sessions",
+        })
+    }
+}
+
+/// The most of each thing a report may contain. `None` means unlimited, which
+/// is the default.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// The most distinct files.
+    pub max_files: Option<u64>,
+
+    /// The highest line number any sample or span may have. A broken upload
+    /// can claim a hit on line 4 billion, and formats with a row per line,
+    /// like pyreport chunks, would then be padded out to that length.
+    pub max_lines_per_file: Option<u64>,
+
+    /// The most distinct labels, i.e. [`Context`](models::Context)s.
+    pub max_labels: Option<u64>,
+
+    /// The most sessions, i.e. [`RawUpload`](models::RawUpload)s.
+    pub max_sessions: Option<u64>,
+}
+
+/// Fails with [`CodecovError::LimitExceeded`] if `found` is over `max`.
+fn check(limit: Limit, max: Option<u64>, found: u64) -> Result<()> {
+    match max {
+        Some(max) if found > max => Err(CodecovError::LimitExceeded { limit, max, found }),
+        _ => Ok(()),
+    }
+}
+
+/// Forwards every call to `B` after checking it against [`Limits`].
+///
+/// Files and labels are counted by ID, so inserting the same one twice only
+/// counts once. Sessions are checked before they're inserted. Files and labels
+/// are inserted first and then checked, so `B` ends up with one more than the
+/// limit when it's exceeded; the caller is expected to throw the report away
+/// either way.
+pub struct LimitedReportBuilder<B> {
+    inner: B,
+    limits: Limits,
+
+    files: HashSet<i64>,
+    labels: HashSet<i64>,
+    sessions: u64,
+}
+
+impl<B> LimitedReportBuilder<B> {
+    pub fn new(inner: B, limits: Limits) -> Self {
+        LimitedReportBuilder {
+            inner,
+            limits,
+            files: HashSet::new(),
+            labels: HashSet::new(),
+            sessions: 0,
+        }
+    }
+
+    /// Returns the wrapped builder, e.g. to build it without going through
+    /// [`ReportBuilder::build`].
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    fn check_file(&mut self, file: &models::SourceFile) -> Result<()> {
+        self.files.insert(file.id);
+        check(Limit::Files, self.limits.max_files, self.files.len() as u64)
+    }
+
+    fn check_label(&mut self, context: &models::Context) -> Result<()> {
+        self.labels.insert(context.id);
+        check(
+            Limit::Labels,
+            self.limits.max_labels,
+            self.labels.len() as u64,
+        )
+    }
+
+    fn check_line(&self, line_no: i64) -> Result<()> {
+        check(
+            Limit::LinesPerFile,
+            self.limits.max_lines_per_file,
+            line_no.max(0) as u64,
+        )
+    }
+
+    fn check_span(&self, span: &models::SpanData) -> Result<()> {
+        for line_no in [span.start_line, span.end_line].into_iter().flatten() {
+            self.check_line(line_no)?;
+        }
+        Ok(())
+    }
+
+    fn check_new_session(&self) -> Result<()> {
+        check(Limit::Sessions, self.limits.max_sessions, self.sessions + 1)
+    }
+}
+
+impl<R: Report, B: ReportBuilder<R>> ReportBuilder<R> for LimitedReportBuilder<B> {
+    fn insert_file(&mut self, path: &str) -> Result<models::SourceFile> {
+        let file = self.inner.insert_file(path)?;
+        self.check_file(&file)?;
+        Ok(file)
+    }
+
+    fn update_file_metadata(&mut self, file: &models::SourceFile) -> Result<()> {
+        self.inner.update_file_metadata(file)
+    }
+
+    fn insert_context(&mut self, name: &str) -> Result<models::Context> {
+        let context = self.inner.insert_context(name)?;
+        self.check_label(&context)?;
+        Ok(context)
+    }
+
+    fn multi_insert_context(&mut self, names: &[&str]) -> Result<Vec<models::Context>> {
+        let contexts = self.inner.multi_insert_context(names)?;
+        for context in &contexts {
+            self.check_label(context)?;
+        }
+        Ok(contexts)
+    }
+
+    fn insert_coverage_sample(
+        &mut self,
+        sample: models::CoverageSample,
+    ) -> Result<models::CoverageSample> {
+        self.check_line(sample.line_no)?;
+        self.inner.insert_coverage_sample(sample)
+    }
+
+    fn multi_insert_coverage_sample(
+        &mut self,
+        samples: Vec<&mut models::CoverageSample>,
+    ) -> Result<()> {
+        for sample in &samples {
+            self.check_line(sample.line_no)?;
+        }
+        self.inner.multi_insert_coverage_sample(samples)
+    }
+
+    fn multi_update_coverage_sample(&mut self, samples: &[models::CoverageSample]) -> Result<()> {
+        self.inner.multi_update_coverage_sample(samples)
+    }
+
+    fn insert_branches_data(
+        &mut self,
+        branch: models::BranchesData,
+    ) -> Result<models::BranchesData> {
+        self.inner.insert_branches_data(branch)
+    }
+
+    fn multi_insert_branches_data(
+        &mut self,
+        branches: Vec<&mut models::BranchesData>,
+    ) -> Result<()> {
+        self.inner.multi_insert_branches_data(branches)
+    }
+
+    fn insert_method_data(&mut self, method: models::MethodData) -> Result<models::MethodData> {
+        self.inner.insert_method_data(method)
+    }
+
+    fn multi_insert_method_data(&mut self, methods: Vec<&mut models::MethodData>) -> Result<()> {
+        self.inner.multi_insert_method_data(methods)
+    }
+
+    fn insert_span_data(&mut self, span: models::SpanData) -> Result<models::SpanData> {
+        self.check_span(&span)?;
+        self.inner.insert_span_data(span)
+    }
+
+    fn multi_insert_span_data(&mut self, spans: Vec<&mut models::SpanData>) -> Result<()> {
+        for span in &spans {
+            self.check_span(span)?;
+        }
+        self.inner.multi_insert_span_data(spans)
+    }
+
+    fn associate_context(&mut self, assoc: models::ContextAssoc) -> Result<models::ContextAssoc> {
+        self.inner.associate_context(assoc)
+    }
+
+    fn multi_associate_context(&mut self, assocs: Vec<&mut models::ContextAssoc>) -> Result<()> {
+        self.inner.multi_associate_context(assocs)
+    }
+
+    fn insert_upload_tag(&mut self, tag: models::UploadTag) -> Result<models::UploadTag> {
+        self.inner.insert_upload_tag(tag)
+    }
+
+    fn multi_insert_session_file_totals(
+        &mut self,
+        totals: &[models::SessionFileTotals],
+    ) -> Result<()> {
+        self.inner.multi_insert_session_file_totals(totals)
+    }
+
+    fn multi_insert_line_attributes(&mut self, attributes: &[models::LineAttribute]) -> Result<()> {
+        self.inner.multi_insert_line_attributes(attributes)
+    }
+
+    fn insert_raw_upload(
+        &mut self,
+        upload_details: models::RawUpload,
+    ) -> Result<models::RawUpload> {
+        self.check_new_session()?;
+        let upload = self.inner.insert_raw_upload(upload_details)?;
+        self.sessions += 1;
+        Ok(upload)
+    }
+
+    fn update_raw_upload_url(
+        &mut self,
+        raw_upload_id: i64,
+        raw_upload_url: Option<&str>,
+    ) -> Result<()> {
+        self.inner
+            .update_raw_upload_url(raw_upload_id, raw_upload_url)
+    }
+
+    fn update_upload_state(
+        &mut self,
+        raw_upload_id: i64,
+        state: models::UploadState,
+    ) -> Result<()> {
+        self.inner.update_upload_state(raw_upload_id, state)
+    }
+
+    fn insert_raw_upload_idempotent(
+        &mut self,
+        upload_details: models::RawUpload,
+        on_duplicate: DuplicateUploadPolicy,
+    ) -> Result<Option<models::RawUpload>> {
+        // Only new uploads count, and whether this one is new isn't known
+        // until it's been inserted
+        let upload = self
+            .inner
+            .insert_raw_upload_idempotent(upload_details, on_duplicate)?;
+        if upload.is_some() {
+            self.check_new_session()?;
+            self.sessions += 1;
+        }
+        Ok(upload)
+    }
+
+    fn supersede_upload(&mut self, raw_upload_id: i64) -> Result<i64> {
+        self.inner.supersede_upload(raw_upload_id)
+    }
+
+    fn savepoint(&mut self) -> Result<()> {
+        self.inner.savepoint()
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        self.inner.release_savepoint()
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        self.inner.rollback_to_savepoint()
+    }
+
+    fn build(self) -> Result<R> {
+        self.inner.build()
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::SqliteReportBuilder;
+
+    fn setup(temp_dir: &TempDir, limits: Limits) -> LimitedReportBuilder<SqliteReportBuilder> {
+        let builder = SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+        LimitedReportBuilder::new(builder, limits)
+    }
+
+    #[track_caller]
+    fn assert_exceeded<T: fmt::Debug>(result: Result<T>, limit: Limit, max: u64, found: u64) {
+        match result {
+            Err(CodecovError::LimitExceeded {
+                limit: actual_limit,
+                max: actual_max,
+                found: actual_found,
+            }) => assert_eq!(
+                (actual_limit, actual_max, actual_found),
+                (limit, max, found)
+            ),
+            other => panic!("expected {limit} limit to be exceeded, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut builder = setup(&temp_dir, Limits::default());
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let file = builder.insert_file("src/lib.rs").unwrap();
+        builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no: i64::MAX,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_max_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let limits = Limits {
+            max_files: Some(2),
+            ..Default::default()
+        };
+        let mut builder = setup(&temp_dir, limits);
+        builder.insert_file("src/a.rs").unwrap();
+        builder.insert_file("src/b.rs").unwrap();
+        assert_exceeded(builder.insert_file("src/c.rs"), Limit::Files, 2, 3);
+    }
+
+    #[test]
+    fn test_max_lines_per_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let limits = Limits {
+            max_lines_per_file: Some(100),
+            ..Default::default()
+        };
+        let mut builder = setup(&temp_dir, limits);
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let file = builder.insert_file("src/lib.rs").unwrap();
+        let sample = |line_no| models::CoverageSample {
+            raw_upload_id: upload.id,
+            source_file_id: file.id,
+            line_no,
+            ..Default::default()
+        };
+        builder.insert_coverage_sample(sample(100)).unwrap();
+
+        let mut samples = [sample(1), sample(u32::MAX as i64)];
+        assert_exceeded(
+            builder.multi_insert_coverage_sample(samples.iter_mut().collect()),
+            Limit::LinesPerFile,
+            100,
+            u32::MAX as u64,
+        );
+        assert_exceeded(
+            builder.insert_span_data(models::SpanData {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                start_line: Some(1),
+                end_line: Some(101),
+                ..Default::default()
+            }),
+            Limit::LinesPerFile,
+            100,
+            101,
+        );
+
+        // Nothing from the rejected batch made it in
+        let report = builder.build().unwrap();
+        assert_eq!(report.list_coverage_samples().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_max_labels() {
+        let temp_dir = TempDir::new().unwrap();
+        let limits = Limits {
+            max_labels: Some(2),
+            ..Default::default()
+        };
+        let mut builder = setup(&temp_dir, limits);
+        builder.insert_context("test_a").unwrap();
+        assert_exceeded(
+            builder.multi_insert_context(&["test_b", "test_c"]),
+            Limit::Labels,
+            2,
+            3,
+        );
+    }
+
+    #[test]
+    fn test_max_sessions() {
+        let temp_dir = TempDir::new().unwrap();
+        let limits = Limits {
+            max_sessions: Some(1),
+            ..Default::default()
+        };
+        let mut builder = setup(&temp_dir, limits);
+        builder.insert_raw_upload(Default::default()).unwrap();
+        assert_exceeded(
+            builder.insert_raw_upload(Default::default()),
+            Limit::Sessions,
+            1,
+            2,
+        );
+
+        // The rejected session wasn't inserted
+        let report = builder.build().unwrap();
+        assert_eq!(report.list_raw_uploads().unwrap().len(), 1);
+    }
+}
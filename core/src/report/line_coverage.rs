@@ -0,0 +1,502 @@
+//! A compact summary of which lines of a file are covered, for services that
+//! need to compare coverage between commits or uploads without exchanging
+//! whole reports.
+//!
+//! [`LineCoverage`] holds one [`LineStatus`] per line and can be written in
+//! two forms:
+//! - Run-length encoded text, e.g. `"2u3hm"`. Each run is a count followed by
+//!   `u` (untracked), `m` (miss), `p` (partial) or `h` (hit), and the count is
+//!   left out when it's 1. `"2u3hm"` says lines 1-2 aren't tracked, lines 3-5
+//!   were hit and line 6 was missed.
+//! - A bitmap with 2 bits per line, 4 lines per byte, starting from the low
+//!   bits of the first byte. Untracked is `0`, miss `1`, partial `2` and hit
+//!   `3`.
+//!
+//! Neither form records lines after the last tracked one, so a file's
+//! coverage always round-trips but its length may not.
+//!
+//! Editors and overlays that highlight lines can ask for
+//! [`CoverageRange`]s instead, which merge consecutive lines with the same
+//! status and leave out untracked ones.
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use winnow::{
+    ascii::dec_uint,
+    combinator::{alt, eof, opt, repeat, terminated},
+    error::{AddContext, ContextError, StrContext},
+    PResult, Parser,
+};
+
+use super::{models, Report};
+use crate::error::{CodecovError, Result};
+
+/// More lines than any real source file has. Parsing stops before expanding
+/// runs past this so a short string can't allocate gigabytes.
+const MAX_LINES: usize = 1 << 24;
+
+/// The coverage of a single line across every sample recorded for it. Ordered
+/// from least to most covered.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default, Hash)]
+pub enum LineStatus {
+    /// No sample recorded any coverage data for the line.
+    #[default]
+    Untracked,
+    Miss,
+    Partial,
+    Hit,
+}
+
+impl LineStatus {
+    /// The status of a single sample, with the same `NULL` semantics as
+    /// `summary.sql`. A branch sample counts as hit when all of its branches
+    /// were, and a sample with no coverage data is untracked.
+    pub fn of_sample(sample: &models::CoverageSample) -> LineStatus {
+        let branches = sample.hit_branches.zip(sample.total_branches);
+        if sample.hits.is_some_and(|hits| hits > 0)
+            || branches.is_some_and(|(hit, total)| hit >= total)
+        {
+            LineStatus::Hit
+        } else if sample.hits == Some(0) || sample.hit_branches == Some(0) {
+            LineStatus::Miss
+        } else if branches.is_some_and(|(hit, total)| hit > 0 && hit < total) {
+            LineStatus::Partial
+        } else {
+            LineStatus::Untracked
+        }
+    }
+
+    fn code(self) -> char {
+        match self {
+            LineStatus::Untracked => 'u',
+            LineStatus::Miss => 'm',
+            LineStatus::Partial => 'p',
+            LineStatus::Hit => 'h',
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            LineStatus::Untracked => 0,
+            LineStatus::Miss => 1,
+            LineStatus::Partial => 2,
+            LineStatus::Hit => 3,
+        }
+    }
+
+    fn from_bits(bits: u8) -> LineStatus {
+        match bits & 0b11 {
+            0 => LineStatus::Untracked,
+            1 => LineStatus::Miss,
+            2 => LineStatus::Partial,
+            _ => LineStatus::Hit,
+        }
+    }
+}
+
+/// A run of consecutive lines with the same status. See
+/// [`LineCoverage::ranges`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct CoverageRange {
+    /// The first line of the run, counting from 1.
+    pub start_line: i64,
+    /// The last line of the run, inclusive.
+    pub end_line: i64,
+    pub status: LineStatus,
+}
+
+/// The [`LineStatus`] of each line in a file, up to the last tracked one.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct LineCoverage {
+    statuses: Vec<LineStatus>,
+}
+
+impl LineCoverage {
+    /// Builds the coverage of a file from its samples. Each line gets the
+    /// most covered status of any of its samples, the same way
+    /// [`Report::summary`] counts lines.
+    pub fn from_samples<'a>(
+        samples: impl IntoIterator<Item = &'a models::CoverageSample>,
+    ) -> LineCoverage {
+        let mut statuses = vec![];
+        for sample in samples {
+            let Ok(index) = usize::try_from(sample.line_no - 1) else {
+                continue;
+            };
+            let status = LineStatus::of_sample(sample);
+            if status == LineStatus::Untracked {
+                continue;
+            }
+            if index >= statuses.len() {
+                statuses.resize(index + 1, LineStatus::Untracked);
+            }
+            statuses[index] = statuses[index].max(status);
+        }
+        LineCoverage { statuses }
+    }
+
+    /// Builds coverage from each line's status, starting with line 1.
+    pub fn from_statuses(statuses: impl IntoIterator<Item = LineStatus>) -> LineCoverage {
+        let mut coverage = LineCoverage {
+            statuses: statuses.into_iter().collect(),
+        };
+        coverage.trim();
+        coverage
+    }
+
+    /// The status of line `line_no`, counting from 1.
+    pub fn status(&self, line_no: i64) -> LineStatus {
+        usize::try_from(line_no - 1)
+            .ok()
+            .and_then(|index| self.statuses.get(index))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Each line's status, starting with line 1.
+    pub fn statuses(&self) -> &[LineStatus] {
+        &self.statuses
+    }
+
+    /// The number of the last tracked line, or 0 if there aren't any.
+    pub fn len(&self) -> usize {
+        self.statuses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statuses.is_empty()
+    }
+
+    /// Merges consecutive lines with the same status into ranges, ordered by
+    /// line. Untracked lines aren't included, so a blank line between two hit
+    /// ones splits them into two ranges.
+    pub fn ranges(&self) -> Vec<CoverageRange> {
+        let mut ranges = vec![];
+        let mut start_line = 1;
+        for run in self.statuses.chunk_by(|a, b| a == b) {
+            let end_line = start_line + run.len() as i64 - 1;
+            if run[0] != LineStatus::Untracked {
+                ranges.push(CoverageRange {
+                    start_line,
+                    end_line,
+                    status: run[0],
+                });
+            }
+            start_line = end_line + 1;
+        }
+        ranges
+    }
+
+    /// Writes the coverage as run-length encoded text. Same as `to_string()`.
+    pub fn to_rle(&self) -> String {
+        self.to_string()
+    }
+
+    /// Parses run-length encoded text written by [`LineCoverage::to_rle`].
+    pub fn from_rle(input: &str) -> Result<LineCoverage> {
+        let mut remaining = input;
+        let runs: Vec<_> = terminated(repeat(0.., run), eof)
+            .parse_next(&mut remaining)
+            .map_err(|e| {
+                CodecovError::parser_error(input, remaining, e.into_inner().unwrap_or_default())
+            })?;
+
+        let mut statuses = vec![];
+        for (count, status) in runs {
+            if statuses.len() + count > MAX_LINES {
+                return Err(CodecovError::parser_error(
+                    input,
+                    "",
+                    ContextError::new().add_context(&input, StrContext::Label("line count")),
+                ));
+            }
+            statuses.resize(statuses.len() + count, status);
+        }
+        Ok(LineCoverage::from_statuses(statuses))
+    }
+
+    /// Packs the coverage into a bitmap.
+    pub fn to_bitmap(&self) -> Vec<u8> {
+        self.statuses
+            .chunks(4)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |byte, (i, status)| byte | status.bits() << (i * 2))
+            })
+            .collect()
+    }
+
+    /// Unpacks a bitmap written by [`LineCoverage::to_bitmap`].
+    pub fn from_bitmap(bitmap: &[u8]) -> LineCoverage {
+        LineCoverage::from_statuses(
+            bitmap
+                .iter()
+                .flat_map(|byte| (0..4).map(move |i| LineStatus::from_bits(byte >> (i * 2)))),
+        )
+    }
+
+    /// Drops untracked lines from the end.
+    fn trim(&mut self) {
+        let len = self
+            .statuses
+            .iter()
+            .rposition(|status| *status != LineStatus::Untracked)
+            .map_or(0, |i| i + 1);
+        self.statuses.truncate(len);
+    }
+}
+
+impl fmt::Display for LineCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for run in self.statuses.chunk_by(|a, b| a == b) {
+            if run.len() > 1 {
+                write!(f, "{}", run.len())?;
+            }
+            write!(f, "{}", run[0].code())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for LineCoverage {
+    type Err = CodecovError;
+
+    fn from_str(s: &str) -> Result<LineCoverage> {
+        LineCoverage::from_rle(s)
+    }
+}
+
+/// Parses a single run, e.g. `3h` or `m`.
+fn run(buf: &mut &str) -> PResult<(usize, LineStatus)> {
+    (
+        opt(dec_uint::<_, u32, _>),
+        alt((
+            'u'.value(LineStatus::Untracked),
+            'm'.value(LineStatus::Miss),
+            'p'.value(LineStatus::Partial),
+            'h'.value(LineStatus::Hit),
+        )),
+    )
+        .map(|(count, status)| (count.map_or(1, |count| count as usize), status))
+        .context(StrContext::Label("run"))
+        .parse_next(buf)
+}
+
+/// Builds the [`LineCoverage`] of `file` from every upload in `report`.
+pub fn line_coverage<R: Report>(report: &R, file: &models::SourceFile) -> Result<LineCoverage> {
+    Ok(LineCoverage::from_samples(
+        &report.list_samples_for_file(file)?,
+    ))
+}
+
+/// Lists the [`CoverageRange`]s of `file` from every upload in `report`.
+pub fn coverage_ranges_for_file<R: Report>(
+    report: &R,
+    file: &models::SourceFile,
+) -> Result<Vec<CoverageRange>> {
+    Ok(line_coverage(report, file)?.ranges())
+}
+
+/// Builds the [`LineCoverage`] of every file in `report`, keyed by path.
+pub fn line_coverage_by_path<R: Report>(report: &R) -> Result<BTreeMap<String, LineCoverage>> {
+    report
+        .list_files()?
+        .into_iter()
+        .map(|file| Ok((file.path.clone(), line_coverage(report, &file)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(
+        line_no: i64,
+        hits: Option<i64>,
+        branches: Option<(i64, i64)>,
+    ) -> models::CoverageSample {
+        models::CoverageSample {
+            line_no,
+            hits,
+            hit_branches: branches.map(|(hit, _)| hit),
+            total_branches: branches.map(|(_, total)| total),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_of_sample() {
+        assert_eq!(
+            LineStatus::of_sample(&sample(1, Some(3), None)),
+            LineStatus::Hit
+        );
+        assert_eq!(
+            LineStatus::of_sample(&sample(1, Some(0), None)),
+            LineStatus::Miss
+        );
+        assert_eq!(
+            LineStatus::of_sample(&sample(1, None, Some((2, 2)))),
+            LineStatus::Hit
+        );
+        assert_eq!(
+            LineStatus::of_sample(&sample(1, None, Some((1, 2)))),
+            LineStatus::Partial
+        );
+        assert_eq!(
+            LineStatus::of_sample(&sample(1, None, Some((0, 2)))),
+            LineStatus::Miss
+        );
+        assert_eq!(
+            LineStatus::of_sample(&sample(1, None, None)),
+            LineStatus::Untracked
+        );
+    }
+
+    #[test]
+    fn test_from_samples() {
+        let coverage = LineCoverage::from_samples(&[
+            sample(3, Some(1), None),
+            sample(4, Some(0), None),
+            // The most covered sample for a line wins
+            sample(5, Some(0), None),
+            sample(5, None, Some((1, 2))),
+            sample(6, None, Some((1, 2))),
+            sample(6, Some(2), None),
+            // Samples with no coverage data don't extend the file
+            sample(9, None, None),
+            sample(0, Some(1), None),
+        ]);
+        assert_eq!(
+            coverage.statuses(),
+            &[
+                LineStatus::Untracked,
+                LineStatus::Untracked,
+                LineStatus::Hit,
+                LineStatus::Miss,
+                LineStatus::Partial,
+                LineStatus::Hit,
+            ]
+        );
+        assert_eq!(coverage.status(5), LineStatus::Partial);
+        assert_eq!(coverage.status(9), LineStatus::Untracked);
+        assert_eq!(coverage.status(0), LineStatus::Untracked);
+        assert_eq!(coverage.to_rle(), "2uhmph");
+    }
+
+    #[test]
+    fn test_rle() {
+        let coverage = LineCoverage::from_statuses(
+            [LineStatus::Untracked; 2]
+                .into_iter()
+                .chain([LineStatus::Hit; 3])
+                .chain([LineStatus::Miss])
+                .chain([LineStatus::Partial; 12]),
+        );
+        assert_eq!(coverage.to_rle(), "2u3hm12p");
+        assert_eq!(LineCoverage::from_rle("2u3hm12p").unwrap(), coverage);
+        assert_eq!("2u3hm12p".parse::<LineCoverage>().unwrap(), coverage);
+
+        // Runs don't have to be merged, and trailing untracked lines are
+        // dropped
+        assert_eq!(LineCoverage::from_rle("uu1h2h0mm12p4u").unwrap(), coverage);
+
+        assert_eq!(LineCoverage::default().to_rle(), "");
+        assert_eq!(LineCoverage::from_rle("").unwrap(), LineCoverage::default());
+        assert_eq!(
+            LineCoverage::from_rle("5u").unwrap(),
+            LineCoverage::default()
+        );
+    }
+
+    #[test]
+    fn test_rle_errors() {
+        for (input, column) in [("2u3x", 3), ("h3", 2), (" h", 1), ("-1h", 1)] {
+            match LineCoverage::from_rle(input) {
+                Err(CodecovError::ParserError { column: c, .. }) => {
+                    assert_eq!(c, column, "{input}")
+                }
+                other => panic!("{input}: {other:?}"),
+            }
+        }
+        assert!(matches!(
+            LineCoverage::from_rle(&format!("{MAX_LINES}uh")),
+            Err(CodecovError::ParserError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_bitmap() {
+        let coverage = LineCoverage::from_rle("2uhmph").unwrap();
+        // Lines 1-4 in the first byte and 5-6 in the second, low bits first
+        assert_eq!(coverage.to_bitmap(), &[0b0111_0000, 0b0000_1110]);
+        assert_eq!(LineCoverage::from_bitmap(&coverage.to_bitmap()), coverage);
+
+        assert!(LineCoverage::default().to_bitmap().is_empty());
+        assert_eq!(LineCoverage::from_bitmap(&[0, 0b11, 0]).to_rle(), "4uh");
+    }
+
+    #[test]
+    fn test_ranges() {
+        let coverage = LineCoverage::from_rle("2u3hm2p2uh").unwrap();
+        let range = |start_line, end_line, status| CoverageRange {
+            start_line,
+            end_line,
+            status,
+        };
+        assert_eq!(
+            coverage.ranges(),
+            [
+                range(3, 5, LineStatus::Hit),
+                range(6, 6, LineStatus::Miss),
+                range(7, 8, LineStatus::Partial),
+                range(11, 11, LineStatus::Hit),
+            ]
+        );
+        assert!(LineCoverage::default().ranges().is_empty());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_line_coverage_matches_summary() {
+        use tempfile::TempDir;
+
+        use crate::test_utils::sqlite_report::build_sample_report;
+
+        let temp_dir = TempDir::new().unwrap();
+        let report = build_sample_report(temp_dir.path().join("db.sqlite")).unwrap();
+
+        let mut counts = [0u64; 4];
+        for coverage in line_coverage_by_path(&report).unwrap().values() {
+            for status in coverage.statuses() {
+                counts[status.bits() as usize] += 1;
+            }
+            assert_eq!(
+                &LineCoverage::from_rle(&coverage.to_rle()).unwrap(),
+                coverage
+            );
+            assert_eq!(&LineCoverage::from_bitmap(&coverage.to_bitmap()), coverage);
+
+            let ranged_lines: i64 = coverage
+                .ranges()
+                .iter()
+                .map(|range| range.end_line - range.start_line + 1)
+                .sum();
+            let tracked_lines = coverage
+                .statuses()
+                .iter()
+                .filter(|status| **status != LineStatus::Untracked)
+                .count();
+            assert_eq!(ranged_lines as usize, tracked_lines);
+        }
+        let [_, misses, partials, hits] = counts;
+
+        let totals = report.summary().unwrap().totals;
+        assert!(totals.lines > 0);
+        assert_eq!(hits, totals.hits);
+        assert_eq!(misses, totals.misses);
+        assert_eq!(partials, totals.partials);
+        assert_eq!(hits + misses + partials, totals.lines);
+    }
+}
@@ -0,0 +1,1978 @@
+//! A [`Report`] that lives entirely in memory, for targets like
+//! `wasm32-unknown-unknown` where SQLite and file IO aren't available. Every
+//! table is a `Vec` and queries are linear scans, so it's meant for one or a
+//! handful of uploads at a time rather than whole-repository reports.
+//!
+//! Query results match `SqliteReport`'s,
+//! including the orders documented on [`Report`].
+
+use std::{
+    cmp::max,
+    collections::{hash_map::RandomState, BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::{BuildHasher, Hasher},
+    ops::{Range, RangeFrom},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::{
+    models,
+    summary::{ReportSummary, SummaryCounts},
+    DuplicateUploadPolicy, MergePolicy, Report, ReportBuilder,
+};
+use crate::{
+    error::{CodecovError, Result},
+    parsers::json::JsonVal,
+};
+
+#[derive(Debug, Default, Clone)]
+pub struct MemoryReport {
+    files: Vec<models::SourceFile>,
+    contexts: Vec<models::Context>,
+    samples: Vec<models::CoverageSample>,
+    branches: Vec<models::BranchesData>,
+    methods: Vec<models::MethodData>,
+    spans: Vec<models::SpanData>,
+    assocs: Vec<models::ContextAssoc>,
+    uploads: Vec<models::RawUpload>,
+    tags: Vec<models::UploadTag>,
+    session_file_totals: Vec<models::SessionFileTotals>,
+    line_attributes: Vec<models::LineAttribute>,
+
+    /// The generation of each upload that has been superseded at least once.
+    generations: HashMap<i64, i64>,
+
+    // IDs in `files`, `contexts` and `uploads`, which must be unique
+    file_ids: HashSet<i64>,
+    context_ids: HashSet<i64>,
+    upload_ids: HashSet<i64>,
+}
+
+/// The length of each table, in declaration order. Appending is the only way
+/// rows are added, so truncating back to these lengths undoes any inserts.
+type TableLens = [usize; 11];
+
+impl MemoryReport {
+    pub fn new() -> MemoryReport {
+        MemoryReport::default()
+    }
+
+    fn lens(&self) -> TableLens {
+        [
+            self.files.len(),
+            self.contexts.len(),
+            self.samples.len(),
+            self.branches.len(),
+            self.methods.len(),
+            self.spans.len(),
+            self.assocs.len(),
+            self.uploads.len(),
+            self.tags.len(),
+            self.session_file_totals.len(),
+            self.line_attributes.len(),
+        ]
+    }
+
+    fn truncate(&mut self, lens: TableLens) {
+        let [files, contexts, samples, branches, methods, spans, assocs, uploads, tags, session_file_totals, line_attributes] =
+            lens;
+        self.files.truncate(files);
+        self.contexts.truncate(contexts);
+        self.samples.truncate(samples);
+        self.branches.truncate(branches);
+        self.methods.truncate(methods);
+        self.spans.truncate(spans);
+        self.assocs.truncate(assocs);
+        self.uploads.truncate(uploads);
+        self.tags.truncate(tags);
+        self.session_file_totals.truncate(session_file_totals);
+        self.line_attributes.truncate(line_attributes);
+        self.rebuild_indexes();
+    }
+
+    fn rebuild_indexes(&mut self) {
+        self.file_ids = self.files.iter().map(|file| file.id).collect();
+        self.context_ids = self.contexts.iter().map(|context| context.id).collect();
+        self.upload_ids = self.uploads.iter().map(|upload| upload.id).collect();
+    }
+
+    fn push_file(&mut self, file: models::SourceFile) -> Result<()> {
+        if !self.file_ids.insert(file.id) {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "file {:?} already exists",
+                file.path
+            )));
+        }
+        self.files.push(file);
+        Ok(())
+    }
+
+    fn push_context(&mut self, context: models::Context) -> Result<()> {
+        if !self.context_ids.insert(context.id) {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "context {:?} already exists",
+                context.name
+            )));
+        }
+        self.contexts.push(context);
+        Ok(())
+    }
+
+    fn push_upload(&mut self, upload: models::RawUpload) -> Result<()> {
+        if let Some(external_id) = &upload.external_id {
+            if self
+                .uploads
+                .iter()
+                .any(|existing| existing.external_id.as_ref() == Some(external_id))
+            {
+                return Err(CodecovError::ReportBuilderError(format!(
+                    "upload with external ID {external_id:?} already exists"
+                )));
+            }
+        }
+        if !self.upload_ids.insert(upload.id) {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "upload with ID {} already exists",
+                upload.id
+            )));
+        }
+        self.uploads.push(upload);
+        Ok(())
+    }
+
+    fn push_tag(&mut self, tag: models::UploadTag) -> Result<()> {
+        if self
+            .tags
+            .iter()
+            .any(|existing| existing.raw_upload_id == tag.raw_upload_id && existing.key == tag.key)
+        {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "upload {} already has a tag {:?}",
+                tag.raw_upload_id, tag.key
+            )));
+        }
+        self.tags.push(tag);
+        Ok(())
+    }
+
+    fn push_session_file_totals(&mut self, totals: models::SessionFileTotals) -> Result<()> {
+        if self.session_file_totals.iter().any(|existing| {
+            existing.source_file_id == totals.source_file_id
+                && existing.raw_upload_id == totals.raw_upload_id
+        }) {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "file {} already has totals for upload {}",
+                totals.source_file_id, totals.raw_upload_id
+            )));
+        }
+        self.session_file_totals.push(totals);
+        Ok(())
+    }
+
+    fn line_attribute_mut(
+        &mut self,
+        attribute: &models::LineAttribute,
+    ) -> Option<&mut models::LineAttribute> {
+        self.line_attributes.iter_mut().find(|existing| {
+            existing.raw_upload_id == attribute.raw_upload_id
+                && existing.source_file_id == attribute.source_file_id
+                && existing.line_no == attribute.line_no
+        })
+    }
+
+    /// Adds `attribute`'s flags to the line's existing ones, like the
+    /// `line_attribute` upsert does.
+    fn add_line_attribute(&mut self, attribute: models::LineAttribute) {
+        match self.line_attribute_mut(&attribute) {
+            Some(existing) => existing.attributes |= attribute.attributes,
+            None => self.line_attributes.push(attribute),
+        }
+    }
+
+    /// `(raw_upload_id, source_file_id, line_no)` of every line with
+    /// attributes, whose samples totals leave out.
+    fn attributed_lines(&self) -> HashSet<(i64, i64, i64)> {
+        self.line_attributes
+            .iter()
+            .filter(|attribute| !attribute.attributes.is_empty())
+            .map(|attribute| {
+                (
+                    attribute.raw_upload_id,
+                    attribute.source_file_id,
+                    attribute.line_no,
+                )
+            })
+            .collect()
+    }
+
+    fn delete_raw_upload(&mut self, raw_upload_id: i64) {
+        self.tags.retain(|tag| tag.raw_upload_id != raw_upload_id);
+        self.generations.remove(&raw_upload_id);
+        self.delete_measurements(raw_upload_id);
+        self.uploads.retain(|upload| upload.id != raw_upload_id);
+        self.rebuild_indexes();
+    }
+
+    /// Deletes the data recorded for an upload, leaving the upload itself and
+    /// its tags.
+    fn delete_measurements(&mut self, raw_upload_id: i64) {
+        self.session_file_totals
+            .retain(|totals| totals.raw_upload_id != raw_upload_id);
+        self.line_attributes
+            .retain(|attribute| attribute.raw_upload_id != raw_upload_id);
+        self.assocs
+            .retain(|assoc| assoc.raw_upload_id != raw_upload_id);
+        self.spans
+            .retain(|span| span.raw_upload_id != raw_upload_id);
+        self.methods
+            .retain(|method| method.raw_upload_id != raw_upload_id);
+        self.branches
+            .retain(|branch| branch.raw_upload_id != raw_upload_id);
+        self.samples
+            .retain(|sample| sample.raw_upload_id != raw_upload_id);
+    }
+
+    fn file(&self, id: i64) -> Option<&models::SourceFile> {
+        self.files.iter().find(|file| file.id == id)
+    }
+
+    fn upload(&self, id: i64) -> Option<&models::RawUpload> {
+        self.uploads.iter().find(|upload| upload.id == id)
+    }
+
+    fn sample(&self, raw_upload_id: i64, local_sample_id: i64) -> Option<&models::CoverageSample> {
+        self.samples.iter().find(|sample| {
+            sample.raw_upload_id == raw_upload_id && sample.local_sample_id == local_sample_id
+        })
+    }
+
+    /// The contexts of `assocs`, ordered by name and without duplicates.
+    fn contexts_for<'a>(
+        &self,
+        assocs: impl Iterator<Item = &'a models::ContextAssoc>,
+    ) -> Vec<models::Context> {
+        let ids: HashSet<i64> = assocs.map(|assoc| assoc.context_id).collect();
+        let mut contexts: Vec<_> = self
+            .contexts
+            .iter()
+            .filter(|context| ids.contains(&context.id))
+            .cloned()
+            .collect();
+        contexts.sort_by(|a, b| (&a.name, a.id).cmp(&(&b.name, b.id)));
+        contexts
+    }
+
+    fn uploads_where(&self, f: impl Fn(&models::RawUpload) -> bool) -> Vec<models::RawUpload> {
+        let mut uploads: Vec<_> = self.uploads.iter().filter(|u| f(u)).cloned().collect();
+        uploads.sort_by_key(|upload| upload.id);
+        uploads
+    }
+
+    fn samples_where(
+        &self,
+        f: impl Fn(&models::CoverageSample) -> bool,
+    ) -> Vec<models::CoverageSample> {
+        let mut samples: Vec<_> = self.samples.iter().filter(|s| f(s)).cloned().collect();
+        samples.sort_by_key(|sample| (sample.raw_upload_id, sample.local_sample_id));
+        samples
+    }
+
+    /// Totals over `samples` the way `totals.sql` computes them: each sample
+    /// counts once per method declared on it, or once if it has none, and
+    /// samples on lines with attributes don't count.
+    fn coverage_totals<'a>(
+        &'a self,
+        samples: impl Iterator<Item = &'a models::CoverageSample>,
+    ) -> models::CoverageTotals {
+        let mut methods: HashMap<(i64, i64), Vec<&models::MethodData>> = HashMap::new();
+        for method in &self.methods {
+            methods
+                .entry((method.raw_upload_id, method.local_sample_id))
+                .or_default()
+                .push(method);
+        }
+
+        let attributed = self.attributed_lines();
+
+        let mut totals = models::CoverageTotals::default();
+        for sample in samples {
+            if attributed.contains(&(sample.raw_upload_id, sample.source_file_id, sample.line_no)) {
+                continue;
+            }
+            let sample_methods = methods
+                .get(&(sample.raw_upload_id, sample.local_sample_id))
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            for method in sample_methods
+                .iter()
+                .map(Some)
+                .chain(sample_methods.is_empty().then_some(None))
+            {
+                let hit = sample.hits.is_some_and(|hits| hits > 0);
+                match sample.coverage_type {
+                    models::CoverageType::Line | models::CoverageType::Statement => {
+                        totals.total_lines += 1;
+                        totals.hit_lines += hit as u64;
+                    }
+                    models::CoverageType::Branch => {
+                        totals.total_branch_roots += 1;
+                        totals.hit_branches += sample.hit_branches.unwrap_or(0) as u64;
+                        totals.total_branches += sample.total_branches.unwrap_or(0) as u64;
+                    }
+                    models::CoverageType::Method => {
+                        totals.total_methods += 1;
+                        totals.hit_methods += hit as u64;
+                    }
+                }
+                // Complexity counts wherever a method was declared, even if
+                // the sample there is a branch
+                if let Some(method) = method {
+                    totals.hit_complexity_paths += method.hit_complexity_paths.unwrap_or(0) as u64;
+                    totals.total_complexity += method.total_complexity.unwrap_or(0) as u64;
+                }
+            }
+        }
+        totals
+    }
+
+    /// Merges `other` into this report. See [`SqliteReport::merge_attached`]
+    /// for the algorithm, which this follows step for step.
+    ///
+    /// [`SqliteReport::merge_attached`]: crate::report::SqliteReport::merge_attached
+    fn merge_from(&mut self, other: &MemoryReport, policy: MergePolicy) {
+        // Samples from an upload we already have need local IDs that don't
+        // collide with ours. Uploads we don't have are shifted by 0.
+        let shifts: HashMap<i64, i64> = other
+            .uploads
+            .iter()
+            .map(|upload| {
+                let max_local_id = [
+                    self.samples
+                        .iter()
+                        .filter(|s| s.raw_upload_id == upload.id)
+                        .map(|s| s.local_sample_id)
+                        .max(),
+                    self.branches
+                        .iter()
+                        .filter(|b| b.raw_upload_id == upload.id)
+                        .map(|b| b.local_branch_id)
+                        .max(),
+                    self.methods
+                        .iter()
+                        .filter(|m| m.raw_upload_id == upload.id)
+                        .map(|m| m.local_method_id)
+                        .max(),
+                    self.spans
+                        .iter()
+                        .filter(|s| s.raw_upload_id == upload.id)
+                        .map(|s| s.local_span_id)
+                        .max(),
+                ]
+                .into_iter()
+                .flatten()
+                .max()
+                .unwrap_or(-1);
+                (upload.id, max_local_id + 1)
+            })
+            .collect();
+
+        // Incoming `(raw_upload_id, local_sample_id)` -> the sample of ours it
+        // collided with and whether the incoming copy of the upload is newer
+        let mut conflicts: HashMap<(i64, i64), (i64, bool)> = HashMap::new();
+        if policy != MergePolicy::KeepBoth {
+            // `CoverageType` isn't hashable, so its `Debug` name stands in
+            let mut existing: HashMap<(i64, i64, i64, String), i64> = HashMap::new();
+            for sample in &self.samples {
+                let key = (
+                    sample.raw_upload_id,
+                    sample.source_file_id,
+                    sample.line_no,
+                    format!("{:?}", sample.coverage_type),
+                );
+                let id = existing.entry(key).or_insert(sample.local_sample_id);
+                *id = (*id).min(sample.local_sample_id);
+            }
+            let timestamp = |report: &MemoryReport, id: i64| {
+                report
+                    .upload(id)
+                    .and_then(|upload| upload.timestamp)
+                    .unwrap_or(i64::MIN)
+            };
+            for incoming in &other.samples {
+                let key = (
+                    incoming.raw_upload_id,
+                    incoming.source_file_id,
+                    incoming.line_no,
+                    format!("{:?}", incoming.coverage_type),
+                );
+                if let Some(&sample_id) = existing.get(&key) {
+                    let incoming_wins = timestamp(other, incoming.raw_upload_id)
+                        > timestamp(self, incoming.raw_upload_id);
+                    conflicts.insert(
+                        (incoming.raw_upload_id, incoming.local_sample_id),
+                        (sample_id, incoming_wins),
+                    );
+                }
+            }
+        }
+        let conflict = |raw_upload_id: i64, local_sample_id: Option<i64>| {
+            local_sample_id.and_then(|id| conflicts.get(&(raw_upload_id, id)).copied())
+        };
+
+        // Files and contexts are keyed by a hash of their names, so the same
+        // ones in both reports have the same IDs
+        for file in &other.files {
+            if self.file_ids.insert(file.id) {
+                self.files.push(file.clone());
+            }
+        }
+        for upload in &other.uploads {
+            if self.upload_ids.insert(upload.id) {
+                self.uploads.push(upload.clone());
+            }
+        }
+        for context in &other.contexts {
+            if self.context_ids.insert(context.id) {
+                self.contexts.push(context.clone());
+            }
+        }
+
+        if policy != MergePolicy::KeepBoth {
+            // Several incoming samples may have collided with the same one of
+            // ours, so they're aggregated first
+            let mut merged: BTreeMap<(i64, i64), MergedSample> = BTreeMap::new();
+            for incoming in &other.samples {
+                let Some((sample_id, incoming_wins)) =
+                    conflict(incoming.raw_upload_id, Some(incoming.local_sample_id))
+                else {
+                    continue;
+                };
+                merged
+                    .entry((incoming.raw_upload_id, sample_id))
+                    .or_default()
+                    .add(incoming, incoming_wins, policy);
+            }
+            let branch_count_policy = match policy {
+                MergePolicy::SumHits => MergePolicy::MaxHits,
+                _ => policy,
+            };
+            for sample in &mut self.samples {
+                let Some(merged) = merged.get(&(sample.raw_upload_id, sample.local_sample_id))
+                else {
+                    continue;
+                };
+                let wins = merged.incoming_wins;
+                sample.hits = combine(policy, wins, sample.hits, merged.hits);
+                sample.hit_branches = combine(
+                    branch_count_policy,
+                    wins,
+                    sample.hit_branches,
+                    merged.hit_branches,
+                );
+                sample.total_branches = combine(
+                    branch_count_policy,
+                    wins,
+                    sample.total_branches,
+                    merged.total_branches,
+                );
+                if policy == MergePolicy::PreferNewest && wins {
+                    sample.messages = merged.messages.clone();
+                }
+            }
+        }
+
+        // Decide which incoming branches and methods of colliding samples to
+        // take, updating ours along the way
+        let mut take_conflicting_branch: HashSet<(i64, i64)> = HashSet::new();
+        let mut take_conflicting_method: HashSet<(i64, i64)> = HashSet::new();
+        match policy {
+            MergePolicy::KeepBoth => {}
+            MergePolicy::PreferNewest => {
+                let wins_for: HashSet<(i64, i64)> = conflicts
+                    .iter()
+                    .filter(|(_, (_, wins))| *wins)
+                    .map(|(&(raw_upload_id, _), &(sample_id, _))| (raw_upload_id, sample_id))
+                    .collect();
+                self.branches.retain(|branch| {
+                    !wins_for.contains(&(branch.raw_upload_id, branch.local_sample_id))
+                });
+                self.methods.retain(|method| {
+                    !wins_for.contains(&(method.raw_upload_id, method.local_sample_id))
+                });
+                for branch in &other.branches {
+                    if let Some((_, true)) =
+                        conflict(branch.raw_upload_id, Some(branch.local_sample_id))
+                    {
+                        take_conflicting_branch
+                            .insert((branch.raw_upload_id, branch.local_branch_id));
+                    }
+                }
+                for method in &other.methods {
+                    if let Some((_, true)) =
+                        conflict(method.raw_upload_id, Some(method.local_sample_id))
+                    {
+                        take_conflicting_method
+                            .insert((method.raw_upload_id, method.local_method_id));
+                    }
+                }
+            }
+            MergePolicy::SumHits | MergePolicy::MaxHits => {
+                type BranchKey = (i64, i64, String, String);
+                let branch_key = |raw_upload_id, sample_id, branch: &models::BranchesData| {
+                    (
+                        raw_upload_id,
+                        sample_id,
+                        format!("{:?}", branch.branch_format),
+                        branch.branch.clone(),
+                    )
+                };
+                let mut incoming_hits: HashMap<BranchKey, Option<i64>> = HashMap::new();
+                for branch in &other.branches {
+                    let Some((sample_id, _)) =
+                        conflict(branch.raw_upload_id, Some(branch.local_sample_id))
+                    else {
+                        continue;
+                    };
+                    let hits = incoming_hits
+                        .entry(branch_key(branch.raw_upload_id, sample_id, branch))
+                        .or_default();
+                    *hits = aggregate(policy, *hits, Some(branch.hits));
+                }
+                let existing: HashSet<BranchKey> = self
+                    .branches
+                    .iter()
+                    .map(|branch| branch_key(branch.raw_upload_id, branch.local_sample_id, branch))
+                    .collect();
+                for branch in &mut self.branches {
+                    let key = branch_key(branch.raw_upload_id, branch.local_sample_id, branch);
+                    if let Some(&hits) = incoming_hits.get(&key) {
+                        if let Some(hits) = combine(policy, false, Some(branch.hits), hits) {
+                            branch.hits = hits;
+                        }
+                    }
+                }
+                for branch in &other.branches {
+                    let Some((sample_id, _)) =
+                        conflict(branch.raw_upload_id, Some(branch.local_sample_id))
+                    else {
+                        continue;
+                    };
+                    if !existing.contains(&branch_key(branch.raw_upload_id, sample_id, branch)) {
+                        take_conflicting_branch
+                            .insert((branch.raw_upload_id, branch.local_branch_id));
+                    }
+                }
+            }
+        }
+
+        // Everything else is keyed by `(raw_upload_id, local_*_id)`, which
+        // `shifts` keeps unique, so we can concatenate the tables
+        let shift = |raw_upload_id: i64| shifts.get(&raw_upload_id).copied().unwrap_or(0);
+        let sample_id = |raw_upload_id: i64, local_sample_id: i64| {
+            conflict(raw_upload_id, Some(local_sample_id))
+                .map(|(sample_id, _)| sample_id)
+                .unwrap_or(local_sample_id + shift(raw_upload_id))
+        };
+        for sample in &other.samples {
+            if conflict(sample.raw_upload_id, Some(sample.local_sample_id)).is_none() {
+                self.samples.push(models::CoverageSample {
+                    local_sample_id: sample.local_sample_id + shift(sample.raw_upload_id),
+                    ..sample.clone()
+                });
+            }
+        }
+        for branch in &other.branches {
+            let colliding = conflict(branch.raw_upload_id, Some(branch.local_sample_id)).is_some();
+            if !colliding
+                || take_conflicting_branch.contains(&(branch.raw_upload_id, branch.local_branch_id))
+            {
+                self.branches.push(models::BranchesData {
+                    local_branch_id: branch.local_branch_id + shift(branch.raw_upload_id),
+                    local_sample_id: sample_id(branch.raw_upload_id, branch.local_sample_id),
+                    ..branch.clone()
+                });
+            }
+        }
+        if matches!(policy, MergePolicy::SumHits | MergePolicy::MaxHits) {
+            // Colliding samples with a row for each of their branches are
+            // recounted from the merged branches, like `SqliteReport` does
+            let targets: HashSet<(i64, i64)> = conflicts
+                .iter()
+                .map(|(&(raw_upload_id, _), &(sample_id, _))| (raw_upload_id, sample_id))
+                .collect();
+            let mut counts: HashMap<(i64, i64), (i64, i64)> = HashMap::new();
+            for branch in &self.branches {
+                let key = (branch.raw_upload_id, branch.local_sample_id);
+                if targets.contains(&key) {
+                    let (hit, total) = counts.entry(key).or_default();
+                    *hit += i64::from(branch.hits > 0);
+                    *total += 1;
+                }
+            }
+            for sample in &mut self.samples {
+                let Some(&(hit, total)) =
+                    counts.get(&(sample.raw_upload_id, sample.local_sample_id))
+                else {
+                    continue;
+                };
+                if total >= sample.total_branches.unwrap_or(0) {
+                    sample.hit_branches = Some(hit);
+                    sample.total_branches = Some(total);
+                }
+            }
+        }
+        for method in &other.methods {
+            let colliding = conflict(method.raw_upload_id, Some(method.local_sample_id)).is_some();
+            if !colliding
+                || take_conflicting_method.contains(&(method.raw_upload_id, method.local_method_id))
+            {
+                self.methods.push(models::MethodData {
+                    local_method_id: method.local_method_id + shift(method.raw_upload_id),
+                    local_sample_id: sample_id(method.raw_upload_id, method.local_sample_id),
+                    ..method.clone()
+                });
+            }
+        }
+        let mut dropped_spans: HashSet<(i64, i64)> = HashSet::new();
+        for span in &other.spans {
+            if conflict(span.raw_upload_id, span.local_sample_id).is_some() {
+                dropped_spans.insert((span.raw_upload_id, span.local_span_id));
+                continue;
+            }
+            self.spans.push(models::SpanData {
+                local_span_id: span.local_span_id + shift(span.raw_upload_id),
+                local_sample_id: span
+                    .local_sample_id
+                    .map(|id| sample_id(span.raw_upload_id, id)),
+                ..span.clone()
+            });
+        }
+        for assoc in &other.assocs {
+            if !shifts.contains_key(&assoc.raw_upload_id)
+                || assoc
+                    .local_span_id
+                    .is_some_and(|id| dropped_spans.contains(&(assoc.raw_upload_id, id)))
+            {
+                continue;
+            }
+            self.assocs.push(models::ContextAssoc {
+                local_sample_id: assoc
+                    .local_sample_id
+                    .map(|id| sample_id(assoc.raw_upload_id, id)),
+                local_span_id: assoc
+                    .local_span_id
+                    .map(|id| id + shift(assoc.raw_upload_id)),
+                ..assoc.clone()
+            });
+        }
+
+        // Uploads we already had may now have the same association twice
+        let mut seen = HashSet::new();
+        self.assocs.retain(|assoc| {
+            shift(assoc.raw_upload_id) == 0
+                || seen.insert((
+                    assoc.context_id,
+                    assoc.raw_upload_id,
+                    assoc.local_sample_id,
+                    assoc.local_span_id,
+                    assoc.source_file_id,
+                ))
+        });
+        for tag in &other.tags {
+            let _ = self.push_tag(tag.clone());
+        }
+        for totals in &other.session_file_totals {
+            let _ = self.push_session_file_totals(totals.clone());
+        }
+        for attribute in &other.line_attributes {
+            self.add_line_attribute(attribute.clone());
+        }
+    }
+}
+
+/// The incoming samples that collided with one of ours, aggregated.
+#[derive(Default)]
+struct MergedSample {
+    hits: Option<i64>,
+    hit_branches: Option<i64>,
+    total_branches: Option<i64>,
+    messages: Option<JsonVal>,
+    incoming_wins: bool,
+}
+
+impl MergedSample {
+    fn add(&mut self, incoming: &models::CoverageSample, incoming_wins: bool, policy: MergePolicy) {
+        self.hits = aggregate(policy, self.hits, incoming.hits);
+        self.hit_branches = aggregate(
+            MergePolicy::MaxHits,
+            self.hit_branches,
+            incoming.hit_branches,
+        );
+        self.total_branches = aggregate(
+            MergePolicy::MaxHits,
+            self.total_branches,
+            incoming.total_branches,
+        );
+        // SQLite compares the serialized JSON
+        if incoming.messages.as_ref().map(JsonVal::to_string)
+            > self.messages.as_ref().map(JsonVal::to_string)
+        {
+            self.messages = incoming.messages.clone();
+        }
+        self.incoming_wins |= incoming_wins;
+    }
+}
+
+/// Folds `value` into `acc` like SQL's `sum()` under [`MergePolicy::SumHits`]
+/// or `max()` otherwise: missing values are skipped.
+fn aggregate(policy: MergePolicy, acc: Option<i64>, value: Option<i64>) -> Option<i64> {
+    match (acc, value) {
+        (Some(acc), Some(value)) if policy == MergePolicy::SumHits => Some(acc + value),
+        (Some(acc), Some(value)) => Some(max(acc, value)),
+        (acc, value) => acc.or(value),
+    }
+}
+
+/// Combines our value with the incoming one according to `policy`.
+fn combine(
+    policy: MergePolicy,
+    incoming_wins: bool,
+    ours: Option<i64>,
+    theirs: Option<i64>,
+) -> Option<i64> {
+    match policy {
+        MergePolicy::PreferNewest if incoming_wins => theirs,
+        MergePolicy::PreferNewest => ours,
+        MergePolicy::SumHits if ours.is_none() && theirs.is_none() => None,
+        MergePolicy::SumHits => Some(ours.unwrap_or(0) + theirs.unwrap_or(0)),
+        // `None` is less than any `Some`
+        _ => max(ours, theirs),
+    }
+}
+
+impl Report for MemoryReport {
+    fn list_files(&self) -> Result<Vec<models::SourceFile>> {
+        let mut files = self.files.clone();
+        files.sort_by(|a, b| (&a.path, a.id).cmp(&(&b.path, b.id)));
+        Ok(files)
+    }
+
+    fn list_contexts(&self) -> Result<Vec<models::Context>> {
+        let mut contexts = self.contexts.clone();
+        contexts.sort_by(|a, b| (&a.name, a.id).cmp(&(&b.name, b.id)));
+        Ok(contexts)
+    }
+
+    fn list_coverage_samples(&self) -> Result<Vec<models::CoverageSample>> {
+        Ok(self.samples_where(|_| true))
+    }
+
+    fn list_branches_for_sample(
+        &self,
+        sample: &models::CoverageSample,
+    ) -> Result<Vec<models::BranchesData>> {
+        let mut branches: Vec<_> = self
+            .branches
+            .iter()
+            .filter(|branch| {
+                branch.raw_upload_id == sample.raw_upload_id
+                    && branch.local_sample_id == sample.local_sample_id
+            })
+            .cloned()
+            .collect();
+        branches.sort_by_key(|branch| branch.local_branch_id);
+        Ok(branches)
+    }
+
+    fn get_method_for_sample(
+        &self,
+        sample: &models::CoverageSample,
+    ) -> Result<Option<models::MethodData>> {
+        Ok(self
+            .methods
+            .iter()
+            .find(|method| {
+                method.raw_upload_id == sample.raw_upload_id
+                    && method.local_sample_id == sample.local_sample_id
+            })
+            .cloned())
+    }
+
+    fn list_spans_for_sample(
+        &self,
+        sample: &models::CoverageSample,
+    ) -> Result<Vec<models::SpanData>> {
+        let mut spans: Vec<_> = self
+            .spans
+            .iter()
+            .filter(|span| {
+                span.raw_upload_id == sample.raw_upload_id
+                    && span.local_sample_id == Some(sample.local_sample_id)
+            })
+            .cloned()
+            .collect();
+        spans.sort_by_key(|span| span.local_span_id);
+        Ok(spans)
+    }
+
+    fn list_contexts_for_sample(
+        &self,
+        sample: &models::CoverageSample,
+    ) -> Result<Vec<models::Context>> {
+        Ok(self.contexts_for(self.assocs.iter().filter(|assoc| {
+            assoc.raw_upload_id == sample.raw_upload_id
+                && assoc.local_sample_id == Some(sample.local_sample_id)
+        })))
+    }
+
+    fn list_samples_for_file(
+        &self,
+        file: &models::SourceFile,
+    ) -> Result<Vec<models::CoverageSample>> {
+        let mut samples = self.samples_where(|sample| sample.source_file_id == file.id);
+        samples.sort_by_key(|sample| sample.line_no);
+        Ok(samples)
+    }
+
+    fn samples_for_line(
+        &self,
+        file: &models::SourceFile,
+        line_no: i64,
+    ) -> Result<Vec<(models::CoverageSample, models::RawUpload)>> {
+        Ok(self
+            .samples_where(|sample| sample.source_file_id == file.id && sample.line_no == line_no)
+            .into_iter()
+            .filter_map(|sample| {
+                let upload = self.upload(sample.raw_upload_id)?.clone();
+                Some((sample, upload))
+            })
+            .collect())
+    }
+
+    fn list_samples_for_context(
+        &self,
+        context: &models::Context,
+    ) -> Result<Vec<models::CoverageSample>> {
+        let mut samples: Vec<_> = self
+            .assocs
+            .iter()
+            .filter(|assoc| assoc.context_id == context.id)
+            .filter_map(|assoc| self.sample(assoc.raw_upload_id, assoc.local_sample_id?))
+            .cloned()
+            .collect();
+        samples.sort_by_key(|sample| (sample.raw_upload_id, sample.local_sample_id));
+        Ok(samples)
+    }
+
+    fn list_files_for_context(&self, context: &models::Context) -> Result<Vec<models::SourceFile>> {
+        let ids: HashSet<i64> = self
+            .list_samples_for_context(context)?
+            .iter()
+            .map(|sample| sample.source_file_id)
+            .collect();
+        let mut files: Vec<_> = self
+            .files
+            .iter()
+            .filter(|file| ids.contains(&file.id))
+            .cloned()
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    fn list_contexts_for_file(&self, file: &models::SourceFile) -> Result<Vec<models::Context>> {
+        Ok(self.contexts_for(self.assocs.iter().filter(|assoc| {
+            assoc.source_file_id == Some(file.id)
+                && assoc.local_sample_id.is_none()
+                && assoc.local_span_id.is_none()
+        })))
+    }
+
+    fn list_contexts_for_upload(
+        &self,
+        raw_upload: &models::RawUpload,
+    ) -> Result<Vec<models::Context>> {
+        Ok(self.contexts_for(self.assocs.iter().filter(|assoc| {
+            assoc.raw_upload_id == raw_upload.id
+                && assoc.source_file_id.is_none()
+                && assoc.local_sample_id.is_none()
+                && assoc.local_span_id.is_none()
+        })))
+    }
+
+    fn list_contexts_for_lines(&self, lines: &[(&str, i64)]) -> Result<Vec<models::Context>> {
+        let lines: HashSet<(i64, i64)> = lines
+            .iter()
+            .flat_map(|(path, line_no)| {
+                self.files
+                    .iter()
+                    .filter(move |file| file.path == *path)
+                    .map(move |file| (file.id, *line_no))
+            })
+            .collect();
+        let samples: HashSet<(i64, i64)> = self
+            .samples_where(|sample| lines.contains(&(sample.source_file_id, sample.line_no)))
+            .iter()
+            .map(|sample| (sample.raw_upload_id, sample.local_sample_id))
+            .collect();
+        Ok(self.contexts_for(self.assocs.iter().filter(|assoc| {
+            assoc
+                .local_sample_id
+                .is_some_and(|id| samples.contains(&(assoc.raw_upload_id, id)))
+        })))
+    }
+
+    fn list_methods_for_file(
+        &self,
+        file: &models::SourceFile,
+    ) -> Result<Vec<(models::MethodData, models::CoverageSample)>> {
+        let mut methods: Vec<_> = self
+            .methods
+            .iter()
+            .filter(|method| method.source_file_id == file.id)
+            .filter_map(|method| {
+                let sample = self.sample(method.raw_upload_id, method.local_sample_id)?;
+                Some((method.clone(), sample.clone()))
+            })
+            .collect();
+        methods.sort_by_key(|(method, sample)| {
+            (sample.line_no, method.raw_upload_id, method.local_method_id)
+        });
+        Ok(methods)
+    }
+
+    fn list_raw_uploads(&self) -> Result<Vec<models::RawUpload>> {
+        Ok(self.uploads_where(|_| true))
+    }
+
+    fn list_raw_uploads_for_url(&self, raw_upload_url: &str) -> Result<Vec<models::RawUpload>> {
+        Ok(self.uploads_where(|upload| upload.raw_upload_url.as_deref() == Some(raw_upload_url)))
+    }
+
+    fn list_uploads_by_state(&self, state: models::UploadState) -> Result<Vec<models::RawUpload>> {
+        Ok(self.uploads_where(|upload| upload.state.as_deref() == Some(state.as_str())))
+    }
+
+    fn list_uploads_in_range(&self, range: Range<i64>) -> Result<Vec<models::RawUpload>> {
+        let mut uploads =
+            self.uploads_where(|upload| upload.timestamp.is_some_and(|t| range.contains(&t)));
+        uploads.sort_by_key(|upload| (upload.timestamp, upload.id));
+        Ok(uploads)
+    }
+
+    fn list_tags_for_upload(
+        &self,
+        raw_upload: &models::RawUpload,
+    ) -> Result<Vec<models::UploadTag>> {
+        let mut tags: Vec<_> = self
+            .tags
+            .iter()
+            .filter(|tag| tag.raw_upload_id == raw_upload.id)
+            .cloned()
+            .collect();
+        tags.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(tags)
+    }
+
+    fn list_uploads_by_tag(&self, key: &str, value: &str) -> Result<Vec<models::RawUpload>> {
+        let ids: HashSet<i64> = self
+            .tags
+            .iter()
+            .filter(|tag| tag.key == key && tag.value == value)
+            .map(|tag| tag.raw_upload_id)
+            .collect();
+        Ok(self.uploads_where(|upload| ids.contains(&upload.id)))
+    }
+
+    fn list_session_file_totals(
+        &self,
+        file: &models::SourceFile,
+    ) -> Result<Vec<models::SessionFileTotals>> {
+        let mut totals: Vec<_> = self
+            .session_file_totals
+            .iter()
+            .filter(|totals| totals.source_file_id == file.id)
+            .cloned()
+            .collect();
+        totals.sort_by_key(|totals| totals.raw_upload_id);
+        Ok(totals)
+    }
+
+    fn list_line_attributes(
+        &self,
+        file: &models::SourceFile,
+    ) -> Result<Vec<models::LineAttribute>> {
+        let mut attributes: Vec<_> = self
+            .line_attributes
+            .iter()
+            .filter(|attribute| attribute.source_file_id == file.id)
+            .cloned()
+            .collect();
+        attributes.sort_by_key(|attribute| (attribute.line_no, attribute.raw_upload_id));
+        Ok(attributes)
+    }
+
+    fn get_file_metadata(&self, path: &str) -> Result<Option<models::SourceFile>> {
+        Ok(self.files.iter().find(|file| file.path == path).cloned())
+    }
+
+    fn file_for_chunk_index(&self, chunk_index: i64) -> Result<Option<models::SourceFile>> {
+        Ok(self
+            .files
+            .iter()
+            .filter(|file| file.chunk_index == Some(chunk_index))
+            .min_by_key(|file| file.id)
+            .cloned())
+    }
+
+    fn chunk_index_for_file(&self, file: &models::SourceFile) -> Result<Option<i64>> {
+        Ok(self.file(file.id).and_then(|file| file.chunk_index))
+    }
+
+    fn list_out_of_bounds_samples(&self) -> Result<Vec<models::CoverageSample>> {
+        Ok(self.samples_where(|sample| {
+            self.file(sample.source_file_id)
+                .and_then(|file| file.line_count)
+                .is_some_and(|line_count| sample.line_no > line_count)
+        }))
+    }
+
+    fn merge(&mut self, other: &MemoryReport, policy: MergePolicy) -> Result<()> {
+        for incoming in &other.uploads {
+            let Some(external_id) = &incoming.external_id else {
+                continue;
+            };
+            if let Some(existing) = self.uploads.iter().find(|existing| {
+                existing.external_id.as_ref() == Some(external_id) && existing.id != incoming.id
+            }) {
+                return Err(CodecovError::ReportBuilderError(format!(
+                    "upload with external ID {external_id:?} is upload {} in this report but upload {} in the one being merged",
+                    existing.id, incoming.id
+                )));
+            }
+        }
+        self.merge_from(other, policy);
+        Ok(())
+    }
+
+    fn totals(&self) -> Result<models::ReportTotals> {
+        Ok(models::ReportTotals {
+            files: self.files.len() as u64,
+            uploads: self.uploads.len() as u64,
+            test_cases: self.contexts.len() as u64,
+            coverage: self.coverage_totals(self.samples.iter()),
+        })
+    }
+
+    fn file_totals(&self, file: &models::SourceFile) -> Result<models::CoverageTotals> {
+        Ok(self.coverage_totals(
+            self.samples
+                .iter()
+                .filter(|sample| sample.source_file_id == file.id),
+        ))
+    }
+
+    fn list_file_totals(&self) -> Result<Vec<(models::SourceFile, models::CoverageTotals)>> {
+        let mut samples_by_file: HashMap<i64, Vec<&models::CoverageSample>> = HashMap::new();
+        for sample in &self.samples {
+            samples_by_file
+                .entry(sample.source_file_id)
+                .or_default()
+                .push(sample);
+        }
+        Ok(self
+            .list_files()?
+            .into_iter()
+            .map(|file| {
+                let samples = samples_by_file.remove(&file.id).unwrap_or_default();
+                let totals = self.coverage_totals(samples.into_iter());
+                (file, totals)
+            })
+            .collect())
+    }
+
+    fn list_upload_totals(&self) -> Result<Vec<(models::RawUpload, models::CoverageTotals)>> {
+        let mut samples_by_upload: HashMap<i64, Vec<&models::CoverageSample>> = HashMap::new();
+        for sample in &self.samples {
+            samples_by_upload
+                .entry(sample.raw_upload_id)
+                .or_default()
+                .push(sample);
+        }
+        Ok(self
+            .list_raw_uploads()?
+            .into_iter()
+            .map(|upload| {
+                let samples = samples_by_upload.remove(&upload.id).unwrap_or_default();
+                let totals = self.coverage_totals(samples.into_iter());
+                (upload, totals)
+            })
+            .collect())
+    }
+
+    fn totals_by_coverage_type(&self) -> Result<models::CoverageTypeTotals> {
+        let of_type = |coverage_type: models::CoverageType| {
+            self.coverage_totals(
+                self.samples
+                    .iter()
+                    .filter(move |sample| sample.coverage_type == coverage_type),
+            )
+        };
+        Ok(models::CoverageTypeTotals {
+            line: of_type(models::CoverageType::Line),
+            branch: of_type(models::CoverageType::Branch),
+            method: of_type(models::CoverageType::Method),
+            statement: of_type(models::CoverageType::Statement),
+        })
+    }
+
+    /// Follows `summary.sql`: each line counts once per flag, with the "most
+    /// covered" status any upload with that flag recorded for it.
+    fn summary(&self) -> Result<ReportSummary> {
+        // Each upload appears once with no flag, which makes up the
+        // whole-report summary, and once more for each of its flags
+        let mut upload_flags: HashMap<i64, Vec<Option<String>>> = HashMap::new();
+        for upload in &self.uploads {
+            let flags = upload_flags.entry(upload.id).or_default();
+            flags.push(None);
+            let values = match &upload.flags {
+                None => vec![],
+                Some(JsonVal::Array(values)) => values.iter().collect(),
+                Some(JsonVal::Object(values)) => values.values().collect(),
+                Some(value) => vec![value],
+            };
+            flags.extend(values.into_iter().filter_map(|value| match value {
+                JsonVal::Null => None,
+                JsonVal::String(flag) => Some(Some(flag.clone())),
+                value => Some(Some(value.to_string())),
+            }));
+        }
+
+        #[derive(Default)]
+        struct FlagLine {
+            status: i64,
+            hit_branches: Option<i64>,
+            total_branches: Option<i64>,
+        }
+        let attributed = self.attributed_lines();
+        let mut lines: BTreeMap<(Option<String>, i64, i64), FlagLine> = BTreeMap::new();
+        for sample in &self.samples {
+            if attributed.contains(&(sample.raw_upload_id, sample.source_file_id, sample.line_no)) {
+                continue;
+            }
+            let status = coverage_status(sample);
+            for flag in upload_flags
+                .get(&sample.raw_upload_id)
+                .into_iter()
+                .flatten()
+            {
+                let line = lines
+                    .entry((flag.clone(), sample.source_file_id, sample.line_no))
+                    .or_insert(FlagLine {
+                        status,
+                        ..Default::default()
+                    });
+                line.status = max(line.status, status);
+                line.hit_branches = max(line.hit_branches, sample.hit_branches);
+                line.total_branches = max(line.total_branches, sample.total_branches);
+            }
+        }
+
+        let mut summary = ReportSummary::default();
+        let mut files: HashMap<Option<String>, BTreeSet<i64>> = HashMap::new();
+        for ((flag, file_id, _), line) in lines {
+            files.entry(flag.clone()).or_default().insert(file_id);
+            let counts = match flag {
+                Some(flag) => summary.flags.entry(flag).or_default(),
+                None => &mut summary.totals,
+            };
+            counts.lines += 1;
+            counts.hits += (line.status == 2) as u64;
+            counts.misses += (line.status == 0) as u64;
+            counts.partials += (line.status == 1) as u64;
+            counts.hit_branches += line.hit_branches.unwrap_or(0) as u64;
+            counts.total_branches += line.total_branches.unwrap_or(0) as u64;
+        }
+        for (flag, file_ids) in files {
+            let counts: &mut SummaryCounts = match flag {
+                Some(flag) => summary.flags.entry(flag).or_default(),
+                None => &mut summary.totals,
+            };
+            counts.files = file_ids.len() as u64;
+        }
+        Ok(summary)
+    }
+}
+
+/// Whether `sample` is a hit (2), partial (1), miss (0) or skipped (-1), with
+/// the same SQL `NULL` semantics as `summary.sql`.
+fn coverage_status(sample: &models::CoverageSample) -> i64 {
+    let branches = sample.hit_branches.zip(sample.total_branches);
+    if sample.hits.is_some_and(|hits| hits > 0) || branches.is_some_and(|(hit, total)| hit >= total)
+    {
+        2
+    } else if sample.hits == Some(0) || sample.hit_branches == Some(0) {
+        0
+    } else if branches.is_some_and(|(hit, total)| hit > 0 && hit < total) {
+        1
+    } else {
+        -1
+    }
+}
+
+/// Which tables to roll back to, and the whole report as it was if anything
+/// was modified in place since the savepoint was made.
+#[derive(Debug)]
+struct Savepoint {
+    lens: TableLens,
+    snapshot: Option<MemoryReport>,
+}
+
+/// A seed no other builder in this process has. `RandomState` adds entropy
+/// where the platform has any; the counter keeps seeds apart where it doesn't.
+fn unique_seed() -> u64 {
+    static BUILDERS: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(BUILDERS.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+/// Builds a [`MemoryReport`]. Savepoints cost nothing until something is
+/// updated or deleted while one is open, at which point the report is cloned
+/// so the change can be undone.
+#[derive(Debug)]
+pub struct MemoryReportBuilder {
+    report: MemoryReport,
+
+    /// Local IDs are assigned from a single sequence, like
+    /// [`SqliteReportBuilder`](crate::report::SqliteReportBuilder) does.
+    id_sequence: RangeFrom<i64>,
+
+    upload_id_seed: u64,
+    uploads_inserted: u64,
+
+    savepoints: Vec<Savepoint>,
+}
+
+impl Default for MemoryReportBuilder {
+    fn default() -> Self {
+        MemoryReportBuilder::new()
+    }
+}
+
+impl MemoryReportBuilder {
+    /// Each builder gets its own upload ID seed, so reports built in the same
+    /// process can be merged without their uploads being taken for the same
+    /// one.
+    pub fn new() -> MemoryReportBuilder {
+        MemoryReportBuilder::with_upload_id_seed(unique_seed())
+    }
+
+    /// There's no randomness source on `wasm32-unknown-unknown`, so upload IDs
+    /// are a hash of `seed` and how many uploads this builder has inserted.
+    /// Reports that will be merged with each other need different seeds to
+    /// avoid their uploads being taken for the same one. A fixed seed makes
+    /// upload IDs reproducible.
+    pub fn with_upload_id_seed(seed: u64) -> MemoryReportBuilder {
+        MemoryReportBuilder {
+            report: MemoryReport::new(),
+            id_sequence: 0..,
+            upload_id_seed: seed,
+            uploads_inserted: 0,
+            savepoints: Vec::new(),
+        }
+    }
+
+    /// Call before modifying existing rows so the open savepoints can restore
+    /// them.
+    fn snapshot(&mut self) {
+        for savepoint in &mut self.savepoints {
+            if savepoint.snapshot.is_none() {
+                let mut snapshot = self.report.clone();
+                snapshot.truncate(savepoint.lens);
+                savepoint.snapshot = Some(snapshot);
+            }
+        }
+    }
+
+    fn upload_mut(&mut self, raw_upload_id: i64) -> Result<&mut models::RawUpload> {
+        self.report
+            .uploads
+            .iter_mut()
+            .find(|upload| upload.id == raw_upload_id)
+            .ok_or_else(|| {
+                CodecovError::ReportBuilderError(format!("no upload with ID {raw_upload_id}"))
+            })
+    }
+}
+
+impl ReportBuilder<MemoryReport> for MemoryReportBuilder {
+    fn insert_file(&mut self, path: &str) -> Result<models::SourceFile> {
+        let model = models::SourceFile::new(path);
+        self.report.push_file(model.clone())?;
+        Ok(model)
+    }
+
+    fn update_file_metadata(&mut self, file: &models::SourceFile) -> Result<()> {
+        self.snapshot();
+        if let Some(existing) = self.report.files.iter_mut().find(|f| f.id == file.id) {
+            if let Some(language) = &file.language {
+                existing.language = Some(language.clone());
+            }
+            if let Some(content_hash) = &file.content_hash {
+                existing.content_hash = Some(content_hash.clone());
+            }
+            existing.line_count = file.line_count.or(existing.line_count);
+            existing.chunk_index = file.chunk_index.or(existing.chunk_index);
+        }
+        Ok(())
+    }
+
+    fn insert_context(&mut self, name: &str) -> Result<models::Context> {
+        let model = models::Context::new(name);
+        self.report.push_context(model.clone())?;
+        Ok(model)
+    }
+
+    fn multi_insert_context(&mut self, names: &[&str]) -> Result<Vec<models::Context>> {
+        names.iter().map(|name| self.insert_context(name)).collect()
+    }
+
+    fn insert_coverage_sample(
+        &mut self,
+        mut sample: models::CoverageSample,
+    ) -> Result<models::CoverageSample> {
+        sample.local_sample_id = self.id_sequence.next().unwrap();
+        self.report.samples.push(sample.clone());
+        Ok(sample)
+    }
+
+    fn multi_insert_coverage_sample(
+        &mut self,
+        samples: Vec<&mut models::CoverageSample>,
+    ) -> Result<()> {
+        for sample in samples {
+            *sample = self.insert_coverage_sample(sample.clone())?;
+        }
+        Ok(())
+    }
+
+    fn multi_update_coverage_sample(&mut self, samples: &[models::CoverageSample]) -> Result<()> {
+        let indices = samples
+            .iter()
+            .map(|sample| {
+                self.report
+                    .samples
+                    .iter()
+                    .position(|s| {
+                        (s.raw_upload_id, s.local_sample_id)
+                            == (sample.raw_upload_id, sample.local_sample_id)
+                    })
+                    .ok_or_else(|| {
+                        CodecovError::ReportBuilderError(format!(
+                            "no sample to update for {sample}"
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.snapshot();
+        for (index, sample) in indices.into_iter().zip(samples) {
+            let existing = &mut self.report.samples[index];
+            existing.coverage_type = sample.coverage_type;
+            existing.hits = sample.hits;
+            existing.hit_branches = sample.hit_branches;
+            existing.total_branches = sample.total_branches;
+            existing.messages = sample.messages.clone();
+        }
+        Ok(())
+    }
+
+    fn insert_branches_data(
+        &mut self,
+        mut branch: models::BranchesData,
+    ) -> Result<models::BranchesData> {
+        branch.local_branch_id = self.id_sequence.next().unwrap();
+        self.report.branches.push(branch.clone());
+        Ok(branch)
+    }
+
+    fn multi_insert_branches_data(
+        &mut self,
+        branches: Vec<&mut models::BranchesData>,
+    ) -> Result<()> {
+        for branch in branches {
+            *branch = self.insert_branches_data(branch.clone())?;
+        }
+        Ok(())
+    }
+
+    fn insert_method_data(&mut self, mut method: models::MethodData) -> Result<models::MethodData> {
+        method.local_method_id = self.id_sequence.next().unwrap();
+        self.report.methods.push(method.clone());
+        Ok(method)
+    }
+
+    fn multi_insert_method_data(&mut self, methods: Vec<&mut models::MethodData>) -> Result<()> {
+        for method in methods {
+            *method = self.insert_method_data(method.clone())?;
+        }
+        Ok(())
+    }
+
+    fn insert_span_data(&mut self, mut span: models::SpanData) -> Result<models::SpanData> {
+        span.local_span_id = self.id_sequence.next().unwrap();
+        self.report.spans.push(span.clone());
+        Ok(span)
+    }
+
+    fn multi_insert_span_data(&mut self, spans: Vec<&mut models::SpanData>) -> Result<()> {
+        for span in spans {
+            *span = self.insert_span_data(span.clone())?;
+        }
+        Ok(())
+    }
+
+    fn associate_context(&mut self, assoc: models::ContextAssoc) -> Result<models::ContextAssoc> {
+        self.report.assocs.push(assoc.clone());
+        Ok(assoc)
+    }
+
+    fn multi_associate_context(&mut self, assocs: Vec<&mut models::ContextAssoc>) -> Result<()> {
+        self.report
+            .assocs
+            .extend(assocs.into_iter().map(|assoc| assoc.clone()));
+        Ok(())
+    }
+
+    fn insert_upload_tag(&mut self, tag: models::UploadTag) -> Result<models::UploadTag> {
+        self.report.push_tag(tag.clone())?;
+        Ok(tag)
+    }
+
+    fn multi_insert_session_file_totals(
+        &mut self,
+        totals: &[models::SessionFileTotals],
+    ) -> Result<()> {
+        // Like a single `INSERT`, insert all of them or none
+        let len = self.report.session_file_totals.len();
+        for totals in totals {
+            if let Err(e) = self.report.push_session_file_totals(totals.clone()) {
+                self.report.session_file_totals.truncate(len);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn multi_insert_line_attributes(&mut self, attributes: &[models::LineAttribute]) -> Result<()> {
+        if attributes
+            .iter()
+            .any(|attribute| self.report.line_attribute_mut(attribute).is_some())
+        {
+            self.snapshot();
+        }
+        for attribute in attributes {
+            self.report.add_line_attribute(attribute.clone());
+        }
+        Ok(())
+    }
+
+    fn insert_raw_upload(
+        &mut self,
+        mut raw_upload: models::RawUpload,
+    ) -> Result<models::RawUpload> {
+        let mut bytes = self.upload_id_seed.to_le_bytes().to_vec();
+        bytes.extend(self.uploads_inserted.to_le_bytes());
+        self.uploads_inserted += 1;
+        raw_upload.id = seahash::hash(&bytes) as i64;
+        self.report.push_upload(raw_upload.clone())?;
+        Ok(raw_upload)
+    }
+
+    fn update_raw_upload_url(
+        &mut self,
+        raw_upload_id: i64,
+        raw_upload_url: Option<&str>,
+    ) -> Result<()> {
+        self.snapshot();
+        if let Ok(upload) = self.upload_mut(raw_upload_id) {
+            upload.raw_upload_url = raw_upload_url.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    fn update_upload_state(
+        &mut self,
+        raw_upload_id: i64,
+        state: models::UploadState,
+    ) -> Result<()> {
+        let upload = self.upload_mut(raw_upload_id)?;
+        if let Some(current) = upload.upload_state() {
+            if !current.can_transition_to(state) {
+                return Err(CodecovError::ReportBuilderError(format!(
+                    "upload {raw_upload_id} can't go from {} to {}",
+                    current.as_str(),
+                    state.as_str()
+                )));
+            }
+        }
+        self.snapshot();
+        self.upload_mut(raw_upload_id)?.state = Some(state.as_str().to_string());
+        Ok(())
+    }
+
+    fn insert_raw_upload_idempotent(
+        &mut self,
+        raw_upload: models::RawUpload,
+        on_duplicate: DuplicateUploadPolicy,
+    ) -> Result<Option<models::RawUpload>> {
+        let Some(external_id) = &raw_upload.external_id else {
+            return self.insert_raw_upload(raw_upload).map(Some);
+        };
+        let existing = self
+            .report
+            .uploads
+            .iter()
+            .find(|upload| upload.external_id.as_ref() == Some(external_id))
+            .map(|upload| upload.id);
+
+        match (existing, on_duplicate) {
+            (None, _) => {}
+            (Some(_), DuplicateUploadPolicy::Skip) => return Ok(None),
+            (Some(existing_id), DuplicateUploadPolicy::Replace) => {
+                self.snapshot();
+                self.report.delete_raw_upload(existing_id);
+            }
+        }
+        self.insert_raw_upload(raw_upload).map(Some)
+    }
+
+    /// There's nobody to read the old generation while the new one is
+    /// inserted, so it's deleted right away.
+    fn supersede_upload(&mut self, raw_upload_id: i64) -> Result<i64> {
+        self.upload_mut(raw_upload_id)?;
+        self.snapshot();
+        self.report.delete_measurements(raw_upload_id);
+        let generation = self.report.generations.entry(raw_upload_id).or_default();
+        *generation += 1;
+        Ok(*generation)
+    }
+
+    fn savepoint(&mut self) -> Result<()> {
+        self.savepoints.push(Savepoint {
+            lens: self.report.lens(),
+            snapshot: None,
+        });
+        Ok(())
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        self.savepoints.pop().ok_or_else(|| {
+            CodecovError::ReportBuilderError("no savepoint to release".to_string())
+        })?;
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        let savepoint = self.savepoints.pop().ok_or_else(|| {
+            CodecovError::ReportBuilderError("no savepoint to roll back to".to_string())
+        })?;
+        match savepoint.snapshot {
+            Some(snapshot) => self.report = snapshot,
+            None => self.report.truncate(savepoint.lens),
+        }
+        Ok(())
+    }
+
+    /// Open savepoints are released.
+    fn build(self) -> Result<MemoryReport> {
+        Ok(self.report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::report::summary::SummaryCounts;
+
+    fn sample(
+        raw_upload_id: i64,
+        file: &models::SourceFile,
+        line_no: i64,
+        hits: i64,
+    ) -> models::CoverageSample {
+        models::CoverageSample {
+            raw_upload_id,
+            source_file_id: file.id,
+            line_no,
+            coverage_type: models::CoverageType::Line,
+            hits: Some(hits),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_and_query() {
+        let mut builder = MemoryReportBuilder::new();
+        let upload = builder
+            .insert_raw_upload(models::RawUpload {
+                flags: Some(json!(["unit"])),
+                ..Default::default()
+            })
+            .unwrap();
+        let b_file = builder.insert_file("src/b.rs").unwrap();
+        let a_file = builder.insert_file("src/a.rs").unwrap();
+        assert!(matches!(
+            builder.insert_file("src/a.rs"),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+        let context = builder.insert_context("test_a").unwrap();
+
+        let line_2 = builder
+            .insert_coverage_sample(sample(upload.id, &a_file, 2, 0))
+            .unwrap();
+        let line_1 = builder
+            .insert_coverage_sample(sample(upload.id, &a_file, 1, 3))
+            .unwrap();
+        let _ = builder
+            .insert_coverage_sample(sample(upload.id, &b_file, 1, 1))
+            .unwrap();
+        let _ = builder
+            .associate_context(models::ContextAssoc {
+                context_id: context.id,
+                raw_upload_id: upload.id,
+                local_sample_id: Some(line_1.local_sample_id),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let report = builder.build().unwrap();
+        assert_eq!(
+            report.list_files().unwrap(),
+            vec![a_file.clone(), b_file.clone()]
+        );
+        assert_eq!(
+            report.list_samples_for_file(&a_file).unwrap(),
+            vec![line_1.clone(), line_2]
+        );
+        assert_eq!(
+            report.list_samples_for_context(&context).unwrap(),
+            vec![line_1.clone()]
+        );
+        assert_eq!(
+            report.list_files_for_context(&context).unwrap(),
+            vec![a_file.clone()]
+        );
+        assert_eq!(
+            report.samples_for_line(&a_file, 1).unwrap(),
+            vec![(line_1, upload)]
+        );
+
+        let totals = report.totals().unwrap();
+        assert_eq!(totals.files, 2);
+        assert_eq!(totals.coverage.total_lines, 3);
+        assert_eq!(totals.coverage.hit_lines, 2);
+
+        let counts = SummaryCounts {
+            files: 2,
+            lines: 3,
+            hits: 2,
+            misses: 1,
+            ..Default::default()
+        };
+        let summary = report.summary().unwrap();
+        assert_eq!(summary.totals, counts);
+        assert_eq!(summary.flags["unit"], counts);
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let mut builder = MemoryReportBuilder::new();
+        let upload = builder
+            .insert_raw_upload(models::RawUpload::default())
+            .unwrap();
+        let file = builder.insert_file("src/a.rs").unwrap();
+
+        builder.savepoint().unwrap();
+        let _ = builder
+            .insert_coverage_sample(sample(upload.id, &file, 1, 1))
+            .unwrap();
+        builder.savepoint().unwrap();
+        builder
+            .update_upload_state(upload.id, models::UploadState::Processed)
+            .unwrap();
+        let _ = builder.insert_file("src/b.rs").unwrap();
+        builder.rollback_to_savepoint().unwrap();
+        builder.release_savepoint().unwrap();
+        assert!(builder.rollback_to_savepoint().is_err());
+
+        // The file inserted after the inner savepoint can be inserted again
+        let _ = builder.insert_file("src/b.rs").unwrap();
+
+        let report = builder.build().unwrap();
+        assert_eq!(report.list_coverage_samples().unwrap().len(), 1);
+        assert_eq!(report.list_raw_uploads().unwrap()[0].state, None);
+        assert_eq!(report.list_files().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_update_coverage_sample() {
+        let mut builder = MemoryReportBuilder::new();
+        let upload = builder
+            .insert_raw_upload(models::RawUpload::default())
+            .unwrap();
+        let file = builder.insert_file("src/a.rs").unwrap();
+        let mut line = builder
+            .insert_coverage_sample(sample(upload.id, &file, 1, 0))
+            .unwrap();
+
+        // Updates are rolled back with the savepoint they were made in
+        builder.savepoint().unwrap();
+        line.hits = Some(4);
+        builder.update_coverage_sample(&line).unwrap();
+        builder.rollback_to_savepoint().unwrap();
+        line.hits = Some(2);
+        builder.update_coverage_sample(&line).unwrap();
+
+        let missing = models::CoverageSample {
+            local_sample_id: line.local_sample_id + 1,
+            ..line.clone()
+        };
+        assert!(matches!(
+            builder.multi_update_coverage_sample(&[line.clone(), missing]),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+
+        let report = builder.build().unwrap();
+        assert_eq!(report.list_coverage_samples().unwrap(), vec![line]);
+        assert_eq!(report.totals().unwrap().coverage.hit_lines, 1);
+    }
+
+    #[test]
+    fn test_supersede_upload() {
+        let mut builder = MemoryReportBuilder::new();
+        let upload = builder
+            .insert_raw_upload(models::RawUpload::default())
+            .unwrap();
+        let file = builder.insert_file("src/a.rs").unwrap();
+        let _ = builder
+            .insert_coverage_sample(sample(upload.id, &file, 1, 0))
+            .unwrap();
+
+        builder.savepoint().unwrap();
+        assert_eq!(builder.supersede_upload(upload.id).unwrap(), 1);
+        builder.rollback_to_savepoint().unwrap();
+
+        assert_eq!(builder.supersede_upload(upload.id).unwrap(), 1);
+        let _ = builder
+            .insert_coverage_sample(sample(upload.id, &file, 1, 2))
+            .unwrap();
+        assert_eq!(builder.supersede_upload(upload.id).unwrap(), 2);
+        let line = builder
+            .insert_coverage_sample(sample(upload.id, &file, 1, 5))
+            .unwrap();
+        assert!(matches!(
+            builder.supersede_upload(upload.id + 1),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+
+        let report = builder.build().unwrap();
+        assert_eq!(report.list_coverage_samples().unwrap(), vec![line]);
+    }
+
+    #[test]
+    fn test_merge_default_builders() {
+        let build = |hits| {
+            let mut builder = MemoryReportBuilder::default();
+            let upload = builder
+                .insert_raw_upload(models::RawUpload::default())
+                .unwrap();
+            let file = builder.insert_file("src/a.rs").unwrap();
+            let _ = builder
+                .insert_coverage_sample(sample(upload.id, &file, 1, hits))
+                .unwrap();
+            builder.build().unwrap()
+        };
+
+        let mut report = build(1);
+        report.merge(&build(0), MergePolicy::SumHits).unwrap();
+        assert_eq!(report.list_raw_uploads().unwrap().len(), 2);
+        assert_eq!(report.list_coverage_samples().unwrap().len(), 2);
+        assert_eq!(report.totals().unwrap().coverage.hit_lines, 1);
+    }
+
+    #[test]
+    fn test_merge_recounts_branches() {
+        // Two copies of one upload that each hit a different branch of line 1
+        let build = |branch_hits: [i64; 2]| {
+            let mut builder = MemoryReportBuilder::with_upload_id_seed(0);
+            let upload = builder
+                .insert_raw_upload(models::RawUpload::default())
+                .unwrap();
+            let file = builder.insert_file("src/a.rs").unwrap();
+            let line = builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no: 1,
+                    coverage_type: models::CoverageType::Branch,
+                    hit_branches: Some(1),
+                    total_branches: Some(2),
+                    ..Default::default()
+                })
+                .unwrap();
+            for (i, hits) in branch_hits.into_iter().enumerate() {
+                let _ = builder
+                    .insert_branches_data(models::BranchesData {
+                        raw_upload_id: upload.id,
+                        source_file_id: file.id,
+                        local_sample_id: line.local_sample_id,
+                        hits,
+                        branch_format: models::BranchFormat::Condition,
+                        branch: i.to_string(),
+                        ..Default::default()
+                    })
+                    .unwrap();
+            }
+            builder.build().unwrap()
+        };
+
+        for policy in [MergePolicy::SumHits, MergePolicy::MaxHits] {
+            let mut report = build([1, 0]);
+            report.merge(&build([0, 1]), policy).unwrap();
+            let samples = report.list_coverage_samples().unwrap();
+            assert_eq!(samples.len(), 1);
+            assert_eq!(samples[0].hit_branches, Some(2));
+            assert_eq!(samples[0].total_branches, Some(2));
+            let hits: Vec<i64> = report.branches.iter().map(|branch| branch.hits).collect();
+            assert_eq!(hits, vec![1, 1]);
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_matches_sqlite_report() {
+        use tempfile::TempDir;
+
+        use crate::{
+            parsers::pyreport::{parse_pyreport_buffers, ParseOptions},
+            report::{SqliteReport, SqliteReportBuilder},
+        };
+
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../test_utils/fixtures/pyreport");
+        let report_json =
+            std::fs::read(fixtures.join("codecov-rs-reports-json-d2a9ba1.txt")).unwrap();
+        let chunks =
+            std::fs::read_to_string(fixtures.join("codecov-rs-chunks-d2a9ba1.txt")).unwrap();
+        let options = ParseOptions::default();
+
+        let parse_memory = |seed| {
+            parse_pyreport_buffers(
+                &report_json,
+                &chunks,
+                MemoryReportBuilder::with_upload_id_seed(seed),
+                &options,
+            )
+            .unwrap()
+            .0
+            .build()
+            .unwrap()
+        };
+        let temp_dir = TempDir::new().unwrap();
+        let parse_sqlite = |name: &str| {
+            let mut builder = SqliteReportBuilder::open(temp_dir.path().join(name)).unwrap();
+            let _ = parse_pyreport_buffers(
+                &report_json,
+                &chunks,
+                builder.transaction().unwrap(),
+                &options,
+            )
+            .unwrap();
+            builder.build().unwrap()
+        };
+        let assert_same = |memory: &MemoryReport, sqlite: &SqliteReport| {
+            assert_eq!(memory.list_files().unwrap(), sqlite.list_files().unwrap());
+            assert_eq!(
+                memory.list_contexts().unwrap(),
+                sqlite.list_contexts().unwrap()
+            );
+            let files = sqlite.list_files().unwrap();
+            let lines: Vec<_> = files
+                .iter()
+                .flat_map(|file| (1..=50).map(|line_no| (file.path.as_str(), line_no)))
+                .collect();
+            assert_eq!(
+                memory.list_contexts_for_lines(&lines).unwrap(),
+                sqlite.list_contexts_for_lines(&lines).unwrap()
+            );
+            assert_eq!(memory.totals().unwrap(), sqlite.totals().unwrap());
+            assert_eq!(
+                memory.totals_by_coverage_type().unwrap(),
+                sqlite.totals_by_coverage_type().unwrap()
+            );
+            assert_eq!(memory.summary().unwrap(), sqlite.summary().unwrap());
+            assert_eq!(
+                memory.list_file_totals().unwrap(),
+                sqlite.list_file_totals().unwrap()
+            );
+            // Upload IDs differ between the two, so uploads are in a
+            // different order
+            let upload_totals = |totals: Vec<(models::RawUpload, models::CoverageTotals)>| {
+                let mut totals: Vec<_> = totals
+                    .into_iter()
+                    .map(|(_, totals)| format!("{totals:?}"))
+                    .collect();
+                totals.sort();
+                totals
+            };
+            assert_eq!(
+                upload_totals(memory.list_upload_totals().unwrap()),
+                upload_totals(sqlite.list_upload_totals().unwrap())
+            );
+            for file in sqlite.list_files().unwrap() {
+                assert_eq!(
+                    memory.file_totals(&file).unwrap(),
+                    sqlite.file_totals(&file).unwrap()
+                );
+                // Upload IDs differ between the two, but not how many there are
+                assert_eq!(
+                    memory.list_session_file_totals(&file).unwrap().len(),
+                    sqlite.list_session_file_totals(&file).unwrap().len()
+                );
+            }
+        };
+
+        let mut memory = parse_memory(0);
+        let mut sqlite = parse_sqlite("a.sqlite");
+        assert_same(&memory, &sqlite);
+
+        // Merging a different upload of the same data
+        memory
+            .merge(&parse_memory(1), MergePolicy::KeepBoth)
+            .unwrap();
+        sqlite
+            .merge(&parse_sqlite("b.sqlite"), MergePolicy::KeepBoth)
+            .unwrap();
+        assert_same(&memory, &sqlite);
+
+        // Merging a copy of a report collides every sample
+        for policy in [
+            MergePolicy::SumHits,
+            MergePolicy::MaxHits,
+            MergePolicy::PreferNewest,
+        ] {
+            let mut memory = parse_memory(0);
+            memory.merge(&parse_memory(0), policy).unwrap();
+            let mut sqlite = parse_sqlite(&format!("{policy:?}.sqlite"));
+            let copy = temp_dir.path().join(format!("{policy:?}-copy.sqlite"));
+            std::fs::copy(&sqlite.filename, &copy).unwrap();
+            sqlite
+                .merge(&SqliteReport::open(copy).unwrap(), policy)
+                .unwrap();
+            assert_same(&memory, &sqlite);
+        }
+    }
+}
@@ -1,18 +1,91 @@
 pub mod models;
 
+pub mod annotate;
+pub mod checks;
+pub mod components;
+pub mod construct;
+pub mod label_pruning;
+pub mod limits;
+pub mod line_coverage;
+pub mod ordering;
+pub mod summary;
+pub mod tee;
+pub mod timeseries;
+
+pub use annotate::{annotate, Annotation, AnnotationKind, FileDiff};
+#[cfg(feature = "sqlite")]
+pub use construct::from_samples;
+pub use construct::{insert_samples, SampleSpec};
+pub use label_pruning::LabelPruning;
+pub use limits::{LimitedReportBuilder, Limits};
+pub use tee::TeeReportBuilder;
+#[cfg(feature = "sqlite")]
 pub mod sqlite;
+#[cfg(feature = "sqlite")]
 pub use sqlite::{SqliteReport, SqliteReportBuilder, SqliteReportBuilderTx};
 
+#[cfg(feature = "wasm")]
+pub mod memory;
+#[cfg(feature = "wasm")]
+pub use memory::{MemoryReport, MemoryReportBuilder};
+
 #[cfg(feature = "pyreport")]
 pub mod pyreport;
 
+use std::ops::Range;
+
+use ordering::{ContextOrder, FileOrder, SampleOrder, UploadOrder};
+
 use crate::error::Result;
 
+/// What [`ReportBuilder::insert_raw_upload_idempotent`] does when the report
+/// already has an upload with the same
+/// [`external_id`](models::RawUpload::external_id), e.g. because a worker
+/// crashed partway through ingesting it and is retrying.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum DuplicateUploadPolicy {
+    /// Keep the existing upload and its data.
+    #[default]
+    Skip,
+    /// Delete the existing upload and all of its data, then insert the new
+    /// upload in its place.
+    Replace,
+}
+
+/// What [`Report::merge`] does when both reports have a sample for the same
+/// line and [`models::CoverageType`] from the same upload, e.g. because a
+/// partial retry of the upload was ingested into a separate report.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum MergePolicy {
+    /// Keep both samples.
+    #[default]
+    KeepBoth,
+    /// Keep one sample whose hits are the sum of both. Branch hits are summed
+    /// per branch.
+    SumHits,
+    /// Keep one sample with the higher hits and branch counts of the two.
+    MaxHits,
+    /// Keep the sample from whichever copy of the upload has the later
+    /// [`timestamp`](models::RawUpload::timestamp). A missing timestamp counts
+    /// as the oldest, and ties keep the sample already in this report.
+    PreferNewest,
+}
+
 /// An interface for coverage data.
+///
+/// Every `list_*` method returns its results in a fixed order, documented on
+/// the method, so output is the same each time a report is read. Unless noted
+/// otherwise, files are ordered by path, contexts by name, uploads by ID, and
+/// samples by upload and then the order they were inserted in. The
+/// `list_*_ordered` methods take an explicit sort key instead.
 pub trait Report {
+    /// Lists every file, ordered by path.
     fn list_files(&self) -> Result<Vec<models::SourceFile>>;
+    /// Lists every context, ordered by name.
     fn list_contexts(&self) -> Result<Vec<models::Context>>;
+    /// Lists every sample, ordered by upload and then insertion order.
     fn list_coverage_samples(&self) -> Result<Vec<models::CoverageSample>>;
+    /// Lists the branches recorded for `sample`, in insertion order.
     fn list_branches_for_sample(
         &self,
         sample: &models::CoverageSample,
@@ -21,25 +94,162 @@ pub trait Report {
         &self,
         sample: &models::CoverageSample,
     ) -> Result<Option<models::MethodData>>;
+    /// Lists the spans recorded for `sample`, in insertion order.
     fn list_spans_for_sample(
         &self,
         sample: &models::CoverageSample,
     ) -> Result<Vec<models::SpanData>>;
+    /// Lists the contexts associated with `sample`, ordered by name.
     fn list_contexts_for_sample(
         &self,
         sample: &models::CoverageSample,
     ) -> Result<Vec<models::Context>>;
+    /// Lists the samples in `file`, ordered by line and then by upload and
+    /// insertion order.
     fn list_samples_for_file(
         &self,
         file: &models::SourceFile,
     ) -> Result<Vec<models::CoverageSample>>;
+    /// Lists the samples for line `line_no` of `file` from every upload, each
+    /// alongside the upload it came from. Ordered by upload and then
+    /// insertion order.
+    fn samples_for_line(
+        &self,
+        file: &models::SourceFile,
+        line_no: i64,
+    ) -> Result<Vec<(models::CoverageSample, models::RawUpload)>>;
+    /// Lists the samples associated with `context`, e.g. the lines a test
+    /// executed.
+    fn list_samples_for_context(
+        &self,
+        context: &models::Context,
+    ) -> Result<Vec<models::CoverageSample>>;
+    /// Lists the files with any samples associated with `context`.
+    fn list_files_for_context(&self, context: &models::Context) -> Result<Vec<models::SourceFile>>;
+    /// Lists the contexts associated with `file` as a whole by any upload.
+    /// Contexts associated with only some of its samples aren't included.
+    fn list_contexts_for_file(&self, file: &models::SourceFile) -> Result<Vec<models::Context>>;
+    /// Lists the contexts associated with `raw_upload` as a whole, such as
+    /// contexts standing in for its flags.
+    fn list_contexts_for_upload(
+        &self,
+        raw_upload: &models::RawUpload,
+    ) -> Result<Vec<models::Context>>;
+    /// Lists the contexts associated with any sample on the given lines, e.g.
+    /// the tests to run for a change. Each line is a file path and a line
+    /// number. Ordered by name.
+    fn list_contexts_for_lines(&self, lines: &[(&str, i64)]) -> Result<Vec<models::Context>>;
+    /// Lists the [`models::MethodData`]s in `file` alongside the
+    /// [`models::CoverageSample`] each was declared on, which holds its hits.
+    /// Ordered by line.
+    fn list_methods_for_file(
+        &self,
+        file: &models::SourceFile,
+    ) -> Result<Vec<(models::MethodData, models::CoverageSample)>>;
+    /// Lists every upload, ordered by ID.
     fn list_raw_uploads(&self) -> Result<Vec<models::RawUpload>>;
+    /// Lists the uploads whose payload is stored at `raw_upload_url`.
+    fn list_raw_uploads_for_url(&self, raw_upload_url: &str) -> Result<Vec<models::RawUpload>>;
+    /// Lists the uploads whose [`models::RawUpload::upload_state`] is `state`.
+    fn list_uploads_by_state(&self, state: models::UploadState) -> Result<Vec<models::RawUpload>>;
+    /// Lists the uploads whose [`timestamp`](models::RawUpload::timestamp)
+    /// falls in `range` of Unix seconds, ordered by timestamp and then ID.
+    /// Uploads without a timestamp are never listed.
+    fn list_uploads_in_range(&self, range: Range<i64>) -> Result<Vec<models::RawUpload>>;
+    /// Lists the [`models::UploadTag`]s attached to `raw_upload`, ordered by
+    /// key.
+    fn list_tags_for_upload(
+        &self,
+        raw_upload: &models::RawUpload,
+    ) -> Result<Vec<models::UploadTag>>;
+    /// Lists the uploads tagged with `key` set to `value`, ordered by ID.
+    fn list_uploads_by_tag(&self, key: &str, value: &str) -> Result<Vec<models::RawUpload>>;
+    /// Lists the [`models::SessionFileTotals`] recorded for `file`, ordered by
+    /// upload ID.
+    fn list_session_file_totals(
+        &self,
+        file: &models::SourceFile,
+    ) -> Result<Vec<models::SessionFileTotals>>;
+    /// Lists the [`models::LineAttribute`]s recorded for `file`, ordered by
+    /// line and then upload ID.
+    fn list_line_attributes(&self, file: &models::SourceFile)
+        -> Result<Vec<models::LineAttribute>>;
+
+    /// Lists every file, sorted by `order`.
+    fn list_files_ordered(&self, order: FileOrder) -> Result<Vec<models::SourceFile>> {
+        let mut files = self.list_files()?;
+        ordering::sort_files(&mut files, order);
+        Ok(files)
+    }
+
+    /// Lists every context, sorted by `order`.
+    fn list_contexts_ordered(&self, order: ContextOrder) -> Result<Vec<models::Context>> {
+        let mut contexts = self.list_contexts()?;
+        ordering::sort_contexts(&mut contexts, order);
+        Ok(contexts)
+    }
+
+    /// Lists every sample, sorted by `order`.
+    fn list_coverage_samples_ordered(
+        &self,
+        order: SampleOrder,
+    ) -> Result<Vec<models::CoverageSample>> {
+        let mut samples = self.list_coverage_samples()?;
+        ordering::sort_samples(self, &mut samples, order)?;
+        Ok(samples)
+    }
+
+    /// Lists every upload, sorted by `order`.
+    fn list_raw_uploads_ordered(&self, order: UploadOrder) -> Result<Vec<models::RawUpload>> {
+        let mut uploads = self.list_raw_uploads()?;
+        ordering::sort_uploads(&mut uploads, order);
+        Ok(uploads)
+    }
+
+    /// Looks up the [`models::SourceFile`] at `path`, including whatever
+    /// metadata we have for it. Returns `None` if the report has no such file.
+    fn get_file_metadata(&self, path: &str) -> Result<Option<models::SourceFile>>;
+
+    /// Looks up the [`models::SourceFile`] that was parsed from the pyreport
+    /// chunk at `chunk_index`. If several files have that index, e.g. after
+    /// merging reports, returns the one with the lowest ID.
+    fn file_for_chunk_index(&self, chunk_index: i64) -> Result<Option<models::SourceFile>>;
+
+    /// Looks up the index of the pyreport chunk `file` was parsed from.
+    /// Returns `None` if it wasn't parsed from a pyreport.
+    fn chunk_index_for_file(&self, file: &models::SourceFile) -> Result<Option<i64>>;
+
+    /// Lists samples whose line number is past the end of their file. Files
+    /// without a known [`models::SourceFile::line_count`] are never flagged.
+    fn list_out_of_bounds_samples(&self) -> Result<Vec<models::CoverageSample>>;
 
     /// Merges another report into this one. Does not modify the other report.
-    fn merge(&mut self, other: &Self) -> Result<()>;
+    /// `policy` decides what happens to samples both reports have for the
+    /// same line of the same upload.
+    fn merge(&mut self, other: &Self, policy: MergePolicy) -> Result<()>;
 
     /// Computes aggregated metrics for the data in the report.
     fn totals(&self) -> Result<models::ReportTotals>;
+
+    /// Computes aggregated coverage metrics for a single file.
+    fn file_totals(&self, file: &models::SourceFile) -> Result<models::CoverageTotals>;
+
+    /// Computes [`Report::file_totals`] for every file at once, ordered like
+    /// [`Report::list_files`]. Files without samples have all-zero totals.
+    fn list_file_totals(&self) -> Result<Vec<(models::SourceFile, models::CoverageTotals)>>;
+
+    /// Computes aggregated coverage metrics for each upload on its own,
+    /// ordered like [`Report::list_raw_uploads`]. Uploads without samples
+    /// have all-zero totals.
+    fn list_upload_totals(&self) -> Result<Vec<(models::RawUpload, models::CoverageTotals)>>;
+
+    /// Computes aggregated coverage metrics separately for line, branch and
+    /// method samples.
+    fn totals_by_coverage_type(&self) -> Result<models::CoverageTypeTotals>;
+
+    /// Summarizes the lines and branches covered by the whole report and by
+    /// the uploads with each flag.
+    fn summary(&self) -> Result<summary::ReportSummary>;
 }
 
 /// An interface for creating a new coverage report.
@@ -47,9 +257,18 @@ pub trait ReportBuilder<R: Report> {
     /// Create a [`models::SourceFile`] record and return it.
     fn insert_file(&mut self, path: &str) -> Result<models::SourceFile>;
 
+    /// Set the `language`, `content_hash`, `line_count` and `chunk_index` of
+    /// the existing [`models::SourceFile`] with `file.id`. Fields that are
+    /// `None` are left as they were.
+    fn update_file_metadata(&mut self, file: &models::SourceFile) -> Result<()>;
+
     /// Create a [`models::Context`] record and return it.
     fn insert_context(&mut self, name: &str) -> Result<models::Context>;
 
+    /// Create several [`models::Context`] records in one query and return
+    /// them in the same order as `names`.
+    fn multi_insert_context(&mut self, names: &[&str]) -> Result<Vec<models::Context>>;
+
     /// Create a [`models::CoverageSample`] record and return it. The passed-in
     /// model's `local_sample_id` field is ignored and overwritten with a value
     /// that is unique among all `CoverageSample`s with the same
@@ -68,6 +287,19 @@ pub trait ReportBuilder<R: Report> {
         samples: Vec<&mut models::CoverageSample>,
     ) -> Result<()>;
 
+    /// Replace the `coverage_type`, `hits`, `hit_branches`, `total_branches`
+    /// and `messages` of the existing [`models::CoverageSample`] with the same
+    /// `raw_upload_id` and `local_sample_id`, e.g. to repair or carry forward
+    /// its coverage. Its file and line can't be changed. Fails without
+    /// changing anything if there's no such sample.
+    fn update_coverage_sample(&mut self, sample: &models::CoverageSample) -> Result<()> {
+        self.multi_update_coverage_sample(std::slice::from_ref(sample))
+    }
+
+    /// Like [`ReportBuilder::update_coverage_sample`] for several samples at
+    /// once. Fails without changing anything if any of them don't exist.
+    fn multi_update_coverage_sample(&mut self, samples: &[models::CoverageSample]) -> Result<()>;
+
     /// Create a [`models::BranchesData`] record and return it. The passed-in
     /// model's `local_branch_id` field is ignored and overwritten with a value
     /// that is unique among all `BranchesData`s with the same `raw_upload_id`.
@@ -116,10 +348,77 @@ pub trait ReportBuilder<R: Report> {
     /// [`models::Context`]s with other models.
     fn multi_associate_context(&mut self, assocs: Vec<&mut models::ContextAssoc>) -> Result<()>;
 
+    /// Create a [`models::UploadTag`] record and return it. Fails if the
+    /// upload already has a tag with the same key.
+    fn insert_upload_tag(&mut self, tag: models::UploadTag) -> Result<models::UploadTag>;
+
+    /// Create many [`models::SessionFileTotals`] records. Fails if a file
+    /// already has totals for the same upload.
+    fn multi_insert_session_file_totals(
+        &mut self,
+        totals: &[models::SessionFileTotals],
+    ) -> Result<()>;
+
+    /// Record [`models::LineAttribute`]s. Attributes for a line that already
+    /// has some for the same upload are added to them.
+    fn multi_insert_line_attributes(&mut self, attributes: &[models::LineAttribute]) -> Result<()>;
+
     /// Create a [`models::RawUpload`] record and return it.
     fn insert_raw_upload(&mut self, upload_details: models::RawUpload)
         -> Result<models::RawUpload>;
 
+    /// Set the [`models::RawUpload::raw_upload_url`] of the upload with ID
+    /// `raw_upload_id`, e.g. after its payload was moved in archive storage.
+    fn update_raw_upload_url(
+        &mut self,
+        raw_upload_id: i64,
+        raw_upload_url: Option<&str>,
+    ) -> Result<()>;
+
+    /// Move the upload with ID `raw_upload_id` to `state`. Fails without
+    /// changing anything if the upload doesn't exist or its current state
+    /// can't move to `state` (see [`models::UploadState`]). Uploads whose
+    /// state isn't an [`models::UploadState`] can move to any state.
+    fn update_upload_state(&mut self, raw_upload_id: i64, state: models::UploadState)
+        -> Result<()>;
+
+    /// Create a [`models::RawUpload`] record unless one with the same
+    /// [`models::RawUpload::external_id`] already exists, in which case
+    /// `on_duplicate` decides what happens. Returns `None` if the existing
+    /// upload was kept and the caller should not ingest its data again.
+    /// Uploads without an `external_id` are always inserted.
+    fn insert_raw_upload_idempotent(
+        &mut self,
+        upload_details: models::RawUpload,
+        on_duplicate: DuplicateUploadPolicy,
+    ) -> Result<Option<models::RawUpload>>;
+
+    /// Start a new generation of the upload with ID `raw_upload_id` so it can
+    /// be reprocessed in place. Its samples, branches, methods, spans and
+    /// context associations stop showing up in queries, and whatever is
+    /// inserted for the upload afterwards replaces them. Its per-session file
+    /// totals are deleted so they can be inserted again. The upload itself
+    /// and its tags are untouched. Returns the new generation, which starts
+    /// at 0 for a new upload.
+    ///
+    /// Insert the new generation in the same transaction to make the whole
+    /// reprocess atomic. [`SqliteReportBuilder`] keeps the old rows, marked as
+    /// superseded, until [`SqliteReport::purge_superseded`] deletes them;
+    /// other implementations may delete them right away.
+    fn supersede_upload(&mut self, raw_upload_id: i64) -> Result<i64>;
+
+    /// Mark a point that later changes can be undone back to with
+    /// [`ReportBuilder::rollback_to_savepoint`], e.g. to discard the partial
+    /// results of parsing a malformed chunk of input. Savepoints nest; the
+    /// other savepoint methods act on the most recent one.
+    fn savepoint(&mut self) -> Result<()>;
+
+    /// Keep the changes made since the most recent savepoint and forget it.
+    fn release_savepoint(&mut self) -> Result<()>;
+
+    /// Undo the changes made since the most recent savepoint and forget it.
+    fn rollback_to_savepoint(&mut self) -> Result<()>;
+
     /// Consume `self` and return a [`Report`].
     fn build(self) -> Result<R>;
 }
@@ -0,0 +1,152 @@
+//! Sort keys for the `list_*_ordered` methods on [`Report`]. The plain
+//! `list_*` methods each document the one order they return.
+
+use std::collections::HashMap;
+
+use super::{models, Report};
+use crate::error::Result;
+
+/// How [`Report::list_files_ordered`] sorts files.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum FileOrder {
+    /// By path. This is the order [`Report::list_files`] uses.
+    #[default]
+    Path,
+    /// By ID. IDs are hashes of paths, so this order is stable across
+    /// reports but otherwise meaningless.
+    Id,
+}
+
+/// How [`Report::list_contexts_ordered`] sorts contexts.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ContextOrder {
+    /// By name. This is the order [`Report::list_contexts`] uses.
+    #[default]
+    Name,
+    /// By ID, which is a hash of the name.
+    Id,
+}
+
+/// How [`Report::list_coverage_samples_ordered`] sorts samples.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum SampleOrder {
+    /// By upload, then by the order they were inserted in. This is the order
+    /// [`Report::list_coverage_samples`] uses.
+    #[default]
+    Id,
+    /// By the path of their file, then line, then upload and insertion order.
+    Location,
+}
+
+/// How [`Report::list_raw_uploads_ordered`] sorts uploads.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum UploadOrder {
+    /// By ID. This is the order [`Report::list_raw_uploads`] uses.
+    #[default]
+    Id,
+    /// By timestamp, then ID. Uploads without a timestamp come last.
+    Timestamp,
+}
+
+pub(crate) fn sort_files(files: &mut [models::SourceFile], order: FileOrder) {
+    match order {
+        FileOrder::Path => files.sort_by(|a, b| (&a.path, a.id).cmp(&(&b.path, b.id))),
+        FileOrder::Id => files.sort_by_key(|file| file.id),
+    }
+}
+
+pub(crate) fn sort_contexts(contexts: &mut [models::Context], order: ContextOrder) {
+    match order {
+        ContextOrder::Name => contexts.sort_by(|a, b| (&a.name, a.id).cmp(&(&b.name, b.id))),
+        ContextOrder::Id => contexts.sort_by_key(|context| context.id),
+    }
+}
+
+pub(crate) fn sort_samples<R: Report + ?Sized>(
+    report: &R,
+    samples: &mut [models::CoverageSample],
+    order: SampleOrder,
+) -> Result<()> {
+    match order {
+        SampleOrder::Id => {
+            samples.sort_by_key(|sample| (sample.raw_upload_id, sample.local_sample_id))
+        }
+        SampleOrder::Location => {
+            let paths: HashMap<i64, String> = report
+                .list_files()?
+                .into_iter()
+                .map(|file| (file.id, file.path))
+                .collect();
+            samples.sort_by_cached_key(|sample| {
+                (
+                    paths.get(&sample.source_file_id).cloned(),
+                    sample.line_no,
+                    sample.raw_upload_id,
+                    sample.local_sample_id,
+                )
+            });
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn sort_uploads(uploads: &mut [models::RawUpload], order: UploadOrder) {
+    match order {
+        UploadOrder::Id => uploads.sort_by_key(|upload| upload.id),
+        UploadOrder::Timestamp => {
+            uploads.sort_by_key(|upload| (upload.timestamp.is_none(), upload.timestamp, upload.id))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::test_utils::sqlite_report::build_sample_report;
+
+    #[test]
+    fn test_list_ordered() {
+        let temp_dir = TempDir::new().unwrap();
+        let report = build_sample_report(temp_dir.path().join("db.sqlite")).unwrap();
+
+        let files = report.list_files_ordered(FileOrder::Path).unwrap();
+        assert_eq!(files, report.list_files().unwrap());
+        assert!(files.windows(2).all(|pair| pair[0].path < pair[1].path));
+        let files = report.list_files_ordered(FileOrder::Id).unwrap();
+        assert!(files.windows(2).all(|pair| pair[0].id < pair[1].id));
+
+        let contexts = report.list_contexts_ordered(ContextOrder::Name).unwrap();
+        assert_eq!(contexts, report.list_contexts().unwrap());
+        assert!(contexts.windows(2).all(|pair| pair[0].name <= pair[1].name));
+        let contexts = report.list_contexts_ordered(ContextOrder::Id).unwrap();
+        assert!(contexts.windows(2).all(|pair| pair[0].id < pair[1].id));
+
+        let samples = report
+            .list_coverage_samples_ordered(SampleOrder::Id)
+            .unwrap();
+        assert_eq!(samples, report.list_coverage_samples().unwrap());
+        let samples = report
+            .list_coverage_samples_ordered(SampleOrder::Location)
+            .unwrap();
+        let path = |sample: &models::CoverageSample| {
+            &files
+                .iter()
+                .find(|file| file.id == sample.source_file_id)
+                .unwrap()
+                .path
+        };
+        assert!(samples
+            .windows(2)
+            .all(|pair| (path(&pair[0]), pair[0].line_no) <= (path(&pair[1]), pair[1].line_no)));
+
+        let uploads = report.list_raw_uploads_ordered(UploadOrder::Id).unwrap();
+        assert_eq!(uploads, report.list_raw_uploads().unwrap());
+        assert!(uploads.windows(2).all(|pair| pair[0].id < pair[1].id));
+        let uploads = report
+            .list_raw_uploads_ordered(UploadOrder::Timestamp)
+            .unwrap();
+        assert_eq!(uploads.len(), 2);
+    }
+}
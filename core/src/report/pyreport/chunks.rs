@@ -1,46 +1,26 @@
-use std::io::Write;
+use std::{io::Write, iter::Peekable, num::NonZeroUsize};
 
+use rusqlite::{Connection, OpenFlags};
 use serde_json::json;
 
-use super::{CHUNKS_FILE_END_OF_CHUNK, CHUNKS_FILE_HEADER_TERMINATOR};
+use super::format::{
+    self, strip_trailing_nulls, CHUNKS_FILE_END_OF_CHUNK, CHUNKS_FILE_HEADER_TERMINATOR,
+};
 use crate::{
     error::{CodecovError, Result},
     parsers::json::{JsonNumber, JsonVal},
-    report::{models, sqlite::json_value_from_sql, SqliteReport},
+    report::{
+        models,
+        sqlite::{json_value_from_sql, StatementCacheStats, StatementCounters},
+        SqliteReport,
+    },
 };
 
-/// To save space, trailing nulls are removed from arrays in `ReportLine`s.
-///
-/// Examples:
-/// ```notrust
-/// // These two lines are equivalent
-/// [0, null, [[0, 1, null, null, null]], null, null, null]
-/// [0, null, [[0, 1]]]
-///
-/// // If a later field is present, as in the following example, no nulls may be removed
-/// [0, "m", [[0, 1, null, null, [0, 1]]], null, null, [[0, 1, null, ["label"]]]]
-/// ```
-fn array_without_trailing_nulls(mut json_array: JsonVal) -> JsonVal {
-    let JsonVal::Array(vals) = &mut json_array else {
-        return json_array;
-    };
-
-    for (i, val) in vals.iter().enumerate().rev() {
-        if val != &JsonVal::Null {
-            return JsonVal::Array(vals[0..=i].into());
-        }
-    }
-    // If we get out of the for loop, there were no non-null elements
-    json!([])
-}
-
 /// The chunks file header contains a "labels index" mapping of a numeric ID to
 /// a string label name. `queries/chunks_file_header.sql` builds the whole index
 /// into a JSON object and we just have to deserialize it.
 fn query_chunks_file_header(report: &SqliteReport) -> Result<JsonVal> {
-    let mut stmt = report
-        .conn
-        .prepare_cached(include_str!("queries/chunks_file_header.sql"))?;
+    let mut stmt = report.prepare_cached(include_str!("queries/chunks_file_header.sql"))?;
     Ok(stmt.query_row([], |row| row.get(0).and_then(|s| json_value_from_sql(s, 0)))?)
 }
 
@@ -66,7 +46,7 @@ fn maybe_write_current_line(
         // Every line is preceded by, but not followed by, a newline. When starting a
         // new chunk, the cursor will be at the end of the header object on the
         // line before data is supposed to start.
-        write!(output, "\n{}", array_without_trailing_nulls(line_values))?;
+        write!(output, "\n{}", strip_trailing_nulls(line_values))?;
         Ok(line_no)
     } else {
         // We don't have a new line to print. Return the old value for
@@ -75,54 +55,6 @@ fn maybe_write_current_line(
     }
 }
 
-/// The coverage field in a report line can be an integer, representing a hit
-/// count, or a string representation of a fraction where the numerator is the
-/// number of branches that were covered and the denominator is the total number
-/// of possible branches.
-fn format_coverage(
-    hits: &Option<i64>,
-    hit_branches: &Option<i64>,
-    total_branches: &Option<i64>,
-) -> Result<JsonVal> {
-    match (hits, hit_branches, total_branches) {
-        (Some(hits), _, _) => Ok(JsonVal::Number(JsonNumber::from(*hits))),
-        (_, Some(hit_branches), Some(total_branches)) => Ok(JsonVal::String(format!(
-            "{}/{}",
-            hit_branches, total_branches
-        ))),
-        _ => Err(CodecovError::PyreportConversionError(
-            "incomplete coverage data".to_string(),
-        )),
-    }
-}
-
-/// Method coverage has type `"m"` and branch coverage has type `"b"`. Line
-/// coverage is serialized as `null`, which unfortunately is also what gets
-/// serialized when coverage type is omitted entirely.
-fn format_coverage_type(coverage_type: &models::CoverageType) -> JsonVal {
-    match coverage_type {
-        models::CoverageType::Line => JsonVal::Null,
-        models::CoverageType::Branch => JsonVal::String("b".to_string()),
-        models::CoverageType::Method => JsonVal::String("m".to_string()),
-    }
-}
-
-/// Complexity is written as either a single integer, representing the total
-/// cyclomatic complexity of a method, or a list containing exactly two
-/// integers, the first being the number of "complexity paths" hit and the
-/// second being the total complexity.
-fn format_complexity(
-    hit_complexity_paths: &Option<i64>,
-    total_complexity: &Option<i64>,
-) -> JsonVal {
-    match (hit_complexity_paths, total_complexity) {
-        (Some(hit_paths), Some(total)) => json!([hit_paths, total]),
-        (None, Some(total)) => json!(total),
-        (Some(hit_paths), None) => json!(hit_paths),
-        (None, None) => JsonVal::Null,
-    }
-}
-
 /// The data for a single report line in a chunk is spread across multiple rows
 /// in the results of `queries/samples_to_chunks.rs`. However, every row
 /// contains a copy of certain aggregate metrics for a line. This helper
@@ -139,12 +71,16 @@ fn build_report_line_from_row(row: &rusqlite::Row) -> Result<(i64, JsonVal)> {
     let hit_complexity_paths = row.get::<usize, Option<i64>>(6)?;
     let total_complexity = row.get::<usize, Option<i64>>(7)?;
 
-    let coverage = format_coverage(&hits, &hit_branches, &total_branches)?;
-    let coverage_type_json = format_coverage_type(&coverage_type);
-    let complexity = format_complexity(&hit_complexity_paths, &total_complexity);
+    let coverage = format::coverage(hits, hit_branches, total_branches)?;
+    let coverage_type_json = format::coverage_type(&coverage_type);
+    let complexity = format::complexity(hit_complexity_paths, total_complexity);
+    let messages = match row.get(17)? {
+        Some(messages) => json_value_from_sql(messages, 17)?,
+        None => JsonVal::Null,
+    };
     Ok((
         line_no,
-        json!([coverage, coverage_type_json, [], null, complexity, null]),
+        json!([coverage, coverage_type_json, [], messages, complexity, null]),
     ))
 }
 
@@ -154,14 +90,14 @@ fn build_report_line_from_row(row: &rusqlite::Row) -> Result<(i64, JsonVal)> {
 /// this helper function returns the JSON value that will be written for them.
 fn build_line_session_from_row(row: &rusqlite::Row) -> Result<JsonVal> {
     let session_index = row.get::<usize, i64>(8)?;
-    let hits = row.get(10)?;
-    let hit_branches = row.get(11)?;
-    let total_branches = row.get(12)?;
-    let hit_complexity_paths = row.get(13)?;
-    let total_complexity = row.get(14)?;
+    let hits = row.get(9)?;
+    let hit_branches = row.get(10)?;
+    let total_branches = row.get(11)?;
+    let hit_complexity_paths = row.get(12)?;
+    let total_complexity = row.get(13)?;
 
-    let coverage = format_coverage(&hits, &hit_branches, &total_branches)?;
-    let complexity = format_complexity(&hit_complexity_paths, &total_complexity);
+    let coverage = format::coverage(hits, hit_branches, total_branches)?;
+    let complexity = format::complexity(hit_complexity_paths, total_complexity);
 
     let mut line_session_values = vec![
         JsonVal::Number(JsonNumber::from(session_index)),
@@ -172,18 +108,16 @@ fn build_line_session_from_row(row: &rusqlite::Row) -> Result<JsonVal> {
     ];
 
     // both of these are json
-    if let Some(missing_branches) = row.get(15)? {
-        line_session_values[2] = json_value_from_sql(missing_branches, 15)?;
+    if let Some(missing_branches) = row.get(14)? {
+        line_session_values[2] = json_value_from_sql(missing_branches, 14)?;
     }
 
-    if let Some(partials) = row.get(16)? {
-        line_session_values[3] = json_value_from_sql(partials, 16)?;
+    if let Some(partials) = row.get(15)? {
+        line_session_values[3] = json_value_from_sql(partials, 15)?;
     }
 
     // This probably does unnecessary copies
-    Ok(array_without_trailing_nulls(JsonVal::Array(
-        line_session_values,
-    )))
+    Ok(strip_trailing_nulls(JsonVal::Array(line_session_values)))
 }
 
 /// The primary source for a report line's per-session metrics is its `sessions`
@@ -195,20 +129,20 @@ fn build_line_session_from_row(row: &rusqlite::Row) -> Result<JsonVal> {
 /// there are no labels.
 fn build_datapoint_from_row(row: &rusqlite::Row) -> Result<Option<JsonVal>> {
     let session_index = row.get::<usize, i64>(8)?;
-    let labels_raw = row.get::<usize, Option<String>>(17)?;
+    let labels_raw = row.get::<usize, Option<String>>(16)?;
     if let Some(labels_raw) = labels_raw {
         let coverage_type = row.get::<usize, models::CoverageType>(2)?;
-        let hits = row.get::<usize, Option<i64>>(10)?;
-        let hit_branches = row.get::<usize, Option<i64>>(11)?;
-        let total_branches = row.get::<usize, Option<i64>>(12)?;
+        let hits = row.get::<usize, Option<i64>>(9)?;
+        let hit_branches = row.get::<usize, Option<i64>>(10)?;
+        let total_branches = row.get::<usize, Option<i64>>(11)?;
 
-        let coverage = format_coverage(&hits, &hit_branches, &total_branches)?;
-        let coverage_type_json = format_coverage_type(&coverage_type);
+        let coverage = format::coverage(hits, hit_branches, total_branches)?;
+        let coverage_type_json = format::coverage_type(&coverage_type);
         Ok(Some(json!([
             session_index,
             coverage,
             coverage_type_json,
-            json_value_from_sql(labels_raw, 17)?
+            json_value_from_sql(labels_raw, 16)?
         ])))
     } else {
         Ok(None)
@@ -218,19 +152,143 @@ fn build_datapoint_from_row(row: &rusqlite::Row) -> Result<Option<JsonVal>> {
 /// Builds a chunks file from a [`SqliteReport`] and writes it to `output_file`.
 /// See [`crate::report::pyreport`] for more details about the content and
 /// structure of a chunks file.
-pub fn sql_to_chunks(report: &SqliteReport, output: &mut impl Write) -> Result<()> {
+///
+/// With more than one thread, the chunks are split into contiguous ranges and
+/// each range is serialized on its own thread into a separate buffer. The
+/// buffers are written to `output` in order once every thread is done. Each
+/// thread opens its own read-only connection to `report.filename`, so anything
+/// not yet committed to `report.conn` won't be serialized. Statements prepared
+/// on those connections are still counted in the report's
+/// [`SqliteReport::statement_cache_stats`].
+pub fn sql_to_chunks(
+    report: &SqliteReport,
+    output: &mut impl Write,
+    threads: NonZeroUsize,
+) -> Result<()> {
     let chunks_file_header = query_chunks_file_header(report)?;
     write!(
         output,
         "{chunks_file_header}{CHUNKS_FILE_HEADER_TERMINATOR}"
     )?;
 
-    // TODO: query from chunk_indices rather than samples in case there are chunks
-    // with no samples?
-    let mut stmt = report
-        .conn
-        .prepare_cached(include_str!("queries/samples_to_chunks.sql"))?;
-    let mut rows = stmt.query([])?;
+    let chunk_count: i64 =
+        report
+            .conn
+            .query_row("SELECT count(*) FROM source_file", [], |row| row.get(0))?;
+    let threads = (threads.get() as i64).min(chunk_count);
+    if threads <= 1 {
+        write_chunk_range(&report.conn, &report.statement_cache, 0, i64::MAX, output)?;
+        return Ok(());
+    }
+
+    let chunks_per_thread = (chunk_count + threads - 1) / threads;
+    let filename = &report.filename;
+    let buffers = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let first_chunk = i * chunks_per_thread;
+                let last_chunk = first_chunk + chunks_per_thread - 1;
+                scope.spawn(move || -> Result<(Vec<u8>, StatementCacheStats)> {
+                    let conn = Connection::open_with_flags(
+                        filename,
+                        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                    )?;
+                    let statement_cache = StatementCounters::default();
+                    let mut buffer = Vec::new();
+                    write_chunk_range(
+                        &conn,
+                        &statement_cache,
+                        first_chunk,
+                        last_chunk,
+                        &mut buffer,
+                    )?;
+                    Ok((buffer, statement_cache.stats()))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("chunk serialization thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    // Each buffer starts with a chunk header rather than a delimiter, so add
+    // one between (non-empty) buffers.
+    let mut first_buffer = true;
+    for (buffer, stats) in &buffers {
+        report.statement_cache.add(*stats);
+        if buffer.is_empty() {
+            continue;
+        }
+        if !first_buffer {
+            write!(output, "{CHUNKS_FILE_END_OF_CHUNK}")?;
+        }
+        output.write_all(buffer)?;
+        first_buffer = false;
+    }
+
+    Ok(())
+}
+
+/// Queries the header of each chunk with an index in
+/// `first_chunk..=last_chunk`, in order. Each header lists the sessions with
+/// data for the chunk's file. See `queries/chunk_headers.sql`.
+fn query_chunk_headers(
+    conn: &Connection,
+    statement_cache: &StatementCounters,
+    first_chunk: i64,
+    last_chunk: i64,
+) -> Result<Vec<(i64, JsonVal)>> {
+    let mut stmt =
+        statement_cache.prepare_cached(conn, include_str!("queries/chunk_headers.sql"))?;
+    let headers = stmt
+        .query_map([first_chunk, last_chunk], |row| {
+            let present_sessions = row.get(1).and_then(|s| json_value_from_sql(s, 1))?;
+            Ok((row.get(0)?, json!({"present_sessions": present_sessions})))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(headers)
+}
+
+/// Writes the headers of every chunk in `headers` up to and including
+/// `chunk_index`, each preceded by the `END_OF_CHUNK` delimiter if a chunk was
+/// written before it. Chunks before `chunk_index` have no lines.
+fn write_chunk_headers(
+    headers: &mut Peekable<impl Iterator<Item = (i64, JsonVal)>>,
+    chunk_index: i64,
+    wrote_chunk: &mut bool,
+    output: &mut impl Write,
+) -> Result<()> {
+    while let Some((_, header)) = headers.next_if(|(index, _)| *index <= chunk_index) {
+        let delimiter = if *wrote_chunk {
+            CHUNKS_FILE_END_OF_CHUNK
+        } else {
+            ""
+        };
+        write!(output, "{delimiter}{header}")?;
+        *wrote_chunk = true;
+    }
+    Ok(())
+}
+
+/// Writes the chunks with indices in `first_chunk..=last_chunk` to `output`.
+/// The first chunk written is not preceded by the `END_OF_CHUNK` delimiter.
+/// Chunks for files without samples are written with only a header.
+fn write_chunk_range(
+    conn: &Connection,
+    statement_cache: &StatementCounters,
+    first_chunk: i64,
+    last_chunk: i64,
+    output: &mut impl Write,
+) -> Result<()> {
+    let mut headers = query_chunk_headers(conn, statement_cache, first_chunk, last_chunk)?
+        .into_iter()
+        .peekable();
+    let mut wrote_chunk = false;
+
+    let mut stmt =
+        statement_cache.prepare_cached(conn, include_str!("queries/samples_to_chunks.sql"))?;
+    let mut rows = stmt.query([first_chunk, last_chunk])?;
 
     let mut current_chunk: Option<i64> = None;
     let mut last_populated_line = 0;
@@ -257,23 +315,7 @@ pub fn sql_to_chunks(report: &SqliteReport, output: &mut impl Write) -> Result<(
             current_report_line = Some(build_report_line_from_row(row)?);
 
             if is_new_chunk {
-                // Each chunk has a header which may contain a list of sessions that have
-                // measurements for lines in that chunk.
-                let present_sessions = row.get(9).and_then(|s| json_value_from_sql(s, 9))?;
-
-                // The first chunk should not be preceded by the `END_OF_CHUNK` header but all
-                // others should be.
-                let delimiter = if current_chunk.is_none() {
-                    ""
-                } else {
-                    CHUNKS_FILE_END_OF_CHUNK
-                };
-                write!(
-                    output,
-                    "{delimiter}{}",
-                    json!({"present_sessions": present_sessions})
-                )?;
-
+                write_chunk_headers(&mut headers, chunk_index, &mut wrote_chunk, output)?;
                 current_chunk = Some(chunk_index);
                 last_populated_line = 0;
             }
@@ -307,6 +349,8 @@ pub fn sql_to_chunks(report: &SqliteReport, output: &mut impl Write) -> Result<(
     // There are no rows following the last line, so we have to manually write
     // it here.
     maybe_write_current_line(current_report_line, output, last_populated_line)?;
+    // Along with any chunks after the last one with lines
+    write_chunk_headers(&mut headers, last_chunk, &mut wrote_chunk, output)?;
 
     Ok(())
 }
@@ -317,7 +361,15 @@ mod tests {
     use tempfile::TempDir;
 
     use super::*;
-    use crate::test_utils::sqlite_report::build_sample_report;
+    use crate::{
+        report::{
+            from_samples, MergePolicy, Report, ReportBuilder, SampleSpec, SqliteReportBuilder,
+        },
+        test_utils::{
+            generator::{generate_sqlite_report, GeneratorConfig},
+            sqlite_report::build_sample_report,
+        },
+    };
 
     struct Ctx {
         temp_dir: TempDir,
@@ -329,20 +381,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_array_without_trailing_nulls() {
-        assert_eq!(
-            array_without_trailing_nulls(json!([1, null, [[0, 1]]])),
-            json!([1, null, [[0, 1]]])
-        );
-        assert_eq!(
-            array_without_trailing_nulls(json!([1, null, [[0, 1]], null, null])),
-            json!([1, null, [[0, 1]]])
-        );
-        assert_eq!(array_without_trailing_nulls(json!([])), json!([]));
-        assert_eq!(array_without_trailing_nulls(json!([null])), json!([]));
-    }
-
     #[test]
     fn test_build_datapoint_from_row() {
         let ctx = setup();
@@ -399,7 +437,7 @@ mod tests {
                 Some(json!([0, 3, "m", ["label1", "label2"]])),
             ),
         ];
-        let query = "select 0, 1, ?1, 3, 4, 5, 6, 7, ?2, 9, ?3, ?4, ?5, 13, 14, 15, 16, ?6";
+        let query = "select 0, 1, ?1, 3, 4, 5, 6, 7, ?2, ?3, ?4, ?5, 12, 13, 14, 15, ?6";
         for test_case in test_cases {
             assert_eq!(
                 report
@@ -484,7 +522,7 @@ mod tests {
                 json!([0, 3, null, [[0, 3, 3], [4, 5, 0]]]),
             ),
         ];
-        let query = "select 0, 1, 2, 3, 4, 5, 6, 7, ?1, 9, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 17";
+        let query = "select 0, 1, 2, 3, 4, 5, 6, 7, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 16";
         for test_case in test_cases {
             assert_eq!(
                 report
@@ -514,6 +552,7 @@ mod tests {
                     None::<Option<i64>>,
                     None::<Option<i64>>,
                     None::<Option<i64>>,
+                    None::<String>,
                 ],
                 (1, json!([3, null, [], null, null, null])),
             ),
@@ -526,6 +565,7 @@ mod tests {
                     None::<Option<i64>>,
                     Some(2),
                     Some(4),
+                    None::<String>,
                 ],
                 (2, json!([3, "m", [], null, [2, 4], null])),
             ),
@@ -538,11 +578,25 @@ mod tests {
                     Some(4),
                     None::<Option<i64>>,
                     None::<Option<i64>>,
+                    None::<String>,
                 ],
                 (3, json!(["2/4", "b", [], null, null, null])),
             ),
+            (
+                rusqlite::params![
+                    4,
+                    models::CoverageType::Line,
+                    Some(1),
+                    None::<Option<i64>>,
+                    None::<Option<i64>>,
+                    None::<Option<i64>>,
+                    None::<Option<i64>>,
+                    Some("{\"type\": \"warning\"}"),
+                ],
+                (4, json!([1, null, [], {"type": "warning"}, null, null])),
+            ),
         ];
-        let query = "select 0, ?1, ?2, ?3, ?4, ?5, ?6, ?7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17";
+        let query = "select 0, ?1, ?2, ?3, ?4, ?5, ?6, ?7, 8, 9, 10, 11, 12, 13, 14, 15, 16, ?8";
         for test_case in test_cases {
             assert_eq!(
                 report
@@ -556,60 +610,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_format_complexity() {
-        assert_eq!(format_complexity(&Some(2), &Some(4)), json!([2, 4]));
-        assert_eq!(format_complexity(&Some(2), &None), json!(2));
-        assert_eq!(format_complexity(&None, &Some(4)), json!(4));
-        assert_eq!(format_complexity(&None, &None), json!(null));
-    }
-
-    #[test]
-    fn test_format_coverage() {
-        // Good inputs
-        assert_eq!(format_coverage(&Some(3), &None, &None).unwrap(), json!(3));
-        assert_eq!(
-            format_coverage(&None, &Some(2), &Some(4)).unwrap(),
-            json!("2/4")
-        );
-
-        // Malformed
-        assert!(
-            format_coverage(&None, &Some(2), &None).is_err_and(|e| match e {
-                CodecovError::PyreportConversionError(s) => s == "incomplete coverage data",
-                _ => false,
-            })
-        );
-        assert!(
-            format_coverage(&None, &None, &Some(4)).is_err_and(|e| match e {
-                CodecovError::PyreportConversionError(s) => s == "incomplete coverage data",
-                _ => false,
-            })
-        );
-        assert!(
-            format_coverage(&None, &None, &None).is_err_and(|e| match e {
-                CodecovError::PyreportConversionError(s) => s == "incomplete coverage data",
-                _ => false,
-            })
-        );
-    }
-
-    #[test]
-    fn test_format_coverage_type() {
-        assert_eq!(
-            format_coverage_type(&models::CoverageType::Line),
-            json!(null)
-        );
-        assert_eq!(
-            format_coverage_type(&models::CoverageType::Branch),
-            json!("b")
-        );
-        assert_eq!(
-            format_coverage_type(&models::CoverageType::Method),
-            json!("m")
-        );
-    }
-
     #[test]
     fn test_maybe_write_current_line() {
         let test_cases: [(_, _, _, &[u8]); 6] = [
@@ -655,7 +655,7 @@ mod tests {
         let report = build_sample_report(ctx.temp_dir.path().join("db.sqlite")).unwrap();
 
         let mut chunks = Vec::new();
-        sql_to_chunks(&report, &mut chunks).unwrap();
+        sql_to_chunks(&report, &mut chunks, NonZeroUsize::MIN).unwrap();
         let chunks = String::from_utf8(chunks).unwrap();
 
         let chunks_header = json!({"labels_index": {"1": "test-case", "2": "test-case 2"}});
@@ -734,4 +734,114 @@ mod tests {
 
         assert_eq!(chunks, expected);
     }
+
+    #[test]
+    fn test_sql_to_chunks_present_sessions_after_merge() {
+        let ctx = setup();
+        let mut report = from_samples(
+            ctx.temp_dir.path().join("a.sqlite"),
+            &["src/a.rs", "src/empty.rs"],
+            &[models::RawUpload::default()],
+            &[SampleSpec::line(0, 0, 1, 1)],
+        )
+        .unwrap();
+
+        let mut builder = SqliteReportBuilder::open(ctx.temp_dir.path().join("b.sqlite")).unwrap();
+        let upload = builder
+            .insert_raw_upload(models::RawUpload::default())
+            .unwrap();
+        let b_file = builder.insert_file("src/b.rs").unwrap();
+        let _ = builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: b_file.id,
+                line_no: 2,
+                hits: Some(0),
+                ..Default::default()
+            })
+            .unwrap();
+        // A file the upload has no lines for but associated a context with
+        let c_file = builder.insert_file("src/c.rs").unwrap();
+        let context = builder.insert_context("test_c").unwrap();
+        let _ = builder
+            .associate_context(models::ContextAssoc {
+                context_id: context.id,
+                raw_upload_id: upload.id,
+                source_file_id: Some(c_file.id),
+                ..Default::default()
+            })
+            .unwrap();
+        let other = builder.build().unwrap();
+        report.merge(&other, MergePolicy::KeepBoth).unwrap();
+
+        let uploads = report.list_raw_uploads().unwrap();
+        let session = |id: i64| uploads.iter().position(|upload| upload.id == id).unwrap();
+        let other_session = session(upload.id);
+        let first_session = 1 - other_session;
+        let mut files = report.list_files().unwrap();
+        files.sort_by_key(|file| file.id);
+        let expected_headers: Vec<_> = files
+            .iter()
+            .map(|file| match file.path.as_str() {
+                "src/a.rs" => json!({"present_sessions": [first_session]}),
+                "src/b.rs" | "src/c.rs" => json!({"present_sessions": [other_session]}),
+                _ => json!({"present_sessions": []}),
+            })
+            .collect();
+
+        for threads in [1, 3] {
+            let mut chunks = Vec::new();
+            sql_to_chunks(&report, &mut chunks, NonZeroUsize::new(threads).unwrap()).unwrap();
+            let chunks = String::from_utf8(chunks).unwrap();
+            let (_, chunks) = chunks.split_once(CHUNKS_FILE_HEADER_TERMINATOR).unwrap();
+            let headers: Vec<JsonVal> = chunks
+                .split(CHUNKS_FILE_END_OF_CHUNK)
+                .map(|chunk| serde_json::from_str(chunk.lines().next().unwrap()).unwrap())
+                .collect();
+            assert_eq!(headers, expected_headers, "with {threads} threads");
+        }
+    }
+
+    #[test]
+    fn test_sql_to_chunks_threads() {
+        let ctx = setup();
+        let config = GeneratorConfig {
+            files: 25,
+            lines_per_file: 20,
+            sessions: 3,
+            labels: 4,
+            seed: 7,
+            ..Default::default()
+        };
+        let report =
+            generate_sqlite_report(&config, ctx.temp_dir.path().join("db.sqlite")).unwrap();
+
+        let mut expected = Vec::new();
+        sql_to_chunks(&report, &mut expected, NonZeroUsize::MIN).unwrap();
+
+        // Includes thread counts that don't evenly divide the chunks and more
+        // threads than chunks
+        for threads in [2, 3, 8, 100] {
+            let mut chunks = Vec::new();
+            sql_to_chunks(&report, &mut chunks, NonZeroUsize::new(threads).unwrap()).unwrap();
+            assert_eq!(
+                String::from_utf8(chunks).unwrap(),
+                String::from_utf8(expected.clone()).unwrap(),
+                "output differs with {threads} threads"
+            );
+        }
+
+        // Each thread prepares both statements on its own connection
+        let stats = report.statement_cache_stats();
+        let mut chunks = Vec::new();
+        sql_to_chunks(&report, &mut chunks, NonZeroUsize::new(2).unwrap()).unwrap();
+        assert_eq!(report.statement_cache_stats().misses, stats.misses + 4);
+
+        let sample_report = build_sample_report(ctx.temp_dir.path().join("sample.sqlite")).unwrap();
+        let mut expected = Vec::new();
+        sql_to_chunks(&sample_report, &mut expected, NonZeroUsize::MIN).unwrap();
+        let mut chunks = Vec::new();
+        sql_to_chunks(&sample_report, &mut chunks, NonZeroUsize::new(2).unwrap()).unwrap();
+        assert_eq!(chunks, expected);
+    }
 }
@@ -0,0 +1,327 @@
+//! Estimates how large [`ToPyreport`](super::ToPyreport) output will be
+//! without serializing anything, so callers can pick where to write it (e.g.
+//! memory or disk, a single upload or a multipart one) up front.
+//!
+//! The estimate is a linear model: `queries/estimate_pyreport_size.sql`
+//! counts the records and measures the strings that end up in the output,
+//! and each count is weighted by roughly how many bytes of JSON punctuation
+//! and formatting that kind of record is written with. The weights were
+//! calibrated against the serialized output of generated reports and the
+//! pyreport fixtures. Everything whose length varies, like paths, labels and
+//! hit counts, is measured rather than modeled, so the estimate holds up for
+//! unusual reports as long as their structure is ordinary.
+
+use crate::{error::Result, report::SqliteReport};
+
+/// How large the report JSON and chunks file written by
+/// [`ToPyreport`](super::ToPyreport) are expected to be.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct PyreportSizeEstimate {
+    pub report_json_bytes: u64,
+    pub chunks_bytes: u64,
+}
+
+impl PyreportSizeEstimate {
+    pub fn total_bytes(&self) -> u64 {
+        self.report_json_bytes + self.chunks_bytes
+    }
+}
+
+/// The columns of `queries/estimate_pyreport_size.sql`. They're all counts,
+/// but they're only used to compute estimates so they're read as floats.
+#[derive(Debug, Default)]
+struct ReportShape {
+    files: f64,
+    path_bytes: f64,
+    uploads: f64,
+    upload_bytes: f64,
+    file_sessions: f64,
+    labels: f64,
+    label_bytes: f64,
+    lines: f64,
+    line_slots: f64,
+    samples: f64,
+    coverage_bytes: f64,
+    message_bytes: f64,
+    methods: f64,
+    missing_branches: f64,
+    missing_branch_bytes: f64,
+    spans: f64,
+    datapoints: f64,
+    datapoint_lines: f64,
+    sample_labels: f64,
+    sample_label_bytes: f64,
+}
+
+impl<'a> TryFrom<&'a rusqlite::Row<'a>> for ReportShape {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &'a rusqlite::Row) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            files: row.get("files")?,
+            path_bytes: row.get("path_bytes")?,
+            uploads: row.get("uploads")?,
+            upload_bytes: row.get("upload_bytes")?,
+            file_sessions: row.get("file_sessions")?,
+            labels: row.get("labels")?,
+            label_bytes: row.get("label_bytes")?,
+            lines: row.get("lines")?,
+            line_slots: row.get("line_slots")?,
+            samples: row.get("samples")?,
+            coverage_bytes: row.get("coverage_bytes")?,
+            message_bytes: row.get("message_bytes")?,
+            methods: row.get("methods")?,
+            missing_branches: row.get("missing_branches")?,
+            missing_branch_bytes: row.get("missing_branch_bytes")?,
+            spans: row.get("spans")?,
+            datapoints: row.get("datapoints")?,
+            datapoint_lines: row.get("datapoint_lines")?,
+            sample_labels: row.get("sample_labels")?,
+            sample_label_bytes: row.get("sample_label_bytes")?,
+        })
+    }
+}
+
+/// The average number of digits in the indices `0..count`, which is how
+/// sessions and labels are referred to in the chunks file.
+fn average_index_digits(count: f64) -> f64 {
+    let mut digits = 0.0;
+    let mut start = 0.0;
+    let mut end = 10.0;
+    let mut width = 1.0;
+    while start < count {
+        digits += (count.min(end) - start) * width;
+        start = end;
+        end *= 10.0;
+        width += 1.0;
+    }
+    digits / count.max(1.0)
+}
+
+impl ReportShape {
+    fn report_json_bytes(&self) -> f64 {
+        let session_digits = average_index_digits(self.uploads);
+
+        // `{"files": {},"sessions": {}}`
+        let skeleton = 28.0;
+        // `"path": [0,[0,159,109,33,17,"68.55346",31,18,0,0,0,0,0],{...,"meta":{"
+        // session_count":1}},null],`
+        let files = self.path_bytes + self.files * 84.0;
+        // `"0":[0,159,109,33,17,"68.55346",31,18],`
+        let file_sessions = self.file_sessions * (34.0 + session_digits);
+        // `"0": {"N":...,"a":...,"t":[...]},`, with the values measured
+        // separately
+        let uploads = self.upload_bytes + self.uploads * (112.0 + session_digits);
+
+        skeleton + files + file_sessions + uploads
+    }
+
+    fn chunks_bytes(&self) -> f64 {
+        let session_digits = average_index_digits(self.uploads);
+        // Labels are numbered from 1
+        let label_digits = average_index_digits(self.labels + 1.0);
+        // Lines and datapoints repeat the coverage of one of their samples
+        let average_coverage_bytes = self.coverage_bytes / self.samples.max(1.0);
+
+        // `{}` or `{"labels_index":{"1":"label",...}}`, and the header
+        // terminator
+        let header = if self.labels > 0.0 {
+            46.0 + self.label_bytes + self.labels * (6.0 + label_digits)
+        } else {
+            29.0
+        };
+        // `{"present_sessions":[0,1]}` and the chunk terminator
+        let chunks = self.files * 49.0 + self.file_sessions * (1.0 + session_digits);
+        // One line break per line up to each file's last sample
+        let line_breaks = self.line_slots;
+        // `[1,null,[...]]`
+        let lines = self.lines * (9.0 + average_coverage_bytes);
+        // `[0,1],` in the line's sessions
+        let samples = self.samples * (4.0 + session_digits) + self.coverage_bytes;
+        // Missing branches are written as `,["0:jump",...]`, complexity as
+        // `,null,null,[2,4]` on both the session and the line, and partials
+        // as `,null,[[3,null,3]]`
+        let branches = self.missing_branch_bytes + self.missing_branches * 4.0;
+        let methods = self.methods * 24.0;
+        let spans = self.spans * 18.0;
+        let messages = self.message_bytes;
+        // `,null,null,[[0,1,null,["label",...]],...]` on lines with labels
+        let datapoints = self.datapoint_lines * 13.0
+            + self.datapoints * (10.0 + session_digits + average_coverage_bytes);
+        let labels = self.sample_label_bytes + self.sample_labels * 3.0;
+
+        header
+            + chunks
+            + line_breaks
+            + lines
+            + samples
+            + branches
+            + methods
+            + spans
+            + messages
+            + datapoints
+            + labels
+    }
+}
+
+impl SqliteReport {
+    /// Estimates how many bytes [`ToPyreport`](super::ToPyreport) would write
+    /// for this report. The estimate is usually within 10% of the actual
+    /// size. Only the queries behind the estimate are run, which takes a
+    /// fraction of the time serializing the report would.
+    pub fn estimate_pyreport_size(&self) -> Result<PyreportSizeEstimate> {
+        let mut stmt = self.prepare_cached(include_str!("queries/estimate_pyreport_size.sql"))?;
+        let shape: ReportShape = stmt.query_row([], |row| row.try_into())?;
+        Ok(PyreportSizeEstimate {
+            report_json_bytes: shape.report_json_bytes().round() as u64,
+            chunks_bytes: shape.chunks_bytes().round() as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        parsers::pyreport::parse_pyreport,
+        report::{pyreport::ToPyreport, ReportBuilder, SqliteReportBuilder},
+        test_utils::{
+            generator::{generate_sqlite_report, GeneratorConfig},
+            sqlite_report::build_sample_report,
+        },
+    };
+
+    /// Serializes `report` and returns the sizes of what was written.
+    fn actual_size(report: &SqliteReport, temp_dir: &TempDir) -> PyreportSizeEstimate {
+        let report_json_path = temp_dir.path().join("report_json.json");
+        let chunks_path = temp_dir.path().join("chunks.txt");
+        report
+            .to_pyreport(
+                &mut File::create(&report_json_path).unwrap(),
+                &mut File::create(&chunks_path).unwrap(),
+            )
+            .unwrap();
+        PyreportSizeEstimate {
+            report_json_bytes: std::fs::metadata(report_json_path).unwrap().len(),
+            chunks_bytes: std::fs::metadata(chunks_path).unwrap().len(),
+        }
+    }
+
+    fn assert_close(estimate: u64, actual: u64, tolerance: f64, what: &str) {
+        let error = (estimate as f64 - actual as f64).abs() / actual as f64;
+        assert!(
+            error <= tolerance,
+            "{what}: estimated {estimate} bytes, actually {actual} ({:.1}% off)",
+            error * 100.0
+        );
+    }
+
+    fn assert_estimate(report: &SqliteReport, temp_dir: &TempDir, tolerance: f64) {
+        let estimate = report.estimate_pyreport_size().unwrap();
+        let actual = actual_size(report, temp_dir);
+        assert_close(
+            estimate.report_json_bytes,
+            actual.report_json_bytes,
+            tolerance,
+            "report JSON",
+        );
+        assert_close(
+            estimate.chunks_bytes,
+            actual.chunks_bytes,
+            tolerance,
+            "chunks",
+        );
+    }
+
+    #[test]
+    fn test_estimate_empty_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let report = SqliteReportBuilder::open(temp_dir.path().join("db.sqlite"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let estimate = report.estimate_pyreport_size().unwrap();
+        let actual = actual_size(&report, &temp_dir);
+        // Only the skeleton of each is written, so the estimate is in the
+        // right ballpark but not proportionally close
+        assert!(
+            estimate
+                .report_json_bytes
+                .abs_diff(actual.report_json_bytes)
+                < 10
+        );
+        assert!(estimate.chunks_bytes.abs_diff(actual.chunks_bytes) < 40);
+    }
+
+    #[test]
+    fn test_estimate_sample_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let report = build_sample_report(temp_dir.path().join("db.sqlite")).unwrap();
+        // The sample report is tiny and has a bit of everything, so fixed
+        // costs that the model averages out are a bigger share of it
+        assert_estimate(&report, &temp_dir, 0.2);
+    }
+
+    #[test]
+    fn test_estimate_pyreport_fixture() {
+        let temp_dir = TempDir::new().unwrap();
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../test_utils/fixtures/pyreport");
+        let mut report_builder =
+            SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+        parse_pyreport(
+            &File::open(fixtures.join("codecov-rs-reports-json-d2a9ba1.txt")).unwrap(),
+            &File::open(fixtures.join("codecov-rs-chunks-d2a9ba1.txt")).unwrap(),
+            &mut report_builder,
+        )
+        .unwrap();
+        let report = report_builder.build().unwrap();
+        assert_estimate(&report, &temp_dir, 0.1);
+    }
+
+    #[test]
+    fn test_estimate_generated_reports() {
+        let configs = [
+            GeneratorConfig {
+                files: 20,
+                lines_per_file: 200,
+                sessions: 1,
+                labels: 0,
+                ..Default::default()
+            },
+            GeneratorConfig {
+                files: 10,
+                lines_per_file: 100,
+                sessions: 12,
+                labels: 0,
+                ..Default::default()
+            },
+            GeneratorConfig {
+                files: 10,
+                lines_per_file: 100,
+                sessions: 3,
+                labels: 50,
+                ..Default::default()
+            },
+            GeneratorConfig {
+                files: 5,
+                lines_per_file: 300,
+                sessions: 2,
+                labels: 500,
+                missing_branches: true,
+                ..Default::default()
+            },
+        ];
+        for (i, config) in configs.iter().enumerate() {
+            let temp_dir = TempDir::new().unwrap();
+            let report =
+                generate_sqlite_report(config, temp_dir.path().join(format!("{i}.sqlite")))
+                    .unwrap();
+            assert_estimate(&report, &temp_dir, 0.1);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Formatting rules that `shared` applies when it serializes report JSON and
+//! chunks files. Output from `ToPyreport` has to match them exactly or Python
+//! will see spurious differences between reports.
+//!
+//! Everything here is public so that other services that read or write
+//! pyreports can follow the same rules instead of re-implementing them.
+
+use serde_json::json;
+
+use super::percent;
+use crate::{
+    error::{CodecovError, Result},
+    parsers::json::{JsonNumber, JsonVal},
+    report::models,
+};
+
+/// Separates the chunks file's header, a JSON object with the labels index,
+/// from the first chunk. Written even if the header is empty.
+pub const CHUNKS_FILE_HEADER_TERMINATOR: &str = "\n<<<<< end_of_header >>>>>\n";
+
+/// Separates one chunk from the next. There's none after the last chunk.
+pub const CHUNKS_FILE_END_OF_CHUNK: &str = "\n<<<<< end_of_chunk >>>>>\n";
+
+/// The coverage field in a report line can be an integer, representing a hit
+/// count, or a string representation of a fraction where the numerator is the
+/// number of branches that were covered and the denominator is the total number
+/// of possible branches. See [`branches`].
+///
+/// Fails if there's neither a hit count nor both branch counts.
+pub fn coverage(
+    hits: Option<i64>,
+    hit_branches: Option<i64>,
+    total_branches: Option<i64>,
+) -> Result<JsonVal> {
+    match (hits, hit_branches, total_branches) {
+        (Some(hits), _, _) => Ok(JsonVal::Number(JsonNumber::from(hits))),
+        (_, Some(hit_branches), Some(total_branches)) => {
+            Ok(JsonVal::String(branches(hit_branches, total_branches)))
+        }
+        _ => Err(CodecovError::PyreportConversionError(
+            "incomplete coverage data".to_string(),
+        )),
+    }
+}
+
+/// Branch coverage is written as `"{hit}/{total}"`, e.g. `"1/2"`.
+pub fn branches(hit_branches: i64, total_branches: i64) -> String {
+    format!("{hit_branches}/{total_branches}")
+}
+
+/// The inverse of [`branches`]. Returns `None` if `value` isn't two
+/// non-negative integers separated by a `/`.
+pub fn parse_branches(value: &str) -> Option<(i64, i64)> {
+    let (hit_branches, total_branches) = value.split_once('/')?;
+    let parse = |count: &str| {
+        count
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| count.parse().ok())
+            .flatten()
+    };
+    Some((parse(hit_branches)?, parse(total_branches)?))
+}
+
+/// Method coverage has type `"m"` and branch coverage has type `"b"`. Line
+/// coverage, along with statement coverage exported as lines, is serialized
+/// as `null`, which unfortunately is also what gets serialized when coverage
+/// type is omitted entirely.
+pub fn coverage_type(coverage_type: &models::CoverageType) -> JsonVal {
+    match coverage_type {
+        models::CoverageType::Line | models::CoverageType::Statement => JsonVal::Null,
+        models::CoverageType::Branch => JsonVal::String("b".to_string()),
+        models::CoverageType::Method => JsonVal::String("m".to_string()),
+    }
+}
+
+/// Complexity is written as either a single integer, representing the total
+/// cyclomatic complexity of a method, or a list containing exactly two
+/// integers, the first being the number of "complexity paths" hit and the
+/// second being the total complexity.
+pub fn complexity(hit_complexity_paths: Option<i64>, total_complexity: Option<i64>) -> JsonVal {
+    match (hit_complexity_paths, total_complexity) {
+        (Some(hit_paths), Some(total)) => json!([hit_paths, total]),
+        (None, Some(total)) => json!(total),
+        (Some(hit_paths), None) => json!(hit_paths),
+        (None, None) => JsonVal::Null,
+    }
+}
+
+/// Python's `json` module writes whole `float`s like `1.0`, but `shared` casts
+/// those to `int` first so they come out as `1`. Anything with a fractional
+/// part stays a float.
+pub fn number(value: f64) -> JsonVal {
+    if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+        JsonVal::Number(JsonNumber::from(value as i64))
+    } else {
+        JsonNumber::from_f64(value).map_or(JsonVal::Null, JsonVal::Number)
+    }
+}
+
+/// To save space, trailing nulls are removed from arrays in `ReportLine`s.
+///
+/// Examples:
+/// ```notrust
+/// // These two lines are equivalent
+/// [0, null, [[0, 1, null, null, null]], null, null, null]
+/// [0, null, [[0, 1]]]
+///
+/// // If a later field is present, as in the following example, no nulls may be removed
+/// [0, "m", [[0, 1, null, null, [0, 1]]], null, null, [[0, 1, null, ["label"]]]]
+/// ```
+pub fn strip_trailing_nulls(json_array: JsonVal) -> JsonVal {
+    strip_trailing(json_array, |val| val.is_null())
+}
+
+/// `ReportTotals.to_database()` removes trailing `0`s and `"0"`s, which is how
+/// the per-session totals for a file are written. Nulls are kept.
+///
+/// ```notrust
+/// [0, 45, 45, 0, 0, "100", 0, 0, 0, 0, 0, 0, 0] -> [0, 45, 45, 0, 0, "100"]
+/// ```
+pub fn strip_trailing_zeros(json_array: JsonVal) -> JsonVal {
+    strip_trailing(json_array, |val| match val {
+        JsonVal::Number(n) => n.as_f64() == Some(0.0),
+        JsonVal::String(s) => s == "0",
+        _ => false,
+    })
+}
+
+fn strip_trailing(mut json_array: JsonVal, strip: impl Fn(&JsonVal) -> bool) -> JsonVal {
+    let JsonVal::Array(vals) = &mut json_array else {
+        return json_array;
+    };
+    let len = vals
+        .iter()
+        .rposition(|val| !strip(val))
+        .map_or(0, |i| i + 1);
+    vals.truncate(len);
+    json_array
+}
+
+/// Aggregated metrics in the layout of `shared`'s `ReportTotals`.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Totals {
+    pub files: i64,
+    pub lines: i64,
+    pub hits: i64,
+    pub misses: i64,
+    pub partials: i64,
+    pub branches: i64,
+    pub methods: i64,
+    pub sessions: i64,
+    pub hit_complexity_paths: i64,
+    pub total_complexity: i64,
+}
+
+impl Totals {
+    /// The full 13-element list written for file totals and session `"t"`
+    /// totals. `messages` and `diff` are always 0.
+    pub fn to_json(&self) -> JsonVal {
+        json!([
+            self.files,
+            self.lines,
+            self.hits,
+            self.misses,
+            self.partials,
+            percent::ratio(self.hits, self.lines),
+            self.branches,
+            self.methods,
+            0, // messages
+            self.sessions,
+            self.hit_complexity_paths,
+            self.total_complexity,
+            0, // diff
+        ])
+    }
+}
+
+/// Builds the `SessionTotalsArray` for a file: an object mapping each session
+/// index to that session's [`Totals`] for the file, with trailing zeros
+/// stripped, plus a `"meta"` key. `session_count` is one more than the highest
+/// session index, as it was when `shared` stored these as a list.
+///
+/// ```notrust
+/// {"0": [0, 45, 45, 0, 0, "100"], "meta": {"session_count": 1}}
+/// ```
+pub fn session_totals<'a>(sessions: impl IntoIterator<Item = (usize, &'a Totals)>) -> JsonVal {
+    let mut object = serde_json::Map::new();
+    let mut session_count = 0;
+    for (session_index, totals) in sessions {
+        session_count = session_count.max(session_index + 1);
+        object.insert(
+            session_index.to_string(),
+            strip_trailing_zeros(totals.to_json()),
+        );
+    }
+    object.insert("meta".to_string(), json!({"session_count": session_count}));
+    JsonVal::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage() {
+        // Good inputs
+        assert_eq!(coverage(Some(3), None, None).unwrap(), json!(3));
+        assert_eq!(coverage(None, Some(2), Some(4)).unwrap(), json!("2/4"));
+
+        // Malformed
+        for (hit_branches, total_branches) in [(Some(2), None), (None, Some(4)), (None, None)] {
+            assert!(
+                coverage(None, hit_branches, total_branches).is_err_and(|e| match e {
+                    CodecovError::PyreportConversionError(s) => s == "incomplete coverage data",
+                    _ => false,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_branches() {
+        assert_eq!(parse_branches(&branches(1, 2)), Some((1, 2)));
+        assert_eq!(parse_branches("0/12"), Some((0, 12)));
+        for malformed in ["", "1", "1/", "/2", "-1/2", "1/2/3", " 1/2", "1.0/2"] {
+            assert_eq!(parse_branches(malformed), None, "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn test_coverage_type() {
+        assert_eq!(coverage_type(&models::CoverageType::Line), json!(null));
+        assert_eq!(coverage_type(&models::CoverageType::Branch), json!("b"));
+        assert_eq!(coverage_type(&models::CoverageType::Method), json!("m"));
+        assert_eq!(coverage_type(&models::CoverageType::Statement), json!(null));
+    }
+
+    #[test]
+    fn test_complexity() {
+        assert_eq!(complexity(Some(2), Some(4)), json!([2, 4]));
+        assert_eq!(complexity(Some(2), None), json!(2));
+        assert_eq!(complexity(None, Some(4)), json!(4));
+        assert_eq!(complexity(None, None), json!(null));
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number(1.0), json!(1));
+        assert_eq!(number(0.0), json!(0));
+        assert_eq!(number(-3.0), json!(-3));
+        assert_eq!(number(1.5), json!(1.5));
+        assert_eq!(number(f64::NAN), json!(null));
+    }
+
+    #[test]
+    fn test_strip_trailing_nulls() {
+        assert_eq!(
+            strip_trailing_nulls(json!([1, null, [[0, 1]]])),
+            json!([1, null, [[0, 1]]])
+        );
+        assert_eq!(
+            strip_trailing_nulls(json!([1, null, [[0, 1]], null, null])),
+            json!([1, null, [[0, 1]]])
+        );
+        assert_eq!(strip_trailing_nulls(json!([1, 0, "0"])), json!([1, 0, "0"]));
+        assert_eq!(strip_trailing_nulls(json!([])), json!([]));
+        assert_eq!(strip_trailing_nulls(json!([null])), json!([]));
+        assert_eq!(
+            strip_trailing_nulls(json!("not an array")),
+            json!("not an array")
+        );
+    }
+
+    #[test]
+    fn test_strip_trailing_zeros() {
+        assert_eq!(
+            strip_trailing_zeros(json!([0, 45, 45, 0, 0, "100", 0, 0, 0, 0, 0, 0, 0])),
+            json!([0, 45, 45, 0, 0, "100"])
+        );
+        assert_eq!(
+            strip_trailing_zeros(json!([0, 5, 0, 5, 0, "0", 0])),
+            json!([0, 5, 0, 5])
+        );
+        assert_eq!(strip_trailing_zeros(json!([1, 0.0])), json!([1]));
+        assert_eq!(strip_trailing_zeros(json!([1, null, 0])), json!([1, null]));
+        assert_eq!(strip_trailing_zeros(json!([0, 0])), json!([]));
+    }
+
+    #[test]
+    fn test_totals_to_json() {
+        let totals = Totals {
+            files: 3,
+            lines: 19,
+            hits: 17,
+            misses: 1,
+            partials: 1,
+            branches: 2,
+            methods: 4,
+            sessions: 1,
+            hit_complexity_paths: 5,
+            total_complexity: 6,
+        };
+        assert_eq!(
+            totals.to_json(),
+            json!([3, 19, 17, 1, 1, "89.47368", 2, 4, 0, 1, 5, 6, 0])
+        );
+        assert_eq!(
+            Totals::default().to_json(),
+            json!([0, 0, 0, 0, 0, "100", 0, 0, 0, 0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn test_session_totals() {
+        let first = Totals {
+            lines: 45,
+            hits: 45,
+            ..Default::default()
+        };
+        let third = Totals {
+            lines: 5,
+            hits: 1,
+            misses: 4,
+            methods: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            session_totals([(0, &first), (2, &third)]),
+            json!({
+                "0": [0, 45, 45, 0, 0, "100"],
+                "2": [0, 5, 1, 4, 0, "20.00000", 0, 1],
+                "meta": {"session_count": 3}
+            })
+        );
+        assert_eq!(session_totals([]), json!({"meta": {"session_count": 0}}));
+    }
+}
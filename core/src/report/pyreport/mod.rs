@@ -11,9 +11,9 @@
  * [`shared/reports/types.py`](https://github.com/codecov/shared/blob/main/shared/reports/types.py),
  * and [`shared/utils/sessions.py`](https://github.com/codecov/shared/blob/main/shared/utils/sessions.py).
  *
- * Parsers that will build a [`SqliteReport`] from these parts live in
+ * Parsers that will build a report from these parts live in
  * [`crate::parsers::pyreport`] but code that will convert a
- * [`SqliteReport`] back into a Pyreport lives here.
+ * `SqliteReport` back into a Pyreport lives here.
  *
  * # Report JSON
  *
@@ -250,41 +250,143 @@
  * in this order. If `datapoints` is present, `messages` and `complexity`
  * must also be present, even if their values are just `null`.
  *
+ * [`format`](mod@format) has the terminators and the encodings for these
+ * fields, for code that reads or writes pyreports without going through a
+ * report.
+ *
  * Some particular Python types to look at to understand the chunks file:
  * - [`ReportLine`](https://github.com/codecov/shared/blob/f6c2c3852530192ab0c6b9fd0c0a800c2cbdb16f/shared/reports/types.py#L130)
  * - [`LineSession`](https://github.com/codecov/shared/blob/f6c2c3852530192ab0c6b9fd0c0a800c2cbdb16f/shared/reports/types.py#L76)
  * - [`CoverageDatapoint`](https://github.com/codecov/shared/blob/f6c2c3852530192ab0c6b9fd0c0a800c2cbdb16f/shared/reports/types.py#L98)
  */
 
+#[cfg(feature = "sqlite")]
 use std::{
     fs::File,
     io::{BufWriter, Write},
+    num::NonZeroUsize,
 };
 
+#[cfg(feature = "sqlite")]
 use super::SqliteReport;
+#[cfg(feature = "sqlite")]
 use crate::error::Result;
 
+#[cfg(feature = "sqlite")]
 mod chunks;
+#[cfg(feature = "sqlite")]
+mod estimate;
+#[doc(hidden)]
+pub mod format;
+pub(crate) mod percent;
+#[cfg(feature = "sqlite")]
 mod report_json;
+#[cfg(feature = "sqlite")]
+mod statements;
 pub mod types;
 
-pub(crate) const CHUNKS_FILE_HEADER_TERMINATOR: &str = "\n<<<<< end_of_header >>>>>\n";
-pub(crate) const CHUNKS_FILE_END_OF_CHUNK: &str = "\n<<<<< end_of_chunk >>>>>\n";
+#[cfg(feature = "sqlite")]
+pub use estimate::PyreportSizeEstimate;
+pub use format::{CHUNKS_FILE_END_OF_CHUNK, CHUNKS_FILE_HEADER_TERMINATOR};
+#[cfg(feature = "sqlite")]
+pub use statements::StatementExport;
+
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone)]
+pub struct PyreportOptions {
+    /// How many threads to serialize the chunks file with. Each thread opens
+    /// its own read-only connection to the report's database and serializes a
+    /// contiguous range of chunks. Threads beyond the number of available
+    /// cores only add overhead.
+    pub threads: NonZeroUsize,
+
+    /// If set, only the uploads with these IDs are exported, as if they were
+    /// the only ones in the report. Their sessions are numbered from 0 in the
+    /// order of their IDs.
+    pub raw_upload_ids: Option<Vec<i64>>,
+
+    /// Pyreports have no statements, so the statement samples an upload
+    /// recorded on a line are exported as one line sample, with hits picked
+    /// this way.
+    pub statements: StatementExport,
+}
+
+#[cfg(feature = "sqlite")]
+impl Default for PyreportOptions {
+    fn default() -> Self {
+        Self {
+            threads: NonZeroUsize::MIN,
+            raw_upload_ids: None,
+            statements: StatementExport::default(),
+        }
+    }
+}
 
+#[cfg(feature = "sqlite")]
 pub trait ToPyreport {
     /// Format and write the contents of a [`SqliteReport`] to
     /// `report_json_file` and `chunks_file`.
-    fn to_pyreport(&self, report_json_file: &mut File, chunks_file: &mut File) -> Result<()>;
+    fn to_pyreport(&self, report_json_file: &mut File, chunks_file: &mut File) -> Result<()> {
+        self.to_pyreport_with_options(report_json_file, chunks_file, &Default::default())
+    }
+
+    /// Like [`ToPyreport::to_pyreport`], but only exports the uploads whose
+    /// IDs are in `raw_upload_ids`. See [`PyreportOptions::raw_upload_ids`].
+    fn to_pyreport_filtered(
+        &self,
+        report_json_file: &mut File,
+        chunks_file: &mut File,
+        raw_upload_ids: &[i64],
+    ) -> Result<()> {
+        let options = PyreportOptions {
+            raw_upload_ids: Some(raw_upload_ids.to_vec()),
+            ..Default::default()
+        };
+        self.to_pyreport_with_options(report_json_file, chunks_file, &options)
+    }
+
+    /// Like [`ToPyreport::to_pyreport`], but configured by `options`.
+    fn to_pyreport_with_options(
+        &self,
+        report_json_file: &mut File,
+        chunks_file: &mut File,
+        options: &PyreportOptions,
+    ) -> Result<()>;
 }
 
+#[cfg(feature = "sqlite")]
 impl ToPyreport for SqliteReport {
-    fn to_pyreport(&self, report_json_file: &mut File, chunks_file: &mut File) -> Result<()> {
+    fn to_pyreport_with_options(
+        &self,
+        report_json_file: &mut File,
+        chunks_file: &mut File,
+        options: &PyreportOptions,
+    ) -> Result<()> {
+        // Sessions are numbered by their position among the report's uploads,
+        // so a filtered export is made from a copy without the others. Every
+        // session needs its own samples, and statements have to be merged
+        // into lines, too.
+        let has_statements = statements::has_statements(&self.conn)?;
+        if options.raw_upload_ids.is_some() || has_statements || self.has_collapsed_uploads()? {
+            let copy = self.export_snapshot(options.raw_upload_ids.as_deref(), |conn| {
+                if has_statements {
+                    statements::merge_statements(conn, options.statements)?;
+                }
+                Ok(())
+            })?;
+            let options = PyreportOptions {
+                raw_upload_ids: None,
+                ..options.clone()
+            };
+            return copy.to_pyreport_with_options(report_json_file, chunks_file, &options);
+        }
+
         let mut writer = BufWriter::new(report_json_file);
         report_json::sql_to_report_json(self, &mut writer)?;
         writer.flush()?;
 
         let mut writer = BufWriter::new(chunks_file);
-        chunks::sql_to_chunks(self, &mut writer)?;
+        chunks::sql_to_chunks(self, &mut writer, options.threads)?;
         writer.flush()?;
 
         Ok(())
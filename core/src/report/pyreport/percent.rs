@@ -0,0 +1,81 @@
+//! Coverage percentages as `shared.helpers.ratio` writes them. Both file
+//! totals in the report JSON and per-session totals go through [`ratio`], so
+//! the two can't drift apart.
+//!
+//! `shared` computes the percentage as a float, rounds it to 5 places with
+//! `round()` and formats it with `'%.5f'`. Both steps round the float's exact
+//! binary value half-to-even, which is also what Rust's `{:.5}` does, so the
+//! float is formatted once rather than rounded twice. The result is always 5
+//! decimal places unless the ratio is exactly 0 or 100, so a ratio that only
+//! rounds to 100 is `"100.00000"` and one that only rounds to 0 is
+//! `"0.00000"`.
+
+/// Formats `hits` out of `lines` as a percentage.
+///
+/// ```notrust
+/// ratio(16, 16) == "100"
+/// ratio(0, 16)  == "0"
+/// ratio(1, 3)   == "33.33333"
+/// ```
+///
+/// `hits == lines` is checked first, so a file with no lines is "100".
+pub fn ratio(hits: i64, lines: i64) -> String {
+    match (hits, lines) {
+        (h, l) if h == l => 100.to_string(),
+        (0, _) | (_, 0) => 0.to_string(),
+        (h, l) => format!("{:.5}", h as f64 / l as f64 * 100.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ratio() {
+        assert_eq!(ratio(0, 16), "0".to_string());
+        assert_eq!(ratio(4, 16), "25.00000".to_string());
+        assert_eq!(ratio(16, 16), "100".to_string());
+        assert_eq!(ratio(1, 3), "33.33333".to_string());
+        assert_eq!(ratio(2, 3), "66.66667".to_string());
+        assert_eq!(ratio(1, 8), "12.50000".to_string());
+        assert_eq!(ratio(17, 19), "89.47368".to_string());
+        assert_eq!(ratio(0, 0), "100".to_string());
+
+        // Should not occur in normal usage, just documenting the behavior
+        assert_eq!(ratio(-1, 8), "-12.50000".to_string());
+        assert_eq!(ratio(9, 8), "112.50000".to_string());
+        assert_eq!(ratio(3, 0), "0".to_string());
+    }
+
+    #[test]
+    fn test_ratio_matches_python() {
+        // Output of `shared.helpers.ratio` for each (hits, lines)
+        let cases = [
+            (0, 0, "100"),
+            (5, 0, "0"),
+            (0, 7, "0"),
+            (7, 7, "100"),
+            (1, 7, "14.28571"),
+            // Exact ties at the 6th decimal round half-to-even
+            (1, 256, "0.39062"),
+            (3, 256, "1.17188"),
+            (5, 2048, "0.24414"),
+            (1, 200000, "0.00050"),
+            (1, 300000, "0.00033"),
+            (1, 1000000, "0.00010"),
+            (999999, 1000000, "99.99990"),
+            (9999999, 10000000, "99.99999"),
+            (123456789, 987654321, "12.50000"),
+            (3, 2, "150.00000"),
+            (-1, 4, "-25.00000"),
+            // Only exact ratios of 0 and 100 are shortened
+            (99999999, 100000000, "100.00000"),
+            (1, 100000000, "0.00000"),
+            (1, 2000000000, "0.00000"),
+        ];
+        for (hits, lines, expected) in cases {
+            assert_eq!(ratio(hits, lines), expected, "ratio({hits}, {lines})");
+        }
+    }
+}
@@ -0,0 +1,56 @@
+-- The `present_sessions` header of each chunk in the requested range: the
+-- sessions whose upload has samples in the chunk's file or associated a context
+-- with the file as a whole. Every file gets a row, even one no session has data
+-- for, so that chunks without lines still take up their index.
+with session_indices as (
+select
+  row_number() over (order by raw_upload.id) - 1 as session_index,
+  raw_upload.id as raw_upload_id
+from
+  raw_upload
+),
+-- Must match the corresponding logic in `samples_to_chunks.sql`.
+source_file_indices as (
+select
+  row_number() over (order by source_file.id) - 1 as chunk_index,
+  source_file.id as source_file_id
+from
+  source_file
+),
+file_uploads as (
+select
+  coverage_sample.source_file_id,
+  coverage_sample.raw_upload_id
+from
+  coverage_sample
+where
+  coverage_sample.superseded = 0
+union
+select
+  context_assoc.source_file_id,
+  context_assoc.raw_upload_id
+from
+  context_assoc
+where
+  context_assoc.source_file_id is not null
+  and context_assoc.superseded = 0
+)
+select
+  source_file_indices.chunk_index,
+  json_group_array(session_indices.session_index order by session_indices.session_index) filter (where session_indices.session_index is not null) as present_sessions
+from
+  source_file_indices
+left join
+  file_uploads
+on
+  file_uploads.source_file_id = source_file_indices.source_file_id
+left join
+  session_indices
+on
+  session_indices.raw_upload_id = file_uploads.raw_upload_id
+where
+  source_file_indices.chunk_index between ?1 and ?2
+group by
+  1
+order by
+  1
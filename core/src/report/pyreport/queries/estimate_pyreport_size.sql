@@ -0,0 +1,126 @@
+-- Counts and string lengths that `estimate.rs` turns into an estimate of how
+-- large `ToPyreport` output would be. Each column is named after the field of
+-- `ReportShape` it fills in.
+with file_lines as (
+select
+  coverage_sample.source_file_id,
+  coverage_sample.line_no
+from
+  coverage_sample
+where
+  coverage_sample.superseded = 0
+group by
+  1, 2
+),
+sample_labels as (
+select
+  context_assoc.raw_upload_id,
+  context_assoc.local_sample_id,
+  count(*) as labels,
+  sum(length(context.name)) as label_bytes
+from
+  context_assoc
+inner join
+  context
+on
+  context.id = context_assoc.context_id
+where
+  context_assoc.local_sample_id is not null
+  and context_assoc.superseded = 0
+group by
+  1, 2
+)
+select
+  (select count(*) from source_file) as files,
+  (select coalesce(sum(length(source_file.path)), 0) from source_file) as path_bytes,
+  (select count(*) from raw_upload) as uploads,
+  (
+    select
+      coalesce(sum(
+        -- Strings are quoted and missing values are written as `null`
+        coalesce(length(raw_upload.timestamp), 4)
+        + coalesce(length(raw_upload.raw_upload_url) + 2, 4)
+        + coalesce(length(raw_upload.flags), 4)
+        + coalesce(length(raw_upload.provider) + 2, 4)
+        + coalesce(length(raw_upload.build) + 2, 4)
+        + coalesce(length(raw_upload.name) + 2, 4)
+        + coalesce(length(raw_upload.job_name) + 2, 4)
+        + coalesce(length(raw_upload.ci_run_url) + 2, 4)
+        + coalesce(length(raw_upload.state) + 2, 4)
+        + coalesce(length(raw_upload.env) + 2, 4)
+        + coalesce(length(raw_upload.session_type) + 2, 4)
+        + coalesce(length(raw_upload.session_extras), 4)
+      ), 0)
+    from
+      raw_upload
+  ) as upload_bytes,
+  (
+    select
+      count(*)
+    from
+      (select distinct source_file_id, raw_upload_id from coverage_sample where superseded = 0)
+  ) as file_sessions,
+  (select count(*) from context) as labels,
+  (select coalesce(sum(length(context.name)), 0) from context) as label_bytes,
+  (select count(*) from file_lines) as lines,
+  (
+    select
+      coalesce(sum(max_line_no), 0)
+    from
+      (select max(line_no) as max_line_no from file_lines group by source_file_id)
+  ) as line_slots,
+  (select count(*) from coverage_sample where superseded = 0) as samples,
+  (
+    select
+      coalesce(sum(iif(
+        coverage_sample.hits is not null,
+        length(coverage_sample.hits),
+        length(coverage_sample.hit_branches) + length(coverage_sample.total_branches) + 3
+      )), 0)
+    from
+      coverage_sample
+    where
+      coverage_sample.superseded = 0
+  ) as coverage_bytes,
+  (
+    select
+      coalesce(sum(length(coverage_sample.messages)), 0)
+    from
+      coverage_sample
+    where
+      coverage_sample.superseded = 0
+  ) as message_bytes,
+  (select count(*) from method_data where superseded = 0) as methods,
+  (
+    select
+      count(*)
+    from
+      branches_data
+    where
+      branches_data.hits = 0
+      and branches_data.superseded = 0
+  ) as missing_branches,
+  (
+    select
+      coalesce(sum(length(branches_data.branch)), 0)
+    from
+      branches_data
+    where
+      branches_data.hits = 0
+      and branches_data.superseded = 0
+  ) as missing_branch_bytes,
+  (select count(*) from span_data where superseded = 0) as spans,
+  (select count(*) from sample_labels) as datapoints,
+  (
+    select
+      count(distinct coverage_sample.source_file_id || ':' || coverage_sample.line_no)
+    from
+      sample_labels
+    inner join
+      coverage_sample
+    on
+      coverage_sample.raw_upload_id = sample_labels.raw_upload_id
+      and coverage_sample.local_sample_id = sample_labels.local_sample_id
+  ) as datapoint_lines,
+  (select coalesce(sum(sample_labels.labels), 0) from sample_labels) as sample_labels,
+  (select coalesce(sum(sample_labels.label_bytes), 0) from sample_labels) as sample_label_bytes
@@ -0,0 +1,51 @@
+-- Same categorization as `sessions_to_report_json.sql`, but aggregated per
+-- (source_file, session) for each file's session totals.
+with samples_categorized as (
+select
+  coverage_sample.raw_upload_id,
+  coverage_sample.source_file_id,
+  coverage_sample.coverage_type,
+  iif(coverage_sample.hits > 0 or coverage_sample.hit_branches >= coverage_sample.total_branches, 1, 0) as hit,
+  iif(coverage_sample.hits = 0 or coverage_sample.hit_branches = 0, 1, 0) as miss,
+  iif(coverage_sample.hit_branches > 0 and coverage_sample.hit_branches < coverage_sample.total_branches, 1, 0) as partial,
+  iif(method_data.hit_complexity_paths is null, method_data.total_complexity, method_data.hit_complexity_paths) as hit_complexity_paths,
+  iif(method_data.hit_complexity_paths is null, null, method_data.total_complexity) as total_complexity
+from
+  coverage_sample
+left join
+  method_data
+on
+  method_data.raw_upload_id = coverage_sample.raw_upload_id
+  and method_data.local_sample_id = coverage_sample.local_sample_id
+where
+  coverage_sample.superseded = 0
+),
+-- Must match the session index computed in `sessions_to_report_json.sql`.
+sessions_with_index as (
+select
+  row_number() over (order by uploads.raw_upload_id) - 1 as session_index,
+  uploads.raw_upload_id
+from
+  (select distinct raw_upload_id from coverage_sample where superseded = 0) as uploads
+)
+select
+  samples_categorized.source_file_id,
+  sessions_with_index.session_index,
+  count(*) as lines,
+  sum(samples_categorized.hit) as hits,
+  sum(samples_categorized.miss) as misses,
+  sum(samples_categorized.partial) as partials,
+  sum(iif(samples_categorized.coverage_type = 'b', 1, 0)) as branches,
+  sum(iif(samples_categorized.coverage_type = 'm', 1, 0)) as methods,
+  coalesce(sum(samples_categorized.hit_complexity_paths), 0) as hit_complexity_paths,
+  coalesce(sum(samples_categorized.total_complexity), 0) as total_complexity
+from
+  samples_categorized
+inner join
+  sessions_with_index
+on
+  sessions_with_index.raw_upload_id = samples_categorized.raw_upload_id
+group by
+  1, 2
+order by
+  1, 2
@@ -31,6 +31,8 @@ left join
 on
   method_data.raw_upload_id = coverage_sample.raw_upload_id
   and method_data.local_sample_id = coverage_sample.local_sample_id
+where
+  coverage_sample.superseded = 0
 ),
 -- Compute the chunks file index of each `source_file` record. Must match the
 -- corresponding logic in `samples_to_chunks.sql`.
@@ -5,23 +5,23 @@ select
 from
   raw_upload
 ),
-chunks_file_indices as (
+-- Chunk indices are computed over every `source_file` record before we filter
+-- down to the requested range of chunks.
+source_file_indices as (
 select
   row_number() over (order by source_file.id) - 1 as chunk_index,
-  source_file.id as source_file_id,
-  json_group_array(distinct session_indices.session_index order by session_indices.session_index asc) as present_sessions
+  source_file.id as source_file_id
 from
   source_file
-left join
-  coverage_sample
-on
-  coverage_sample.source_file_id = source_file.id
-left join
-  session_indices
-on
-  coverage_sample.raw_upload_id = session_indices.raw_upload_id
-group by
-  2
+),
+chunks_file_indices as (
+select
+  source_file_indices.chunk_index,
+  source_file_indices.source_file_id
+from
+  source_file_indices
+where
+  source_file_indices.chunk_index between ?1 and ?2
 ),
 formatted_span_data as (
 select
@@ -35,7 +35,6 @@ from
 line_sessions as (
 select
   chunks_file_indices.chunk_index,
-  chunks_file_indices.present_sessions,
   coverage_sample.line_no,
   session_indices.session_index,
   coverage_sample.coverage_type,
@@ -44,6 +43,7 @@ select
   coverage_sample.total_branches,
   method_data.hit_complexity_paths,
   method_data.total_complexity,
+  coverage_sample.messages,
   -- The `order by` below is not strictly necessary, it just makes writing test cases easier
   json_group_array(branches_data.branch order by branches_data.branch) filter (where branches_data.branch is not null and branches_data.hits = 0) as missing_branches,
   json_group_array(json(formatted_span_data.pyreport_partial)) filter (where formatted_span_data.pyreport_partial is not null) as partials,
@@ -82,8 +82,11 @@ left join
   context
 on
   context_assoc.context_id = context.id
-group by 1, 2, 3, 4
-order by 1, 2, 3, context.name
+where
+  chunks_file_indices.chunk_index between ?1 and ?2
+  and coverage_sample.superseded = 0
+group by 1, 2, 3
+order by 1, 2, context.name
 ),
 report_line_totals as (
 select
@@ -93,7 +96,9 @@ select
   sum(line_sessions.hit_branches) as hit_branches,
   sum(line_sessions.total_branches) as total_branches,
   sum(line_sessions.hit_complexity_paths) as hit_complexity_paths,
-  sum(line_sessions.total_complexity) as total_complexity
+  sum(line_sessions.total_complexity) as total_complexity,
+  -- Every session's sample carries a copy of the line's messages
+  max(line_sessions.messages) as messages
 from
   line_sessions
 group by
@@ -109,7 +114,6 @@ select
   report_line_totals.hit_complexity_paths as report_line_hit_complexity_paths,
   report_line_totals.total_complexity as report_line_total_complexity,
   line_sessions.session_index,
-  line_sessions.present_sessions,
   line_sessions.hits,
   line_sessions.hit_branches,
   line_sessions.total_branches,
@@ -117,7 +121,8 @@ select
   line_sessions.total_complexity,
   iif(line_sessions.missing_branches = json_array(), null, line_sessions.missing_branches) as missing_branches,
   iif(json(line_sessions.partials) = json_array(), null, json(line_sessions.partials)) as partials,
-  iif(line_sessions.labels = json_array(), null, line_sessions.labels) as labels
+  iif(line_sessions.labels = json_array(), null, line_sessions.labels) as labels,
+  report_line_totals.messages as report_line_messages
 from
   line_sessions
 left join
@@ -19,6 +19,8 @@ left join
 on
   method_data.raw_upload_id = coverage_sample.raw_upload_id
   and method_data.local_sample_id = coverage_sample.local_sample_id
+where
+  coverage_sample.superseded = 0
 )
 select
   cast(row_number() over (order by raw_upload.id) - 1 as text) as session_index,
@@ -32,7 +34,8 @@ select
   sum(iif(samples_categorized.coverage_type = 'm', 1, 0)) as session_methods,
   coalesce(sum(samples_categorized.hit_complexity_paths), 0) as session_hit_complexity_paths,
   coalesce(sum(samples_categorized.total_complexity), 0) as session_total_complexity,
-  raw_upload.timestamp,
+  -- Python expects whole seconds, even if a float was stored somehow
+  cast(raw_upload.timestamp as integer) as timestamp,
   raw_upload.raw_upload_url,
   raw_upload.flags,
   raw_upload.provider,
@@ -1,21 +1,42 @@
-use std::io::Write;
+use std::{collections::HashMap, io::Write};
 
 use serde_json::json;
 
+use super::format;
 use crate::{
     error::Result,
     parsers::json::JsonVal,
     report::{models, sqlite::json_value_from_sql, SqliteReport},
 };
 
-/// Coverage percentages are written with 5 decimal places of precision unless
-/// they are 0 or 100.
-fn calculate_coverage_pct(hits: i64, lines: i64) -> String {
-    match (hits, lines) {
-        (0, _) => 0.to_string(),
-        (h, l) if h == l => 100.to_string(),
-        (h, l) => format!("{:.5}", h as f64 / l as f64 * 100.0),
+/// Queries each file's totals in each session, keyed by file ID. Entries
+/// are `(session index, totals)` in session index order.
+fn query_file_session_totals(
+    report: &SqliteReport,
+) -> Result<HashMap<i64, Vec<(usize, format::Totals)>>> {
+    let mut stmt =
+        report.prepare_cached(include_str!("queries/file_sessions_to_report_json.sql"))?;
+    let mut rows = stmt.query([])?;
+
+    let mut file_session_totals: HashMap<i64, Vec<_>> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let totals = format::Totals {
+            lines: row.get(2)?,
+            hits: row.get(3)?,
+            misses: row.get(4)?,
+            partials: row.get(5)?,
+            branches: row.get(6)?,
+            methods: row.get(7)?,
+            hit_complexity_paths: row.get(8)?,
+            total_complexity: row.get(9)?,
+            ..Default::default()
+        };
+        file_session_totals
+            .entry(row.get(0)?)
+            .or_default()
+            .push((row.get(1)?, totals));
     }
+    Ok(file_session_totals)
 }
 
 /// Build the "files" object inside of a report JSON and write it to
@@ -36,52 +57,49 @@ fn calculate_coverage_pct(hits: i64, lines: i64) -> String {
 /// See [`crate::report::pyreport`] for more details about the content and
 /// structure of a report JSON.
 fn sql_to_files_dict(report: &SqliteReport, output: &mut impl Write) -> Result<()> {
-    let mut stmt = report
-        .conn
-        .prepare_cached(include_str!("queries/files_to_report_json.sql"))?;
+    let mut stmt = report.prepare_cached(include_str!("queries/files_to_report_json.sql"))?;
     let mut rows = stmt.query([])?;
+    let file_session_totals = query_file_session_totals(report)?;
 
     /// Each row returned by `queries/files_to_report_json.sql` represents a
     /// `models::SourceFile` from a `SqliteReport` alongside some aggregated
     /// coverage metrics for that file. This helper function returns the
     /// key/value pair that will be written into the files object for a row,
     /// where the key is the file's path and the value is its data.
-    fn build_file_from_row(row: &rusqlite::Row) -> Result<(String, JsonVal)> {
+    fn build_file_from_row(
+        row: &rusqlite::Row,
+        file_session_totals: &HashMap<i64, Vec<(usize, format::Totals)>>,
+    ) -> Result<(String, JsonVal)> {
         let chunk_index = row.get::<usize, i64>(0)?;
+        let file_id = row.get::<usize, i64>(1)?;
         let new_path = row.get(2)?;
-        let lines = row.get::<usize, i64>(3)?;
-        let hits = row.get::<usize, i64>(4)?;
-        let misses = row.get::<usize, i64>(5)?;
-        let partials = row.get::<usize, i64>(6)?;
-        let branches = row.get::<usize, i64>(7)?;
-        let methods = row.get::<usize, i64>(8)?;
-        let hit_complexity_paths = row.get::<usize, i64>(9)?;
-        let total_complexity = row.get::<usize, i64>(10)?;
-
-        let coverage_pct = calculate_coverage_pct(hits, lines);
-        let totals = json!([
-            0, // file_count
-            lines,
-            hits,
-            misses,
-            partials,
-            coverage_pct,
-            branches,
-            methods,
-            0, // messages
-            0, // sessions
-            hit_complexity_paths,
-            total_complexity,
-            0, // diff
-        ]);
+        let totals = format::Totals {
+            lines: row.get(3)?,
+            hits: row.get(4)?,
+            misses: row.get(5)?,
+            partials: row.get(6)?,
+            branches: row.get(7)?,
+            methods: row.get(8)?,
+            hit_complexity_paths: row.get(9)?,
+            total_complexity: row.get(10)?,
+            ..Default::default()
+        };
+
+        let session_totals = format::session_totals(
+            file_session_totals
+                .get(&file_id)
+                .into_iter()
+                .flatten()
+                .map(|(session_index, totals)| (*session_index, totals)),
+        );
 
         Ok((
             new_path,
             json!([
                 chunk_index,
-                totals,
-                JsonVal::Null, /* session_totals */
-                JsonVal::Null  /* diff_totals */
+                totals.to_json(),
+                session_totals,
+                JsonVal::Null /* diff_totals */
             ]),
         ))
     }
@@ -92,7 +110,7 @@ fn sql_to_files_dict(report: &SqliteReport, output: &mut impl Write) -> Result<(
     write!(output, "\"files\": {{")?;
     let mut first_file = true;
     while let Some(row) = rows.next()? {
-        let (file_path, file) = build_file_from_row(row)?;
+        let (file_path, file) = build_file_from_row(row, &file_session_totals)?;
         // No preceding , for the first file we write
         let delimiter = if first_file { "" } else { "," };
         write!(output, "{delimiter}\"{file_path}\": {file}")?;
@@ -120,9 +138,7 @@ fn sql_to_files_dict(report: &SqliteReport, output: &mut impl Write) -> Result<(
 /// See [`crate::report::pyreport`] for more details about the content and
 /// structure of a report JSON.
 fn sql_to_sessions_dict(report: &SqliteReport, output: &mut impl Write) -> Result<()> {
-    let mut stmt = report
-        .conn
-        .prepare_cached(include_str!("queries/sessions_to_report_json.sql"))?;
+    let mut stmt = report.prepare_cached(include_str!("queries/sessions_to_report_json.sql"))?;
     let mut rows = stmt.query([])?;
 
     /// Each row returned by `queries/sessions_to_report_json.sql` represents a
@@ -132,32 +148,18 @@ fn sql_to_sessions_dict(report: &SqliteReport, output: &mut impl Write) -> Resul
     /// the session ID and the value is the data for that session.
     fn build_session_from_row(row: &rusqlite::Row) -> Result<(String, JsonVal)> {
         let session_id = row.get::<usize, String>(0)?;
-        let file_count = row.get::<usize, i64>(2)?;
-        let lines = row.get::<usize, i64>(3)?;
-        let hits = row.get::<usize, i64>(4)?;
-        let misses = row.get::<usize, i64>(5)?;
-        let partials = row.get::<usize, i64>(6)?;
-        let branches = row.get::<usize, i64>(7)?;
-        let methods = row.get::<usize, i64>(8)?;
-        let hit_complexity_paths = row.get::<usize, i64>(9)?;
-        let total_complexity = row.get::<usize, i64>(10)?;
-
-        let coverage_pct = calculate_coverage_pct(hits, lines);
-        let totals = json!([
-            file_count,
-            lines,
-            hits,
-            misses,
-            partials,
-            coverage_pct,
-            branches,
-            methods,
-            0, // messages
-            0, // sessions
-            hit_complexity_paths,
-            total_complexity,
-            0, // diff
-        ]);
+        let totals = format::Totals {
+            files: row.get(2)?,
+            lines: row.get(3)?,
+            hits: row.get(4)?,
+            misses: row.get(5)?,
+            partials: row.get(6)?,
+            branches: row.get(7)?,
+            methods: row.get(8)?,
+            hit_complexity_paths: row.get(9)?,
+            total_complexity: row.get(10)?,
+            ..Default::default()
+        };
 
         let flags = if let Some(flags) = row.get(13)? {
             Some(json_value_from_sql(flags, 13)?)
@@ -189,7 +191,7 @@ fn sql_to_sessions_dict(report: &SqliteReport, output: &mut impl Write) -> Resul
         Ok((
             session_id,
             json!({
-                "t": totals,
+                "t": totals.to_json(),
                 "d": raw_upload.timestamp,
                 "a": raw_upload.raw_upload_url,
                 "f": raw_upload.flags,
@@ -253,19 +255,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_calculate_coverage_pct() {
-        assert_eq!(calculate_coverage_pct(0, 16), "0".to_string());
-        assert_eq!(calculate_coverage_pct(4, 16), "25.00000".to_string());
-        assert_eq!(calculate_coverage_pct(16, 16), "100".to_string());
-        assert_eq!(calculate_coverage_pct(1, 3), "33.33333".to_string());
-        assert_eq!(calculate_coverage_pct(1, 8), "12.50000".to_string());
-
-        // Should not occur in normal usage, just documenting the behavior
-        assert_eq!(calculate_coverage_pct(-1, 8), "-12.50000".to_string());
-        assert_eq!(calculate_coverage_pct(9, 8), "112.50000".to_string());
-    }
-
     #[test]
     fn test_sql_to_files_dict() {
         let ctx = setup();
@@ -297,7 +286,11 @@ mod tests {
                         4,          // total complexity
                         0           // diff
                     ],
-                    null,
+                    {
+                        "0": [0, 3, 2, 0, 1, "66.66667", 1, 1],
+                        "1": [0, 2, 0, 2, 0, "0", 0, 1, 0, 0, 2, 4],
+                        "meta": {"session_count": 2}
+                    },
                     null
                 ],
                 "src/report/report.rs": [
@@ -317,7 +310,10 @@ mod tests {
                         4,      // total complexity
                         0       // diff
                     ],
-                    null,
+                    {
+                        "0": [0, 4, 4, 0, 0, "100", 1, 1, 0, 0, 2, 4],
+                        "meta": {"session_count": 1}
+                    },
                     null
                 ],
             }
@@ -404,6 +400,26 @@ mod tests {
         assert_eq!(sessions_dict, expected);
     }
 
+    #[test]
+    fn test_sql_to_sessions_dict_whole_seconds() {
+        let ctx = setup();
+        let report = build_sample_report(ctx.temp_dir.path().join("db.sqlite")).unwrap();
+        report
+            .conn
+            .execute("UPDATE raw_upload SET timestamp = timestamp + 0.75", [])
+            .unwrap();
+
+        let mut sessions_output = Vec::new();
+        sessions_output.push(b'{');
+        sql_to_sessions_dict(&report, &mut sessions_output).unwrap();
+        sessions_output.push(b'}');
+
+        let sessions_dict: JsonVal = serde_json::from_slice(&sessions_output).unwrap();
+        let timestamps = ["0", "1"].map(|index| &sessions_dict["sessions"][index]["d"]);
+        assert_eq!(timestamps, [&json!(123), &json!(456)]);
+        assert!(timestamps.iter().all(|timestamp| timestamp.is_i64()));
+    }
+
     #[test]
     fn test_sql_to_report_json() {
         let ctx = setup();
@@ -420,13 +436,20 @@ mod tests {
                 "src/report/models.rs": [
                     0,
                     [0, 5, 2, 2, 1, "40.00000", 1, 2, 0, 0, 2, 4, 0],
-                    null,
+                    {
+                        "0": [0, 3, 2, 0, 1, "66.66667", 1, 1],
+                        "1": [0, 2, 0, 2, 0, "0", 0, 1, 0, 0, 2, 4],
+                        "meta": {"session_count": 2}
+                    },
                     null
                 ],
                 "src/report/report.rs": [
                     1,
                     [0, 4, 4, 0, 0, "100", 1, 1, 0, 0, 2, 4, 0],
-                    null,
+                    {
+                        "0": [0, 4, 4, 0, 0, "100", 1, 1, 0, 0, 2, 4],
+                        "meta": {"session_count": 1}
+                    },
                     null
                 ],
             },
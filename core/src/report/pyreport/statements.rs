@@ -0,0 +1,235 @@
+//! Merging [`CoverageType::Statement`](models::CoverageType::Statement)
+//! samples into line samples, since a pyreport has no statements and only
+//! one sample per line and session.
+
+use rusqlite::Connection;
+
+use crate::{error::Result, report::models};
+
+/// How [`ToPyreport`](super::ToPyreport) turns the statements an upload
+/// recorded on one line into a single line sample.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum StatementExport {
+    /// The line's hits are the most any of its statements were hit, so the
+    /// line is covered if any statement on it ran.
+    #[default]
+    Max,
+
+    /// The line's hits are the fewest any of its statements were hit, so the
+    /// line is only covered if every statement on it ran.
+    Min,
+}
+
+/// Whether the report at `conn` has any current statement samples.
+pub(super) fn has_statements(conn: &Connection) -> Result<bool> {
+    Ok(conn.query_row(
+        "SELECT EXISTS (
+           SELECT 1 FROM main.coverage_sample
+           WHERE coverage_type = ?1 AND superseded = 0
+         )",
+        [models::CoverageType::Statement],
+        |row| row.get(0),
+    )?)
+}
+
+/// Replaces the current statement samples for each (upload, file, line) with
+/// one line sample, whose hits are picked according to `policy`. The sample
+/// with the lowest local ID is kept, and the labels and spans attached to the
+/// others are moved onto it.
+///
+/// Meant for a copy of a report about to be exported, since the individual
+/// statements are lost.
+pub(super) fn merge_statements(conn: &Connection, policy: StatementExport) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "CREATE TEMP TABLE merged_statement AS
+         SELECT
+           statement.raw_upload_id,
+           statement.local_sample_id,
+           kept.local_sample_id AS kept_local_sample_id
+         FROM main.coverage_sample statement
+         JOIN (
+           SELECT raw_upload_id, source_file_id, line_no, min(local_sample_id) AS local_sample_id
+           FROM main.coverage_sample
+           WHERE coverage_type = ?1 AND superseded = 0
+           GROUP BY 1, 2, 3
+         ) kept
+           ON kept.raw_upload_id = statement.raw_upload_id
+           AND kept.source_file_id = statement.source_file_id
+           AND kept.line_no = statement.line_no
+         WHERE statement.coverage_type = ?1 AND statement.superseded = 0",
+        [models::CoverageType::Statement],
+    )?;
+    tx.execute_batch(
+        "CREATE INDEX temp.merged_statement_sample
+           ON merged_statement (raw_upload_id, local_sample_id);",
+    )?;
+
+    let aggregate = match policy {
+        StatementExport::Max => "max",
+        StatementExport::Min => "min",
+    };
+    tx.execute(
+        &format!(
+            "UPDATE main.coverage_sample SET
+               coverage_type = ?1,
+               hits = (
+                 SELECT {aggregate}(statement.hits)
+                 FROM merged_statement
+                 JOIN main.coverage_sample statement
+                   ON statement.raw_upload_id = merged_statement.raw_upload_id
+                   AND statement.local_sample_id = merged_statement.local_sample_id
+                 WHERE merged_statement.raw_upload_id = coverage_sample.raw_upload_id
+                   AND merged_statement.kept_local_sample_id = coverage_sample.local_sample_id
+               )
+             WHERE (raw_upload_id, local_sample_id) IN (
+               SELECT raw_upload_id, kept_local_sample_id FROM merged_statement
+             )"
+        ),
+        [models::CoverageType::Line],
+    )?;
+
+    tx.execute_batch(
+        "UPDATE main.span_data SET
+           local_sample_id = (
+             SELECT kept_local_sample_id FROM merged_statement
+             WHERE merged_statement.raw_upload_id = span_data.raw_upload_id
+               AND merged_statement.local_sample_id = span_data.local_sample_id
+           )
+         WHERE (raw_upload_id, local_sample_id) IN (
+           SELECT raw_upload_id, local_sample_id FROM merged_statement
+         );
+
+         -- A label on several of the line's statements is only attached once
+         INSERT INTO main.context_assoc (context_id, raw_upload_id, local_sample_id)
+         SELECT DISTINCT
+           context_assoc.context_id,
+           merged_statement.raw_upload_id,
+           merged_statement.kept_local_sample_id
+         FROM main.context_assoc
+         JOIN merged_statement
+           ON merged_statement.raw_upload_id = context_assoc.raw_upload_id
+           AND merged_statement.local_sample_id = context_assoc.local_sample_id
+         WHERE merged_statement.local_sample_id != merged_statement.kept_local_sample_id
+           AND context_assoc.local_span_id IS NULL
+           AND NOT EXISTS (
+             SELECT 1 FROM main.context_assoc existing
+             WHERE existing.context_id = context_assoc.context_id
+               AND existing.raw_upload_id = merged_statement.raw_upload_id
+               AND existing.local_sample_id = merged_statement.kept_local_sample_id
+               AND existing.local_span_id IS NULL
+           );
+
+         DELETE FROM main.context_assoc
+         WHERE local_span_id IS NULL
+           AND (raw_upload_id, local_sample_id) IN (
+             SELECT raw_upload_id, local_sample_id FROM merged_statement
+             WHERE local_sample_id != kept_local_sample_id
+           );
+
+         DELETE FROM main.branches_data
+         WHERE (raw_upload_id, local_sample_id) IN (
+           SELECT raw_upload_id, local_sample_id FROM merged_statement
+           WHERE local_sample_id != kept_local_sample_id
+         );
+
+         DELETE FROM main.method_data
+         WHERE (raw_upload_id, local_sample_id) IN (
+           SELECT raw_upload_id, local_sample_id FROM merged_statement
+           WHERE local_sample_id != kept_local_sample_id
+         );
+
+         DELETE FROM main.coverage_sample
+         WHERE (raw_upload_id, local_sample_id) IN (
+           SELECT raw_upload_id, local_sample_id FROM merged_statement
+           WHERE local_sample_id != kept_local_sample_id
+         );
+
+         DROP TABLE merged_statement;",
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{Report, ReportBuilder, SqliteReportBuilder};
+
+    #[test]
+    fn test_merge_statements() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut builder = SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let file = builder.insert_file("src/lib.rs").unwrap();
+        let statements: Vec<_> = [2, 0]
+            .into_iter()
+            .map(|hits| {
+                builder
+                    .insert_coverage_sample(models::CoverageSample {
+                        raw_upload_id: upload.id,
+                        source_file_id: file.id,
+                        line_no: 1,
+                        coverage_type: models::CoverageType::Statement,
+                        hits: Some(hits),
+                        ..Default::default()
+                    })
+                    .unwrap()
+            })
+            .collect();
+        let [shared, second] = [
+            builder.insert_context("shared").unwrap(),
+            builder.insert_context("second").unwrap(),
+        ];
+        for (context, statement) in [
+            (&shared, &statements[0]),
+            (&shared, &statements[1]),
+            (&second, &statements[1]),
+        ] {
+            builder
+                .associate_context(models::ContextAssoc {
+                    context_id: context.id,
+                    raw_upload_id: upload.id,
+                    local_sample_id: Some(statement.local_sample_id),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        builder
+            .insert_span_data(models::SpanData {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                local_sample_id: Some(statements[1].local_sample_id),
+                hits: 0,
+                start_col: Some(10),
+                end_col: Some(20),
+                ..Default::default()
+            })
+            .unwrap();
+        let report = builder.build().unwrap();
+        assert!(has_statements(&report.conn).unwrap());
+
+        merge_statements(&report.conn, StatementExport::Min).unwrap();
+        assert!(!has_statements(&report.conn).unwrap());
+
+        let samples = report.list_coverage_samples().unwrap();
+        let [line] = samples.as_slice() else {
+            panic!("expected one sample, got {samples:?}");
+        };
+        assert_eq!(line.local_sample_id, statements[0].local_sample_id);
+        assert_eq!(line.coverage_type, models::CoverageType::Line);
+        assert_eq!(line.hits, Some(0));
+
+        let mut contexts: Vec<_> = report
+            .list_contexts_for_sample(line)
+            .unwrap()
+            .into_iter()
+            .map(|context| context.name)
+            .collect();
+        contexts.sort();
+        assert_eq!(contexts, ["second", "shared"]);
+        assert_eq!(report.list_spans_for_sample(line).unwrap().len(), 1);
+    }
+}
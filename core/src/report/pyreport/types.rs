@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
 pub use super::super::models::CoverageType;
 use crate::parsers::json::JsonVal;
@@ -41,8 +41,9 @@ pub enum Complexity {
 }
 
 /// Enum representing the possible shapes of data about missing branch coverage.
+/// Borrows from the chunks file it was parsed from.
 #[derive(Clone, Debug, PartialEq)]
-pub enum MissingBranch {
+pub enum MissingBranch<'a> {
     /// Identifies a specific branch by its "block" and "branch" numbers chosen
     /// by the instrumentation. Lcov does it this way.
     BlockAndBranch(u32, u32),
@@ -50,7 +51,7 @@ pub enum MissingBranch {
     /// Identifies a specific branch as one of a set of conditions tied to a
     /// line. In Cobertura, this condition may be accompanied by a "type" such
     /// as "jump".
-    Condition(u32, Option<String>),
+    Condition(u32, Option<&'a str>),
 
     /// Identifies a specific branch as a line number the branch is located at.
     Line(u32),
@@ -71,7 +72,7 @@ pub struct Partial {
 /// Each upload to our system constitutes a "session" and may be tagged with
 /// flags or other context that we want to filter on when viewing report data.
 #[derive(Debug, PartialEq)]
-pub struct LineSession {
+pub struct LineSession<'a> {
     /// This ID indicates which session the measurement was taken in. It can be
     /// used as a key in `buf.state.report_json_sessions` to get the ID of a
     /// [`Context`](models::Context) in order to create a
@@ -85,7 +86,7 @@ pub struct LineSession {
 
     /// A list of specific branches/conditions stemming from this line that were
     /// not covered if this line is a branch. May be omitted, or may be null.
-    pub branches: Option<Option<Vec<MissingBranch>>>,
+    pub branches: Option<Option<Vec<MissingBranch<'a>>>>,
 
     /// A list of "line partials" which indicate different coverage values for
     /// different subspans of this line. May be omitted, or may be null.
@@ -104,7 +105,7 @@ pub struct LineSession {
 /// label with an index found in the chunks file header. Older reports stored
 /// many copies of the full strings.
 #[derive(Clone, Debug)]
-pub enum RawLabel {
+pub enum RawLabel<'a> {
     /// A numeric ID that was assigned to this label. The original label can be
     /// accessed in the `"labels_index"` key in the chunks file's header.
     /// For our parser's purposes, we can access the ID of the
@@ -116,7 +117,10 @@ pub enum RawLabel {
     /// should be in `buf.state.labels_index` pointing at the ID for a
     /// [`Context`](models::Context). Otherwise, we should create that
     /// `Context` + mapping ourselves.
-    LabelName(String),
+    ///
+    /// Borrowed from the chunks file unless the name contained escape
+    /// sequences.
+    LabelName(Cow<'a, str>),
 }
 
 /// An object that is similar to a [`LineSession`], containing coverage
@@ -143,8 +147,9 @@ pub struct CoverageDatapoint {
     /// no way to tell which it is when deserializing.
     pub _coverage_type: Option<CoverageType>,
 
-    /// A list of labels (e.g. test cases) that apply to this datapoint.
-    pub labels: Vec<String>,
+    /// The IDs of the [`Context`](models::Context)s for the labels (e.g. test
+    /// cases) that apply to this datapoint.
+    pub labels: Vec<i64>,
 }
 
 /// Contains all of the coverage measurements for a line in a source file.
@@ -155,11 +160,11 @@ pub struct CoverageDatapoint {
 /// `datapoints`, if present, contains mostly-redundant [`CoverageDatapoint`]s.
 /// This is where [`RawLabel`]s are found.
 ///
-/// `_messages`, `_complexity` are ignored. `coverage` is used to detect/correct
+/// `_complexity` is ignored. `coverage` is used to detect/correct
 /// malformed input data and is thrown away in favor of the coverage data in
 /// each `LineSession`.
 #[derive(Debug, PartialEq)]
-pub struct ReportLine {
+pub struct ReportLine<'a> {
     pub line_no: i64,
 
     /// An aggregated coverage status across all of the [`LineSession`]s in
@@ -171,10 +176,12 @@ pub struct ReportLine {
     /// The list of measurements taken for this line. Each of these corresponds
     /// to a [`CoverageSample`](models::CoverageSample) record in a
     /// `SqliteReport`.
-    pub sessions: Vec<LineSession>,
+    pub sessions: Vec<LineSession<'a>>,
 
-    /// Long forgotten field that takes up space.
-    pub _messages: Option<Option<JsonVal>>,
+    /// Long forgotten field whose contents we don't understand. It's stored on
+    /// each session's [`CoverageSample`](models::CoverageSample) so it can be
+    /// written back out unchanged.
+    pub messages: Option<Option<JsonVal>>,
 
     /// An aggregated complexity metric across all of the [`LineSession`]s in
     /// `sessions`.
@@ -186,138 +193,3 @@ pub struct ReportLine {
     /// measurement was collected).
     pub datapoints: Option<Option<HashMap<u32, CoverageDatapoint>>>,
 }
-
-/// Account for some quirks and malformed data. See code comments for details.
-pub(crate) fn normalize_coverage_measurement(
-    coverage: &PyreportCoverage,
-    coverage_type: &CoverageType,
-) -> (PyreportCoverage, CoverageType) {
-    match (coverage, coverage_type) {
-        // Clojure uses `true` to indicate partial coverage without giving any specific information
-        // about the missed/covered branches. Our parsers for other formats just make up "1/2" and
-        // move on, so we do that here as well.
-        (PyreportCoverage::Partial(), _) => (
-            PyreportCoverage::BranchesTaken {
-                covered: 1,
-                total: 2,
-            },
-            *coverage_type,
-        ),
-
-        // For method coverage, Jacoco contains aggregated instruction coverage, branch coverage,
-        // and cyclomatic complexity metrics. If the branch coverage fields are present and
-        // non-zero, our parser will prefer them even though method coverage should be an int.
-        // We normalize by treating the numerator of the branch coverage as a hit count, so "0/2"
-        // is a miss but "3/4" is 3 hits. Not great, but the data is bugged to begin with.
-        (PyreportCoverage::BranchesTaken { covered, .. }, CoverageType::Method) => {
-            (PyreportCoverage::HitCount(*covered), CoverageType::Method)
-        }
-
-        // Our Go parser does not properly fill out the `coverage_type` field. If the `coverage`
-        // field has branch data in it, override the coverage type.
-        (PyreportCoverage::BranchesTaken { .. }, CoverageType::Line) => {
-            (coverage.clone(), CoverageType::Branch)
-        }
-
-        // We see some instances of Scale Scoverage reports being incorrectly translated into
-        // Cobertura reports. In Scoverage, 0 for branch coverage means miss, 1 means partial, and
-        // 2 means hit. It seems when converting to Cobertura, the value is taken as a raw hit
-        // count and not coverted to `branch-rate` or something, and our Cobertura parser doesn't
-        // handle it. So, we handle it here.
-        (PyreportCoverage::HitCount(n), CoverageType::Branch) => {
-            assert!(*n == 0 || *n == 1 || *n == 2); // TODO soften assert
-            (
-                PyreportCoverage::BranchesTaken {
-                    covered: *n,
-                    total: 2,
-                },
-                CoverageType::Branch,
-            )
-        }
-
-        // Everything's fine.
-        (_, _) => (coverage.clone(), *coverage_type),
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_normalize_coverage_measurement() {
-        // Cases that don't need adjustmnet
-        assert_eq!(
-            normalize_coverage_measurement(&PyreportCoverage::HitCount(3), &CoverageType::Line),
-            (PyreportCoverage::HitCount(3), CoverageType::Line)
-        );
-        assert_eq!(
-            normalize_coverage_measurement(&PyreportCoverage::HitCount(3), &CoverageType::Method),
-            (PyreportCoverage::HitCount(3), CoverageType::Method)
-        );
-        assert_eq!(
-            normalize_coverage_measurement(
-                &PyreportCoverage::BranchesTaken {
-                    covered: 2,
-                    total: 4
-                },
-                &CoverageType::Branch
-            ),
-            (
-                PyreportCoverage::BranchesTaken {
-                    covered: 2,
-                    total: 4
-                },
-                CoverageType::Branch
-            )
-        );
-
-        // Cases that need adjustment
-        assert_eq!(
-            normalize_coverage_measurement(&PyreportCoverage::Partial(), &CoverageType::Branch),
-            (
-                PyreportCoverage::BranchesTaken {
-                    covered: 1,
-                    total: 2
-                },
-                CoverageType::Branch
-            )
-        );
-        assert_eq!(
-            normalize_coverage_measurement(&PyreportCoverage::HitCount(1), &CoverageType::Branch),
-            (
-                PyreportCoverage::BranchesTaken {
-                    covered: 1,
-                    total: 2
-                },
-                CoverageType::Branch
-            )
-        );
-        assert_eq!(
-            normalize_coverage_measurement(
-                &PyreportCoverage::BranchesTaken {
-                    covered: 1,
-                    total: 2
-                },
-                &CoverageType::Line
-            ),
-            (
-                PyreportCoverage::BranchesTaken {
-                    covered: 1,
-                    total: 2
-                },
-                CoverageType::Branch
-            )
-        );
-        assert_eq!(
-            normalize_coverage_measurement(
-                &PyreportCoverage::BranchesTaken {
-                    covered: 1,
-                    total: 2
-                },
-                &CoverageType::Method,
-            ),
-            (PyreportCoverage::HitCount(1), CoverageType::Method,)
-        );
-    }
-}
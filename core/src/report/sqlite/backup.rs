@@ -0,0 +1,195 @@
+//! Archiving a report to a file and restoring it again while it's open, with
+//! SQLite's online backup API.
+
+use std::path::Path;
+
+use rusqlite::{
+    backup::{Backup, Progress, StepResult},
+    Connection, OpenFlags,
+};
+
+use super::{backup_step, check_compatibility, migrate, runs::install_run_view, SqliteReport};
+use crate::error::{CodecovError, Result};
+
+/// How many pages [`SqliteReport::backup_to`] and
+/// [`SqliteReport::restore_from`] copy before reporting progress and letting
+/// other connections at the database.
+const PAGES_PER_STEP: i32 = 256;
+
+impl SqliteReport {
+    /// Copies the report into a new database file at `path`, overwriting
+    /// anything already there, then checks that the copy isn't corrupt.
+    ///
+    /// The copy is made a few pages at a time, calling `progress` after each
+    /// step. Other connections can write to the report in between, but each
+    /// time they do the copy starts over, so a report that's constantly being
+    /// written to may take a while to back up. Use [`SqliteReport::snapshot`]
+    /// to copy it all at once instead. A step that another connection keeps
+    /// locked for too long fails like [`SqliteReport::snapshot`] does.
+    pub fn backup_to(&self, path: &Path, mut progress: impl FnMut(Progress)) -> Result<()> {
+        let mut dest = Connection::open(path)?;
+        copy(&self.conn, &mut dest, &mut progress)?;
+        verify_integrity(&dest)
+    }
+
+    /// Replaces the contents of the report with the database file at `path`,
+    /// such as one written by [`SqliteReport::backup_to`], and migrates it to
+    /// the latest schema.
+    ///
+    /// `path` is checked for corruption and for a schema we can't use before
+    /// anything is copied, so the report is untouched if it fails either
+    /// check. `progress` is called the same way as for
+    /// [`SqliteReport::backup_to`].
+    pub fn restore_from(&mut self, path: &Path, mut progress: impl FnMut(Progress)) -> Result<()> {
+        let source = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        verify_integrity(&source)?;
+        check_compatibility(&source)?;
+
+        // The run view lives in `temp`, which the backup doesn't replace, and
+        // would otherwise shadow `coverage_sample` while migrating
+        self.conn
+            .execute_batch("DROP VIEW IF EXISTS temp.coverage_sample")?;
+        copy(&source, &mut self.conn, &mut progress)?;
+        migrate(&mut self.conn)?;
+        install_run_view(&self.conn)
+    }
+}
+
+/// Copies the `main` database of `source` over that of `dest`.
+fn copy(
+    source: &Connection,
+    dest: &mut Connection,
+    progress: &mut impl FnMut(Progress),
+) -> Result<()> {
+    let backup = Backup::new(source, dest)?;
+    loop {
+        // Waits, within limits, while another connection is writing to one
+        // side
+        match backup_step(&backup, PAGES_PER_STEP)? {
+            StepResult::Done => {
+                progress(backup.progress());
+                return Ok(());
+            }
+            _ => progress(backup.progress()),
+        }
+    }
+}
+
+/// Fails with [`CodecovError::CorruptDatabase`] if SQLite finds anything wrong
+/// with the structure of the database at `conn` or with its foreign keys.
+fn verify_integrity(conn: &Connection) -> Result<()> {
+    let mut problems = conn
+        .prepare("PRAGMA integrity_check")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if problems == ["ok"] {
+        problems.clear();
+    }
+
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let table: String = row.get(0)?;
+        let rowid: Option<i64> = row.get(1)?;
+        let parent: String = row.get(2)?;
+        problems.push(match rowid {
+            Some(rowid) => format!("row {rowid} of {table} references a missing {parent}"),
+            None => format!("a row of {table} references a missing {parent}"),
+        });
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(CodecovError::CorruptDatabase { problems })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{models, Report, ReportBuilder, SqliteReportBuilder};
+
+    fn build_report(db_file: &Path, paths: &[&str]) -> SqliteReport {
+        let mut builder = SqliteReportBuilder::open(db_file.to_path_buf()).unwrap();
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        for path in paths {
+            let file = builder.insert_file(path).unwrap();
+            builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no: 1,
+                    hits: Some(1),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report = build_report(&temp_dir.path().join("db.sqlite"), &["src/lib.rs"]);
+        let backup_file = temp_dir.path().join("backup.sqlite");
+
+        let mut steps = Vec::new();
+        report
+            .backup_to(&backup_file, |p| steps.push(p.remaining))
+            .unwrap();
+        assert_eq!(steps.last(), Some(&0));
+
+        let backup = SqliteReport::open_readonly(backup_file.clone()).unwrap();
+        assert_eq!(backup.list_files().unwrap(), report.list_files().unwrap());
+        assert_eq!(backup.totals().unwrap(), report.totals().unwrap());
+        drop(backup);
+
+        let expected_totals = report.totals().unwrap();
+        report
+            .conn
+            .execute("DELETE FROM coverage_sample", [])
+            .unwrap();
+        assert_ne!(report.totals().unwrap(), expected_totals);
+
+        let mut steps = 0;
+        report.restore_from(&backup_file, |_| steps += 1).unwrap();
+        assert!(steps > 0);
+        assert_eq!(report.totals().unwrap(), expected_totals);
+        assert_eq!(report.list_files().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_source() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report = build_report(&temp_dir.path().join("db.sqlite"), &["src/lib.rs"]);
+        let expected_totals = report.totals().unwrap();
+
+        let garbage = temp_dir.path().join("garbage.sqlite");
+        std::fs::write(&garbage, vec![b'x'; 8192]).unwrap();
+        assert!(report.restore_from(&garbage, |_| {}).is_err());
+
+        // A sample pointing at a file that doesn't exist
+        let dangling = temp_dir.path().join("dangling.sqlite");
+        let other = build_report(&dangling, &["src/main.rs"]);
+        other
+            .conn
+            .execute_batch(
+                "PRAGMA foreign_keys = OFF;
+                 DELETE FROM source_file;",
+            )
+            .unwrap();
+        drop(other);
+        assert!(matches!(
+            report.restore_from(&dangling, |_| {}),
+            Err(CodecovError::CorruptDatabase { .. })
+        ));
+
+        assert_eq!(report.totals().unwrap(), expected_totals);
+    }
+}
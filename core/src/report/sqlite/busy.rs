@@ -0,0 +1,101 @@
+use std::{thread, time::Duration};
+
+use rusqlite::{
+    backup::{Backup, StepResult},
+    ffi, ErrorCode,
+};
+
+/// How long a connection waits for another connection's lock before failing
+/// with `SQLITE_BUSY`, unless it's set otherwise with
+/// [`SqliteReportBuilder::set_busy_policy`](super::SqliteReportBuilder::set_busy_policy)
+/// or [`SqliteReport::set_busy_timeout`](super::SqliteReport::set_busy_timeout).
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How a [`SqliteReportBuilder`](super::SqliteReportBuilder) waits for other
+/// connections, e.g. another worker's builder for the same report, to
+/// release their locks. See
+/// [`SqliteReportBuilder::set_busy_policy`](super::SqliteReportBuilder::set_busy_policy).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct BusyPolicy {
+    /// How long SQLite itself waits for a lock before giving up.
+    pub timeout: Duration,
+
+    /// How many more times to try beginning or committing a transaction after
+    /// SQLite gave up waiting.
+    pub max_retries: u32,
+
+    /// How long to sleep before the first retry. Each retry after that sleeps
+    /// twice as long as the one before.
+    pub backoff: Duration,
+}
+
+impl BusyPolicy {
+    /// How [`backup_step`] waits for other connections. SQLite's busy timeout
+    /// doesn't apply to backups, so this retries for about as long as
+    /// [`DEFAULT_BUSY_TIMEOUT`] instead.
+    pub(crate) const BACKUP: BusyPolicy = BusyPolicy {
+        timeout: DEFAULT_BUSY_TIMEOUT,
+        max_retries: 9,
+        backoff: Duration::from_millis(10),
+    };
+}
+
+impl Default for BusyPolicy {
+    fn default() -> BusyPolicy {
+        BusyPolicy {
+            timeout: DEFAULT_BUSY_TIMEOUT,
+            max_retries: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl BusyPolicy {
+    /// Runs `op`, retrying it with backoff while it fails because another
+    /// connection holds a lock. `op` must be safe to repeat after failing
+    /// this way, like `BEGIN` or `COMMIT`.
+    pub(crate) fn retry<T>(
+        &self,
+        mut op: impl FnMut() -> rusqlite::Result<T>,
+    ) -> rusqlite::Result<T> {
+        let mut backoff = self.backoff;
+        for _attempt in 0..self.max_retries {
+            match op() {
+                Err(e) if is_busy(&e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(attempt = _attempt, ?backoff, "database is busy, retrying");
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+        op()
+    }
+}
+
+/// Whether `error` means another connection holds a lock we needed.
+pub(crate) fn is_busy(error: &rusqlite::Error) -> bool {
+    matches!(
+        error.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Copies up to `pages` pages with `backup`, retrying with
+/// [`BusyPolicy::BACKUP`] while another connection has either side locked.
+/// Fails with an error [`is_busy`] recognizes if it's still locked after
+/// that, rather than waiting forever.
+pub(crate) fn backup_step(backup: &Backup, pages: i32) -> rusqlite::Result<StepResult> {
+    BusyPolicy::BACKUP.retry(|| match backup.step(pages)? {
+        StepResult::Busy => Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_BUSY),
+            None,
+        )),
+        StepResult::Locked => Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_LOCKED),
+            None,
+        )),
+        result => Ok(result),
+    })
+}
@@ -0,0 +1,218 @@
+//! Detecting reports that were damaged after they were finalized.
+//!
+//! [`SqliteReport::finalize_with_checksum`] hashes a canonical dump of every
+//! table except `report_metadata` and records the digest in
+//! `report_metadata`. [`SqliteReport::verify_checksum`] dumps the tables again
+//! and compares. A dump lists each table's name and columns followed by its
+//! rows, every value rendered with SQLite's `quote()` and the rows sorted by
+//! all of their columns, so the digest doesn't depend on the order rows were
+//! inserted in or where they landed in the file.
+//!
+//! The digest is a 64-bit SeaHash, which catches accidental damage like a
+//! partial copy or a corrupted download. It doesn't detect tampering: it isn't
+//! cryptographic, and anyone who can edit a report can recompute its checksum,
+//! so a valid checksum says nothing about who produced the report.
+
+use std::hash::{Hash, Hasher};
+
+use rusqlite::OptionalExtension;
+
+use super::SqliteReport;
+use crate::error::Result;
+
+/// The `report_metadata` key the checksum is stored under.
+const CHECKSUM_KEY: &str = "checksum";
+
+/// What [`SqliteReport::verify_checksum`] found.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ChecksumStatus {
+    /// The report was never finalized with
+    /// [`SqliteReport::finalize_with_checksum`].
+    Missing,
+
+    /// The report's contents match its checksum.
+    Valid,
+
+    /// The report's contents changed after it was finalized, e.g. because it
+    /// was damaged in storage or in transit.
+    Mismatch {
+        /// The checksum recorded when the report was finalized.
+        expected: String,
+
+        /// The checksum of the report's current contents.
+        found: String,
+    },
+}
+
+/// Hashes a canonical dump of every table in the report except
+/// `report_metadata`.
+fn content_checksum(report: &SqliteReport) -> Result<String> {
+    let tables = report
+        .prepare_cached(
+            "SELECT name FROM sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name <> 'report_metadata'
+             ORDER BY name",
+        )?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    let mut hasher = seahash::SeaHasher::new();
+    for table in &tables {
+        let columns = report
+            .prepare_cached("SELECT name FROM pragma_table_info(?1) ORDER BY cid")?
+            .query_map([table], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        table.hash(&mut hasher);
+        columns.hash(&mut hasher);
+
+        let quoted = columns
+            .iter()
+            .map(|column| format!("quote(\"{column}\")"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let order = columns
+            .iter()
+            .map(|column| format!("\"{column}\""))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut stmt = report.prepare_cached(&format!(
+            "SELECT {quoted} FROM main.\"{table}\" ORDER BY {order}"
+        ))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            for i in 0..columns.len() {
+                row.get::<_, String>(i)?.hash(&mut hasher);
+            }
+        }
+        // Keeps rows from one table from lining up with another's
+        "\n".hash(&mut hasher);
+    }
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+impl SqliteReport {
+    /// Computes a checksum of the report's contents, records it in the
+    /// report, and returns it. Call this once the report is done changing,
+    /// e.g. before uploading it somewhere, so whoever downloads it can check
+    /// it with [`SqliteReport::verify_checksum`] without validating every
+    /// row. Anything that changes the report afterwards, including opening a
+    /// [`SqliteReportBuilder`](super::SqliteReportBuilder) on it, means it has
+    /// to be finalized again.
+    pub fn finalize_with_checksum(&mut self) -> Result<String> {
+        let checksum = content_checksum(self)?;
+        self.conn.execute(
+            "INSERT INTO report_metadata (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            (CHECKSUM_KEY, &checksum),
+        )?;
+        Ok(checksum)
+    }
+
+    /// Checks the report's contents against the checksum recorded by
+    /// [`SqliteReport::finalize_with_checksum`]. A report that was cut short
+    /// usually can't be read at all, in which case this fails with the
+    /// error SQLite gave.
+    pub fn verify_checksum(&self) -> Result<ChecksumStatus> {
+        let expected: Option<String> = self
+            .prepare_cached("SELECT value FROM report_metadata WHERE key = ?1")?
+            .query_row([CHECKSUM_KEY], |row| row.get(0))
+            .optional()?;
+        let Some(expected) = expected else {
+            return Ok(ChecksumStatus::Missing);
+        };
+
+        let found = content_checksum(self)?;
+        Ok(if found == expected {
+            ChecksumStatus::Valid
+        } else {
+            ChecksumStatus::Mismatch { expected, found }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{models, ReportBuilder, SqliteReportBuilder};
+
+    fn build_report(builder: &mut SqliteReportBuilder) {
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let file = builder.insert_file("src/lib.rs").unwrap();
+        for line_no in 1..=3 {
+            builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    hits: Some(line_no % 2),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_finalize_and_verify_checksum() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut builder = SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+        build_report(&mut builder);
+        let mut report = builder.build().unwrap();
+
+        assert_eq!(report.verify_checksum().unwrap(), ChecksumStatus::Missing);
+
+        let checksum = report.finalize_with_checksum().unwrap();
+        assert_eq!(report.verify_checksum().unwrap(), ChecksumStatus::Valid);
+
+        // Finalizing again doesn't hash the checksum it recorded
+        assert_eq!(report.finalize_with_checksum().unwrap(), checksum);
+
+        report
+            .conn
+            .execute("UPDATE coverage_sample SET hits = 5 WHERE line_no = 2", [])
+            .unwrap();
+        assert_eq!(
+            report.verify_checksum().unwrap(),
+            ChecksumStatus::Mismatch {
+                expected: checksum.clone(),
+                found: content_checksum(&report).unwrap(),
+            }
+        );
+
+        report
+            .conn
+            .execute("UPDATE coverage_sample SET hits = 0 WHERE line_no = 2", [])
+            .unwrap();
+        assert_eq!(report.verify_checksum().unwrap(), ChecksumStatus::Valid);
+
+        report
+            .conn
+            .execute("DELETE FROM coverage_sample WHERE line_no = 3", [])
+            .unwrap();
+        assert!(matches!(
+            report.verify_checksum().unwrap(),
+            ChecksumStatus::Mismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn test_checksum_ignores_row_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut builder = SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+        build_report(&mut builder);
+        let mut report = builder.build().unwrap();
+        report.finalize_with_checksum().unwrap();
+
+        // Rewriting a row moves it to the end without changing its contents
+        report
+            .conn
+            .execute_batch(
+                "CREATE TEMP TABLE moved AS SELECT * FROM coverage_sample WHERE line_no = 1;
+                 DELETE FROM coverage_sample WHERE line_no = 1;
+                 INSERT INTO coverage_sample SELECT * FROM moved;",
+            )
+            .unwrap();
+        assert_eq!(report.verify_checksum().unwrap(), ChecksumStatus::Valid);
+    }
+}
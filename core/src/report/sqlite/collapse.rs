@@ -0,0 +1,513 @@
+//! Storing uploads that recorded exactly the same coverage only once.
+//!
+//! Test matrices often upload the same coverage over and over, e.g. once per
+//! OS for code that doesn't vary by platform, and each copy costs as many rows
+//! as the first. [`SqliteReport::collapse_identical_uploads`] keeps the data
+//! of one upload from each group of identical ones and records the rest as
+//! aliases of it in `collapsed_upload`. The aliases keep their own
+//! `raw_upload` rows and tags. [`SqliteReport::expand_collapsed_uploads`]
+//! copies the data back.
+//!
+//! Nothing else reads through the aliases, so until a report is expanded its
+//! aliased uploads look like they have no coverage at all. Pyreport export
+//! expands a copy of the report first, and
+//! [`SqliteReportBuilder::open`](super::SqliteReportBuilder::open) expands
+//! automatically. Merging fails until both reports are expanded.
+
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+use rusqlite::Connection;
+
+use super::{models::Insertable, SqliteReport, StatementCounters};
+use crate::{
+    error::{CodecovError, Result},
+    report::models,
+};
+
+/// What [`SqliteReport::collapse_identical_uploads`] collapsed.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct CollapsedUploads {
+    /// The number of uploads that became aliases.
+    pub uploads: usize,
+
+    /// The number of rows deleted from those uploads.
+    pub rows: usize,
+}
+
+/// The tables with an upload's data, children before parents.
+const DATA_TABLES: &[&str] = &[
+    "context_assoc",
+    "span_data",
+    "method_data",
+    "branches_data",
+    "session_file_totals",
+    "line_attribute",
+    "coverage_sample",
+    "coverage_run",
+];
+
+/// Whether the database at `conn` (or the one attached as `schema`) has any
+/// collapsed uploads.
+pub(super) fn has_collapsed(conn: &Connection, schema: &str) -> Result<bool> {
+    Ok(conn.query_row(
+        &format!("SELECT EXISTS (SELECT 1 FROM {schema}.collapsed_upload)"),
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+/// Fails if the database at `conn` or the one attached as `schema` has
+/// collapsed uploads, which merging doesn't know how to combine.
+pub(super) fn ensure_expanded(conn: &Connection, schema: &str) -> Result<()> {
+    for schema in ["main", schema] {
+        if has_collapsed(conn, schema)? {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "{schema} has collapsed uploads, expand them before merging"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Gives every alias of upload `canonical_raw_upload_id` its own copy of the
+/// upload's data, before the upload is deleted or changed.
+pub(super) fn expand_aliases_of(
+    conn: &Connection,
+    statement_cache: &StatementCounters,
+    canonical_raw_upload_id: i64,
+) -> Result<()> {
+    let aliases = statement_cache
+        .prepare_cached(
+            conn,
+            "SELECT raw_upload_id FROM collapsed_upload WHERE canonical_raw_upload_id = ?1",
+        )?
+        .query_map([canonical_raw_upload_id], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+    for alias in aliases {
+        copy_upload_data(conn, statement_cache, canonical_raw_upload_id, alias)?;
+    }
+    statement_cache
+        .prepare_cached(
+            conn,
+            "DELETE FROM collapsed_upload WHERE canonical_raw_upload_id = ?1",
+        )?
+        .execute([canonical_raw_upload_id])?;
+    Ok(())
+}
+
+/// Gives every alias its own copy of its canonical upload's data. Returns the
+/// number of uploads expanded.
+pub(super) fn expand_collapsed(conn: &mut Connection) -> Result<usize> {
+    let tx = conn.transaction()?;
+    let statement_cache = StatementCounters::default();
+    let canonical_ids = tx
+        .prepare("SELECT DISTINCT canonical_raw_upload_id FROM collapsed_upload")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+    let expanded: usize = tx.query_row("SELECT count(*) FROM collapsed_upload", [], |row| {
+        row.get(0)
+    })?;
+    for canonical_raw_upload_id in canonical_ids {
+        expand_aliases_of(&tx, &statement_cache, canonical_raw_upload_id)?;
+    }
+    tx.commit()?;
+    Ok(expanded)
+}
+
+/// Copies the data of upload `from` to upload `to`, keeping its local IDs.
+fn copy_upload_data(
+    conn: &Connection,
+    statement_cache: &StatementCounters,
+    from: i64,
+    to: i64,
+) -> Result<()> {
+    fn copy<T: Insertable>(
+        conn: &Connection,
+        statement_cache: &StatementCounters,
+        extra_fields: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<()> {
+        let fields = T::FIELDS.join(", ");
+        let values = T::FIELDS
+            .iter()
+            .map(|&field| {
+                if field == "raw_upload_id" {
+                    "?2"
+                } else {
+                    field
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let query = format!(
+            "INSERT INTO main.{table} ({fields}{extra_fields}) SELECT {values}{extra_fields} FROM main.{table} WHERE raw_upload_id = ?1",
+            table = T::TABLE_NAME,
+        );
+        statement_cache
+            .prepare_cached(conn, &query)?
+            .execute([from, to])?;
+        Ok(())
+    }
+
+    // Parents before children, so foreign keys are never dangling
+    copy::<models::CoverageSample>(conn, statement_cache, ", superseded", from, to)?;
+    statement_cache
+        .prepare_cached(
+            conn,
+            "INSERT INTO main.coverage_run (raw_upload_id, first_local_sample_id, source_file_id, first_line_no, last_line_no, hits) SELECT ?2, first_local_sample_id, source_file_id, first_line_no, last_line_no, hits FROM main.coverage_run WHERE raw_upload_id = ?1",
+        )?
+        .execute([from, to])?;
+    copy::<models::SessionFileTotals>(conn, statement_cache, "", from, to)?;
+    copy::<models::LineAttribute>(conn, statement_cache, "", from, to)?;
+    copy::<models::BranchesData>(conn, statement_cache, ", superseded", from, to)?;
+    copy::<models::MethodData>(conn, statement_cache, ", superseded", from, to)?;
+    Ok(())
+}
+
+/// Queries for everything an upload recorded, as one JSON row per record in a
+/// stable order, ignoring the IDs it was recorded under. Local sample IDs are
+/// replaced with each sample's rank in the upload, so uploads parsed side by
+/// side with interleaved IDs still match.
+fn upload_data_queries() -> [String; 5] {
+    const RANKED: &str = "WITH ranked AS (SELECT local_sample_id, row_number() OVER (ORDER BY source_file_id, line_no, coverage_type, superseded, local_sample_id) AS rank FROM coverage_sample WHERE raw_upload_id = ?1)";
+    [
+        format!("{RANKED} SELECT json_array(ranked.rank, source_file_id, line_no, coverage_type, hits, hit_branches, total_branches, messages, superseded) FROM coverage_sample INNER JOIN ranked USING (local_sample_id) WHERE raw_upload_id = ?1 ORDER BY ranked.rank"),
+        format!("{RANKED} SELECT json_array(ranked.rank, source_file_id, hits, branch_format, branch, superseded) FROM branches_data LEFT JOIN ranked USING (local_sample_id) WHERE raw_upload_id = ?1 ORDER BY 1"),
+        format!("{RANKED} SELECT json_array(ranked.rank, source_file_id, line_no, hit_branches, total_branches, hit_complexity_paths, total_complexity, name, signature, superseded) FROM method_data LEFT JOIN ranked USING (local_sample_id) WHERE raw_upload_id = ?1 ORDER BY 1"),
+        "SELECT json_array(source_file_id, files, lines, hits, misses, partials, coverage, branches, methods, messages, sessions, complexity, complexity_total, diff) FROM session_file_totals WHERE raw_upload_id = ?1 ORDER BY source_file_id".to_string(),
+        "SELECT json_array(source_file_id, line_no, attributes) FROM line_attribute WHERE raw_upload_id = ?1 ORDER BY source_file_id, line_no".to_string(),
+    ]
+}
+
+/// Hashes the rows of [`upload_data_queries`] for an upload, to find uploads
+/// that might be identical.
+fn upload_data_hash(report: &SqliteReport, raw_upload_id: i64) -> Result<u64> {
+    let mut hasher = seahash::SeaHasher::new();
+    for query in &upload_data_queries() {
+        let mut stmt = report.prepare_cached(query)?;
+        let mut rows = stmt.query([raw_upload_id])?;
+        while let Some(row) = rows.next()? {
+            row.get::<_, String>(0)?.hash(&mut hasher);
+        }
+        // Keeps rows from one table from lining up with another's
+        "\n".hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+/// Whether uploads `left` and `right` recorded exactly the same rows, to
+/// confirm that uploads with the same [`upload_data_hash`] really are
+/// identical.
+fn upload_data_equal(report: &SqliteReport, left: i64, right: i64) -> Result<bool> {
+    for query in &upload_data_queries() {
+        // A statement is taken out of the cache while it's in use, so the
+        // second of these is prepared anew
+        let mut left_stmt = report.prepare_cached(query)?;
+        let mut right_stmt = report.prepare_cached(query)?;
+        let mut left_rows = left_stmt.query([left])?;
+        let mut right_rows = right_stmt.query([right])?;
+        loop {
+            match (left_rows.next()?, right_rows.next()?) {
+                (None, None) => break,
+                (Some(left_row), Some(right_row)) => {
+                    if left_row.get::<_, String>(0)? != right_row.get::<_, String>(0)? {
+                        return Ok(false);
+                    }
+                }
+                _ => return Ok(false),
+            }
+        }
+    }
+    Ok(true)
+}
+
+impl SqliteReport {
+    /// Finds uploads that recorded exactly the same samples, branches,
+    /// methods, file totals and line attributes, and stores each group's data
+    /// only once, under the upload with the lowest ID. Uploads without any
+    /// samples, and uploads with spans or context associations, are left
+    /// alone.
+    ///
+    /// Until it's expanded with [`SqliteReport::expand_collapsed_uploads`],
+    /// the report reads as if the collapsed uploads had no coverage, except
+    /// when it's exported as a pyreport. Merging fails, and opening a
+    /// [`SqliteReportBuilder`](super::SqliteReportBuilder) on the report
+    /// expands it. The freed space isn't returned to the filesystem until
+    /// [`SqliteReport::compact`] is called.
+    pub fn collapse_identical_uploads(&mut self) -> Result<CollapsedUploads> {
+        let candidates = self
+            .prepare_cached(
+                "SELECT id FROM raw_upload
+                 WHERE EXISTS (SELECT 1 FROM coverage_sample WHERE coverage_sample.raw_upload_id = raw_upload.id)
+                 AND NOT EXISTS (SELECT 1 FROM span_data WHERE span_data.raw_upload_id = raw_upload.id)
+                 AND NOT EXISTS (SELECT 1 FROM context_assoc WHERE context_assoc.raw_upload_id = raw_upload.id)
+                 ORDER BY id",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+
+        // Uploads with the same hash are only candidates: each is compared
+        // row by row with the canonical uploads that share its hash, so a
+        // collision can't delete an upload's distinct data
+        let mut canonical: HashMap<u64, Vec<i64>> = HashMap::new();
+        let mut aliases = vec![];
+        for raw_upload_id in candidates {
+            let hash = upload_data_hash(self, raw_upload_id)?;
+            let group = canonical.entry(hash).or_default();
+            let mut found = None;
+            for &canonical_id in group.iter() {
+                if upload_data_equal(self, canonical_id, raw_upload_id)? {
+                    found = Some(canonical_id);
+                    break;
+                }
+            }
+            match found {
+                Some(canonical_id) => aliases.push((raw_upload_id, canonical_id)),
+                None => group.push(raw_upload_id),
+            }
+        }
+
+        let mut collapsed = CollapsedUploads::default();
+        let tx = self.conn.transaction()?;
+        for (raw_upload_id, canonical_id) in aliases {
+            // An upload that was already canonical hands its aliases over
+            tx.execute(
+                "UPDATE collapsed_upload SET canonical_raw_upload_id = ?2 WHERE canonical_raw_upload_id = ?1",
+                (raw_upload_id, canonical_id),
+            )?;
+            for table in DATA_TABLES {
+                collapsed.rows += tx.execute(
+                    &format!("DELETE FROM main.{table} WHERE raw_upload_id = ?1"),
+                    [raw_upload_id],
+                )?;
+            }
+            tx.execute(
+                "INSERT INTO collapsed_upload (raw_upload_id, canonical_raw_upload_id) VALUES (?1, ?2)",
+                (raw_upload_id, canonical_id),
+            )?;
+            collapsed.uploads += 1;
+        }
+        tx.commit()?;
+        Ok(collapsed)
+    }
+
+    /// Gives every upload collapsed by
+    /// [`SqliteReport::collapse_identical_uploads`] its own copy of its data
+    /// again. Returns the number of uploads expanded.
+    pub fn expand_collapsed_uploads(&mut self) -> Result<usize> {
+        expand_collapsed(&mut self.conn)
+    }
+
+    /// Whether the report has any uploads collapsed by
+    /// [`SqliteReport::collapse_identical_uploads`].
+    pub fn has_collapsed_uploads(&self) -> Result<bool> {
+        has_collapsed(&self.conn, "main")
+    }
+}
+
+#[cfg(all(test, feature = "pyreport"))]
+mod tests {
+    use std::{
+        fs::File,
+        io::{Read, Seek},
+        path::PathBuf,
+    };
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{
+        pyreport::ToPyreport, sqlite::SnapshotTarget, MergePolicy, Report, ReportBuilder,
+        SqliteReportBuilder,
+    };
+
+    /// Builds a report where the first two uploads record the same lines and
+    /// branches, with their samples inserted in turn so their local IDs
+    /// interleave, and the third misses a line the others hit.
+    fn build_report(db_file: PathBuf) -> (SqliteReport, Vec<i64>) {
+        let mut builder = SqliteReportBuilder::open(db_file).unwrap();
+        let uploads: Vec<_> = (0..3)
+            .map(|_| builder.insert_raw_upload(Default::default()).unwrap().id)
+            .collect();
+        let file = builder.insert_file("src/lib.rs").unwrap();
+        for line_no in 1..=4 {
+            for (i, &raw_upload_id) in uploads.iter().enumerate() {
+                let is_branch = line_no == 4;
+                let sample = builder
+                    .insert_coverage_sample(models::CoverageSample {
+                        raw_upload_id,
+                        source_file_id: file.id,
+                        line_no,
+                        coverage_type: if is_branch {
+                            models::CoverageType::Branch
+                        } else {
+                            models::CoverageType::Line
+                        },
+                        hits: Some(if i == 2 && line_no == 2 { 0 } else { 1 }),
+                        hit_branches: is_branch.then_some(1),
+                        total_branches: is_branch.then_some(2),
+                        ..Default::default()
+                    })
+                    .unwrap();
+                if is_branch {
+                    builder
+                        .insert_branches_data(models::BranchesData {
+                            raw_upload_id,
+                            source_file_id: file.id,
+                            local_sample_id: sample.local_sample_id,
+                            hits: 0,
+                            branch_format: models::BranchFormat::Condition,
+                            branch: "1".to_string(),
+                            ..Default::default()
+                        })
+                        .unwrap();
+                }
+            }
+        }
+        (builder.build().unwrap(), uploads)
+    }
+
+    /// Every sample, with its branches, keyed by its upload and line instead
+    /// of its local ID.
+    fn samples_by_upload(report: &SqliteReport) -> Vec<(i64, i64, Option<i64>, usize)> {
+        let mut samples: Vec<_> = report
+            .list_coverage_samples()
+            .unwrap()
+            .into_iter()
+            .map(|sample| {
+                let branches = report.list_branches_for_sample(&sample).unwrap().len();
+                (sample.raw_upload_id, sample.line_no, sample.hits, branches)
+            })
+            .collect();
+        samples.sort();
+        samples
+    }
+
+    fn export(report: &SqliteReport, dir: &TempDir, name: &str) -> (String, String) {
+        let report_json_path = dir.path().join(format!("{name}.json"));
+        let chunks_path = dir.path().join(format!("{name}.txt"));
+        report
+            .to_pyreport(
+                &mut File::create(&report_json_path).unwrap(),
+                &mut File::create(&chunks_path).unwrap(),
+            )
+            .unwrap();
+        let read = |path: PathBuf| {
+            let mut contents = String::new();
+            File::open(path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        (read(report_json_path), read(chunks_path))
+    }
+
+    #[test]
+    fn test_upload_data_equal() {
+        let temp_dir = TempDir::new().unwrap();
+        let (report, uploads) = build_report(temp_dir.path().join("db.sqlite"));
+
+        assert!(upload_data_equal(&report, uploads[0], uploads[1]).unwrap());
+        assert!(upload_data_equal(&report, uploads[1], uploads[0]).unwrap());
+        assert!(!upload_data_equal(&report, uploads[0], uploads[2]).unwrap());
+
+        // An upload with a subset of another's rows isn't equal to it either way
+        report
+            .conn
+            .execute(
+                "DELETE FROM branches_data WHERE raw_upload_id = ?1",
+                [uploads[1]],
+            )
+            .unwrap();
+        assert!(!upload_data_equal(&report, uploads[0], uploads[1]).unwrap());
+        assert!(!upload_data_equal(&report, uploads[1], uploads[0]).unwrap());
+    }
+
+    #[test]
+    fn test_collapse_and_expand() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut report, uploads) = build_report(temp_dir.path().join("db.sqlite"));
+        let expected_samples = samples_by_upload(&report);
+        let expected_pyreport = export(&report, &temp_dir, "expected");
+
+        let collapsed = report.collapse_identical_uploads().unwrap();
+        // The second upload's 4 samples and 1 branch
+        assert_eq!(
+            collapsed,
+            CollapsedUploads {
+                uploads: 1,
+                rows: 5
+            }
+        );
+        assert!(report.has_collapsed_uploads().unwrap());
+        // The lower of the identical uploads' IDs is kept
+        let alias = uploads[0].max(uploads[1]);
+        assert!(report
+            .list_coverage_samples()
+            .unwrap()
+            .iter()
+            .all(|sample| sample.raw_upload_id != alias));
+        // The alias is still an upload
+        assert_eq!(report.list_raw_uploads().unwrap().len(), 3);
+
+        // Collapsing again finds nothing new
+        assert_eq!(
+            report.collapse_identical_uploads().unwrap(),
+            CollapsedUploads::default()
+        );
+
+        // Exports see every upload's data
+        assert_eq!(export(&report, &temp_dir, "collapsed"), expected_pyreport);
+
+        assert_eq!(report.expand_collapsed_uploads().unwrap(), 1);
+        assert!(!report.has_collapsed_uploads().unwrap());
+        assert_eq!(samples_by_upload(&report), expected_samples);
+    }
+
+    #[test]
+    fn test_collapsed_uploads_survive_canonical_deletion() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut report, uploads) = build_report(temp_dir.path().join("db.sqlite"));
+        let alias = uploads[0].max(uploads[1]);
+        let expected = report.snapshot_uploads(SnapshotTarget::Memory, &[alias]);
+        let expected = export(&expected.unwrap(), &temp_dir, "expected");
+        report.collapse_identical_uploads().unwrap();
+
+        // Filtering out the canonical upload deletes it from the copy
+        let mut report_json = tempfile::tempfile().unwrap();
+        let mut chunks = tempfile::tempfile().unwrap();
+        report
+            .to_pyreport_filtered(&mut report_json, &mut chunks, &[alias])
+            .unwrap();
+        let mut actual = (String::new(), String::new());
+        report_json.rewind().unwrap();
+        report_json.read_to_string(&mut actual.0).unwrap();
+        chunks.rewind().unwrap();
+        chunks.read_to_string(&mut actual.1).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_builder_and_merge_expand() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_file = temp_dir.path().join("db.sqlite");
+        let (mut report, _) = build_report(db_file.clone());
+        let expected_samples = samples_by_upload(&report);
+        report.collapse_identical_uploads().unwrap();
+
+        let other_file = temp_dir.path().join("other.sqlite");
+        let mut other = SqliteReport::open(other_file).unwrap();
+        assert!(other.merge(&report, MergePolicy::KeepBoth).is_err());
+        drop(report);
+
+        let report = SqliteReportBuilder::open(db_file).unwrap().build().unwrap();
+        assert!(!report.has_collapsed_uploads().unwrap());
+        assert_eq!(samples_by_upload(&report), expected_samples);
+        other.merge(&report, MergePolicy::KeepBoth).unwrap();
+    }
+}
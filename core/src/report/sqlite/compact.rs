@@ -0,0 +1,456 @@
+//! Managing the on-disk footprint of report artifacts we keep around
+//! long-term.
+
+use std::collections::BTreeMap;
+
+use super::{runs::install_run_view, SqliteReport};
+use crate::{error::Result, report::LabelPruning};
+
+/// Options for [`SqliteReport::compact`].
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct CompactOptions {
+    /// Delete every context and context association. These record which
+    /// tests (or other contexts) covered each line, which is often the bulk
+    /// of a report and isn't needed once it's been used to pick tests to run.
+    /// The tables themselves are kept so the report can still be queried.
+    pub drop_contexts: bool,
+
+    /// Store runs of identical line coverage as single rows with
+    /// [`SqliteReport::pack_coverage_runs`] before rebuilding the file.
+    pub pack_coverage_runs: bool,
+}
+
+/// Which labels [`SqliteReport::strip_labels`] removes. Labels are the
+/// contexts attached to individual lines, e.g. the tests that covered them.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum LabelRetention {
+    /// Remove every label.
+    DropAll,
+
+    /// Remove labels that are attached to more than this many lines. A test
+    /// that covers most of the codebase isn't much use for picking tests to
+    /// run but costs as much space as all the narrow ones put together.
+    DropAbove(usize),
+}
+
+/// What [`SqliteReport::strip_labels`] or [`SqliteReport::prune_labels`]
+/// removed.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct StrippedLabels {
+    /// The number of [`Context`](crate::report::models::Context)s deleted.
+    pub contexts: usize,
+
+    /// The number of [`ContextAssoc`](crate::report::models::ContextAssoc)s
+    /// deleted.
+    pub assocs: usize,
+}
+
+/// How much space a report takes up on disk.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct SizeStats {
+    /// The size of the database file.
+    pub total_bytes: u64,
+
+    /// The space used by each table, including its indexes. SQLite's own
+    /// tables (e.g. `sqlite_schema`) are listed as well.
+    pub tables: BTreeMap<String, u64>,
+}
+
+impl SqliteReport {
+    /// Shrinks the database file by rebuilding it without free pages, and
+    /// refreshes the statistics SQLite uses to plan queries.
+    pub fn compact(&mut self, options: &CompactOptions) -> Result<()> {
+        if options.drop_contexts {
+            let tx = self.conn.transaction()?;
+            tx.execute_batch("DELETE FROM context_assoc; DELETE FROM context;")?;
+            tx.commit()?;
+        }
+        if options.pack_coverage_runs {
+            self.pack_coverage_runs()?;
+        }
+        // Rebuilding indexes trips over the view that expands packed runs
+        self.conn
+            .execute_batch("DROP VIEW IF EXISTS temp.coverage_sample; VACUUM; ANALYZE;")?;
+        install_run_view(&self.conn)?;
+        Ok(())
+    }
+
+    /// Removes labels from a report that has already been built, keeping its
+    /// coverage data. Associations with whole uploads or files, like flags,
+    /// are left alone, and a context is only deleted if nothing else refers
+    /// to it. The freed space isn't returned to the filesystem until
+    /// [`SqliteReport::compact`] is called.
+    ///
+    /// To leave labels out while parsing instead, see
+    /// [`ParseOptions::drop_labels`](crate::parsers::pyreport::ParseOptions::drop_labels).
+    pub fn strip_labels(&mut self, retention: LabelRetention) -> Result<StrippedLabels> {
+        let tx = self.conn.transaction()?;
+        let stripped = match retention {
+            LabelRetention::DropAll => {
+                // Only contexts that were used as labels may be deleted, not
+                // e.g. ones that were never associated with anything
+                tx.execute(
+                    "CREATE TEMP TABLE stripped_label AS
+                     SELECT DISTINCT context_id FROM context_assoc
+                     WHERE local_sample_id IS NOT NULL OR local_span_id IS NOT NULL",
+                    [],
+                )?;
+                let assocs = tx.execute(
+                    "DELETE FROM context_assoc WHERE local_sample_id IS NOT NULL OR local_span_id IS NOT NULL",
+                    [],
+                )?;
+                let contexts = tx.execute(
+                    "DELETE FROM context WHERE id IN (SELECT context_id FROM temp.stripped_label)
+                     AND NOT EXISTS (SELECT 1 FROM context_assoc WHERE context_id = context.id)",
+                    [],
+                )?;
+                tx.execute("DROP TABLE temp.stripped_label", [])?;
+                StrippedLabels { contexts, assocs }
+            }
+            LabelRetention::DropAbove(max_lines) => {
+                let context_ids = {
+                    let mut stmt = tx.prepare(
+                        "SELECT context_id FROM context_assoc WHERE local_sample_id IS NOT NULL OR local_span_id IS NOT NULL GROUP BY context_id HAVING count(*) > ?1",
+                    )?;
+                    let context_ids = stmt
+                        .query_map([max_lines as i64], |row| row.get::<_, i64>(0))?
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    context_ids
+                };
+
+                let mut stripped = StrippedLabels::default();
+                let mut delete_assocs = tx.prepare(
+                    "DELETE FROM context_assoc WHERE context_id = ?1 AND (local_sample_id IS NOT NULL OR local_span_id IS NOT NULL)",
+                )?;
+                let mut delete_context = tx.prepare(
+                    "DELETE FROM context WHERE id = ?1 AND NOT EXISTS (SELECT 1 FROM context_assoc WHERE context_id = ?1)",
+                )?;
+                for context_id in context_ids {
+                    stripped.assocs += delete_assocs.execute([context_id])?;
+                    stripped.contexts += delete_context.execute([context_id])?;
+                }
+                stripped
+            }
+        };
+        tx.commit()?;
+        Ok(stripped)
+    }
+
+    /// Removes the labels on each sample that `pruning` says to leave off,
+    /// along with any context that was only used by them. When
+    /// [`LabelPruning::max_labels_per_sample`] is set, the labels that come
+    /// first by name are kept. Labels on spans, uploads and files are left
+    /// alone. Like [`SqliteReport::strip_labels`], the freed space isn't
+    /// returned to the filesystem until [`SqliteReport::compact`] is called.
+    ///
+    /// To prune labels while parsing instead, see
+    /// [`ParseOptions::label_pruning`](crate::parsers::pyreport::ParseOptions::label_pruning).
+    pub fn prune_labels(&mut self, pruning: LabelPruning) -> Result<StrippedLabels> {
+        if !pruning.is_enabled() {
+            return Ok(StrippedLabels::default());
+        }
+        let max_labels = pruning.max_labels_per_sample.map(|max| max as i64);
+
+        let tx = self.conn.transaction()?;
+        // A sample is fully covered under the same rules as
+        // `LineStatus::of_sample`
+        tx.execute(
+            "CREATE TEMP TABLE pruned_label AS
+             SELECT assoc_rowid, context_id FROM (
+                 SELECT
+                     context_assoc.rowid AS assoc_rowid,
+                     context_assoc.context_id,
+                     coverage_sample.hits > 0
+                         OR coverage_sample.hit_branches >= coverage_sample.total_branches AS hit,
+                     row_number() OVER (
+                         PARTITION BY context_assoc.raw_upload_id, context_assoc.local_sample_id
+                         ORDER BY context.name, context.id
+                     ) AS rank
+                 FROM context_assoc
+                 INNER JOIN context ON context.id = context_assoc.context_id
+                 LEFT JOIN coverage_sample
+                     ON coverage_sample.raw_upload_id = context_assoc.raw_upload_id
+                     AND coverage_sample.local_sample_id = context_assoc.local_sample_id
+                 WHERE context_assoc.local_sample_id IS NOT NULL
+             )
+             WHERE (?1 AND hit) OR rank > ?2",
+            (pruning.drop_fully_covered, max_labels),
+        )?;
+        let assocs = tx.execute(
+            "DELETE FROM context_assoc WHERE rowid IN (SELECT assoc_rowid FROM temp.pruned_label)",
+            [],
+        )?;
+        let contexts = tx.execute(
+            "DELETE FROM context WHERE id IN (SELECT context_id FROM temp.pruned_label)
+             AND NOT EXISTS (SELECT 1 FROM context_assoc WHERE context_id = context.id)",
+            [],
+        )?;
+        tx.execute("DROP TABLE temp.pruned_label", [])?;
+        tx.commit()?;
+        Ok(StrippedLabels { contexts, assocs })
+    }
+
+    /// Measures the space used by each table.
+    pub fn size_stats(&self) -> Result<SizeStats> {
+        let page_count: u64 = self
+            .conn
+            .pragma_query_value(None, "page_count", |row| row.get(0))?;
+        let page_size: u64 = self
+            .conn
+            .pragma_query_value(None, "page_size", |row| row.get(0))?;
+
+        // `dbstat` lists tables and indexes by their own names, so we use the
+        // schema to attribute each index to its table
+        let mut stmt = self.prepare_cached(
+            "SELECT coalesce(schema.tbl_name, dbstat.name) AS table_name, sum(dbstat.pgsize) AS bytes FROM dbstat LEFT JOIN sqlite_schema schema ON schema.name = dbstat.name GROUP BY 1 ORDER BY 1",
+        )?;
+        let tables = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<BTreeMap<String, u64>>>()?;
+
+        Ok(SizeStats {
+            total_bytes: page_count * page_size,
+            tables,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        report::{models, Report, ReportBuilder, SqliteReportBuilder},
+        test_utils::sqlite_report::build_sample_report,
+    };
+
+    #[test]
+    fn test_size_stats() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_file = temp_dir.path().join("db.sqlite");
+        let report = build_sample_report(db_file.clone()).unwrap();
+
+        let stats = report.size_stats().unwrap();
+        assert_eq!(
+            stats.total_bytes,
+            std::fs::metadata(&db_file).unwrap().len()
+        );
+        for table in ["coverage_sample", "context_assoc", "sqlite_schema"] {
+            assert!(stats.tables[table] > 0, "{table}");
+        }
+        // Indexes are counted under their tables
+        assert!(!stats.tables.contains_key("context_assoc_sample"));
+        assert!(stats.tables.values().sum::<u64>() <= stats.total_bytes);
+    }
+
+    #[test]
+    fn test_compact() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report = build_sample_report(temp_dir.path().join("db.sqlite")).unwrap();
+        let totals = report.totals().unwrap();
+
+        report.compact(&Default::default()).unwrap();
+        assert_eq!(report.totals().unwrap(), totals);
+        assert!(!report.list_contexts().unwrap().is_empty());
+
+        // Leave some free pages behind for `compact()` to clean up
+        report
+            .conn
+            .execute_batch(
+                "CREATE TABLE filler AS SELECT zeroblob(100000) AS data; DROP TABLE filler;",
+            )
+            .unwrap();
+        let before = report.size_stats().unwrap();
+        report
+            .compact(&CompactOptions {
+                drop_contexts: true,
+                ..Default::default()
+            })
+            .unwrap();
+        let after = report.size_stats().unwrap();
+        assert!(after.total_bytes < before.total_bytes);
+        assert!(report.list_contexts().unwrap().is_empty());
+        assert_eq!(
+            report.totals().unwrap(),
+            models::ReportTotals {
+                test_cases: 0,
+                ..totals
+            }
+        );
+    }
+
+    #[test]
+    fn test_strip_labels() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report_builder =
+            SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+        let upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        let flag = report_builder.insert_context("flag:unit").unwrap();
+        let test_wide = report_builder.insert_context("test_wide").unwrap();
+        let test_narrow = report_builder.insert_context("test_narrow").unwrap();
+        let _unused = report_builder.insert_context("test_unused").unwrap();
+
+        report_builder
+            .associate_context(models::ContextAssoc {
+                context_id: flag.id,
+                raw_upload_id: upload.id,
+                ..Default::default()
+            })
+            .unwrap();
+        for line_no in 1..=3 {
+            let sample = report_builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    hits: Some(1),
+                    ..Default::default()
+                })
+                .unwrap();
+            let mut label_ids = vec![test_wide.id];
+            if line_no == 1 {
+                label_ids.push(test_narrow.id);
+            }
+            for context_id in label_ids {
+                report_builder
+                    .associate_context(models::ContextAssoc {
+                        context_id,
+                        raw_upload_id: upload.id,
+                        local_sample_id: Some(sample.local_sample_id),
+                        source_file_id: Some(file.id),
+                        ..Default::default()
+                    })
+                    .unwrap();
+            }
+        }
+        let mut report = report_builder.build().unwrap();
+        let samples = report.list_coverage_samples().unwrap();
+
+        // Only `test_wide` covers more than 2 lines
+        let stripped = report.strip_labels(LabelRetention::DropAbove(2)).unwrap();
+        assert_eq!(
+            stripped,
+            StrippedLabels {
+                contexts: 1,
+                assocs: 3
+            }
+        );
+        let names: Vec<_> = report
+            .list_contexts()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, &["flag:unit", "test_narrow", "test_unused"]);
+        assert_eq!(
+            report.list_contexts_for_sample(&samples[0]).unwrap(),
+            std::slice::from_ref(&test_narrow)
+        );
+
+        // The flag is associated with the whole upload, and `test_unused` was
+        // never a label, so they're kept
+        let stripped = report.strip_labels(LabelRetention::DropAll).unwrap();
+        assert_eq!(
+            stripped,
+            StrippedLabels {
+                contexts: 1,
+                assocs: 1
+            }
+        );
+        let names: Vec<_> = report
+            .list_contexts()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, &["flag:unit", "test_unused"]);
+        assert_eq!(report.list_contexts_for_upload(&upload).unwrap(), &[flag]);
+        assert_eq!(report.list_coverage_samples().unwrap(), samples);
+    }
+
+    #[test]
+    fn test_prune_labels() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report_builder =
+            SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+        let upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        let tests: Vec<_> = ["test_a", "test_b", "test_c"]
+            .into_iter()
+            .map(|name| report_builder.insert_context(name).unwrap())
+            .collect();
+
+        // Line 1 is hit, line 2 is a partial branch and line 3 is missed, and
+        // every test is attached to all of them
+        let samples: Vec<_> = [
+            (Some(1), None, None),
+            (None, Some(1), Some(2)),
+            (Some(0), None, None),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (hits, hit_branches, total_branches))| {
+            report_builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no: i as i64 + 1,
+                    hits,
+                    hit_branches,
+                    total_branches,
+                    ..Default::default()
+                })
+                .unwrap()
+        })
+        .collect();
+        for sample in &samples {
+            for test in &tests {
+                report_builder
+                    .associate_context(models::ContextAssoc {
+                        context_id: test.id,
+                        raw_upload_id: upload.id,
+                        local_sample_id: Some(sample.local_sample_id),
+                        ..Default::default()
+                    })
+                    .unwrap();
+            }
+        }
+        let mut report = report_builder.build().unwrap();
+
+        assert_eq!(
+            report.prune_labels(LabelPruning::default()).unwrap(),
+            StrippedLabels::default()
+        );
+
+        let pruned = report
+            .prune_labels(LabelPruning {
+                max_labels_per_sample: Some(2),
+                drop_fully_covered: true,
+            })
+            .unwrap();
+        assert_eq!(
+            pruned,
+            StrippedLabels {
+                contexts: 1,
+                assocs: 5
+            }
+        );
+        assert!(report
+            .list_contexts_for_sample(&samples[0])
+            .unwrap()
+            .is_empty());
+        for sample in &samples[1..] {
+            assert_eq!(
+                report.list_contexts_for_sample(sample).unwrap(),
+                &tests[..2]
+            );
+        }
+        assert_eq!(report.list_contexts().unwrap(), &tests[..2]);
+    }
+}
@@ -0,0 +1,288 @@
+//! Cleaning up after CI jobs that upload the same coverage more than once.
+
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+use super::{delete_raw_upload, SqliteReport};
+use crate::{
+    error::Result,
+    report::{models, Report},
+};
+
+/// What [`SqliteReport::dedup_uploads`] does with redundant uploads.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum DedupPolicy {
+    /// Delete them and all of their data.
+    #[default]
+    Remove,
+    /// Move their context associations to the upload that's kept, then
+    /// delete them. Associations with a sample move to the kept upload's
+    /// samples on the same line. Associations with a span are dropped.
+    Merge,
+}
+
+/// Copies the context associations of upload `?1` to upload `?2`, skipping
+/// ones `?2` already has.
+const MERGE_CONTEXT_ASSOCS: &[&str] = &[
+    // Upload-wide and file-wide associations
+    "INSERT INTO context_assoc (context_id, raw_upload_id, source_file_id) SELECT DISTINCT assoc.context_id, ?2, assoc.source_file_id FROM context_assoc assoc WHERE assoc.raw_upload_id = ?1 AND assoc.local_sample_id IS NULL AND assoc.local_span_id IS NULL AND assoc.superseded = 0 AND NOT EXISTS (SELECT 1 FROM context_assoc existing WHERE existing.context_id = assoc.context_id AND existing.raw_upload_id = ?2 AND existing.local_sample_id IS NULL AND existing.local_span_id IS NULL AND existing.source_file_id IS assoc.source_file_id AND existing.superseded = 0)",
+    // Sample associations, matched up by line
+    "INSERT INTO context_assoc (context_id, raw_upload_id, local_sample_id, source_file_id) SELECT DISTINCT assoc.context_id, ?2, kept_sample.local_sample_id, assoc.source_file_id FROM context_assoc assoc INNER JOIN coverage_sample sample ON sample.raw_upload_id = assoc.raw_upload_id AND sample.local_sample_id = assoc.local_sample_id INNER JOIN coverage_sample kept_sample ON kept_sample.raw_upload_id = ?2 AND kept_sample.source_file_id = sample.source_file_id AND kept_sample.line_no = sample.line_no AND kept_sample.coverage_type = sample.coverage_type AND kept_sample.superseded = 0 WHERE assoc.raw_upload_id = ?1 AND assoc.local_span_id IS NULL AND assoc.superseded = 0 AND NOT EXISTS (SELECT 1 FROM context_assoc existing WHERE existing.context_id = assoc.context_id AND existing.raw_upload_id = ?2 AND existing.local_sample_id = kept_sample.local_sample_id)",
+];
+
+/// The coverage upload `?1` recorded, as one JSON row per sample in a stable
+/// order, ignoring the IDs it was recorded under. A collapsed upload's
+/// coverage is its canonical upload's.
+const COVERAGE_CONTENT: &str = "SELECT json_array(source_file_id, line_no, coverage_type, hits, hit_branches, total_branches) FROM coverage_sample WHERE raw_upload_id = coalesce((SELECT canonical_raw_upload_id FROM collapsed_upload WHERE raw_upload_id = ?1), ?1) AND superseded = 0 ORDER BY source_file_id, line_no, coverage_type, hits, hit_branches, total_branches";
+
+/// Hashes the rows of [`COVERAGE_CONTENT`] for an upload, to find uploads that
+/// might have recorded the same coverage.
+fn coverage_content_hash(report: &SqliteReport, raw_upload_id: i64) -> Result<u64> {
+    let mut stmt = report.prepare_cached(COVERAGE_CONTENT)?;
+    let mut rows = stmt.query([raw_upload_id])?;
+    let mut hasher = seahash::SeaHasher::new();
+    while let Some(row) = rows.next()? {
+        row.get::<_, String>(0)?.hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+/// Whether uploads `left` and `right` recorded exactly the same coverage, to
+/// confirm that uploads with the same [`coverage_content_hash`] really did.
+fn coverage_content_equal(report: &SqliteReport, left: i64, right: i64) -> Result<bool> {
+    // A statement is taken out of the cache while it's in use, so the second
+    // of these is prepared anew
+    let mut left_stmt = report.prepare_cached(COVERAGE_CONTENT)?;
+    let mut right_stmt = report.prepare_cached(COVERAGE_CONTENT)?;
+    let mut left_rows = left_stmt.query([left])?;
+    let mut right_rows = right_stmt.query([right])?;
+    loop {
+        match (left_rows.next()?, right_rows.next()?) {
+            (None, None) => return Ok(true),
+            (Some(left_row), Some(right_row)) => {
+                if left_row.get::<_, String>(0)? != right_row.get::<_, String>(0)? {
+                    return Ok(false);
+                }
+            }
+            _ => return Ok(false),
+        }
+    }
+}
+
+impl SqliteReport {
+    /// Finds uploads with the same flags, job name and coverage, and keeps
+    /// only the earliest of each group (by timestamp, then ID). Uploads
+    /// without a job name are never considered redundant, and uploads are
+    /// compared sample by sample before one is removed. Returns the uploads
+    /// that were removed.
+    pub fn dedup_uploads(&mut self, policy: DedupPolicy) -> Result<Vec<models::RawUpload>> {
+        let mut uploads = self.list_raw_uploads()?;
+        uploads.sort_by_key(|upload| (upload.timestamp.is_none(), upload.timestamp, upload.id));
+
+        // Uploads with the same key are only candidates until their samples
+        // are compared, so a hash collision can't remove distinct coverage
+        let mut kept: HashMap<(String, String, u64), Vec<i64>> = HashMap::new();
+        let mut redundant = vec![];
+        for upload in uploads {
+            let Some(job_name) = &upload.job_name else {
+                continue;
+            };
+            let flags = upload
+                .flags
+                .as_ref()
+                .map(|flags| flags.to_string())
+                .unwrap_or_default();
+            let key = (
+                flags,
+                job_name.clone(),
+                coverage_content_hash(self, upload.id)?,
+            );
+            let group = kept.entry(key).or_default();
+            let mut found = None;
+            for &kept_id in group.iter() {
+                if coverage_content_equal(self, kept_id, upload.id)? {
+                    found = Some(kept_id);
+                    break;
+                }
+            }
+            match found {
+                Some(kept_id) => redundant.push((upload, kept_id)),
+                None => group.push(upload.id),
+            }
+        }
+
+        let tx = self.conn.transaction()?;
+        for (upload, kept_id) in &redundant {
+            if policy == DedupPolicy::Merge {
+                for stmt in MERGE_CONTEXT_ASSOCS {
+                    self.statement_cache
+                        .prepare_cached(&tx, stmt)?
+                        .execute([upload.id, *kept_id])?;
+                }
+            }
+            delete_raw_upload(&tx, &self.statement_cache, upload.id)?;
+        }
+        tx.commit()?;
+
+        Ok(redundant.into_iter().map(|(upload, _)| upload).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{sqlite::RepairMode, ReportBuilder, SqliteReportBuilder};
+
+    struct Ctx {
+        temp_dir: TempDir,
+    }
+
+    fn setup() -> Ctx {
+        Ctx {
+            temp_dir: TempDir::new().ok().unwrap(),
+        }
+    }
+
+    /// Builds a report with four uploads: two identical ones from `job 1`, one
+    /// from `job 1` with different coverage, and one from `job 2` with the
+    /// same coverage. Each is associated with its own upload-wide context and
+    /// a context on its sample for line 1.
+    fn build_report(ctx: &Ctx) -> (SqliteReport, Vec<models::RawUpload>) {
+        let mut report_builder =
+            SqliteReportBuilder::open(ctx.temp_dir.path().join("db.sqlite")).unwrap();
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+        let test_case = report_builder.insert_context("test_case").unwrap();
+
+        let mut uploads = vec![];
+        for (i, (job_name, hits)) in [("job 1", 1), ("job 1", 1), ("job 1", 2), ("job 2", 1)]
+            .into_iter()
+            .enumerate()
+        {
+            let upload = report_builder
+                .insert_raw_upload(models::RawUpload {
+                    timestamp: Some(100 + i as i64),
+                    job_name: Some(job_name.to_string()),
+                    flags: Some(json!(["unit"])),
+                    ..Default::default()
+                })
+                .unwrap();
+            let context = report_builder
+                .insert_context(&format!("upload {i}"))
+                .unwrap();
+            report_builder
+                .associate_context(models::ContextAssoc {
+                    context_id: context.id,
+                    raw_upload_id: upload.id,
+                    ..Default::default()
+                })
+                .unwrap();
+            for line_no in [1, 2] {
+                let sample = report_builder
+                    .insert_coverage_sample(models::CoverageSample {
+                        raw_upload_id: upload.id,
+                        source_file_id: file.id,
+                        line_no,
+                        hits: Some(hits),
+                        ..Default::default()
+                    })
+                    .unwrap();
+                if line_no == 1 {
+                    report_builder
+                        .associate_context(models::ContextAssoc {
+                            context_id: test_case.id,
+                            raw_upload_id: upload.id,
+                            local_sample_id: Some(sample.local_sample_id),
+                            ..Default::default()
+                        })
+                        .unwrap();
+                }
+            }
+            uploads.push(upload);
+        }
+
+        (report_builder.build().unwrap(), uploads)
+    }
+
+    fn upload_ids(report: &SqliteReport) -> Vec<i64> {
+        let mut ids: Vec<_> = report
+            .list_raw_uploads()
+            .unwrap()
+            .iter()
+            .map(|upload| upload.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_coverage_content_equal() {
+        let ctx = setup();
+        let (report, uploads) = build_report(&ctx);
+
+        assert!(coverage_content_equal(&report, uploads[0].id, uploads[1].id).unwrap());
+        assert!(!coverage_content_equal(&report, uploads[0].id, uploads[2].id).unwrap());
+        // Only coverage is compared, not job names
+        assert!(coverage_content_equal(&report, uploads[0].id, uploads[3].id).unwrap());
+    }
+
+    #[test]
+    fn test_dedup_uploads_remove() {
+        let ctx = setup();
+        let (mut report, uploads) = build_report(&ctx);
+
+        let removed = report.dedup_uploads(DedupPolicy::Remove).unwrap();
+        assert_eq!(removed, &uploads[1..2]);
+
+        let mut expected_ids = vec![uploads[0].id, uploads[2].id, uploads[3].id];
+        expected_ids.sort();
+        assert_eq!(upload_ids(&report), expected_ids);
+        assert_eq!(report.list_coverage_samples().unwrap().len(), 6);
+        assert_eq!(
+            report.list_contexts_for_upload(&uploads[0]).unwrap().len(),
+            1
+        );
+
+        // Running it again finds nothing
+        assert!(report
+            .dedup_uploads(DedupPolicy::Remove)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_dedup_uploads_merge() {
+        let ctx = setup();
+        let (mut report, uploads) = build_report(&ctx);
+
+        let removed = report.dedup_uploads(DedupPolicy::Merge).unwrap();
+        assert_eq!(removed, &uploads[1..2]);
+        assert_eq!(report.list_coverage_samples().unwrap().len(), 6);
+
+        let names: Vec<_> = report
+            .list_contexts_for_upload(&uploads[0])
+            .unwrap()
+            .into_iter()
+            .map(|context| context.name)
+            .collect();
+        assert_eq!(names, ["upload 0", "upload 1"]);
+
+        // The removed upload's sample context was already on the kept sample,
+        // so it isn't duplicated
+        let kept_sample = report
+            .list_coverage_samples()
+            .unwrap()
+            .into_iter()
+            .find(|sample| sample.raw_upload_id == uploads[0].id && sample.line_no == 1)
+            .unwrap();
+        assert_eq!(
+            report.list_contexts_for_sample(&kept_sample).unwrap().len(),
+            1
+        );
+        assert!(report.repair(RepairMode::DryRun).unwrap().is_empty());
+    }
+}
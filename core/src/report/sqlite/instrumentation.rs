@@ -0,0 +1,112 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use super::StatementCounters;
+
+/// Hooks that a [`super::SqliteReportBuilder`] calls as it writes to its
+/// database. Both methods have no-op default implementations so implementers
+/// can pick the events they care about.
+///
+/// # Examples
+///
+/// ```
+/// # use std::time::Duration;
+/// # use codecov_rs::report::sqlite::BuilderInstrumentation;
+/// #[derive(Default)]
+/// struct CommitLogger;
+///
+/// impl BuilderInstrumentation for CommitLogger {
+///     fn on_commit(&mut self, duration: Duration) {
+///         println!("committed in {duration:?}");
+///     }
+/// }
+/// ```
+pub trait BuilderInstrumentation {
+    /// Called when a transaction that wrote `rows` rows to `table` is
+    /// committed, once per insert it ran and before
+    /// [`BuilderInstrumentation::on_commit`]. Rows an upsert merged into an
+    /// existing row count as written. Inserts that were rolled back are never
+    /// reported.
+    fn on_insert(&mut self, _table: &'static str, _rows: usize) {}
+
+    /// Called after a transaction was committed. `duration` only covers the
+    /// `COMMIT` itself, not the statements run inside the transaction.
+    fn on_commit(&mut self, _duration: Duration) {}
+}
+
+/// Counters accumulated by a [`super::SqliteReportBuilder`] over its lifetime.
+/// Retrieve them with [`super::SqliteReportBuilder::stats`].
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct BuilderStats {
+    /// Number of rows inserted by committed transactions, keyed by table name.
+    /// See [`BuilderInstrumentation::on_insert`].
+    pub rows_inserted: BTreeMap<&'static str, u64>,
+
+    /// Number of transactions that were committed. Each non-transaction
+    /// [`crate::report::ReportBuilder`] call on a
+    /// [`super::SqliteReportBuilder`] runs in its own transaction unless it's
+    /// part of a batch (see [`super::BatchPolicy`]).
+    pub transactions: u64,
+
+    /// Wall-clock time spent executing inserts and commits.
+    pub sqlite_time: Duration,
+}
+
+/// Owned by a [`super::SqliteReportBuilder`] and mutably borrowed by each
+/// [`super::SqliteReportBuilderTx`] it creates.
+#[derive(Default)]
+pub(crate) struct Instrumentation {
+    pub stats: BuilderStats,
+    pub hooks: Option<Box<dyn BuilderInstrumentation + Send>>,
+    pub statement_cache: StatementCounters,
+
+    /// Inserts run in the open transaction, which aren't counted until it's
+    /// committed.
+    pending_inserts: Vec<(&'static str, usize)>,
+
+    /// The length of `pending_inserts` when each open savepoint was created.
+    savepoints: Vec<usize>,
+}
+
+impl Instrumentation {
+    pub fn record_insert(&mut self, table: &'static str, rows: usize, duration: Duration) {
+        self.stats.sqlite_time += duration;
+        self.pending_inserts.push((table, rows));
+    }
+
+    pub fn record_commit(&mut self, duration: Duration) {
+        self.stats.transactions += 1;
+        self.stats.sqlite_time += duration;
+        self.savepoints.clear();
+        for (table, rows) in self.pending_inserts.drain(..) {
+            *self.stats.rows_inserted.entry(table).or_default() += rows as u64;
+            if let Some(hooks) = &mut self.hooks {
+                hooks.on_insert(table, rows);
+            }
+        }
+        if let Some(hooks) = &mut self.hooks {
+            hooks.on_commit(duration);
+        }
+    }
+
+    /// Forgets the inserts run in a transaction that was rolled back.
+    pub fn record_rollback(&mut self) {
+        self.pending_inserts.clear();
+        self.savepoints.clear();
+    }
+
+    pub fn record_savepoint(&mut self) {
+        self.savepoints.push(self.pending_inserts.len());
+    }
+
+    pub fn record_release_savepoint(&mut self) {
+        self.savepoints.pop();
+    }
+
+    /// Forgets the inserts run since the most recent savepoint, which was
+    /// rolled back to and released.
+    pub fn record_rollback_to_savepoint(&mut self) {
+        if let Some(len) = self.savepoints.pop() {
+            self.pending_inserts.truncate(len);
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::{collections::HashSet, fmt};
+
+use rusqlite::Connection;
+
+use super::{models::Insertable, StatementCounters};
+use crate::error::{CodecovError, Result};
+
+/// How much a [`SqliteReportBuilder`](super::SqliteReportBuilder) checks that
+/// the rows it inserts refer to rows that exist. See
+/// [`SqliteReportBuilder::set_integrity_mode`](super::SqliteReportBuilder::set_integrity_mode).
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum IntegrityMode {
+    /// Don't check anything. Inserts are a little faster, and any orphans
+    /// left behind can be cleaned up with
+    /// [`SqliteReport::repair`](super::SqliteReport::repair).
+    Off,
+    /// Have SQLite enforce the schema's foreign keys. A violation fails with
+    /// [`CodecovError::SqliteConstraintViolation`], which doesn't say which
+    /// row was missing.
+    #[default]
+    ForeignKeys,
+    /// Enforce foreign keys, and also look up every row a model refers to
+    /// before inserting it. A missing row fails with a
+    /// [`CodecovError::ReportBuilderError`] naming it. This catches references
+    /// the schema can't declare, like a
+    /// [`ContextAssoc`](crate::report::models::ContextAssoc)'s sample or span,
+    /// but costs a query per reference, so it's meant for tests.
+    Validate,
+}
+
+/// A row that a model refers to. See [`Insertable::references`].
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub(crate) enum Reference {
+    RawUpload(i64),
+    SourceFile(i64),
+    Context(i64),
+    Sample {
+        raw_upload_id: i64,
+        local_sample_id: i64,
+    },
+    Span {
+        raw_upload_id: i64,
+        local_span_id: i64,
+    },
+}
+
+impl Reference {
+    fn exists(self, conn: &Connection, statement_cache: &StatementCounters) -> Result<bool> {
+        let (sql, params) = match self {
+            Reference::RawUpload(id) => ("SELECT 1 FROM raw_upload WHERE id = ?1", (id, None)),
+            Reference::SourceFile(id) => ("SELECT 1 FROM source_file WHERE id = ?1", (id, None)),
+            Reference::Context(id) => ("SELECT 1 FROM context WHERE id = ?1", (id, None)),
+            Reference::Sample {
+                raw_upload_id,
+                local_sample_id,
+            } => (
+                "SELECT 1 FROM coverage_sample WHERE raw_upload_id = ?1 AND local_sample_id = ?2",
+                (raw_upload_id, Some(local_sample_id)),
+            ),
+            Reference::Span {
+                raw_upload_id,
+                local_span_id,
+            } => (
+                "SELECT 1 FROM span_data WHERE raw_upload_id = ?1 AND local_span_id = ?2",
+                (raw_upload_id, Some(local_span_id)),
+            ),
+        };
+        let mut stmt = statement_cache.prepare_cached(conn, sql)?;
+        Ok(match params {
+            (id, None) => stmt.exists([id])?,
+            (id, Some(local_id)) => stmt.exists([id, local_id])?,
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reference::RawUpload(id) => write!(f, "raw upload {id}"),
+            Reference::SourceFile(id) => write!(f, "source file {id}"),
+            Reference::Context(id) => write!(f, "context {id}"),
+            Reference::Sample {
+                raw_upload_id,
+                local_sample_id,
+            } => write!(f, "sample {local_sample_id} of raw upload {raw_upload_id}"),
+            Reference::Span {
+                raw_upload_id,
+                local_span_id,
+            } => write!(f, "span {local_span_id} of raw upload {raw_upload_id}"),
+        }
+    }
+}
+
+/// Fails with a [`CodecovError::ReportBuilderError`] naming the model and the
+/// row it refers to if any row that `models` refer to doesn't exist.
+pub(super) fn check_references<'a, T: Insertable + fmt::Display + 'a>(
+    models: impl Iterator<Item = &'a T>,
+    conn: &Connection,
+    statement_cache: &StatementCounters,
+) -> Result<()> {
+    let mut checked = HashSet::new();
+    for model in models {
+        for reference in model.references() {
+            if checked.insert(reference) && !reference.exists(conn, statement_cache)? {
+                return Err(CodecovError::ReportBuilderError(format!(
+                    "{} row ({model}) refers to {reference}, which doesn't exist",
+                    T::TABLE_NAME
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        error::ConstraintKind,
+        report::{models, sqlite::SqliteReportBuilder, ReportBuilder},
+    };
+
+    struct Ctx {
+        temp_dir: TempDir,
+    }
+
+    fn setup() -> Ctx {
+        Ctx {
+            temp_dir: TempDir::new().ok().unwrap(),
+        }
+    }
+
+    fn builder(ctx: &Ctx, mode: IntegrityMode) -> SqliteReportBuilder {
+        let mut builder =
+            SqliteReportBuilder::open(ctx.temp_dir.path().join(format!("{mode:?}.sqlite")))
+                .unwrap();
+        builder.set_integrity_mode(mode).unwrap();
+        builder
+    }
+
+    fn orphan_sample(raw_upload_id: i64) -> models::CoverageSample {
+        models::CoverageSample {
+            raw_upload_id,
+            source_file_id: 123,
+            line_no: 1,
+            hits: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_integrity_off() {
+        let ctx = setup();
+        let mut builder = builder(&ctx, IntegrityMode::Off);
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        builder
+            .insert_coverage_sample(orphan_sample(upload.id))
+            .unwrap();
+        // The setting carries over to the report
+        let report = builder.build().unwrap();
+        let foreign_keys: bool = report
+            .conn
+            .pragma_query_value(None, "foreign_keys", |row| row.get(0))
+            .unwrap();
+        assert!(!foreign_keys);
+    }
+
+    #[test]
+    fn test_integrity_foreign_keys() {
+        let ctx = setup();
+        let mut builder = builder(&ctx, IntegrityMode::ForeignKeys);
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let error = builder
+            .insert_coverage_sample(orphan_sample(upload.id))
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CodecovError::SqliteConstraintViolation {
+                kind: ConstraintKind::ForeignKey,
+                ..
+            }
+        ));
+
+        // The schema can't declare a context association's sample as a foreign key
+        let context = builder.insert_context("test_case").unwrap();
+        builder
+            .associate_context(models::ContextAssoc {
+                context_id: context.id,
+                raw_upload_id: upload.id,
+                local_sample_id: Some(456),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_integrity_validate() {
+        let ctx = setup();
+        let mut builder = builder(&ctx, IntegrityMode::Validate);
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let file = builder.insert_file("src/lib.rs").unwrap();
+        let context = builder.insert_context("test_case").unwrap();
+
+        let error = builder
+            .insert_coverage_sample(orphan_sample(upload.id))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("report builder error: 'coverage_sample row (<file 123>:1 line hits=1 upload={} sample=0) refers to source file 123, which doesn't exist'", upload.id)
+        );
+
+        let mut samples = [upload.id, 789].map(|raw_upload_id| models::CoverageSample {
+            source_file_id: file.id,
+            ..orphan_sample(raw_upload_id)
+        });
+        let error = builder
+            .multi_insert_coverage_sample(samples.iter_mut().collect())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("report builder error: 'coverage_sample row ({}) refers to raw upload 789, which doesn't exist'", samples[1])
+        );
+
+        let sample = builder.insert_coverage_sample(samples[0].clone()).unwrap();
+        let mut assocs =
+            [sample.local_sample_id, 456].map(|local_sample_id| models::ContextAssoc {
+                context_id: context.id,
+                raw_upload_id: upload.id,
+                local_sample_id: Some(local_sample_id),
+                ..Default::default()
+            });
+        let error = builder
+            .multi_associate_context(assocs.iter_mut().collect())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("report builder error: 'context_assoc row (context {} upload={} sample=456) refers to sample 456 of raw upload {}, which doesn't exist'", context.id, upload.id, upload.id)
+        );
+        builder.associate_context(assocs[0].clone()).unwrap();
+
+        let report = builder.build().unwrap();
+        assert_eq!(
+            crate::report::Report::list_coverage_samples(&report).unwrap(),
+            &[sample]
+        );
+    }
+
+    #[test]
+    fn test_integrity_validate_upload_tag() {
+        let ctx = setup();
+        let mut builder = builder(&ctx, IntegrityMode::Validate);
+        let tag = models::UploadTag {
+            raw_upload_id: 789,
+            key: "flag".to_string(),
+            value: "unit".to_string(),
+        };
+        let error = builder.insert_upload_tag(tag.clone()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("report builder error: 'upload_tag row ({tag}) refers to raw upload 789, which doesn't exist'")
+        );
+    }
+
+    #[test]
+    fn test_integrity_validate_session_file_totals() {
+        let ctx = setup();
+        let mut builder = builder(&ctx, IntegrityMode::Validate);
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let totals = models::SessionFileTotals {
+            raw_upload_id: upload.id,
+            source_file_id: 123,
+            ..Default::default()
+        };
+        let error = builder
+            .multi_insert_session_file_totals(std::slice::from_ref(&totals))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("report builder error: 'session_file_totals row ({totals}) refers to source file 123, which doesn't exist'")
+        );
+    }
+
+    #[test]
+    fn test_integrity_validate_line_attributes() {
+        let ctx = setup();
+        let mut builder = builder(&ctx, IntegrityMode::Validate);
+        let file = builder.insert_file("src/lib.rs").unwrap();
+        let attribute = models::LineAttribute {
+            raw_upload_id: 789,
+            source_file_id: file.id,
+            line_no: 1,
+            attributes: models::LineAttributes::default(),
+        };
+        let error = builder
+            .multi_insert_line_attributes(std::slice::from_ref(&attribute))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("report builder error: 'line_attribute row ({attribute}) refers to raw upload 789, which doesn't exist'")
+        );
+    }
+
+    #[test]
+    fn test_set_integrity_mode_in_batch() {
+        let ctx = setup();
+        let mut builder = builder(&ctx, IntegrityMode::Validate);
+        builder.begin().unwrap();
+        assert!(builder.set_integrity_mode(IntegrityMode::Off).is_err());
+        builder.commit().unwrap();
+        builder.set_integrity_mode(IntegrityMode::Off).unwrap();
+    }
+}
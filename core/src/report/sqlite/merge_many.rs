@@ -0,0 +1,346 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use super::SqliteReport;
+use crate::{
+    error::{CodecovError, Result},
+    report::{MergePolicy, Report},
+};
+
+/// A report waiting to be merged at some level of
+/// [`SqliteReport::merge_many`]'s merge tree.
+struct MergeInput {
+    path: PathBuf,
+    /// Whether `merge_many` created the report and may modify or delete it.
+    intermediate: bool,
+}
+
+impl SqliteReport {
+    /// Merges the reports at `paths` into a new report at `out_path`, using
+    /// up to `threads` threads.
+    ///
+    /// The reports are merged pairwise, like a tournament bracket: each round
+    /// merges neighbouring pairs in parallel and hands the results to the
+    /// next round, until one report is left. Every round copies all of the
+    /// data merged so far, so this does more work in total than merging the
+    /// reports into one in order and only comes out ahead when there are
+    /// enough cores to run each round's merges side by side. `benches/merge.rs`
+    /// compares the two. Reports are only ever merged with their neighbours,
+    /// so the result is the same as merging in order apart from the local IDs
+    /// of samples.
+    ///
+    /// Intermediate reports are written next to `out_path` and removed once
+    /// they've been merged. `out_path` must not exist yet. The reports at
+    /// `paths` aren't modified, except that they're migrated to the latest
+    /// schema like [`SqliteReport::open`] does.
+    pub fn merge_many(
+        paths: &[PathBuf],
+        out_path: PathBuf,
+        threads: usize,
+        policy: MergePolicy,
+    ) -> Result<SqliteReport> {
+        if out_path.exists() {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "merge output {} already exists",
+                out_path.display()
+            )));
+        }
+        let threads = threads.max(1);
+
+        let mut inputs: Vec<MergeInput> = paths
+            .iter()
+            .map(|path| MergeInput {
+                path: path.clone(),
+                intermediate: false,
+            })
+            .collect();
+        let mut round = 0;
+        while inputs.len() > 1 {
+            inputs = match merge_round(inputs, &out_path, round, threads, policy) {
+                Ok(inputs) => inputs,
+                Err((error, leftovers)) => {
+                    remove_intermediates(&leftovers);
+                    return Err(error);
+                }
+            };
+            round += 1;
+        }
+
+        match inputs.pop() {
+            Some(MergeInput {
+                path,
+                intermediate: true,
+            }) => {
+                std::fs::File::open(&path)?.sync_all()?;
+                std::fs::rename(&path, &out_path)?;
+                SqliteReport::open(out_path)
+            }
+            // A single input is copied rather than moved
+            Some(MergeInput { path, .. }) => {
+                let mut report = SqliteReport::open(out_path)?;
+                report.merge(&SqliteReport::open(path)?, policy)?;
+                Ok(report)
+            }
+            None => SqliteReport::open(out_path),
+        }
+    }
+}
+
+/// Merges each pair of neighbouring `inputs` in parallel. An odd input out is
+/// passed along to the next round as-is.
+///
+/// If any merge fails, returns the first error along with every intermediate
+/// report that still exists so they can be cleaned up.
+fn merge_round(
+    inputs: Vec<MergeInput>,
+    out_path: &Path,
+    round: usize,
+    threads: usize,
+    policy: MergePolicy,
+) -> std::result::Result<Vec<MergeInput>, (CodecovError, Vec<MergeInput>)> {
+    let pairs: Vec<&[MergeInput]> = inputs.chunks(2).collect();
+    let next_pair = AtomicUsize::new(0);
+    let merge_index = |index: usize| -> Result<MergeInput> {
+        match pairs[index] {
+            [left, right] => merge_pair(left, right, out_path, round, index, policy),
+            [only] => Ok(MergeInput {
+                path: only.path.clone(),
+                intermediate: only.intermediate,
+            }),
+            _ => unreachable!(),
+        }
+    };
+
+    let mut results: Vec<(usize, Result<MergeInput>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(pairs.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = vec![];
+                    loop {
+                        let index = next_pair.fetch_add(1, Ordering::Relaxed);
+                        if index >= pairs.len() {
+                            break results;
+                        }
+                        results.push((index, merge_index(index)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+
+    let mut merged = Vec::with_capacity(results.len());
+    let mut first_error = None;
+    for (index, result) in results {
+        match result {
+            Ok(input) => merged.push(input),
+            Err(error) => {
+                // A failed merge may have left its left side or its copy of
+                // it behind
+                merged.extend(pairs[index].iter().filter(|input| input.intermediate).map(
+                    |input| MergeInput {
+                        path: input.path.clone(),
+                        intermediate: true,
+                    },
+                ));
+                merged.push(MergeInput {
+                    path: intermediate_path(out_path, round, index),
+                    intermediate: true,
+                });
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    match first_error {
+        Some(error) => Err((error, merged)),
+        None => Ok(merged),
+    }
+}
+
+/// Merges `right` into `left`, or into a copy of `left` if it's one of the
+/// caller's.
+fn merge_pair(
+    left: &MergeInput,
+    right: &MergeInput,
+    out_path: &Path,
+    round: usize,
+    index: usize,
+    policy: MergePolicy,
+) -> Result<MergeInput> {
+    let path = if left.intermediate {
+        left.path.clone()
+    } else {
+        let path = intermediate_path(out_path, round, index);
+        std::fs::copy(&left.path, &path)?;
+        path
+    };
+    let mut merged = SqliteReport::open(path)?;
+    // Intermediate reports are thrown away if anything goes wrong, so they
+    // don't need to survive a crash. The final one is synced before it's
+    // moved into place.
+    merged
+        .conn
+        .execute_batch("PRAGMA synchronous = OFF; PRAGMA journal_mode = MEMORY;")?;
+    merged.merge(&SqliteReport::open(right.path.clone())?, policy)?;
+
+    if right.intermediate {
+        std::fs::remove_file(&right.path)?;
+    }
+    Ok(MergeInput {
+        path: merged.filename.clone(),
+        intermediate: true,
+    })
+}
+
+/// Where the merge of the `index`th pair in `round` is written, e.g.
+/// `out.sqlite.merge-0-3` for `out.sqlite`.
+fn intermediate_path(out_path: &Path, round: usize, index: usize) -> PathBuf {
+    let mut file_name = out_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".merge-{round}-{index}"));
+    out_path.with_file_name(file_name)
+}
+
+fn remove_intermediates(inputs: &[MergeInput]) {
+    for input in inputs.iter().filter(|input| input.intermediate) {
+        // Best effort, the merge has already failed
+        let _ = std::fs::remove_file(&input.path);
+    }
+}
+
+#[cfg(all(test, feature = "pyreport"))]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        report::{ReportBuilder, SqliteReportBuilder},
+        test_utils::generator::{generate_sqlite_report, GeneratorConfig},
+    };
+
+    fn generate_shards(temp_dir: &TempDir, count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|i| {
+                let path = temp_dir.path().join(format!("shard-{i}.sqlite"));
+                let config = GeneratorConfig {
+                    files: 4,
+                    lines_per_file: 20,
+                    sessions: 1,
+                    labels: 3,
+                    seed: i as u64,
+                    ..Default::default()
+                };
+                generate_sqlite_report(&config, path.clone()).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn merge_sequentially(paths: &[PathBuf], out_path: PathBuf) -> SqliteReport {
+        let mut report = SqliteReport::open(out_path).unwrap();
+        for path in paths {
+            report
+                .merge(
+                    &SqliteReport::open(path.clone()).unwrap(),
+                    MergePolicy::SumHits,
+                )
+                .unwrap();
+        }
+        report
+    }
+
+    fn leftover_files(temp_dir: &TempDir) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(".merge-"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_merge_many_matches_sequential_merge() {
+        let temp_dir = TempDir::new().unwrap();
+        let shards = generate_shards(&temp_dir, 7);
+        let expected = merge_sequentially(&shards, temp_dir.path().join("sequential.sqlite"));
+
+        for threads in [1, 3, 8] {
+            let out_path = temp_dir.path().join(format!("merged-{threads}.sqlite"));
+            let merged =
+                SqliteReport::merge_many(&shards, out_path.clone(), threads, MergePolicy::SumHits)
+                    .unwrap();
+            assert_eq!(merged.filename, out_path);
+            assert_eq!(merged.list_files().unwrap(), expected.list_files().unwrap());
+            assert_eq!(
+                merged.list_raw_uploads().unwrap(),
+                expected.list_raw_uploads().unwrap()
+            );
+            assert_eq!(merged.totals().unwrap(), expected.totals().unwrap());
+            assert_eq!(merged.summary().unwrap(), expected.summary().unwrap());
+        }
+        assert!(leftover_files(&temp_dir).is_empty());
+    }
+
+    #[test]
+    fn test_merge_many_small_inputs() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let empty = SqliteReport::merge_many(
+            &[],
+            temp_dir.path().join("empty.sqlite"),
+            4,
+            MergePolicy::SumHits,
+        )
+        .unwrap();
+        assert!(empty.list_files().unwrap().is_empty());
+
+        let shards = generate_shards(&temp_dir, 1);
+        let single = SqliteReport::merge_many(
+            &shards,
+            temp_dir.path().join("single.sqlite"),
+            4,
+            MergePolicy::SumHits,
+        )
+        .unwrap();
+        let shard = SqliteReport::open(shards[0].clone()).unwrap();
+        assert_eq!(single.totals().unwrap(), shard.totals().unwrap());
+        assert!(shards[0].exists());
+    }
+
+    #[test]
+    fn test_merge_many_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let shards = generate_shards(&temp_dir, 3);
+
+        let out_path = temp_dir.path().join("exists.sqlite");
+        let _ = SqliteReportBuilder::open(out_path.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(matches!(
+            SqliteReport::merge_many(&shards, out_path, 2, MergePolicy::SumHits),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+
+        // A shard that isn't a report fails its merge, and every intermediate
+        // report is cleaned up
+        let bogus = temp_dir.path().join("bogus.sqlite");
+        std::fs::write(&bogus, "not a database").unwrap();
+        let mut paths = shards.clone();
+        paths.push(bogus);
+        paths.extend(shards);
+        let out_path = temp_dir.path().join("merged.sqlite");
+        assert!(
+            SqliteReport::merge_many(&paths, out_path.clone(), 2, MergePolicy::SumHits).is_err()
+        );
+        assert!(!out_path.exists());
+        assert_eq!(leftover_files(&temp_dir), Vec::<String>::new());
+    }
+}
@@ -5,34 +5,251 @@
  * Notes on SQLite performance:
  * - Some `ORDER BY` clauses are to make writing test cases simple and may
  *   not be necessary
+ *
+ * Notes on concurrent access:
+ * - Several processes may open the same report, but SQLite only lets one
+ *   connection write at a time.
+ * - Builders take the write lock as soon as a transaction or batch begins
+ *   (`BEGIN IMMEDIATE`) instead of on their first write. Two builders that
+ *   both started reading can't then deadlock trying to upgrade their locks,
+ *   which SQLite would fail right away with `SQLITE_BUSY` no matter how
+ *   long they were willing to wait.
+ * - Every connection waits up to [`DEFAULT_BUSY_TIMEOUT`] for a lock, and
+ *   builders retry beginning and committing transactions with backoff after
+ *   that, per their [`BusyPolicy`]. A builder holds the write lock for as
+ *   long as its transaction or batch is open, so other writers' policies
+ *   should allow for the longest batch.
+ * - Anything still busy fails with an error for which
+ *   [`CodecovError::is_busy`] is true, so callers can decide whether to try
+ *   again later.
  */
-use std::{path::PathBuf, sync::LazyLock};
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
 use include_dir::{include_dir, Dir};
-use rusqlite::Connection;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
 use rusqlite_migration::Migrations;
 
-use crate::error::Result;
+use crate::error::{CodecovError, Result};
 
+mod backup;
+mod busy;
+mod checksum;
+mod collapse;
+mod compact;
+mod dedup;
+mod instrumentation;
+mod integrity;
+mod merge_many;
 mod models;
+mod rename;
+mod repair;
 mod report;
 mod report_builder;
+mod runs;
+mod snapshot;
+mod stale;
+mod statement_cache;
+mod stats;
+mod supersede;
+mod totals_cache;
 
-pub use models::*;
+pub(crate) use busy::{backup_step, is_busy};
+pub use busy::{BusyPolicy, DEFAULT_BUSY_TIMEOUT};
+pub use checksum::ChecksumStatus;
+pub use collapse::CollapsedUploads;
+pub use compact::*;
+pub use dedup::*;
+pub use instrumentation::*;
+pub use integrity::IntegrityMode;
+pub(crate) use models::*;
+pub use repair::*;
 pub use report::*;
 pub use report_builder::*;
+pub use runs::PackedRuns;
+pub use snapshot::SnapshotTarget;
+pub use statement_cache::*;
+pub use stats::ReportStats;
+pub use totals_cache::{TotalsCache, TotalsCacheStats};
 
 static MIGRATIONS_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/migrations");
 static MIGRATIONS: LazyLock<Migrations<'static>> =
     LazyLock::new(|| Migrations::from_directory(&MIGRATIONS_DIR).unwrap());
 
+/// The version of this crate, recorded in every report it opens for writing.
+const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Fails with [`CodecovError::NewerCrateVersion`] if a newer version of this
+/// crate has written to the database. Databases from before we recorded the
+/// version pass.
+fn check_crate_version(conn: &Connection) -> Result<()> {
+    match recorded_crate_version(conn)? {
+        Some(found) if version_key(&found) > version_key(CRATE_VERSION) => {
+            Err(CodecovError::NewerCrateVersion {
+                found,
+                current: CRATE_VERSION.to_string(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The crate version recorded in the database at `conn`, if any.
+fn recorded_crate_version(conn: &Connection) -> Result<Option<String>> {
+    let has_metadata: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'report_metadata')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_metadata {
+        return Ok(None);
+    }
+
+    Ok(conn
+        .query_row(
+            "SELECT value FROM report_metadata WHERE key = 'crate_version'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// The numeric components of a `major.minor.patch` version, ignoring any
+/// pre-release or build suffix, in an order that compares correctly.
+fn version_key(version: &str) -> Vec<u64> {
+    version
+        .split(['-', '+'])
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Checks that we know how to read the database at `conn` and returns its
+/// schema version.
+fn check_compatibility(conn: &Connection) -> Result<usize> {
+    check_crate_version(conn)?;
+
+    // `rusqlite_migration` tracks the schema version in `user_version`
+    let found: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let latest = MIGRATIONS_DIR.dirs().count();
+    if found > latest {
+        return Err(CodecovError::SchemaVersionMismatch { found, latest });
+    }
+    Ok(found)
+}
+
 fn open_database(filename: &PathBuf) -> Result<Connection> {
     let mut conn = Connection::open(filename)?;
-    MIGRATIONS.to_latest(&mut conn)?;
+    conn.busy_timeout(DEFAULT_BUSY_TIMEOUT)?;
+
+    migrate(&mut conn)?;
+    conn.set_prepared_statement_cache_capacity(DEFAULT_STATEMENT_CACHE_CAPACITY);
+
+    Ok(conn)
+}
+
+/// Brings the database at `conn` up to the latest schema and records this
+/// crate's version in it.
+///
+/// A database that is already up to date and records this crate's version is
+/// left untouched, so opening it doesn't take the write lock.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let found = check_compatibility(conn)?;
+    MIGRATIONS.to_latest(conn)?;
+
+    // We already know the recorded version isn't newer than ours
+    let migrated = found < MIGRATIONS_DIR.dirs().count();
+    let outdated = recorded_crate_version(conn)?
+        .is_none_or(|recorded| version_key(&recorded) < version_key(CRATE_VERSION));
+    if !migrated && !outdated {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO report_metadata (key, value) VALUES ('crate_version', ?1)
+         ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+        [CRATE_VERSION],
+    )?;
+    Ok(())
+}
+
+/// Names the migrations that [`open_database`] would run on the database at
+/// `filename`, in order, without creating or modifying it.
+fn pending_migrations(filename: &Path) -> Result<Vec<String>> {
+    let mut names: Vec<String> = MIGRATIONS_DIR
+        .dirs()
+        .filter_map(|dir| dir.path().file_name()?.to_str().map(String::from))
+        .collect();
+    names.sort();
+    if !filename.exists() {
+        return Ok(names);
+    }
+
+    let conn = Connection::open_with_flags(
+        filename,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    conn.busy_timeout(DEFAULT_BUSY_TIMEOUT)?;
+    let found = check_compatibility(&conn)?;
+    Ok(names.split_off(found))
+}
+
+/// Opens an existing database without migrating or otherwise modifying it.
+/// Its schema must already be at the latest version.
+fn open_database_readonly(filename: &PathBuf) -> Result<Connection> {
+    let conn = Connection::open_with_flags(
+        filename,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    conn.busy_timeout(DEFAULT_BUSY_TIMEOUT)?;
+
+    check_crate_version(&conn)?;
+    let found: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let latest = MIGRATIONS_DIR.dirs().count();
+    if found != latest {
+        return Err(CodecovError::SchemaVersionMismatch { found, latest });
+    }
+    conn.set_prepared_statement_cache_capacity(DEFAULT_STATEMENT_CACHE_CAPACITY);
 
     Ok(conn)
 }
 
+/// Deletes the upload with ID `raw_upload_id` and all of its data.
+fn delete_raw_upload(
+    conn: &Connection,
+    statement_cache: &StatementCounters,
+    raw_upload_id: i64,
+) -> Result<()> {
+    collapse::expand_aliases_of(conn, statement_cache, raw_upload_id)?;
+    // Children before parents so foreign keys are never dangling
+    for table in [
+        "collapsed_upload",
+        "upload_tag",
+        "session_file_totals",
+        "line_attribute",
+        "context_assoc",
+        "span_data",
+        "method_data",
+        "branches_data",
+        "coverage_sample",
+        "coverage_run",
+    ] {
+        statement_cache
+            .prepare_cached(
+                conn,
+                &format!("DELETE FROM {table} WHERE raw_upload_id = ?1"),
+            )?
+            .execute([raw_upload_id])?;
+    }
+    statement_cache
+        .prepare_cached(conn, "DELETE FROM raw_upload WHERE id = ?1")?
+        .execute([raw_upload_id])?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::num::NonZeroUsize;
@@ -61,7 +278,7 @@ mod tests {
         let conn = open_database(&db_file).unwrap();
         assert_eq!(
             MIGRATIONS.current_version(&conn),
-            Ok(SchemaVersion::Inside(NonZeroUsize::new(1).unwrap()))
+            Ok(SchemaVersion::Inside(NonZeroUsize::new(23).unwrap()))
         );
     }
 
@@ -88,4 +305,151 @@ mod tests {
         assert_eq!(id, 1);
         assert_eq!(path, "src/report.rs");
     }
+
+    #[test]
+    fn test_open_database_newer_schema_version() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        {
+            let conn = open_database(&db_file).unwrap();
+            conn.pragma_update(None, "user_version", 100).unwrap();
+        }
+
+        let error = open_database(&db_file).unwrap_err();
+        assert!(matches!(
+            error,
+            CodecovError::SchemaVersionMismatch {
+                found: 100,
+                latest: 23
+            }
+        ));
+    }
+
+    #[test]
+    fn test_open_database_readonly() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        // Doesn't create missing files
+        assert!(open_database_readonly(&db_file).is_err());
+        assert!(!db_file.exists());
+
+        drop(open_database(&db_file).unwrap());
+        let conn = open_database_readonly(&db_file).unwrap();
+        assert!(conn
+            .execute("INSERT INTO source_file (id, path) VALUES (1, 'a.rs')", [])
+            .is_err());
+        drop(conn);
+
+        // Older and newer schemas are both rejected, and older ones aren't
+        // migrated
+        for version in [5, 100] {
+            {
+                let conn = Connection::open(&db_file).unwrap();
+                conn.pragma_update(None, "user_version", version).unwrap();
+            }
+            let error = open_database_readonly(&db_file).unwrap_err();
+            assert!(matches!(
+                error,
+                CodecovError::SchemaVersionMismatch { found, latest: 23 } if found == version
+            ));
+        }
+    }
+
+    #[test]
+    fn test_pending_migrations() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let all = pending_migrations(&db_file).unwrap();
+        assert_eq!(all.len(), MIGRATIONS_DIR.dirs().count());
+        assert_eq!(all[0], "01-init");
+        assert_eq!(all[16], "17-report-metadata");
+        assert!(!db_file.exists());
+
+        drop(open_database(&db_file).unwrap());
+        assert!(pending_migrations(&db_file).unwrap().is_empty());
+
+        {
+            let conn = Connection::open(&db_file).unwrap();
+            conn.pragma_update(None, "user_version", 15).unwrap();
+        }
+        assert_eq!(pending_migrations(&db_file).unwrap(), &all[15..]);
+        assert_eq!(all[15], "16-raw-upload-timestamp-index");
+
+        {
+            let conn = Connection::open(&db_file).unwrap();
+            conn.pragma_update(None, "user_version", 100).unwrap();
+        }
+        assert!(matches!(
+            pending_migrations(&db_file).unwrap_err(),
+            CodecovError::SchemaVersionMismatch { found: 100, .. }
+        ));
+    }
+
+    #[test]
+    fn test_open_database_newer_crate_version() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let set_version = |version: &str| {
+            let conn = Connection::open(&db_file).unwrap();
+            conn.execute(
+                "UPDATE report_metadata SET value = ?1 WHERE key = 'crate_version'",
+                [version],
+            )
+            .unwrap();
+        };
+        let recorded_version = || -> String {
+            let conn = Connection::open(&db_file).unwrap();
+            conn.query_row(
+                "SELECT value FROM report_metadata WHERE key = 'crate_version'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+
+        drop(open_database(&db_file).unwrap());
+        assert_eq!(recorded_version(), CRATE_VERSION);
+
+        // Reopening an up-to-date database doesn't write to it. `data_version`
+        // changes when another connection commits.
+        let observer = Connection::open(&db_file).unwrap();
+        let data_version = || -> i64 {
+            observer
+                .pragma_query_value(None, "data_version", |row| row.get(0))
+                .unwrap()
+        };
+        let before = data_version();
+        drop(open_database(&db_file).unwrap());
+        assert_eq!(data_version(), before);
+        drop(observer);
+
+        // Older versions are replaced with ours
+        set_version("0.0.0-alpha");
+        drop(open_database(&db_file).unwrap());
+        assert_eq!(recorded_version(), CRATE_VERSION);
+
+        set_version("999.0.0");
+        let is_newer = |error| {
+            matches!(
+                error,
+                CodecovError::NewerCrateVersion { found, current }
+                    if found == "999.0.0" && current == CRATE_VERSION
+            )
+        };
+        assert!(is_newer(open_database(&db_file).unwrap_err()));
+        assert!(is_newer(open_database_readonly(&db_file).unwrap_err()));
+        assert!(is_newer(pending_migrations(&db_file).unwrap_err()));
+        assert_eq!(recorded_version(), "999.0.0");
+    }
+
+    #[test]
+    fn test_version_key() {
+        assert!(version_key("0.10.0") > version_key("0.9.12"));
+        assert!(version_key("1.0.0") > version_key("0.99.0"));
+        assert_eq!(version_key("1.2.3-rc.1+build.5"), version_key("1.2.3"));
+    }
 }
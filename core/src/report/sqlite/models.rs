@@ -12,7 +12,11 @@
 
 use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
 
-use super::super::models::*;
+use super::{
+    super::{models::*, summary::SummaryCounts},
+    integrity::Reference,
+    StatementCounters,
+};
 use crate::{error::Result, parsers::json::JsonVal};
 
 /// Takes care of the boilerplate to insert a model into the database.
@@ -24,7 +28,9 @@ use crate::{error::Result, parsers::json::JsonVal};
 ///
 /// # Examples
 ///
-/// ```
+/// This trait is internal to the crate, so the example isn't run as a doctest.
+///
+/// ```ignore
 /// # use codecov_rs::report::sqlite::Insertable;
 /// struct File {
 ///      id: i64,
@@ -56,6 +62,17 @@ pub trait Insertable {
     /// matching the `FIELDS`.
     fn extend_params<'a>(&'a self, params: &mut Vec<&'a dyn rusqlite::ToSql>);
 
+    /// An `ON CONFLICT` clause for the `INSERT`, if rows can be merged into
+    /// existing ones rather than failing.
+    const UPSERT: Option<&'static str> = None;
+
+    /// The rows this model refers to, which
+    /// [`IntegrityMode::Validate`](super::IntegrityMode::Validate) checks
+    /// exist before inserting it.
+    fn references(&self) -> Vec<Reference> {
+        vec![]
+    }
+
     /// Determines the maximum chunk size depending on the number of fields and
     /// placeholder limit.
     fn maximum_chunk_size(conn: &rusqlite::Connection) -> usize {
@@ -88,37 +105,53 @@ pub trait Insertable {
             }
             query.push_str(&placeholder);
         }
+        if let Some(upsert) = Self::UPSERT {
+            query.push(' ');
+            query.push_str(upsert);
+        }
         query.push(';');
 
         query
     }
 
-    fn insert(&self, conn: &rusqlite::Connection) -> Result<()> {
-        let mut stmt = conn.prepare_cached(&Self::build_query(1))?;
+    /// Inserts this model, returning the number of rows written: 0 if an
+    /// `UPSERT` did nothing.
+    fn insert(
+        &self,
+        conn: &rusqlite::Connection,
+        statement_cache: &StatementCounters,
+    ) -> Result<usize> {
+        let mut stmt = statement_cache.prepare_cached(conn, &Self::build_query(1))?;
         let mut params = vec![];
         self.extend_params(&mut params);
-        stmt.execute(params.as_slice())?;
-
-        Ok(())
+        Ok(stmt.execute(params.as_slice())?)
     }
 
-    fn multi_insert<'a, I>(mut models: I, conn: &rusqlite::Connection) -> Result<()>
+    /// Inserts `models`, returning the number of rows written like
+    /// [`Insertable::insert`].
+    fn multi_insert<'a, I>(
+        mut models: I,
+        conn: &rusqlite::Connection,
+        statement_cache: &StatementCounters,
+    ) -> Result<usize>
     where
         I: Iterator<Item = &'a Self> + ExactSizeIterator,
         Self: 'a,
     {
         let chunk_size = Self::maximum_chunk_size(conn);
+        let mut written = 0;
 
         let mut params = Vec::with_capacity(Self::FIELDS.len() * (models.len().min(chunk_size)));
 
         // first: insert huge chunks using a single prepared (cached) query
         if models.len() >= chunk_size {
-            let mut chunked_stmt = conn.prepare_cached(&Self::build_query(chunk_size))?;
+            let mut chunked_stmt =
+                statement_cache.prepare_cached(conn, &Self::build_query(chunk_size))?;
             while models.len() >= chunk_size {
                 for row in models.by_ref().take(chunk_size) {
                     row.extend_params(&mut params);
                 }
-                chunked_stmt.execute(params.as_slice())?;
+                written += chunked_stmt.execute(params.as_slice())?;
                 params.clear();
             }
         }
@@ -132,11 +165,103 @@ pub trait Insertable {
             for row in models {
                 row.extend_params(&mut params);
             }
-            remainder_stmt.execute(params.as_slice())?;
+            written += remainder_stmt.execute(params.as_slice())?;
+            params.clear();
+        }
+
+        Ok(written)
+    }
+
+    /// Builds an `INSERT` query that takes its rows from a single parameter: a
+    /// JSON array with an array of field values for each row.
+    fn build_json_query() -> String {
+        let mut query = format!("INSERT INTO {} (", Self::TABLE_NAME);
+        query.push_str(&Self::FIELDS.join(", "));
+        query.push_str(") SELECT ");
+        for i in 0..Self::FIELDS.len() {
+            if i > 0 {
+                query.push_str(", ");
+            }
+            query.push_str(&format!("json_extract(value, '$[{i}]')"));
+        }
+        // SQLite can't tell an upsert's `ON` from a join's without the `WHERE`
+        query.push_str(" FROM json_each(?1) WHERE true");
+        if let Some(upsert) = Self::UPSERT {
+            query.push(' ');
+            query.push_str(upsert);
+        }
+        query.push(';');
+
+        query
+    }
+
+    /// Like [`Insertable::multi_insert`], but binds each chunk of
+    /// `JSON_CHUNK_SIZE` models as one JSON array and unpacks it with
+    /// `json_each()`, rather than binding every field separately. Chunks with
+    /// values that JSON can't represent, like blobs or non-finite floats, are
+    /// inserted with [`Insertable::multi_insert`] instead.
+    fn multi_insert_json<'a, I>(
+        models: I,
+        conn: &rusqlite::Connection,
+        statement_cache: &StatementCounters,
+    ) -> Result<usize>
+    where
+        I: Iterator<Item = &'a Self>,
+        Self: 'a,
+    {
+        let mut written = 0;
+        let mut stmt = statement_cache.prepare_cached(conn, &Self::build_json_query())?;
+        let mut chunk = Vec::with_capacity(JSON_CHUNK_SIZE);
+        let mut rows = Vec::with_capacity(JSON_CHUNK_SIZE);
+        let mut params = Vec::with_capacity(Self::FIELDS.len());
+
+        let mut models = models.peekable();
+        while models.peek().is_some() {
+            chunk.extend(models.by_ref().take(JSON_CHUNK_SIZE));
+            for model in &chunk {
+                model.extend_params(&mut params);
+                let row = params
+                    .drain(..)
+                    .map(|param| param.to_sql().map(|value| json_from_sql(&value)))
+                    .collect::<rusqlite::Result<Option<Vec<_>>>>()?;
+                match row {
+                    Some(row) => rows.push(JsonVal::Array(row)),
+                    None => break,
+                }
+            }
+
+            if rows.len() == chunk.len() {
+                let json = serde_json::to_string(&rows)?;
+                written += stmt.execute([json])?;
+            } else {
+                written += Self::multi_insert(chunk.iter().copied(), conn, statement_cache)?;
+            }
+            chunk.clear();
+            rows.clear();
             params.clear();
         }
 
-        Ok(())
+        Ok(written)
+    }
+}
+
+/// How many models [`Insertable::multi_insert_json`] binds per statement.
+const JSON_CHUNK_SIZE: usize = 4096;
+
+/// Converts a parameter to the JSON value `json_extract()` turns back into the
+/// same SQLite value, or `None` if there isn't one.
+fn json_from_sql(value: &ToSqlOutput<'_>) -> Option<JsonVal> {
+    let value = match value {
+        ToSqlOutput::Borrowed(value) => *value,
+        ToSqlOutput::Owned(value) => value.into(),
+        _ => return None,
+    };
+    match value {
+        ValueRef::Null => Some(JsonVal::Null),
+        ValueRef::Integer(i) => Some(i.into()),
+        ValueRef::Real(f) => serde_json::Number::from_f64(f).map(JsonVal::Number),
+        ValueRef::Text(text) => std::str::from_utf8(text).ok().map(Into::into),
+        ValueRef::Blob(_) => None,
     }
 }
 
@@ -154,6 +279,7 @@ impl ToSql for CoverageType {
             CoverageType::Line => Ok("l".into()),
             CoverageType::Branch => Ok("b".into()),
             CoverageType::Method => Ok("m".into()),
+            CoverageType::Statement => Ok("s".into()),
         }
     }
 }
@@ -164,12 +290,25 @@ impl FromSql for CoverageType {
             "l" => CoverageType::Line,
             "b" => CoverageType::Branch,
             "m" => CoverageType::Method,
+            "s" => CoverageType::Statement,
             _ => panic!("Uh oh"),
         };
         Ok(variant)
     }
 }
 
+impl ToSql for LineAttributes {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.0.into())
+    }
+}
+
+impl FromSql for LineAttributes {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(LineAttributes(value.as_i64()?))
+    }
+}
+
 impl ToSql for BranchFormat {
     fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         match self {
@@ -199,18 +338,33 @@ impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for SourceFile {
         Ok(Self {
             id: row.get(row.as_ref().column_index("id")?)?,
             path: row.get(row.as_ref().column_index("path")?)?,
+            language: row.get(row.as_ref().column_index("language")?)?,
+            content_hash: row.get(row.as_ref().column_index("content_hash")?)?,
+            line_count: row.get(row.as_ref().column_index("line_count")?)?,
+            chunk_index: row.get(row.as_ref().column_index("chunk_index")?)?,
         })
     }
 }
 
 impl Insertable for SourceFile {
     const TABLE_NAME: &'static str = "source_file";
-    const FIELDS: &'static [&'static str] = &["id", "path"];
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "path",
+        "language",
+        "content_hash",
+        "line_count",
+        "chunk_index",
+    ];
 
     fn extend_params<'a>(&'a self, params: &mut Vec<&'a dyn rusqlite::ToSql>) {
         params.extend(&[
             &self.id as &dyn rusqlite::ToSql,
             &self.path as &dyn rusqlite::ToSql,
+            &self.language as &dyn rusqlite::ToSql,
+            &self.content_hash as &dyn rusqlite::ToSql,
+            &self.line_count as &dyn rusqlite::ToSql,
+            &self.chunk_index as &dyn rusqlite::ToSql,
         ])
     }
 }
@@ -219,6 +373,12 @@ impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for CoverageSample {
     type Error = rusqlite::Error;
 
     fn try_from(row: &'a ::rusqlite::Row) -> Result<Self, Self::Error> {
+        let messages_index = row.as_ref().column_index("messages")?;
+        let messages = if let Some(messages) = row.get(messages_index)? {
+            Some(json_value_from_sql(messages, messages_index)?)
+        } else {
+            None
+        };
         Ok(Self {
             raw_upload_id: row.get(row.as_ref().column_index("raw_upload_id")?)?,
             local_sample_id: row.get(row.as_ref().column_index("local_sample_id")?)?,
@@ -228,6 +388,7 @@ impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for CoverageSample {
             hits: row.get(row.as_ref().column_index("hits")?)?,
             hit_branches: row.get(row.as_ref().column_index("hit_branches")?)?,
             total_branches: row.get(row.as_ref().column_index("total_branches")?)?,
+            messages,
         })
     }
 }
@@ -243,6 +404,7 @@ impl Insertable for CoverageSample {
         "hits",
         "hit_branches",
         "total_branches",
+        "messages",
     ];
 
     fn extend_params<'a>(&'a self, params: &mut Vec<&'a dyn rusqlite::ToSql>) {
@@ -255,8 +417,16 @@ impl Insertable for CoverageSample {
             &self.hits as &dyn rusqlite::ToSql,
             &self.hit_branches as &dyn rusqlite::ToSql,
             &self.total_branches as &dyn rusqlite::ToSql,
+            &self.messages as &dyn rusqlite::ToSql,
         ])
     }
+
+    fn references(&self) -> Vec<Reference> {
+        vec![
+            Reference::RawUpload(self.raw_upload_id),
+            Reference::SourceFile(self.source_file_id),
+        ]
+    }
 }
 
 impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for BranchesData {
@@ -298,6 +468,16 @@ impl Insertable for BranchesData {
             &self.branch as &dyn rusqlite::ToSql,
         ])
     }
+
+    fn references(&self) -> Vec<Reference> {
+        vec![
+            Reference::SourceFile(self.source_file_id),
+            Reference::Sample {
+                raw_upload_id: self.raw_upload_id,
+                local_sample_id: self.local_sample_id,
+            },
+        ]
+    }
 }
 
 impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for MethodData {
@@ -314,6 +494,8 @@ impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for MethodData {
             total_branches: row.get(row.as_ref().column_index("total_branches")?)?,
             hit_complexity_paths: row.get(row.as_ref().column_index("hit_complexity_paths")?)?,
             total_complexity: row.get(row.as_ref().column_index("total_complexity")?)?,
+            name: row.get(row.as_ref().column_index("name")?)?,
+            signature: row.get(row.as_ref().column_index("signature")?)?,
         })
     }
 }
@@ -330,6 +512,8 @@ impl Insertable for MethodData {
         "total_branches",
         "hit_complexity_paths",
         "total_complexity",
+        "name",
+        "signature",
     ];
 
     fn extend_params<'a>(&'a self, params: &mut Vec<&'a dyn rusqlite::ToSql>) {
@@ -343,8 +527,20 @@ impl Insertable for MethodData {
             &self.total_branches as &dyn rusqlite::ToSql,
             &self.hit_complexity_paths as &dyn rusqlite::ToSql,
             &self.total_complexity as &dyn rusqlite::ToSql,
+            &self.name as &dyn rusqlite::ToSql,
+            &self.signature as &dyn rusqlite::ToSql,
         ])
     }
+
+    fn references(&self) -> Vec<Reference> {
+        vec![
+            Reference::SourceFile(self.source_file_id),
+            Reference::Sample {
+                raw_upload_id: self.raw_upload_id,
+                local_sample_id: self.local_sample_id,
+            },
+        ]
+    }
 }
 
 impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for SpanData {
@@ -392,6 +588,21 @@ impl Insertable for SpanData {
             &self.end_col as &dyn rusqlite::ToSql,
         ])
     }
+
+    fn references(&self) -> Vec<Reference> {
+        let mut references = vec![
+            Reference::RawUpload(self.raw_upload_id),
+            Reference::SourceFile(self.source_file_id),
+        ];
+        references.extend(
+            self.local_sample_id
+                .map(|local_sample_id| Reference::Sample {
+                    raw_upload_id: self.raw_upload_id,
+                    local_sample_id,
+                }),
+        );
+        references
+    }
 }
 
 impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for ContextAssoc {
@@ -403,6 +614,7 @@ impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for ContextAssoc {
             raw_upload_id: row.get(row.as_ref().column_index("raw_upload_id")?)?,
             local_sample_id: row.get(row.as_ref().column_index("local_sample_id")?)?,
             local_span_id: row.get(row.as_ref().column_index("local_span_id")?)?,
+            source_file_id: row.get(row.as_ref().column_index("source_file_id")?)?,
         })
     }
 }
@@ -414,6 +626,7 @@ impl Insertable for ContextAssoc {
         "raw_upload_id",
         "local_sample_id",
         "local_span_id",
+        "source_file_id",
     ];
 
     fn extend_params<'a>(&'a self, params: &mut Vec<&'a dyn rusqlite::ToSql>) {
@@ -422,8 +635,130 @@ impl Insertable for ContextAssoc {
             &self.raw_upload_id as &dyn rusqlite::ToSql,
             &self.local_sample_id as &dyn rusqlite::ToSql,
             &self.local_span_id as &dyn rusqlite::ToSql,
+            &self.source_file_id as &dyn rusqlite::ToSql,
+        ])
+    }
+
+    fn references(&self) -> Vec<Reference> {
+        let mut references = vec![
+            Reference::Context(self.context_id),
+            Reference::RawUpload(self.raw_upload_id),
+        ];
+        references.extend(self.source_file_id.map(Reference::SourceFile));
+        references.extend(
+            self.local_sample_id
+                .map(|local_sample_id| Reference::Sample {
+                    raw_upload_id: self.raw_upload_id,
+                    local_sample_id,
+                }),
+        );
+        references.extend(self.local_span_id.map(|local_span_id| Reference::Span {
+            raw_upload_id: self.raw_upload_id,
+            local_span_id,
+        }));
+        references
+    }
+}
+
+impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for UploadTag {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &'a ::rusqlite::Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            raw_upload_id: row.get(row.as_ref().column_index("raw_upload_id")?)?,
+            key: row.get(row.as_ref().column_index("key")?)?,
+            value: row.get(row.as_ref().column_index("value")?)?,
+        })
+    }
+}
+
+impl Insertable for UploadTag {
+    const TABLE_NAME: &'static str = "upload_tag";
+    const FIELDS: &'static [&'static str] = &["raw_upload_id", "key", "value"];
+
+    fn extend_params<'a>(&'a self, params: &mut Vec<&'a dyn rusqlite::ToSql>) {
+        params.extend(&[
+            &self.raw_upload_id as &dyn rusqlite::ToSql,
+            &self.key as &dyn rusqlite::ToSql,
+            &self.value as &dyn rusqlite::ToSql,
+        ])
+    }
+
+    fn references(&self) -> Vec<Reference> {
+        vec![Reference::RawUpload(self.raw_upload_id)]
+    }
+}
+
+impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for SessionFileTotals {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &'a ::rusqlite::Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            source_file_id: row.get(row.as_ref().column_index("source_file_id")?)?,
+            raw_upload_id: row.get(row.as_ref().column_index("raw_upload_id")?)?,
+            files: row.get(row.as_ref().column_index("files")?)?,
+            lines: row.get(row.as_ref().column_index("lines")?)?,
+            hits: row.get(row.as_ref().column_index("hits")?)?,
+            misses: row.get(row.as_ref().column_index("misses")?)?,
+            partials: row.get(row.as_ref().column_index("partials")?)?,
+            coverage: row.get(row.as_ref().column_index("coverage")?)?,
+            branches: row.get(row.as_ref().column_index("branches")?)?,
+            methods: row.get(row.as_ref().column_index("methods")?)?,
+            messages: row.get(row.as_ref().column_index("messages")?)?,
+            sessions: row.get(row.as_ref().column_index("sessions")?)?,
+            complexity: row.get(row.as_ref().column_index("complexity")?)?,
+            complexity_total: row.get(row.as_ref().column_index("complexity_total")?)?,
+            diff: row.get(row.as_ref().column_index("diff")?)?,
+        })
+    }
+}
+
+impl Insertable for SessionFileTotals {
+    const TABLE_NAME: &'static str = "session_file_totals";
+    const FIELDS: &'static [&'static str] = &[
+        "source_file_id",
+        "raw_upload_id",
+        "files",
+        "lines",
+        "hits",
+        "misses",
+        "partials",
+        "coverage",
+        "branches",
+        "methods",
+        "messages",
+        "sessions",
+        "complexity",
+        "complexity_total",
+        "diff",
+    ];
+
+    fn extend_params<'a>(&'a self, params: &mut Vec<&'a dyn rusqlite::ToSql>) {
+        params.extend(&[
+            &self.source_file_id as &dyn rusqlite::ToSql,
+            &self.raw_upload_id as &dyn rusqlite::ToSql,
+            &self.files as &dyn rusqlite::ToSql,
+            &self.lines as &dyn rusqlite::ToSql,
+            &self.hits as &dyn rusqlite::ToSql,
+            &self.misses as &dyn rusqlite::ToSql,
+            &self.partials as &dyn rusqlite::ToSql,
+            &self.coverage as &dyn rusqlite::ToSql,
+            &self.branches as &dyn rusqlite::ToSql,
+            &self.methods as &dyn rusqlite::ToSql,
+            &self.messages as &dyn rusqlite::ToSql,
+            &self.sessions as &dyn rusqlite::ToSql,
+            &self.complexity as &dyn rusqlite::ToSql,
+            &self.complexity_total as &dyn rusqlite::ToSql,
+            &self.diff as &dyn rusqlite::ToSql,
         ])
     }
+
+    fn references(&self) -> Vec<Reference> {
+        vec![
+            Reference::RawUpload(self.raw_upload_id),
+            Reference::SourceFile(self.source_file_id),
+        ]
+    }
 }
 
 impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for Context {
@@ -465,6 +800,7 @@ impl Insertable for RawUpload {
         "env",
         "session_type",
         "session_extras",
+        "external_id",
     ];
 
     fn extend_params<'a>(&'a self, params: &mut Vec<&'a dyn rusqlite::ToSql>) {
@@ -482,6 +818,7 @@ impl Insertable for RawUpload {
             &self.env as &dyn rusqlite::ToSql,
             &self.session_type as &dyn rusqlite::ToSql,
             &self.session_extras as &dyn rusqlite::ToSql,
+            &self.external_id as &dyn rusqlite::ToSql,
         ])
     }
 }
@@ -516,6 +853,7 @@ impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for RawUpload {
             env: row.get(row.as_ref().column_index("env")?)?,
             session_type: row.get(row.as_ref().column_index("session_type")?)?,
             session_extras,
+            external_id: row.get(row.as_ref().column_index("external_id")?)?,
         })
     }
 }
@@ -551,6 +889,59 @@ impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for ReportTotals {
     }
 }
 
+impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for SummaryCounts {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &'a ::rusqlite::Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            files: row.get(row.as_ref().column_index("files")?)?,
+            lines: row.get(row.as_ref().column_index("lines")?)?,
+            hits: row.get(row.as_ref().column_index("hits")?)?,
+            misses: row.get(row.as_ref().column_index("misses")?)?,
+            partials: row.get(row.as_ref().column_index("partials")?)?,
+            hit_branches: row.get(row.as_ref().column_index("hit_branches")?)?,
+            total_branches: row.get(row.as_ref().column_index("total_branches")?)?,
+        })
+    }
+}
+
+impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for LineAttribute {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &'a ::rusqlite::Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            raw_upload_id: row.get(row.as_ref().column_index("raw_upload_id")?)?,
+            source_file_id: row.get(row.as_ref().column_index("source_file_id")?)?,
+            line_no: row.get(row.as_ref().column_index("line_no")?)?,
+            attributes: row.get(row.as_ref().column_index("attributes")?)?,
+        })
+    }
+}
+
+impl Insertable for LineAttribute {
+    const TABLE_NAME: &'static str = "line_attribute";
+    const FIELDS: &'static [&'static str] =
+        &["raw_upload_id", "source_file_id", "line_no", "attributes"];
+    const UPSERT: Option<&'static str> =
+        Some("ON CONFLICT DO UPDATE SET attributes = attributes | excluded.attributes");
+
+    fn extend_params<'a>(&'a self, params: &mut Vec<&'a dyn rusqlite::ToSql>) {
+        params.extend(&[
+            &self.raw_upload_id as &dyn rusqlite::ToSql,
+            &self.source_file_id as &dyn rusqlite::ToSql,
+            &self.line_no as &dyn rusqlite::ToSql,
+            &self.attributes as &dyn rusqlite::ToSql,
+        ])
+    }
+
+    fn references(&self) -> Vec<Reference> {
+        vec![
+            Reference::RawUpload(self.raw_upload_id),
+            Reference::SourceFile(self.source_file_id),
+        ]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::json;
@@ -563,6 +954,7 @@ mod tests {
         },
         *,
     };
+    use crate::error::{CodecovError, ConstraintKind};
 
     #[derive(PartialEq, Debug)]
     struct TestModel {
@@ -594,6 +986,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn json_query_builder() {
+        assert_eq!(
+            TestModel::build_json_query(),
+            "INSERT INTO test (id, data) SELECT json_extract(value, '$[0]'), json_extract(value, '$[1]') FROM json_each(?1) WHERE true;"
+        );
+    }
+
+    #[test]
+    fn test_json_from_sql() {
+        let json = |value: rusqlite::types::Value| json_from_sql(&ToSqlOutput::Owned(value));
+        assert_eq!(json(rusqlite::types::Value::Null), Some(json!(null)));
+        assert_eq!(json(i64::MAX.into()), Some(json!(i64::MAX)));
+        assert_eq!(json(0.5.into()), Some(json!(0.5)));
+        assert_eq!(json("'[1]'".to_string().into()), Some(json!("'[1]'")));
+        assert_eq!(json(f64::NAN.into()), None);
+        assert_eq!(json(vec![0u8].into()), None);
+    }
+
     impl<'a> std::convert::TryFrom<&'a rusqlite::Row<'a>> for TestModel {
         type Error = rusqlite::Error;
 
@@ -649,8 +1060,10 @@ mod tests {
             data: "foo".to_string(),
         };
 
-        model.insert(&ctx.report.conn).unwrap();
-        let duplicate_result = model.insert(&ctx.report.conn);
+        model
+            .insert(&ctx.report.conn, &ctx.report.statement_cache)
+            .unwrap();
+        let duplicate_result = model.insert(&ctx.report.conn, &ctx.report.statement_cache);
 
         let test_models = list_test_models(&ctx.report);
         assert_eq!(test_models, vec![model]);
@@ -676,7 +1089,34 @@ mod tests {
             })
             .collect();
 
-        TestModel::multi_insert(models_to_insert.iter(), &ctx.report.conn).unwrap();
+        TestModel::multi_insert(
+            models_to_insert.iter(),
+            &ctx.report.conn,
+            &ctx.report.statement_cache,
+        )
+        .unwrap();
+
+        let test_models = list_test_models(&ctx.report);
+        assert_eq!(test_models, models_to_insert);
+    }
+
+    #[test]
+    fn test_test_model_multi_insert_json() {
+        let ctx = setup();
+
+        let models_to_insert: Vec<_> = (0..(JSON_CHUNK_SIZE as i64 + 111))
+            .map(|id| TestModel {
+                id,
+                data: format!("Test \"{id}\""),
+            })
+            .collect();
+
+        TestModel::multi_insert_json(
+            models_to_insert.iter(),
+            &ctx.report.conn,
+            &ctx.report.statement_cache,
+        )
+        .unwrap();
 
         let test_models = list_test_models(&ctx.report);
         assert_eq!(test_models, models_to_insert);
@@ -689,10 +1129,14 @@ mod tests {
         let model = SourceFile {
             id: 0,
             path: "src/report/report.rs".to_string(),
+            language: Some("rust".to_string()),
+            ..Default::default()
         };
 
-        model.insert(&ctx.report.conn).unwrap();
-        let duplicate_result = model.insert(&ctx.report.conn);
+        model
+            .insert(&ctx.report.conn, &ctx.report.statement_cache)
+            .unwrap();
+        let duplicate_result = model.insert(&ctx.report.conn, &ctx.report.statement_cache);
 
         let files = ctx.report.list_files().unwrap();
         assert_eq!(files, vec![model]);
@@ -702,6 +1146,14 @@ mod tests {
             error.to_string(),
             "sqlite failure: 'UNIQUE constraint failed: source_file.id'"
         );
+        assert!(matches!(
+            error,
+            CodecovError::SqliteConstraintViolation {
+                kind: ConstraintKind::PrimaryKey,
+                ref columns,
+                ..
+            } if columns == &["source_file.id"]
+        ));
     }
 
     #[test]
@@ -713,8 +1165,10 @@ mod tests {
             name: "test_upload".to_string(),
         };
 
-        model.insert(&ctx.report.conn).unwrap();
-        let duplicate_result = model.insert(&ctx.report.conn);
+        model
+            .insert(&ctx.report.conn, &ctx.report.statement_cache)
+            .unwrap();
+        let duplicate_result = model.insert(&ctx.report.conn, &ctx.report.statement_cache);
 
         let contexts = ctx.report.list_contexts().unwrap();
         assert_eq!(contexts, vec![model]);
@@ -744,13 +1198,14 @@ mod tests {
             raw_upload_id: raw_upload.id,
             local_sample_id: Some(rand::random()),
             local_span_id: None,
+            source_file_id: None,
         };
 
-        model.insert(&report.conn).unwrap();
+        model.insert(&report.conn, &report.statement_cache).unwrap();
         let assoc: ContextAssoc = report
             .conn
             .query_row(
-                "SELECT context_id, raw_upload_id, local_sample_id, local_span_id FROM context_assoc",
+                "SELECT context_id, raw_upload_id, local_sample_id, local_span_id, source_file_id FROM context_assoc",
                 [],
                 |row| row.try_into(),
             )
@@ -787,8 +1242,8 @@ mod tests {
             ..Default::default()
         };
 
-        model.insert(&report.conn).unwrap();
-        let duplicate_result = model.insert(&report.conn);
+        model.insert(&report.conn, &report.statement_cache).unwrap();
+        let duplicate_result = model.insert(&report.conn, &report.statement_cache);
 
         let samples = report.list_coverage_samples().unwrap();
         assert_eq!(samples, vec![model]);
@@ -798,6 +1253,14 @@ mod tests {
             error.to_string(),
             "sqlite failure: 'UNIQUE constraint failed: coverage_sample.raw_upload_id, coverage_sample.local_sample_id'"
         );
+        assert!(matches!(
+            error,
+            CodecovError::SqliteConstraintViolation {
+                kind: ConstraintKind::Unique,
+                ref columns,
+                ..
+            } if columns == &["coverage_sample.raw_upload_id", "coverage_sample.local_sample_id"]
+        ));
     }
 
     #[test]
@@ -820,7 +1283,7 @@ mod tests {
             source_file_id: source_file.id,
             ..Default::default()
         }
-        .insert(&report.conn)
+        .insert(&report.conn, &report.statement_cache)
         .unwrap();
 
         let model = BranchesData {
@@ -831,8 +1294,8 @@ mod tests {
             ..Default::default()
         };
 
-        model.insert(&report.conn).unwrap();
-        let duplicate_result = model.insert(&report.conn);
+        model.insert(&report.conn, &report.statement_cache).unwrap();
+        let duplicate_result = model.insert(&report.conn, &report.statement_cache);
 
         let branch: BranchesData = report
             .conn
@@ -878,13 +1341,13 @@ mod tests {
             ..Default::default()
         };
 
-        model.insert(&report.conn).unwrap();
-        let duplicate_result = model.insert(&report.conn);
+        model.insert(&report.conn, &report.statement_cache).unwrap();
+        let duplicate_result = model.insert(&report.conn, &report.statement_cache);
 
         let method: MethodData = report
             .conn
             .query_row(
-                "SELECT raw_upload_id, local_method_id, source_file_id, local_sample_id, line_no, hit_branches, total_branches, hit_complexity_paths, total_complexity FROM method_data",
+                "SELECT raw_upload_id, local_method_id, source_file_id, local_sample_id, line_no, hit_branches, total_branches, hit_complexity_paths, total_complexity, name, signature FROM method_data",
                 [],
                 |row| row.try_into(),
             ).unwrap();
@@ -917,8 +1380,8 @@ mod tests {
             ..Default::default()
         };
 
-        model.insert(&report.conn).unwrap();
-        let duplicate_result = model.insert(&report.conn);
+        model.insert(&report.conn, &report.statement_cache).unwrap();
+        let duplicate_result = model.insert(&report.conn, &report.statement_cache);
 
         let branch: SpanData = report
             .conn
@@ -954,10 +1417,13 @@ mod tests {
             env: Some("env".to_string()),
             session_type: Some("uploaded".to_string()),
             session_extras: Some(json!({})),
+            external_id: Some("upload-5".to_string()),
         };
 
-        model.insert(&ctx.report.conn).unwrap();
-        let duplicate_result = model.insert(&ctx.report.conn);
+        model
+            .insert(&ctx.report.conn, &ctx.report.statement_cache)
+            .unwrap();
+        let duplicate_result = model.insert(&ctx.report.conn, &ctx.report.statement_cache);
 
         let uploads = ctx.report.list_raw_uploads().unwrap();
         assert_eq!(uploads, vec![model]);
@@ -0,0 +1,29 @@
+select
+  coalesce(sum(iif(coverage_sample.coverage_type in ('l', 's') and coverage_sample.hits > 0, 1, 0)), 0) as hit_lines,
+  coalesce(sum(iif(coverage_sample.coverage_type in ('l', 's'), 1, 0)), 0) as total_lines,
+  coalesce(sum(iif(coverage_sample.coverage_type = 'b', coverage_sample.hit_branches, 0)), 0) as hit_branches,
+  coalesce(sum(iif(coverage_sample.coverage_type = 'b', coverage_sample.total_branches, 0)), 0) as total_branches,
+  coalesce(sum(iif(coverage_sample.coverage_type = 'b', 1, 0)), 0) as total_branch_roots,
+  coalesce(sum(iif(coverage_sample.coverage_type = 'm' and coverage_sample.hits > 0, 1, 0)), 0) as hit_methods,
+  coalesce(sum(iif(coverage_sample.coverage_type = 'm', 1, 0)), 0) as total_methods,
+  coalesce(sum(method_data.hit_complexity_paths), 0) as hit_complexity_paths,
+  coalesce(sum(method_data.total_complexity), 0) as total_complexity
+from
+  coverage_sample
+left join
+  method_data
+on
+  coverage_sample.raw_upload_id = method_data.raw_upload_id
+  and coverage_sample.local_sample_id = method_data.local_sample_id
+where
+  coverage_sample.source_file_id = ?1
+  and coverage_sample.superseded = 0
+  -- Lines with attributes don't count
+  and not exists (
+    select 1 from line_attribute
+    where
+      line_attribute.raw_upload_id = coverage_sample.raw_upload_id
+      and line_attribute.source_file_id = coverage_sample.source_file_id
+      and line_attribute.line_no = coverage_sample.line_no
+      and line_attribute.attributes != 0
+  )
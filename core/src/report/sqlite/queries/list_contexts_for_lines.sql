@@ -0,0 +1,39 @@
+-- `?1` is a JSON array of `[path, line_no]` pairs.
+with changed_lines as (
+select
+  json_extract(json_each.value, '$[0]') as path,
+  json_extract(json_each.value, '$[1]') as line_no
+from
+  json_each(?1)
+)
+select distinct
+  context.id,
+  context.name
+from
+  changed_lines
+join
+  source_file
+on
+  source_file.path = changed_lines.path
+-- Samples with contexts are never packed into runs, so the table has all of
+-- them and its indexes can be used
+join
+  main.coverage_sample
+on
+  main.coverage_sample.source_file_id = source_file.id
+  and main.coverage_sample.line_no = changed_lines.line_no
+join
+  context_assoc
+on
+  context_assoc.raw_upload_id = main.coverage_sample.raw_upload_id
+  and context_assoc.local_sample_id = main.coverage_sample.local_sample_id
+join
+  context
+on
+  context.id = context_assoc.context_id
+where
+  main.coverage_sample.superseded = 0
+  and context_assoc.superseded = 0
+order by
+  context.name,
+  context.id
@@ -0,0 +1,25 @@
+select
+  source_file.id,
+  source_file.path,
+  source_file.language,
+  source_file.content_hash,
+  source_file.line_count,
+  source_file.chunk_index,
+  v_file_totals.hit_lines,
+  v_file_totals.total_lines,
+  v_file_totals.hit_branches,
+  v_file_totals.total_branches,
+  v_file_totals.total_branch_roots,
+  v_file_totals.hit_methods,
+  v_file_totals.total_methods,
+  v_file_totals.hit_complexity_paths,
+  v_file_totals.total_complexity
+from
+  source_file
+join
+  v_file_totals
+on
+  v_file_totals.source_file_id = source_file.id
+order by
+  source_file.path,
+  source_file.id
@@ -0,0 +1,32 @@
+select
+  raw_upload.id,
+  raw_upload.timestamp,
+  raw_upload.raw_upload_url,
+  raw_upload.flags,
+  raw_upload.provider,
+  raw_upload.build,
+  raw_upload.name,
+  raw_upload.job_name,
+  raw_upload.ci_run_url,
+  raw_upload.state,
+  raw_upload.env,
+  raw_upload.session_type,
+  raw_upload.session_extras,
+  raw_upload.external_id,
+  v_upload_totals.hit_lines,
+  v_upload_totals.total_lines,
+  v_upload_totals.hit_branches,
+  v_upload_totals.total_branches,
+  v_upload_totals.total_branch_roots,
+  v_upload_totals.hit_methods,
+  v_upload_totals.total_methods,
+  v_upload_totals.hit_complexity_paths,
+  v_upload_totals.total_complexity
+from
+  raw_upload
+join
+  v_upload_totals
+on
+  v_upload_totals.raw_upload_id = raw_upload.id
+order by
+  raw_upload.id
@@ -0,0 +1,90 @@
+-- Determine whether each `coverage_sample` record is a hit/miss/partial/skip.
+-- Must match the corresponding logic in `files_to_report_json.sql`.
+with samples_categorized as (
+select
+  coverage_sample.raw_upload_id,
+  coverage_sample.source_file_id,
+  coverage_sample.line_no,
+  coverage_sample.hit_branches,
+  coverage_sample.total_branches,
+  iif(
+    coverage_sample.hits > 0 or coverage_sample.hit_branches >= coverage_sample.total_branches,
+    2,     -- hit
+    iif(
+      coverage_sample.hits = 0 or coverage_sample.hit_branches = 0,
+      0,   -- miss
+      iif(
+        coverage_sample.hit_branches > 0 and coverage_sample.hit_branches < coverage_sample.total_branches,
+        1, -- partial
+        -1 -- skipped
+      )
+    )
+  ) as coverage_status
+from
+  coverage_sample
+where
+  coverage_sample.superseded = 0
+  -- Lines with attributes don't count
+  and not exists (
+    select 1 from line_attribute
+    where
+      line_attribute.raw_upload_id = coverage_sample.raw_upload_id
+      and line_attribute.source_file_id = coverage_sample.source_file_id
+      and line_attribute.line_no = coverage_sample.line_no
+      and line_attribute.attributes != 0
+  )
+),
+-- Each upload appears once with a null flag, which makes up the whole-report
+-- summary, and once more for each of its flags.
+upload_flags as (
+select
+  raw_upload.id as raw_upload_id,
+  null as flag
+from
+  raw_upload
+union all
+select
+  raw_upload.id as raw_upload_id,
+  json_each.value as flag
+from
+  raw_upload,
+  json_each(raw_upload.flags)
+where
+  raw_upload.flags is not null
+),
+-- Flatten the samples for each (flag, source_file, line) into a single record,
+-- picking the "most covered" status the same way `files_to_report_json.sql`
+-- does.
+flag_lines_flattened as (
+select
+  upload_flags.flag,
+  samples_categorized.source_file_id,
+  samples_categorized.line_no,
+  max(samples_categorized.coverage_status) as coverage_status,
+  max(samples_categorized.hit_branches) as hit_branches,
+  max(samples_categorized.total_branches) as total_branches
+from
+  samples_categorized
+join
+  upload_flags
+on
+  upload_flags.raw_upload_id = samples_categorized.raw_upload_id
+group by
+  1, 2, 3
+)
+select
+  flag_lines_flattened.flag,
+  count(distinct flag_lines_flattened.source_file_id) as files,
+  count(*) as lines,
+  sum(iif(flag_lines_flattened.coverage_status = 2, 1, 0)) as hits,
+  sum(iif(flag_lines_flattened.coverage_status = 0, 1, 0)) as misses,
+  sum(iif(flag_lines_flattened.coverage_status = 1, 1, 0)) as partials,
+  coalesce(sum(flag_lines_flattened.hit_branches), 0) as hit_branches,
+  coalesce(sum(flag_lines_flattened.total_branches), 0) as total_branches
+from
+  flag_lines_flattened
+group by
+  1
+order by
+  flag_lines_flattened.flag is not null,
+  flag_lines_flattened.flag
@@ -0,0 +1,240 @@
+//! Renaming files and contexts in a report that's already been built.
+//!
+//! A [`models::SourceFile`]'s ID is the hash of its path and a
+//! [`models::Context`]'s is the hash of its name, so renaming one gives it a
+//! new ID, and every row that refers to the old ID has to follow. That makes
+//! fixing up paths after ingestion, or migrating test names, as cheap as a few
+//! `UPDATE`s instead of rebuilding the report from its uploads.
+
+use rusqlite::OptionalExtension;
+
+use super::SqliteReport;
+use crate::{
+    error::{CodecovError, Result},
+    report::models,
+};
+
+/// The tables with a `source_file_id` column.
+const FILE_TABLES: [&str; 8] = [
+    "coverage_sample",
+    "branches_data",
+    "method_data",
+    "span_data",
+    "context_assoc",
+    "coverage_run",
+    "session_file_totals",
+    "line_attribute",
+];
+
+impl SqliteReport {
+    /// Renames the file at `old_path` to `new_path`, moving everything
+    /// measured in it along with it, all in one transaction. Returns the
+    /// renamed file. Fails without changing anything if there's no file at
+    /// `old_path` or there's already one at `new_path`.
+    pub fn rename_file(&mut self, old_path: &str, new_path: &str) -> Result<models::SourceFile> {
+        let old_id = models::SourceFile::new(old_path).id;
+        let new_id = models::SourceFile::new(new_path).id;
+
+        let tx = self.conn.transaction()?;
+        if old_path != new_path && exists(&tx, "source_file", new_id)? {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "a file with path {new_path:?} already exists"
+            )));
+        }
+        // The file's ID changes before the rows that refer to it do
+        tx.pragma_update(None, "defer_foreign_keys", true)?;
+        let file = self
+            .statement_cache
+            .prepare_cached(
+                &tx,
+                "UPDATE source_file SET id = ?2, path = ?3 WHERE id = ?1
+                 RETURNING id, path, language, content_hash, line_count, chunk_index",
+            )?
+            .query_row((old_id, new_id, new_path), |row| row.try_into())
+            .optional()?;
+        let Some(file) = file else {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "no file with path {old_path:?}"
+            )));
+        };
+        for table in FILE_TABLES {
+            self.statement_cache
+                .prepare_cached(
+                    &tx,
+                    &format!("UPDATE {table} SET source_file_id = ?2 WHERE source_file_id = ?1"),
+                )?
+                .execute((old_id, new_id))?;
+        }
+        tx.commit()?;
+        Ok(file)
+    }
+
+    /// Renames the context named `old_name` to `new_name`, keeping everything
+    /// it's attached to, all in one transaction. Returns the renamed context.
+    /// Fails without changing anything if there's no context named `old_name`
+    /// or there's already one named `new_name`.
+    pub fn rename_context(&mut self, old_name: &str, new_name: &str) -> Result<models::Context> {
+        let old_id = models::Context::new(old_name).id;
+        let new_id = models::Context::new(new_name).id;
+
+        let tx = self.conn.transaction()?;
+        if old_name != new_name && exists(&tx, "context", new_id)? {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "a context named {new_name:?} already exists"
+            )));
+        }
+        tx.pragma_update(None, "defer_foreign_keys", true)?;
+        let context = self
+            .statement_cache
+            .prepare_cached(
+                &tx,
+                "UPDATE context SET id = ?2, name = ?3 WHERE id = ?1 RETURNING id, name",
+            )?
+            .query_row((old_id, new_id, new_name), |row| row.try_into())
+            .optional()?;
+        let Some(context) = context else {
+            return Err(CodecovError::ReportBuilderError(format!(
+                "no context named {old_name:?}"
+            )));
+        };
+        self.statement_cache
+            .prepare_cached(
+                &tx,
+                "UPDATE context_assoc SET context_id = ?2 WHERE context_id = ?1",
+            )?
+            .execute((old_id, new_id))?;
+        tx.commit()?;
+        Ok(context)
+    }
+}
+
+/// Whether `table` has a row with ID `id`.
+fn exists(conn: &rusqlite::Connection, table: &str, id: i64) -> Result<bool> {
+    Ok(conn.query_row(
+        &format!("SELECT EXISTS (SELECT 1 FROM {table} WHERE id = ?1)"),
+        [id],
+        |row| row.get(0),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{report::Report, test_utils::sqlite_report::build_sample_report};
+
+    fn assert_no_dangling_references(report: &SqliteReport) {
+        let mut stmt = report.conn.prepare("PRAGMA foreign_key_check").unwrap();
+        assert!(stmt.query([]).unwrap().next().unwrap().is_none());
+        let dangling_assocs: i64 = report
+            .conn
+            .query_row(
+                "SELECT count(*) FROM context_assoc WHERE context_id NOT IN (SELECT id FROM context)",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(dangling_assocs, 0);
+    }
+
+    #[test]
+    fn test_rename_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report = build_sample_report(temp_dir.path().join("db.sqlite")).unwrap();
+        let files = report.list_files().unwrap();
+        let old = &files[0];
+        let totals = report.file_totals(old).unwrap();
+        let sample_count = |report: &SqliteReport, file: &models::SourceFile| {
+            report
+                .list_coverage_samples()
+                .unwrap()
+                .iter()
+                .filter(|sample| sample.source_file_id == file.id)
+                .count()
+        };
+        let samples = sample_count(&report, old);
+        assert!(samples > 0);
+
+        let renamed = report.rename_file(&old.path, "renamed.rs").unwrap();
+        assert_eq!(renamed.id, models::SourceFile::new("renamed.rs").id);
+        assert_eq!(renamed.path, "renamed.rs");
+        assert_eq!(renamed.line_count, old.line_count);
+
+        let paths: Vec<String> = report
+            .list_files()
+            .unwrap()
+            .into_iter()
+            .map(|file| file.path)
+            .collect();
+        assert!(paths.contains(&"renamed.rs".to_string()));
+        assert!(!paths.contains(&old.path));
+        assert_eq!(sample_count(&report, old), 0);
+        assert_eq!(sample_count(&report, &renamed), samples);
+        assert_eq!(report.file_totals(&renamed).unwrap(), totals);
+        assert_no_dangling_references(&report);
+    }
+
+    #[test]
+    fn test_rename_file_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report = build_sample_report(temp_dir.path().join("db.sqlite")).unwrap();
+        let files = report.list_files().unwrap();
+
+        assert!(matches!(
+            report.rename_file(&files[0].path, &files[1].path),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+        assert!(matches!(
+            report.rename_file("missing.rs", "renamed.rs"),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+        assert_eq!(report.list_files().unwrap(), files);
+
+        // Renaming a file to its own path changes nothing
+        assert_eq!(
+            report.rename_file(&files[0].path, &files[0].path).unwrap(),
+            files[0]
+        );
+        assert_eq!(report.list_files().unwrap(), files);
+    }
+
+    #[test]
+    fn test_rename_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report = build_sample_report(temp_dir.path().join("db.sqlite")).unwrap();
+        let contexts = report.list_contexts().unwrap();
+        let old = &contexts[0];
+        let labeled_samples = |report: &SqliteReport, name: &str| {
+            report
+                .list_coverage_samples()
+                .unwrap()
+                .iter()
+                .filter(|sample| {
+                    report
+                        .list_contexts_for_sample(sample)
+                        .unwrap()
+                        .iter()
+                        .any(|context| context.name == name)
+                })
+                .count()
+        };
+        let labeled = labeled_samples(&report, &old.name);
+
+        let renamed = report.rename_context(&old.name, "renamed").unwrap();
+        assert_eq!(renamed, models::Context::new("renamed"));
+        assert_eq!(labeled_samples(&report, "renamed"), labeled);
+        assert_eq!(labeled_samples(&report, &old.name), 0);
+        assert_eq!(report.list_contexts().unwrap().len(), contexts.len());
+
+        assert!(matches!(
+            report.rename_context("renamed", &contexts[1].name),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+        assert!(matches!(
+            report.rename_context(&old.name, "other"),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+        assert_no_dangling_references(&report);
+    }
+}
@@ -0,0 +1,271 @@
+//! Fixes for corruption we've seen in reports written by workers that crashed
+//! partway through.
+
+use super::SqliteReport;
+use crate::error::Result;
+
+/// Whether [`SqliteReport::repair`] changes the report or only reports what
+/// it would change.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum RepairMode {
+    #[default]
+    Apply,
+    DryRun,
+}
+
+/// A kind of problem [`SqliteReport::repair`] knows how to fix.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RepairKind {
+    /// Samples whose upload or file doesn't exist. They're deleted.
+    OrphanedSamples,
+    /// Branch, method or span records whose sample doesn't exist. They're
+    /// deleted.
+    OrphanedMeasurements,
+    /// Upload tags, session file totals, line attributes or packed coverage
+    /// runs whose upload or file doesn't exist. They're deleted.
+    OrphanedUploadData,
+    /// Context associations whose context, upload, file, sample or span
+    /// doesn't exist. They're deleted.
+    OrphanedContextAssocs,
+    /// Context associations that are identical to an earlier one. All but the
+    /// first are deleted.
+    DuplicateContextAssocs,
+    /// Samples with both a hit count and a branch fraction. The fields that
+    /// don't go with the sample's coverage type are cleared.
+    ConflictingSampleValues,
+}
+
+/// The number of rows [`SqliteReport::repair`] deleted or changed (or would
+/// have) for one [`RepairKind`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct RepairStep {
+    pub kind: RepairKind,
+    pub rows: usize,
+}
+
+/// What [`SqliteReport::repair`] did, or would do. Kinds of problems that
+/// weren't found are left out.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct RepairPlan {
+    pub steps: Vec<RepairStep>,
+}
+
+impl RepairPlan {
+    /// Whether the report had nothing to repair.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+/// Repairs in the order they run. Each one may leave behind problems only
+/// a later one fixes, e.g. deleting orphaned samples orphans their branches.
+const REPAIRS: &[(RepairKind, &str)] = &[
+    (
+        RepairKind::OrphanedSamples,
+        "DELETE FROM coverage_sample WHERE raw_upload_id NOT IN (SELECT id FROM raw_upload) OR source_file_id NOT IN (SELECT id FROM source_file)",
+    ),
+    (
+        RepairKind::OrphanedMeasurements,
+        "DELETE FROM branches_data WHERE NOT EXISTS (SELECT 1 FROM coverage_sample sample WHERE sample.raw_upload_id = branches_data.raw_upload_id AND sample.local_sample_id = branches_data.local_sample_id);
+         DELETE FROM method_data WHERE NOT EXISTS (SELECT 1 FROM coverage_sample sample WHERE sample.raw_upload_id = method_data.raw_upload_id AND sample.local_sample_id = method_data.local_sample_id);
+         DELETE FROM span_data WHERE local_sample_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM coverage_sample sample WHERE sample.raw_upload_id = span_data.raw_upload_id AND sample.local_sample_id = span_data.local_sample_id)",
+    ),
+    (
+        RepairKind::OrphanedUploadData,
+        "DELETE FROM upload_tag WHERE raw_upload_id NOT IN (SELECT id FROM raw_upload);
+         DELETE FROM session_file_totals WHERE raw_upload_id NOT IN (SELECT id FROM raw_upload) OR source_file_id NOT IN (SELECT id FROM source_file);
+         DELETE FROM line_attribute WHERE raw_upload_id NOT IN (SELECT id FROM raw_upload) OR source_file_id NOT IN (SELECT id FROM source_file);
+         DELETE FROM main.coverage_run WHERE raw_upload_id NOT IN (SELECT id FROM raw_upload) OR source_file_id NOT IN (SELECT id FROM source_file)",
+    ),
+    (
+        RepairKind::OrphanedContextAssocs,
+        "DELETE FROM context_assoc WHERE context_id NOT IN (SELECT id FROM context)
+           OR raw_upload_id NOT IN (SELECT id FROM raw_upload)
+           OR source_file_id NOT IN (SELECT id FROM source_file)
+           OR (local_sample_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM coverage_sample sample WHERE sample.raw_upload_id = context_assoc.raw_upload_id AND sample.local_sample_id = context_assoc.local_sample_id))
+           OR (local_span_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM span_data span WHERE span.raw_upload_id = context_assoc.raw_upload_id AND span.local_span_id = context_assoc.local_span_id))",
+    ),
+    (
+        // `GROUP BY` considers `NULL`s equal even though the primary key doesn't
+        RepairKind::DuplicateContextAssocs,
+        "DELETE FROM context_assoc WHERE rowid NOT IN (SELECT min(rowid) FROM context_assoc GROUP BY context_id, raw_upload_id, local_sample_id, local_span_id, source_file_id)",
+    ),
+    (
+        RepairKind::ConflictingSampleValues,
+        "UPDATE coverage_sample
+         SET
+           hits = iif(coverage_type = 'b', NULL, hits),
+           hit_branches = iif(coverage_type = 'b', hit_branches, NULL),
+           total_branches = iif(coverage_type = 'b', total_branches, NULL)
+         WHERE hits IS NOT NULL AND (hit_branches IS NOT NULL OR total_branches IS NOT NULL)",
+    ),
+];
+
+impl SqliteReport {
+    /// Fixes the problems described by [`RepairKind`], all in one transaction.
+    /// With [`RepairMode::DryRun`] the transaction is rolled back, so the
+    /// returned plan is exactly what [`RepairMode::Apply`] would do.
+    pub fn repair(&mut self, mode: RepairMode) -> Result<RepairPlan> {
+        let tx = self.conn.transaction()?;
+        // Orphans are deleted parents-first, which would trip foreign keys if
+        // they were checked before the whole repair is done
+        tx.pragma_update(None, "defer_foreign_keys", true)?;
+        let mut plan = RepairPlan::default();
+        for (kind, sql) in REPAIRS {
+            let mut rows = 0;
+            for statement in sql.split(';') {
+                rows += tx.execute(statement, [])?;
+            }
+            if rows > 0 {
+                plan.steps.push(RepairStep { kind: *kind, rows });
+            }
+        }
+
+        match mode {
+            RepairMode::Apply => tx.commit()?,
+            RepairMode::DryRun => tx.rollback()?,
+        }
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        report::{models, Report},
+        test_utils::sqlite_report::build_sample_report,
+    };
+
+    struct Ctx {
+        temp_dir: TempDir,
+    }
+
+    fn setup() -> Ctx {
+        Ctx {
+            temp_dir: TempDir::new().ok().unwrap(),
+        }
+    }
+
+    fn corrupt(report: &SqliteReport) {
+        report
+            .conn
+            .execute_batch(
+                "
+-- Reports written with foreign keys enforced can't have orphans
+PRAGMA foreign_keys = OFF;
+-- A sample for an upload that doesn't exist, with a branch and a context
+INSERT INTO coverage_sample (raw_upload_id, local_sample_id, source_file_id, line_no, coverage_type, hits) SELECT 999, 0, id, 1, 'l', 1 FROM source_file LIMIT 1;
+INSERT INTO branches_data (raw_upload_id, local_sample_id, local_branch_id, source_file_id, hits, branch_format, branch) SELECT 999, 0, 0, id, 1, 'l', '2' FROM source_file LIMIT 1;
+INSERT INTO context_assoc (context_id, raw_upload_id, local_sample_id) SELECT id, 999, 0 FROM context LIMIT 1;
+-- A duplicate of an existing upload-wide association
+INSERT INTO context_assoc (context_id, raw_upload_id) SELECT id, 5 FROM context LIMIT 1;
+INSERT INTO context_assoc (context_id, raw_upload_id) SELECT id, 5 FROM context LIMIT 1;
+-- Line samples with branch fractions
+UPDATE coverage_sample SET hit_branches = 1, total_branches = 2 WHERE coverage_type = 'l' AND raw_upload_id = 5;
+PRAGMA foreign_keys = ON;
+",
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_repair_clean_report() {
+        let ctx = setup();
+        let mut report = build_sample_report(ctx.temp_dir.path().join("db.sqlite")).unwrap();
+        assert!(report.repair(RepairMode::Apply).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_repair() {
+        let ctx = setup();
+        let mut report = build_sample_report(ctx.temp_dir.path().join("db.sqlite")).unwrap();
+        let samples = report.list_coverage_samples().unwrap();
+        let totals = report.totals().unwrap();
+        corrupt(&report);
+        let conflicting_samples = samples
+            .iter()
+            .filter(|sample| {
+                sample.raw_upload_id == 5 && sample.coverage_type == models::CoverageType::Line
+            })
+            .count();
+        assert!(conflicting_samples > 0);
+
+        let expected_plan = RepairPlan {
+            steps: vec![
+                RepairStep {
+                    kind: RepairKind::OrphanedSamples,
+                    rows: 1,
+                },
+                RepairStep {
+                    kind: RepairKind::OrphanedMeasurements,
+                    rows: 1,
+                },
+                RepairStep {
+                    kind: RepairKind::OrphanedContextAssocs,
+                    rows: 1,
+                },
+                RepairStep {
+                    kind: RepairKind::DuplicateContextAssocs,
+                    rows: 1,
+                },
+                RepairStep {
+                    kind: RepairKind::ConflictingSampleValues,
+                    rows: conflicting_samples,
+                },
+            ],
+        };
+
+        // A dry run doesn't change anything, so it can be repeated
+        assert_eq!(report.repair(RepairMode::DryRun).unwrap(), expected_plan);
+        assert_eq!(report.repair(RepairMode::DryRun).unwrap(), expected_plan);
+
+        assert_eq!(report.repair(RepairMode::Apply).unwrap(), expected_plan);
+        assert!(report.repair(RepairMode::DryRun).unwrap().is_empty());
+        assert_eq!(report.list_coverage_samples().unwrap(), samples);
+        assert_eq!(report.totals().unwrap(), totals);
+    }
+
+    #[test]
+    fn test_repair_orphaned_upload_data() {
+        let ctx = setup();
+        let mut report = build_sample_report(ctx.temp_dir.path().join("db.sqlite")).unwrap();
+        let orphans = [
+            ("upload_tag", "INSERT INTO upload_tag (raw_upload_id, key, value) VALUES (999, 'flag', 'unit')"),
+            ("session_file_totals", "INSERT INTO session_file_totals (source_file_id, raw_upload_id, files, lines, hits, misses, partials, branches, methods, messages, sessions, complexity, complexity_total, diff) VALUES (999, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0)"),
+            ("line_attribute", "INSERT INTO line_attribute (raw_upload_id, source_file_id, line_no, attributes) VALUES (999, 999, 1, 1)"),
+            ("coverage_run", "INSERT INTO main.coverage_run (raw_upload_id, first_local_sample_id, source_file_id, first_line_no, last_line_no, hits) VALUES (999, 0, 999, 1, 2, 1)"),
+        ];
+        for (table, sql) in orphans {
+            let count = |report: &SqliteReport| -> i64 {
+                report
+                    .conn
+                    .query_row(&format!("SELECT count(*) FROM main.{table}"), [], |row| {
+                        row.get(0)
+                    })
+                    .unwrap()
+            };
+            let before = count(&report);
+            report
+                .conn
+                .execute_batch(&format!(
+                    "PRAGMA foreign_keys = OFF; {sql}; PRAGMA foreign_keys = ON;"
+                ))
+                .unwrap();
+
+            assert_eq!(
+                report.repair(RepairMode::Apply).unwrap(),
+                RepairPlan {
+                    steps: vec![RepairStep {
+                        kind: RepairKind::OrphanedUploadData,
+                        rows: 1,
+                    }],
+                },
+                "{table}"
+            );
+            assert_eq!(count(&report), before, "{table}");
+        }
+    }
+}
@@ -1,16 +1,26 @@
-use std::{fmt, path::PathBuf};
+use std::{
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use rusqlite::{Connection, OptionalExtension};
+use rusqlite::{CachedStatement, Connection, OpenFlags, OptionalExtension};
 
-use super::open_database;
+use super::{
+    collapse, json_value_from_sql, open_database, open_database_readonly, pending_migrations, runs,
+    Insertable, StatementCacheStats, StatementCounters,
+};
 use crate::{
-    error::Result,
-    report::{models, Report},
+    error::{CodecovError, Result},
+    report::{models, summary::ReportSummary, MergePolicy, Report},
 };
 
 pub struct SqliteReport {
     pub filename: PathBuf,
     pub conn: Connection,
+
+    pub(crate) statement_cache: StatementCounters,
 }
 
 impl fmt::Debug for SqliteReport {
@@ -22,16 +32,368 @@ impl fmt::Debug for SqliteReport {
 impl SqliteReport {
     pub fn open(filename: PathBuf) -> Result<SqliteReport> {
         let conn = open_database(&filename)?;
-        Ok(SqliteReport { filename, conn })
+        runs::install_run_view(&conn)?;
+        Ok(SqliteReport {
+            filename,
+            conn,
+            statement_cache: StatementCounters::default(),
+        })
+    }
+
+    /// Opens an existing report for reading only, e.g. an archived artifact.
+    /// Unlike [`SqliteReport::open`], this never runs migrations, so it fails
+    /// with [`CodecovError::SchemaVersionMismatch`] if the report's schema
+    /// isn't exactly the latest one. Anything that writes to the report will
+    /// fail.
+    ///
+    /// [`CodecovError::SchemaVersionMismatch`]: crate::error::CodecovError::SchemaVersionMismatch
+    pub fn open_readonly(filename: PathBuf) -> Result<SqliteReport> {
+        let conn = open_database_readonly(&filename)?;
+        runs::install_run_view(&conn)?;
+        Ok(SqliteReport {
+            filename,
+            conn,
+            statement_cache: StatementCounters::default(),
+        })
+    }
+
+    /// Names the migrations, in order, that [`SqliteReport::open`] would run
+    /// on the report at `filename`, without running them or otherwise
+    /// touching the file. A report that doesn't exist yet would get all of
+    /// them. Fails like `open` would if the report was written by a newer
+    /// version of this crate.
+    pub fn pending_migrations(filename: &Path) -> Result<Vec<String>> {
+        pending_migrations(filename)
+    }
+
+    /// Sets how long our connection waits for another connection's lock
+    /// before failing with an error for which [`CodecovError::is_busy`] is
+    /// true. The default is [`super::DEFAULT_BUSY_TIMEOUT`].
+    pub fn set_busy_timeout(&self, timeout: Duration) -> Result<()> {
+        Ok(self.conn.busy_timeout(timeout)?)
+    }
+
+    /// Sets how many prepared statements our connection keeps cached. The
+    /// default is [`super::DEFAULT_STATEMENT_CACHE_CAPACITY`]. Shrinking the
+    /// cache evicts the least recently used statements.
+    pub fn set_statement_cache_capacity(&self, capacity: usize) {
+        self.conn.set_prepared_statement_cache_capacity(capacity);
+    }
+
+    /// How often our connection's prepared statement cache has been hit and
+    /// missed since the report was opened, including by the
+    /// [`super::SqliteReportBuilder`] that built it.
+    pub fn statement_cache_stats(&self) -> StatementCacheStats {
+        self.statement_cache.stats()
+    }
+
+    /// Prepares `sql` through our connection's statement cache, counting the
+    /// hit or miss.
+    pub(crate) fn prepare_cached(&self, sql: &str) -> rusqlite::Result<CachedStatement<'_>> {
+        self.statement_cache.prepare_cached(&self.conn, sql)
+    }
+
+    /// Copies the rows of `T`'s table that match `filter` from the attached
+    /// database `schema` into ours, naming only the columns in
+    /// [`Insertable::FIELDS`] and whether each row is superseded. Local IDs are
+    /// shifted past ours according to `temp.merge_offset`, and references
+    /// to incoming samples that collided with one of ours are pointed at
+    /// ours according to `temp.merge_conflict`.
+    fn merge_table<T: Insertable>(
+        conn: &Connection,
+        statement_cache: &StatementCounters,
+        schema: &str,
+        filter: &str,
+    ) -> Result<()> {
+        let fields = T::FIELDS.join(", ");
+        let values = T::FIELDS
+            .iter()
+            .map(|field| match *field {
+                "local_sample_id" if T::TABLE_NAME != models::CoverageSample::TABLE_NAME => {
+                    "coalesce(conflict.sample_id, incoming.local_sample_id + shift.shift)"
+                        .to_string()
+                }
+                field if field.starts_with("local_") => format!("incoming.{field} + shift.shift"),
+                field => format!("incoming.{field}"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let query = format!(
+            "INSERT INTO {table} ({fields}, superseded) SELECT {values}, incoming.superseded FROM {schema}.{table} incoming INNER JOIN temp.merge_offset shift ON shift.raw_upload_id = incoming.raw_upload_id LEFT JOIN temp.merge_conflict conflict ON conflict.raw_upload_id = incoming.raw_upload_id AND conflict.incoming_sample_id = incoming.local_sample_id WHERE {filter}",
+            table = T::TABLE_NAME
+        );
+        let _ = statement_cache.prepare_cached(conn, &query)?.execute([])?;
+        Ok(())
+    }
+
+    /// Merges the report attached to our connection as `schema` into this
+    /// one in a single transaction. [`Report::merge`] attaches the other
+    /// report, calls this, and detaches it again; call this directly if the
+    /// other report is already attached.
+    ///
+    /// Samples that collide under `policy` are combined into ours. Branches
+    /// of colliding samples are combined branch by branch, or replaced
+    /// wholesale under [`MergePolicy::PreferNewest`]. Under
+    /// [`MergePolicy::SumHits`] and [`MergePolicy::MaxHits`], a colliding
+    /// sample whose branches all have a row is given the hit and total branch
+    /// counts of its merged branches; otherwise it keeps the larger of each.
+    /// Their methods and spans
+    /// are only taken if the incoming sample wins under
+    /// [`MergePolicy::PreferNewest`] (methods) or never (spans).
+    ///
+    /// Fails before anything is merged if the other report has an upload with
+    /// the same [`external_id`](models::RawUpload::external_id) as one of
+    /// ours but a different ID.
+    pub fn merge_attached(&mut self, schema: &str, policy: MergePolicy) -> Result<()> {
+        let schema = format!("\"{}\"", schema.replace('"', "\"\""));
+        runs::ensure_unpacked(&self.conn, &schema)?;
+        collapse::ensure_expanded(&self.conn, &schema)?;
+        ensure_no_duplicate_uploads(&self.conn, &schema)?;
+        let tx = self.conn.transaction()?;
+
+        // Samples from an upload we already have need local IDs that don't
+        // collide with ours. Uploads we don't have are shifted by 0.
+        tx.execute_batch(&format!(
+            "CREATE TEMP TABLE merge_offset (raw_upload_id INTEGER PRIMARY KEY, shift INTEGER NOT NULL);
+             INSERT INTO temp.merge_offset (raw_upload_id, shift) SELECT upload.id, 1 + max(
+                 coalesce((SELECT max(local_sample_id) FROM main.coverage_sample WHERE raw_upload_id = upload.id), -1),
+                 coalesce((SELECT max(local_branch_id) FROM main.branches_data WHERE raw_upload_id = upload.id), -1),
+                 coalesce((SELECT max(local_method_id) FROM main.method_data WHERE raw_upload_id = upload.id), -1),
+                 coalesce((SELECT max(local_span_id) FROM main.span_data WHERE raw_upload_id = upload.id), -1)
+             ) FROM {schema}.raw_upload upload;
+             CREATE TEMP TABLE merge_conflict (raw_upload_id INTEGER NOT NULL, incoming_sample_id INTEGER NOT NULL, sample_id INTEGER NOT NULL, incoming_wins INTEGER NOT NULL, PRIMARY KEY (raw_upload_id, incoming_sample_id));"
+        ))?;
+        if policy != MergePolicy::KeepBoth {
+            // A missing timestamp sorts before every real one
+            tx.execute_batch(&format!(
+                "INSERT INTO temp.merge_conflict (raw_upload_id, incoming_sample_id, sample_id, incoming_wins)
+                 SELECT incoming.raw_upload_id, incoming.local_sample_id, min(existing.local_sample_id), coalesce(incoming_upload.timestamp, -9223372036854775808) > coalesce(existing_upload.timestamp, -9223372036854775808)
+                 FROM {schema}.coverage_sample incoming
+                 INNER JOIN main.coverage_sample existing ON existing.raw_upload_id = incoming.raw_upload_id AND existing.source_file_id = incoming.source_file_id AND existing.line_no = incoming.line_no AND existing.coverage_type = incoming.coverage_type AND existing.superseded = 0
+                 INNER JOIN {schema}.raw_upload incoming_upload ON incoming_upload.id = incoming.raw_upload_id
+                 INNER JOIN main.raw_upload existing_upload ON existing_upload.id = existing.raw_upload_id
+                 WHERE incoming.superseded = 0
+                 GROUP BY incoming.raw_upload_id, incoming.local_sample_id"
+            ))?;
+        }
+
+        // The same `source_file` and `context` records may appear in multiple
+        // databases. They use a hash of their "names" as their PK so any
+        // instance of them will come up with the same PK. We can `INSERT OR
+        // IGNORE` to effectively union the tables
+        tx.execute_batch(&format!(
+            "INSERT OR IGNORE INTO source_file SELECT * FROM {schema}.source_file;
+             INSERT OR IGNORE INTO raw_upload SELECT * FROM {schema}.raw_upload;
+             INSERT OR IGNORE INTO context SELECT * FROM {schema}.context;"
+        ))?;
+
+        let hits_aggregate = match policy {
+            MergePolicy::SumHits => "sum",
+            _ => "max",
+        };
+        let combine = |policy: MergePolicy, ours: &str, theirs: &str| {
+            match policy {
+            MergePolicy::SumHits => format!("iif({ours} IS NULL AND {theirs} IS NULL, NULL, coalesce({ours}, 0) + coalesce({theirs}, 0))"),
+            MergePolicy::PreferNewest => format!("iif(merged.incoming_wins, {theirs}, {ours})"),
+            _ => format!("max(coalesce({ours}, {theirs}), coalesce({theirs}, {ours}))"),
+        }
+        };
+        let branch_count_policy = match policy {
+            MergePolicy::SumHits => MergePolicy::MaxHits,
+            _ => policy,
+        };
+        if policy != MergePolicy::KeepBoth {
+            tx.execute_batch(&format!(
+                "UPDATE coverage_sample SET hits = {hits}, hit_branches = {hit_branches}, total_branches = {total_branches}, messages = {messages}
+                 FROM (SELECT conflict.raw_upload_id, conflict.sample_id, {hits_aggregate}(incoming.hits) AS hits, max(incoming.hit_branches) AS hit_branches, max(incoming.total_branches) AS total_branches, max(incoming.messages) AS messages, max(conflict.incoming_wins) AS incoming_wins
+                       FROM temp.merge_conflict conflict INNER JOIN {schema}.coverage_sample incoming ON incoming.raw_upload_id = conflict.raw_upload_id AND incoming.local_sample_id = conflict.incoming_sample_id
+                       GROUP BY conflict.raw_upload_id, conflict.sample_id) AS merged
+                 WHERE coverage_sample.raw_upload_id = merged.raw_upload_id AND coverage_sample.local_sample_id = merged.sample_id",
+                hits = combine(policy, "coverage_sample.hits", "merged.hits"),
+                hit_branches = combine(branch_count_policy, "coverage_sample.hit_branches", "merged.hit_branches"),
+                total_branches = combine(branch_count_policy, "coverage_sample.total_branches", "merged.total_branches"),
+                messages = match policy {
+                    MergePolicy::PreferNewest => combine(policy, "coverage_sample.messages", "merged.messages"),
+                    _ => "coverage_sample.messages".to_string(),
+                },
+            ))?;
+        }
+        let conflict_branch_filter = match policy {
+            MergePolicy::KeepBoth => "0".to_string(),
+            MergePolicy::PreferNewest => {
+                tx.execute_batch(
+                    "DELETE FROM branches_data WHERE EXISTS (SELECT 1 FROM temp.merge_conflict conflict WHERE conflict.incoming_wins AND conflict.raw_upload_id = branches_data.raw_upload_id AND conflict.sample_id = branches_data.local_sample_id);
+                     DELETE FROM method_data WHERE EXISTS (SELECT 1 FROM temp.merge_conflict conflict WHERE conflict.incoming_wins AND conflict.raw_upload_id = method_data.raw_upload_id AND conflict.sample_id = method_data.local_sample_id);",
+                )?;
+                "conflict.incoming_wins".to_string()
+            }
+            _ => {
+                tx.execute_batch(&format!(
+                    "UPDATE branches_data SET hits = {hits}
+                     FROM (SELECT conflict.raw_upload_id, conflict.sample_id, incoming.branch_format, incoming.branch, {hits_aggregate}(incoming.hits) AS hits
+                           FROM temp.merge_conflict conflict INNER JOIN {schema}.branches_data incoming ON incoming.raw_upload_id = conflict.raw_upload_id AND incoming.local_sample_id = conflict.incoming_sample_id
+                           GROUP BY conflict.raw_upload_id, conflict.sample_id, incoming.branch_format, incoming.branch) AS merged
+                     WHERE branches_data.raw_upload_id = merged.raw_upload_id AND branches_data.local_sample_id = merged.sample_id AND branches_data.branch_format = merged.branch_format AND branches_data.branch = merged.branch",
+                    hits = combine(policy, "branches_data.hits", "merged.hits"),
+                ))?;
+                "NOT EXISTS (SELECT 1 FROM branches_data existing WHERE existing.raw_upload_id = conflict.raw_upload_id AND existing.local_sample_id = conflict.sample_id AND existing.branch_format = incoming.branch_format AND existing.branch = incoming.branch)".to_string()
+            }
+        };
+        let conflict_method_filter = match policy {
+            MergePolicy::PreferNewest => "conflict.incoming_wins",
+            _ => "0",
+        };
+
+        // Everything else is keyed by `(raw_upload_id, local_*_id)`, which
+        // `temp.merge_offset` keeps unique, so we can concatenate the tables.
+        // The sample tables' rowids are local to each database, so we leave
+        // them out and let SQLite assign new ones.
+        Self::merge_table::<models::CoverageSample>(
+            &tx,
+            &self.statement_cache,
+            &schema,
+            "conflict.sample_id IS NULL",
+        )?;
+        Self::merge_table::<models::BranchesData>(
+            &tx,
+            &self.statement_cache,
+            &schema,
+            &format!("conflict.sample_id IS NULL OR ({conflict_branch_filter})"),
+        )?;
+        if matches!(policy, MergePolicy::SumHits | MergePolicy::MaxHits) {
+            // Colliding samples with a row for each of their branches are
+            // recounted from the merged branches, since the larger of two
+            // counts undercounts branches each copy hit that the other missed
+            tx.execute_batch(
+                "UPDATE coverage_sample SET hit_branches = merged.hit_branches, total_branches = merged.total_branches
+                 FROM (SELECT branches_data.raw_upload_id, branches_data.local_sample_id, sum(branches_data.hits > 0) AS hit_branches, count(*) AS total_branches
+                       FROM branches_data INNER JOIN (SELECT DISTINCT raw_upload_id, sample_id FROM temp.merge_conflict) conflict ON conflict.raw_upload_id = branches_data.raw_upload_id AND conflict.sample_id = branches_data.local_sample_id
+                       WHERE branches_data.superseded = 0
+                       GROUP BY branches_data.raw_upload_id, branches_data.local_sample_id) AS merged
+                 WHERE coverage_sample.raw_upload_id = merged.raw_upload_id AND coverage_sample.local_sample_id = merged.local_sample_id AND merged.total_branches >= coalesce(coverage_sample.total_branches, 0)",
+            )?;
+        }
+        Self::merge_table::<models::MethodData>(
+            &tx,
+            &self.statement_cache,
+            &schema,
+            &format!("conflict.sample_id IS NULL OR ({conflict_method_filter})"),
+        )?;
+        Self::merge_table::<models::SpanData>(
+            &tx,
+            &self.statement_cache,
+            &schema,
+            "conflict.sample_id IS NULL",
+        )?;
+        Self::merge_table::<models::ContextAssoc>(
+            &tx,
+            &self.statement_cache,
+            &schema,
+            &format!("NOT EXISTS (SELECT 1 FROM {schema}.span_data span INNER JOIN temp.merge_conflict span_conflict ON span_conflict.raw_upload_id = span.raw_upload_id AND span_conflict.incoming_sample_id = span.local_sample_id WHERE span.raw_upload_id = incoming.raw_upload_id AND span.local_span_id = incoming.local_span_id)"),
+        )?;
+
+        // Uploads we already had may now have the same association twice
+        tx.execute_batch(&format!(
+            "DELETE FROM context_assoc WHERE raw_upload_id IN (SELECT raw_upload_id FROM temp.merge_offset WHERE shift > 0) AND rowid NOT IN (SELECT min(rowid) FROM context_assoc WHERE raw_upload_id IN (SELECT raw_upload_id FROM temp.merge_offset WHERE shift > 0) GROUP BY context_id, raw_upload_id, local_sample_id, local_span_id, source_file_id, superseded);
+             INSERT OR IGNORE INTO upload_tag (raw_upload_id, key, value) SELECT raw_upload_id, key, value FROM {schema}.upload_tag;
+             INSERT OR IGNORE INTO session_file_totals SELECT * FROM {schema}.session_file_totals;
+             INSERT INTO line_attribute SELECT * FROM {schema}.line_attribute WHERE true ON CONFLICT DO UPDATE SET attributes = attributes | excluded.attributes;
+             DROP TABLE temp.merge_offset;
+             DROP TABLE temp.merge_conflict;"
+        ))?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Creates a new report at `filename` with only `files` and the data
+    /// recorded for them. Every upload and context is kept, along with any
+    /// associations that aren't tied to a dropped file.
+    pub fn subset(&self, filename: PathBuf, files: &[models::SourceFile]) -> Result<SqliteReport> {
+        let mut subset = SqliteReport::open(filename)?;
+        subset.merge(self, MergePolicy::KeepBoth)?;
+
+        let tx = subset.conn.transaction()?;
+        tx.execute_batch("CREATE TEMP TABLE subset_file (id INTEGER PRIMARY KEY)")?;
+        {
+            let mut stmt = tx.prepare("INSERT OR IGNORE INTO subset_file (id) VALUES (?1)")?;
+            for file in files {
+                stmt.execute([file.id])?;
+            }
+        }
+        // Associations go first because sample-level ones are found through
+        // the samples they're tied to
+        tx.execute_batch(
+            "DELETE FROM context_assoc WHERE source_file_id NOT IN (SELECT id FROM subset_file);
+             DELETE FROM context_assoc WHERE local_sample_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM coverage_sample sample INNER JOIN subset_file ON sample.source_file_id = subset_file.id WHERE sample.raw_upload_id = context_assoc.raw_upload_id AND sample.local_sample_id = context_assoc.local_sample_id);
+             DELETE FROM context_assoc WHERE local_span_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM span_data span INNER JOIN subset_file ON span.source_file_id = subset_file.id WHERE span.raw_upload_id = context_assoc.raw_upload_id AND span.local_span_id = context_assoc.local_span_id);
+             DELETE FROM span_data WHERE source_file_id NOT IN (SELECT id FROM subset_file);
+             DELETE FROM session_file_totals WHERE source_file_id NOT IN (SELECT id FROM subset_file);
+             DELETE FROM line_attribute WHERE source_file_id NOT IN (SELECT id FROM subset_file);
+             DELETE FROM method_data WHERE source_file_id NOT IN (SELECT id FROM subset_file);
+             DELETE FROM branches_data WHERE source_file_id NOT IN (SELECT id FROM subset_file);
+             DELETE FROM coverage_sample WHERE source_file_id NOT IN (SELECT id FROM subset_file);
+             DELETE FROM source_file WHERE id NOT IN (SELECT id FROM subset_file);
+             DROP TABLE subset_file;",
+        )?;
+        tx.commit()?;
+
+        Ok(subset)
+    }
+
+    /// Runs `f` with a read-only connection to the report, for queries the
+    /// [`Report`] trait doesn't cover. The connection is opened separately
+    /// from [`SqliteReport::conn`] with `SQLITE_OPEN_READ_ONLY` and
+    /// `query_only` set, so nothing `f` does can modify the report.
+    pub fn with_connection<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Connection) -> Result<T>,
+    {
+        let conn = Connection::open_with_flags(
+            &self.filename,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        runs::install_run_view(&conn)?;
+        conn.pragma_update(None, "query_only", true)?;
+        f(&conn)
+    }
+}
+
+/// Fails if an upload in the database attached as `schema` has the same
+/// `external_id` as one of ours but a different ID. They're the same upload
+/// ingested twice, and since its data is keyed by the upload's ID, merging it
+/// would leave the incoming copy's data pointing at an upload that isn't
+/// there.
+fn ensure_no_duplicate_uploads(conn: &Connection, schema: &str) -> Result<()> {
+    let duplicate: Option<(String, i64, i64)> = conn
+        .query_row(
+            &format!(
+                "SELECT incoming.external_id, existing.id, incoming.id
+                 FROM {schema}.raw_upload incoming
+                 INNER JOIN main.raw_upload existing ON existing.external_id = incoming.external_id
+                 WHERE existing.id <> incoming.id
+                 LIMIT 1"
+            ),
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    match duplicate {
+        Some((external_id, existing_id, incoming_id)) => {
+            Err(CodecovError::ReportBuilderError(format!(
+                "upload with external ID {external_id:?} is upload {existing_id} in this report but upload {incoming_id} in the one being merged"
+            )))
+        }
+        None => Ok(()),
     }
 }
 
 impl Report for SqliteReport {
     // TODO: implement for real, just using for integration tests
     fn list_files(&self) -> Result<Vec<models::SourceFile>> {
-        let mut stmt = self
-            .conn
-            .prepare_cached("SELECT id, path FROM source_file")?;
+        let mut stmt = self.prepare_cached(
+            "SELECT id, path, language, content_hash, line_count, chunk_index FROM source_file ORDER BY path, id",
+        )?;
         let files = stmt
             .query_map([], |row| row.try_into())?
             .collect::<rusqlite::Result<Vec<models::SourceFile>>>()?;
@@ -40,7 +402,7 @@ impl Report for SqliteReport {
 
     // TODO: implement for real, just using for integration tests
     fn list_contexts(&self) -> Result<Vec<models::Context>> {
-        let mut stmt = self.conn.prepare_cached("SELECT id, name FROM context")?;
+        let mut stmt = self.prepare_cached("SELECT id, name FROM context ORDER BY name, id")?;
         let contexts = stmt
             .query_map([], |row| row.try_into())?
             .collect::<rusqlite::Result<Vec<models::Context>>>()?;
@@ -49,9 +411,7 @@ impl Report for SqliteReport {
 
     // TODO implement for real, just using for integration tests
     fn list_coverage_samples(&self) -> Result<Vec<models::CoverageSample>> {
-        let mut stmt = self
-            .conn
-            .prepare_cached("SELECT raw_upload_id, local_sample_id, source_file_id, line_no, coverage_type, hits, hit_branches, total_branches FROM coverage_sample ORDER BY 2, 3")?;
+        let mut stmt = self.prepare_cached("SELECT raw_upload_id, local_sample_id, source_file_id, line_no, coverage_type, hits, hit_branches, total_branches, messages FROM coverage_sample WHERE superseded = 0 ORDER BY raw_upload_id, local_sample_id")?;
         let samples = stmt
             .query_map([], |row| row.try_into())?
             .collect::<rusqlite::Result<Vec<models::CoverageSample>>>()?;
@@ -62,11 +422,11 @@ impl Report for SqliteReport {
         &self,
         sample: &models::CoverageSample,
     ) -> Result<Vec<models::BranchesData>> {
-        let mut stmt = self
-            .conn
-            .prepare_cached("SELECT branches_data.local_branch_id, branches_data.raw_upload_id, branches_data.source_file_id, branches_data.local_sample_id, branches_data.branch, branches_data.branch_format, branches_data.hits FROM branches_data WHERE branches_data.local_sample_id = ?1")?;
+        let mut stmt = self.prepare_cached("SELECT branches_data.local_branch_id, branches_data.raw_upload_id, branches_data.source_file_id, branches_data.local_sample_id, branches_data.branch, branches_data.branch_format, branches_data.hits FROM branches_data WHERE branches_data.raw_upload_id = ?1 AND branches_data.local_sample_id = ?2 AND branches_data.superseded = 0 ORDER BY branches_data.raw_upload_id, branches_data.local_branch_id")?;
         let branches = stmt
-            .query_map([sample.local_sample_id], |row| row.try_into())?
+            .query_map([sample.raw_upload_id, sample.local_sample_id], |row| {
+                row.try_into()
+            })?
             .collect::<rusqlite::Result<Vec<models::BranchesData>>>()?;
         Ok(branches)
     }
@@ -75,12 +435,12 @@ impl Report for SqliteReport {
         &self,
         sample: &models::CoverageSample,
     ) -> Result<Option<models::MethodData>> {
-        let mut stmt = self
-            .conn
-            .prepare_cached("SELECT method_data.local_method_id, method_data.raw_upload_id, method_data.source_file_id, method_data.local_sample_id, method_data.line_no, method_data.hit_branches, method_data.total_branches, method_data.hit_complexity_paths, method_data.total_complexity FROM method_data WHERE method_data.local_sample_id = ?1")?;
+        let mut stmt = self.prepare_cached("SELECT method_data.local_method_id, method_data.raw_upload_id, method_data.source_file_id, method_data.local_sample_id, method_data.line_no, method_data.hit_branches, method_data.total_branches, method_data.hit_complexity_paths, method_data.total_complexity, method_data.name, method_data.signature FROM method_data WHERE method_data.raw_upload_id = ?1 AND method_data.local_sample_id = ?2 AND method_data.superseded = 0")?;
 
         Ok(stmt
-            .query_row([sample.local_sample_id], |row| row.try_into())
+            .query_row([sample.raw_upload_id, sample.local_sample_id], |row| {
+                row.try_into()
+            })
             .optional()?)
     }
 
@@ -88,11 +448,11 @@ impl Report for SqliteReport {
         &self,
         sample: &models::CoverageSample,
     ) -> Result<Vec<models::SpanData>> {
-        let mut stmt = self
-            .conn
-            .prepare_cached("SELECT span_data.local_span_id, span_data.raw_upload_id, span_data.source_file_id, span_data.local_sample_id, span_data.hits, span_data.start_line, span_data.start_col, span_data.end_line, span_data.end_col FROM span_data WHERE span_data.local_sample_id = ?1")?;
+        let mut stmt = self.prepare_cached("SELECT span_data.local_span_id, span_data.raw_upload_id, span_data.source_file_id, span_data.local_sample_id, span_data.hits, span_data.start_line, span_data.start_col, span_data.end_line, span_data.end_col FROM span_data WHERE span_data.raw_upload_id = ?1 AND span_data.local_sample_id = ?2 AND span_data.superseded = 0 ORDER BY span_data.raw_upload_id, span_data.local_span_id")?;
         let span = stmt
-            .query_map([sample.local_sample_id], |row| row.try_into())?
+            .query_map([sample.raw_upload_id, sample.local_sample_id], |row| {
+                row.try_into()
+            })?
             .collect::<rusqlite::Result<Vec<models::SpanData>>>()?;
         Ok(span)
     }
@@ -102,11 +462,11 @@ impl Report for SqliteReport {
         &self,
         sample: &models::CoverageSample,
     ) -> Result<Vec<models::Context>> {
-        let mut stmt = self
-            .conn
-            .prepare_cached("SELECT context.id, context.name FROM context INNER JOIN context_assoc ON context.id = context_assoc.context_id WHERE context_assoc.local_sample_id = ?1")?;
+        let mut stmt = self.prepare_cached("SELECT context.id, context.name FROM context INNER JOIN context_assoc ON context.id = context_assoc.context_id WHERE context_assoc.raw_upload_id = ?1 AND context_assoc.local_sample_id = ?2 AND context_assoc.superseded = 0 ORDER BY context.name, context.id")?;
         let contexts = stmt
-            .query_map([sample.local_sample_id], |row| row.try_into())?
+            .query_map([sample.raw_upload_id, sample.local_sample_id], |row| {
+                row.try_into()
+            })?
             .collect::<rusqlite::Result<Vec<models::Context>>>()?;
         Ok(contexts)
     }
@@ -116,63 +476,284 @@ impl Report for SqliteReport {
         &self,
         file: &models::SourceFile,
     ) -> Result<Vec<models::CoverageSample>> {
-        let mut stmt = self
-            .conn
-            .prepare_cached("SELECT sample.local_sample_id, sample.raw_upload_id, sample.source_file_id, sample.line_no, sample.coverage_type, sample.hits, sample.hit_branches, sample.total_branches FROM coverage_sample sample INNER JOIN source_file ON sample.source_file_id = source_file.id WHERE source_file_id=?1")?;
+        let mut stmt = self.prepare_cached("SELECT sample.local_sample_id, sample.raw_upload_id, sample.source_file_id, sample.line_no, sample.coverage_type, sample.hits, sample.hit_branches, sample.total_branches, sample.messages FROM coverage_sample sample INNER JOIN source_file ON sample.source_file_id = source_file.id WHERE source_file_id=?1 AND sample.superseded = 0 ORDER BY sample.line_no, sample.raw_upload_id, sample.local_sample_id")?;
         let samples = stmt
             .query_map([file.id], |row| row.try_into())?
             .collect::<rusqlite::Result<Vec<models::CoverageSample>>>()?;
         Ok(samples)
     }
 
+    fn samples_for_line(
+        &self,
+        file: &models::SourceFile,
+        line_no: i64,
+    ) -> Result<Vec<(models::CoverageSample, models::RawUpload)>> {
+        let mut stmt = self.prepare_cached("SELECT sample.local_sample_id, sample.raw_upload_id, sample.source_file_id, sample.line_no, sample.coverage_type, sample.hits, sample.hit_branches, sample.total_branches, sample.messages, upload.id, upload.timestamp, upload.raw_upload_url, upload.flags, upload.provider, upload.build, upload.name, upload.job_name, upload.ci_run_url, upload.state, upload.env, upload.session_type, upload.session_extras, upload.external_id FROM coverage_sample sample INNER JOIN raw_upload upload ON sample.raw_upload_id = upload.id WHERE sample.source_file_id = ?1 AND sample.line_no = ?2 AND sample.superseded = 0 ORDER BY sample.raw_upload_id, sample.local_sample_id")?;
+        let samples = stmt
+            .query_map((file.id, line_no), |row| {
+                Ok((row.try_into()?, row.try_into()?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(samples)
+    }
+
+    fn list_samples_for_context(
+        &self,
+        context: &models::Context,
+    ) -> Result<Vec<models::CoverageSample>> {
+        let mut stmt = self.prepare_cached("SELECT sample.local_sample_id, sample.raw_upload_id, sample.source_file_id, sample.line_no, sample.coverage_type, sample.hits, sample.hit_branches, sample.total_branches, sample.messages FROM coverage_sample sample INNER JOIN context_assoc assoc ON sample.raw_upload_id = assoc.raw_upload_id AND sample.local_sample_id = assoc.local_sample_id WHERE assoc.context_id = ?1 AND sample.superseded = 0 AND assoc.superseded = 0 ORDER BY sample.raw_upload_id, sample.local_sample_id")?;
+        let samples = stmt
+            .query_map([context.id], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::CoverageSample>>>()?;
+        Ok(samples)
+    }
+
+    fn list_files_for_context(&self, context: &models::Context) -> Result<Vec<models::SourceFile>> {
+        let mut stmt = self.prepare_cached("SELECT DISTINCT source_file.id, source_file.path, source_file.language, source_file.content_hash, source_file.line_count, source_file.chunk_index FROM source_file INNER JOIN coverage_sample sample ON sample.source_file_id = source_file.id INNER JOIN context_assoc assoc ON sample.raw_upload_id = assoc.raw_upload_id AND sample.local_sample_id = assoc.local_sample_id WHERE assoc.context_id = ?1 AND sample.superseded = 0 AND assoc.superseded = 0 ORDER BY source_file.path")?;
+        let files = stmt
+            .query_map([context.id], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::SourceFile>>>()?;
+        Ok(files)
+    }
+
+    fn list_contexts_for_file(&self, file: &models::SourceFile) -> Result<Vec<models::Context>> {
+        let mut stmt = self.prepare_cached("SELECT DISTINCT context.id, context.name FROM context INNER JOIN context_assoc assoc ON context.id = assoc.context_id WHERE assoc.source_file_id = ?1 AND assoc.local_sample_id IS NULL AND assoc.local_span_id IS NULL AND assoc.superseded = 0 ORDER BY context.name")?;
+        let contexts = stmt
+            .query_map([file.id], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::Context>>>()?;
+        Ok(contexts)
+    }
+
+    fn list_contexts_for_upload(
+        &self,
+        raw_upload: &models::RawUpload,
+    ) -> Result<Vec<models::Context>> {
+        let mut stmt = self.prepare_cached("SELECT DISTINCT context.id, context.name FROM context INNER JOIN context_assoc assoc ON context.id = assoc.context_id WHERE assoc.raw_upload_id = ?1 AND assoc.source_file_id IS NULL AND assoc.local_sample_id IS NULL AND assoc.local_span_id IS NULL AND assoc.superseded = 0 ORDER BY context.name")?;
+        let contexts = stmt
+            .query_map([raw_upload.id], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::Context>>>()?;
+        Ok(contexts)
+    }
+
+    fn list_contexts_for_lines(&self, lines: &[(&str, i64)]) -> Result<Vec<models::Context>> {
+        let mut stmt = self.prepare_cached(include_str!("queries/list_contexts_for_lines.sql"))?;
+        let contexts = stmt
+            .query_map([serde_json::to_string(lines)?], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::Context>>>()?;
+        Ok(contexts)
+    }
+
+    fn list_methods_for_file(
+        &self,
+        file: &models::SourceFile,
+    ) -> Result<Vec<(models::MethodData, models::CoverageSample)>> {
+        let mut stmt = self.prepare_cached("SELECT method_data.local_method_id, method_data.raw_upload_id, method_data.source_file_id, method_data.local_sample_id, method_data.line_no, method_data.hit_branches, method_data.total_branches, method_data.hit_complexity_paths, method_data.total_complexity, method_data.name, method_data.signature, sample.line_no AS sample_line_no, sample.coverage_type, sample.hits, sample.hit_branches AS sample_hit_branches, sample.total_branches AS sample_total_branches, sample.messages FROM method_data INNER JOIN coverage_sample sample ON method_data.raw_upload_id = sample.raw_upload_id AND method_data.local_sample_id = sample.local_sample_id WHERE method_data.source_file_id = ?1 AND method_data.superseded = 0 ORDER BY sample.line_no, method_data.raw_upload_id, method_data.local_method_id")?;
+        let methods = stmt
+            .query_map([file.id], |row| {
+                let method: models::MethodData = row.try_into()?;
+                let mut sample = models::CoverageSample {
+                    raw_upload_id: method.raw_upload_id,
+                    local_sample_id: method.local_sample_id,
+                    source_file_id: method.source_file_id,
+                    line_no: row.get("sample_line_no")?,
+                    coverage_type: row.get("coverage_type")?,
+                    hits: row.get("hits")?,
+                    hit_branches: row.get("sample_hit_branches")?,
+                    total_branches: row.get("sample_total_branches")?,
+                    messages: None,
+                };
+                if let Some(messages) = row.get("messages")? {
+                    let messages_index = row.as_ref().column_index("messages")?;
+                    sample.messages = Some(json_value_from_sql(messages, messages_index)?);
+                }
+                Ok((method, sample))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(methods)
+    }
+
     fn list_raw_uploads(&self) -> Result<Vec<models::RawUpload>> {
-        let mut stmt = self.conn.prepare_cached("SELECT id, timestamp, raw_upload_url, flags, provider, build, name, job_name, ci_run_url, state, env, session_type, session_extras FROM raw_upload")?;
+        let mut stmt = self.prepare_cached("SELECT id, timestamp, raw_upload_url, flags, provider, build, name, job_name, ci_run_url, state, env, session_type, session_extras, external_id FROM raw_upload ORDER BY id")?;
         let uploads = stmt
             .query_map([], |row| row.try_into())?
             .collect::<rusqlite::Result<Vec<models::RawUpload>>>()?;
         Ok(uploads)
     }
 
+    fn list_raw_uploads_for_url(&self, raw_upload_url: &str) -> Result<Vec<models::RawUpload>> {
+        let mut stmt = self.prepare_cached("SELECT id, timestamp, raw_upload_url, flags, provider, build, name, job_name, ci_run_url, state, env, session_type, session_extras, external_id FROM raw_upload WHERE raw_upload_url = ?1 ORDER BY id")?;
+        let uploads = stmt
+            .query_map([raw_upload_url], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::RawUpload>>>()?;
+        Ok(uploads)
+    }
+
+    fn list_uploads_by_state(&self, state: models::UploadState) -> Result<Vec<models::RawUpload>> {
+        let mut stmt = self.prepare_cached("SELECT id, timestamp, raw_upload_url, flags, provider, build, name, job_name, ci_run_url, state, env, session_type, session_extras, external_id FROM raw_upload WHERE state = ?1 ORDER BY id")?;
+        let uploads = stmt
+            .query_map([state.as_str()], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::RawUpload>>>()?;
+        Ok(uploads)
+    }
+
+    fn list_uploads_in_range(&self, range: Range<i64>) -> Result<Vec<models::RawUpload>> {
+        let mut stmt = self.prepare_cached("SELECT id, timestamp, raw_upload_url, flags, provider, build, name, job_name, ci_run_url, state, env, session_type, session_extras, external_id FROM raw_upload WHERE timestamp >= ?1 AND timestamp < ?2 ORDER BY timestamp, id")?;
+        let uploads = stmt
+            .query_map([range.start, range.end], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::RawUpload>>>()?;
+        Ok(uploads)
+    }
+
+    fn list_tags_for_upload(
+        &self,
+        raw_upload: &models::RawUpload,
+    ) -> Result<Vec<models::UploadTag>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT raw_upload_id, key, value FROM upload_tag WHERE raw_upload_id = ?1 ORDER BY key",
+        )?;
+        let tags = stmt
+            .query_map([raw_upload.id], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::UploadTag>>>()?;
+        Ok(tags)
+    }
+
+    fn list_uploads_by_tag(&self, key: &str, value: &str) -> Result<Vec<models::RawUpload>> {
+        let mut stmt = self.prepare_cached("SELECT raw_upload.id, raw_upload.timestamp, raw_upload.raw_upload_url, raw_upload.flags, raw_upload.provider, raw_upload.build, raw_upload.name, raw_upload.job_name, raw_upload.ci_run_url, raw_upload.state, raw_upload.env, raw_upload.session_type, raw_upload.session_extras, raw_upload.external_id FROM raw_upload INNER JOIN upload_tag tag ON tag.raw_upload_id = raw_upload.id WHERE tag.key = ?1 AND tag.value = ?2 ORDER BY raw_upload.id")?;
+        let uploads = stmt
+            .query_map([key, value], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::RawUpload>>>()?;
+        Ok(uploads)
+    }
+
+    fn list_session_file_totals(
+        &self,
+        file: &models::SourceFile,
+    ) -> Result<Vec<models::SessionFileTotals>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT source_file_id, raw_upload_id, files, lines, hits, misses, partials, coverage, branches, methods, messages, sessions, complexity, complexity_total, diff FROM session_file_totals WHERE source_file_id = ?1 ORDER BY raw_upload_id",
+        )?;
+        let totals = stmt
+            .query_map([file.id], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::SessionFileTotals>>>()?;
+        Ok(totals)
+    }
+
+    fn list_line_attributes(
+        &self,
+        file: &models::SourceFile,
+    ) -> Result<Vec<models::LineAttribute>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT raw_upload_id, source_file_id, line_no, attributes FROM line_attribute WHERE source_file_id = ?1 ORDER BY line_no, raw_upload_id",
+        )?;
+        let attributes = stmt
+            .query_map([file.id], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::LineAttribute>>>()?;
+        Ok(attributes)
+    }
+
+    fn get_file_metadata(&self, path: &str) -> Result<Option<models::SourceFile>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT id, path, language, content_hash, line_count, chunk_index FROM source_file WHERE path = ?1",
+        )?;
+        Ok(stmt.query_row([path], |row| row.try_into()).optional()?)
+    }
+
+    fn file_for_chunk_index(&self, chunk_index: i64) -> Result<Option<models::SourceFile>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT id, path, language, content_hash, line_count, chunk_index FROM source_file WHERE chunk_index = ?1 ORDER BY id LIMIT 1",
+        )?;
+        Ok(stmt
+            .query_row([chunk_index], |row| row.try_into())
+            .optional()?)
+    }
+
+    fn chunk_index_for_file(&self, file: &models::SourceFile) -> Result<Option<i64>> {
+        let mut stmt = self.prepare_cached("SELECT chunk_index FROM source_file WHERE id = ?1")?;
+        Ok(stmt
+            .query_row([file.id], |row| row.get(0))
+            .optional()?
+            .flatten())
+    }
+
+    fn list_out_of_bounds_samples(&self) -> Result<Vec<models::CoverageSample>> {
+        let mut stmt = self.prepare_cached("SELECT sample.local_sample_id, sample.raw_upload_id, sample.source_file_id, sample.line_no, sample.coverage_type, sample.hits, sample.hit_branches, sample.total_branches, sample.messages FROM coverage_sample sample INNER JOIN source_file ON sample.source_file_id = source_file.id WHERE sample.line_no > source_file.line_count AND sample.superseded = 0 ORDER BY sample.raw_upload_id, sample.local_sample_id")?;
+        let samples = stmt
+            .query_map([], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::CoverageSample>>>()?;
+        Ok(samples)
+    }
+
     /// Merge `other` into `self` without modifying `other`.
-    ///
-    /// TODO: Probably put this in a commit
-    fn merge(&mut self, other: &SqliteReport) -> Result<()> {
-        //        let tx = self.conn.transaction()?;
+    fn merge(&mut self, other: &SqliteReport, policy: MergePolicy) -> Result<()> {
         let _ = self
             .conn
             .execute("ATTACH DATABASE ?1 AS other", [other.conn.path()])?;
-
-        let merge_stmts = [
-            // The same `source_file` and `context` records may appear in multiple databases. They
-            // use a hash of their "names" as their PK so any instance of them will
-            // come up with the same PK. We can `INSERT OR IGNORE` to effectively union the tables
-            "INSERT OR IGNORE INTO source_file SELECT * FROM other.source_file",
-            "INSERT OR IGNORE INTO raw_upload SELECT * FROM other.raw_upload",
-            "INSERT OR IGNORE INTO context SELECT * FROM other.context",
-            // For everything else, we use a joint primary key that should be globally unique and
-            // can simply concatenate the tables
-            "INSERT INTO coverage_sample SELECT * FROM other.coverage_sample",
-            "INSERT INTO branches_data SELECT * FROM other.branches_data",
-            "INSERT INTO method_data SELECT * FROM other.method_data",
-            "INSERT INTO span_data SELECT * FROM other.span_data",
-            "INSERT INTO context_assoc SELECT * FROM other.context_assoc",
-        ];
-        for stmt in merge_stmts {
-            let _ = self.conn.prepare_cached(stmt)?.execute([])?;
-        }
-
+        let merged = self.merge_attached("other", policy);
         self.conn.execute_batch("DETACH DATABASE other")?;
-
-        Ok(())
+        merged
     }
 
     fn totals(&self) -> Result<models::ReportTotals> {
-        let mut stmt = self
-            .conn
-            .prepare_cached(include_str!("queries/totals.sql"))?;
+        let mut stmt = self.prepare_cached(include_str!("queries/totals.sql"))?;
 
         Ok(stmt.query_row([], |row| row.try_into())?)
     }
+
+    fn file_totals(&self, file: &models::SourceFile) -> Result<models::CoverageTotals> {
+        let mut stmt = self.prepare_cached(include_str!("queries/file_totals.sql"))?;
+
+        Ok(stmt.query_row([file.id], |row| row.try_into())?)
+    }
+
+    fn list_file_totals(&self) -> Result<Vec<(models::SourceFile, models::CoverageTotals)>> {
+        let mut stmt = self.prepare_cached(include_str!("queries/list_file_totals.sql"))?;
+        let totals = stmt
+            .query_map([], |row| Ok((row.try_into()?, row.try_into()?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(totals)
+    }
+
+    fn list_upload_totals(&self) -> Result<Vec<(models::RawUpload, models::CoverageTotals)>> {
+        let mut stmt = self.prepare_cached(include_str!("queries/list_upload_totals.sql"))?;
+        let totals = stmt
+            .query_map([], |row| Ok((row.try_into()?, row.try_into()?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(totals)
+    }
+
+    fn totals_by_coverage_type(&self) -> Result<models::CoverageTypeTotals> {
+        let mut stmt = self.prepare_cached(include_str!("queries/totals_by_coverage_type.sql"))?;
+        let mut rows = stmt.query([])?;
+
+        let mut totals = models::CoverageTypeTotals::default();
+        while let Some(row) = rows.next()? {
+            let type_totals = match row.get("coverage_type")? {
+                models::CoverageType::Line => &mut totals.line,
+                models::CoverageType::Branch => &mut totals.branch,
+                models::CoverageType::Method => &mut totals.method,
+                models::CoverageType::Statement => &mut totals.statement,
+            };
+            *type_totals = row.try_into()?;
+        }
+        Ok(totals)
+    }
+
+    fn summary(&self) -> Result<ReportSummary> {
+        let mut stmt = self.prepare_cached(include_str!("queries/summary.sql"))?;
+        let mut rows = stmt.query([])?;
+
+        let mut summary = ReportSummary::default();
+        while let Some(row) = rows.next()? {
+            let counts = row.try_into()?;
+            match row.get::<_, Option<String>>("flag")? {
+                Some(flag) => {
+                    summary.flags.insert(flag, counts);
+                }
+                None => summary.totals = counts,
+            }
+        }
+        Ok(summary)
+    }
 }
 
 #[cfg(test)]
@@ -183,7 +764,7 @@ mod tests {
     use tempfile::TempDir;
 
     use super::{super::SqliteReportBuilder, *};
-    use crate::report::ReportBuilder;
+    use crate::report::{summary::SummaryCounts, ReportBuilder};
 
     struct Ctx {
         temp_dir: TempDir,
@@ -204,7 +785,7 @@ mod tests {
         let report = SqliteReport::open(db_file).unwrap();
         assert_eq!(
             super::super::MIGRATIONS.current_version(&report.conn),
-            Ok(SchemaVersion::Inside(NonZeroUsize::new(1).unwrap()))
+            Ok(SchemaVersion::Inside(NonZeroUsize::new(23).unwrap()))
         );
     }
 
@@ -332,25 +913,22 @@ mod tests {
 
         let mut left = left_report_builder.build().unwrap();
         let right = right_report_builder.build().unwrap();
-        left.merge(&right).unwrap();
+        left.merge(&right, MergePolicy::KeepBoth).unwrap();
 
-        // NOTE: the assertions here are sensitive to the sort order:
+        // Files are listed by path, contexts by name and samples by upload
         assert_eq!(
             left.list_files().unwrap(),
             &[file_1.clone(), file_2.clone(), file_3.clone()]
         );
-        assert_eq!(left.list_contexts().unwrap(), &[test_case_2, test_case_1]);
-        assert_eq!(
-            left.list_coverage_samples().unwrap(),
-            &[
-                line_1.clone(),
-                line_4.clone(),
-                line_2.clone(),
-                line_5.clone(),
-                line_3.clone(),
-                line_6.clone()
-            ]
-        );
+        assert_eq!(left.list_contexts().unwrap(), &[test_case_1, test_case_2]);
+        let left_samples = [line_1.clone(), line_2.clone(), line_3.clone()];
+        let right_samples = [line_4.clone(), line_5.clone(), line_6.clone()];
+        let expected_samples = if upload_1.id < upload_2.id {
+            [left_samples, right_samples].concat()
+        } else {
+            [right_samples, left_samples].concat()
+        };
+        assert_eq!(left.list_coverage_samples().unwrap(), expected_samples);
         assert_eq!(left.list_samples_for_file(&file_1).unwrap(), &[line_1]);
         assert_eq!(
             left.list_samples_for_file(&file_2).unwrap(),
@@ -362,87 +940,1614 @@ mod tests {
         );
     }
 
-    #[test]
-    fn test_totals() {
-        let ctx = setup();
-        let db_file = ctx.temp_dir.path().join("db.sqlite");
-        assert!(!db_file.exists());
-        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
-
-        let file_1 = report_builder.insert_file("src/report.rs").unwrap();
-        let file_2 = report_builder.insert_file("src/report/models.rs").unwrap();
-        let upload_1 = report_builder
-            .insert_raw_upload(Default::default())
-            .unwrap();
-        let test_case_1 = report_builder.insert_context("test_totals").unwrap();
-        let line_1 = report_builder
-            .insert_coverage_sample(models::CoverageSample {
-                raw_upload_id: upload_1.id,
-                source_file_id: file_1.id,
-                line_no: 1,
-                coverage_type: models::CoverageType::Line,
-                ..Default::default()
-            })
-            .unwrap();
-        let line_2 = report_builder
-            .insert_coverage_sample(models::CoverageSample {
-                source_file_id: file_2.id,
-                raw_upload_id: upload_1.id,
-                line_no: 1,
-                coverage_type: models::CoverageType::Branch,
-                hit_branches: Some(1),
-                total_branches: Some(2),
+    /// Builds two reports that share an upload, as if a retry of it had been
+    /// ingested separately, and merges the second into the first. Each copy
+    /// has the same two branches on line 2, with the hits in `branch_hits`.
+    fn merge_retried_upload(
+        ctx: &Ctx,
+        name: &str,
+        timestamps: (i64, i64),
+        branch_hits: ([i64; 2], [i64; 2]),
+        policy: MergePolicy,
+    ) -> SqliteReport {
+        let mut left_builder =
+            SqliteReportBuilder::open(ctx.temp_dir.path().join(format!("{name}-left.sqlite")))
+                .unwrap();
+        let file = left_builder.insert_file("src/report.rs").unwrap();
+        let upload = left_builder
+            .insert_raw_upload(models::RawUpload {
+                timestamp: Some(timestamps.0),
                 ..Default::default()
             })
             .unwrap();
-        let line_3 = report_builder
-            .insert_coverage_sample(models::CoverageSample {
-                raw_upload_id: upload_1.id,
-                source_file_id: file_2.id,
-                line_no: 2,
-                coverage_type: models::CoverageType::Method,
-                hits: Some(2),
-                ..Default::default()
-            })
+        let context = left_builder.insert_context("test_retry").unwrap();
+
+        let mut right_builder =
+            SqliteReportBuilder::open(ctx.temp_dir.path().join(format!("{name}-right.sqlite")))
+                .unwrap();
+        let _ = right_builder.insert_file("src/report.rs").unwrap();
+        let _ = right_builder.insert_context("test_retry").unwrap();
+        let _ = right_builder
+            .conn
+            .execute(
+                "INSERT INTO raw_upload (id, timestamp) VALUES (?1, ?2)",
+                [upload.id, timestamps.1],
+            )
             .unwrap();
-        let _ = report_builder.insert_method_data(models::MethodData {
-            raw_upload_id: upload_1.id,
-            source_file_id: file_2.id,
-            local_sample_id: line_3.local_sample_id,
-            line_no: Some(2),
-            hit_complexity_paths: Some(2),
-            total_complexity: Some(4),
-            ..Default::default()
-        });
-        for line in [&line_1, &line_2, &line_3] {
-            let _ = report_builder.associate_context(models::ContextAssoc {
-                raw_upload_id: upload_1.id,
-                context_id: test_case_1.id,
-                local_sample_id: Some(line.local_sample_id),
-                ..Default::default()
-            });
+
+        // Lines 1 and 2 are in both reports, line 3 only on the left and line
+        // 4 only on the right
+        for (builder, line_hits, branch_hits, only_line) in [
+            (&mut left_builder, 1, branch_hits.0, 3),
+            (&mut right_builder, 3, branch_hits.1, 4),
+        ] {
+            let line = builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no: 1,
+                    coverage_type: models::CoverageType::Line,
+                    hits: Some(line_hits),
+                    ..Default::default()
+                })
+                .unwrap();
+            let branch = builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no: 2,
+                    coverage_type: models::CoverageType::Branch,
+                    hit_branches: Some(branch_hits.iter().filter(|hits| **hits > 0).count() as i64),
+                    total_branches: Some(2),
+                    ..Default::default()
+                })
+                .unwrap();
+            for (i, hits) in branch_hits.into_iter().enumerate() {
+                let _ = builder
+                    .insert_branches_data(models::BranchesData {
+                        raw_upload_id: upload.id,
+                        source_file_id: file.id,
+                        local_sample_id: branch.local_sample_id,
+                        hits,
+                        branch_format: models::BranchFormat::Condition,
+                        branch: i.to_string(),
+                        ..Default::default()
+                    })
+                    .unwrap();
+            }
+            let _ = builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no: only_line,
+                    coverage_type: models::CoverageType::Line,
+                    hits: Some(1),
+                    ..Default::default()
+                })
+                .unwrap();
+            let _ = builder
+                .associate_context(models::ContextAssoc {
+                    context_id: context.id,
+                    raw_upload_id: upload.id,
+                    local_sample_id: Some(line.local_sample_id),
+                    ..Default::default()
+                })
+                .unwrap();
         }
 
-        let report = report_builder.build().unwrap();
+        let mut left = left_builder.build().unwrap();
+        let right = right_builder.build().unwrap();
+        left.merge(&right, policy).unwrap();
+        left
+    }
 
-        let expected_totals = models::ReportTotals {
-            files: 2,
-            uploads: 1,
-            test_cases: 1,
-            coverage: models::CoverageTotals {
-                hit_lines: 0,
-                total_lines: 1,
-                hit_branches: 1,
-                total_branches: 2,
-                total_branch_roots: 1,
-                hit_methods: 1,
-                total_methods: 1,
-                hit_complexity_paths: 2,
-                total_complexity: 4,
-            },
+    #[test]
+    fn test_merge_duplicate_external_id() {
+        let ctx = setup();
+        let open = |name: &str| {
+            let mut builder = SqliteReportBuilder::open(ctx.temp_dir.path().join(name)).unwrap();
+            let file = builder.insert_file("src/report.rs").unwrap();
+            let upload = builder
+                .insert_raw_upload(models::RawUpload {
+                    external_id: Some("job-1".to_string()),
+                    ..Default::default()
+                })
+                .unwrap();
+            builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no: 1,
+                    hits: Some(1),
+                    ..Default::default()
+                })
+                .unwrap();
+            builder.build().unwrap()
         };
+        let mut left = open("left.sqlite");
+        let right = open("right.sqlite");
+        let uploads = left.list_raw_uploads().unwrap();
+        let samples = left.list_coverage_samples().unwrap();
+        assert_ne!(uploads, right.list_raw_uploads().unwrap());
 
-        let totals = report.totals().unwrap();
-        assert_eq!(totals, expected_totals);
+        let err = left.merge(&right, MergePolicy::KeepBoth).unwrap_err();
+        assert!(
+            matches!(&err, CodecovError::ReportBuilderError(message) if message.contains("\"job-1\"")),
+            "{err}"
+        );
+        assert_eq!(left.list_raw_uploads().unwrap(), uploads);
+        assert_eq!(left.list_coverage_samples().unwrap(), samples);
+    }
+
+    /// `(line_no, hits, hit_branches)` for a sample.
+    type SampleCoverage = (i64, Option<i64>, Option<i64>);
+
+    /// Returns the coverage of each sample in `report` and the hits of each
+    /// branch on line 2.
+    fn merged_coverage(report: &SqliteReport) -> (Vec<SampleCoverage>, Vec<i64>) {
+        let samples = report
+            .list_coverage_samples_ordered(crate::report::ordering::SampleOrder::Location)
+            .unwrap()
+            .into_iter()
+            .map(|sample| (sample.line_no, sample.hits, sample.hit_branches))
+            .collect();
+        let branches = report
+            .conn
+            .prepare("SELECT branches_data.hits FROM branches_data INNER JOIN coverage_sample sample ON sample.raw_upload_id = branches_data.raw_upload_id AND sample.local_sample_id = branches_data.local_sample_id WHERE sample.line_no = 2 ORDER BY branches_data.branch, branches_data.rowid")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        (samples, branches)
+    }
+
+    #[test]
+    fn test_merge_policies() {
+        let ctx = setup();
+
+        let report = merge_retried_upload(
+            &ctx,
+            "keep",
+            (100, 200),
+            ([1, 0], [1, 1]),
+            MergePolicy::KeepBoth,
+        );
+        let (samples, branches) = merged_coverage(&report);
+        assert_eq!(
+            samples,
+            [
+                (1, Some(1), None),
+                (1, Some(3), None),
+                (2, None, Some(1)),
+                (2, None, Some(2)),
+                (3, Some(1), None),
+                (4, Some(1), None),
+            ]
+        );
+        assert_eq!(branches, [1, 1, 0, 1]);
+
+        let report = merge_retried_upload(
+            &ctx,
+            "sum",
+            (100, 200),
+            ([1, 0], [1, 1]),
+            MergePolicy::SumHits,
+        );
+        let (samples, branches) = merged_coverage(&report);
+        assert_eq!(
+            samples,
+            [
+                (1, Some(4), None),
+                (2, None, Some(2)),
+                (3, Some(1), None),
+                (4, Some(1), None),
+            ]
+        );
+        assert_eq!(branches, [2, 1]);
+
+        let report = merge_retried_upload(
+            &ctx,
+            "max",
+            (100, 200),
+            ([1, 0], [1, 1]),
+            MergePolicy::MaxHits,
+        );
+        let (samples, branches) = merged_coverage(&report);
+        assert_eq!(
+            samples,
+            [
+                (1, Some(3), None),
+                (2, None, Some(2)),
+                (3, Some(1), None),
+                (4, Some(1), None),
+            ]
+        );
+        assert_eq!(branches, [1, 1]);
+
+        let report = merge_retried_upload(
+            &ctx,
+            "newer",
+            (100, 200),
+            ([1, 0], [1, 1]),
+            MergePolicy::PreferNewest,
+        );
+        let (samples, branches) = merged_coverage(&report);
+        assert_eq!(
+            samples,
+            [
+                (1, Some(3), None),
+                (2, None, Some(2)),
+                (3, Some(1), None),
+                (4, Some(1), None),
+            ]
+        );
+        assert_eq!(branches, [1, 1]);
+
+        let report = merge_retried_upload(
+            &ctx,
+            "older",
+            (200, 100),
+            ([1, 0], [1, 1]),
+            MergePolicy::PreferNewest,
+        );
+        let (samples, branches) = merged_coverage(&report);
+        assert_eq!(
+            samples,
+            [
+                (1, Some(1), None),
+                (2, None, Some(1)),
+                (3, Some(1), None),
+                (4, Some(1), None),
+            ]
+        );
+        assert_eq!(branches, [1, 0]);
+
+        // Each copy hit the branch the other missed, so together they hit both
+        for (name, policy, expected_branches) in [
+            ("sum-split", MergePolicy::SumHits, [1, 1]),
+            ("max-split", MergePolicy::MaxHits, [1, 1]),
+        ] {
+            let report = merge_retried_upload(&ctx, name, (100, 200), ([1, 0], [0, 1]), policy);
+            let (samples, branches) = merged_coverage(&report);
+            assert_eq!(samples[1], (2, None, Some(2)));
+            assert_eq!(branches, expected_branches);
+        }
+
+        // The line both copies associated with the context is only associated
+        // once
+        let contexts = report.list_contexts().unwrap();
+        let line_1 = report
+            .list_coverage_samples()
+            .unwrap()
+            .into_iter()
+            .find(|sample| sample.line_no == 1)
+            .unwrap();
+        assert_eq!(report.list_contexts_for_sample(&line_1).unwrap(), contexts);
+        let assoc_count: i64 = report
+            .conn
+            .query_row("SELECT count(*) FROM context_assoc", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(assoc_count, 1);
+    }
+
+    #[test]
+    fn test_samples_for_line() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+        let other_file = report_builder.insert_file("src/lib.rs").unwrap();
+        let mut uploads: Vec<_> = ["unit", "integration"]
+            .into_iter()
+            .map(|name| {
+                report_builder
+                    .insert_raw_upload(models::RawUpload {
+                        name: Some(name.to_string()),
+                        timestamp: Some(1704827412),
+                        ..Default::default()
+                    })
+                    .unwrap()
+            })
+            .collect();
+        uploads.sort_by_key(|upload| upload.id);
+
+        let mut expected = vec![];
+        for upload in &uploads {
+            for (sample_file, line_no) in [(&file, 1), (&file, 2), (&other_file, 1)] {
+                let sample = report_builder
+                    .insert_coverage_sample(models::CoverageSample {
+                        raw_upload_id: upload.id,
+                        source_file_id: sample_file.id,
+                        line_no,
+                        hits: Some(1),
+                        ..Default::default()
+                    })
+                    .unwrap();
+                if sample_file.id == file.id && line_no == 1 {
+                    expected.push((sample, upload.clone()));
+                }
+            }
+        }
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(report.samples_for_line(&file, 1).unwrap(), expected);
+        assert!(report.samples_for_line(&file, 3).unwrap().is_empty());
+
+        let plan: String = report
+            .conn
+            .query_row(
+                "EXPLAIN QUERY PLAN SELECT * FROM coverage_sample WHERE source_file_id = 1 AND line_no = 1",
+                [],
+                |row| row.get(3),
+            )
+            .unwrap();
+        assert!(plan.contains("coverage_sample_source_file_line"), "{plan}");
+    }
+
+    #[test]
+    fn test_list_samples_for_context() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+        let upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        let test_a = report_builder.insert_context("test_a").unwrap();
+        let test_b = report_builder.insert_context("test_b").unwrap();
+
+        let mut samples: Vec<_> = (1..=3)
+            .map(|line_no| models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no,
+                hits: Some(1),
+                ..Default::default()
+            })
+            .collect();
+        report_builder
+            .multi_insert_coverage_sample(samples.iter_mut().collect())
+            .unwrap();
+
+        let mut assocs: Vec<_> = [
+            (&test_a, &samples[0]),
+            (&test_a, &samples[2]),
+            (&test_b, &samples[1]),
+        ]
+        .into_iter()
+        .map(|(context, sample)| models::ContextAssoc {
+            context_id: context.id,
+            raw_upload_id: upload.id,
+            local_sample_id: Some(sample.local_sample_id),
+            ..Default::default()
+        })
+        .collect();
+        report_builder
+            .multi_associate_context(assocs.iter_mut().collect())
+            .unwrap();
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.list_samples_for_context(&test_a).unwrap(),
+            &[samples[0].clone(), samples[2].clone()]
+        );
+        assert_eq!(
+            report.list_samples_for_context(&test_b).unwrap(),
+            std::slice::from_ref(&samples[1])
+        );
+        let unused = models::Context::new("unused");
+        assert!(report.list_samples_for_context(&unused).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_contexts_for_lines() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file_1 = report_builder.insert_file("src/report.rs").unwrap();
+        let file_2 = report_builder.insert_file("src/report/models.rs").unwrap();
+        let upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        let test_a = report_builder.insert_context("test_a").unwrap();
+        let test_b = report_builder.insert_context("test_b").unwrap();
+        let test_c = report_builder.insert_context("test_c").unwrap();
+
+        let mut samples: Vec<_> = [(&file_1, 1), (&file_1, 2), (&file_2, 1)]
+            .into_iter()
+            .map(|(file, line_no)| models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no,
+                hits: Some(1),
+                ..Default::default()
+            })
+            .collect();
+        report_builder
+            .multi_insert_coverage_sample(samples.iter_mut().collect())
+            .unwrap();
+
+        let mut assocs: Vec<_> = [
+            (&test_b, &samples[0]),
+            (&test_a, &samples[0]),
+            (&test_b, &samples[1]),
+            (&test_c, &samples[2]),
+        ]
+        .into_iter()
+        .map(|(context, sample)| models::ContextAssoc {
+            context_id: context.id,
+            raw_upload_id: upload.id,
+            local_sample_id: Some(sample.local_sample_id),
+            ..Default::default()
+        })
+        .collect();
+        report_builder
+            .multi_associate_context(assocs.iter_mut().collect())
+            .unwrap();
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report
+                .list_contexts_for_lines(&[("src/report.rs", 1), ("src/report.rs", 2)])
+                .unwrap(),
+            [test_a.clone(), test_b.clone()]
+        );
+        assert_eq!(
+            report
+                .list_contexts_for_lines(&[("src/report/models.rs", 1), ("src/report.rs", 3)])
+                .unwrap(),
+            [test_c]
+        );
+        assert!(report
+            .list_contexts_for_lines(&[("src/missing.rs", 1)])
+            .unwrap()
+            .is_empty());
+        assert!(report.list_contexts_for_lines(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_files_for_context() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file_1 = report_builder.insert_file("src/report.rs").unwrap();
+        let file_2 = report_builder.insert_file("src/report/models.rs").unwrap();
+        let test_a = report_builder.insert_context("test_a").unwrap();
+        let test_b = report_builder.insert_context("test_b").unwrap();
+
+        // Both uploads use `local_sample_id` 0 for different files, and only
+        // `test_a` is associated with the first upload's sample
+        let mut samples = vec![];
+        for (file, context) in [(&file_1, &test_a), (&file_2, &test_b)] {
+            let upload = report_builder
+                .insert_raw_upload(Default::default())
+                .unwrap();
+            let sample = report_builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no: 1,
+                    hits: Some(1),
+                    ..Default::default()
+                })
+                .unwrap();
+            report_builder
+                .associate_context(models::ContextAssoc {
+                    context_id: context.id,
+                    raw_upload_id: upload.id,
+                    local_sample_id: Some(sample.local_sample_id),
+                    ..Default::default()
+                })
+                .unwrap();
+            samples.push(sample);
+        }
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.list_files_for_context(&test_a).unwrap(),
+            std::slice::from_ref(&file_1)
+        );
+        assert_eq!(
+            report.list_files_for_context(&test_b).unwrap(),
+            std::slice::from_ref(&file_2)
+        );
+        assert_eq!(
+            report.list_contexts_for_sample(&samples[0]).unwrap(),
+            &[test_a]
+        );
+        assert_eq!(
+            report.list_contexts_for_sample(&samples[1]).unwrap(),
+            &[test_b]
+        );
+    }
+
+    #[test]
+    fn test_list_contexts_for_file_and_upload() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file_1 = report_builder.insert_file("src/report.rs").unwrap();
+        let file_2 = report_builder.insert_file("src/report/models.rs").unwrap();
+        let upload_1 = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        let upload_2 = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        let component = report_builder.insert_context("component:report").unwrap();
+        let unit = report_builder.insert_context("flag:unit").unwrap();
+        let test_a = report_builder.insert_context("test_a").unwrap();
+
+        let sample = report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload_1.id,
+                source_file_id: file_1.id,
+                line_no: 1,
+                hits: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        for assoc in [
+            // Both uploads put `file_1` in the same component
+            models::ContextAssoc {
+                context_id: component.id,
+                raw_upload_id: upload_1.id,
+                source_file_id: Some(file_1.id),
+                ..Default::default()
+            },
+            models::ContextAssoc {
+                context_id: component.id,
+                raw_upload_id: upload_2.id,
+                source_file_id: Some(file_1.id),
+                ..Default::default()
+            },
+            models::ContextAssoc {
+                context_id: unit.id,
+                raw_upload_id: upload_1.id,
+                ..Default::default()
+            },
+            models::ContextAssoc {
+                context_id: test_a.id,
+                raw_upload_id: upload_1.id,
+                local_sample_id: Some(sample.local_sample_id),
+                source_file_id: Some(file_1.id),
+                ..Default::default()
+            },
+        ] {
+            report_builder.associate_context(assoc).unwrap();
+        }
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.list_contexts_for_file(&file_1).unwrap(),
+            std::slice::from_ref(&component)
+        );
+        assert!(report.list_contexts_for_file(&file_2).unwrap().is_empty());
+        assert_eq!(
+            report.list_contexts_for_upload(&upload_1).unwrap(),
+            std::slice::from_ref(&unit)
+        );
+        assert!(report
+            .list_contexts_for_upload(&upload_2)
+            .unwrap()
+            .is_empty());
+        assert_eq!(report.list_contexts_for_sample(&sample).unwrap(), &[test_a]);
+    }
+
+    #[test]
+    fn test_raw_upload_url() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let upload_1 = report_builder
+            .insert_raw_upload(models::RawUpload {
+                raw_upload_url: Some("v4/raw/a.txt".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        let upload_2 = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        report_builder
+            .update_raw_upload_url(upload_2.id, Some("v4/raw/b.txt"))
+            .unwrap();
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.list_raw_uploads_for_url("v4/raw/a.txt").unwrap(),
+            &[upload_1]
+        );
+        let expected = models::RawUpload {
+            raw_upload_url: Some("v4/raw/b.txt".to_string()),
+            ..upload_2
+        };
+        assert_eq!(
+            report.list_raw_uploads_for_url("v4/raw/b.txt").unwrap(),
+            &[expected]
+        );
+        assert!(report
+            .list_raw_uploads_for_url("v4/raw/c.txt")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_upload_tags() {
+        let ctx = setup();
+        let mut report_builder =
+            SqliteReportBuilder::open(ctx.temp_dir.path().join("db.sqlite")).unwrap();
+        let mut uploads = vec![];
+        for python in ["3.11", "3.12", "3.12"] {
+            let upload = report_builder
+                .insert_raw_upload(Default::default())
+                .unwrap();
+            for (key, value) in [("python", python), ("os", "linux")] {
+                report_builder
+                    .insert_upload_tag(models::UploadTag {
+                        raw_upload_id: upload.id,
+                        key: key.to_string(),
+                        value: value.to_string(),
+                    })
+                    .unwrap();
+            }
+            uploads.push(upload);
+        }
+
+        // An upload has one value per key
+        assert!(report_builder
+            .insert_upload_tag(models::UploadTag {
+                raw_upload_id: uploads[0].id,
+                key: "python".to_string(),
+                value: "3.13".to_string(),
+            })
+            .is_err());
+
+        let report = report_builder.build().unwrap();
+        let keys: Vec<_> = report
+            .list_tags_for_upload(&uploads[0])
+            .unwrap()
+            .into_iter()
+            .map(|tag| (tag.key, tag.value))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                ("os".to_string(), "linux".to_string()),
+                ("python".to_string(), "3.11".to_string())
+            ]
+        );
+
+        let mut expected_ids = vec![uploads[1].id, uploads[2].id];
+        expected_ids.sort();
+        let tagged_ids: Vec<_> = report
+            .list_uploads_by_tag("python", "3.12")
+            .unwrap()
+            .into_iter()
+            .map(|upload| upload.id)
+            .collect();
+        assert_eq!(tagged_ids, expected_ids);
+        assert!(report
+            .list_uploads_by_tag("python", "2.7")
+            .unwrap()
+            .is_empty());
+
+        // Tags are carried over when merging
+        let mut merged = SqliteReport::open(ctx.temp_dir.path().join("merged.sqlite")).unwrap();
+        merged.merge(&report, MergePolicy::KeepBoth).unwrap();
+        assert_eq!(merged.list_uploads_by_tag("os", "linux").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_session_file_totals() {
+        let ctx = setup();
+        let mut report_builder =
+            SqliteReportBuilder::open(ctx.temp_dir.path().join("db.sqlite")).unwrap();
+        let file_1 = report_builder.insert_file("src/a.rs").unwrap();
+        let file_2 = report_builder.insert_file("src/b.rs").unwrap();
+        let mut uploads: Vec<_> = (0..2)
+            .map(|_| {
+                report_builder
+                    .insert_raw_upload(Default::default())
+                    .unwrap()
+            })
+            .collect();
+        uploads.sort_by_key(|upload| upload.id);
+
+        let totals = |file: &models::SourceFile, upload: &models::RawUpload, hits| {
+            models::SessionFileTotals {
+                source_file_id: file.id,
+                raw_upload_id: upload.id,
+                lines: 4,
+                hits,
+                misses: 4 - hits,
+                coverage: Some(format!("{}", hits * 25)),
+                ..Default::default()
+            }
+        };
+        let inserted = [
+            totals(&file_1, &uploads[1], 1),
+            totals(&file_1, &uploads[0], 4),
+            totals(&file_2, &uploads[0], 0),
+        ];
+        report_builder
+            .multi_insert_session_file_totals(&inserted)
+            .unwrap();
+
+        // A file has one set of totals per upload
+        assert!(report_builder
+            .multi_insert_session_file_totals(&[totals(&file_2, &uploads[0], 2)])
+            .is_err());
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.list_session_file_totals(&file_1).unwrap(),
+            [inserted[1].clone(), inserted[0].clone()]
+        );
+        assert_eq!(
+            report.list_session_file_totals(&file_2).unwrap(),
+            [inserted[2].clone()]
+        );
+
+        // Totals are carried over when merging and dropped with their file
+        let mut merged = SqliteReport::open(ctx.temp_dir.path().join("merged.sqlite")).unwrap();
+        merged.merge(&report, MergePolicy::KeepBoth).unwrap();
+        assert_eq!(merged.list_session_file_totals(&file_1).unwrap().len(), 2);
+        let subset = report
+            .subset(
+                ctx.temp_dir.path().join("subset.sqlite"),
+                std::slice::from_ref(&file_2),
+            )
+            .unwrap();
+        assert!(subset.list_session_file_totals(&file_1).unwrap().is_empty());
+        assert_eq!(subset.list_session_file_totals(&file_2).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_line_attributes() {
+        let ctx = setup();
+        let mut report_builder =
+            SqliteReportBuilder::open(ctx.temp_dir.path().join("db.sqlite")).unwrap();
+        let file = report_builder.insert_file("src/a.rs").unwrap();
+        let upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        for (line_no, hits) in [(1, 1), (2, 0), (3, 0)] {
+            report_builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    coverage_type: models::CoverageType::Line,
+                    hits: Some(hits),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let attribute = |line_no, attributes| models::LineAttribute {
+            raw_upload_id: upload.id,
+            source_file_id: file.id,
+            line_no,
+            attributes,
+        };
+        report_builder
+            .multi_insert_line_attributes(&[
+                attribute(2, models::LineAttributes::EXCLUDED),
+                attribute(3, models::LineAttributes::default()),
+            ])
+            .unwrap();
+        // Attributes for the same line add up
+        report_builder
+            .multi_insert_line_attributes(&[attribute(2, models::LineAttributes::GENERATED)])
+            .unwrap();
+
+        let report = report_builder.build().unwrap();
+        let excluded_and_generated =
+            models::LineAttributes::EXCLUDED | models::LineAttributes::GENERATED;
+        assert_eq!(
+            report.list_line_attributes(&file).unwrap(),
+            [
+                attribute(2, excluded_and_generated),
+                attribute(3, models::LineAttributes::default()),
+            ]
+        );
+
+        // Line 2 is left out of every total, but line 3 has no flags set
+        let expected = models::CoverageTotals {
+            hit_lines: 1,
+            total_lines: 2,
+            ..Default::default()
+        };
+        assert_eq!(report.totals().unwrap().coverage, expected);
+        assert_eq!(report.file_totals(&file).unwrap(), expected);
+        assert_eq!(
+            report.list_file_totals().unwrap(),
+            [(file.clone(), expected.clone())]
+        );
+        assert_eq!(report.totals_by_coverage_type().unwrap().line, expected);
+        assert_eq!(report.summary().unwrap().totals.lines, 2);
+        let view_totals: (i64, i64) = report
+            .with_connection(|conn| {
+                Ok(conn.query_row(
+                    "SELECT (SELECT total_lines FROM v_file_totals), (SELECT total_lines FROM v_upload_totals)",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?)
+            })
+            .unwrap();
+        assert_eq!(view_totals, (2, 2));
+
+        // Attributes are carried over when merging
+        let mut merged = SqliteReport::open(ctx.temp_dir.path().join("merged.sqlite")).unwrap();
+        merged.merge(&report, MergePolicy::KeepBoth).unwrap();
+        assert_eq!(
+            merged.list_line_attributes(&file).unwrap(),
+            report.list_line_attributes(&file).unwrap()
+        );
+        assert_eq!(merged.totals().unwrap().coverage, expected);
+
+        // A new generation of the upload records its own attributes
+        let mut report_builder =
+            SqliteReportBuilder::open(ctx.temp_dir.path().join("db.sqlite")).unwrap();
+        report_builder.supersede_upload(upload.id).unwrap();
+        let report = report_builder.build().unwrap();
+        assert!(report.list_line_attributes(&file).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_upload_state() {
+        use models::UploadState::*;
+
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let upload_1 = report_builder
+            .insert_raw_upload(models::RawUpload {
+                state: Some(Received.as_str().to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        // Uploads parsed from a pyreport have states we don't know about
+        let upload_2 = report_builder
+            .insert_raw_upload(models::RawUpload {
+                state: Some("uploaded".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(upload_1.upload_state(), Some(Received));
+        assert_eq!(upload_2.upload_state(), None);
+
+        report_builder
+            .update_upload_state(upload_1.id, Processing)
+            .unwrap();
+        report_builder
+            .update_upload_state(upload_2.id, Processing)
+            .unwrap();
+        report_builder
+            .update_upload_state(upload_2.id, Error)
+            .unwrap();
+        report_builder
+            .update_upload_state(upload_2.id, Processing)
+            .unwrap();
+        report_builder
+            .update_upload_state(upload_2.id, Processed)
+            .unwrap();
+
+        let err = report_builder
+            .update_upload_state(upload_2.id, Processing)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "report builder error: 'upload {} can't go from processed to processing'",
+                upload_2.id
+            )
+        );
+        assert!(report_builder
+            .update_upload_state(1234, Processing)
+            .is_err());
+
+        let report = report_builder.build().unwrap();
+        let processing = report.list_uploads_by_state(Processing).unwrap();
+        assert_eq!(processing.len(), 1);
+        assert_eq!(processing[0].id, upload_1.id);
+        assert_eq!(processing[0].upload_state(), Some(Processing));
+        let processed = report.list_uploads_by_state(Processed).unwrap();
+        assert_eq!(processed.len(), 1);
+        assert_eq!(processed[0].id, upload_2.id);
+        assert!(report.list_uploads_by_state(Received).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_uploads_in_range() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let mut uploads = Vec::new();
+        for timestamp in [Some(300), Some(100), None, Some(200)] {
+            uploads.push(
+                report_builder
+                    .insert_raw_upload(models::RawUpload {
+                        timestamp,
+                        ..Default::default()
+                    })
+                    .unwrap(),
+            );
+        }
+
+        let report = report_builder.build().unwrap();
+        let ids = |range| -> Vec<i64> {
+            report
+                .list_uploads_in_range(range)
+                .unwrap()
+                .into_iter()
+                .map(|upload| upload.id)
+                .collect()
+        };
+        // Ordered by timestamp, and the end of the range is excluded
+        assert_eq!(ids(100..300), vec![uploads[1].id, uploads[3].id]);
+        assert_eq!(
+            ids(i64::MIN..i64::MAX),
+            vec![uploads[1].id, uploads[3].id, uploads[0].id]
+        );
+        assert!(ids(301..400).is_empty());
+    }
+
+    #[test]
+    fn test_file_metadata() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+        report_builder.insert_file("src/report/models.rs").unwrap();
+
+        report_builder
+            .update_file_metadata(&models::SourceFile {
+                language: Some("rust".to_string()),
+                content_hash: Some("abc123".to_string()),
+                ..file.clone()
+            })
+            .unwrap();
+        // `None`s don't clear metadata we already have
+        report_builder
+            .update_file_metadata(&models::SourceFile {
+                line_count: Some(120),
+                ..file.clone()
+            })
+            .unwrap();
+
+        let report = report_builder.build().unwrap();
+        let expected = models::SourceFile {
+            language: Some("rust".to_string()),
+            content_hash: Some("abc123".to_string()),
+            line_count: Some(120),
+            ..file
+        };
+        assert_eq!(
+            report.get_file_metadata("src/report.rs").unwrap(),
+            Some(expected)
+        );
+        assert_eq!(
+            report.get_file_metadata("src/report/models.rs").unwrap(),
+            Some(models::SourceFile::new("src/report/models.rs"))
+        );
+        assert_eq!(report.get_file_metadata("src/lib.rs").unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_out_of_bounds_samples() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file_1 = report_builder.insert_file("src/report.rs").unwrap();
+        let file_2 = report_builder.insert_file("src/report/models.rs").unwrap();
+        report_builder
+            .update_file_metadata(&models::SourceFile {
+                line_count: Some(10),
+                ..file_1.clone()
+            })
+            .unwrap();
+        let raw_upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+
+        let mut samples = vec![];
+        for (file, line_no) in [(&file_1, 10), (&file_1, 11), (&file_2, 500)] {
+            samples.push(
+                report_builder
+                    .insert_coverage_sample(models::CoverageSample {
+                        raw_upload_id: raw_upload.id,
+                        source_file_id: file.id,
+                        line_no,
+                        coverage_type: models::CoverageType::Line,
+                        hits: Some(1),
+                        ..Default::default()
+                    })
+                    .unwrap(),
+            );
+        }
+
+        let report = report_builder.build().unwrap();
+        // `file_2` has no known line count so its sample isn't flagged
+        assert_eq!(
+            report.list_out_of_bounds_samples().unwrap(),
+            std::slice::from_ref(&samples[1])
+        );
+    }
+
+    #[test]
+    fn test_list_methods_for_file() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file_1 = report_builder.insert_file("src/report.rs").unwrap();
+        let file_2 = report_builder.insert_file("src/report/models.rs").unwrap();
+        let raw_upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+
+        let mut expected = vec![];
+        for (file, line_no, name) in [
+            (&file_1, 8, "open"),
+            (&file_1, 3, "new"),
+            (&file_2, 3, "build"),
+        ] {
+            let sample = report_builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    coverage_type: models::CoverageType::Method,
+                    hits: Some(line_no),
+                    ..Default::default()
+                })
+                .unwrap();
+            let method = report_builder
+                .insert_method_data(models::MethodData {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    local_sample_id: sample.local_sample_id,
+                    line_no: Some(line_no),
+                    name: Some(name.to_string()),
+                    signature: Some(format!("fn {name}()")),
+                    ..Default::default()
+                })
+                .unwrap();
+            expected.push((method, sample));
+        }
+
+        let report = report_builder.build().unwrap();
+        // Ordered by line
+        assert_eq!(
+            report.list_methods_for_file(&file_1).unwrap(),
+            &[expected[1].clone(), expected[0].clone()]
+        );
+        assert_eq!(
+            report.list_methods_for_file(&file_2).unwrap(),
+            std::slice::from_ref(&expected[2])
+        );
+    }
+
+    #[test]
+    fn test_totals() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        assert!(!db_file.exists());
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        let file_1 = report_builder.insert_file("src/report.rs").unwrap();
+        let file_2 = report_builder.insert_file("src/report/models.rs").unwrap();
+        let upload_1 = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        let test_case_1 = report_builder.insert_context("test_totals").unwrap();
+        let line_1 = report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload_1.id,
+                source_file_id: file_1.id,
+                line_no: 1,
+                coverage_type: models::CoverageType::Line,
+                ..Default::default()
+            })
+            .unwrap();
+        let line_2 = report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                source_file_id: file_2.id,
+                raw_upload_id: upload_1.id,
+                line_no: 1,
+                coverage_type: models::CoverageType::Branch,
+                hit_branches: Some(1),
+                total_branches: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        let line_3 = report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload_1.id,
+                source_file_id: file_2.id,
+                line_no: 2,
+                coverage_type: models::CoverageType::Method,
+                hits: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        let _ = report_builder.insert_method_data(models::MethodData {
+            raw_upload_id: upload_1.id,
+            source_file_id: file_2.id,
+            local_sample_id: line_3.local_sample_id,
+            line_no: Some(2),
+            hit_complexity_paths: Some(2),
+            total_complexity: Some(4),
+            ..Default::default()
+        });
+        for line in [&line_1, &line_2, &line_3] {
+            let _ = report_builder.associate_context(models::ContextAssoc {
+                raw_upload_id: upload_1.id,
+                context_id: test_case_1.id,
+                local_sample_id: Some(line.local_sample_id),
+                ..Default::default()
+            });
+        }
+
+        let report = report_builder.build().unwrap();
+
+        let expected_totals = models::ReportTotals {
+            files: 2,
+            uploads: 1,
+            test_cases: 1,
+            coverage: models::CoverageTotals {
+                hit_lines: 0,
+                total_lines: 1,
+                hit_branches: 1,
+                total_branches: 2,
+                total_branch_roots: 1,
+                hit_methods: 1,
+                total_methods: 1,
+                hit_complexity_paths: 2,
+                total_complexity: 4,
+            },
+        };
+
+        let totals = report.totals().unwrap();
+        assert_eq!(totals, expected_totals);
+
+        let totals_by_type = report.totals_by_coverage_type().unwrap();
+        assert_eq!(
+            totals_by_type,
+            models::CoverageTypeTotals {
+                line: models::CoverageTotals {
+                    total_lines: 1,
+                    ..Default::default()
+                },
+                branch: models::CoverageTotals {
+                    hit_branches: 1,
+                    total_branches: 2,
+                    total_branch_roots: 1,
+                    ..Default::default()
+                },
+                method: models::CoverageTotals {
+                    hit_methods: 1,
+                    total_methods: 1,
+                    hit_complexity_paths: 2,
+                    total_complexity: 4,
+                    ..Default::default()
+                },
+                statement: Default::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_file_totals() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        let file_1 = report_builder.insert_file("src/report.rs").unwrap();
+        let file_2 = report_builder.insert_file("src/report/models.rs").unwrap();
+        let empty_file = report_builder.insert_file("src/lib.rs").unwrap();
+        let upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+
+        let sample =
+            |line_no, coverage_type, hits, hit_branches, total_branches| models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file_1.id,
+                line_no,
+                coverage_type,
+                hits,
+                hit_branches,
+                total_branches,
+                ..Default::default()
+            };
+        let method_line = report_builder
+            .insert_coverage_sample(sample(1, models::CoverageType::Method, Some(1), None, None))
+            .unwrap();
+        // A method whose declaration is also a branch
+        let branch_line = report_builder
+            .insert_coverage_sample(sample(
+                5,
+                models::CoverageType::Branch,
+                None,
+                Some(1),
+                Some(2),
+            ))
+            .unwrap();
+        report_builder
+            .insert_coverage_sample(sample(2, models::CoverageType::Line, Some(0), None, None))
+            .unwrap();
+        report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                source_file_id: file_2.id,
+                ..sample(1, models::CoverageType::Line, Some(3), None, None)
+            })
+            .unwrap();
+        for (line, hit_complexity_paths, total_complexity) in
+            [(&method_line, 2, 3), (&branch_line, 1, 4)]
+        {
+            report_builder
+                .insert_method_data(models::MethodData {
+                    raw_upload_id: upload.id,
+                    source_file_id: file_1.id,
+                    local_sample_id: line.local_sample_id,
+                    hit_complexity_paths: Some(hit_complexity_paths),
+                    total_complexity: Some(total_complexity),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.file_totals(&file_1).unwrap(),
+            models::CoverageTotals {
+                hit_lines: 0,
+                total_lines: 1,
+                hit_branches: 1,
+                total_branches: 2,
+                total_branch_roots: 1,
+                hit_methods: 1,
+                total_methods: 1,
+                hit_complexity_paths: 3,
+                total_complexity: 7,
+            }
+        );
+        assert_eq!(
+            report.file_totals(&file_2).unwrap(),
+            models::CoverageTotals {
+                hit_lines: 1,
+                total_lines: 1,
+                hit_branches: 0,
+                total_branches: 0,
+                total_branch_roots: 0,
+                hit_methods: 0,
+                total_methods: 0,
+                hit_complexity_paths: 0,
+                total_complexity: 0,
+            }
+        );
+        assert_eq!(report.file_totals(&empty_file).unwrap().total_lines, 0);
+
+        let file_totals = report.list_file_totals().unwrap();
+        let paths: Vec<_> = file_totals.iter().map(|(file, _)| &file.path).collect();
+        assert_eq!(
+            paths,
+            &["src/lib.rs", "src/report.rs", "src/report/models.rs"]
+        );
+        for (file, totals) in file_totals {
+            assert_eq!(totals, report.file_totals(&file).unwrap());
+        }
+
+        // Report totals include the complexity of the method on the branch line
+        let totals = report.totals().unwrap().coverage;
+        assert_eq!(
+            (totals.hit_complexity_paths, totals.total_complexity),
+            (3, 7)
+        );
+    }
+
+    #[test]
+    fn test_list_upload_totals() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+        let mut uploads: Vec<_> = (0..3)
+            .map(|_| {
+                report_builder
+                    .insert_raw_upload(Default::default())
+                    .unwrap()
+            })
+            .collect();
+        uploads.sort_by_key(|upload| upload.id);
+        for (upload, line_no, hits) in [
+            (&uploads[0], 1, 1),
+            (&uploads[0], 2, 0),
+            (&uploads[1], 1, 3),
+        ] {
+            report_builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    hits: Some(hits),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let report = report_builder.build().unwrap();
+
+        let lines = |hit_lines, total_lines| models::CoverageTotals {
+            hit_lines,
+            total_lines,
+            ..Default::default()
+        };
+        assert_eq!(
+            report.list_upload_totals().unwrap(),
+            vec![
+                (uploads[0].clone(), lines(1, 2)),
+                (uploads[1].clone(), lines(1, 1)),
+                (uploads[2].clone(), lines(0, 0)),
+            ]
+        );
+
+        // The views behind it can be queried without going through `Report`
+        let from_views: (i64, i64) = report
+            .with_connection(|conn| {
+                Ok(conn.query_row(
+                    "SELECT (SELECT sum(total_lines) FROM v_file_totals), (SELECT sum(hit_lines) FROM v_upload_totals)",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?)
+            })
+            .unwrap();
+        assert_eq!(from_views, (3, 2));
+    }
+
+    #[test]
+    fn test_statement_totals() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+        let upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        // Two statements on line 1 and a line on line 2
+        for (line_no, coverage_type, hits) in [
+            (1, models::CoverageType::Statement, 1),
+            (1, models::CoverageType::Statement, 0),
+            (2, models::CoverageType::Line, 1),
+        ] {
+            report_builder
+                .insert_coverage_sample(models::CoverageSample {
+                    raw_upload_id: upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    coverage_type,
+                    hits: Some(hits),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let report = report_builder.build().unwrap();
+
+        let statements = report
+            .list_coverage_samples()
+            .unwrap()
+            .into_iter()
+            .filter(|sample| sample.coverage_type == models::CoverageType::Statement)
+            .count();
+        assert_eq!(statements, 2);
+
+        let lines = |hit_lines, total_lines| models::CoverageTotals {
+            hit_lines,
+            total_lines,
+            ..Default::default()
+        };
+        assert_eq!(report.totals().unwrap().coverage, lines(2, 3));
+        assert_eq!(report.file_totals(&file).unwrap(), lines(2, 3));
+        let totals_by_type = report.totals_by_coverage_type().unwrap();
+        assert_eq!(totals_by_type.line, lines(1, 1));
+        assert_eq!(totals_by_type.statement, lines(1, 2));
+
+        let from_views: (i64, i64) = report
+            .with_connection(|conn| {
+                Ok(conn.query_row(
+                    "SELECT (SELECT total_lines FROM v_file_totals), (SELECT hit_lines FROM v_upload_totals)",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?)
+            })
+            .unwrap();
+        assert_eq!(from_views, (3, 2));
+    }
+
+    #[test]
+    fn test_summary() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        let file_1 = report_builder.insert_file("src/report.rs").unwrap();
+        let file_2 = report_builder.insert_file("src/report/models.rs").unwrap();
+        let mut uploads = vec![];
+        for flags in [
+            Some(serde_json::json!(["unit"])),
+            Some(serde_json::json!(["unit", "integration"])),
+            None,
+        ] {
+            uploads.push(
+                report_builder
+                    .insert_raw_upload(models::RawUpload {
+                        flags,
+                        ..Default::default()
+                    })
+                    .unwrap(),
+            );
+        }
+
+        let sample = |upload: &models::RawUpload, file: &models::SourceFile, line_no| {
+            models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no,
+                coverage_type: models::CoverageType::Line,
+                hits: Some(0),
+                ..Default::default()
+            }
+        };
+        // Missed by one upload but hit by another
+        report_builder
+            .insert_coverage_sample(sample(&uploads[0], &file_1, 1))
+            .unwrap();
+        report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                hits: Some(3),
+                ..sample(&uploads[1], &file_1, 1)
+            })
+            .unwrap();
+        // Partially hit
+        report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                coverage_type: models::CoverageType::Branch,
+                hits: None,
+                hit_branches: Some(1),
+                total_branches: Some(2),
+                ..sample(&uploads[0], &file_1, 2)
+            })
+            .unwrap();
+        // Missed by an upload without flags
+        report_builder
+            .insert_coverage_sample(sample(&uploads[2], &file_2, 1))
+            .unwrap();
+
+        let report = report_builder.build().unwrap();
+        let summary = report.summary().unwrap();
+        assert_eq!(
+            summary.totals,
+            SummaryCounts {
+                files: 2,
+                lines: 3,
+                hits: 1,
+                misses: 1,
+                partials: 1,
+                hit_branches: 1,
+                total_branches: 2,
+            }
+        );
+        assert_eq!(
+            summary.flags.keys().collect::<Vec<_>>(),
+            vec!["integration", "unit"]
+        );
+        assert_eq!(
+            summary.flags["unit"],
+            SummaryCounts {
+                files: 1,
+                lines: 2,
+                hits: 1,
+                misses: 0,
+                partials: 1,
+                hit_branches: 1,
+                total_branches: 2,
+            }
+        );
+        assert_eq!(
+            summary.flags["integration"],
+            SummaryCounts {
+                files: 1,
+                lines: 1,
+                hits: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_summary_empty_report() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let report = SqliteReportBuilder::open(db_file).unwrap().build().unwrap();
+
+        assert_eq!(report.summary().unwrap(), Default::default());
+    }
+
+    #[test]
+    fn test_totals_empty_report() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let report = SqliteReportBuilder::open(db_file).unwrap().build().unwrap();
+
+        assert_eq!(report.totals().unwrap(), Default::default());
+    }
+
+    #[test]
+    fn test_open_readonly() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let report =
+            crate::test_utils::sqlite_report::build_sample_report(db_file.clone()).unwrap();
+        let totals = report.totals().unwrap();
+        drop(report);
+        let contents = std::fs::read(&db_file).unwrap();
+
+        let mut report = SqliteReport::open_readonly(db_file.clone()).unwrap();
+        assert_eq!(report.totals().unwrap(), totals);
+        let other = SqliteReport::open(ctx.temp_dir.path().join("other.sqlite")).unwrap();
+        assert!(report.merge(&other, MergePolicy::KeepBoth).is_err());
+        drop(report);
+        assert_eq!(std::fs::read(&db_file).unwrap(), contents);
+
+        let missing = ctx.temp_dir.path().join("missing.sqlite");
+        assert!(SqliteReport::open_readonly(missing.clone()).is_err());
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn test_with_connection() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let report =
+            crate::test_utils::sqlite_report::build_sample_report(db_file.clone()).unwrap();
+        let file_count = report.list_files().unwrap().len() as i64;
+
+        let count: i64 = report
+            .with_connection(|conn| {
+                Ok(conn.query_row("SELECT count(*) FROM source_file", [], |row| row.get(0))?)
+            })
+            .unwrap();
+        assert_eq!(count, file_count);
+
+        // Writes fail, even after turning `query_only` back off
+        let result =
+            report.with_connection(|conn| Ok(conn.execute("DELETE FROM source_file", [])?));
+        assert!(result.is_err());
+        let result = report.with_connection(|conn| {
+            conn.pragma_update(None, "query_only", false)?;
+            Ok(conn.execute("DELETE FROM context_assoc", [])?)
+        });
+        assert!(result.is_err());
+        assert_eq!(report.list_files().unwrap().len() as i64, file_count);
+
+        // The report's own connection can still write
+        report
+            .conn
+            .execute("INSERT INTO context (id, name) VALUES (12345, 'new')", [])
+            .unwrap();
     }
 }
@@ -1,34 +1,102 @@
 use std::{
+    fmt,
     ops::RangeFrom,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 use rand::Rng;
-use rusqlite::{Connection, Transaction};
+use rusqlite::{
+    CachedStatement, Connection, DropBehavior, OptionalExtension, Transaction, TransactionBehavior,
+};
 
-use super::{models::Insertable, open_database, SqliteReport};
+use super::{
+    collapse, delete_raw_upload, instrumentation::Instrumentation, integrity::check_references,
+    is_busy, models::Insertable, open_database, runs, BuilderInstrumentation, BuilderStats,
+    BusyPolicy, IntegrityMode, SqliteReport, StatementCacheStats,
+};
 use crate::{
     error::{CodecovError, Result},
-    report::{models, ReportBuilder},
+    report::{models, DuplicateUploadPolicy, ReportBuilder},
 };
 
-/// Returned by [`SqliteReportBuilder::transaction`]. Contains the actual
-/// implementation for most of the [`ReportBuilder`] trait except for `build()`
-/// which is implemented on [`SqliteReportBuilder`]. All
+/// Returned by [`SqliteReportBuilder::transaction`]. Implements the
+/// [`ReportBuilder`] trait by running each operation in its transaction,
+/// except for `build()` which is implemented on [`SqliteReportBuilder`]. The
+/// transaction is committed when it goes out of scope, or earlier by
+/// [`SqliteReportBuilderTx::commit`]. All
 /// [`SqliteReportBuilderTx`]s created by a [`SqliteReportBuilder`] must
 /// go out of scope before [`SqliteReportBuilder::build()`] can be called
 /// because their `conn` member mutably borrows the SQLite database and prevents
 /// `build()` from moving it into a [`SqliteReport`].
 pub struct SqliteReportBuilderTx<'a> {
     id_sequence: &'a mut RangeFrom<i64>,
+    instrumentation: &'a mut Instrumentation,
+    integrity_mode: IntegrityMode,
+    busy_policy: BusyPolicy,
+    bulk_insert_mode: BulkInsertMode,
 
     pub filename: &'a Path,
     pub conn: Transaction<'a>,
 }
 
 impl SqliteReportBuilderTx<'_> {
+    /// Commits the transaction now instead of when it goes out of scope. A
+    /// commit on drop can only log a failure, while this returns it.
+    pub fn commit(mut self) -> Result<()> {
+        let committed = self.commit_timed();
+        if committed.is_err() && !self.conn.is_autocommit() {
+            // Rather than trying again on drop
+            let _ = self.conn.execute_batch("ROLLBACK");
+            self.instrumentation.record_rollback();
+        }
+        committed
+    }
+
     pub fn rollback(self) -> Result<()> {
-        Ok(self.conn.rollback()?)
+        self.instrumentation.record_rollback();
+        // `Transaction::drop()` is a no-op once the transaction is finished
+        Ok(self.conn.execute_batch("ROLLBACK")?)
+    }
+
+    /// Commits the transaction, waiting for the database per our
+    /// [`BusyPolicy`], and records how long it took.
+    fn commit_timed(&mut self) -> Result<()> {
+        let start = Instant::now();
+        // `Transaction::drop()` is a no-op once the transaction is finished
+        self.busy_policy
+            .retry(|| self.conn.execute_batch("COMMIT"))?;
+        let elapsed = start.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(?elapsed, "commit");
+        self.instrumentation.record_commit(elapsed);
+        Ok(())
+    }
+
+    fn builder_conn(&mut self) -> BuilderConn<'_> {
+        BuilderConn {
+            conn: &self.conn,
+            id_sequence: self.id_sequence,
+            instrumentation: self.instrumentation,
+            integrity_mode: self.integrity_mode,
+            bulk_insert_mode: self.bulk_insert_mode,
+        }
+    }
+}
+
+impl Drop for SqliteReportBuilderTx<'_> {
+    /// Commits the transaction ourselves, instead of leaving it to
+    /// [`Transaction`]'s own `Drop` impl, so that we can time it.
+    fn drop(&mut self) {
+        if self.conn.is_autocommit() || self.conn.drop_behavior() != DropBehavior::Commit {
+            return;
+        }
+        if let Err(_e) = self.commit_timed() {
+            // `Transaction`'s own `Drop` impl rolls it back
+            self.instrumentation.record_rollback();
+            #[cfg(feature = "tracing")]
+            tracing::error!(error = %_e, "failed to commit transaction");
+        }
     }
 }
 
@@ -36,10 +104,22 @@ impl SqliteReportBuilderTx<'_> {
 /// The [`SqliteReportBuilder::transaction`] method returns a
 /// [`SqliteReportBuilderTx`], an auxiliary [`ReportBuilder`] implementation
 /// which will run its operations in a transaction that gets committed when the
-/// [`SqliteReportBuilderTx`] goes out of scope. A non-transaction
-/// [`SqliteReportBuilder`]'s `ReportBuilder` functions (except for `build()`)
-/// call `self.transaction()?` for each call and delegate to the
-/// [`SqliteReportBuilderTx`] implementation.
+/// [`SqliteReportBuilderTx`] goes out of scope.
+///
+/// By default, each of a non-transaction [`SqliteReportBuilder`]'s
+/// `ReportBuilder` functions (except for `build()`) runs in its own
+/// transaction. Committing that often is slow, so calls can be batched into
+/// fewer transactions instead:
+/// - [`SqliteReportBuilder::begin`] opens a batch that every call joins until
+///   [`SqliteReportBuilder::commit`] is called.
+/// - [`SqliteReportBuilder::set_batch_policy`] with [`BatchPolicy::Auto`] opens
+///   batches as needed and commits them once they are big or old enough.
+///
+/// A call that fails in a batch leaves nothing of itself behind, but the calls
+/// before it stay in the batch. `build()` commits an open batch. If the builder
+/// is dropped instead, the batch's changes are rolled back. Calling
+/// `transaction()` while a batch is open is an error, as SQLite transactions
+/// don't nest.
 pub struct SqliteReportBuilder {
     pub filename: PathBuf,
     pub conn: Connection,
@@ -49,99 +129,443 @@ pub struct SqliteReportBuilder {
     /// [`BranchesData`](models::BranchesData),
     /// [`MethodData`](models::MethodData), and [`SpanData`](models::SpanData).
     id_sequence: RangeFrom<i64>,
+
+    instrumentation: Instrumentation,
+    integrity_mode: IntegrityMode,
+    busy_policy: BusyPolicy,
+    bulk_insert_mode: BulkInsertMode,
+
+    batch_policy: BatchPolicy,
+    batch: Option<Batch>,
+}
+
+/// How a [`SqliteReportBuilder`] groups its `ReportBuilder` calls into
+/// transactions. See [`SqliteReportBuilder::set_batch_policy`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum BatchPolicy {
+    /// Calls run in their own transaction unless a batch was opened with
+    /// [`SqliteReportBuilder::begin`].
+    #[default]
+    Manual,
+    /// Calls open a batch if there isn't one, and the batch is committed
+    /// after a call brings it to `max_operations` calls or finishes
+    /// `max_duration` after it was opened. Neither limit applies if `None`.
+    /// Limits are only checked after each call, so an idle batch stays open.
+    Auto {
+        max_operations: Option<usize>,
+        max_duration: Option<Duration>,
+    },
+}
+
+/// How a [`SqliteReportBuilder`] inserts many rows at once, e.g. in
+/// [`ReportBuilder::multi_insert_coverage_sample`]. See
+/// [`SqliteReportBuilder::set_bulk_insert_mode`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum BulkInsertMode {
+    /// Each statement inserts as many rows as fit in SQLite's limit on
+    /// parameters, binding every field of every row separately.
+    #[default]
+    Placeholders,
+    /// Each statement binds a few thousand rows as a single JSON array, which
+    /// SQLite unpacks with `json_each()`. This binds one parameter instead of
+    /// one per field, but serializing and parsing the JSON costs more than
+    /// that saves for the rows we insert today, so compare the two with the
+    /// `insert` benchmark before switching. Rows with values JSON can't hold,
+    /// like non-finite floats, are inserted as with
+    /// [`BulkInsertMode::Placeholders`].
+    JsonEach,
+}
+
+/// A transaction opened by [`SqliteReportBuilder::begin`] and shared by
+/// calls until it's committed.
+#[derive(Debug)]
+struct Batch {
+    opened: Instant,
+    operations: usize,
+    /// Savepoints that haven't been released or rolled back yet.
+    savepoints: usize,
 }
 
 impl SqliteReportBuilder {
+    #[cfg_attr(feature = "tracing", tracing::instrument)]
     pub fn open(filename: PathBuf) -> Result<SqliteReportBuilder> {
-        let conn = open_database(&filename)?;
+        let mut conn = open_database(&filename)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        // The builder writes to `coverage_sample` directly
+        runs::unpack_runs(&mut conn)?;
+        // and every upload's samples have to be its own
+        collapse::expand_collapsed(&mut conn)?;
         Ok(SqliteReportBuilder {
             filename,
             conn,
             id_sequence: 0..,
+            instrumentation: Instrumentation::default(),
+            integrity_mode: IntegrityMode::default(),
+            busy_policy: BusyPolicy::default(),
+            bulk_insert_mode: BulkInsertMode::default(),
+            batch_policy: BatchPolicy::default(),
+            batch: None,
         })
     }
 
+    /// Register hooks to be called as this builder inserts rows and commits
+    /// transactions. Replaces any previously-registered hooks.
+    pub fn set_instrumentation(&mut self, hooks: impl BuilderInstrumentation + Send + 'static) {
+        self.instrumentation.hooks = Some(Box::new(hooks));
+    }
+
+    /// Counters for the work this builder has done so far.
+    pub fn stats(&self) -> &BuilderStats {
+        &self.instrumentation.stats
+    }
+
+    /// Sets how many prepared statements our connection keeps cached. The
+    /// setting carries over to the [`SqliteReport`] that
+    /// [`build()`](ReportBuilder::build) returns.
+    pub fn set_statement_cache_capacity(&self, capacity: usize) {
+        self.conn.set_prepared_statement_cache_capacity(capacity);
+    }
+
+    /// How often our connection's prepared statement cache has been hit and
+    /// missed so far. The counts carry over to the [`SqliteReport`] that
+    /// [`build()`](ReportBuilder::build) returns.
+    pub fn statement_cache_stats(&self) -> StatementCacheStats {
+        self.instrumentation.statement_cache.stats()
+    }
+
+    /// Sets how thoroughly inserted rows' references to other rows are
+    /// checked. The default, [`IntegrityMode::ForeignKeys`], leaves it to
+    /// SQLite. SQLite can't change whether it enforces foreign keys in the
+    /// middle of a transaction, so this is an error while a batch is open.
+    /// Foreign key enforcement carries over to the [`SqliteReport`] that
+    /// [`build()`](ReportBuilder::build) returns.
+    pub fn set_integrity_mode(&mut self, mode: IntegrityMode) -> Result<()> {
+        if self.batch.is_some() {
+            return Err(CodecovError::ReportBuilderError(
+                "called `set_integrity_mode()` with a batch open".to_string(),
+            ));
+        }
+        self.conn
+            .pragma_update(None, "foreign_keys", mode != IntegrityMode::Off)?;
+        self.integrity_mode = mode;
+        Ok(())
+    }
+
+    /// Sets how long to wait for other connections to the same report to
+    /// release their locks, and how many times to retry beginning and
+    /// committing transactions if they don't. The timeout carries over to the
+    /// [`SqliteReport`] that [`build()`](ReportBuilder::build) returns.
+    pub fn set_busy_policy(&mut self, policy: BusyPolicy) -> Result<()> {
+        self.conn.busy_timeout(policy.timeout)?;
+        self.busy_policy = policy;
+        Ok(())
+    }
+
+    /// Sets how `multi_insert_*` calls bind their rows. The default is
+    /// [`BulkInsertMode::Placeholders`].
+    pub fn set_bulk_insert_mode(&mut self, mode: BulkInsertMode) {
+        self.bulk_insert_mode = mode;
+    }
+
     /// Create a [`SqliteReportBuilderTx`] with a [`rusqlite::Transaction`] that
-    /// will automatically commit itself when it goes out of scope.
+    /// will automatically commit itself when it goes out of scope. The
+    /// transaction takes the database's write lock right away, waiting for it
+    /// per our [`BusyPolicy`].
     ///
     /// Each `Transaction` holds a mutable reference to `self.conn` and prevents
     /// `self.build()` from being called.
     pub fn transaction(&mut self) -> Result<SqliteReportBuilderTx<'_>> {
+        if self.batch.is_some() {
+            return Err(CodecovError::ReportBuilderError(
+                "called `transaction()` with a batch open".to_string(),
+            ));
+        }
+        // We already know no batch is open, so the transaction doesn't need
+        // `transaction_with_behavior()`'s mutable borrow to rule out nesting,
+        // which couldn't be retried
+        let conn = &self.conn;
+        let conn = self
+            .busy_policy
+            .retry(|| Transaction::new_unchecked(conn, TransactionBehavior::Immediate))?;
         let mut builder_tx = SqliteReportBuilderTx {
             filename: &self.filename,
-            conn: self.conn.transaction()?,
+            conn,
             id_sequence: &mut self.id_sequence,
+            instrumentation: &mut self.instrumentation,
+            integrity_mode: self.integrity_mode,
+            busy_policy: self.busy_policy,
+            bulk_insert_mode: self.bulk_insert_mode,
         };
-        builder_tx
-            .conn
-            .set_drop_behavior(rusqlite::DropBehavior::Commit);
+        builder_tx.conn.set_drop_behavior(DropBehavior::Commit);
         Ok(builder_tx)
     }
+
+    /// Set how `ReportBuilder` calls are grouped into transactions. Doesn't
+    /// affect a batch that's already open until its next call.
+    pub fn set_batch_policy(&mut self, policy: BatchPolicy) {
+        self.batch_policy = policy;
+    }
+
+    /// Open a batch: a transaction that subsequent `ReportBuilder` calls will
+    /// share until [`SqliteReportBuilder::commit`] is called.
+    pub fn begin(&mut self) -> Result<()> {
+        if self.batch.is_some() {
+            return Err(CodecovError::ReportBuilderError(
+                "called `begin()` with a batch already open".to_string(),
+            ));
+        }
+        self.busy_policy
+            .retry(|| self.conn.execute_batch("BEGIN IMMEDIATE"))?;
+        self.batch = Some(Batch {
+            opened: Instant::now(),
+            operations: 0,
+            savepoints: 0,
+        });
+        Ok(())
+    }
+
+    /// Commit the open batch. If the commit fails because the database is
+    /// busy, the batch stays open and can be committed again later.
+    pub fn commit(&mut self) -> Result<()> {
+        if self.batch.is_none() {
+            return Err(CodecovError::ReportBuilderError(
+                "called `commit()` without a batch open".to_string(),
+            ));
+        }
+        let start = Instant::now();
+        let committed = self.busy_policy.retry(|| self.conn.execute_batch("COMMIT"));
+        if let Err(e) = committed {
+            if !is_busy(&e) {
+                self.batch = None;
+                self.instrumentation.record_rollback();
+            }
+            return Err(e.into());
+        }
+        let _batch = self.batch.take().unwrap();
+        let elapsed = start.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(?elapsed, operations = _batch.operations, "commit batch");
+        self.instrumentation.record_commit(elapsed);
+        Ok(())
+    }
+
+    /// Lets this builder add to the upload `raw_upload_id` after another
+    /// builder, e.g. one that was interrupted partway through a parse, has
+    /// already inserted into it. Local IDs are only unique within an upload
+    /// and each builder starts counting them from 0, so this moves our count
+    /// past the ones already in use.
+    pub fn continue_upload(&mut self, raw_upload_id: i64) -> Result<()> {
+        self.batch_conn().skip_local_ids(raw_upload_id)
+    }
+
+    /// A [`BuilderConn`] that runs operations in the open batch, if any.
+    fn batch_conn(&mut self) -> BuilderConn<'_> {
+        BuilderConn {
+            conn: &self.conn,
+            id_sequence: &mut self.id_sequence,
+            instrumentation: &mut self.instrumentation,
+            integrity_mode: self.integrity_mode,
+            bulk_insert_mode: self.bulk_insert_mode,
+        }
+    }
+
+    /// Run `op` in the open batch, opening one first if the batch policy calls
+    /// for it, or else in its own transaction.
+    fn run<T>(&mut self, op: impl FnOnce(&mut BuilderConn<'_>) -> Result<T>) -> Result<T> {
+        let (max_operations, max_duration) = match self.batch_policy {
+            BatchPolicy::Manual if self.batch.is_none() => {
+                return op(&mut self.transaction()?.builder_conn());
+            }
+            BatchPolicy::Manual => (None, None),
+            BatchPolicy::Auto {
+                max_operations,
+                max_duration,
+            } => (max_operations, max_duration),
+        };
+        if self.batch.is_none() {
+            self.begin()?;
+        }
+
+        // A call that fails partway through, like a `multi_insert_*()` whose
+        // last statement violates a constraint, is undone without losing the
+        // rest of the batch
+        self.conn.execute_batch("SAVEPOINT report_builder_op")?;
+        self.instrumentation.record_savepoint();
+        let result = op(&mut self.batch_conn());
+        match result {
+            Ok(_) => {
+                self.conn.execute_batch("RELEASE report_builder_op")?;
+                self.instrumentation.record_release_savepoint();
+            }
+            Err(_) => {
+                self.conn
+                    .execute_batch("ROLLBACK TO report_builder_op; RELEASE report_builder_op")?;
+                self.instrumentation.record_rollback_to_savepoint();
+            }
+        }
+
+        let batch = self.batch.as_mut().unwrap();
+        batch.operations += 1;
+        let full = max_operations.is_some_and(|max| batch.operations >= max);
+        let expired = max_duration.is_some_and(|max| batch.opened.elapsed() >= max);
+        // Committing would release any savepoints out from under the caller
+        if (full || expired) && batch.savepoints == 0 {
+            self.commit()?;
+        }
+        result
+    }
 }
 
 impl ReportBuilder<SqliteReport> for SqliteReportBuilder {
     fn insert_file(&mut self, path: &str) -> Result<models::SourceFile> {
-        self.transaction()?.insert_file(path)
+        self.run(|b| b.insert_file(path))
+    }
+
+    fn update_file_metadata(&mut self, file: &models::SourceFile) -> Result<()> {
+        self.run(|b| b.update_file_metadata(file))
     }
 
     fn insert_context(&mut self, name: &str) -> Result<models::Context> {
-        self.transaction()?.insert_context(name)
+        self.run(|b| b.insert_context(name))
+    }
+
+    fn multi_insert_context(&mut self, names: &[&str]) -> Result<Vec<models::Context>> {
+        self.run(|b| b.multi_insert_context(names))
     }
 
     fn insert_coverage_sample(
         &mut self,
         sample: models::CoverageSample,
     ) -> Result<models::CoverageSample> {
-        self.transaction()?.insert_coverage_sample(sample)
+        self.run(|b| b.insert_coverage_sample(sample))
     }
 
     fn multi_insert_coverage_sample(
         &mut self,
         samples: Vec<&mut models::CoverageSample>,
     ) -> Result<()> {
-        self.transaction()?.multi_insert_coverage_sample(samples)
+        self.run(|b| b.multi_insert_coverage_sample(samples))
+    }
+
+    fn multi_update_coverage_sample(&mut self, samples: &[models::CoverageSample]) -> Result<()> {
+        self.run(|b| b.multi_update_coverage_sample(samples))
     }
 
     fn insert_branches_data(
         &mut self,
         branch: models::BranchesData,
     ) -> Result<models::BranchesData> {
-        self.transaction()?.insert_branches_data(branch)
+        self.run(|b| b.insert_branches_data(branch))
     }
 
     fn multi_insert_branches_data(
         &mut self,
         branches: Vec<&mut models::BranchesData>,
     ) -> Result<()> {
-        self.transaction()?.multi_insert_branches_data(branches)
+        self.run(|b| b.multi_insert_branches_data(branches))
     }
 
     fn insert_method_data(&mut self, method: models::MethodData) -> Result<models::MethodData> {
-        self.transaction()?.insert_method_data(method)
+        self.run(|b| b.insert_method_data(method))
     }
 
     fn multi_insert_method_data(&mut self, methods: Vec<&mut models::MethodData>) -> Result<()> {
-        self.transaction()?.multi_insert_method_data(methods)
+        self.run(|b| b.multi_insert_method_data(methods))
     }
 
     fn insert_span_data(&mut self, span: models::SpanData) -> Result<models::SpanData> {
-        self.transaction()?.insert_span_data(span)
+        self.run(|b| b.insert_span_data(span))
     }
 
     fn multi_insert_span_data(&mut self, spans: Vec<&mut models::SpanData>) -> Result<()> {
-        self.transaction()?.multi_insert_span_data(spans)
+        self.run(|b| b.multi_insert_span_data(spans))
     }
 
     fn associate_context(&mut self, assoc: models::ContextAssoc) -> Result<models::ContextAssoc> {
-        self.transaction()?.associate_context(assoc)
+        self.run(|b| b.associate_context(assoc))
     }
 
     fn multi_associate_context(&mut self, assocs: Vec<&mut models::ContextAssoc>) -> Result<()> {
-        self.transaction()?.multi_associate_context(assocs)
+        self.run(|b| b.multi_associate_context(assocs))
+    }
+
+    fn insert_upload_tag(&mut self, tag: models::UploadTag) -> Result<models::UploadTag> {
+        self.run(|b| b.insert_upload_tag(tag))
+    }
+
+    fn multi_insert_session_file_totals(
+        &mut self,
+        totals: &[models::SessionFileTotals],
+    ) -> Result<()> {
+        self.run(|b| b.multi_insert_session_file_totals(totals))
+    }
+
+    fn multi_insert_line_attributes(&mut self, attributes: &[models::LineAttribute]) -> Result<()> {
+        self.run(|b| b.multi_insert_line_attributes(attributes))
     }
 
     fn insert_raw_upload(&mut self, raw_upload: models::RawUpload) -> Result<models::RawUpload> {
-        self.transaction()?.insert_raw_upload(raw_upload)
+        self.run(|b| b.insert_raw_upload(raw_upload))
+    }
+
+    fn update_raw_upload_url(
+        &mut self,
+        raw_upload_id: i64,
+        raw_upload_url: Option<&str>,
+    ) -> Result<()> {
+        self.run(|b| b.update_raw_upload_url(raw_upload_id, raw_upload_url))
+    }
+
+    fn update_upload_state(
+        &mut self,
+        raw_upload_id: i64,
+        state: models::UploadState,
+    ) -> Result<()> {
+        self.run(|b| b.update_upload_state(raw_upload_id, state))
+    }
+
+    fn insert_raw_upload_idempotent(
+        &mut self,
+        raw_upload: models::RawUpload,
+        on_duplicate: DuplicateUploadPolicy,
+    ) -> Result<Option<models::RawUpload>> {
+        self.run(|b| b.insert_raw_upload_idempotent(raw_upload, on_duplicate))
+    }
+
+    fn supersede_upload(&mut self, raw_upload_id: i64) -> Result<i64> {
+        self.run(|b| b.supersede_upload(raw_upload_id))
+    }
+
+    /// Savepoints need a batch to live in. With [`BatchPolicy::Auto`] one is
+    /// opened if needed and isn't committed automatically until its
+    /// savepoints are released or rolled back; with [`BatchPolicy::Manual`]
+    /// it's an error to call this without calling `begin()` first.
+    fn savepoint(&mut self) -> Result<()> {
+        if self.batch.is_none() {
+            match self.batch_policy {
+                BatchPolicy::Manual => {
+                    return Err(CodecovError::ReportBuilderError(
+                        "called `savepoint()` without a batch open".to_string(),
+                    ))
+                }
+                BatchPolicy::Auto { .. } => self.begin()?,
+            }
+        }
+        self.batch_conn().savepoint()?;
+        self.batch.as_mut().unwrap().savepoints += 1;
+        Ok(())
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        self.batch_conn().release_savepoint()?;
+        self.batch.as_mut().unwrap().savepoints -= 1;
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        self.batch_conn().rollback_to_savepoint()?;
+        self.batch.as_mut().unwrap().savepoints -= 1;
+        Ok(())
     }
 
     /// Consumes this builder and returns a [`SqliteReport`].
@@ -206,34 +630,287 @@ impl ReportBuilder<SqliteReport> for SqliteReportBuilder {
     /// // Works fine now
     /// let report = report_builder.build().unwrap();
     /// ```
-    fn build(self) -> Result<SqliteReport> {
+    ///
+    /// An open batch is committed first.
+    fn build(mut self) -> Result<SqliteReport> {
+        if self.batch.is_some() {
+            self.commit()?;
+        }
         Ok(SqliteReport {
             filename: self.filename,
             conn: self.conn,
+            statement_cache: self.instrumentation.statement_cache,
         })
     }
 }
 
 impl ReportBuilder<SqliteReport> for SqliteReportBuilderTx<'_> {
+    fn insert_file(&mut self, path: &str) -> Result<models::SourceFile> {
+        self.builder_conn().insert_file(path)
+    }
+
+    fn update_file_metadata(&mut self, file: &models::SourceFile) -> Result<()> {
+        self.builder_conn().update_file_metadata(file)
+    }
+
+    fn insert_context(&mut self, name: &str) -> Result<models::Context> {
+        self.builder_conn().insert_context(name)
+    }
+
+    fn multi_insert_context(&mut self, names: &[&str]) -> Result<Vec<models::Context>> {
+        self.builder_conn().multi_insert_context(names)
+    }
+
+    fn insert_coverage_sample(
+        &mut self,
+        sample: models::CoverageSample,
+    ) -> Result<models::CoverageSample> {
+        self.builder_conn().insert_coverage_sample(sample)
+    }
+
+    fn multi_insert_coverage_sample(
+        &mut self,
+        samples: Vec<&mut models::CoverageSample>,
+    ) -> Result<()> {
+        self.builder_conn().multi_insert_coverage_sample(samples)
+    }
+
+    fn multi_update_coverage_sample(&mut self, samples: &[models::CoverageSample]) -> Result<()> {
+        self.builder_conn().multi_update_coverage_sample(samples)
+    }
+
+    fn insert_branches_data(
+        &mut self,
+        branch: models::BranchesData,
+    ) -> Result<models::BranchesData> {
+        self.builder_conn().insert_branches_data(branch)
+    }
+
+    fn multi_insert_branches_data(
+        &mut self,
+        branches: Vec<&mut models::BranchesData>,
+    ) -> Result<()> {
+        self.builder_conn().multi_insert_branches_data(branches)
+    }
+
+    fn insert_method_data(&mut self, method: models::MethodData) -> Result<models::MethodData> {
+        self.builder_conn().insert_method_data(method)
+    }
+
+    fn multi_insert_method_data(&mut self, methods: Vec<&mut models::MethodData>) -> Result<()> {
+        self.builder_conn().multi_insert_method_data(methods)
+    }
+
+    fn insert_span_data(&mut self, span: models::SpanData) -> Result<models::SpanData> {
+        self.builder_conn().insert_span_data(span)
+    }
+
+    fn multi_insert_span_data(&mut self, spans: Vec<&mut models::SpanData>) -> Result<()> {
+        self.builder_conn().multi_insert_span_data(spans)
+    }
+
+    fn associate_context(&mut self, assoc: models::ContextAssoc) -> Result<models::ContextAssoc> {
+        self.builder_conn().associate_context(assoc)
+    }
+
+    fn multi_associate_context(&mut self, assocs: Vec<&mut models::ContextAssoc>) -> Result<()> {
+        self.builder_conn().multi_associate_context(assocs)
+    }
+
+    fn insert_upload_tag(&mut self, tag: models::UploadTag) -> Result<models::UploadTag> {
+        self.builder_conn().insert_upload_tag(tag)
+    }
+
+    fn multi_insert_session_file_totals(
+        &mut self,
+        totals: &[models::SessionFileTotals],
+    ) -> Result<()> {
+        self.builder_conn().multi_insert_session_file_totals(totals)
+    }
+
+    fn multi_insert_line_attributes(&mut self, attributes: &[models::LineAttribute]) -> Result<()> {
+        self.builder_conn().multi_insert_line_attributes(attributes)
+    }
+
+    fn insert_raw_upload(&mut self, raw_upload: models::RawUpload) -> Result<models::RawUpload> {
+        self.builder_conn().insert_raw_upload(raw_upload)
+    }
+
+    fn update_raw_upload_url(
+        &mut self,
+        raw_upload_id: i64,
+        raw_upload_url: Option<&str>,
+    ) -> Result<()> {
+        self.builder_conn()
+            .update_raw_upload_url(raw_upload_id, raw_upload_url)
+    }
+
+    fn update_upload_state(
+        &mut self,
+        raw_upload_id: i64,
+        state: models::UploadState,
+    ) -> Result<()> {
+        self.builder_conn()
+            .update_upload_state(raw_upload_id, state)
+    }
+
+    fn insert_raw_upload_idempotent(
+        &mut self,
+        raw_upload: models::RawUpload,
+        on_duplicate: DuplicateUploadPolicy,
+    ) -> Result<Option<models::RawUpload>> {
+        self.builder_conn()
+            .insert_raw_upload_idempotent(raw_upload, on_duplicate)
+    }
+
+    fn supersede_upload(&mut self, raw_upload_id: i64) -> Result<i64> {
+        self.builder_conn().supersede_upload(raw_upload_id)
+    }
+
+    fn savepoint(&mut self) -> Result<()> {
+        self.builder_conn().savepoint()
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        self.builder_conn().release_savepoint()
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        self.builder_conn().rollback_to_savepoint()
+    }
+
+    fn build(self) -> Result<SqliteReport> {
+        Err(CodecovError::ReportBuilderError(
+            "called `build()` on a transaction".to_string(),
+        ))
+    }
+}
+
+/// The actual implementation of [`ReportBuilder`] for SQLite. Runs each
+/// operation on `conn` as-is, leaving transactions to whoever created it: a
+/// [`SqliteReportBuilderTx`], or a [`SqliteReportBuilder`] with an open batch.
+struct BuilderConn<'a> {
+    conn: &'a Connection,
+    id_sequence: &'a mut RangeFrom<i64>,
+    instrumentation: &'a mut Instrumentation,
+    integrity_mode: IntegrityMode,
+    bulk_insert_mode: BulkInsertMode,
+}
+
+impl<'a> BuilderConn<'a> {
+    fn prepare_cached(&self, sql: &str) -> rusqlite::Result<CachedStatement<'a>> {
+        self.instrumentation
+            .statement_cache
+            .prepare_cached(self.conn, sql)
+    }
+
+    fn insert<T: Insertable + fmt::Display>(&mut self, model: &T) -> Result<()> {
+        if self.integrity_mode == IntegrityMode::Validate {
+            check_references(
+                std::iter::once(model),
+                self.conn,
+                &self.instrumentation.statement_cache,
+            )?;
+        }
+        let start = Instant::now();
+        let rows = model.insert(self.conn, &self.instrumentation.statement_cache)?;
+        let elapsed = start.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::trace!(table = T::TABLE_NAME, rows, ?elapsed, "insert");
+        self.instrumentation
+            .record_insert(T::TABLE_NAME, rows, elapsed);
+        Ok(())
+    }
+
+    /// Advances `id_sequence` past every local ID already used in the upload
+    /// `raw_upload_id`, whoever inserted them.
+    fn skip_local_ids(&mut self, raw_upload_id: i64) -> Result<()> {
+        let next_id: i64 = self
+            .prepare_cached(
+                "SELECT 1 + max(
+                     coalesce((SELECT max(local_sample_id) FROM coverage_sample WHERE raw_upload_id = ?1), -1),
+                     coalesce((SELECT max(local_branch_id) FROM branches_data WHERE raw_upload_id = ?1), -1),
+                     coalesce((SELECT max(local_method_id) FROM method_data WHERE raw_upload_id = ?1), -1),
+                     coalesce((SELECT max(local_span_id) FROM span_data WHERE raw_upload_id = ?1), -1)
+                 )",
+            )?
+            .query_row([raw_upload_id], |row| row.get(0))?;
+        if next_id > self.id_sequence.start {
+            *self.id_sequence = next_id..;
+        }
+        Ok(())
+    }
+
+    fn multi_insert<'b, T, I>(&mut self, models: I) -> Result<()>
+    where
+        T: Insertable + fmt::Display + 'b,
+        I: Iterator<Item = &'b T> + ExactSizeIterator + Clone,
+    {
+        if self.integrity_mode == IntegrityMode::Validate {
+            check_references(
+                models.clone(),
+                self.conn,
+                &self.instrumentation.statement_cache,
+            )?;
+        }
+        let start = Instant::now();
+        let statement_cache = &self.instrumentation.statement_cache;
+        let rows = match self.bulk_insert_mode {
+            BulkInsertMode::Placeholders => T::multi_insert(models, self.conn, statement_cache)?,
+            BulkInsertMode::JsonEach => T::multi_insert_json(models, self.conn, statement_cache)?,
+        };
+        let elapsed = start.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(table = T::TABLE_NAME, rows, ?elapsed, "multi_insert");
+        self.instrumentation
+            .record_insert(T::TABLE_NAME, rows, elapsed);
+        Ok(())
+    }
+}
+
+impl ReportBuilder<SqliteReport> for BuilderConn<'_> {
     fn insert_file(&mut self, path: &str) -> Result<models::SourceFile> {
         let model = models::SourceFile::new(path);
-        model.insert(&self.conn)?;
+        self.insert(&model)?;
         Ok(model)
     }
 
+    fn update_file_metadata(&mut self, file: &models::SourceFile) -> Result<()> {
+        let mut stmt = self.prepare_cached(
+            "UPDATE source_file SET language = coalesce(?2, language), content_hash = coalesce(?3, content_hash), line_count = coalesce(?4, line_count), chunk_index = coalesce(?5, chunk_index) WHERE id = ?1",
+        )?;
+        stmt.execute((
+            file.id,
+            &file.language,
+            &file.content_hash,
+            file.line_count,
+            file.chunk_index,
+        ))?;
+        Ok(())
+    }
+
     fn insert_context(&mut self, name: &str) -> Result<models::Context> {
         let model = models::Context::new(name);
-        model.insert(&self.conn)?;
+        self.insert(&model)?;
         Ok(model)
     }
 
+    fn multi_insert_context(&mut self, names: &[&str]) -> Result<Vec<models::Context>> {
+        let contexts: Vec<_> = names
+            .iter()
+            .map(|name| models::Context::new(name))
+            .collect();
+        self.multi_insert(contexts.iter())?;
+        Ok(contexts)
+    }
+
     fn insert_coverage_sample(
         &mut self,
         mut sample: models::CoverageSample,
     ) -> Result<models::CoverageSample> {
         // TODO handle error
         sample.local_sample_id = self.id_sequence.next().unwrap();
-        sample.insert(&self.conn)?;
+        self.insert(&sample)?;
         Ok(sample)
     }
 
@@ -244,7 +921,36 @@ impl ReportBuilder<SqliteReport> for SqliteReportBuilderTx<'_> {
         for sample in &mut samples {
             sample.local_sample_id = self.id_sequence.next().unwrap();
         }
-        models::CoverageSample::multi_insert(samples.iter().map(|v| &**v), &self.conn)?;
+        self.multi_insert(samples.iter().map(|v| &**v))?;
+        Ok(())
+    }
+
+    fn multi_update_coverage_sample(&mut self, samples: &[models::CoverageSample]) -> Result<()> {
+        let mut exists = self.prepare_cached(
+            "SELECT 1 FROM coverage_sample WHERE raw_upload_id = ?1 AND local_sample_id = ?2",
+        )?;
+        for sample in samples {
+            if !exists.exists((sample.raw_upload_id, sample.local_sample_id))? {
+                return Err(CodecovError::ReportBuilderError(format!(
+                    "no sample to update for {sample}"
+                )));
+            }
+        }
+
+        let mut stmt = self.prepare_cached(
+            "UPDATE coverage_sample SET coverage_type = ?3, hits = ?4, hit_branches = ?5, total_branches = ?6, messages = ?7 WHERE raw_upload_id = ?1 AND local_sample_id = ?2",
+        )?;
+        for sample in samples {
+            stmt.execute((
+                sample.raw_upload_id,
+                sample.local_sample_id,
+                sample.coverage_type,
+                sample.hits,
+                sample.hit_branches,
+                sample.total_branches,
+                &sample.messages,
+            ))?;
+        }
         Ok(())
     }
 
@@ -254,7 +960,7 @@ impl ReportBuilder<SqliteReport> for SqliteReportBuilderTx<'_> {
     ) -> Result<models::BranchesData> {
         // TODO handle error
         branch.local_branch_id = self.id_sequence.next().unwrap();
-        branch.insert(&self.conn)?;
+        self.insert(&branch)?;
         Ok(branch)
     }
 
@@ -265,14 +971,14 @@ impl ReportBuilder<SqliteReport> for SqliteReportBuilderTx<'_> {
         for branch in &mut branches {
             branch.local_branch_id = self.id_sequence.next().unwrap();
         }
-        models::BranchesData::multi_insert(branches.iter().map(|v| &**v), &self.conn)?;
+        self.multi_insert(branches.iter().map(|v| &**v))?;
         Ok(())
     }
 
     fn insert_method_data(&mut self, mut method: models::MethodData) -> Result<models::MethodData> {
         // TODO handle error
         method.local_method_id = self.id_sequence.next().unwrap();
-        method.insert(&self.conn)?;
+        self.insert(&method)?;
         Ok(method)
     }
 
@@ -283,14 +989,14 @@ impl ReportBuilder<SqliteReport> for SqliteReportBuilderTx<'_> {
         for method in &mut methods {
             method.local_method_id = self.id_sequence.next().unwrap();
         }
-        models::MethodData::multi_insert(methods.iter().map(|v| &**v), &self.conn)?;
+        self.multi_insert(methods.iter().map(|v| &**v))?;
         Ok(())
     }
 
     fn insert_span_data(&mut self, mut span: models::SpanData) -> Result<models::SpanData> {
         // TODO handle error
         span.local_span_id = self.id_sequence.next().unwrap();
-        span.insert(&self.conn)?;
+        self.insert(&span)?;
         Ok(span)
     }
 
@@ -298,29 +1004,168 @@ impl ReportBuilder<SqliteReport> for SqliteReportBuilderTx<'_> {
         for span in &mut spans {
             span.local_span_id = self.id_sequence.next().unwrap();
         }
-        models::SpanData::multi_insert(spans.iter().map(|v| &**v), &self.conn)?;
+        self.multi_insert(spans.iter().map(|v| &**v))?;
         Ok(())
     }
 
     fn associate_context(&mut self, assoc: models::ContextAssoc) -> Result<models::ContextAssoc> {
-        assoc.insert(&self.conn)?;
+        self.insert(&assoc)?;
         Ok(assoc)
     }
 
     fn multi_associate_context(&mut self, assocs: Vec<&mut models::ContextAssoc>) -> Result<()> {
-        models::ContextAssoc::multi_insert(assocs.iter().map(|v| &**v), &self.conn)?;
+        self.multi_insert(assocs.iter().map(|v| &**v))?;
         Ok(())
     }
 
+    fn insert_upload_tag(&mut self, tag: models::UploadTag) -> Result<models::UploadTag> {
+        self.insert(&tag)?;
+        Ok(tag)
+    }
+
+    fn multi_insert_session_file_totals(
+        &mut self,
+        totals: &[models::SessionFileTotals],
+    ) -> Result<()> {
+        self.multi_insert(totals.iter())
+    }
+
+    fn multi_insert_line_attributes(&mut self, attributes: &[models::LineAttribute]) -> Result<()> {
+        self.multi_insert(attributes.iter())
+    }
+
     fn insert_raw_upload(
         &mut self,
         mut raw_upload: models::RawUpload,
     ) -> Result<models::RawUpload> {
         raw_upload.id = rand::thread_rng().gen();
-        raw_upload.insert(&self.conn)?;
+        self.insert(&raw_upload)?;
         Ok(raw_upload)
     }
 
+    fn update_raw_upload_url(
+        &mut self,
+        raw_upload_id: i64,
+        raw_upload_url: Option<&str>,
+    ) -> Result<()> {
+        let mut stmt =
+            self.prepare_cached("UPDATE raw_upload SET raw_upload_url = ?2 WHERE id = ?1")?;
+        stmt.execute((raw_upload_id, raw_upload_url))?;
+        Ok(())
+    }
+
+    fn update_upload_state(
+        &mut self,
+        raw_upload_id: i64,
+        state: models::UploadState,
+    ) -> Result<()> {
+        let current: Option<String> = self
+            .prepare_cached("SELECT state FROM raw_upload WHERE id = ?1")?
+            .query_row([raw_upload_id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| {
+                CodecovError::ReportBuilderError(format!("no upload with ID {raw_upload_id}"))
+            })?;
+        if let Some(current) = current.as_deref().and_then(models::UploadState::parse) {
+            if !current.can_transition_to(state) {
+                return Err(CodecovError::ReportBuilderError(format!(
+                    "upload {raw_upload_id} can't go from {} to {}",
+                    current.as_str(),
+                    state.as_str()
+                )));
+            }
+        }
+        self.prepare_cached("UPDATE raw_upload SET state = ?2 WHERE id = ?1")?
+            .execute((raw_upload_id, state.as_str()))?;
+        Ok(())
+    }
+
+    /// Replacing an upload deletes its old rows in the current transaction, so
+    /// if the new upload's data is inserted in the same transaction, the whole
+    /// re-ingest is atomic.
+    fn insert_raw_upload_idempotent(
+        &mut self,
+        raw_upload: models::RawUpload,
+        on_duplicate: DuplicateUploadPolicy,
+    ) -> Result<Option<models::RawUpload>> {
+        let Some(external_id) = &raw_upload.external_id else {
+            return self.insert_raw_upload(raw_upload).map(Some);
+        };
+        let existing: Option<i64> = self
+            .prepare_cached("SELECT id FROM raw_upload WHERE external_id = ?1")?
+            .query_row([external_id], |row| row.get(0))
+            .optional()?;
+
+        match (existing, on_duplicate) {
+            (None, _) => {}
+            (Some(_), DuplicateUploadPolicy::Skip) => return Ok(None),
+            (Some(existing_id), DuplicateUploadPolicy::Replace) => {
+                delete_raw_upload(
+                    self.conn,
+                    &self.instrumentation.statement_cache,
+                    existing_id,
+                )?;
+            }
+        }
+        self.insert_raw_upload(raw_upload).map(Some)
+    }
+
+    fn supersede_upload(&mut self, raw_upload_id: i64) -> Result<i64> {
+        let generation: i64 = self
+            .prepare_cached(
+                "UPDATE raw_upload SET generation = generation + 1 WHERE id = ?1 RETURNING generation",
+            )?
+            .query_row([raw_upload_id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| {
+                CodecovError::ReportBuilderError(format!("upload {raw_upload_id} doesn't exist"))
+            })?;
+
+        for table in [
+            "coverage_sample",
+            "branches_data",
+            "method_data",
+            "span_data",
+            "context_assoc",
+        ] {
+            self.prepare_cached(&format!(
+                "UPDATE {table} SET superseded = 1 WHERE raw_upload_id = ?1 AND superseded = 0"
+            ))?
+            .execute([raw_upload_id])?;
+        }
+        for table in ["session_file_totals", "line_attribute"] {
+            self.prepare_cached(&format!("DELETE FROM {table} WHERE raw_upload_id = ?1"))?
+                .execute([raw_upload_id])?;
+        }
+
+        // The superseded rows keep their local IDs, so the new generation's
+        // have to start after them even if this builder didn't insert them
+        self.skip_local_ids(raw_upload_id)?;
+        Ok(generation)
+    }
+
+    // SQLite resolves a savepoint name to the most recent savepoint with that
+    // name, so reusing one name gives us a stack
+    fn savepoint(&mut self) -> Result<()> {
+        self.conn.execute_batch("SAVEPOINT report_builder")?;
+        self.instrumentation.record_savepoint();
+        Ok(())
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        self.conn.execute_batch("RELEASE report_builder")?;
+        self.instrumentation.record_release_savepoint();
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        // `ROLLBACK TO` leaves the savepoint on the stack
+        self.conn
+            .execute_batch("ROLLBACK TO report_builder; RELEASE report_builder")?;
+        self.instrumentation.record_rollback_to_savepoint();
+        Ok(())
+    }
+
     fn build(self) -> Result<SqliteReport> {
         Err(CodecovError::ReportBuilderError(
             "called `build()` on a transaction".to_string(),
@@ -358,7 +1203,7 @@ mod tests {
         let report_builder = SqliteReportBuilder::open(db_file).unwrap();
         assert_eq!(
             super::super::MIGRATIONS.current_version(&report_builder.conn),
-            Ok(SchemaVersion::Inside(NonZeroUsize::new(1).unwrap()))
+            Ok(SchemaVersion::Inside(NonZeroUsize::new(23).unwrap()))
         );
     }
 
@@ -396,6 +1241,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_multi_insert_context() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        let names: Vec<_> = (0..1000).map(|i| format!("test_{i}")).collect();
+        let names: Vec<_> = names.iter().map(String::as_str).collect();
+        let contexts = report_builder.multi_insert_context(&names).unwrap();
+        assert_eq!(
+            contexts,
+            names
+                .iter()
+                .map(|name| models::Context::new(name))
+                .collect::<Vec<_>>()
+        );
+
+        let report = report_builder.build().unwrap();
+        let mut actual_contexts = report.list_contexts().unwrap();
+        actual_contexts.sort_by_key(|context| context.id);
+        let mut expected_contexts = contexts;
+        expected_contexts.sort_by_key(|context| context.id);
+        assert_eq!(actual_contexts, expected_contexts);
+    }
+
     #[test]
     fn test_insert_coverage_sample() {
         let ctx = setup();
@@ -416,6 +1286,7 @@ mod tests {
             hits: Some(3),
             hit_branches: Some(2),
             total_branches: Some(4),
+            messages: None,
         };
         let actual_sample = report_builder
             .insert_coverage_sample(expected_sample.clone())
@@ -503,6 +1374,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_update_coverage_sample() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+        let raw_upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+        let mut samples: Vec<models::CoverageSample> = (1..=3)
+            .map(|line_no| {
+                report_builder
+                    .insert_coverage_sample(models::CoverageSample {
+                        source_file_id: file.id,
+                        raw_upload_id: raw_upload.id,
+                        line_no,
+                        hits: Some(0),
+                        ..Default::default()
+                    })
+                    .unwrap()
+            })
+            .collect();
+
+        samples[0].hits = Some(3);
+        report_builder.update_coverage_sample(&samples[0]).unwrap();
+
+        samples[1].coverage_type = models::CoverageType::Branch;
+        samples[1].hits = None;
+        samples[1].hit_branches = Some(1);
+        samples[1].total_branches = Some(2);
+        samples[2].hits = Some(1);
+        samples[2].messages = Some(serde_json::json!(["message"]));
+        report_builder
+            .multi_update_coverage_sample(&samples[1..])
+            .unwrap();
+
+        // Nothing is updated if any sample doesn't exist, and files and lines
+        // can't change
+        let missing = models::CoverageSample {
+            local_sample_id: 10,
+            ..samples[0].clone()
+        };
+        let moved = models::CoverageSample {
+            line_no: 10,
+            hits: Some(5),
+            ..samples[0].clone()
+        };
+        assert!(matches!(
+            report_builder.multi_update_coverage_sample(&[moved, missing]),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(report.list_coverage_samples().unwrap(), samples);
+    }
+
     #[test]
     fn test_insert_branches_data() {
         let ctx = setup();
@@ -668,6 +1596,8 @@ mod tests {
             total_branches: Some(2),
             hit_complexity_paths: Some(1),
             total_complexity: Some(2),
+            name: Some("insert_method_data".to_string()),
+            signature: None,
         };
 
         let actual_method = report_builder
@@ -934,6 +1864,7 @@ mod tests {
             raw_upload_id: raw_upload.id,
             local_sample_id: Some(coverage_sample.local_sample_id),
             local_span_id: Some(span.local_span_id),
+            source_file_id: None,
         };
         let actual_assoc = report_builder
             .associate_context(models::ContextAssoc {
@@ -941,6 +1872,7 @@ mod tests {
                 raw_upload_id: raw_upload.id,
                 local_sample_id: Some(coverage_sample.local_sample_id),
                 local_span_id: Some(span.local_span_id),
+                source_file_id: None,
             })
             .unwrap();
         assert_eq!(actual_assoc, expected_assoc);
@@ -1024,6 +1956,68 @@ mod tests {
         assert_eq!(fetched_uploads, &[inserted_upload]);
     }
 
+    #[test]
+    fn test_insert_raw_upload_idempotent() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file = report_builder.insert_file("src/report.rs").unwrap();
+
+        let upload = |name: &str| models::RawUpload {
+            name: Some(name.to_string()),
+            external_id: Some("upload-1".to_string()),
+            ..Default::default()
+        };
+        let first = report_builder
+            .insert_raw_upload_idempotent(upload("first"), DuplicateUploadPolicy::Skip)
+            .unwrap()
+            .unwrap();
+        report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: first.id,
+                source_file_id: file.id,
+                line_no: 1,
+                hits: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+
+        // A plain insert with the same `external_id` is a constraint violation
+        let error = report_builder
+            .insert_raw_upload(upload("dupe"))
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CodecovError::SqliteConstraintViolation {
+                kind: crate::error::ConstraintKind::Unique,
+                ..
+            }
+        ));
+
+        let skipped = report_builder
+            .insert_raw_upload_idempotent(upload("second"), DuplicateUploadPolicy::Skip)
+            .unwrap();
+        assert_eq!(skipped, None);
+
+        let replaced = report_builder
+            .insert_raw_upload_idempotent(upload("third"), DuplicateUploadPolicy::Replace)
+            .unwrap()
+            .unwrap();
+        let unrelated = report_builder
+            .insert_raw_upload_idempotent(Default::default(), DuplicateUploadPolicy::Skip)
+            .unwrap()
+            .unwrap();
+
+        let report = report_builder.build().unwrap();
+        let mut uploads = report.list_raw_uploads().unwrap();
+        uploads.sort_by_key(|u| u.id);
+        let mut expected = vec![replaced, unrelated];
+        expected.sort_by_key(|u| u.id);
+        assert_eq!(uploads, expected);
+        // The first upload's samples went with it
+        assert!(report.list_coverage_samples().unwrap().is_empty());
+    }
+
     #[test]
     fn test_transaction_drop_behavior() {
         let ctx = setup();
@@ -1034,6 +2028,33 @@ mod tests {
         assert_eq!(tx.conn.drop_behavior(), rusqlite::DropBehavior::Commit);
     }
 
+    #[test]
+    fn test_transaction_commit() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        // Dropping a transaction commits it
+        {
+            let mut tx = report_builder.transaction().unwrap();
+            tx.insert_file("dropped.rs").unwrap();
+        }
+        // Committing explicitly doesn't commit again on drop
+        let mut tx = report_builder.transaction().unwrap();
+        tx.insert_file("committed.rs").unwrap();
+        tx.commit().unwrap();
+        assert_eq!(report_builder.stats().transactions, 2);
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(
+            report.list_files().unwrap(),
+            &[
+                models::SourceFile::new("committed.rs"),
+                models::SourceFile::new("dropped.rs")
+            ]
+        );
+    }
+
     #[test]
     fn test_transaction_cannot_build() {
         let ctx = setup();
@@ -1063,4 +2084,424 @@ mod tests {
         let files = report.list_files().unwrap();
         assert_eq!(files.len(), 0);
     }
+
+    #[test]
+    fn test_begin_commit() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        report_builder.begin().unwrap();
+        assert!(report_builder.begin().is_err());
+        assert!(report_builder.transaction().is_err());
+        report_builder.insert_file("foo.rs").unwrap();
+        report_builder.insert_file("bar.rs").unwrap();
+        assert!(!report_builder.conn.is_autocommit());
+        report_builder.commit().unwrap();
+        assert!(report_builder.conn.is_autocommit());
+        assert!(report_builder.commit().is_err());
+        assert_eq!(report_builder.stats().transactions, 1);
+
+        // `build()` commits an open batch
+        report_builder.begin().unwrap();
+        report_builder.insert_file("baz.rs").unwrap();
+        let report = report_builder.build().unwrap();
+        assert_eq!(report.list_files().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_dropped_batch_is_rolled_back() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+
+        let mut report_builder = SqliteReportBuilder::open(db_file.clone()).unwrap();
+        report_builder.insert_file("foo.rs").unwrap();
+        report_builder.begin().unwrap();
+        report_builder.insert_file("bar.rs").unwrap();
+        drop(report_builder);
+
+        let report = SqliteReport::open(db_file).unwrap();
+        assert_eq!(
+            report.list_files().unwrap(),
+            &[models::SourceFile::new("foo.rs")]
+        );
+    }
+
+    #[test]
+    fn test_auto_batch_policy() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        report_builder.set_batch_policy(BatchPolicy::Auto {
+            max_operations: Some(3),
+            max_duration: None,
+        });
+        for i in 0..7 {
+            report_builder.insert_file(&format!("{i}.rs")).unwrap();
+        }
+        // Two full batches were committed and the seventh call is still open
+        assert_eq!(report_builder.stats().transactions, 2);
+        assert!(!report_builder.conn.is_autocommit());
+
+        report_builder.commit().unwrap();
+        report_builder.set_batch_policy(BatchPolicy::Auto {
+            max_operations: None,
+            max_duration: Some(Duration::ZERO),
+        });
+        report_builder.insert_file("8.rs").unwrap();
+        assert_eq!(report_builder.stats().transactions, 4);
+        assert!(report_builder.conn.is_autocommit());
+
+        let report = report_builder.build().unwrap();
+        assert_eq!(report.list_files().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_failed_call_is_rolled_back_in_batch() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        // One context per statement, so the duplicate fails after the rows
+        // before it were inserted
+        let _ = report_builder
+            .conn
+            .set_limit(rusqlite::limits::Limit::SQLITE_LIMIT_VARIABLE_NUMBER, 2);
+
+        report_builder.set_batch_policy(BatchPolicy::Auto {
+            max_operations: None,
+            max_duration: None,
+        });
+        report_builder.insert_context("kept").unwrap();
+        assert!(report_builder
+            .multi_insert_context(&["a", "b", "kept"])
+            .is_err());
+        // The batch is still open and usable
+        assert!(!report_builder.conn.is_autocommit());
+        report_builder.insert_context("c").unwrap();
+
+        let report = report_builder.build().unwrap();
+        let mut names: Vec<_> = report
+            .list_contexts()
+            .unwrap()
+            .into_iter()
+            .map(|context| context.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, &["c", "kept"]);
+    }
+
+    #[test]
+    fn test_busy_policy() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut first = SqliteReportBuilder::open(db_file.clone()).unwrap();
+        let mut second = SqliteReportBuilder::open(db_file).unwrap();
+        second
+            .set_busy_policy(BusyPolicy {
+                timeout: Duration::from_millis(10),
+                max_retries: 1,
+                backoff: Duration::from_millis(10),
+            })
+            .unwrap();
+
+        // The first builder holds the write lock from the start of its batch
+        first.begin().unwrap();
+        let error = second.insert_file("src/b.rs").unwrap_err();
+        assert!(error.is_busy(), "{error:?}");
+        assert!(second.transaction().is_err_and(|e| e.is_busy()));
+        assert!(second.begin().unwrap_err().is_busy());
+
+        // Retries outlast a batch that's committed in the meantime
+        let _ = first.insert_file("src/a.rs").unwrap();
+        let committer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            first.commit().unwrap();
+        });
+        second
+            .set_busy_policy(BusyPolicy {
+                timeout: Duration::from_millis(1),
+                max_retries: 20,
+                backoff: Duration::from_millis(10),
+            })
+            .unwrap();
+        let _ = second.insert_file("src/b.rs").unwrap();
+        committer.join().unwrap();
+
+        let report = second.build().unwrap();
+        assert_eq!(report.list_files().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        {
+            let mut tx = report_builder.transaction().unwrap();
+            tx.insert_file("kept.rs").unwrap();
+
+            tx.savepoint().unwrap();
+            tx.insert_file("released.rs").unwrap();
+            // Nested savepoints are rolled back independently
+            tx.savepoint().unwrap();
+            tx.insert_file("rolled_back.rs").unwrap();
+            tx.rollback_to_savepoint().unwrap();
+            tx.release_savepoint().unwrap();
+
+            tx.savepoint().unwrap();
+            tx.insert_file("also_rolled_back.rs").unwrap();
+            tx.rollback_to_savepoint().unwrap();
+
+            assert!(tx.release_savepoint().is_err());
+        }
+
+        // The non-transaction builder needs a batch
+        assert!(report_builder.savepoint().is_err());
+        report_builder.set_batch_policy(BatchPolicy::Auto {
+            max_operations: Some(1),
+            max_duration: None,
+        });
+        report_builder.savepoint().unwrap();
+        report_builder.insert_file("batched.rs").unwrap();
+        report_builder
+            .insert_file("batched_rolled_back.rs")
+            .unwrap();
+        // Auto-commit waited for the savepoint
+        assert!(!report_builder.conn.is_autocommit());
+        report_builder.rollback_to_savepoint().unwrap();
+
+        let report = report_builder.build().unwrap();
+        let mut paths: Vec<_> = report
+            .list_files()
+            .unwrap()
+            .into_iter()
+            .map(|f| f.path)
+            .collect();
+        paths.sort();
+        assert_eq!(paths, &["kept.rs", "released.rs"]);
+    }
+
+    #[test]
+    fn test_stats_and_instrumentation() {
+        use std::{
+            sync::{Arc, Mutex},
+            time::Duration,
+        };
+
+        #[derive(Default)]
+        struct Recorder {
+            events: Arc<Mutex<Vec<(&'static str, usize)>>>,
+            commits: Arc<Mutex<usize>>,
+        }
+
+        impl BuilderInstrumentation for Recorder {
+            fn on_insert(&mut self, table: &'static str, rows: usize) {
+                self.events.lock().unwrap().push((table, rows));
+            }
+
+            fn on_commit(&mut self, _duration: Duration) {
+                *self.commits.lock().unwrap() += 1;
+            }
+        }
+
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+
+        let recorder = Recorder::default();
+        let events = recorder.events.clone();
+        let commits = recorder.commits.clone();
+        report_builder.set_instrumentation(recorder);
+
+        let file = report_builder.insert_file("foo.rs").unwrap();
+        let raw_upload = report_builder
+            .insert_raw_upload(Default::default())
+            .unwrap();
+
+        {
+            let mut tx = report_builder.transaction().unwrap();
+            let mut samples: Vec<_> = (0..3)
+                .map(|line_no| models::CoverageSample {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    ..Default::default()
+                })
+                .collect();
+            tx.multi_insert_coverage_sample(samples.iter_mut().collect())
+                .unwrap();
+        }
+
+        // Rolled back transactions are not counted as commits, and their
+        // inserts aren't counted at all
+        let mut tx = report_builder.transaction().unwrap();
+        tx.insert_file("bar.rs").unwrap();
+        tx.rollback().unwrap();
+
+        // Nor are inserts that were rolled back to a savepoint
+        {
+            let mut tx = report_builder.transaction().unwrap();
+            tx.insert_file("baz.rs").unwrap();
+            tx.savepoint().unwrap();
+            tx.insert_file("qux.rs").unwrap();
+            tx.rollback_to_savepoint().unwrap();
+        }
+
+        // Inserts are only reported once their transaction is committed
+        report_builder.begin().unwrap();
+        report_builder.insert_file("quux.rs").unwrap();
+        assert_eq!(events.lock().unwrap().len(), 4);
+        report_builder.commit().unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            &[
+                ("source_file", 1),
+                ("raw_upload", 1),
+                ("coverage_sample", 3),
+                ("source_file", 1),
+                ("source_file", 1)
+            ]
+        );
+        assert_eq!(*commits.lock().unwrap(), 5);
+
+        let stats = report_builder.stats();
+        assert_eq!(stats.transactions, 5);
+        assert_eq!(
+            stats.rows_inserted,
+            [
+                ("coverage_sample", 3),
+                ("raw_upload", 1),
+                ("source_file", 3)
+            ]
+            .into()
+        );
+        assert!(stats.sqlite_time > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_statement_cache_stats() {
+        let ctx = setup();
+        let db_file = ctx.temp_dir.path().join("db.sqlite");
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        assert_eq!(report_builder.statement_cache_stats(), Default::default());
+
+        for path in ["a.rs", "b.rs", "c.rs"] {
+            let _ = report_builder.insert_file(path).unwrap();
+        }
+        let builder_stats = report_builder.statement_cache_stats();
+        assert_eq!(builder_stats.misses, 1);
+        assert_eq!(builder_stats.hits, 2);
+
+        // The counts carry over to the report
+        let report = report_builder.build().unwrap();
+        assert_eq!(report.statement_cache_stats(), builder_stats);
+        for _ in 0..2 {
+            let _ = report.list_files().unwrap();
+        }
+        assert_eq!(
+            report.statement_cache_stats(),
+            StatementCacheStats { hits: 3, misses: 2 }
+        );
+
+        // Without a cache, every statement has to be prepared again
+        report.set_statement_cache_capacity(0);
+        for _ in 0..2 {
+            let _ = report.list_files().unwrap();
+        }
+        assert_eq!(
+            report.statement_cache_stats(),
+            StatementCacheStats { hits: 3, misses: 4 }
+        );
+    }
+
+    #[test]
+    fn test_json_each_bulk_insert_mode() {
+        let ctx = setup();
+
+        let build = |mode: BulkInsertMode| {
+            let db_file = ctx.temp_dir.path().join(format!("{mode:?}.sqlite"));
+            let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+            report_builder.set_bulk_insert_mode(mode);
+
+            // More than fit in one statement either way
+            let names: Vec<_> = (0..10000).map(|i| format!("test_{i}")).collect();
+            let names: Vec<_> = names.iter().map(String::as_str).collect();
+            report_builder.multi_insert_context(&names).unwrap();
+
+            let file = report_builder.insert_file("src/report.rs").unwrap();
+            let raw_upload = report_builder
+                .insert_raw_upload(Default::default())
+                .unwrap();
+            let mut samples: Vec<_> = (0..100)
+                .map(|line_no| models::CoverageSample {
+                    raw_upload_id: raw_upload.id,
+                    source_file_id: file.id,
+                    line_no,
+                    coverage_type: models::CoverageType::Branch,
+                    hits: (line_no % 3 != 0).then_some(line_no),
+                    hit_branches: Some(1),
+                    total_branches: Some(2),
+                    messages: (line_no == 7).then(|| json!({"text": "it's \"quoted\""})),
+                    ..Default::default()
+                })
+                .collect();
+            report_builder
+                .multi_insert_coverage_sample(samples.iter_mut().collect())
+                .unwrap();
+
+            // Upserts merge attributes for the same line
+            let attributes: Vec<_> = [
+                models::LineAttributes::EXCLUDED,
+                models::LineAttributes::GENERATED,
+            ]
+            .into_iter()
+            .map(|attributes| models::LineAttribute {
+                raw_upload_id: raw_upload.id,
+                source_file_id: file.id,
+                line_no: 1,
+                attributes,
+            })
+            .collect();
+            report_builder
+                .multi_insert_line_attributes(&attributes)
+                .unwrap();
+
+            report_builder.build().unwrap()
+        };
+
+        // Raw upload IDs are random, so they're left out of the comparison
+        let contents = |report: SqliteReport| {
+            let file = &report.list_files().unwrap()[0];
+            let samples: Vec<_> = report
+                .list_coverage_samples()
+                .unwrap()
+                .into_iter()
+                .map(|sample| models::CoverageSample {
+                    raw_upload_id: 0,
+                    ..sample
+                })
+                .collect();
+            let attributes: Vec<_> = report
+                .list_line_attributes(file)
+                .unwrap()
+                .into_iter()
+                .map(|attribute| attribute.attributes)
+                .collect();
+            (report.list_contexts().unwrap(), samples, attributes)
+        };
+
+        let expected = contents(build(BulkInsertMode::Placeholders));
+        let actual = contents(build(BulkInsertMode::JsonEach));
+        assert_eq!(actual, expected);
+        assert_eq!(actual.0.len(), 10000);
+        assert_eq!(
+            actual.2,
+            [models::LineAttributes(
+                models::LineAttributes::EXCLUDED.0 | models::LineAttributes::GENERATED.0
+            )]
+        );
+    }
 }
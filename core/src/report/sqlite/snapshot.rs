@@ -0,0 +1,234 @@
+//! Consistent copies of a report that can be read while the original keeps
+//! being written to.
+
+use std::path::PathBuf;
+
+use rand::Rng;
+use rusqlite::{backup::Backup, Connection};
+
+use super::{
+    backup_step, delete_raw_upload, runs::install_run_view, SqliteReport, StatementCounters,
+};
+use crate::error::Result;
+
+/// Where [`SqliteReport::snapshot`] puts its copy.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SnapshotTarget {
+    /// A database file at this path. Anything already there is overwritten.
+    File(PathBuf),
+
+    /// A database that only exists in memory, until the returned report is
+    /// dropped.
+    Memory,
+}
+
+impl SqliteReport {
+    /// Copies the report as of its last committed transaction into `target`
+    /// with SQLite's backup API and opens the copy read-only.
+    ///
+    /// The whole copy is made in one step under a read lock, so another
+    /// connection, even in another process, can't commit anything partway
+    /// through it, and the copy never includes anything that connection
+    /// hasn't committed yet. Once it's made, the copy doesn't see later
+    /// writes to the original. If another connection holds a lock that keeps
+    /// the copy from starting for too long, this fails with an error whose
+    /// [`CodecovError::is_busy`](crate::error::CodecovError::is_busy) is true.
+    ///
+    /// A [`SnapshotTarget::Memory`] copy is a named in-memory database, so
+    /// things that open their own connections to
+    /// [`SqliteReport::filename`], like [`SqliteReport::with_connection`],
+    /// work the same as for a file.
+    pub fn snapshot(&self, target: SnapshotTarget) -> Result<SqliteReport> {
+        let copy = self.copy_to(target)?;
+        install_run_view(&copy.conn)?;
+        copy.conn.pragma_update(None, "query_only", true)?;
+        Ok(copy)
+    }
+
+    /// Like [`SqliteReport::snapshot`], but the copy only has the uploads
+    /// whose IDs are in `raw_upload_ids`, along with their samples and
+    /// everything attached to them. IDs that aren't in the report are
+    /// ignored.
+    ///
+    /// Files that none of the remaining uploads have coverage for are left
+    /// out as well. Contexts are all kept.
+    pub fn snapshot_uploads(
+        &self,
+        target: SnapshotTarget,
+        raw_upload_ids: &[i64],
+    ) -> Result<SqliteReport> {
+        let copy = self.copy_to(target)?;
+        copy.retain_uploads(raw_upload_ids)?;
+        install_run_view(&copy.conn)?;
+        copy.conn.pragma_update(None, "query_only", true)?;
+        Ok(copy)
+    }
+
+    /// An in-memory snapshot to export the report from, with collapsed
+    /// uploads expanded and, if `raw_upload_ids` is set, only those uploads.
+    /// `prepare` can make further changes to the copy before it's made
+    /// read-only.
+    #[cfg(feature = "pyreport")]
+    pub(crate) fn export_snapshot(
+        &self,
+        raw_upload_ids: Option<&[i64]>,
+        prepare: impl FnOnce(&Connection) -> Result<()>,
+    ) -> Result<SqliteReport> {
+        let mut copy = self.copy_to(SnapshotTarget::Memory)?;
+        if let Some(raw_upload_ids) = raw_upload_ids {
+            copy.retain_uploads(raw_upload_ids)?;
+        }
+        super::collapse::expand_collapsed(&mut copy.conn)?;
+        prepare(&copy.conn)?;
+        install_run_view(&copy.conn)?;
+        copy.conn.pragma_update(None, "query_only", true)?;
+        Ok(copy)
+    }
+
+    /// Deletes every upload whose ID isn't in `raw_upload_ids`, then every
+    /// file nothing refers to anymore.
+    fn retain_uploads(&self, raw_upload_ids: &[i64]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let unselected = tx
+            .prepare("SELECT id FROM raw_upload")?
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|id| !raw_upload_ids.contains(id));
+        for id in unselected {
+            delete_raw_upload(&tx, &self.statement_cache, id)?;
+        }
+        tx.execute_batch(
+            "DELETE FROM source_file WHERE
+               id NOT IN (SELECT source_file_id FROM coverage_sample)
+               AND id NOT IN (SELECT source_file_id FROM coverage_run)
+               AND id NOT IN (SELECT source_file_id FROM session_file_totals)
+               AND id NOT IN (SELECT source_file_id FROM line_attribute)
+               AND id NOT IN (
+                 SELECT source_file_id FROM context_assoc
+                 WHERE source_file_id IS NOT NULL
+               )",
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Makes the copy for [`SqliteReport::snapshot`] without installing the
+    /// run view or making it read-only.
+    fn copy_to(&self, target: SnapshotTarget) -> Result<SqliteReport> {
+        let filename = match target {
+            SnapshotTarget::File(path) => path,
+            SnapshotTarget::Memory => {
+                let name: u64 = rand::thread_rng().gen();
+                PathBuf::from(format!(
+                    "file:codecov-snapshot-{name:016x}?mode=memory&cache=shared"
+                ))
+            }
+        };
+
+        let mut conn = Connection::open(&filename)?;
+        {
+            let backup = Backup::new(&self.conn, &mut conn)?;
+            // A negative page count copies every page in a single step. It
+            // can't start while another connection is committing, so wait
+            // for that to finish.
+            backup_step(&backup, -1)?;
+        }
+        Ok(SqliteReport {
+            filename,
+            conn,
+            statement_cache: StatementCounters::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{models, Report, ReportBuilder, SqliteReportBuilder};
+
+    fn insert_line<R: Report>(builder: &mut impl ReportBuilder<R>, path: &str) {
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let file = builder.insert_file(path).unwrap();
+        builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no: 1,
+                hits: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_file = temp_dir.path().join("db.sqlite");
+        let mut builder = SqliteReportBuilder::open(db_file.clone()).unwrap();
+        insert_line(&mut builder, "src/lib.rs");
+        let report = builder.build().unwrap();
+
+        for target in [
+            SnapshotTarget::File(temp_dir.path().join("snapshot.sqlite")),
+            SnapshotTarget::Memory,
+        ] {
+            let snapshot = report.snapshot(target).unwrap();
+            assert_eq!(snapshot.totals().unwrap(), report.totals().unwrap());
+            assert_eq!(snapshot.list_files().unwrap(), report.list_files().unwrap());
+
+            // Separate connections see the copy too
+            let files: i64 = snapshot
+                .with_connection(|conn| {
+                    Ok(conn.query_row("SELECT count(*) FROM source_file", [], |row| row.get(0))?)
+                })
+                .unwrap();
+            assert_eq!(files, 1);
+
+            // The copy can't be written to
+            assert!(snapshot
+                .conn
+                .execute("DELETE FROM coverage_sample", [])
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_snapshot_ignores_other_writers() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_file = temp_dir.path().join("db.sqlite");
+        let mut builder = SqliteReportBuilder::open(db_file.clone()).unwrap();
+        insert_line(&mut builder, "src/lib.rs");
+        drop(builder);
+        let report = SqliteReport::open(db_file.clone()).unwrap();
+
+        // Another connection is partway through a transaction when the
+        // snapshot is taken, and commits it afterwards
+        let mut writer = SqliteReportBuilder::open(db_file.clone()).unwrap();
+        let mut tx = writer.transaction().unwrap();
+        insert_line(&mut tx, "src/main.rs");
+        let snapshot = report.snapshot(SnapshotTarget::Memory).unwrap();
+        drop(tx);
+
+        assert_eq!(report.list_files().unwrap().len(), 2);
+        assert_eq!(snapshot.list_files().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_gives_up_on_locked_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_file = temp_dir.path().join("db.sqlite");
+        let report = SqliteReport::open(db_file.clone()).unwrap();
+
+        // An exclusive lock keeps the backup from ever starting
+        let locker = Connection::open(&db_file).unwrap();
+        locker.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        let error = report.snapshot(SnapshotTarget::Memory).unwrap_err();
+        assert!(error.is_busy());
+
+        locker.execute_batch("COMMIT").unwrap();
+        assert!(report.snapshot(SnapshotTarget::Memory).is_ok());
+    }
+}
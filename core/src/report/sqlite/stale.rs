@@ -0,0 +1,84 @@
+//! Finding files whose coverage was measured against different content than
+//! what's in the repository now, so it can be flagged as stale.
+
+use std::collections::HashMap;
+
+use super::SqliteReport;
+use crate::{error::Result, report::models};
+
+impl SqliteReport {
+    /// Lists the files whose recorded
+    /// [`content_hash`](models::SourceFile::content_hash) differs from the
+    /// hash in `current_hashes`, which is keyed by path, ordered by path.
+    ///
+    /// Hashes are compared as-is, so `current_hashes` has to be in the same
+    /// form as the coverage format recorded them in. Files without a recorded
+    /// hash, or without an entry in `current_hashes`, can't be compared and
+    /// aren't listed.
+    pub fn detect_stale_files(
+        &self,
+        current_hashes: &HashMap<String, String>,
+    ) -> Result<Vec<models::SourceFile>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT id, path, language, content_hash, line_count, chunk_index FROM source_file WHERE content_hash IS NOT NULL ORDER BY path, id",
+        )?;
+        let files = stmt
+            .query_map([], |row| row.try_into())?
+            .collect::<rusqlite::Result<Vec<models::SourceFile>>>()?;
+        Ok(files
+            .into_iter()
+            .filter(|file| {
+                current_hashes
+                    .get(&file.path)
+                    .is_some_and(|current| file.content_hash.as_ref() != Some(current))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{ReportBuilder, SqliteReportBuilder};
+
+    #[test]
+    fn test_detect_stale_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report_builder =
+            SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+        for (path, content_hash) in [
+            ("src/changed.rs", Some("abc")),
+            ("src/same.rs", Some("def")),
+            ("src/deleted.rs", Some("123")),
+            ("src/unhashed.rs", None),
+        ] {
+            let file = report_builder.insert_file(path).unwrap();
+            report_builder
+                .update_file_metadata(&models::SourceFile {
+                    content_hash: content_hash.map(String::from),
+                    ..file
+                })
+                .unwrap();
+        }
+        let report = report_builder.build().unwrap();
+
+        let current_hashes = HashMap::from(
+            [
+                ("src/changed.rs", "xyz"),
+                ("src/same.rs", "def"),
+                ("src/unhashed.rs", "456"),
+                ("src/new.rs", "789"),
+            ]
+            .map(|(path, hash)| (path.to_string(), hash.to_string())),
+        );
+        let stale: Vec<_> = report
+            .detect_stale_files(&current_hashes)
+            .unwrap()
+            .into_iter()
+            .map(|file| file.path)
+            .collect();
+        assert_eq!(stale, ["src/changed.rs"]);
+    }
+}
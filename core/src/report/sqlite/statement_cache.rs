@@ -0,0 +1,93 @@
+use std::cell::Cell;
+
+use rusqlite::{CachedStatement, Connection, StatementStatus};
+
+/// How many prepared statements each connection keeps cached unless
+/// [`super::SqliteReport::set_statement_cache_capacity`] or
+/// [`super::SqliteReportBuilder::set_statement_cache_capacity`] says
+/// otherwise. rusqlite's own default of 16 is smaller than the number of
+/// distinct statements a typical ingest or serialization runs, so statements
+/// would be evicted and re-prepared over and over.
+pub const DEFAULT_STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// How often preparing a statement found it in the connection's prepared
+/// statement cache. A high miss count relative to hits suggests the cache
+/// capacity is too small for the workload.
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy)]
+pub struct StatementCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Counts prepared statement cache hits and misses for one connection. Owned
+/// by whichever [`super::SqliteReport`] or [`super::SqliteReportBuilder`]
+/// owns the connection and passed along to everything that prepares
+/// statements on it.
+#[derive(Debug, Default)]
+pub(crate) struct StatementCounters(Cell<StatementCacheStats>);
+
+impl StatementCounters {
+    /// Calls [`Connection::prepare_cached`] and records whether the statement
+    /// came from the cache. rusqlite doesn't say, so a statement that has been
+    /// run before counts as a hit and any other as a miss.
+    pub fn prepare_cached<'conn>(
+        &self,
+        conn: &'conn Connection,
+        sql: &str,
+    ) -> rusqlite::Result<CachedStatement<'conn>> {
+        let stmt = conn.prepare_cached(sql)?;
+        let mut stats = self.0.get();
+        if stmt.get_status(StatementStatus::Run) > 0 {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
+        }
+        self.0.set(stats);
+        Ok(stmt)
+    }
+
+    pub fn stats(&self) -> StatementCacheStats {
+        self.0.get()
+    }
+
+    /// Adds counts recorded for another connection, e.g. one opened for a
+    /// worker thread, to these.
+    #[cfg(feature = "pyreport")]
+    pub fn add(&self, other: StatementCacheStats) {
+        let mut stats = self.0.get();
+        stats.hits += other.hits;
+        stats.misses += other.misses;
+        self.0.set(stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_cached_counts_hits_and_misses() {
+        let conn = Connection::open_in_memory().unwrap();
+        let counters = StatementCounters::default();
+
+        for _ in 0..3 {
+            let _: i64 = counters
+                .prepare_cached(&conn, "SELECT 1")
+                .unwrap()
+                .query_row([], |row| row.get(0))
+                .unwrap();
+        }
+        assert_eq!(counters.stats(), StatementCacheStats { hits: 2, misses: 1 });
+
+        // A statement evicted from the cache has to be prepared again
+        conn.set_prepared_statement_cache_capacity(1);
+        for sql in ["SELECT 2", "SELECT 1"] {
+            let _: i64 = counters
+                .prepare_cached(&conn, sql)
+                .unwrap()
+                .query_row([], |row| row.get(0))
+                .unwrap();
+        }
+        assert_eq!(counters.stats(), StatementCacheStats { hits: 2, misses: 3 });
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use super::SqliteReport;
+use crate::error::Result;
+
+/// How big a report is, by count rather than bytes. See
+/// [`SqliteReport::stats`].
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ReportStats {
+    /// The number of rows stored in each table, including tables that are
+    /// empty. Packed runs count as one row of `coverage_run` each, and SQLite's
+    /// own tables aren't listed.
+    pub row_counts: BTreeMap<String, u64>,
+
+    /// The number of files with at least one current sample.
+    pub files: u64,
+
+    /// The number of uploads, including superseded ones.
+    pub uploads: u64,
+
+    /// The number of contexts, whether or not anything refers to them.
+    pub contexts: u64,
+
+    /// The highest line number with a current sample, if there are any.
+    pub max_line_no: Option<i64>,
+
+    /// The number of distinct contexts attached to current samples, i.e. the
+    /// labels the pyreport format tracks per line.
+    pub labels: u64,
+}
+
+impl SqliteReport {
+    /// Counts what the report holds: rows per table, and the distinct files,
+    /// uploads, contexts and labels in it. Meant for deciding how to handle a
+    /// report before doing anything expensive with it, e.g. whether to
+    /// [strip its labels](SqliteReport::strip_labels). Every count comes from
+    /// the same snapshot of the report, even if another connection is writing
+    /// to it.
+    pub fn stats(&self) -> Result<ReportStats> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        let tables = self
+            .prepare_cached(
+                "SELECT name FROM main.sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        let mut row_counts = BTreeMap::new();
+        if !tables.is_empty() {
+            let query = tables
+                .iter()
+                .map(|table| format!("SELECT '{table}', count(*) FROM main.\"{table}\""))
+                .collect::<Vec<_>>()
+                .join(" UNION ALL ");
+            let mut stmt = self.prepare_cached(&query)?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                row_counts.insert(row.get(0)?, row.get(1)?);
+            }
+        }
+
+        let (files, uploads, contexts, max_line_no, labels) = self
+            .prepare_cached(
+                "SELECT
+                     (SELECT count(DISTINCT source_file_id) FROM coverage_sample WHERE superseded = 0),
+                     (SELECT count(*) FROM raw_upload),
+                     (SELECT count(*) FROM context),
+                     (SELECT max(line_no) FROM coverage_sample WHERE superseded = 0),
+                     (SELECT count(DISTINCT context_id) FROM context_assoc WHERE local_sample_id IS NOT NULL AND superseded = 0)",
+            )?
+            .query_row([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?;
+        tx.finish()?;
+
+        Ok(ReportStats {
+            row_counts,
+            files,
+            uploads,
+            contexts,
+            max_line_no,
+            labels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::{
+        report::{Report, ReportBuilder, SqliteReportBuilder},
+        test_utils::sqlite_report::build_sample_report,
+    };
+
+    #[test]
+    fn test_stats() {
+        let temp_dir = TempDir::new().unwrap();
+        let report = build_sample_report(temp_dir.path().join("db.sqlite")).unwrap();
+
+        let stats = report.stats().unwrap();
+        let samples = report.list_coverage_samples().unwrap();
+        assert_eq!(stats.row_counts["coverage_sample"], samples.len() as u64);
+        assert_eq!(stats.row_counts["source_file"], 2);
+        assert_eq!(stats.row_counts["collapsed_upload"], 0);
+        assert!(!stats.row_counts.contains_key("sqlite_sequence"));
+        assert_eq!(stats.files, 2);
+        assert_eq!(
+            stats.uploads,
+            report.list_raw_uploads().unwrap().len() as u64
+        );
+        assert_eq!(stats.contexts, report.list_contexts().unwrap().len() as u64);
+        assert_eq!(
+            stats.max_line_no,
+            samples.iter().map(|sample| sample.line_no).max()
+        );
+        assert!(stats.labels > 0 && stats.labels <= stats.contexts);
+    }
+
+    #[test]
+    fn test_stats_empty_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut report_builder =
+            SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+        report_builder.insert_file("src/lib.rs").unwrap();
+        let report = report_builder.build().unwrap();
+
+        let stats = report.stats().unwrap();
+        assert_eq!(stats.row_counts["source_file"], 1);
+        assert_eq!(stats.row_counts["coverage_sample"], 0);
+        assert_eq!(stats.files, 0);
+        assert_eq!(stats.uploads, 0);
+        assert_eq!(stats.max_line_no, None);
+        assert_eq!(stats.labels, 0);
+    }
+}
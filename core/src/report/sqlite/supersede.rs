@@ -0,0 +1,298 @@
+use rusqlite::OptionalExtension;
+
+use super::SqliteReport;
+use crate::error::Result;
+
+/// The tables whose rows [`ReportBuilder::supersede_upload`] marks as
+/// superseded, children before parents.
+///
+/// [`ReportBuilder::supersede_upload`]: crate::report::ReportBuilder::supersede_upload
+const SUPERSEDABLE_TABLES: [&str; 5] = [
+    "context_assoc",
+    "span_data",
+    "method_data",
+    "branches_data",
+    "coverage_sample",
+];
+
+impl SqliteReport {
+    /// The current generation of the upload with ID `raw_upload_id`, or
+    /// `None` if there's no such upload. Uploads start at generation 0 and
+    /// move to the next one each time they're superseded with
+    /// [`ReportBuilder::supersede_upload`](crate::report::ReportBuilder::supersede_upload).
+    pub fn upload_generation(&self, raw_upload_id: i64) -> Result<Option<i64>> {
+        Ok(self
+            .prepare_cached("SELECT generation FROM raw_upload WHERE id = ?1")?
+            .query_row([raw_upload_id], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// Deletes every superseded row, which queries already skip. Returns how
+    /// many rows were deleted.
+    pub fn purge_superseded(&mut self) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut deleted = 0;
+        for table in SUPERSEDABLE_TABLES {
+            deleted += self
+                .statement_cache
+                .prepare_cached(&tx, &format!("DELETE FROM {table} WHERE superseded = 1"))?
+                .execute([])?;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        error::CodecovError,
+        report::{models, Report, ReportBuilder, SqliteReportBuilder},
+    };
+
+    /// Inserts a line sample, a branch sample with one branch, a method, a
+    /// span and labels for `upload`, with every hit count set to `hits`.
+    fn insert_measurements<B: ReportBuilder<SqliteReport>>(
+        builder: &mut B,
+        upload: &models::RawUpload,
+        file: &models::SourceFile,
+        hits: i64,
+    ) {
+        let line = builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no: 1,
+                coverage_type: models::CoverageType::Line,
+                hits: Some(hits),
+                ..Default::default()
+            })
+            .unwrap();
+        let branch = builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no: 2,
+                coverage_type: models::CoverageType::Branch,
+                hit_branches: Some(hits.min(1)),
+                total_branches: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        builder
+            .insert_branches_data(models::BranchesData {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                local_sample_id: branch.local_sample_id,
+                hits,
+                branch_format: models::BranchFormat::Condition,
+                branch: "0:jump".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        let method = builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no: 3,
+                coverage_type: models::CoverageType::Method,
+                hits: Some(hits),
+                ..Default::default()
+            })
+            .unwrap();
+        builder
+            .insert_method_data(models::MethodData {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                local_sample_id: method.local_sample_id,
+                line_no: Some(3),
+                hit_complexity_paths: Some(hits),
+                total_complexity: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        builder
+            .insert_span_data(models::SpanData {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                local_sample_id: Some(line.local_sample_id),
+                hits,
+                start_line: Some(1),
+                end_line: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        let context = builder
+            .insert_context(&format!("upload_{}_{hits}", upload.id))
+            .unwrap();
+        builder
+            .associate_context(models::ContextAssoc {
+                context_id: context.id,
+                raw_upload_id: upload.id,
+                local_sample_id: Some(line.local_sample_id),
+                ..Default::default()
+            })
+            .unwrap();
+        builder
+            .associate_context(models::ContextAssoc {
+                context_id: context.id,
+                raw_upload_id: upload.id,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    fn count_rows(report: &SqliteReport, superseded: bool) -> usize {
+        SUPERSEDABLE_TABLES
+            .iter()
+            .map(|table| {
+                report
+                    .conn
+                    .query_row(
+                        &format!("SELECT count(*) FROM {table} WHERE superseded = ?1"),
+                        [superseded],
+                        |row| row.get::<_, usize>(0),
+                    )
+                    .unwrap()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_supersede_upload() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_file = temp_dir.path().join("db.sqlite");
+
+        let mut builder = SqliteReportBuilder::open(db_file.clone()).unwrap();
+        let file = builder.insert_file("src/report.rs").unwrap();
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let other_upload = builder.insert_raw_upload(Default::default()).unwrap();
+        insert_measurements(&mut builder, &upload, &file, 0);
+        insert_measurements(&mut builder, &other_upload, &file, 0);
+        let report = builder.build().unwrap();
+        let original_samples = report.list_coverage_samples().unwrap();
+        assert_eq!(report.upload_generation(upload.id).unwrap(), Some(0));
+
+        // Reprocess the upload with a fresh builder, which doesn't know which
+        // local IDs are taken
+        let mut builder = SqliteReportBuilder::open(db_file.clone()).unwrap();
+        {
+            let mut tx = builder.transaction().unwrap();
+            assert_eq!(tx.supersede_upload(upload.id).unwrap(), 1);
+            insert_measurements(&mut tx, &upload, &file, 3);
+
+            // Readers outside the transaction still see the old generation
+            assert_eq!(report.upload_generation(upload.id).unwrap(), Some(0));
+            assert_eq!(report.list_coverage_samples().unwrap(), original_samples);
+        }
+        let report = builder.build().unwrap();
+        assert_eq!(report.upload_generation(upload.id).unwrap(), Some(1));
+        assert_eq!(report.upload_generation(other_upload.id).unwrap(), Some(0));
+        assert_eq!(report.upload_generation(1234).unwrap(), None);
+
+        // Only the new generation shows up for the reprocessed upload
+        let samples = report.list_coverage_samples().unwrap();
+        assert_eq!(samples.len(), 6);
+        let reprocessed: Vec<_> = samples
+            .iter()
+            .filter(|sample| sample.raw_upload_id == upload.id)
+            .collect();
+        assert_eq!(reprocessed.len(), 3);
+        assert!(reprocessed
+            .iter()
+            .all(|sample| sample.hits == Some(3) || sample.hit_branches == Some(1)));
+        let method = report
+            .get_method_for_sample(reprocessed[2])
+            .unwrap()
+            .unwrap();
+        assert_eq!(method.hit_complexity_paths, Some(3));
+        let contexts = report.list_contexts_for_sample(reprocessed[0]).unwrap();
+        assert_eq!(
+            contexts,
+            vec![models::Context::new(&format!("upload_{}_3", upload.id))]
+        );
+        let contexts = report.list_contexts_for_upload(&upload).unwrap();
+        assert_eq!(
+            contexts,
+            vec![models::Context::new(&format!("upload_{}_3", upload.id))]
+        );
+
+        let totals = report.totals().unwrap();
+        assert_eq!(totals.coverage.hit_lines, 1);
+        assert_eq!(totals.coverage.total_lines, 2);
+        assert_eq!(totals.coverage.hit_complexity_paths, 3);
+        let summary = report.summary().unwrap();
+        assert_eq!(summary.totals.hits, 3);
+        assert_eq!(summary.totals.misses, 0);
+
+        // The old generation is kept until it's purged
+        assert_eq!(count_rows(&report, true), 8);
+        assert_eq!(count_rows(&report, false), 16);
+        let mut report = report;
+        assert_eq!(report.purge_superseded().unwrap(), 8);
+        assert_eq!(count_rows(&report, true), 0);
+        assert_eq!(report.list_coverage_samples().unwrap(), samples);
+    }
+
+    #[test]
+    fn test_supersede_upload_rollback() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_file = temp_dir.path().join("db.sqlite");
+
+        let mut builder = SqliteReportBuilder::open(db_file).unwrap();
+        let file = builder.insert_file("src/report.rs").unwrap();
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        insert_measurements(&mut builder, &upload, &file, 1);
+
+        // Abandoning a reprocess leaves the old generation as it was
+        {
+            let mut tx = builder.transaction().unwrap();
+            tx.supersede_upload(upload.id).unwrap();
+            insert_measurements(&mut tx, &upload, &file, 5);
+            tx.rollback().unwrap();
+        }
+        assert!(matches!(
+            builder.supersede_upload(1234),
+            Err(CodecovError::ReportBuilderError(_))
+        ));
+
+        let report = builder.build().unwrap();
+        assert_eq!(report.upload_generation(upload.id).unwrap(), Some(0));
+        assert_eq!(count_rows(&report, true), 0);
+        assert!(report
+            .list_coverage_samples()
+            .unwrap()
+            .iter()
+            .all(|sample| sample.hits == Some(1) || sample.hit_branches == Some(1)));
+    }
+
+    #[test]
+    fn test_merge_keeps_superseded_rows() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let mut builder = SqliteReportBuilder::open(temp_dir.path().join("a.sqlite")).unwrap();
+        let file = builder.insert_file("src/report.rs").unwrap();
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        insert_measurements(&mut builder, &upload, &file, 0);
+        builder.supersede_upload(upload.id).unwrap();
+        insert_measurements(&mut builder, &upload, &file, 2);
+        let superseded = builder.build().unwrap();
+
+        let mut merged = SqliteReportBuilder::open(temp_dir.path().join("b.sqlite"))
+            .unwrap()
+            .build()
+            .unwrap();
+        merged
+            .merge(&superseded, crate::report::MergePolicy::SumHits)
+            .unwrap();
+        assert_eq!(count_rows(&merged, true), 8);
+        assert_eq!(
+            merged.list_coverage_samples().unwrap(),
+            superseded.list_coverage_samples().unwrap()
+        );
+        assert_eq!(merged.totals().unwrap(), superseded.totals().unwrap());
+    }
+}
@@ -0,0 +1,243 @@
+//! Remembering the results of totals queries, for services that answer many
+//! requests from one report.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+use super::SqliteReport;
+use crate::{
+    error::Result,
+    report::{models, summary::ReportSummary, Report},
+};
+
+/// How often a [`TotalsCache`] could answer from what it remembered.
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy)]
+pub struct TotalsCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+
+    /// How many times the cache was cleared because the report changed.
+    pub invalidations: u64,
+}
+
+impl TotalsCacheStats {
+    /// The fraction of lookups that were hits, or 0 if there haven't been
+    /// any.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    totals: Option<models::ReportTotals>,
+    file_totals: HashMap<i64, Option<models::CoverageTotals>>,
+    list_file_totals: Option<Vec<(models::SourceFile, models::CoverageTotals)>>,
+    list_upload_totals: Option<Vec<(models::RawUpload, models::CoverageTotals)>>,
+    totals_by_coverage_type: Option<models::CoverageTypeTotals>,
+    summary: Option<ReportSummary>,
+}
+
+/// Wraps a [`SqliteReport`] and remembers the results of its totals queries,
+/// so asking for the same totals again doesn't run the query again.
+///
+/// Before each lookup the cache checks whether the database has changed
+/// since it last looked, whether through the report's own connection or any
+/// other, and forgets everything if it has. Create one with
+/// [`SqliteReport::totals_cache`].
+pub struct TotalsCache<'a> {
+    report: &'a SqliteReport,
+    /// `PRAGMA data_version` and the connection's total changes as of the
+    /// last lookup.
+    version: Cell<Option<(i64, i64)>>,
+    entries: RefCell<Entries>,
+    stats: Cell<TotalsCacheStats>,
+}
+
+impl SqliteReport {
+    /// Creates an empty [`TotalsCache`] for this report.
+    pub fn totals_cache(&self) -> TotalsCache<'_> {
+        TotalsCache {
+            report: self,
+            version: Cell::new(None),
+            entries: RefCell::default(),
+            stats: Cell::default(),
+        }
+    }
+}
+
+impl TotalsCache<'_> {
+    /// Like [`Report::totals`].
+    pub fn totals(&self) -> Result<models::ReportTotals> {
+        self.lookup(|entries| &mut entries.totals, || self.report.totals())
+    }
+
+    /// Like [`Report::file_totals`].
+    pub fn file_totals(&self, file: &models::SourceFile) -> Result<models::CoverageTotals> {
+        self.lookup(
+            |entries| entries.file_totals.entry(file.id).or_default(),
+            || self.report.file_totals(file),
+        )
+    }
+
+    /// Like [`Report::list_file_totals`].
+    pub fn list_file_totals(&self) -> Result<Vec<(models::SourceFile, models::CoverageTotals)>> {
+        self.lookup(
+            |entries| &mut entries.list_file_totals,
+            || self.report.list_file_totals(),
+        )
+    }
+
+    /// Like [`Report::list_upload_totals`].
+    pub fn list_upload_totals(&self) -> Result<Vec<(models::RawUpload, models::CoverageTotals)>> {
+        self.lookup(
+            |entries| &mut entries.list_upload_totals,
+            || self.report.list_upload_totals(),
+        )
+    }
+
+    /// Like [`Report::totals_by_coverage_type`].
+    pub fn totals_by_coverage_type(&self) -> Result<models::CoverageTypeTotals> {
+        self.lookup(
+            |entries| &mut entries.totals_by_coverage_type,
+            || self.report.totals_by_coverage_type(),
+        )
+    }
+
+    /// Like [`Report::summary`].
+    pub fn summary(&self) -> Result<ReportSummary> {
+        self.lookup(|entries| &mut entries.summary, || self.report.summary())
+    }
+
+    /// Forgets everything the cache remembers.
+    pub fn clear(&self) {
+        *self.entries.borrow_mut() = Entries::default();
+    }
+
+    pub fn stats(&self) -> TotalsCacheStats {
+        self.stats.get()
+    }
+
+    /// Returns the remembered value in the `entry` of [`Entries`], or
+    /// computes and remembers it.
+    fn lookup<T: Clone>(
+        &self,
+        entry: impl Fn(&mut Entries) -> &mut Option<T>,
+        compute: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        self.invalidate_if_changed()?;
+        let mut stats = self.stats.get();
+        let cached = entry(&mut self.entries.borrow_mut()).clone();
+        let value = match cached {
+            Some(value) => {
+                stats.hits += 1;
+                value
+            }
+            None => {
+                stats.misses += 1;
+                let value = compute()?;
+                *entry(&mut self.entries.borrow_mut()) = Some(value.clone());
+                value
+            }
+        };
+        self.stats.set(stats);
+        Ok(value)
+    }
+
+    fn invalidate_if_changed(&self) -> Result<()> {
+        // `data_version` changes when other connections commit, and
+        // `total_changes()` when this one writes
+        let version = self
+            .report
+            .prepare_cached(
+                "SELECT (SELECT data_version FROM pragma_data_version()), total_changes()",
+            )?
+            .query_row([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let previous = self.version.replace(Some(version));
+        if previous != Some(version) {
+            if previous.is_some() {
+                let mut stats = self.stats.get();
+                stats.invalidations += 1;
+                self.stats.set(stats);
+            }
+            self.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{ReportBuilder, SqliteReportBuilder};
+
+    fn insert_line(builder: &mut SqliteReportBuilder, path: &str, hits: i64) {
+        let upload = builder.insert_raw_upload(Default::default()).unwrap();
+        let file = builder.insert_file(path).unwrap();
+        builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no: 1,
+                hits: Some(hits),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_totals_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_file = temp_dir.path().join("db.sqlite");
+        let mut builder = SqliteReportBuilder::open(db_file.clone()).unwrap();
+        insert_line(&mut builder, "src/lib.rs", 1);
+        drop(builder);
+        let report = SqliteReport::open(db_file.clone()).unwrap();
+        let cache = report.totals_cache();
+
+        let totals = cache.totals().unwrap();
+        assert_eq!(totals, report.totals().unwrap());
+        assert_eq!(cache.totals().unwrap(), totals);
+        let file = &report.list_files().unwrap()[0];
+        assert_eq!(
+            cache.file_totals(file).unwrap(),
+            report.file_totals(file).unwrap()
+        );
+        cache.file_totals(file).unwrap();
+        assert_eq!(
+            cache.stats(),
+            TotalsCacheStats {
+                hits: 2,
+                misses: 2,
+                invalidations: 0,
+            }
+        );
+        assert_eq!(cache.stats().hit_rate(), 0.5);
+
+        // Another connection adds a miss
+        let mut builder = SqliteReportBuilder::open(db_file).unwrap();
+        insert_line(&mut builder, "src/main.rs", 0);
+        drop(builder);
+        let totals = cache.totals().unwrap();
+        assert_eq!(totals.coverage.total_lines, 2);
+        assert_eq!(cache.stats().invalidations, 1);
+        assert_eq!(cache.stats().misses, 3);
+
+        // Writes through the report's own connection count too
+        report
+            .conn
+            .execute("UPDATE coverage_sample SET hits = 1", [])
+            .unwrap();
+        assert_eq!(cache.totals().unwrap().coverage.hit_lines, 2);
+        assert_eq!(cache.stats().invalidations, 2);
+    }
+}
@@ -0,0 +1,136 @@
+//! A compact overview of a report's coverage, for places like a CLI or the
+//! Python bindings that want to show users the same numbers.
+//!
+//! Lines are counted the way pyreport counts them: each (file, line) is
+//! counted once, with the "most covered" status any upload recorded for it.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+/// Line and branch counts for a report or for the uploads with a given flag.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SummaryCounts {
+    /// The number of files with any lines tracked.
+    pub files: u64,
+
+    /// The number of lines tracked.
+    pub lines: u64,
+
+    /// The number of lines that were fully hit.
+    pub hits: u64,
+
+    /// The number of lines that were not hit at all.
+    pub misses: u64,
+
+    /// The number of branch lines that were hit but had some branches missed.
+    pub partials: u64,
+
+    /// The number of branch paths that were hit.
+    pub hit_branches: u64,
+
+    /// The number of possible branch paths tracked.
+    pub total_branches: u64,
+}
+
+impl SummaryCounts {
+    /// The percentage of tracked lines that were fully hit, or `None` if no
+    /// lines were tracked.
+    pub fn coverage_pct(&self) -> Option<f64> {
+        (self.lines > 0).then(|| self.hits as f64 / self.lines as f64 * 100.0)
+    }
+}
+
+impl fmt::Display for SummaryCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} lines ({} hit, {} missed, {} partial), {}/{} branches",
+            self.files,
+            self.lines,
+            self.hits,
+            self.misses,
+            self.partials,
+            self.hit_branches,
+            self.total_branches,
+        )?;
+        match self.coverage_pct() {
+            Some(pct) => write!(f, ", {pct:.2}% coverage"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Counts for a whole report alongside a breakdown by upload flag. Built by
+/// [`Report::summary`](super::Report::summary).
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReportSummary {
+    /// Counts across every upload in the report.
+    #[serde(flatten)]
+    pub totals: SummaryCounts,
+
+    /// Counts across only the uploads with each flag. An upload with several
+    /// flags is counted under each of them, and uploads with no flags are not
+    /// counted under any.
+    pub flags: BTreeMap<String, SummaryCounts>,
+}
+
+impl fmt::Display for ReportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.totals)?;
+        for (flag, counts) in &self.flags {
+            write!(f, "\n  {flag}: {counts}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(files: u64, lines: u64, hits: u64) -> SummaryCounts {
+        SummaryCounts {
+            files,
+            lines,
+            hits,
+            misses: lines - hits - 1,
+            partials: 1,
+            hit_branches: 1,
+            total_branches: 2,
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let summary = ReportSummary {
+            totals: counts(2, 8, 6),
+            flags: BTreeMap::from([("unit".to_string(), counts(1, 3, 1))]),
+        };
+        assert_eq!(
+            summary.to_string(),
+            "2 files, 8 lines (6 hit, 1 missed, 1 partial), 1/2 branches, 75.00% coverage
+  unit: 1 files, 3 lines (1 hit, 1 missed, 1 partial), 1/2 branches, 33.33% coverage"
+        );
+
+        assert_eq!(
+            ReportSummary::default().to_string(),
+            "0 files, 0 lines (0 hit, 0 missed, 0 partial), 0/0 branches"
+        );
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let summary = ReportSummary {
+            totals: counts(2, 8, 6),
+            flags: BTreeMap::from([("unit".to_string(), counts(1, 3, 1))]),
+        };
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["lines"], 8);
+        assert_eq!(json["flags"]["unit"]["hits"], 1);
+        assert_eq!(
+            serde_json::from_value::<ReportSummary>(json).unwrap(),
+            summary
+        );
+    }
+}
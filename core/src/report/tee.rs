@@ -0,0 +1,538 @@
+//! A [`ReportBuilder`] that writes everything to two other builders, e.g. to
+//! check a new storage backend against an old one using the same parser run.
+
+use std::{collections::HashMap, marker::PhantomData};
+
+use super::{models, DuplicateUploadPolicy, Report, ReportBuilder};
+use crate::error::{CodecovError, Result};
+
+/// Forwards every call to a primary builder `A` and then to a secondary
+/// builder `B`. Callers see what `A` returns, including the IDs it assigns.
+///
+/// `B` assigns its own upload IDs and local sample, branch, method and span
+/// IDs, so the tee remembers which of `B`'s IDs each of `A`'s corresponds to
+/// and translates models on their way to `B`. Uploads, samples and spans
+/// therefore have to be inserted through the tee before anything refers to
+/// them. File and context IDs are hashes of their names, so they're the same
+/// in both builders.
+///
+/// IDs are translated before either builder is called, so a model that
+/// refers to something the tee hasn't seen is rejected by both. If `A`
+/// succeeds and `B` fails, though, the error is returned but `A`'s change is
+/// kept, so the two reports may differ afterwards.
+///
+/// [`ReportBuilder::build`] builds both reports and returns `A`'s, dropping
+/// `B`'s. Use [`TeeReportBuilder::build_both`] to keep both.
+pub struct TeeReportBuilder<A, B, RB> {
+    primary: A,
+    secondary: B,
+
+    upload_ids: HashMap<i64, i64>,
+    sample_ids: HashMap<(i64, i64), i64>,
+    span_ids: HashMap<(i64, i64), i64>,
+
+    _secondary_report: PhantomData<RB>,
+}
+
+impl<A, B, RB> TeeReportBuilder<A, B, RB> {
+    pub fn new(primary: A, secondary: B) -> Self {
+        TeeReportBuilder {
+            primary,
+            secondary,
+            upload_ids: HashMap::new(),
+            sample_ids: HashMap::new(),
+            span_ids: HashMap::new(),
+            _secondary_report: PhantomData,
+        }
+    }
+
+    /// Builds both reports and returns them as `(primary, secondary)`.
+    pub fn build_both<RA>(self) -> Result<(RA, RB)>
+    where
+        RA: Report,
+        RB: Report,
+        A: ReportBuilder<RA>,
+        B: ReportBuilder<RB>,
+    {
+        Ok((self.primary.build()?, self.secondary.build()?))
+    }
+
+    fn upload_id(&self, raw_upload_id: i64) -> Result<i64> {
+        self.upload_ids.get(&raw_upload_id).copied().ok_or_else(|| {
+            CodecovError::ReportBuilderError(format!(
+                "upload {raw_upload_id} wasn't inserted through this tee"
+            ))
+        })
+    }
+
+    fn sample_id(&self, raw_upload_id: i64, local_sample_id: i64) -> Result<i64> {
+        self.sample_ids
+            .get(&(raw_upload_id, local_sample_id))
+            .copied()
+            .ok_or_else(|| {
+                CodecovError::ReportBuilderError(format!(
+                    "sample {local_sample_id} of upload {raw_upload_id} wasn't inserted through this tee"
+                ))
+            })
+    }
+
+    fn span_id(&self, raw_upload_id: i64, local_span_id: i64) -> Result<i64> {
+        self.span_ids
+            .get(&(raw_upload_id, local_span_id))
+            .copied()
+            .ok_or_else(|| {
+                CodecovError::ReportBuilderError(format!(
+                    "span {local_span_id} of upload {raw_upload_id} wasn't inserted through this tee"
+                ))
+            })
+    }
+
+    /// Returns a copy of `sample` with `B`'s IDs.
+    fn translate_sample(&self, sample: &models::CoverageSample) -> Result<models::CoverageSample> {
+        Ok(models::CoverageSample {
+            raw_upload_id: self.upload_id(sample.raw_upload_id)?,
+            ..sample.clone()
+        })
+    }
+
+    fn translate_branch(&self, branch: &models::BranchesData) -> Result<models::BranchesData> {
+        Ok(models::BranchesData {
+            raw_upload_id: self.upload_id(branch.raw_upload_id)?,
+            local_sample_id: self.sample_id(branch.raw_upload_id, branch.local_sample_id)?,
+            ..branch.clone()
+        })
+    }
+
+    fn translate_method(&self, method: &models::MethodData) -> Result<models::MethodData> {
+        Ok(models::MethodData {
+            raw_upload_id: self.upload_id(method.raw_upload_id)?,
+            local_sample_id: self.sample_id(method.raw_upload_id, method.local_sample_id)?,
+            ..method.clone()
+        })
+    }
+
+    fn translate_span(&self, span: &models::SpanData) -> Result<models::SpanData> {
+        Ok(models::SpanData {
+            raw_upload_id: self.upload_id(span.raw_upload_id)?,
+            local_sample_id: span
+                .local_sample_id
+                .map(|id| self.sample_id(span.raw_upload_id, id))
+                .transpose()?,
+            ..span.clone()
+        })
+    }
+
+    fn translate_assoc(&self, assoc: &models::ContextAssoc) -> Result<models::ContextAssoc> {
+        Ok(models::ContextAssoc {
+            raw_upload_id: self.upload_id(assoc.raw_upload_id)?,
+            local_sample_id: assoc
+                .local_sample_id
+                .map(|id| self.sample_id(assoc.raw_upload_id, id))
+                .transpose()?,
+            local_span_id: assoc
+                .local_span_id
+                .map(|id| self.span_id(assoc.raw_upload_id, id))
+                .transpose()?,
+            ..assoc.clone()
+        })
+    }
+}
+
+impl<RA, RB, A, B> ReportBuilder<RA> for TeeReportBuilder<A, B, RB>
+where
+    RA: Report,
+    RB: Report,
+    A: ReportBuilder<RA>,
+    B: ReportBuilder<RB>,
+{
+    fn insert_file(&mut self, path: &str) -> Result<models::SourceFile> {
+        let file = self.primary.insert_file(path)?;
+        self.secondary.insert_file(path)?;
+        Ok(file)
+    }
+
+    fn update_file_metadata(&mut self, file: &models::SourceFile) -> Result<()> {
+        self.primary.update_file_metadata(file)?;
+        self.secondary.update_file_metadata(file)
+    }
+
+    fn insert_context(&mut self, name: &str) -> Result<models::Context> {
+        let context = self.primary.insert_context(name)?;
+        self.secondary.insert_context(name)?;
+        Ok(context)
+    }
+
+    fn multi_insert_context(&mut self, names: &[&str]) -> Result<Vec<models::Context>> {
+        let contexts = self.primary.multi_insert_context(names)?;
+        self.secondary.multi_insert_context(names)?;
+        Ok(contexts)
+    }
+
+    fn insert_coverage_sample(
+        &mut self,
+        sample: models::CoverageSample,
+    ) -> Result<models::CoverageSample> {
+        let theirs = self.translate_sample(&sample)?;
+        let sample = self.primary.insert_coverage_sample(sample)?;
+        let theirs = self.secondary.insert_coverage_sample(theirs)?;
+        self.sample_ids.insert(
+            (sample.raw_upload_id, sample.local_sample_id),
+            theirs.local_sample_id,
+        );
+        Ok(sample)
+    }
+
+    fn multi_insert_coverage_sample(
+        &mut self,
+        mut samples: Vec<&mut models::CoverageSample>,
+    ) -> Result<()> {
+        let mut theirs = samples
+            .iter()
+            .map(|sample| self.translate_sample(sample))
+            .collect::<Result<Vec<_>>>()?;
+        self.primary
+            .multi_insert_coverage_sample(samples.iter_mut().map(|s| &mut **s).collect())?;
+        self.secondary
+            .multi_insert_coverage_sample(theirs.iter_mut().collect())?;
+        for (ours, theirs) in samples.iter().zip(&theirs) {
+            self.sample_ids.insert(
+                (ours.raw_upload_id, ours.local_sample_id),
+                theirs.local_sample_id,
+            );
+        }
+        Ok(())
+    }
+
+    fn multi_update_coverage_sample(&mut self, samples: &[models::CoverageSample]) -> Result<()> {
+        let theirs = samples
+            .iter()
+            .map(|sample| {
+                Ok(models::CoverageSample {
+                    local_sample_id: self
+                        .sample_id(sample.raw_upload_id, sample.local_sample_id)?,
+                    ..self.translate_sample(sample)?
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.primary.multi_update_coverage_sample(samples)?;
+        self.secondary.multi_update_coverage_sample(&theirs)
+    }
+
+    fn insert_branches_data(
+        &mut self,
+        branch: models::BranchesData,
+    ) -> Result<models::BranchesData> {
+        let theirs = self.translate_branch(&branch)?;
+        let branch = self.primary.insert_branches_data(branch)?;
+        self.secondary.insert_branches_data(theirs)?;
+        Ok(branch)
+    }
+
+    fn multi_insert_branches_data(
+        &mut self,
+        mut branches: Vec<&mut models::BranchesData>,
+    ) -> Result<()> {
+        let mut theirs = branches
+            .iter()
+            .map(|branch| self.translate_branch(branch))
+            .collect::<Result<Vec<_>>>()?;
+        self.primary
+            .multi_insert_branches_data(branches.iter_mut().map(|b| &mut **b).collect())?;
+        self.secondary
+            .multi_insert_branches_data(theirs.iter_mut().collect())
+    }
+
+    fn insert_method_data(&mut self, method: models::MethodData) -> Result<models::MethodData> {
+        let theirs = self.translate_method(&method)?;
+        let method = self.primary.insert_method_data(method)?;
+        self.secondary.insert_method_data(theirs)?;
+        Ok(method)
+    }
+
+    fn multi_insert_method_data(
+        &mut self,
+        mut methods: Vec<&mut models::MethodData>,
+    ) -> Result<()> {
+        let mut theirs = methods
+            .iter()
+            .map(|method| self.translate_method(method))
+            .collect::<Result<Vec<_>>>()?;
+        self.primary
+            .multi_insert_method_data(methods.iter_mut().map(|m| &mut **m).collect())?;
+        self.secondary
+            .multi_insert_method_data(theirs.iter_mut().collect())
+    }
+
+    fn insert_span_data(&mut self, span: models::SpanData) -> Result<models::SpanData> {
+        let theirs = self.translate_span(&span)?;
+        let span = self.primary.insert_span_data(span)?;
+        let theirs = self.secondary.insert_span_data(theirs)?;
+        self.span_ids.insert(
+            (span.raw_upload_id, span.local_span_id),
+            theirs.local_span_id,
+        );
+        Ok(span)
+    }
+
+    fn multi_insert_span_data(&mut self, mut spans: Vec<&mut models::SpanData>) -> Result<()> {
+        let mut theirs = spans
+            .iter()
+            .map(|span| self.translate_span(span))
+            .collect::<Result<Vec<_>>>()?;
+        self.primary
+            .multi_insert_span_data(spans.iter_mut().map(|s| &mut **s).collect())?;
+        self.secondary
+            .multi_insert_span_data(theirs.iter_mut().collect())?;
+        for (ours, theirs) in spans.iter().zip(&theirs) {
+            self.span_ids.insert(
+                (ours.raw_upload_id, ours.local_span_id),
+                theirs.local_span_id,
+            );
+        }
+        Ok(())
+    }
+
+    fn associate_context(&mut self, assoc: models::ContextAssoc) -> Result<models::ContextAssoc> {
+        let theirs = self.translate_assoc(&assoc)?;
+        let assoc = self.primary.associate_context(assoc)?;
+        self.secondary.associate_context(theirs)?;
+        Ok(assoc)
+    }
+
+    fn multi_associate_context(
+        &mut self,
+        mut assocs: Vec<&mut models::ContextAssoc>,
+    ) -> Result<()> {
+        let mut theirs = assocs
+            .iter()
+            .map(|assoc| self.translate_assoc(assoc))
+            .collect::<Result<Vec<_>>>()?;
+        self.primary
+            .multi_associate_context(assocs.iter_mut().map(|a| &mut **a).collect())?;
+        self.secondary
+            .multi_associate_context(theirs.iter_mut().collect())
+    }
+
+    fn insert_upload_tag(&mut self, tag: models::UploadTag) -> Result<models::UploadTag> {
+        let theirs = models::UploadTag {
+            raw_upload_id: self.upload_id(tag.raw_upload_id)?,
+            ..tag.clone()
+        };
+        let tag = self.primary.insert_upload_tag(tag)?;
+        self.secondary.insert_upload_tag(theirs)?;
+        Ok(tag)
+    }
+
+    fn multi_insert_session_file_totals(
+        &mut self,
+        totals: &[models::SessionFileTotals],
+    ) -> Result<()> {
+        let theirs = totals
+            .iter()
+            .map(|totals| {
+                Ok(models::SessionFileTotals {
+                    raw_upload_id: self.upload_id(totals.raw_upload_id)?,
+                    ..totals.clone()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.primary.multi_insert_session_file_totals(totals)?;
+        self.secondary.multi_insert_session_file_totals(&theirs)
+    }
+
+    fn multi_insert_line_attributes(&mut self, attributes: &[models::LineAttribute]) -> Result<()> {
+        let theirs = attributes
+            .iter()
+            .map(|attribute| {
+                Ok(models::LineAttribute {
+                    raw_upload_id: self.upload_id(attribute.raw_upload_id)?,
+                    ..attribute.clone()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.primary.multi_insert_line_attributes(attributes)?;
+        self.secondary.multi_insert_line_attributes(&theirs)
+    }
+
+    fn insert_raw_upload(
+        &mut self,
+        upload_details: models::RawUpload,
+    ) -> Result<models::RawUpload> {
+        let upload = self.primary.insert_raw_upload(upload_details)?;
+        let theirs = self.secondary.insert_raw_upload(upload.clone())?;
+        self.upload_ids.insert(upload.id, theirs.id);
+        Ok(upload)
+    }
+
+    fn update_raw_upload_url(
+        &mut self,
+        raw_upload_id: i64,
+        raw_upload_url: Option<&str>,
+    ) -> Result<()> {
+        let theirs = self.upload_id(raw_upload_id)?;
+        self.primary
+            .update_raw_upload_url(raw_upload_id, raw_upload_url)?;
+        self.secondary.update_raw_upload_url(theirs, raw_upload_url)
+    }
+
+    fn update_upload_state(
+        &mut self,
+        raw_upload_id: i64,
+        state: models::UploadState,
+    ) -> Result<()> {
+        let theirs = self.upload_id(raw_upload_id)?;
+        self.primary.update_upload_state(raw_upload_id, state)?;
+        self.secondary.update_upload_state(theirs, state)
+    }
+
+    fn insert_raw_upload_idempotent(
+        &mut self,
+        upload_details: models::RawUpload,
+        on_duplicate: DuplicateUploadPolicy,
+    ) -> Result<Option<models::RawUpload>> {
+        let upload = self
+            .primary
+            .insert_raw_upload_idempotent(upload_details.clone(), on_duplicate)?;
+        let theirs = self
+            .secondary
+            .insert_raw_upload_idempotent(upload_details, on_duplicate)?;
+        match (&upload, theirs) {
+            (Some(ours), Some(theirs)) => {
+                self.upload_ids.insert(ours.id, theirs.id);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(CodecovError::ReportBuilderError(
+                    "only one of the tee's builders already had the upload".to_string(),
+                ))
+            }
+        }
+        Ok(upload)
+    }
+
+    fn supersede_upload(&mut self, raw_upload_id: i64) -> Result<i64> {
+        let theirs = self.upload_id(raw_upload_id)?;
+        let generation = self.primary.supersede_upload(raw_upload_id)?;
+        self.secondary.supersede_upload(theirs)?;
+        Ok(generation)
+    }
+
+    fn savepoint(&mut self) -> Result<()> {
+        self.primary.savepoint()?;
+        self.secondary.savepoint()
+    }
+
+    fn release_savepoint(&mut self) -> Result<()> {
+        self.primary.release_savepoint()?;
+        self.secondary.release_savepoint()
+    }
+
+    fn rollback_to_savepoint(&mut self) -> Result<()> {
+        self.primary.rollback_to_savepoint()?;
+        self.secondary.rollback_to_savepoint()
+    }
+
+    fn build(self) -> Result<RA> {
+        Ok(self.build_both()?.0)
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::{SqliteReport, SqliteReportBuilder};
+
+    type Tee = TeeReportBuilder<SqliteReportBuilder, SqliteReportBuilder, SqliteReport>;
+
+    fn setup(temp_dir: &TempDir) -> Tee {
+        TeeReportBuilder::new(
+            SqliteReportBuilder::open(temp_dir.path().join("primary.sqlite")).unwrap(),
+            SqliteReportBuilder::open(temp_dir.path().join("secondary.sqlite")).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_tee() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut tee = setup(&temp_dir);
+
+        let upload = tee.insert_raw_upload(Default::default()).unwrap();
+        let file = tee.insert_file("src/lib.rs").unwrap();
+        let context = tee.insert_context("test_case").unwrap();
+        let mut samples: Vec<_> = (1..=3)
+            .map(|line_no| models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no,
+                hits: Some(line_no),
+                ..Default::default()
+            })
+            .collect();
+        tee.multi_insert_coverage_sample(samples.iter_mut().collect())
+            .unwrap();
+        let span = tee
+            .insert_span_data(models::SpanData {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                local_sample_id: Some(samples[2].local_sample_id),
+                hits: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        tee.associate_context(models::ContextAssoc {
+            context_id: context.id,
+            raw_upload_id: upload.id,
+            local_sample_id: Some(samples[1].local_sample_id),
+            ..Default::default()
+        })
+        .unwrap();
+        tee.associate_context(models::ContextAssoc {
+            context_id: context.id,
+            raw_upload_id: upload.id,
+            local_span_id: Some(span.local_span_id),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // IDs that didn't come through the tee can't be translated
+        let error = tee
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: 1234,
+                source_file_id: file.id,
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("wasn't inserted through this tee"));
+
+        let (primary, secondary) = tee.build_both().unwrap();
+        assert_eq!(
+            primary.list_files().unwrap(),
+            secondary.list_files().unwrap()
+        );
+        assert_eq!(primary.totals().unwrap(), secondary.totals().unwrap());
+
+        let secondary_upload = &secondary.list_raw_uploads().unwrap()[0];
+        let without_upload = |report: &SqliteReport| -> Vec<_> {
+            report
+                .list_coverage_samples()
+                .unwrap()
+                .into_iter()
+                .map(|sample| (sample.line_no, sample.hits))
+                .collect()
+        };
+        assert_eq!(without_upload(&primary), without_upload(&secondary));
+        assert_eq!(
+            secondary
+                .list_samples_for_context(&context)
+                .unwrap()
+                .iter()
+                .map(|sample| (sample.raw_upload_id, sample.line_no))
+                .collect::<Vec<_>>(),
+            vec![(secondary_upload.id, 2)]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Totals for a run of commits, for charts that only need a handful of
+//! numbers per commit.
+
+#[cfg(feature = "sqlite")]
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::models::ReportTotals;
+#[cfg(feature = "sqlite")]
+use super::{Report, SqliteReport};
+#[cfg(feature = "sqlite")]
+use crate::error::{CodecovError, Result};
+
+/// [`ReportTotals`] for a series of commits, stored column-wise so the JSON
+/// form stays small: entry `i` of each list is for `commitish[i]`.
+#[derive(PartialEq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TotalsTimeSeries {
+    pub commitish: Vec<String>,
+    pub files: Vec<u64>,
+    pub uploads: Vec<u64>,
+    pub hit_lines: Vec<u64>,
+    pub total_lines: Vec<u64>,
+    pub hit_branches: Vec<u64>,
+    pub total_branches: Vec<u64>,
+    pub hit_methods: Vec<u64>,
+    pub total_methods: Vec<u64>,
+
+    /// The percentage of lines hit, or `None` if no lines were tracked.
+    pub coverage: Vec<Option<f64>>,
+}
+
+impl TotalsTimeSeries {
+    /// Appends `totals` for `commitish` to the end of the series.
+    pub fn push(&mut self, commitish: &str, totals: &ReportTotals) {
+        let coverage = &totals.coverage;
+        self.commitish.push(commitish.to_string());
+        self.files.push(totals.files);
+        self.uploads.push(totals.uploads);
+        self.hit_lines.push(coverage.hit_lines);
+        self.total_lines.push(coverage.total_lines);
+        self.hit_branches.push(coverage.hit_branches);
+        self.total_branches.push(coverage.total_branches);
+        self.hit_methods.push(coverage.hit_methods);
+        self.total_methods.push(coverage.total_methods);
+        self.coverage.push(
+            (coverage.total_lines > 0)
+                .then(|| coverage.hit_lines as f64 / coverage.total_lines as f64 * 100.0),
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.commitish.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commitish.is_empty()
+    }
+}
+
+/// Opens each `(commitish, path)` report in turn and collects its totals.
+/// Reports are opened with [`SqliteReport::open_readonly`], so none of them
+/// are modified: this fails if any of them doesn't exist rather than creating
+/// it, and if any of them needs migrating rather than migrating it.
+#[cfg(feature = "sqlite")]
+pub fn totals_time_series<C: AsRef<str>, P: AsRef<Path>>(
+    reports: impl IntoIterator<Item = (C, P)>,
+) -> Result<TotalsTimeSeries> {
+    let mut series = TotalsTimeSeries::default();
+    for (commitish, path) in reports {
+        let path = path.as_ref();
+        if !path.is_file() {
+            return Err(CodecovError::IOError(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no report at {}", path.display()),
+            )));
+        }
+        let report = SqliteReport::open_readonly(path.to_path_buf())?;
+        series.push(commitish.as_ref(), &report.totals()?);
+    }
+    Ok(series)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::test_utils::sqlite_report::build_sample_report;
+
+    #[test]
+    fn test_totals_time_series() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = temp_dir.path().join("first.sqlite");
+        let second = temp_dir.path().join("second.sqlite");
+        let totals = build_sample_report(first.clone())
+            .unwrap()
+            .totals()
+            .unwrap();
+        SqliteReport::open(second.clone()).unwrap();
+
+        let series = totals_time_series([("abc123", &first), ("def456", &second)]).unwrap();
+        assert_eq!(series.len(), 2);
+        assert_eq!(series.commitish, ["abc123", "def456"]);
+        assert_eq!(series.files, [totals.files, 0]);
+        assert_eq!(series.hit_lines, [totals.coverage.hit_lines, 0]);
+        assert_eq!(series.total_lines, [totals.coverage.total_lines, 0]);
+        assert!(series.coverage[0].is_some());
+        assert_eq!(series.coverage[1], None);
+
+        let json = serde_json::to_value(&series).unwrap();
+        assert_eq!(json["commitish"], json!(["abc123", "def456"]));
+        assert_eq!(json["coverage"][1], json!(null));
+
+        let missing = temp_dir.path().join("missing.sqlite");
+        assert!(matches!(
+            totals_time_series([("ghi789", &missing)]),
+            Err(CodecovError::IOError(_))
+        ));
+        assert!(!missing.exists());
+
+        // Reports with an older schema are left alone
+        let old = temp_dir.path().join("old.sqlite");
+        SqliteReport::open(old.clone()).unwrap();
+        rusqlite::Connection::open(&old)
+            .unwrap()
+            .pragma_update(None, "user_version", 1)
+            .unwrap();
+        assert!(matches!(
+            totals_time_series([("jkl012", &old)]),
+            Err(CodecovError::SchemaVersionMismatch { found: 1, .. })
+        ));
+        let user_version: usize = rusqlite::Connection::open(&old)
+            .unwrap()
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(user_version, 1);
+    }
+}
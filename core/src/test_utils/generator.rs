@@ -0,0 +1,346 @@
+//! Synthesizes pyreport inputs (report JSON + chunks file) and
+//! [`SqliteReport`]s of arbitrary size so benchmarks and tests can exercise
+//! large-repo scale without real customer data.
+//!
+//! Output is fully determined by the [`GeneratorConfig`], including its
+//! `seed`, so the same config always produces byte-for-byte identical files.
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use winnow::Parser;
+
+use crate::{
+    error::{CodecovError, Result},
+    parsers::pyreport::{chunks, report_json},
+    report::{
+        pyreport::{CHUNKS_FILE_END_OF_CHUNK, CHUNKS_FILE_HEADER_TERMINATOR},
+        ReportBuilder, SqliteReport, SqliteReportBuilder,
+    },
+};
+
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    /// Number of source files, each of which gets its own chunk.
+    pub files: usize,
+
+    /// Number of lines in each file's chunk, including empty lines.
+    pub lines_per_file: usize,
+
+    /// Number of sessions (uploads) in the report JSON. Each measured line is
+    /// covered by at least one of them.
+    pub sessions: usize,
+
+    /// Number of distinct labels in the chunks file's `labels_index`. If 0,
+    /// no datapoints are written.
+    pub labels: usize,
+
+    /// Whether datapoints name their labels directly, like older chunks files,
+    /// rather than referring to them by their ID in the `labels_index`.
+    pub inline_labels: bool,
+
+    /// Whether branch lines list which of their branches were missed.
+    pub missing_branches: bool,
+
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            files: 10,
+            lines_per_file: 100,
+            sessions: 2,
+            labels: 0,
+            inline_labels: false,
+            missing_branches: false,
+            seed: 0,
+        }
+    }
+}
+
+/// Writes a report JSON describing `config.files` files and `config.sessions`
+/// sessions to `output`.
+pub fn write_report_json(config: &GeneratorConfig, output: &mut impl Write) -> io::Result<()> {
+    write!(output, "{{\"files\": {{")?;
+    for file in 0..config.files {
+        let delimiter = if file == 0 { "" } else { ", " };
+        write!(
+            output,
+            "{delimiter}\"src/generated/file_{file}.rs\": [{file}, null, null, null]"
+        )?;
+    }
+
+    write!(output, "}}, \"sessions\": {{")?;
+    for session in 0..config.sessions {
+        let delimiter = if session == 0 { "" } else { ", " };
+        write!(
+            output,
+            "{delimiter}\"{session}\": {{\"t\": null, \"d\": {timestamp}, \"a\": \"v4/raw/generated/{session}.txt\", \"f\": [\"flag-{flag}\"], \"c\": null, \"n\": null, \"N\": null, \"j\": \"job {session}\", \"u\": null, \"p\": null, \"e\": null, \"st\": \"uploaded\", \"se\": {{}}}}",
+            timestamp = 1704827412 + session,
+            flag = session % 3,
+        )?;
+    }
+    write!(output, "}}}}")?;
+
+    Ok(())
+}
+
+/// Writes a chunks file with one chunk per file in `config` to `output`.
+pub fn write_chunks(config: &GeneratorConfig, output: &mut impl Write) -> io::Result<()> {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    write!(output, "{{\"labels_index\": {{")?;
+    let indexed_labels = if config.inline_labels {
+        0
+    } else {
+        config.labels
+    };
+    for label in 0..indexed_labels {
+        let delimiter = if label == 0 { "" } else { ", " };
+        write!(output, "{delimiter}\"{label}\": \"test_case_{label}\"")?;
+    }
+    write!(output, "}}}}{CHUNKS_FILE_HEADER_TERMINATOR}")?;
+
+    for file in 0..config.files {
+        if file > 0 {
+            write!(output, "{CHUNKS_FILE_END_OF_CHUNK}")?;
+        }
+        write!(output, "{{}}")?;
+        for _ in 0..config.lines_per_file {
+            writeln!(output)?;
+            write_line(config, &mut rng, output)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a single report line, or nothing if the line is randomly chosen to
+/// be empty.
+fn write_line(
+    config: &GeneratorConfig,
+    rng: &mut StdRng,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    if config.sessions == 0 || rng.gen_bool(0.2) {
+        return Ok(());
+    }
+
+    let session_count = rng.gen_range(1..=config.sessions);
+    let mut session_ids: Vec<usize> = (0..config.sessions).collect();
+    let (session_ids, _) = session_ids.partial_shuffle(rng, session_count);
+    session_ids.sort();
+
+    // (line coverage, coverage type, per-session coverage)
+    let (coverage, coverage_type, session_coverages): (String, &str, Vec<String>) =
+        match rng.gen_range(0..10) {
+            // Branch
+            0 | 1 => {
+                let total = 2 * rng.gen_range(1..=2);
+                let covered: Vec<u32> = session_ids
+                    .iter()
+                    .map(|_| rng.gen_range(0..=total))
+                    .collect();
+                let max = covered.iter().max().unwrap();
+                (
+                    format!("\"{max}/{total}\""),
+                    "\"b\"",
+                    covered
+                        .iter()
+                        .map(|c| {
+                            let coverage = format!("\"{c}/{total}\"");
+                            if !config.missing_branches || *c == total {
+                                return coverage;
+                            }
+                            let missing: Vec<String> =
+                                (*c..total).map(|b| format!("\"{b}:jump\"")).collect();
+                            format!("{coverage}, [{}]", missing.join(", "))
+                        })
+                        .collect(),
+                )
+            }
+            // Method
+            2 => {
+                let hits: Vec<u32> = session_ids.iter().map(|_| rng.gen_range(0..5)).collect();
+                (
+                    hits.iter().sum::<u32>().to_string(),
+                    "\"m\"",
+                    hits.iter().map(u32::to_string).collect(),
+                )
+            }
+            // Line
+            _ => {
+                let hits: Vec<u32> = session_ids.iter().map(|_| rng.gen_range(0..5)).collect();
+                (
+                    hits.iter().sum::<u32>().to_string(),
+                    "null",
+                    hits.iter().map(u32::to_string).collect(),
+                )
+            }
+        };
+
+    write!(output, "[{coverage}, {coverage_type}, [")?;
+    for (i, (session_id, session_coverage)) in
+        session_ids.iter().zip(&session_coverages).enumerate()
+    {
+        let delimiter = if i == 0 { "" } else { ", " };
+        write!(output, "{delimiter}[{session_id}, {session_coverage}]")?;
+    }
+    write!(output, "]")?;
+
+    if config.labels > 0 {
+        write!(output, ", null, null, [")?;
+        for (i, (session_id, session_coverage)) in
+            session_ids.iter().zip(&session_coverages).enumerate()
+        {
+            let delimiter = if i == 0 { "" } else { ", " };
+            // Datapoints only repeat the coverage, not the missing branches
+            let datapoint_coverage = session_coverage.split(", [").next().unwrap();
+            let label = rng.gen_range(0..config.labels);
+            let label = if config.inline_labels {
+                format!("\"test_case_{label}\"")
+            } else {
+                label.to_string()
+            };
+            write!(
+                output,
+                "{delimiter}[{session_id}, {datapoint_coverage}, {coverage_type}, [{label}]]"
+            )?;
+        }
+        write!(output, "]")?;
+    }
+    write!(output, "]")?;
+
+    Ok(())
+}
+
+/// Generates a pyreport per `config` and parses it into a new [`SqliteReport`]
+/// at `path`.
+pub fn generate_sqlite_report(config: &GeneratorConfig, path: PathBuf) -> Result<SqliteReport> {
+    let mut report_json_buf = Vec::new();
+    write_report_json(config, &mut report_json_buf)?;
+    let mut chunks_buf = Vec::new();
+    write_chunks(config, &mut chunks_buf)?;
+    // We only ever write ASCII
+    let chunks_buf = String::from_utf8(chunks_buf).unwrap();
+
+    let mut report_builder = SqliteReportBuilder::open(path)?;
+    {
+        let mut tx = report_builder.transaction()?;
+        let report_json::ParsedReportJson {
+            files, sessions, ..
+        } = report_json::parse_report_json(&report_json_buf, &mut tx)?;
+
+        let mut chunks_stream = chunks::ReportOutputStream {
+            input: chunks_buf.as_str(),
+            state: chunks::ParseCtx::new(tx, files, sessions),
+        };
+        chunks::parse_chunks_file
+            .parse_next(&mut chunks_stream)
+            .map_err(|e| {
+                CodecovError::parser_error(
+                    &chunks_buf,
+                    chunks_stream.input,
+                    e.into_inner().unwrap_or_default(),
+                )
+            })?;
+    }
+    report_builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::report::Report;
+
+    #[test]
+    fn test_generated_output_is_deterministic() {
+        let config = GeneratorConfig {
+            labels: 5,
+            ..Default::default()
+        };
+
+        let mut first = Vec::new();
+        write_chunks(&config, &mut first).unwrap();
+        let mut second = Vec::new();
+        write_chunks(&config, &mut second).unwrap();
+        assert_eq!(first, second);
+
+        let mut other_seed = Vec::new();
+        write_chunks(
+            &GeneratorConfig {
+                seed: 1,
+                ..config.clone()
+            },
+            &mut other_seed,
+        )
+        .unwrap();
+        assert_ne!(first, other_seed);
+    }
+
+    #[test]
+    fn test_generate_sqlite_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = GeneratorConfig {
+            files: 4,
+            lines_per_file: 50,
+            sessions: 3,
+            labels: 7,
+            seed: 42,
+            ..Default::default()
+        };
+
+        let report = generate_sqlite_report(&config, temp_dir.path().join("db.sqlite")).unwrap();
+
+        assert_eq!(report.list_files().unwrap().len(), 4);
+        assert_eq!(report.list_raw_uploads().unwrap().len(), 3);
+        assert_eq!(report.list_contexts().unwrap().len(), 7);
+
+        // Each measured line has a sample for each session that covered it
+        let mut chunks_buf = Vec::new();
+        write_chunks(&config, &mut chunks_buf).unwrap();
+        let measured_lines = chunks_buf
+            .split(|c| *c == b'\n')
+            .filter(|line| line.starts_with(b"["))
+            .count();
+        let samples = report.list_coverage_samples().unwrap().len();
+        assert!(measured_lines > 0);
+        assert!(samples >= measured_lines && samples <= measured_lines * 3);
+    }
+
+    #[test]
+    fn test_generate_legacy_sqlite_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = GeneratorConfig {
+            files: 4,
+            lines_per_file: 50,
+            sessions: 3,
+            labels: 7,
+            inline_labels: true,
+            missing_branches: true,
+            seed: 42,
+        };
+
+        let mut chunks_buf = Vec::new();
+        write_chunks(&config, &mut chunks_buf).unwrap();
+        let chunks_buf = String::from_utf8(chunks_buf).unwrap();
+        assert!(chunks_buf.starts_with("{\"labels_index\": {}}"));
+        assert!(chunks_buf.contains("\"test_case_"));
+        assert!(chunks_buf.contains(":jump\"]"));
+
+        let report = generate_sqlite_report(&config, temp_dir.path().join("db.sqlite")).unwrap();
+        assert!(!report.list_contexts().unwrap().is_empty());
+        let has_branches = report
+            .list_coverage_samples()
+            .unwrap()
+            .iter()
+            .any(|sample| !report.list_branches_for_sample(sample).unwrap().is_empty());
+        assert!(has_branches);
+    }
+}
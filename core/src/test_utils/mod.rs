@@ -1,2 +1,7 @@
+#[cfg(all(feature = "pyreport", feature = "sqlite"))]
+pub mod generator;
+#[cfg(all(feature = "pyreport", feature = "sqlite"))]
+pub mod parity;
+#[cfg(feature = "sqlite")]
 pub mod sqlite_report;
 pub mod test_report;
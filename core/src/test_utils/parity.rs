@@ -0,0 +1,102 @@
+//! Compares the totals codecov-rs computes for a pyreport against the totals
+//! the Python implementation in `shared` computed for it.
+//!
+//! Python computes totals over each line's coverage after merging every
+//! session's data for that line, while [`Report::totals`] aggregates
+//! individual samples. [`pyreport_totals`] does the line-level aggregation
+//! over a [`SqliteReport`] and produces [`PyreportTotals`] in the same shape
+//! as Python's `ReportTotals`, so the two can be compared directly.
+//!
+//! [`Report::totals`]: crate::report::Report::totals
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use winnow::Parser;
+
+use crate::{
+    error::{CodecovError, Result},
+    parsers::pyreport::{chunks, report_json},
+    report::{pyreport::percent, ReportBuilder, SqliteReport, SqliteReportBuilder},
+};
+
+/// Totals in the shape of `shared`'s `ReportTotals`. Deserializes from the
+/// list form `shared` writes to report JSON (`[files, lines, hits, ...]`),
+/// minus the trailing `diff` element which we don't compute.
+#[derive(PartialEq, Debug, Deserialize)]
+pub struct PyreportTotals {
+    pub files: u64,
+    pub lines: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub partials: u64,
+    /// Percentage of lines hit, formatted by [`percent::ratio`].
+    pub coverage: String,
+    pub branches: u64,
+    pub methods: u64,
+    pub messages: u64,
+    pub sessions: u64,
+    pub complexity: u64,
+    pub complexity_total: u64,
+}
+
+/// Computes Python-style totals for `report`.
+pub fn pyreport_totals(report: &SqliteReport) -> Result<PyreportTotals> {
+    let mut stmt = report.prepare_cached(include_str!("queries/pyreport_totals.sql"))?;
+    let totals = stmt.query_row([], |row| {
+        let lines: u64 = row.get("lines")?;
+        let hits: u64 = row.get("hits")?;
+        Ok(PyreportTotals {
+            files: row.get("files")?,
+            lines,
+            hits,
+            misses: row.get("misses")?,
+            partials: row.get("partials")?,
+            coverage: percent::ratio(hits as i64, lines as i64),
+            branches: row.get("branches")?,
+            methods: row.get("methods")?,
+            messages: 0,
+            sessions: row.get("sessions")?,
+            complexity: row.get("complexity")?,
+            complexity_total: row.get("complexity_total")?,
+        })
+    })?;
+    Ok(totals)
+}
+
+/// Parses a pyreport into a new [`SqliteReport`] at `path` and returns its
+/// Python-style totals alongside the `expected` ones, which are in the list
+/// form described on [`PyreportTotals`].
+pub fn compare_totals(
+    report_json: &[u8],
+    chunks_file: &str,
+    expected: &[u8],
+    path: PathBuf,
+) -> Result<(PyreportTotals, PyreportTotals)> {
+    let expected: PyreportTotals = serde_json::from_slice(expected)?;
+
+    let mut report_builder = SqliteReportBuilder::open(path)?;
+    {
+        let mut tx = report_builder.transaction()?;
+        let report_json::ParsedReportJson {
+            files, sessions, ..
+        } = report_json::parse_report_json(report_json, &mut tx)?;
+
+        let mut chunks_stream = chunks::ReportOutputStream {
+            input: chunks_file,
+            state: chunks::ParseCtx::new(tx, files, sessions),
+        };
+        chunks::parse_chunks_file
+            .parse_next(&mut chunks_stream)
+            .map_err(|e| {
+                CodecovError::parser_error(
+                    chunks_file,
+                    chunks_stream.input,
+                    e.into_inner().unwrap_or_default(),
+                )
+            })?;
+    }
+    let report = report_builder.build()?;
+
+    Ok((pyreport_totals(&report)?, expected))
+}
@@ -0,0 +1,34 @@
+with lines as (
+select
+  coverage_sample.source_file_id,
+  coverage_sample.line_no,
+  max(coverage_sample.coverage_type) as coverage_type,
+  -- A line is hit if any session hit it (or took all of its branches)
+  max(iif(coverage_sample.total_branches is null, coverage_sample.hits > 0, coverage_sample.total_branches > 0 and coverage_sample.hit_branches >= coverage_sample.total_branches)) as hit,
+  -- ...and partial if it isn't hit but some session took some of its branches
+  max(iif(coverage_sample.total_branches is null, 0, coverage_sample.hit_branches > 0)) as some_branches_hit,
+  max(coalesce(method_data.hit_complexity_paths, 0)) as hit_complexity_paths,
+  max(coalesce(method_data.total_complexity, 0)) as total_complexity
+from
+  coverage_sample
+left join
+  method_data
+on
+  coverage_sample.raw_upload_id = method_data.raw_upload_id
+  and coverage_sample.local_sample_id = method_data.local_sample_id
+group by
+  1, 2
+)
+select
+  count(distinct lines.source_file_id) as files,
+  count(*) as lines,
+  coalesce(sum(lines.hit), 0) as hits,
+  coalesce(sum(iif(lines.hit = 0 and lines.some_branches_hit = 0, 1, 0)), 0) as misses,
+  coalesce(sum(iif(lines.hit = 0 and lines.some_branches_hit = 1, 1, 0)), 0) as partials,
+  coalesce(sum(iif(lines.coverage_type = 'b', 1, 0)), 0) as branches,
+  coalesce(sum(iif(lines.coverage_type = 'm', 1, 0)), 0) as methods,
+  (select count(*) from raw_upload) as sessions,
+  coalesce(sum(lines.hit_complexity_paths), 0) as complexity,
+  coalesce(sum(lines.total_complexity), 0) as complexity_total
+from
+  lines
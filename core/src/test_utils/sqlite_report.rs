@@ -4,11 +4,16 @@ use serde_json::json;
 
 use crate::{
     error::Result,
-    report::{models, sqlite::Insertable, ReportBuilder, SqliteReport, SqliteReportBuilder},
+    report::{
+        models,
+        sqlite::{Insertable, IntegrityMode},
+        ReportBuilder, SqliteReport, SqliteReportBuilder,
+    },
 };
 
 pub fn build_sample_report(path: PathBuf) -> Result<SqliteReport> {
     let mut builder = SqliteReportBuilder::open(path)?;
+    builder.set_integrity_mode(IntegrityMode::Validate)?;
     let file_1 = builder.insert_file("src/report/report.rs")?;
     let file_2 = builder.insert_file("src/report/models.rs")?;
 
@@ -26,10 +31,11 @@ pub fn build_sample_report(path: PathBuf) -> Result<SqliteReport> {
         env: Some("env upload 1".to_string()),
         session_type: Some("type upload 1".to_string()),
         session_extras: Some(json!({"k1": "v1"})),
+        external_id: None,
     };
     // Insert directly, not through report builder, because we don't want a random
     // ID
-    upload_1.insert(&builder.conn)?;
+    upload_1.insert(&builder.conn, &Default::default())?;
 
     let upload_2 = models::RawUpload {
         id: 10,
@@ -45,10 +51,11 @@ pub fn build_sample_report(path: PathBuf) -> Result<SqliteReport> {
         env: Some("env upload 2".to_string()),
         session_type: Some("type upload 2".to_string()),
         session_extras: Some(json!({"k2": "v2"})),
+        external_id: None,
     };
     // Insert directly, not through report builder, because we don't want a random
     // ID
-    upload_2.insert(&builder.conn)?;
+    upload_2.insert(&builder.conn, &Default::default())?;
 
     let line_1 = builder.insert_coverage_sample(models::CoverageSample {
         raw_upload_id: upload_1.id,
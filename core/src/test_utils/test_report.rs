@@ -1,11 +1,15 @@
+use std::ops::Range;
+
 use crate::{
     error,
     report::{
         models::{
-            BranchesData, Context, ContextAssoc, CoverageSample, MethodData, RawUpload,
-            ReportTotals, SourceFile, SpanData,
+            BranchesData, Context, ContextAssoc, CoverageSample, CoverageTotals,
+            CoverageTypeTotals, LineAttribute, MethodData, RawUpload, ReportTotals,
+            SessionFileTotals, SourceFile, SpanData, UploadState, UploadTag,
         },
-        Report, ReportBuilder,
+        summary::ReportSummary,
+        DuplicateUploadPolicy, MergePolicy, Report, ReportBuilder,
     },
 };
 
@@ -19,11 +23,56 @@ pub struct TestReport {
     pub branches: Vec<BranchesData>,
     pub methods: Vec<MethodData>,
     pub spans: Vec<SpanData>,
+    pub tags: Vec<UploadTag>,
+    pub session_file_totals: Vec<SessionFileTotals>,
+    pub line_attributes: Vec<LineAttribute>,
+    /// The ID passed to each `supersede_upload()` call, in order.
+    pub superseded_uploads: Vec<i64>,
 }
 
 #[derive(Default)]
 pub struct TestReportBuilder {
     pub report: TestReport,
+
+    /// The length of each of `report`'s `Vec`s when each open savepoint was
+    /// created. Rolling back truncates them, which works because nothing is
+    /// ever removed except by `insert_raw_upload_idempotent()` and
+    /// `supersede_upload()`.
+    savepoints: Vec<[usize; 11]>,
+}
+
+impl TestReport {
+    fn lens(&self) -> [usize; 11] {
+        [
+            self.files.len(),
+            self.uploads.len(),
+            self.contexts.len(),
+            self.samples.len(),
+            self.assocs.len(),
+            self.branches.len(),
+            self.methods.len(),
+            self.spans.len(),
+            self.tags.len(),
+            self.session_file_totals.len(),
+            self.line_attributes.len(),
+        ]
+    }
+
+    fn truncate(&mut self, lens: [usize; 11]) {
+        let [files, uploads, contexts, samples, assocs, branches, methods, spans, tags, session_file_totals, line_attributes] =
+            lens;
+        self.files.truncate(files);
+        self.uploads.truncate(uploads);
+        self.contexts.truncate(contexts);
+        self.samples.truncate(samples);
+        self.assocs.truncate(assocs);
+        self.branches.truncate(branches);
+        self.methods.truncate(methods);
+        self.spans.truncate(spans);
+        self.tags.truncate(tags);
+        self.session_file_totals.truncate(session_file_totals);
+        self.line_attributes.truncate(line_attributes);
+    }
 }
 
 impl Report for TestReport {
@@ -62,17 +111,119 @@ impl Report for TestReport {
         todo!()
     }
 
+    fn samples_for_line(
+        &self,
+        _file: &SourceFile,
+        _line_no: i64,
+    ) -> error::Result<Vec<(CoverageSample, RawUpload)>> {
+        todo!()
+    }
+
+    fn list_samples_for_context(&self, _context: &Context) -> error::Result<Vec<CoverageSample>> {
+        todo!()
+    }
+
+    fn list_files_for_context(&self, _context: &Context) -> error::Result<Vec<SourceFile>> {
+        todo!()
+    }
+
+    fn list_contexts_for_file(&self, _file: &SourceFile) -> error::Result<Vec<Context>> {
+        todo!()
+    }
+
+    fn list_contexts_for_upload(&self, _raw_upload: &RawUpload) -> error::Result<Vec<Context>> {
+        todo!()
+    }
+
+    fn list_contexts_for_lines(&self, _lines: &[(&str, i64)]) -> error::Result<Vec<Context>> {
+        todo!()
+    }
+
+    fn list_methods_for_file(
+        &self,
+        _file: &SourceFile,
+    ) -> error::Result<Vec<(MethodData, CoverageSample)>> {
+        todo!()
+    }
+
     fn list_raw_uploads(&self) -> error::Result<Vec<RawUpload>> {
         todo!()
     }
 
-    fn merge(&mut self, _other: &Self) -> error::Result<()> {
+    fn list_raw_uploads_for_url(&self, _raw_upload_url: &str) -> error::Result<Vec<RawUpload>> {
+        todo!()
+    }
+
+    fn list_uploads_by_state(&self, _state: UploadState) -> error::Result<Vec<RawUpload>> {
+        todo!()
+    }
+
+    fn list_uploads_in_range(&self, _range: Range<i64>) -> error::Result<Vec<RawUpload>> {
+        todo!()
+    }
+
+    fn list_tags_for_upload(&self, _raw_upload: &RawUpload) -> error::Result<Vec<UploadTag>> {
+        todo!()
+    }
+
+    fn list_uploads_by_tag(&self, _key: &str, _value: &str) -> error::Result<Vec<RawUpload>> {
+        todo!()
+    }
+
+    fn list_session_file_totals(
+        &self,
+        _file: &SourceFile,
+    ) -> error::Result<Vec<SessionFileTotals>> {
+        todo!()
+    }
+
+    fn list_line_attributes(&self, _file: &SourceFile) -> error::Result<Vec<LineAttribute>> {
+        todo!()
+    }
+
+    fn get_file_metadata(&self, _path: &str) -> error::Result<Option<SourceFile>> {
+        todo!()
+    }
+
+    fn file_for_chunk_index(&self, _chunk_index: i64) -> error::Result<Option<SourceFile>> {
+        todo!()
+    }
+
+    fn chunk_index_for_file(&self, _file: &SourceFile) -> error::Result<Option<i64>> {
+        todo!()
+    }
+
+    fn list_out_of_bounds_samples(&self) -> error::Result<Vec<CoverageSample>> {
+        todo!()
+    }
+
+    fn merge(&mut self, _other: &Self, _policy: MergePolicy) -> error::Result<()> {
         todo!()
     }
 
     fn totals(&self) -> error::Result<ReportTotals> {
         todo!()
     }
+
+    fn file_totals(&self, _file: &SourceFile) -> error::Result<CoverageTotals> {
+        todo!()
+    }
+
+    fn list_file_totals(&self) -> error::Result<Vec<(SourceFile, CoverageTotals)>> {
+        todo!()
+    }
+
+    fn list_upload_totals(&self) -> error::Result<Vec<(RawUpload, CoverageTotals)>> {
+        todo!()
+    }
+
+    fn totals_by_coverage_type(&self) -> error::Result<CoverageTypeTotals> {
+        todo!()
+    }
+
+    fn summary(&self) -> error::Result<ReportSummary> {
+        todo!()
+    }
 }
 
 impl ReportBuilder<TestReport> for TestReportBuilder {
@@ -82,12 +233,26 @@ impl ReportBuilder<TestReport> for TestReportBuilder {
         Ok(file)
     }
 
+    fn update_file_metadata(&mut self, file: &SourceFile) -> error::Result<()> {
+        if let Some(existing) = self.report.files.iter_mut().find(|f| f.id == file.id) {
+            existing.language = file.language.clone().or(existing.language.take());
+            existing.content_hash = file.content_hash.clone().or(existing.content_hash.take());
+            existing.line_count = file.line_count.or(existing.line_count);
+            existing.chunk_index = file.chunk_index.or(existing.chunk_index);
+        }
+        Ok(())
+    }
+
     fn insert_context(&mut self, name: &str) -> error::Result<Context> {
         let context = Context::new(name);
         self.report.contexts.push(context.clone());
         Ok(context)
     }
 
+    fn multi_insert_context(&mut self, names: &[&str]) -> error::Result<Vec<Context>> {
+        names.iter().map(|name| self.insert_context(name)).collect()
+    }
+
     fn insert_coverage_sample(&mut self, sample: CoverageSample) -> error::Result<CoverageSample> {
         self.report.samples.push(sample.clone());
         Ok(sample)
@@ -106,6 +271,22 @@ impl ReportBuilder<TestReport> for TestReportBuilder {
         Ok(())
     }
 
+    fn multi_update_coverage_sample(&mut self, samples: &[CoverageSample]) -> error::Result<()> {
+        for sample in samples {
+            for existing in self.report.samples.iter_mut().filter(|s| {
+                (s.raw_upload_id, s.local_sample_id)
+                    == (sample.raw_upload_id, sample.local_sample_id)
+            }) {
+                *existing = CoverageSample {
+                    source_file_id: existing.source_file_id,
+                    line_no: existing.line_no,
+                    ..sample.clone()
+                };
+            }
+        }
+        Ok(())
+    }
+
     fn insert_branches_data(&mut self, branch: BranchesData) -> error::Result<BranchesData> {
         self.report.branches.push(branch.clone());
         Ok(branch)
@@ -157,13 +338,148 @@ impl ReportBuilder<TestReport> for TestReportBuilder {
         Ok(())
     }
 
+    fn insert_upload_tag(&mut self, tag: UploadTag) -> error::Result<UploadTag> {
+        self.report.tags.push(tag.clone());
+        Ok(tag)
+    }
+
+    fn multi_insert_session_file_totals(
+        &mut self,
+        totals: &[SessionFileTotals],
+    ) -> error::Result<()> {
+        self.report.session_file_totals.extend_from_slice(totals);
+        Ok(())
+    }
+
+    fn multi_insert_line_attributes(&mut self, attributes: &[LineAttribute]) -> error::Result<()> {
+        self.report.line_attributes.extend_from_slice(attributes);
+        Ok(())
+    }
+
     fn insert_raw_upload(&mut self, mut upload_details: RawUpload) -> error::Result<RawUpload> {
         upload_details.id = self.report.uploads.len() as i64;
         self.report.uploads.push(upload_details.clone());
         Ok(upload_details)
     }
 
+    fn update_raw_upload_url(
+        &mut self,
+        raw_upload_id: i64,
+        raw_upload_url: Option<&str>,
+    ) -> error::Result<()> {
+        if let Some(upload) = self
+            .report
+            .uploads
+            .iter_mut()
+            .find(|u| u.id == raw_upload_id)
+        {
+            upload.raw_upload_url = raw_upload_url.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    fn update_upload_state(&mut self, raw_upload_id: i64, state: UploadState) -> error::Result<()> {
+        if let Some(upload) = self
+            .report
+            .uploads
+            .iter_mut()
+            .find(|u| u.id == raw_upload_id)
+        {
+            upload.state = Some(state.as_str().to_string());
+        }
+        Ok(())
+    }
+
+    fn insert_raw_upload_idempotent(
+        &mut self,
+        upload_details: RawUpload,
+        on_duplicate: DuplicateUploadPolicy,
+    ) -> error::Result<Option<RawUpload>> {
+        let existing = upload_details.external_id.as_ref().and_then(|external_id| {
+            self.report
+                .uploads
+                .iter()
+                .position(|u| u.external_id.as_ref() == Some(external_id))
+        });
+        match (existing, on_duplicate) {
+            (None, _) => {}
+            (Some(_), DuplicateUploadPolicy::Skip) => return Ok(None),
+            (Some(index), DuplicateUploadPolicy::Replace) => {
+                let old_id = self.report.uploads.remove(index).id;
+                self.report.samples.retain(|s| s.raw_upload_id != old_id);
+                self.report.branches.retain(|b| b.raw_upload_id != old_id);
+                self.report.methods.retain(|m| m.raw_upload_id != old_id);
+                self.report.spans.retain(|s| s.raw_upload_id != old_id);
+                self.report.assocs.retain(|a| a.raw_upload_id != old_id);
+                self.report.tags.retain(|t| t.raw_upload_id != old_id);
+                self.report
+                    .session_file_totals
+                    .retain(|t| t.raw_upload_id != old_id);
+                self.report
+                    .line_attributes
+                    .retain(|a| a.raw_upload_id != old_id);
+            }
+        }
+        self.insert_raw_upload(upload_details).map(Some)
+    }
+
+    fn supersede_upload(&mut self, raw_upload_id: i64) -> error::Result<i64> {
+        if !self.report.uploads.iter().any(|u| u.id == raw_upload_id) {
+            return Err(error::CodecovError::ReportBuilderError(format!(
+                "upload {raw_upload_id} doesn't exist"
+            )));
+        }
+        self.report
+            .samples
+            .retain(|s| s.raw_upload_id != raw_upload_id);
+        self.report
+            .branches
+            .retain(|b| b.raw_upload_id != raw_upload_id);
+        self.report
+            .methods
+            .retain(|m| m.raw_upload_id != raw_upload_id);
+        self.report
+            .spans
+            .retain(|s| s.raw_upload_id != raw_upload_id);
+        self.report
+            .assocs
+            .retain(|a| a.raw_upload_id != raw_upload_id);
+        self.report
+            .session_file_totals
+            .retain(|t| t.raw_upload_id != raw_upload_id);
+        self.report
+            .line_attributes
+            .retain(|a| a.raw_upload_id != raw_upload_id);
+        self.report.superseded_uploads.push(raw_upload_id);
+        Ok(self
+            .report
+            .superseded_uploads
+            .iter()
+            .filter(|id| **id == raw_upload_id)
+            .count() as i64)
+    }
+
+    fn savepoint(&mut self) -> error::Result<()> {
+        self.savepoints.push(self.report.lens());
+        Ok(())
+    }
+
+    fn release_savepoint(&mut self) -> error::Result<()> {
+        self.savepoints.pop().ok_or_else(no_savepoint)?;
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> error::Result<()> {
+        let lens = self.savepoints.pop().ok_or_else(no_savepoint)?;
+        self.report.truncate(lens);
+        Ok(())
+    }
+
     fn build(self) -> error::Result<TestReport> {
         Ok(self.report)
     }
 }
+
+fn no_savepoint() -> error::CodecovError {
+    error::CodecovError::ReportBuilderError("no savepoint to release or roll back".to_string())
+}
@@ -0,0 +1,100 @@
+//! Tracefiles from tools that write lcov slightly differently than lcov does,
+//! which we accept by default and reject with `LcovOptions::strict()`.
+
+use codecov_rs::{
+    error::CodecovError,
+    parsers::lcov::{parse_lcov, LcovOptions},
+    report::{Report, ReportBuilder, SqliteReport, SqliteReportBuilder},
+};
+use tempfile::TempDir;
+use test_utils::fixtures::{read_fixture, FixtureFormat::Lcov, FixtureSize::Small};
+
+fn parse_fixture(name: &str, options: &LcovOptions) -> Result<SqliteReport, CodecovError> {
+    let temp_dir = TempDir::new().unwrap();
+    let input = read_fixture(Lcov, Small, name).unwrap();
+    let mut builder = SqliteReportBuilder::open(temp_dir.path().join("db.sqlite"))?;
+    parse_lcov(&input, &mut builder, options)?;
+    builder.build()
+}
+
+/// Returns (files, hits, misses, partials) from the report's summary.
+fn counts(report: &SqliteReport) -> (u64, u64, u64, u64) {
+    let totals = report.summary().unwrap().totals;
+    (totals.files, totals.hits, totals.misses, totals.partials)
+}
+
+fn assert_strict_error(name: &str, expected_line: usize) {
+    let error = parse_fixture(name, &LcovOptions::strict()).unwrap_err();
+    match error {
+        CodecovError::ParserError { line, .. } => assert_eq!(line, expected_line),
+        other => panic!("expected a parser error for {name}, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_missing_end_of_record() {
+    let report = parse_fixture("missing-end-of-record.info", &Default::default()).unwrap();
+    let paths: Vec<_> = report
+        .list_files()
+        .unwrap()
+        .into_iter()
+        .map(|file| file.path)
+        .collect();
+    assert_eq!(paths, vec!["src/Main.elm", "src/Parser.elm"]);
+    assert_eq!(counts(&report), (2, 3, 2, 1));
+
+    // The second record starts without the first one ending
+    assert_strict_error("missing-end-of-record.info", 10);
+    let options = LcovOptions {
+        allow_missing_end_of_record: false,
+        ..Default::default()
+    };
+    assert!(parse_fixture("missing-end-of-record.info", &options).is_err());
+}
+
+#[test]
+fn test_duplicate_files() {
+    let report = parse_fixture("duplicate-files.info", &Default::default()).unwrap();
+    assert_eq!(report.list_files().unwrap().len(), 2);
+    // Line 4 is missed in the first record but hit in the second
+    assert_eq!(counts(&report), (2, 4, 1, 0));
+
+    assert_strict_error("duplicate-files.info", 13);
+}
+
+#[test]
+fn test_function_names_with_commas() {
+    let report = parse_fixture("function-names-with-commas.info", &Default::default()).unwrap();
+    assert_eq!(counts(&report), (1, 2, 2, 0));
+
+    let file = &report.list_files().unwrap()[0];
+    let mut names: Vec<_> = report
+        .list_methods_for_file(file)
+        .unwrap()
+        .into_iter()
+        .map(|(method, _)| (method.line_no, method.name))
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            (Some(3), Some("int max<int>(int, int)".to_string())),
+            (
+                Some(8),
+                Some("std::pair<int, int> minmax(int, int)".to_string())
+            ),
+        ]
+    );
+
+    assert_strict_error("function-names-with-commas.info", 3);
+}
+
+#[test]
+fn test_crlf_and_bom() {
+    // Line endings and byte order marks are fine even in strict mode
+    for options in [LcovOptions::default(), LcovOptions::strict()] {
+        let report = parse_fixture("crlf-and-bom.info", &options).unwrap();
+        assert_eq!(report.list_files().unwrap()[0].path, r"src\index.js");
+        assert_eq!(counts(&report), (1, 1, 1, 1));
+    }
+}
@@ -0,0 +1,207 @@
+//! Converts the sample pyreports to SQLite and back and compares the output
+//! against checked-in copies in `fixtures/pyreport/golden`. Set
+//! `UPDATE_FIXTURES` to rewrite them after an intentional change to the
+//! output.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, Write},
+};
+
+use codecov_rs::{
+    parsers::pyreport,
+    report::{pyreport::ToPyreport, ReportBuilder, SqliteReportBuilder},
+};
+use serde_json::{Map, Value};
+use tempfile::TempDir;
+use test_utils::fixtures::{
+    assert_fixture_eq, open_fixture, FixtureFormat::Pyreport, FixtureSize::Small,
+};
+
+/// Parses a pyreport into a new SQLite report and writes it back out,
+/// returning the report JSON and chunks file.
+fn round_trip(report_json_file: &File, chunks_file: &File) -> (String, String) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut report_builder = SqliteReportBuilder::open(temp_dir.path().join("db.sqlite")).unwrap();
+    pyreport::parse_pyreport(report_json_file, chunks_file, &mut report_builder)
+        .expect("Failed to parse pyreport");
+    let report = report_builder.build().unwrap();
+
+    let mut report_json_output = tempfile::tempfile().unwrap();
+    let mut chunks_output = tempfile::tempfile().unwrap();
+    report
+        .to_pyreport(&mut report_json_output, &mut chunks_output)
+        .expect("Failed to write pyreport");
+
+    let read = |mut file: File| {
+        let mut contents = String::new();
+        file.rewind().unwrap();
+        file.read_to_string(&mut contents).unwrap();
+        contents
+    };
+    (read(report_json_output), read(chunks_output))
+}
+
+/// Session indices are assigned in the order of the sessions' upload IDs,
+/// which are random, so this renumbers them in the order of their contents.
+/// The report JSON is pretty-printed so fixture diffs are readable.
+fn normalize(report_json: &str, chunks: &str) -> (String, String) {
+    let mut report_json: Value = serde_json::from_str(report_json).unwrap();
+
+    let sessions = std::mem::take(report_json["sessions"].as_object_mut().unwrap());
+    let mut sessions: Vec<(String, Value)> = sessions.into_iter().collect();
+    sessions.sort_by_cached_key(|(_, session)| session.to_string());
+    let new_index: HashMap<i64, i64> = sessions
+        .iter()
+        .enumerate()
+        .map(|(i, (old, _))| (old.parse().unwrap(), i as i64))
+        .collect();
+    let map_index = |old: &Value| Value::from(new_index[&old.as_i64().unwrap()]);
+    report_json["sessions"] = Value::Object(
+        sessions
+            .into_iter()
+            .enumerate()
+            .map(|(i, (_, session))| (i.to_string(), session))
+            .collect(),
+    );
+
+    // Each file is `[chunk_index, totals, session_totals, diff_totals]`. The
+    // session totals' `session_count` is one more than the highest index in
+    // them, so it changes with the numbering too.
+    for file in report_json["files"].as_object_mut().unwrap().values_mut() {
+        if let Some(session_totals) = file.get_mut(2).and_then(Value::as_object_mut) {
+            let mut session_count = 0;
+            *session_totals = std::mem::take(session_totals)
+                .into_iter()
+                .map(|(key, totals)| match key.parse() {
+                    Ok(old) => {
+                        session_count = session_count.max(new_index[&old] + 1);
+                        (new_index[&old].to_string(), totals)
+                    }
+                    Err(_) => (key, totals),
+                })
+                .collect::<Map<_, _>>();
+            if let Some(meta) = session_totals.get_mut("meta") {
+                meta["session_count"] = session_count.into();
+            }
+        }
+    }
+
+    // Each line is `[coverage, type, sessions, messages, complexity,
+    // datapoints]`, and both sessions and datapoints start with a session index
+    let renumber = |entries: &mut Value| {
+        if let Some(entries) = entries.as_array_mut() {
+            for entry in entries.iter_mut() {
+                entry[0] = map_index(&entry[0]);
+            }
+            entries.sort_by_key(|entry| entry[0].as_i64());
+        }
+    };
+    let mut normalized_chunks = Vec::new();
+    let mut in_header = true;
+    let mut chunk_header_next = false;
+    for line in chunks.split('\n') {
+        let normalized = match line {
+            "<<<<< end_of_header >>>>>" | "<<<<< end_of_chunk >>>>>" => {
+                in_header = false;
+                chunk_header_next = true;
+                line.to_string()
+            }
+            _ if in_header || line.is_empty() => line.to_string(),
+            _ if chunk_header_next => {
+                chunk_header_next = false;
+                let mut header: Value = serde_json::from_str(line).unwrap();
+                if let Some(present) = header["present_sessions"].as_array_mut() {
+                    *present = present.iter().map(map_index).collect();
+                    present.sort_by_key(Value::as_i64);
+                }
+                header.to_string()
+            }
+            _ => {
+                let mut report_line: Value = serde_json::from_str(line).unwrap();
+                renumber(&mut report_line[2]);
+                if let Some(datapoints) = report_line.get_mut(5) {
+                    renumber(datapoints);
+                }
+                report_line.to_string()
+            }
+        };
+        normalized_chunks.push(normalized);
+    }
+
+    (
+        serde_json::to_string_pretty(&report_json).unwrap() + "\n",
+        normalized_chunks.join("\n"),
+    )
+}
+
+/// Round-trips the pyreport fixtures `report_json_name` and `chunks_name` and
+/// compares the output against the golden fixtures for `golden_name`. The
+/// output is then round-tripped again to make sure it comes back unchanged.
+#[track_caller]
+fn assert_golden(golden_name: &str, report_json_name: &str, chunks_name: &str) {
+    let (report_json, chunks) = round_trip(
+        &open_fixture(Pyreport, Small, report_json_name).unwrap(),
+        &open_fixture(Pyreport, Small, chunks_name).unwrap(),
+    );
+    let (normalized_report_json, normalized_chunks) = normalize(&report_json, &chunks);
+    assert_fixture_eq(
+        Pyreport,
+        Small,
+        &format!("golden/{golden_name}-report_json.json"),
+        &normalized_report_json,
+    );
+    assert_fixture_eq(
+        Pyreport,
+        Small,
+        &format!("golden/{golden_name}-chunks.txt"),
+        &normalized_chunks,
+    );
+
+    let write_temp = |contents: &str| {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file.rewind().unwrap();
+        file
+    };
+    let (report_json, chunks) = round_trip(&write_temp(&report_json), &write_temp(&chunks));
+    assert_eq!(
+        normalize(&report_json, &chunks),
+        (normalized_report_json, normalized_chunks)
+    );
+}
+
+#[test]
+fn test_golden_codecov_rs() {
+    // Line endings and byte order marks don't make it into the output
+    for chunks_name in [
+        "codecov-rs-chunks-d2a9ba1.txt",
+        "codecov-rs-chunks-d2a9ba1-crlf.txt",
+        "codecov-rs-chunks-d2a9ba1-bom.txt",
+    ] {
+        assert_golden(
+            "codecov-rs-d2a9ba1",
+            "codecov-rs-reports-json-d2a9ba1.txt",
+            chunks_name,
+        );
+    }
+}
+
+#[test]
+fn test_golden_branches() {
+    assert_golden(
+        "branches",
+        "parity/branches-report_json.json",
+        "parity/branches-chunks.txt",
+    );
+}
+
+#[test]
+fn test_golden_methods() {
+    assert_golden(
+        "methods",
+        "parity/methods-report_json.json",
+        "parity/methods-chunks.txt",
+    );
+}
@@ -6,7 +6,10 @@ use codecov_rs::{
         report_json::{self, ParsedReportJson},
     },
     report::{
-        models, pyreport::ToPyreport, Report, ReportBuilder, SqliteReport, SqliteReportBuilder,
+        models,
+        pyreport::{PyreportOptions, StatementExport, ToPyreport},
+        sqlite::IntegrityMode,
+        Report, ReportBuilder, SqliteReport, SqliteReportBuilder,
     },
 };
 use serde_json::json;
@@ -30,6 +33,13 @@ fn setup() -> Ctx {
     Ctx { temp_dir, db_file }
 }
 
+fn chunk_file(path: &str, chunk_index: i64) -> models::SourceFile {
+    models::SourceFile {
+        chunk_index: Some(chunk_index),
+        ..models::SourceFile::new(path)
+    }
+}
+
 #[test]
 fn test_parse_report_json() {
     let input = read_fixture(Pyreport, Small, "codecov-rs-reports-json-d2a9ba1.txt").unwrap();
@@ -40,17 +50,24 @@ fn test_parse_report_json() {
     let ParsedReportJson {
         files: file_id_map,
         sessions: session_id_map,
+        ..
     } = report_json::parse_report_json(&input, &mut report_builder).expect("Failed to parse");
     let report = report_builder.build().unwrap();
 
     // Test database inserts
     let expected_files = vec![
-        models::SourceFile::new("src/report.rs"),
-        models::SourceFile::new("src/report/models.rs"),
-        models::SourceFile::new("src/report/schema.rs"),
+        chunk_file("src/report.rs", 0),
+        chunk_file("src/report/models.rs", 1),
+        chunk_file("src/report/schema.rs", 2),
     ];
     let files = report.list_files().unwrap();
     assert_eq!(files, expected_files);
+    assert_eq!(
+        report.file_for_chunk_index(1).unwrap(),
+        Some(files[1].clone())
+    );
+    assert_eq!(report.file_for_chunk_index(3).unwrap(), None);
+    assert_eq!(report.chunk_index_for_file(&files[2]).unwrap(), Some(2));
 
     let contexts = report.list_contexts().unwrap();
     assert!(contexts.is_empty());
@@ -74,6 +91,7 @@ fn test_parse_report_json() {
         env: None,
         session_type: Some("uploaded".to_string()),
         session_extras: Some(json!({})),
+        external_id: None,
     };
     assert_eq!(uploads[0], expected_session);
 
@@ -147,6 +165,7 @@ fn test_parse_chunks_file() {
                 hits: Some(hits),
                 hit_branches: None,
                 total_branches: None,
+                messages: None,
             }
         };
     // (start_line, end_line, hits)
@@ -209,14 +228,23 @@ fn test_parse_pyreport() {
     let test_ctx = setup();
 
     let mut report_builder = SqliteReportBuilder::open(test_ctx.db_file).unwrap();
-    pyreport::parse_pyreport(&report_json_file, &chunks_file, &mut report_builder)
+    report_builder
+        .set_integrity_mode(IntegrityMode::Validate)
+        .unwrap();
+    let result = pyreport::parse_pyreport(&report_json_file, &chunks_file, &mut report_builder)
         .expect("Failed to parse pyreport");
     let report = report_builder.build().unwrap();
+    assert_eq!(result.files_touched, 3);
+    assert_eq!(
+        result.samples_inserted,
+        report.list_coverage_samples().unwrap().len()
+    );
+    assert!(result.warnings.is_empty());
 
     let expected_files = [
-        models::SourceFile::new("src/report.rs"),
-        models::SourceFile::new("src/report/models.rs"),
-        models::SourceFile::new("src/report/schema.rs"),
+        chunk_file("src/report.rs", 0),
+        chunk_file("src/report/models.rs", 1),
+        chunk_file("src/report/schema.rs", 2),
     ];
     let files = report.list_files().unwrap();
     assert_eq!(files, expected_files);
@@ -237,6 +265,7 @@ fn test_parse_pyreport() {
         env: None,
         session_type: Some("uploaded".to_string()),
         session_extras: Some(json!({})),
+        external_id: None,
     };
     assert_eq!(uploads[0], expected_session);
 
@@ -256,6 +285,7 @@ fn test_parse_pyreport() {
                 hits: Some(hits),
                 hit_branches: None,
                 total_branches: None,
+                messages: None,
             }
         };
 
@@ -303,6 +333,131 @@ fn test_parse_pyreport() {
     assert_eq!(actual_coverage_samples, expected_coverage_samples);
 }
 
+#[test]
+fn test_parse_pyreport_crlf_and_bom() {
+    let parse = |chunks_fixture: &str, db_file: PathBuf| {
+        let report_json_file =
+            open_fixture(Pyreport, Small, "codecov-rs-reports-json-d2a9ba1.txt").unwrap();
+        let chunks_file = open_fixture(Pyreport, Small, chunks_fixture).unwrap();
+        let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+        pyreport::parse_pyreport(&report_json_file, &chunks_file, &mut report_builder)
+            .expect("Failed to parse pyreport");
+        report_builder.build().unwrap()
+    };
+
+    let test_ctx = setup();
+    let expected = parse("codecov-rs-chunks-d2a9ba1.txt", test_ctx.db_file.clone());
+    for fixture in [
+        "codecov-rs-chunks-d2a9ba1-crlf.txt",
+        "codecov-rs-chunks-d2a9ba1-bom.txt",
+    ] {
+        let report = parse(fixture, test_ctx.temp_dir.path().join(fixture));
+        assert_eq!(
+            report.totals().unwrap(),
+            expected.totals().unwrap(),
+            "{fixture}"
+        );
+        assert_eq!(
+            report.list_coverage_samples().unwrap().len(),
+            expected.list_coverage_samples().unwrap().len()
+        );
+    }
+}
+
+#[test]
+fn test_parse_pyreport_resumable() {
+    let open_inputs = |chunks_fixture: &str| {
+        (
+            open_fixture(Pyreport, Small, "codecov-rs-reports-json-d2a9ba1.txt").unwrap(),
+            open_fixture(Pyreport, Small, chunks_fixture).unwrap(),
+        )
+    };
+    let test_ctx = setup();
+    let options = pyreport::ParseOptions::default();
+
+    let (report_json_file, chunks_file) = open_inputs("codecov-rs-chunks-d2a9ba1.txt");
+    let mut report_builder = SqliteReportBuilder::open(test_ctx.db_file).unwrap();
+    let expected_result =
+        pyreport::parse_pyreport(&report_json_file, &chunks_file, &mut report_builder).unwrap();
+    let expected = report_builder.build().unwrap();
+
+    // Fail partway through the last chunk, like a full disk would
+    let db_file = test_ctx.temp_dir.path().join("resumed.sqlite");
+    let mut report_builder = SqliteReportBuilder::open(db_file.clone()).unwrap();
+    report_builder
+        .conn
+        .execute_batch(&format!(
+            "CREATE TRIGGER fail_last_chunk BEFORE INSERT ON coverage_sample
+             WHEN NEW.source_file_id = {} AND NEW.line_no > 40
+             BEGIN SELECT RAISE(ABORT, 'database or disk is full'); END",
+            models::SourceFile::new("src/report/schema.rs").id
+        ))
+        .unwrap();
+    let (report_json_file, chunks_file) = open_inputs("codecov-rs-chunks-d2a9ba1.txt");
+    assert!(pyreport::resumable::parse_pyreport_resumable(
+        &report_json_file,
+        &chunks_file,
+        &mut report_builder,
+        &options,
+        1,
+    )
+    .is_err());
+    let checkpoint = pyreport::resumable::load_checkpoint(&report_builder.conn)
+        .unwrap()
+        .unwrap();
+    assert_eq!(checkpoint.chunks_committed, 2);
+    report_builder
+        .conn
+        .execute_batch("DROP TRIGGER fail_last_chunk")
+        .unwrap();
+    drop(report_builder);
+
+    // A fresh builder picks up where the last one left off, but only for the
+    // same inputs
+    let mut report_builder = SqliteReportBuilder::open(db_file).unwrap();
+    let (report_json_file, chunks_file) = open_inputs("codecov-rs-chunks-d2a9ba1-crlf.txt");
+    assert!(pyreport::resumable::parse_pyreport_resumable(
+        &report_json_file,
+        &chunks_file,
+        &mut report_builder,
+        &options,
+        1,
+    )
+    .is_err());
+
+    let (report_json_file, chunks_file) = open_inputs("codecov-rs-chunks-d2a9ba1.txt");
+    let result = pyreport::resumable::parse_pyreport_resumable(
+        &report_json_file,
+        &chunks_file,
+        &mut report_builder,
+        &options,
+        1,
+    )
+    .unwrap();
+    assert!(pyreport::resumable::load_checkpoint(&report_builder.conn)
+        .unwrap()
+        .is_none());
+    let report = report_builder.build().unwrap();
+
+    assert_eq!(result.files_touched, expected_result.files_touched);
+    assert_eq!(result.samples_inserted, expected_result.samples_inserted);
+    assert_eq!(result.warnings, expected_result.warnings);
+    assert_eq!(report.list_files().unwrap(), expected.list_files().unwrap());
+    // Upload IDs are random, so leave them out
+    let samples = |report: &SqliteReport| {
+        report
+            .list_coverage_samples()
+            .unwrap()
+            .into_iter()
+            .map(|sample| models::CoverageSample {
+                raw_upload_id: 0,
+                ..sample
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(samples(&report), samples(&expected));
+}
+
 #[test]
 fn test_sql_to_pyreport_to_sql_totals_match() {
     let report_json_input_file =
@@ -358,3 +513,199 @@ fn test_sql_to_pyreport_to_sql_totals_match() {
 
     assert_eq!(original_totals, roundtrip_totals);
 }
+
+#[test]
+fn test_pyreport_messages_round_trip() {
+    let test_ctx = setup();
+    let report_json_input = r#"{"files": {"src/lib.rs": [0, null, null, null]}, "sessions": {"0": {"t": null, "d": null, "a": null, "f": [], "c": null, "n": null, "N": null, "j": null, "u": null, "p": null, "e": null, "st": "uploaded", "se": {}}}}"#;
+    let chunks_input = r#"{}
+[1, null, [[0, 1]], [{"type": "warning", "text": "unreachable"}]]
+[0, null, [[0, 0]], null]"#;
+
+    let report_json_input_path = test_ctx.temp_dir.path().join("report_json_input.json");
+    let chunks_input_path = test_ctx.temp_dir.path().join("chunks_input.txt");
+    std::fs::write(&report_json_input_path, report_json_input).unwrap();
+    std::fs::write(&chunks_input_path, chunks_input).unwrap();
+
+    let mut report_builder = SqliteReportBuilder::open(test_ctx.db_file).unwrap();
+    pyreport::parse_pyreport(
+        &File::open(&report_json_input_path).unwrap(),
+        &File::open(&chunks_input_path).unwrap(),
+        &mut report_builder,
+    )
+    .expect("Failed to parse pyreport");
+    let report = report_builder.build().unwrap();
+
+    let samples = report.list_coverage_samples().unwrap();
+    assert_eq!(samples.len(), 2);
+    assert_eq!(
+        samples[0].messages,
+        Some(json!([{"type": "warning", "text": "unreachable"}]))
+    );
+    assert_eq!(samples[1].messages, None);
+
+    let chunks_output_path = test_ctx.temp_dir.path().join("chunks.txt");
+    let mut report_json_output_file = tempfile::tempfile().unwrap();
+    let mut chunks_output_file = File::options()
+        .create(true)
+        .truncate(true)
+        .read(true)
+        .write(true)
+        .open(&chunks_output_path)
+        .unwrap();
+    report
+        .to_pyreport(&mut report_json_output_file, &mut chunks_output_file)
+        .expect("Failed to write to output files");
+
+    let chunks_output = std::fs::read_to_string(&chunks_output_path).unwrap();
+    assert!(chunks_output.contains(r#"[{"text":"unreachable","type":"warning"}]"#));
+}
+
+#[test]
+fn test_to_pyreport_filtered() {
+    let test_ctx = setup();
+    let mut report_builder = SqliteReportBuilder::open(test_ctx.db_file).unwrap();
+    let mut upload_ids = vec![];
+    for (path, flag) in [
+        ("src/a.rs", "unit"),
+        ("src/b.rs", "integration"),
+        ("src/c.rs", "unit"),
+    ] {
+        let upload = report_builder
+            .insert_raw_upload(models::RawUpload {
+                flags: Some(json!([flag])),
+                ..Default::default()
+            })
+            .unwrap();
+        let file = report_builder.insert_file(path).unwrap();
+        report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no: 1,
+                coverage_type: models::CoverageType::Line,
+                hits: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        upload_ids.push(upload.id);
+    }
+    let report = report_builder.build().unwrap();
+
+    let mut report_json_output_file = tempfile::tempfile().unwrap();
+    let mut chunks_output_file = tempfile::tempfile().unwrap();
+    // Only the "unit" uploads, plus an ID that isn't in the report
+    report
+        .to_pyreport_filtered(
+            &mut report_json_output_file,
+            &mut chunks_output_file,
+            &[upload_ids[0], upload_ids[2], -1],
+        )
+        .expect("Failed to write to output files");
+
+    report_json_output_file.rewind().unwrap();
+    let report_json: serde_json::Value = serde_json::from_reader(&report_json_output_file).unwrap();
+    let sessions = report_json["sessions"].as_object().unwrap();
+    assert_eq!(sessions.keys().collect::<Vec<_>>(), ["0", "1"]);
+    assert!(sessions
+        .values()
+        .all(|session| session["f"] == json!(["unit"])));
+
+    chunks_output_file.rewind().unwrap();
+    let roundtrip_db_path = test_ctx.temp_dir.path().join("roundtrip.sqlite");
+    let mut report_builder = SqliteReportBuilder::open(roundtrip_db_path).unwrap();
+    pyreport::parse_pyreport(
+        &report_json_output_file,
+        &chunks_output_file,
+        &mut report_builder,
+    )
+    .expect("Failed to parse filtered report");
+    let roundtrip = report_builder.build().unwrap();
+    assert_eq!(roundtrip.list_raw_uploads().unwrap().len(), 2);
+    let covered_files: Vec<_> = roundtrip
+        .list_files()
+        .unwrap()
+        .into_iter()
+        .filter(|file| {
+            roundtrip
+                .file_totals(file)
+                .is_ok_and(|totals| totals.hit_lines > 0)
+        })
+        .map(|file| file.path)
+        .collect();
+    assert_eq!(covered_files, ["src/a.rs", "src/c.rs"]);
+
+    // The original report is untouched
+    assert_eq!(report.list_raw_uploads().unwrap().len(), 3);
+}
+
+#[test]
+fn test_to_pyreport_statements() {
+    let test_ctx = setup();
+    let mut report_builder = SqliteReportBuilder::open(test_ctx.db_file).unwrap();
+    let upload = report_builder
+        .insert_raw_upload(Default::default())
+        .unwrap();
+    let file = report_builder.insert_file("src/lib.rs").unwrap();
+    for (line_no, hits) in [(1, 3), (1, 0), (2, 2)] {
+        report_builder
+            .insert_coverage_sample(models::CoverageSample {
+                raw_upload_id: upload.id,
+                source_file_id: file.id,
+                line_no,
+                coverage_type: models::CoverageType::Statement,
+                hits: Some(hits),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    let report = report_builder.build().unwrap();
+    assert_eq!(report.totals().unwrap().coverage.total_lines, 3);
+
+    for (policy, expected_hits) in [
+        (StatementExport::Max, [(1, 3), (2, 2)]),
+        (StatementExport::Min, [(1, 0), (2, 2)]),
+    ] {
+        let mut report_json_output_file = tempfile::tempfile().unwrap();
+        let mut chunks_output_file = tempfile::tempfile().unwrap();
+        let options = PyreportOptions {
+            statements: policy,
+            ..Default::default()
+        };
+        report
+            .to_pyreport_with_options(
+                &mut report_json_output_file,
+                &mut chunks_output_file,
+                &options,
+            )
+            .expect("Failed to write to output files");
+
+        report_json_output_file.rewind().unwrap();
+        chunks_output_file.rewind().unwrap();
+        let roundtrip_db_path = test_ctx.temp_dir.path().join(format!("{policy:?}.sqlite"));
+        let mut report_builder = SqliteReportBuilder::open(roundtrip_db_path).unwrap();
+        pyreport::parse_pyreport(
+            &report_json_output_file,
+            &chunks_output_file,
+            &mut report_builder,
+        )
+        .expect("Failed to parse exported report");
+        let roundtrip = report_builder.build().unwrap();
+
+        // Each line's statements became one line sample
+        let mut samples: Vec<_> = roundtrip
+            .list_coverage_samples()
+            .unwrap()
+            .into_iter()
+            .map(|sample| {
+                assert_eq!(sample.coverage_type, models::CoverageType::Line);
+                (sample.line_no, sample.hits.unwrap())
+            })
+            .collect();
+        samples.sort();
+        assert_eq!(samples, expected_hits);
+    }
+
+    // The original report still has every statement
+    assert_eq!(report.list_coverage_samples().unwrap().len(), 3);
+}
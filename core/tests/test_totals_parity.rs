@@ -0,0 +1,51 @@
+use codecov_rs::test_utils::parity::compare_totals;
+use tempfile::TempDir;
+use test_utils::fixtures::{read_fixture, FixtureFormat::Pyreport, FixtureSize::Small};
+
+/// (report JSON, chunks file, expected totals) for each pyreport in the
+/// corpus. Expected totals are in `shared`'s list form and are written by
+/// `parity/generate_totals.py`, which runs each case through the Python
+/// implementation. Keep its `CASES` in sync with these.
+///
+/// The totals checked in so far were worked out by hand from `shared`'s rules,
+/// so until the script has been run against them this only guards against
+/// regressions, not differences from Python.
+const CASES: &[(&str, &str, &str)] = &[
+    (
+        "codecov-rs-reports-json-d2a9ba1.txt",
+        "codecov-rs-chunks-d2a9ba1.txt",
+        "parity/codecov-rs-totals-d2a9ba1.json",
+    ),
+    // Partial, merged, and `true` branch coverage across two sessions
+    (
+        "parity/branches-report_json.json",
+        "parity/branches-chunks.txt",
+        "parity/branches-totals.json",
+    ),
+    // Method coverage with complexity across two sessions and files
+    (
+        "parity/methods-report_json.json",
+        "parity/methods-chunks.txt",
+        "parity/methods-totals.json",
+    ),
+];
+
+#[test]
+fn test_totals_parity() {
+    let temp_dir = TempDir::new().unwrap();
+
+    for (i, (report_json, chunks, expected)) in CASES.iter().enumerate() {
+        let report_json = read_fixture(Pyreport, Small, report_json).unwrap();
+        let chunks = read_fixture(Pyreport, Small, chunks).unwrap();
+        let expected_totals = read_fixture(Pyreport, Small, expected).unwrap();
+
+        let (actual, expected_totals) = compare_totals(
+            &report_json,
+            std::str::from_utf8(&chunks).unwrap(),
+            &expected_totals,
+            temp_dir.path().join(format!("{i}.sqlite")),
+        )
+        .unwrap();
+        assert_eq!(actual, expected_totals, "totals mismatch for {expected}");
+    }
+}
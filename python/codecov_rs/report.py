@@ -1,3 +1,4 @@
-from ._bindings import SqliteReportBuilder
+from ._bindings import FileTotalsBuffer, SqliteReportBuilder, merge_reports
 
 SqliteReportBuilder.__module__ = __name__
+FileTotalsBuffer.__module__ = __name__
@@ -1,6 +1,34 @@
+from typing import Literal
+
 class SqliteReportBuilder:
     @staticmethod
     def from_pyreport(
         report_json_filepath: str, chunks_filepath: str, out_filepath: str
     ) -> SqliteReportBuilder: ...
     def filepath(self) -> str: ...
+
+class FileTotalsBuffer:
+    """Per-file totals as a read-only 2D buffer of native-endian uint64s.
+
+    `numpy.asarray(buffer)` has one row per file, in the order of `paths()`,
+    and one column per name in `columns()`.
+    """
+
+    @staticmethod
+    def from_sqlite(report_filepath: str) -> FileTotalsBuffer: ...
+    @staticmethod
+    def columns() -> list[str]: ...
+    def paths(self) -> list[str]: ...
+    def __len__(self) -> int: ...
+    def __buffer__(self, flags: int, /) -> memoryview: ...
+
+def merge_reports(
+    paths: list[str],
+    out_path: str,
+    threads: int = 1,
+    policy: Literal["keep_both", "sum_hits", "max_hits", "prefer_newest"] = "keep_both",
+) -> None:
+    """Merge the SQLite reports at `paths` into a new report at `out_path`.
+
+    `out_path` must not exist yet. The GIL is released while merging.
+    """
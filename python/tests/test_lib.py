@@ -1,7 +1,9 @@
 from pathlib import Path
-from tempfile import NamedTemporaryFile
+from tempfile import NamedTemporaryFile, TemporaryDirectory
 
-from codecov_rs.report import SqliteReportBuilder
+import pytest
+
+from codecov_rs.report import FileTotalsBuffer, SqliteReportBuilder, merge_reports
 
 PROJECT_ROOT = Path(__file__).parent.parent.parent
 
@@ -29,3 +31,67 @@ def test_from_pyreport():
         )
         assert report_builder.filepath() is not None
         del report_builder
+
+
+def test_file_totals_buffer():
+    report_json_filepath = get_fixture_path(
+        "test_utils/fixtures/pyreport/codecov-rs-reports-json-d2a9ba1.txt"
+    )
+    chunks_filepath = get_fixture_path(
+        "test_utils/fixtures/pyreport/codecov-rs-chunks-d2a9ba1.txt"
+    )
+
+    with NamedTemporaryFile(delete_on_close=False) as out_file:
+        report_builder = SqliteReportBuilder.from_pyreport(
+            report_json_filepath, chunks_filepath, out_file.name
+        )
+        del report_builder
+        buffer = FileTotalsBuffer.from_sqlite(out_file.name)
+
+    columns = FileTotalsBuffer.columns()
+    assert columns[:2] == ["hit_lines", "total_lines"]
+
+    paths = buffer.paths()
+    assert len(paths) == len(buffer) > 0
+    assert paths == sorted(paths)
+
+    view = memoryview(buffer)
+    assert view.readonly
+    assert view.format == "Q"
+    assert view.shape == (len(paths), len(columns))
+    rows = view.tolist()
+    assert all(row[0] <= row[1] for row in rows)
+    assert sum(row[1] for row in rows) > 0
+
+
+def test_merge_reports():
+    report_json_filepath = get_fixture_path(
+        "test_utils/fixtures/pyreport/codecov-rs-reports-json-d2a9ba1.txt"
+    )
+    chunks_filepath = get_fixture_path(
+        "test_utils/fixtures/pyreport/codecov-rs-chunks-d2a9ba1.txt"
+    )
+
+    with TemporaryDirectory() as temp_dir:
+        shards = [str(Path(temp_dir) / f"shard-{i}.sqlite") for i in range(3)]
+        for shard in shards:
+            report_builder = SqliteReportBuilder.from_pyreport(
+                report_json_filepath, chunks_filepath, shard
+            )
+            del report_builder
+
+        out_path = str(Path(temp_dir) / "merged.sqlite")
+        merge_reports(shards, out_path, threads=2)
+        shard_rows = FileTotalsBuffer.from_sqlite(shards[0])
+        merged_rows = FileTotalsBuffer.from_sqlite(out_path)
+        assert merged_rows.paths() == shard_rows.paths()
+        # Each shard's upload is kept separately
+        assert memoryview(merged_rows).tolist() == [
+            [value * 3 for value in row] for row in memoryview(shard_rows).tolist()
+        ]
+
+        # The output has to be new
+        with pytest.raises(RuntimeError):
+            merge_reports(shards, out_path)
+        with pytest.raises(ValueError):
+            merge_reports(shards, str(Path(temp_dir) / "other.sqlite"), policy="nope")
@@ -0,0 +1,74 @@
+"""Writes the expected totals for core/tests/test_totals_parity.rs.
+
+Each case is loaded into the worker's Python `Report` from `shared`, and the
+totals it computes are written in the list form `shared` uses in report JSON,
+minus the trailing `diff` element, which codecov-rs doesn't compute.
+
+Run it in an environment with `shared` installed (e.g. the worker's):
+
+    python test_utils/fixtures/pyreport/parity/generate_totals.py
+
+Pass `--check` to fail instead of writing if any checked-in totals differ.
+"""
+
+import json
+import sys
+from pathlib import Path
+
+from shared.reports.resources import Report
+
+PYREPORT_FIXTURES = Path(__file__).resolve().parent.parent
+
+# Keep in sync with `CASES` in core/tests/test_totals_parity.rs
+CASES = [
+    (
+        "codecov-rs-reports-json-d2a9ba1.txt",
+        "codecov-rs-chunks-d2a9ba1.txt",
+        "parity/codecov-rs-totals-d2a9ba1.json",
+    ),
+    (
+        "parity/branches-report_json.json",
+        "parity/branches-chunks.txt",
+        "parity/branches-totals.json",
+    ),
+    (
+        "parity/methods-report_json.json",
+        "parity/methods-chunks.txt",
+        "parity/methods-totals.json",
+    ),
+]
+
+
+def compute_totals(report_json_path, chunks_path):
+    report_json = json.loads((PYREPORT_FIXTURES / report_json_path).read_text())
+    chunks = (PYREPORT_FIXTURES / chunks_path).read_text()
+    # Without `totals`, `Report` computes them from the chunks
+    report = Report(
+        files=report_json["files"],
+        sessions=report_json["sessions"],
+        totals=None,
+        chunks=chunks,
+    )
+    return list(report.totals.astuple())[:-1]
+
+
+def main():
+    check = "--check" in sys.argv[1:]
+    stale = []
+    for report_json_path, chunks_path, totals_path in CASES:
+        expected = json.dumps(compute_totals(report_json_path, chunks_path)) + "\n"
+        path = PYREPORT_FIXTURES / totals_path
+        if path.exists() and path.read_text() == expected:
+            continue
+        if check:
+            stale.append(totals_path)
+        else:
+            path.write_text(expected)
+            print(f"wrote {totals_path}")
+
+    if stale:
+        sys.exit(f"totals differ from shared's: {', '.join(stale)}")
+
+
+if __name__ == "__main__":
+    main()
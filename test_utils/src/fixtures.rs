@@ -8,12 +8,14 @@ use std::{
 #[derive(Copy, Clone)]
 pub enum FixtureFormat {
     Pyreport,
+    Lcov,
 }
 
 impl fmt::Display for FixtureFormat {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             FixtureFormat::Pyreport => write!(f, "pyreport"),
+            FixtureFormat::Lcov => write!(f, "lcov"),
         }
     }
 }
@@ -72,3 +74,27 @@ pub fn read_fixture(
     let path = fixture_dir(format, size).join(name);
     std::fs::read(path).map_err(|_| "failed to read file")
 }
+
+/// Asserts that `actual` matches the contents of the fixture `name`. If the
+/// `UPDATE_FIXTURES` environment variable is set, the fixture is written with
+/// `actual` instead, for when the output has changed on purpose:
+///
+/// ```sh
+/// UPDATE_FIXTURES=1 cargo test --all-features --test test_pyreport_golden
+/// ```
+#[track_caller]
+pub fn assert_fixture_eq(format: FixtureFormat, size: FixtureSize, name: &str, actual: &str) {
+    let path = fixture_dir(format, size).join(name);
+    if std::env::var_os("UPDATE_FIXTURES").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing fixture {name}, set UPDATE_FIXTURES to create it"));
+    assert!(
+        expected == actual,
+        "output doesn't match fixture {name}, set UPDATE_FIXTURES if the change is intentional\n--- expected\n{expected}\n--- actual\n{actual}"
+    );
+}